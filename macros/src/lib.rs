@@ -0,0 +1,440 @@
+//! Grammars checked at compile time
+//!
+//! `static_grammar!` takes the same description as `ruparse::grammar!`, builds the
+//! grammar while the crate compiles and runs the `Validator` on it. Validation errors
+//! become compile errors that point at the rule which caused them.
+//!
+//! This is a separate crate because a proc macro can not live next to the library it
+//! uses, add it next to `ruparse` to opt in.
+
+use proc_macro::{Delimiter, Group, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use ruparse::api::ext;
+use ruparse::grammar::validator::{ValidationErrorOwned, ValidationErrors, Validator};
+use ruparse::grammar::{Rule, VarKind, VariableKind};
+
+/// Builds a `ruparse::Parser` from an inline grammar that is validated at compile time
+///
+/// ```text
+/// let parser = static_grammar! {
+///     KWLet: "let" <ident:text> (":" <type:text>)? ("=" <value:value>)? ";";
+///     value: <text:text>;
+/// };
+/// ```
+///
+/// The items are the ones of `ruparse::grammar!`. The first node is the entry and
+/// every literal that is not a word is added to the lexer as a token. Nodes the
+/// grammar refers to have to be declared in the same macro.
+#[proc_macro]
+pub fn static_grammar(input: TokenStream) -> TokenStream {
+    let nodes = match parse_nodes(input.clone()) {
+        Ok(nodes) => nodes,
+        Err(err) => return errors([err]),
+    };
+    let mut errs = check(&nodes);
+    if errs.is_empty() {
+        errs = validate(&nodes);
+    }
+    match errs.is_empty() {
+        true => expand(&nodes, input),
+        false => errors(errs),
+    }
+}
+
+struct Error {
+    span: Span,
+    message: String,
+}
+
+impl Error {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// `::core::compile_error!("message");` with every token at the span of the error
+    fn to_tokens(&self) -> TokenStream {
+        let punct = |c, spacing| {
+            let mut punct = Punct::new(c, spacing);
+            punct.set_span(self.span);
+            TokenTree::Punct(punct)
+        };
+        let mut message = Literal::string(&self.message);
+        message.set_span(self.span);
+        let mut args = Group::new(Delimiter::Parenthesis, TokenTree::Literal(message).into());
+        args.set_span(self.span);
+        [
+            punct(':', Spacing::Joint),
+            punct(':', Spacing::Alone),
+            TokenTree::Ident(proc_macro::Ident::new("core", self.span)),
+            punct(':', Spacing::Joint),
+            punct(':', Spacing::Alone),
+            TokenTree::Ident(proc_macro::Ident::new("compile_error", self.span)),
+            punct('!', Spacing::Alone),
+            TokenTree::Group(args),
+            punct(';', Spacing::Alone),
+        ]
+        .into_iter()
+        .collect()
+    }
+}
+
+/// Reports the errors and still expands to a parser, so that they are the only errors
+fn errors(errors: impl IntoIterator<Item = Error>) -> TokenStream {
+    let mut body = errors
+        .into_iter()
+        .map(|err| err.to_tokens())
+        .collect::<TokenStream>();
+    body.extend(code("::ruparse::Parser::new()"));
+    TokenTree::Group(Group::new(Delimiter::Brace, body)).into()
+}
+
+fn code(text: &str) -> TokenStream {
+    text.parse().expect("generated code is valid")
+}
+
+struct Node {
+    name: String,
+    span: Span,
+    items: Vec<Item>,
+}
+
+enum Item {
+    /// `"let"` or `";"`
+    Literal { text: String, span: Span },
+    /// `text`, `node` or `<var:node>`
+    Token {
+        var: Option<(String, Span)>,
+        kind: String,
+        span: Span,
+    },
+    /// `( ... )?` or `( ... )*`
+    Group {
+        items: Vec<Item>,
+        repeated: bool,
+        span: Span,
+    },
+}
+
+impl Item {
+    fn span(&self) -> Span {
+        match self {
+            Item::Literal { span, .. } | Item::Token { span, .. } | Item::Group { span, .. } => {
+                *span
+            }
+        }
+    }
+}
+
+fn is_punct(tree: Option<&TokenTree>, c: char) -> bool {
+    matches!(tree, Some(TokenTree::Punct(p)) if p.as_char() == c)
+}
+
+fn parse_nodes(input: TokenStream) -> Result<Vec<Node>, Error> {
+    let mut input = input.into_iter();
+    let mut nodes = Vec::new();
+    while let Some(tree) = input.next() {
+        let TokenTree::Ident(name) = tree else {
+            return Err(Error::new(tree.span(), "expected the name of a node"));
+        };
+        match input.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == ':' => (),
+            _ => {
+                return Err(Error::new(
+                    name.span(),
+                    "expected `:` after the name of the node",
+                ))
+            }
+        }
+        let mut items = Vec::new();
+        loop {
+            match input.next() {
+                Some(TokenTree::Punct(p)) if p.as_char() == ';' => break,
+                Some(tree) => items.push(tree),
+                None => {
+                    return Err(Error::new(
+                        name.span(),
+                        format!("expected `;` after the rules of node {name}"),
+                    ))
+                }
+            }
+        }
+        nodes.push(Node {
+            name: name.to_string(),
+            span: name.span(),
+            items: parse_items(items)?,
+        });
+    }
+    Ok(nodes)
+}
+
+fn parse_items(trees: Vec<TokenTree>) -> Result<Vec<Item>, Error> {
+    let mut trees = trees.into_iter();
+    let mut items = Vec::new();
+    while let Some(tree) = trees.next() {
+        let span = tree.span();
+        let item = match tree {
+            TokenTree::Literal(literal) => Item::Literal {
+                text: string(&literal)?,
+                span,
+            },
+            TokenTree::Ident(kind) => Item::Token {
+                var: None,
+                kind: kind.to_string(),
+                span,
+            },
+            TokenTree::Punct(p) if p.as_char() == '<' => {
+                let expected = || Error::new(span, "expected `<variable:node>`");
+                let Some(TokenTree::Ident(var)) = trees.next() else {
+                    return Err(expected());
+                };
+                if !is_punct(trees.next().as_ref(), ':') {
+                    return Err(expected());
+                }
+                let Some(TokenTree::Ident(kind)) = trees.next() else {
+                    return Err(expected());
+                };
+                if !is_punct(trees.next().as_ref(), '>') {
+                    return Err(expected());
+                }
+                Item::Token {
+                    var: Some((var.to_string(), var.span())),
+                    kind: kind.to_string(),
+                    span: kind.span(),
+                }
+            }
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Parenthesis => {
+                let repeated = match trees.next() {
+                    Some(TokenTree::Punct(p)) if p.as_char() == '?' => false,
+                    Some(TokenTree::Punct(p)) if p.as_char() == '*' => true,
+                    _ => return Err(Error::new(span, "expected `?` or `*` after the group")),
+                };
+                let items = parse_items(group.stream().into_iter().collect())?;
+                match items.first() {
+                    None => return Err(Error::new(span, "empty group")),
+                    Some(Item::Group { span, .. }) => {
+                        return Err(Error::new(
+                            *span,
+                            "a group has to start with a literal or a token",
+                        ))
+                    }
+                    Some(_) => (),
+                }
+                Item::Group {
+                    items,
+                    repeated,
+                    span,
+                }
+            }
+            tree => return Err(Error::new(tree.span(), "unexpected rule")),
+        };
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/// Value of a plain string literal
+fn string(literal: &Literal) -> Result<String, Error> {
+    let expected = || Error::new(literal.span(), "expected a string literal");
+    let source = literal.to_string();
+    let inner = source
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(expected)?;
+    let mut text = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        text.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '"' | '\'')) => c,
+            _ => {
+                return Err(Error::new(
+                    literal.span(),
+                    "unsupported escape in string literal",
+                ))
+            }
+        });
+    }
+    Ok(text)
+}
+
+/// Errors that would make the grammar panic while it is built
+fn check(nodes: &[Node]) -> Vec<Error> {
+    let mut errs = Vec::new();
+    for (i, node) in nodes.iter().enumerate() {
+        if nodes[..i].iter().any(|other| other.name == node.name) {
+            errs.push(Error::new(
+                node.span,
+                format!("node {} is declared twice", node.name),
+            ));
+        }
+        let mut vars = Vec::new();
+        for_each_item(&node.items, &mut |item| {
+            if let Item::Token {
+                var: Some((var, span)),
+                ..
+            } = item
+            {
+                if vars.contains(&var) {
+                    errs.push(Error::new(
+                        *span,
+                        format!("variable {var} is declared twice in node {}", node.name),
+                    ));
+                }
+                vars.push(var);
+            }
+        });
+    }
+    errs
+}
+
+fn for_each_item<'n>(items: &'n [Item], f: &mut impl FnMut(&'n Item)) {
+    for item in items {
+        f(item);
+        if let Item::Group { items, .. } = item {
+            for_each_item(items, f);
+        }
+    }
+}
+
+/// Literals that are added to the lexer, see `ext::literal`
+fn tokens(nodes: &[Node]) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    for node in nodes {
+        for_each_item(&node.items, &mut |item| {
+            if let Item::Literal { text, .. } = item {
+                let word = text.chars().all(|c| c.is_alphanumeric() || c == '_');
+                if !word && !tokens.contains(&text.as_str()) {
+                    tokens.push(text.as_str());
+                }
+            }
+        });
+    }
+    tokens
+}
+
+/// The parser `ruparse::grammar!` builds from the same nodes
+fn build(nodes: &[Node]) -> ruparse::Parser<'_> {
+    let mut parser = ruparse::Parser::new();
+    parser.lexer.add_tokens(tokens(nodes).into_iter());
+    for node in nodes {
+        let mut variables = Vec::new();
+        let rules = rules(&node.items, &mut variables);
+        parser
+            .grammar
+            .new_node(&node.name)
+            .rules(rules)
+            .variables(variables)
+            .build();
+    }
+    parser.parser.entry = nodes.first().map(|node| node.name.as_str());
+    parser
+}
+
+fn rules<'n>(items: &'n [Item], variables: &mut Vec<(&'n str, VariableKind)>) -> Vec<Rule<'n>> {
+    items
+        .iter()
+        .map(|item| match item {
+            Item::Literal { text, .. } => ext::is(ext::literal(text)),
+            Item::Token { var, kind, .. } => {
+                let token = match kind.as_str() {
+                    "text" => ext::text(),
+                    node => ext::node(node),
+                };
+                match var {
+                    Some((var, _)) => {
+                        variables.push(ext::node_var(var));
+                        ext::is(token).set(ext::local(var))
+                    }
+                    None => ext::is(token),
+                }
+            }
+            Item::Group {
+                items, repeated, ..
+            } => {
+                let group = rules(items, variables);
+                match repeated {
+                    true => ext::repeated(group),
+                    false => ext::optional(group),
+                }
+            }
+        })
+        .collect()
+}
+
+/// Validation errors at the item they are about, or at the name of their node
+fn validate(nodes: &[Node]) -> Vec<Error> {
+    let parser = build(nodes);
+    let result = Validator::default().validate(&parser);
+    result
+        .errors
+        .iter()
+        .map(|err| {
+            let node = err
+                .node
+                .and_then(|node| nodes.iter().find(|n| n.name == node.name.as_str()));
+            let name = match &err.kind {
+                ValidationErrors::NodeNotFound(name)
+                | ValidationErrors::TokenNotFound(name)
+                | ValidationErrors::EnumeratorNotFound(name) => Some(*name),
+                ValidationErrors::VariableNotFound(VarKind::Local(name))
+                | ValidationErrors::CantUseVariable(VarKind::Local(name)) => Some(*name),
+                _ => None,
+            };
+            let span = node.map_or(Span::call_site(), |node| {
+                name.and_then(|name| find(&node.items, name))
+                    .unwrap_or(node.span)
+            });
+            let owned = ValidationErrorOwned::from(err);
+            Error::new(
+                span,
+                format!("{} [{}]: {}", owned.header, owned.code, owned.message),
+            )
+        })
+        .collect()
+}
+
+/// Span of the first item that refers to the name
+fn find(items: &[Item], name: &str) -> Option<Span> {
+    items.iter().find_map(|item| match item {
+        Item::Literal { text, .. } if text == name => Some(item.span()),
+        Item::Token { var, kind, .. } => match var {
+            Some((var, span)) if var == name => Some(*span),
+            _ if kind == name => Some(item.span()),
+            _ => None,
+        },
+        Item::Group { items, .. } => find(items, name),
+        _ => None,
+    })
+}
+
+/// `ruparse::grammar!` with the lexer tokens and the entry set
+fn expand(nodes: &[Node], input: TokenStream) -> TokenStream {
+    let mut body = code("let mut parser = ::ruparse::Parser::new();");
+    let tokens = tokens(nodes);
+    if !tokens.is_empty() {
+        body.extend(code(&format!(
+            "parser.lexer.add_tokens({tokens:?}.into_iter());"
+        )));
+    }
+    let mut grammar = code("parser.grammar;");
+    grammar.extend(input);
+    body.extend(code("::ruparse::grammar!"));
+    body.extend([TokenTree::Group(Group::new(Delimiter::Brace, grammar))]);
+    if let Some(entry) = nodes.first() {
+        body.extend(code(&format!(
+            "parser.parser.entry = ::core::option::Option::Some({:?});",
+            entry.name
+        )));
+    }
+    body.extend(code("parser"));
+    TokenTree::Group(Group::new(Delimiter::Brace, body)).into()
+}
@@ -0,0 +1,24 @@
+use ruparse::parser::ParseResult;
+use ruparse_macros::static_grammar;
+
+#[test]
+fn static_grammar() {
+    let parser = static_grammar! {
+        KWLet: "let" <ident:text> (":" <type:text>)? ("=" <value:value>)? ";";
+        value: <text:text> ("," text)*;
+    };
+    assert_eq!(parser.parser.entry, Some("KWLet"));
+
+    for (txt, vars) in [
+        ("let x: int = 5, 6;", ["x", "int", "5, 6"]),
+        ("let y;", ["y", "", ""]),
+    ] {
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let text = |var: &str| match res.entry.node_unchecked(var) {
+            Some(node) => ParseResult::stringify_node(node, txt),
+            None => "",
+        };
+        assert_eq!([text("ident"), text("type"), text("value")], vars);
+    }
+}
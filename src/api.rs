@@ -1,5 +1,8 @@
 use crate::{
-    lexer::{TextLocation, Token},
+    grammar::SymbolKind,
+    incremental::TextEdit,
+    lexer::{LineIndex, TabPolicy, TextLocation, Token, TokenData, TokenKinds},
+    memory::NodeVec,
     parser::{self, Nodes},
 };
 use core::panic;
@@ -18,93 +21,291 @@ cfg_if::cfg_if! {
         use alloc::format;
     }
 }
+/// Error returned by the `try_*` accessors
+///
+/// Every accessor has a `try_*` variant that never panics.
+/// The `*_unchecked` accessors panic with the message of this error instead,
+/// they are meant for trees parsed by a grammar that passed the validator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessError {
+    /// Expected a node, found a token
+    IsToken(String),
+    /// Expected a token, found a node
+    IsNode(String),
+    /// Found text skipped by error recovery, see `parser::Nodes::Error`
+    IsError(core::ops::Range<usize>),
+    /// The node has no such variable
+    VariableNotFound {
+        node: String,
+        variable: String,
+        existing: Vec<String>,
+    },
+    /// The variable holds a different kind of value
+    WrongKind {
+        node: String,
+        variable: String,
+        expected: &'static str,
+        found: String,
+        existing: Vec<String>,
+    },
+    /// Node variable is required but was not set
+    MissingNode { node: String, variable: String },
+    /// The node does not record its children, see `NodeBuilder::record_children`
+    ChildrenNotRecorded(String),
+    /// The node does not record its tokens, see `NodeBuilder::record_tokens`
+    TokensNotRecorded(String),
+    /// The node ends before it starts
+    InvalidSpan {
+        node: String,
+        span: core::ops::Range<usize>,
+    },
+}
+
+impl core::fmt::Display for AccessError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AccessError::IsToken(kind) => write!(f, "Expected a node, found token: {}", kind),
+            AccessError::IsNode(name) => write!(f, "Expected a token, found node: {}", name),
+            AccessError::IsError(span) => write!(
+                f,
+                "Found an error recovered from at {}..{}",
+                span.start, span.end
+            ),
+            AccessError::VariableNotFound {
+                node,
+                variable,
+                existing,
+            } => write!(
+                f,
+                "No variable \"{}\" found for node \"{}\". Existing variables: {:?}",
+                variable, node, existing
+            ),
+            AccessError::WrongKind {
+                node,
+                variable,
+                expected,
+                found,
+                existing,
+            } => write!(
+                f,
+                "Variable \"{}\" is not {} for node \"{}\". It is {}. Existing variables: {:?}",
+                variable, expected, node, found, existing
+            ),
+            AccessError::MissingNode { node, variable } => write!(
+                f,
+                "Missing required node variable `{}` on `{}`",
+                variable, node
+            ),
+            AccessError::ChildrenNotRecorded(node) => {
+                write!(f, "Node \"{}\" does not record its children", node)
+            }
+            AccessError::TokensNotRecorded(node) => {
+                write!(f, "Node \"{}\" does not record its tokens", node)
+            }
+            AccessError::InvalidSpan { node, span } => write!(
+                f,
+                "Node \"{}\" ends before it starts: {}..{}",
+                node, span.start, span.end
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AccessError {}
+
+/// Panics with the message of the error
+#[track_caller]
+fn unchecked<T>(result: Result<T, AccessError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => panic!("{}", err),
+    }
+}
+
 impl<'a> parser::Nodes<'a> {
     /// Returns name of node
     ///
     /// Panics if the type is token
     #[track_caller]
-    pub fn get_name(&'a self) -> &'a str {
+    pub fn name_unchecked(&self) -> &'a str {
+        unchecked(self.try_name())
+    }
+    /// Returns name of node
+    pub fn try_name(&self) -> Result<&'a str, AccessError> {
         match self {
-            parser::Nodes::Node(node) => node.name,
-            parser::Nodes::Token(tok) => panic!("No name found for token: {:?}", tok.kind),
+            parser::Nodes::Node(node) => Ok(node.name.as_str()),
+            parser::Nodes::Token(tok) => Err(AccessError::IsToken(tok.kind.to_string())),
+            parser::Nodes::Error { span, .. } => Err(AccessError::IsError(span.clone())),
         }
     }
     /// Returns token type
     ///
     /// Panics if the type is node
     #[track_caller]
-    pub fn expect_token(&self) -> &Token<'_> {
+    pub fn token_unchecked(&self) -> &Token<'a> {
+        unchecked(self.try_token())
+    }
+    /// Returns token type
+    pub fn try_token(&self) -> Result<&Token<'a>, AccessError> {
         match self {
-            parser::Nodes::Node(node) => panic!("No token found for node: {:?}", node.name),
-            parser::Nodes::Token(tok) => tok,
+            parser::Nodes::Node(node) => Err(AccessError::IsNode(node.name.to_string())),
+            parser::Nodes::Token(tok) => Ok(tok),
+            parser::Nodes::Error { span, .. } => Err(AccessError::IsError(span.clone())),
+        }
+    }
+    /// Value attached to the token by a preprocessor
+    ///
+    /// None for nodes and tokens without a value
+    pub fn token_data(&self) -> Option<TokenData> {
+        match self {
+            parser::Nodes::Token(tok) => tok.data,
+            _ => None,
+        }
+    }
+    /// Returns the node
+    ///
+    /// Panics if the type is token
+    #[track_caller]
+    pub fn as_node_unchecked(&self) -> &parser::Node<'a> {
+        unchecked(self.try_as_node())
+    }
+    /// Returns the node or an error if the type is token
+    pub fn try_as_node(&self) -> Result<&parser::Node<'a>, AccessError> {
+        match self {
+            parser::Nodes::Node(node) => Ok(node),
+            parser::Nodes::Token(tok) => Err(AccessError::IsToken(tok.kind.to_string())),
+            parser::Nodes::Error { span, .. } => Err(AccessError::IsError(span.clone())),
         }
     }
     /// The length in text
+    ///
+    /// Panics if the node ends before it starts
     #[track_caller]
-    pub fn len(&self) -> usize {
+    pub fn len_unchecked(&self) -> usize {
+        unchecked(self.try_len())
+    }
+    /// The length in text
+    pub fn try_len(&self) -> Result<usize, AccessError> {
         match self {
-            parser::Nodes::Node(node) => node.last_string_idx - node.first_string_idx,
-            parser::Nodes::Token(tok) => tok.len,
+            parser::Nodes::Node(node) => node
+                .last_string_idx
+                .checked_sub(node.first_string_idx)
+                .ok_or_else(|| AccessError::InvalidSpan {
+                    node: node.name.to_string(),
+                    span: node.first_string_idx..node.last_string_idx,
+                }),
+            parser::Nodes::Token(tok) => Ok(tok.len),
+            parser::Nodes::Error { span, .. } => Ok(span.len()),
         }
     }
     /// Returns value of variable that is a number
     ///
     /// Panics if the variable is not a number or if it does not exist
     #[track_caller]
-    pub fn get_number(&self, variable: &str) -> i32 {
-        match self {
-            parser::Nodes::Node(node) => node.get_number(variable),
-            parser::Nodes::Token(tok) => panic!("No variables found for token: {:?}", tok.kind),
-        }
+    pub fn number_unchecked(&self, variable: &str) -> i32 {
+        unchecked(self.try_number(variable))
+    }
+    /// Returns value of variable that is a number
+    pub fn try_number(&self, variable: &str) -> Result<i32, AccessError> {
+        self.try_as_node()?.try_number(variable)
+    }
+    /// Returns value of variable that is a 64 bit number
+    ///
+    /// Panics if the variable is not a 64 bit number or if it does not exist
+    #[track_caller]
+    pub fn number64_unchecked(&self, variable: &str) -> i64 {
+        unchecked(self.try_number64(variable))
+    }
+    /// Returns value of variable that is a 64 bit number
+    pub fn try_number64(&self, variable: &str) -> Result<i64, AccessError> {
+        self.try_as_node()?.try_number64(variable)
+    }
+    /// Returns value of variable that is a float
+    ///
+    /// Panics if the variable is not a float or if it does not exist
+    #[track_caller]
+    pub fn float_unchecked(&self, variable: &str) -> f64 {
+        unchecked(self.try_float(variable))
+    }
+    /// Returns value of variable that is a float
+    pub fn try_float(&self, variable: &str) -> Result<f64, AccessError> {
+        self.try_as_node()?.try_float(variable)
     }
     /// Returns value of variable that is a bool
     ///
     /// Panics if the variable is not a bool or if it does not exist
     #[track_caller]
-    pub fn get_bool(&self, variable: &str) -> bool {
-        match self {
-            parser::Nodes::Node(node) => node.get_bool(variable),
-            parser::Nodes::Token(tok) => panic!("No variables found for token: {:?}", tok.kind),
-        }
+    pub fn bool_unchecked(&self, variable: &str) -> bool {
+        unchecked(self.try_bool(variable))
+    }
+    /// Returns value of variable that is a bool
+    pub fn try_bool(&self, variable: &str) -> Result<bool, AccessError> {
+        self.try_as_node()?.try_bool(variable)
     }
     /// Returns value of variable that is a node
     ///
     /// Panics if the variable is not a node or if it does not exist
     #[track_caller]
-    pub fn try_get_node(&'a self, variable: &str) -> &'a Option<parser::Nodes<'a>> {
-        match self {
-            parser::Nodes::Node(node_) => node_.try_get_node(variable),
-            parser::Nodes::Token(tok) => panic!("No variables found for token: {:?}", tok.kind),
-        }
+    pub fn node_unchecked(&self, variable: &str) -> &Option<parser::Nodes<'a>> {
+        unchecked(self.try_node(variable))
+    }
+    /// Returns value of variable that is a node
+    pub fn try_node(&self, variable: &str) -> Result<&Option<parser::Nodes<'a>>, AccessError> {
+        self.try_as_node()?.try_node(variable)
     }
     /// Required node variable
+    ///
+    /// Panics if the variable is not a node, if it does not exist or if it is not set
     #[track_caller]
-    pub fn expect_node(&'a self, name: &str) -> &'a Nodes<'a> {
-        match self.try_get_node(name) {
-            Some(n) => n,
-            None => panic!(
-                "Missing required node variable `{}` on `{}`",
-                name,
-                self.get_name()
-            ),
-        }
+    pub fn required_node_unchecked(&self, variable: &str) -> &Nodes<'a> {
+        unchecked(self.try_required_node(variable))
+    }
+    /// Required node variable
+    pub fn try_required_node(&self, variable: &str) -> Result<&Nodes<'a>, AccessError> {
+        self.try_as_node()?.try_required_node(variable)
     }
     /// Returns value of variable that is a list of nodes
     ///
     /// Panics if the variable is not a list of nodes or if it does not exist
     #[track_caller]
-    pub fn get_list(&self, variable: &str) -> &Vec<parser::Nodes<'_>> {
-        match self {
-            parser::Nodes::Node(node_) => node_.get_list(variable),
-            parser::Nodes::Token(tok) => panic!("No variables found for token: {:?}", tok.kind),
-        }
+    pub fn list_unchecked(&self, variable: &str) -> &NodeVec<parser::Nodes<'a>> {
+        unchecked(self.try_list(variable))
+    }
+    /// Returns value of variable that is a list of nodes
+    pub fn try_list(&self, variable: &str) -> Result<&NodeVec<parser::Nodes<'a>>, AccessError> {
+        self.try_as_node()?.try_list(variable)
+    }
+    /// Returns labeled entries of a list variable set with `set_field`
+    ///
+    /// Panics if the variable is not a list of nodes or if it does not exist
+    #[track_caller]
+    pub fn pairs_unchecked(&self, variable: &str) -> Vec<(&'a str, &parser::Nodes<'a>)> {
+        unchecked(self.try_pairs(variable))
+    }
+    /// Returns labeled entries of a list variable set with `set_field`
+    pub fn try_pairs(
+        &self,
+        variable: &str,
+    ) -> Result<Vec<(&'a str, &parser::Nodes<'a>)>, AccessError> {
+        self.try_as_node()?.try_pairs(variable)
     }
+    /// Returns everything the node matched in order
+    ///
+    /// Panics if the type is token or if the node does not record its children
     #[track_caller]
-    pub fn location(&self) -> TextLocation {
-        match self {
-            parser::Nodes::Node(node) => node.location,
-            parser::Nodes::Token(tok) => tok.location,
-        }
+    pub fn children_unchecked(&self) -> &[parser::Nodes<'a>] {
+        unchecked(self.try_children())
+    }
+    /// Returns everything the node matched in order
+    pub fn try_children(&self) -> Result<&[parser::Nodes<'a>], AccessError> {
+        self.try_as_node()?.try_children()
+    }
+    /// Location of the text of the value, see `lexer::Lexer::locate` for the columns
+    /// the lexer counts
+    pub fn location(&self, lines: &LineIndex, tabs: TabPolicy) -> TextLocation {
+        let start = self.str_idx();
+        lines.locate(start, self.str_last_idx().saturating_sub(start), tabs)
     }
 }
 impl<'a> parser::Node<'a> {
@@ -112,16 +313,47 @@ impl<'a> parser::Node<'a> {
     ///
     /// Panics if the variable is not a number or if it does not exist
     #[track_caller]
-    pub fn get_number(&self, variable: &str) -> i32 {
-        match self.variables.get(variable) {
-            Some(num) => match num {
-                &parser::VariableKind::Number(num) => num,
-                _ => panic!(
-                    "Variable \"{}\" is not a number for node \"{}\". It is {:?}. Existing variables: {:?}",
-                    variable, self.name, num, self.variables.keys().collect::<Vec<_>>()
-                ),
-            },
-            None => panic!("No variable \"{}\" found for node \"{}\". Existing variables: {:?}", variable, self.name, self.variables.keys().collect::<Vec<_>>()),
+    pub fn number_unchecked(&self, variable: &str) -> i32 {
+        unchecked(self.try_number(variable))
+    }
+
+    /// Returns value of variable that is a number
+    pub fn try_number(&self, variable: &str) -> Result<i32, AccessError> {
+        match self.variable(variable)? {
+            &parser::VariableKind::Number(num) => Ok(num),
+            other => Err(self.wrong_kind(variable, "a number", other)),
+        }
+    }
+
+    /// Returns value of variable that is a 64 bit number
+    ///
+    /// Panics if the variable is not a 64 bit number or if it does not exist
+    #[track_caller]
+    pub fn number64_unchecked(&self, variable: &str) -> i64 {
+        unchecked(self.try_number64(variable))
+    }
+
+    /// Returns value of variable that is a 64 bit number
+    pub fn try_number64(&self, variable: &str) -> Result<i64, AccessError> {
+        match self.variable(variable)? {
+            &parser::VariableKind::Number64(num) => Ok(num),
+            other => Err(self.wrong_kind(variable, "a 64 bit number", other)),
+        }
+    }
+
+    /// Returns value of variable that is a float
+    ///
+    /// Panics if the variable is not a float or if it does not exist
+    #[track_caller]
+    pub fn float_unchecked(&self, variable: &str) -> f64 {
+        unchecked(self.try_float(variable))
+    }
+
+    /// Returns value of variable that is a float
+    pub fn try_float(&self, variable: &str) -> Result<f64, AccessError> {
+        match self.variable(variable)? {
+            &parser::VariableKind::Float(num) => Ok(num),
+            other => Err(self.wrong_kind(variable, "a float", other)),
         }
     }
 
@@ -129,16 +361,15 @@ impl<'a> parser::Node<'a> {
     ///
     /// Panics if the variable is not a bool or if it does not exist
     #[track_caller]
-    pub fn get_bool(&self, variable: &str) -> bool {
-        match self.variables.get(variable) {
-            Some(bool) => match bool {
-                &parser::VariableKind::Boolean(bool) => bool,
-                _ => panic!(
-                    "Variable \"{}\" is not a bool for node \"{}\". It is {:?}. Existing variables: {:?}",
-                    variable, self.name, bool, self.variables.keys().collect::<Vec<_>>()
-                ),
-            },
-            None => panic!("No variable \"{}\" found for node \"{}\". Existing variables: {:?}", variable, self.name, self.variables.keys().collect::<Vec<_>>()),
+    pub fn bool_unchecked(&self, variable: &str) -> bool {
+        unchecked(self.try_bool(variable))
+    }
+
+    /// Returns value of variable that is a bool
+    pub fn try_bool(&self, variable: &str) -> Result<bool, AccessError> {
+        match self.variable(variable)? {
+            &parser::VariableKind::Boolean(bool) => Ok(bool),
+            other => Err(self.wrong_kind(variable, "a bool", other)),
         }
     }
 
@@ -146,16 +377,34 @@ impl<'a> parser::Node<'a> {
     ///
     /// Panics if the variable is not a node or if it does not exist
     #[track_caller]
-    pub fn try_get_node(&self, variable: &str) -> &Option<parser::Nodes<'_>> {
-        match self.variables.get(variable) {
-            Some(node) => match node {
-                parser::VariableKind::Node(ref node) => node,
-                _ => panic!(
-                    "Variable \"{}\" is not a node for node \"{}\". It is {:?}. Existing variables: {:?}",
-                    variable, self.name, node, self.variables.keys().collect::<Vec<_>>()
-                ),
-            },
-            None => panic!("No variable \"{}\" found for node \"{}\". Existing variables: {:?}", variable, self.name, self.variables.keys().collect::<Vec<_>>()),
+    pub fn node_unchecked(&self, variable: &str) -> &Option<parser::Nodes<'a>> {
+        unchecked(self.try_node(variable))
+    }
+
+    /// Returns value of variable that is a node
+    pub fn try_node(&self, variable: &str) -> Result<&Option<parser::Nodes<'a>>, AccessError> {
+        match self.variable(variable)? {
+            parser::VariableKind::Node(node) => Ok(node),
+            other => Err(self.wrong_kind(variable, "a node", other)),
+        }
+    }
+
+    /// Required node variable
+    ///
+    /// Panics if the variable is not a node, if it does not exist or if it is not set
+    #[track_caller]
+    pub fn required_node_unchecked(&self, variable: &str) -> &Nodes<'a> {
+        unchecked(self.try_required_node(variable))
+    }
+
+    /// Required node variable
+    pub fn try_required_node(&self, variable: &str) -> Result<&Nodes<'a>, AccessError> {
+        match self.try_node(variable)? {
+            Some(node) => Ok(node),
+            None => Err(AccessError::MissingNode {
+                node: self.name.to_string(),
+                variable: variable.to_string(),
+            }),
         }
     }
 
@@ -163,18 +412,210 @@ impl<'a> parser::Node<'a> {
     ///
     /// Panics if the variable is not a list of nodes or if it does not exist
     #[track_caller]
-    pub fn get_list(&self, variable: &str) -> &Vec<parser::Nodes<'_>> {
-        match self.variables.get(variable) {
-            Some(ref array) => match array {
-                parser::VariableKind::NodeList(array) => array,
-                _ => panic!(
-                    "Variable \"{}\" is not an array for node \"{}\". It is {:?}. Existing variables: {:?}",
-                    variable, self.name, array, self.variables.keys().collect::<Vec<_>>()
-                ),
-            },
-            None => panic!("No variable \"{}\" found for node \"{}\". Existing variables: {:?}", variable, self.name, self.variables.keys().collect::<Vec<_>>()),
+    pub fn list_unchecked(&self, variable: &str) -> &NodeVec<parser::Nodes<'a>> {
+        unchecked(self.try_list(variable))
+    }
+
+    /// Returns value of variable that is a list of nodes
+    pub fn try_list(&self, variable: &str) -> Result<&NodeVec<parser::Nodes<'a>>, AccessError> {
+        match self.variable(variable)? {
+            parser::VariableKind::NodeList(array) => Ok(array),
+            other => Err(self.wrong_kind(variable, "an array", other)),
+        }
+    }
+
+    /// Returns labeled entries of a list variable set with `set_field`
+    ///
+    /// Entries pushed with `set` are left out
+    ///
+    /// Panics if the variable is not a list of nodes or if it does not exist
+    #[track_caller]
+    pub fn pairs_unchecked(&self, variable: &str) -> Vec<(&'a str, &parser::Nodes<'a>)> {
+        unchecked(self.try_pairs(variable))
+    }
+
+    /// Returns labeled entries of a list variable set with `set_field`
+    pub fn try_pairs(
+        &self,
+        variable: &str,
+    ) -> Result<Vec<(&'a str, &parser::Nodes<'a>)>, AccessError> {
+        let list = self.try_list(variable)?;
+        Ok(self
+            .fields
+            .iter()
+            .filter(|(var, _, _)| *var == variable)
+            .filter_map(|(_, idx, field)| Some((*field, list.get(*idx)?)))
+            .collect())
+    }
+
+    /// Returns everything the node matched in order
+    ///
+    /// Panics if the node does not record its children
+    #[track_caller]
+    pub fn children_unchecked(&self) -> &[parser::Nodes<'a>] {
+        unchecked(self.try_children())
+    }
+
+    /// Returns everything the node matched in order
+    pub fn try_children(&self) -> Result<&[parser::Nodes<'a>], AccessError> {
+        match &self.children {
+            Some(children) => Ok(children),
+            None => Err(AccessError::ChildrenNotRecorded(self.name.to_string())),
+        }
+    }
+
+    /// Returns the tokens the node matched in order
+    ///
+    /// Panics if the node does not record its tokens
+    #[track_caller]
+    pub fn tokens_unchecked(&self) -> &[Token<'a>] {
+        unchecked(self.try_tokens())
+    }
+
+    /// Returns the tokens the node matched in order
+    pub fn try_tokens(&self) -> Result<&[Token<'a>], AccessError> {
+        match &self.tokens {
+            Some(tokens) => Ok(tokens),
+            None => Err(AccessError::TokensNotRecorded(self.name.to_string())),
         }
     }
+
+    /// Returns the first recorded token of the kind
+    pub fn find_token(&self, kind: TokenKinds) -> Result<Option<&Token<'a>>, AccessError> {
+        Ok(self.try_tokens()?.iter().find(|token| token.kind == kind))
+    }
+
+    /// Whitespace and ignored tokens in front of the node owned by it
+    ///
+    /// Empty unless `Grammar::trivia` is set
+    pub fn leading_trivia(&self) -> &[Token<'a>] {
+        self.trivia.as_ref().map_or(&[], |t| &t.leading)
+    }
+
+    /// Whitespace and ignored tokens after the node owned by it
+    ///
+    /// Empty unless `Grammar::trivia` is set
+    pub fn trailing_trivia(&self) -> &[Token<'a>] {
+        self.trivia.as_ref().map_or(&[], |t| &t.trailing)
+    }
+
+    /// Returns the nested nodes with the name in the order of the text
+    ///
+    /// Only looks through node variables, the node itself is not included
+    pub fn find_all(&self, name: &str) -> Vec<&parser::Node<'a>> {
+        let mut found = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            for variable in node.variables.values() {
+                let values = match variable {
+                    parser::VariableKind::Node(Some(value)) => core::slice::from_ref(value),
+                    parser::VariableKind::NodeList(list) => list.as_slice(),
+                    _ => &[],
+                };
+                for value in values {
+                    if let Nodes::Node(child) = value {
+                        if child.name == name {
+                            found.push(&**child);
+                        }
+                        stack.push(child);
+                    }
+                }
+            }
+        }
+        found.sort_by_key(|node| node.first_string_idx);
+        found
+    }
+
+    fn variable(&self, variable: &str) -> Result<&parser::VariableKind<'a>, AccessError> {
+        self.variables
+            .get(variable)
+            .ok_or_else(|| AccessError::VariableNotFound {
+                node: self.name.to_string(),
+                variable: variable.to_string(),
+                existing: self.variables.keys().map(String::from).collect(),
+            })
+    }
+
+    fn wrong_kind(
+        &self,
+        variable: &str,
+        expected: &'static str,
+        found: &parser::VariableKind,
+    ) -> AccessError {
+        AccessError::WrongKind {
+            node: self.name.to_string(),
+            variable: variable.to_string(),
+            expected,
+            found: found.to_string(),
+            existing: self.variables.keys().map(String::from).collect(),
+        }
+    }
+}
+/// Names of the panicking accessors before the `*_unchecked` convention
+impl<'a> parser::Nodes<'a> {
+    #[deprecated(note = "renamed to `name_unchecked`, see `try_name`")]
+    #[track_caller]
+    pub fn get_name(&self) -> &'a str {
+        self.name_unchecked()
+    }
+    #[deprecated(note = "renamed to `token_unchecked`, see `try_token`")]
+    #[track_caller]
+    pub fn expect_token(&self) -> &Token<'a> {
+        self.token_unchecked()
+    }
+    #[deprecated(note = "renamed to `len_unchecked`, see `try_len`")]
+    #[track_caller]
+    pub fn len(&self) -> usize {
+        self.len_unchecked()
+    }
+    #[deprecated(note = "renamed to `number_unchecked`, see `try_number`")]
+    #[track_caller]
+    pub fn get_number(&self, variable: &str) -> i32 {
+        self.number_unchecked(variable)
+    }
+    #[deprecated(note = "renamed to `bool_unchecked`, see `try_bool`")]
+    #[track_caller]
+    pub fn get_bool(&self, variable: &str) -> bool {
+        self.bool_unchecked(variable)
+    }
+    #[deprecated(note = "renamed to `node_unchecked`, see `try_node`")]
+    #[track_caller]
+    pub fn try_get_node(&self, variable: &str) -> &Option<parser::Nodes<'a>> {
+        self.node_unchecked(variable)
+    }
+    #[deprecated(note = "renamed to `required_node_unchecked`, see `try_required_node`")]
+    #[track_caller]
+    pub fn expect_node(&self, variable: &str) -> &Nodes<'a> {
+        self.required_node_unchecked(variable)
+    }
+    #[deprecated(note = "renamed to `list_unchecked`, see `try_list`")]
+    #[track_caller]
+    pub fn get_list(&self, variable: &str) -> &NodeVec<parser::Nodes<'a>> {
+        self.list_unchecked(variable)
+    }
+}
+/// Names of the panicking accessors before the `*_unchecked` convention
+impl<'a> parser::Node<'a> {
+    #[deprecated(note = "renamed to `number_unchecked`, see `try_number`")]
+    #[track_caller]
+    pub fn get_number(&self, variable: &str) -> i32 {
+        self.number_unchecked(variable)
+    }
+    #[deprecated(note = "renamed to `bool_unchecked`, see `try_bool`")]
+    #[track_caller]
+    pub fn get_bool(&self, variable: &str) -> bool {
+        self.bool_unchecked(variable)
+    }
+    #[deprecated(note = "renamed to `node_unchecked`, see `try_node`")]
+    #[track_caller]
+    pub fn try_get_node(&self, variable: &str) -> &Option<parser::Nodes<'a>> {
+        self.node_unchecked(variable)
+    }
+    #[deprecated(note = "renamed to `list_unchecked`, see `try_list`")]
+    #[track_caller]
+    pub fn get_list(&self, variable: &str) -> &NodeVec<parser::Nodes<'a>> {
+        self.list_unchecked(variable)
+    }
 }
 impl<'a> parser::ParseResult<'a> {
     /// Returns stringified version of the node
@@ -185,6 +626,7 @@ impl<'a> parser::ParseResult<'a> {
         match node {
             parser::Nodes::Node(node) => &text[node.first_string_idx..node.last_string_idx],
             parser::Nodes::Token(tok) => &text[tok.index..tok.index + tok.len],
+            parser::Nodes::Error { span, .. } => &text[span.clone()],
         }
     }
 
@@ -201,20 +643,214 @@ impl<'a> parser::ParseResult<'a> {
         let start_idx = match start {
             parser::Nodes::Node(node) => node.first_string_idx,
             parser::Nodes::Token(tok) => tok.index,
+            parser::Nodes::Error { span, .. } => span.start,
         };
         let end_idx = match end {
             parser::Nodes::Node(node) => node.last_string_idx,
             parser::Nodes::Token(tok) => tok.index + tok.len,
+            parser::Nodes::Error { span, .. } => span.end,
         };
         &text[start_idx..end_idx]
     }
+
+    /// Replaces the text of the node, returns the new text and the edit that was made
+    ///
+    /// The edit can be passed to `crate::Parser::reparse_edit`
+    /// to parse only the replaced node again instead of the whole text
+    #[track_caller]
+    pub fn edit_node(node: &parser::Nodes, text: &str, new_text: &str) -> (String, TextEdit) {
+        let span = crate::scopes::span(node);
+        let mut out = String::with_capacity(text.len() - span.len() + new_text.len());
+        out.push_str(&text[..span.start]);
+        out.push_str(new_text);
+        out.push_str(&text[span.end..]);
+        let edit = TextEdit {
+            span,
+            text: new_text.to_string(),
+        };
+        (out, edit)
+    }
+
+    /// Returns regions of all nodes marked as foldable in the grammar
+    ///
+    /// Regions that start and end on the same line are left out
+    /// since there is nothing to fold
+    pub fn folding_ranges(&self, text: &str) -> Vec<FoldingRange<'a>> {
+        let lines = LineIndex::new(text);
+        let mut ranges = Vec::new();
+        collect_folding_ranges(&self.entry, &lines, &mut ranges);
+        ranges.sort_by_key(|r| (r.start, core::cmp::Reverse(r.end)));
+        ranges
+    }
+
+    /// Returns a hierarchical outline of the document
+    ///
+    /// Built from nodes that declare a symbol in the grammar,
+    /// symbols are nested the same way their nodes are
+    pub fn document_symbols<'t>(&self, text: &'t str) -> Vec<DocumentSymbol<'a, 't>> {
+        let mut symbols = Vec::new();
+        collect_document_symbols(&self.entry, text, &mut symbols);
+        symbols
+    }
+
+    /// Returns the indentation level of the line counted from the nodes
+    /// that indent their children, see `grammar::Node::indents_children`
+    ///
+    /// Lines start at 1, the text has to be the one that was parsed
+    pub fn indent_level_at(&self, text: &str, line: usize) -> usize {
+        indent_level(&self.entry, &LineIndex::new(text), line)
+    }
 }
+
+/// A region of text that can be collapsed by an editor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoldingRange<'a> {
+    /// Name of the node that produced the range
+    pub node: &'a str,
+    /// Line on which the range starts
+    pub start_line: usize,
+    /// Line on which the range ends
+    pub end_line: usize,
+    /// Index of the first byte of the range
+    pub start: usize,
+    /// Index after the last byte of the range
+    pub end: usize,
+}
+
+/// Nodes stored directly in the variables of the node
+fn child_nodes<'n, 'a>(node: &'n parser::Node<'a>) -> impl Iterator<Item = &'n parser::Node<'a>> {
+    node.variables.values().flat_map(|var| {
+        let nodes: &'n [Nodes<'a>] = match var {
+            parser::VariableKind::Node(Some(child)) => core::slice::from_ref(child),
+            parser::VariableKind::NodeList(list) => list,
+            _ => &[],
+        };
+        nodes.iter().filter_map(|child| match child {
+            Nodes::Node(child) => Some(&**child),
+            Nodes::Token(_) | Nodes::Error { .. } => None,
+        })
+    })
+}
+
+fn collect_folding_ranges<'a>(
+    node: &parser::Node<'a>,
+    lines: &LineIndex,
+    ranges: &mut Vec<FoldingRange<'a>>,
+) {
+    if node.foldable {
+        let start = node.first_string_idx;
+        let end = node.last_string_idx.max(start);
+        let start_line = lines.line(start);
+        let end_line = lines.line(end.saturating_sub(1).max(start));
+        if end_line > start_line {
+            ranges.push(FoldingRange {
+                node: node.name.as_str(),
+                start_line,
+                end_line,
+                start,
+                end,
+            });
+        }
+    }
+    for child in child_nodes(node) {
+        collect_folding_ranges(child, lines, ranges);
+    }
+}
+
+/// First and last line of the node
+fn line_span(node: &parser::Node, lines: &LineIndex) -> (usize, usize) {
+    let start = node.first_string_idx;
+    let end = node.last_string_idx.max(start);
+    (
+        lines.line(start),
+        lines.line(end.saturating_sub(1).max(start)),
+    )
+}
+
+fn indent_level(node: &parser::Node, lines: &LineIndex, line: usize) -> usize {
+    let nested = child_nodes(node)
+        .filter(|child| {
+            let (start, end) = line_span(child, lines);
+            (start..=end).contains(&line)
+        })
+        .map(|child| indent_level(child, lines, line))
+        .max();
+    let (start, end) = line_span(node, lines);
+    let inside = start < line && (line < end || line == end && nested.is_some());
+    nested.unwrap_or(0) + (node.indents_children && inside) as usize
+}
+
+/// An entry of the document outline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentSymbol<'a, 't> {
+    /// Name of the symbol taken from the text
+    pub name: &'t str,
+    /// Kind of the symbol
+    pub kind: SymbolKind,
+    /// Name of the node that produced the symbol
+    pub node: &'a str,
+    /// Byte range of the whole node
+    pub range: core::ops::Range<usize>,
+    /// Byte range of the name
+    pub selection_range: core::ops::Range<usize>,
+    /// Symbols nested inside of this one
+    pub children: Vec<DocumentSymbol<'a, 't>>,
+}
+
+fn collect_document_symbols<'a, 't>(
+    node: &parser::Node<'a>,
+    text: &'t str,
+    symbols: &mut Vec<DocumentSymbol<'a, 't>>,
+) {
+    let mut children = Vec::new();
+    for child in child_nodes(node) {
+        collect_document_symbols(child, text, &mut children);
+    }
+    children.sort_by_key(|s| s.range.start);
+
+    let name = node
+        .symbol
+        .and_then(|symbol| match node.variables.get(symbol.name_from) {
+            Some(parser::VariableKind::Node(Some(name))) => Some((symbol.kind, name)),
+            _ => None,
+        });
+    match name {
+        Some((kind, name)) => {
+            let selection_range = match name {
+                Nodes::Node(n) => n.first_string_idx..n.last_string_idx,
+                Nodes::Token(t) => t.index..t.index + t.len,
+                Nodes::Error { span, .. } => span.clone(),
+            };
+            symbols.push(DocumentSymbol {
+                name: &text[selection_range.clone()],
+                kind,
+                node: node.name.as_str(),
+                range: node.first_string_idx..node.last_string_idx,
+                selection_range,
+                children,
+            })
+        }
+        // Nodes that are not symbols (or have no name) pass their symbols to the parent
+        None => symbols.extend(children),
+    }
+}
+
 impl<'a> Nodes<'a> {
     #[track_caller]
     pub fn stringify(&self, txt: &'a str) -> &'a str {
         match self {
             Nodes::Node(node) => &txt[node.first_string_idx..node.last_string_idx],
             Nodes::Token(token) => &txt[token.index..token.index + token.len],
+            Nodes::Error { span, .. } => &txt[span.clone()],
+        }
+    }
+
+    /// None if the text is not the one the value was parsed from
+    pub fn try_stringify(&self, txt: &'a str) -> Option<&'a str> {
+        match self {
+            Nodes::Node(node) => txt.get(node.first_string_idx..node.last_string_idx),
+            Nodes::Token(token) => txt.get(token.index..token.index + token.len),
+            Nodes::Error { span, .. } => txt.get(span.clone()),
         }
     }
 
@@ -223,10 +859,12 @@ impl<'a> Nodes<'a> {
         let end = match end {
             Nodes::Node(node) => node.last_string_idx,
             Nodes::Token(token) => token.index + token.len,
+            Nodes::Error { span, .. } => span.end,
         };
         match self {
             Nodes::Node(node) => &txt[node.first_string_idx..end],
             Nodes::Token(token) => &txt[token.index..end],
+            Nodes::Error { span, .. } => &txt[span.start..end],
         }
     }
 }
@@ -237,9 +875,10 @@ impl Nodes<'_> {
     #[track_caller]
     pub fn ice(&self, msg: &str) -> ! {
         panic!(
-            "internal compiler error UWU 🧊\n source location: {:?}\n node: {}\n msg: {}",
-            self.location(),
-            self.get_name(),
+            "internal compiler error UWU 🧊\n source bytes: {}..{}\n node: {}\n msg: {}",
+            self.str_idx(),
+            self.str_last_idx(),
+            self.name_unchecked(),
             msg,
         )
     }
@@ -248,9 +887,11 @@ impl Nodes<'_> {
 pub mod ext {
     use crate::{
         grammar::{
-            Commands, Comparison, Enumerator, ErrorDefinition, Grammar, MatchToken, Node, OneOf,
-            Parameters, Rule, VarKind, VariableKind,
+            CharClass, Commands, Comparison, Cond, Enumerator, ErrorDefinition, Grammar,
+            InitialValue, LanguageVersion, MatchToken, Node, OneOf, Origin, Parameters, Rule,
+            Symbol, SymbolKind, VarKind, VariableKind,
         },
+        intern,
         lexer::{ControlTokenKind, TokenKinds},
     };
 
@@ -271,8 +912,8 @@ pub mod ext {
     pub fn any() -> MatchToken<'static> {
         MatchToken::Any
     }
-    pub fn node<'a>(node: &'a str) -> MatchToken<'a> {
-        MatchToken::Node(node)
+    pub fn node<'a>(node: &str) -> MatchToken<'a> {
+        MatchToken::Node(intern::Symbol::intern(node))
     }
     pub fn complex<'a>(name: &'a str) -> MatchToken<'a> {
         MatchToken::Token(TokenKinds::Complex(name))
@@ -280,77 +921,187 @@ pub mod ext {
     pub fn enumerator<'a>(enumerator: &'a str) -> MatchToken<'a> {
         MatchToken::Enumerator(enumerator)
     }
+    pub fn alias<'a>(name: &'a str) -> MatchToken<'a> {
+        MatchToken::Alias(name)
+    }
+    /// Tokens of the strings, `tokens("+ - * /".split(' '))`
+    pub fn tokens<'a>(tokens: impl IntoIterator<Item = &'a str>) -> Vec<MatchToken<'a>> {
+        tokens.into_iter().map(token).collect()
+    }
+    /// Words of the strings, `words(["let", "const"])`
+    pub fn words<'a>(words: impl IntoIterator<Item = &'a str>) -> Vec<MatchToken<'a>> {
+        words.into_iter().map(word).collect()
+    }
+    /// Enumerator for `Grammar::add_enum`, `enum_of("operators", tokens(["+", "-"]))`
+    pub fn enum_of<'a>(
+        name: &'a str,
+        values: impl IntoIterator<Item = MatchToken<'a>>,
+    ) -> Enumerator<'a> {
+        Enumerator {
+            name,
+            values: values.into_iter().collect(),
+        }
+    }
     pub fn newline() -> MatchToken<'static> {
         MatchToken::Token(TokenKinds::Control(ControlTokenKind::Eol))
     }
     pub fn eof() -> MatchToken<'static> {
         MatchToken::Token(TokenKinds::Control(ControlTokenKind::Eof))
     }
+    pub fn char(c: char) -> MatchToken<'static> {
+        MatchToken::Char(c)
+    }
+    pub fn char_class(class: CharClass) -> MatchToken<'static> {
+        MatchToken::CharClass(class)
+    }
+    #[track_caller]
     pub fn is<'a>(matches: MatchToken<'a>) -> Rule<'a> {
         Rule::Is {
+            origin: Origin::caller(),
             token: matches,
             rules: Vec::new(),
             parameters: Vec::new(),
         }
     }
+    #[track_caller]
     pub fn isnt<'a>(matches: MatchToken<'a>) -> Rule<'a> {
         Rule::Isnt {
+            origin: Origin::caller(),
             token: matches,
             rules: Vec::new(),
             parameters: Vec::new(),
         }
     }
+    #[track_caller]
     pub fn maybe<'a>(matches: MatchToken<'a>) -> Rule<'a> {
         Rule::Maybe {
+            origin: Origin::caller(),
             token: matches,
             parameters: Vec::new(),
             is: Vec::new(),
             isnt: Vec::new(),
         }
     }
+    #[track_caller]
     pub fn peek<'a>(matches: MatchToken<'a>) -> Rule<'a> {
         Rule::Peek {
+            origin: Origin::caller(),
             token: matches,
             parameters: Vec::new(),
             is: Vec::new(),
             isnt: Vec::new(),
         }
     }
+    #[track_caller]
     pub fn while_<'a>(matches: MatchToken<'a>) -> Rule<'a> {
         Rule::While {
+            origin: Origin::caller(),
             token: matches,
             rules: Vec::new(),
             parameters: Vec::new(),
         }
     }
+    /// Word if the string is made of letters, digits and `_`, token otherwise
+    ///
+    /// Lets `grammar!` tell `"let"` from `";"`
+    pub fn literal(text: &str) -> MatchToken<'_> {
+        match text.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            true => word(text),
+            false => token(text),
+        }
+    }
+    /// `Maybe` of the first rule, the other rules run if it matches
+    ///
+    /// Panics if the first rule is not an `Is`
+    #[track_caller]
+    pub fn optional<'a>(rules: impl IntoIterator<Item = Rule<'a>>) -> Rule<'a> {
+        let (token, is, parameters) = split_first(rules);
+        Rule::Maybe {
+            origin: Origin::caller(),
+            token,
+            is,
+            isnt: Vec::new(),
+            parameters,
+        }
+    }
+    /// `While` of the first rule, the other rules run after every match
+    ///
+    /// Panics if the first rule is not an `Is`
+    #[track_caller]
+    pub fn repeated<'a>(rules: impl IntoIterator<Item = Rule<'a>>) -> Rule<'a> {
+        let (token, rules, parameters) = split_first(rules);
+        Rule::While {
+            origin: Origin::caller(),
+            token,
+            rules,
+            parameters,
+        }
+    }
+    fn split_first<'a>(
+        rules: impl IntoIterator<Item = Rule<'a>>,
+    ) -> (MatchToken<'a>, Vec<Rule<'a>>, Vec<Parameters<'a>>) {
+        let mut rules = rules.into_iter();
+        let Some(Rule::Is {
+            token,
+            rules: mut then,
+            parameters,
+            ..
+        }) = rules.next()
+        else {
+            panic!("A group has to start with a token or a node");
+        };
+        then.extend(rules);
+        (token, then, parameters)
+    }
+    #[track_caller]
     pub fn loop_<'a>() -> Rule<'a> {
-        Rule::Loop { rules: Vec::new() }
+        Rule::Loop {
+            origin: Origin::caller(),
+            rules: Vec::new(),
+        }
+    }
+    #[track_caller]
+    pub fn if_feature<'a>(name: &'a str) -> Rule<'a> {
+        Rule::IfFeature {
+            origin: Origin::caller(),
+            name,
+            rules: Vec::new(),
+            else_rules: Vec::new(),
+        }
     }
+    #[track_caller]
     pub fn maybe_one_of<'a>(options: impl IntoIterator<Item = OneOf<'a>>) -> Rule<'a> {
         Rule::MaybeOneOf {
+            origin: Origin::caller(),
             is_one_of: options.into_iter().collect(),
             isnt: Vec::new(),
         }
     }
+    #[track_caller]
     pub fn is_one_of<'a>(options: impl IntoIterator<Item = OneOf<'a>>) -> Rule<'a> {
         Rule::IsOneOf {
+            origin: Origin::caller(),
             tokens: options.into_iter().collect(),
             parameters: Vec::new(),
         }
     }
+    #[track_caller]
     pub fn until<'a>(matches: MatchToken<'a>) -> Rule<'a> {
         Rule::Until {
+            origin: Origin::caller(),
             token: matches,
             rules: Vec::new(),
             parameters: Vec::new(),
         }
     }
+    #[track_caller]
     pub fn compare<'a>(
         a: impl IntoVarKind<'a>,
         b: impl IntoVarKind<'a>,
         comp: Comparison,
     ) -> Rule<'a> {
         Rule::Command {
+            origin: Origin::caller(),
             command: Commands::Compare {
                 left: a.into_varkind(),
                 right: b.into_varkind(),
@@ -359,38 +1110,143 @@ pub mod ext {
             },
         }
     }
+    /// Runs the `then` rules if the predicate registered under the name returns true
+    #[track_caller]
+    pub fn external<'a>(name: &'a str, args: impl IntoIterator<Item = &'a str>) -> Rule<'a> {
+        Rule::Command {
+            origin: Origin::caller(),
+            command: Commands::External {
+                name,
+                args: args.into_iter().collect(),
+                rules: Vec::new(),
+            },
+        }
+    }
+    /// Runs the `then` rules if the condition is true
+    #[track_caller]
+    pub fn if_<'a>(cond: Cond<'a>) -> Rule<'a> {
+        Rule::Command {
+            origin: Origin::caller(),
+            command: Commands::If {
+                cond,
+                rules: Vec::new(),
+            },
+        }
+    }
+    /// Condition of `if_` that compares the variables like `compare`
+    pub fn comparison<'a>(
+        a: impl IntoVarKind<'a>,
+        b: impl IntoVarKind<'a>,
+        comp: Comparison,
+    ) -> Cond<'a> {
+        Cond::Compare {
+            left: a.into_varkind(),
+            right: b.into_varkind(),
+            comparison: comp,
+        }
+    }
+    /// Condition of `if_` that calls the predicate like `external`
+    pub fn predicate<'a>(name: &'a str, args: impl IntoIterator<Item = &'a str>) -> Cond<'a> {
+        Cond::External {
+            name,
+            args: args.into_iter().collect(),
+        }
+    }
+    pub fn and<'a>(conds: impl IntoIterator<Item = Cond<'a>>) -> Cond<'a> {
+        Cond::And(conds.into_iter().collect())
+    }
+    pub fn or<'a>(conds: impl IntoIterator<Item = Cond<'a>>) -> Cond<'a> {
+        Cond::Or(conds.into_iter().collect())
+    }
+    pub fn not(cond: Cond<'_>) -> Cond<'_> {
+        Cond::Not(Box::new(cond))
+    }
+    #[track_caller]
+    pub fn set_number<'a>(var: impl IntoVarKind<'a>, value: i32) -> Rule<'a> {
+        Rule::Command {
+            origin: Origin::caller(),
+            command: Commands::SetNumber {
+                var: var.into_varkind(),
+                value,
+            },
+        }
+    }
+    #[track_caller]
+    pub fn set_number64<'a>(var: impl IntoVarKind<'a>, value: i64) -> Rule<'a> {
+        Rule::Command {
+            origin: Origin::caller(),
+            command: Commands::SetNumber64 {
+                var: var.into_varkind(),
+                value,
+            },
+        }
+    }
+    #[track_caller]
+    pub fn set_float<'a>(var: impl IntoVarKind<'a>, value: f64) -> Rule<'a> {
+        Rule::Command {
+            origin: Origin::caller(),
+            command: Commands::SetFloat {
+                var: var.into_varkind(),
+                value,
+            },
+        }
+    }
+    #[track_caller]
+    pub fn set_bool<'a>(var: impl IntoVarKind<'a>, value: bool) -> Rule<'a> {
+        Rule::Command {
+            origin: Origin::caller(),
+            command: Commands::SetBool {
+                var: var.into_varkind(),
+                value,
+            },
+        }
+    }
+    #[track_caller]
     pub fn print_msg<'a>(msg: &'a str) -> Rule<'a> {
         Rule::Command {
+            origin: Origin::caller(),
             command: Commands::Print { message: msg },
         }
     }
+    #[track_caller]
     pub fn goto<'a>(label: &'a str) -> Rule<'a> {
         Rule::Command {
+            origin: Origin::caller(),
             command: Commands::Goto { label },
         }
     }
+    #[track_caller]
     pub fn commit() -> Rule<'static> {
         Rule::Command {
+            origin: Origin::caller(),
             command: Commands::Commit { set: true },
         }
     }
+    #[track_caller]
     pub fn label<'a>(identifier: &'a str) -> Rule<'a> {
         Rule::Command {
+            origin: Origin::caller(),
             command: Commands::Label { name: identifier },
         }
     }
+    #[track_caller]
     pub fn return_node<'a>() -> Rule<'a> {
         Rule::Command {
+            origin: Origin::caller(),
             command: Commands::Return,
         }
     }
+    #[track_caller]
     pub fn start<'a>() -> Rule<'a> {
         Rule::Command {
+            origin: Origin::caller(),
             command: Commands::Start,
         }
     }
+    #[track_caller]
     pub fn end<'a>() -> Rule<'a> {
         Rule::Command {
+            origin: Origin::caller(),
             command: Commands::End,
         }
     }
@@ -415,9 +1271,14 @@ pub mod ext {
                 Self::Is { rules, .. } | Self::Isnt { rules, .. } => rules.extend(set_rules),
                 Self::While { rules, .. } | Self::Until { rules, .. } => rules.extend(set_rules),
                 Self::Maybe { is, .. } => is.extend(set_rules),
-                Self::Loop { rules } => rules.extend(set_rules),
+                Self::Loop { rules, .. } => rules.extend(set_rules),
+                Self::IfFeature { rules, .. } => rules.extend(set_rules),
                 Self::Command {
-                    command: Commands::Compare { rules, .. },
+                    command:
+                        Commands::Compare { rules, .. }
+                        | Commands::External { rules, .. }
+                        | Commands::If { rules, .. },
+                    ..
                 } => rules.extend(set_rules),
                 _ => panic!("Can not set 'then' rules for rule: {:?}", self),
             }
@@ -426,6 +1287,7 @@ pub mod ext {
         pub fn otherwise(mut self, set_rules: impl IntoIterator<Item = Rule<'a>>) -> Self {
             match &mut self {
                 Self::Maybe { isnt, .. } => isnt.extend(set_rules),
+                Self::IfFeature { else_rules, .. } => else_rules.extend(set_rules),
                 _ => panic!("Can not set 'otherwise' rulse for rule: {:?}", self),
             }
             self
@@ -433,9 +1295,30 @@ pub mod ext {
         pub fn set(self, var: impl IntoVarKind<'a>) -> Self {
             self.params([Parameters::Set(var.into_varkind())])
         }
+        /// Pushes the value to a local list and labels it with the field name
+        pub fn set_field(self, var: &'a str, field: &'a str) -> Self {
+            self.params([Parameters::SetField(var, field)])
+        }
         pub fn important(self) -> Self {
             self.params([Parameters::Important])
         }
+        pub fn attach_docs(self) -> Self {
+            self.params([Parameters::AttachDocs])
+        }
+        /// Declares the text of the variable in the current scope, see `crate::scopes`
+        pub fn declare(self, var: impl IntoVarKind<'a>) -> Self {
+            self.params([Parameters::Declare(var.into_varkind())])
+        }
+        /// Records the text of the variable as a use of a declared name, see `crate::scopes`
+        pub fn use_name(self, var: impl IntoVarKind<'a>) -> Self {
+            self.params([Parameters::Use(var.into_varkind())])
+        }
+        pub fn enter_scope(self) -> Self {
+            self.params([Parameters::EnterScope])
+        }
+        pub fn exit_scope(self) -> Self {
+            self.params([Parameters::ExitScope])
+        }
         pub fn fail(self, err: &'a ErrorDefinition) -> Self {
             self.params([Parameters::Fail(err)])
         }
@@ -463,6 +1346,9 @@ pub mod ext {
         pub fn commit(self) -> Self {
             self.params([Parameters::Commit(true)])
         }
+        pub fn cut(self) -> Self {
+            self.params([Parameters::Cut])
+        }
         pub fn print(self, txt: &'a str) -> Self {
             self.params([Parameters::Print(txt)])
         }
@@ -475,6 +1361,9 @@ pub mod ext {
         pub fn end(self) -> Self {
             self.params([Parameters::NodeEnd])
         }
+        pub fn split(self) -> Self {
+            self.params([Parameters::Split])
+        }
         pub fn return_node(self) -> Self {
             self.params([Parameters::Return])
         }
@@ -505,6 +1394,12 @@ pub mod ext {
     pub fn number_var(name: &str) -> (&str, VariableKind) {
         (name, VariableKind::Number)
     }
+    pub fn number64_var(name: &str) -> (&str, VariableKind) {
+        (name, VariableKind::Number64)
+    }
+    pub fn float_var(name: &str) -> (&str, VariableKind) {
+        (name, VariableKind::Float)
+    }
     pub fn bool_var(name: &str) -> (&str, VariableKind) {
         (name, VariableKind::Boolean)
     }
@@ -527,9 +1422,30 @@ pub mod ext {
         pub fn set(self, var: impl IntoVarKind<'a>) -> Self {
             self.params([Parameters::Set(var.into_varkind())])
         }
+        /// Pushes the value to a local list and labels it with the field name
+        pub fn set_field(self, var: &'a str, field: &'a str) -> Self {
+            self.params([Parameters::SetField(var, field)])
+        }
         pub fn important(self) -> Self {
             self.params([Parameters::Important])
         }
+        pub fn attach_docs(self) -> Self {
+            self.params([Parameters::AttachDocs])
+        }
+        /// Declares the text of the variable in the current scope, see `crate::scopes`
+        pub fn declare(self, var: impl IntoVarKind<'a>) -> Self {
+            self.params([Parameters::Declare(var.into_varkind())])
+        }
+        /// Records the text of the variable as a use of a declared name, see `crate::scopes`
+        pub fn use_name(self, var: impl IntoVarKind<'a>) -> Self {
+            self.params([Parameters::Use(var.into_varkind())])
+        }
+        pub fn enter_scope(self) -> Self {
+            self.params([Parameters::EnterScope])
+        }
+        pub fn exit_scope(self) -> Self {
+            self.params([Parameters::ExitScope])
+        }
         pub fn fail(self, err: &'a ErrorDefinition) -> Self {
             self.params([Parameters::Fail(err)])
         }
@@ -551,6 +1467,9 @@ pub mod ext {
         pub fn commit(self) -> Self {
             self.params([Parameters::Commit(true)])
         }
+        pub fn cut(self) -> Self {
+            self.params([Parameters::Cut])
+        }
         pub fn print(self, txt: &'a str) -> Self {
             self.params([Parameters::Print(txt)])
         }
@@ -563,6 +1482,9 @@ pub mod ext {
         pub fn end(self) -> Self {
             self.params([Parameters::NodeEnd])
         }
+        pub fn split(self) -> Self {
+            self.params([Parameters::Split])
+        }
         pub fn return_node(self) -> Self {
             self.params([Parameters::Return])
         }
@@ -573,6 +1495,18 @@ pub mod ext {
         pub rules: Vec<Rule<'a>>,
         pub variables: Vec<(&'a str, VariableKind)>,
         pub docs: Option<&'a str>,
+        pub snippet: Option<&'a str>,
+        pub foldable: bool,
+        pub indents_children: bool,
+        pub symbol: Option<Symbol<'a>>,
+        pub since: Option<LanguageVersion>,
+        pub until: Option<LanguageVersion>,
+        pub record_children: bool,
+        pub record_tokens: bool,
+        pub keywords: Vec<&'a str>,
+        pub initial: Vec<(&'a str, InitialValue)>,
+        pub allow_empty: bool,
+        pub sync: Vec<TokenKinds<'a>>,
     }
     pub struct EnumBuilder<'g, 'a> {
         grammar: &'g mut Grammar<'a>,
@@ -587,6 +1521,18 @@ pub mod ext {
                 rules: Vec::new(),
                 variables: Vec::new(),
                 docs: None,
+                snippet: None,
+                foldable: false,
+                indents_children: false,
+                symbol: None,
+                since: None,
+                until: None,
+                record_children: false,
+                record_tokens: false,
+                keywords: Vec::new(),
+                initial: Vec::new(),
+                allow_empty: false,
+                sync: Vec::new(),
             }
         }
         pub fn new_enum<'g>(&'g mut self, name: &'a str) -> EnumBuilder<'g, 'a> {
@@ -613,19 +1559,114 @@ pub mod ext {
             self.variables.extend(variables);
             self
         }
+        /// Declares a single variable, `var("ident", VariableKind::Node)`
+        pub fn var(mut self, name: &'a str, kind: VariableKind) -> Self {
+            self.variables.push((name, kind));
+            self
+        }
         pub fn docs(mut self, text: &'a str) -> Self {
             self.docs = Some(text);
             self
         }
+        /// Completion snippet of the node, see `grammar::Node::snippet`
+        pub fn snippet(mut self, snippet: &'a str) -> Self {
+            self.snippet = Some(snippet);
+            self
+        }
+        pub fn foldable(mut self) -> Self {
+            self.foldable = true;
+            self
+        }
+        /// Indents the lines inside of the node, see `grammar::Node::indents_children`
+        pub fn indents_children(mut self) -> Self {
+            self.indents_children = true;
+            self
+        }
+        pub fn symbol(mut self, name_from: &'a str, kind: SymbolKind) -> Self {
+            self.symbol = Some(Symbol { name_from, kind });
+            self
+        }
+        /// The node can only be used since this version of the language
+        pub fn since(mut self, major: u32, minor: u32) -> Self {
+            self.since = Some(LanguageVersion::new(major, minor));
+            self
+        }
+        /// The node can not be used since this version of the language
+        pub fn until(mut self, major: u32, minor: u32) -> Self {
+            self.until = Some(LanguageVersion::new(major, minor));
+            self
+        }
+        /// Keep everything the node matches in order, see `parser::Node::children`
+        pub fn record_children(mut self) -> Self {
+            self.record_children = true;
+            self
+        }
+        /// Keep the tokens the node matches in order, see `parser::Node::tokens`
+        pub fn record_tokens(mut self) -> Self {
+            self.record_tokens = true;
+            self
+        }
+        /// Values the variables start with, see `grammar::Node::initial`
+        pub fn initial(
+            mut self,
+            values: impl IntoIterator<Item = (&'a str, InitialValue)>,
+        ) -> Self {
+            self.initial.extend(values);
+            self
+        }
+        /// Accepts empty input as the entry, see `grammar::Node::allow_empty`
+        pub fn allow_empty(mut self) -> Self {
+            self.allow_empty = true;
+            self
+        }
+        /// Ends regions skipped by error recovery, see `grammar::Node::sync`
+        pub fn sync(mut self, sync: impl IntoIterator<Item = TokenKinds<'a>>) -> Self {
+            self.sync.extend(sync);
+            self
+        }
+        /// Reserves the words in this node, see `grammar::Node::keywords`
+        pub fn keywords(mut self, keywords: impl IntoIterator<Item = &'a str>) -> Self {
+            self.keywords.extend(keywords);
+            self
+        }
+        /// Adds the node to the grammar and returns the token matching it
+        ///
+        /// Panics if the grammar already has a node of the name
+        /// or if a variable is declared more than once
         pub fn build(self) -> MatchToken<'a> {
+            for (i, (name, _)) in self.variables.iter().enumerate() {
+                assert!(
+                    !self.variables[..i].iter().any(|(other, _)| other == name),
+                    "Variable {} is declared twice in node {}",
+                    name,
+                    self.name
+                );
+            }
+            let name = intern::Symbol::intern(self.name);
             let n = Node {
-                name: self.name,
+                name,
                 rules: self.rules,
-                variables: self.variables,
+                variables: self
+                    .variables
+                    .into_iter()
+                    .map(|(name, kind)| (intern::Symbol::intern(name), kind))
+                    .collect(),
                 docs: self.docs,
+                snippet: self.snippet,
+                foldable: self.foldable,
+                indents_children: self.indents_children,
+                symbol: self.symbol,
+                since: self.since,
+                until: self.until,
+                record_children: self.record_children,
+                record_tokens: self.record_tokens,
+                keywords: self.keywords,
+                initial: self.initial,
+                allow_empty: self.allow_empty,
+                sync: self.sync,
             };
             assert!(self.grammar.add_node(n), "Node already exists");
-            node(self.name)
+            MatchToken::Node(name)
         }
         pub fn has(self, token: MatchToken<'a>, var: &'a str) -> Self {
             self.rules([is(token).set(local(var))])
@@ -645,6 +1686,14 @@ pub mod ext {
             self.options.extend(options);
             self
         }
+        /// Adds a token option for every string
+        pub fn tokens(self, values: impl IntoIterator<Item = &'a str>) -> Self {
+            self.options(tokens(values))
+        }
+        /// Adds a word option for every string
+        pub fn words(self, values: impl IntoIterator<Item = &'a str>) -> Self {
+            self.options(words(values))
+        }
         pub fn build(self) -> MatchToken<'a> {
             let e = Enumerator {
                 name: self.name,
@@ -671,3 +1720,80 @@ pub mod ext {
         }
     }
 }
+
+/// Adds nodes to a grammar from an inline description
+///
+/// ```text
+/// grammar! { parser.grammar;
+///     KWLet: "let" <ident:text> (":" <type:text>)? ("=" <value:value>)? ";";
+///     value: <text:text>;
+/// }
+/// ```
+///
+/// Every node is a name, a colon and its items ended by a semicolon:
+///
+/// - `"let"` matches a word, `";"` a token, see `ext::literal`
+/// - `text` and `name` match text and the node `name`
+/// - `<var:text>` and `<var:name>` do the same and keep the match in the variable `var`
+/// - `( ... )?` matches the items if the first one matches
+/// - `( ... )*` matches the items as long as the first one matches
+#[macro_export]
+macro_rules! grammar {
+    ($grammar:expr; $($nodes:tt)*) => {{
+        let grammar: &mut $crate::grammar::Grammar = &mut $grammar;
+        $crate::grammar!(@nodes grammar; $($nodes)*);
+    }};
+    (@nodes $grammar:ident;) => {};
+    (@nodes $grammar:ident; $name:ident : $($rest:tt)*) => {
+        $crate::grammar!(@node $grammar $name []; $($rest)*);
+    };
+    (@node $grammar:ident $name:ident [$($items:tt)*]; ; $($rest:tt)*) => {
+        let mut rules = $crate::api::ext::rules([]);
+        let mut variables = $crate::api::ext::variables([]);
+        $crate::grammar!(@items rules variables; $($items)*);
+        $grammar
+            .new_node(stringify!($name))
+            .rules(rules)
+            .variables(variables)
+            .build();
+        $crate::grammar!(@nodes $grammar; $($rest)*);
+    };
+    (@node $grammar:ident $name:ident [$($items:tt)*]; $next:tt $($rest:tt)*) => {
+        $crate::grammar!(@node $grammar $name [$($items)* $next]; $($rest)*);
+    };
+    (@items $rules:ident $vars:ident;) => {};
+    (@items $rules:ident $vars:ident; $text:literal $($rest:tt)*) => {
+        $rules.push($crate::api::ext::is($crate::api::ext::literal($text)));
+        $crate::grammar!(@items $rules $vars; $($rest)*);
+    };
+    (@items $rules:ident $vars:ident; <$var:ident : $kind:ident> $($rest:tt)*) => {
+        $rules.push(
+            $crate::api::ext::is($crate::grammar!(@token $kind))
+                .set($crate::api::ext::local(stringify!($var))),
+        );
+        $vars.push($crate::api::ext::node_var(stringify!($var)));
+        $crate::grammar!(@items $rules $vars; $($rest)*);
+    };
+    (@items $rules:ident $vars:ident; ($($group:tt)+) ? $($rest:tt)*) => {
+        let mut group = $crate::api::ext::rules([]);
+        $crate::grammar!(@items group $vars; $($group)+);
+        $rules.push($crate::api::ext::optional(group));
+        $crate::grammar!(@items $rules $vars; $($rest)*);
+    };
+    (@items $rules:ident $vars:ident; ($($group:tt)+) * $($rest:tt)*) => {
+        let mut group = $crate::api::ext::rules([]);
+        $crate::grammar!(@items group $vars; $($group)+);
+        $rules.push($crate::api::ext::repeated(group));
+        $crate::grammar!(@items $rules $vars; $($rest)*);
+    };
+    (@items $rules:ident $vars:ident; $kind:ident $($rest:tt)*) => {
+        $rules.push($crate::api::ext::is($crate::grammar!(@token $kind)));
+        $crate::grammar!(@items $rules $vars; $($rest)*);
+    };
+    (@token text) => {
+        $crate::api::ext::text()
+    };
+    (@token $node:ident) => {
+        $crate::api::ext::node(stringify!($node))
+    };
+}
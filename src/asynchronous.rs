@@ -0,0 +1,165 @@
+//! Lexing and parsing from async code
+//!
+//! The lexer and the parser are synchronous and a large input can keep them busy for a
+//! while. `Lexer::lex_reader` lexes the input line by line as it arrives and yields to
+//! the runtime after every chunk. `Parser::parse_async` runs the parser on a stack of
+//! its own and yields every `YIELD_INTERVAL` rules, so other tasks get to run on any
+//! runtime. The parse keeps its stack while suspended, which makes the future `!Send`:
+//! await it in the task that owns the text, spawn it with `task::spawn_local` if needed.
+
+use std::{fmt, io};
+
+use corosensei::{stack::DefaultStack, CoroutineResult, ScopedCoroutine, Yielder};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    task,
+};
+
+use crate::{
+    lexer::{ControlTokenKind, Lexer, LexerState, PreprocessorError, Token, TokenKinds},
+    parser::{Checkpoint, ParseError, ParseResult, TokenSource},
+    Parser,
+};
+
+/// Number of rules `Parser::parse_async` matches between two yields
+pub const YIELD_INTERVAL: usize = 4096;
+
+/// Bytes read from the input at once by `Lexer::lex_reader`
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Stack of `Parser::parse_async`, as large as the main thread has on Linux
+///
+/// The pages are only committed once the parse nests deep enough to touch them.
+const STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Error of `Lexer::lex_reader`
+#[derive(Debug)]
+pub enum AsyncLexError {
+    Io(io::Error),
+    Lex(PreprocessorError),
+}
+
+impl fmt::Display for AsyncLexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsyncLexError::Io(err) => write!(f, "could not read input: {}", err),
+            AsyncLexError::Lex(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for AsyncLexError {}
+
+fn invalid_utf8() -> AsyncLexError {
+    AsyncLexError::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "stream did not contain valid UTF-8",
+    ))
+}
+
+impl Lexer {
+    /// Reads the whole input into `buf` and lexes it
+    ///
+    /// The input is lexed as it arrives, one chunk of whole lines at a time, and the
+    /// task yields after each of them. `buf` keeps the text for parsing and diagnostics.
+    /// Preprocessors run once the whole input is lexed.
+    pub async fn lex_reader<'a>(
+        &'a self,
+        mut reader: impl AsyncRead + Unpin,
+        buf: &mut String,
+    ) -> Result<Vec<Token<'a>>, AsyncLexError> {
+        let mut tokens = Vec::new();
+        let mut state = LexerState::new();
+        let mut chunk = vec![0; CHUNK_SIZE];
+        // bytes of a character cut by the end of a chunk
+        let mut pending = Vec::new();
+        loop {
+            let read = reader.read(&mut chunk).await.map_err(AsyncLexError::Io)?;
+            if read == 0 {
+                break;
+            }
+            pending.extend_from_slice(&chunk[..read]);
+            let valid = match std::str::from_utf8(&pending) {
+                Ok(text) => text.len(),
+                Err(err) if err.error_len().is_none() => err.valid_up_to(),
+                Err(_) => return Err(invalid_utf8()),
+            };
+            buf.push_str(std::str::from_utf8(&pending[..valid]).map_err(|_| invalid_utf8())?);
+            pending.drain(..valid);
+            if let Some(line_end) = buf[state.index..].rfind('\n') {
+                let end = state.index + line_end + 1;
+                state = self
+                    .lex_lines(buf, state, end, &mut tokens)
+                    .map_err(AsyncLexError::Lex)?;
+                task::yield_now().await;
+            }
+        }
+        if !pending.is_empty() {
+            return Err(invalid_utf8());
+        }
+        self.lex_region_into(buf, state, buf.len(), &mut tokens)
+            .map_err(AsyncLexError::Lex)?;
+        tokens.push(Token {
+            index: buf.len(),
+            len: 0,
+            kind: TokenKinds::Control(ControlTokenKind::Eof),
+            tag: None,
+            data: None,
+        });
+        if let Some(progress) = &self.progress {
+            progress.report(buf.len(), buf.len());
+        }
+        self.preprocess(buf, tokens).map_err(AsyncLexError::Lex)
+    }
+
+    /// Lexes the lines up to `end` and returns the state to continue at
+    ///
+    /// The last token is lexed again with the next chunk, it can go on in there
+    /// like a block doc comment does.
+    fn lex_lines<'a>(
+        &'a self,
+        text: &str,
+        state: LexerState,
+        end: usize,
+        tokens: &mut Vec<Token<'a>>,
+    ) -> Result<LexerState, PreprocessorError> {
+        let start = tokens.len();
+        let state = self.lex_region_into(text, state, end, tokens)?;
+        if tokens.len() == start {
+            return Ok(state);
+        }
+        Ok(tokens.pop().map_or(state, |last| LexerState::before(&last)))
+    }
+}
+
+impl<'a, 'src> Parser<'a>
+where
+    'a: 'src,
+    'src: 'a,
+{
+    /// `Parser::parse` for async code, see the module documentation
+    pub async fn parse_async<T: TokenSource<'src> + ?Sized>(
+        &'a self,
+        tokens: &T,
+        text: &'a str,
+    ) -> Result<ParseResult<'src>, ParseError<'a>> {
+        let Ok(stack) = DefaultStack::new(STACK_SIZE) else {
+            // without a stack of its own the parse can not be suspended
+            task::yield_now().await;
+            return self.parse(tokens, text);
+        };
+        let tokens = self.parser.filter_tokens(text, tokens);
+        let mut parse = ScopedCoroutine::with_stack(stack, |yielder: &Yielder<(), ()>, ()| {
+            let mut pause = || yielder.suspend(());
+            let checkpoint = Checkpoint::new(YIELD_INTERVAL, &mut pause);
+            self.parser
+                .parse_paused(&self.grammar, &self.lexer, text, &tokens, checkpoint)
+        });
+        loop {
+            match parse.resume(()) {
+                CoroutineResult::Yield(()) => task::yield_now().await,
+                CoroutineResult::Return(result) => return result.map_err(|err| *err),
+            }
+        }
+    }
+}
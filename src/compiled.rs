@@ -0,0 +1,1644 @@
+//! Grammars saved in a binary format
+//!
+//! Building and validating a large grammar can take a noticeable part of the
+//! startup time. `CompiledGrammar` saves the lexer, the grammar and the parser
+//! options into a file once, applications then load it without running the
+//! construction and validation code again.
+//!
+//! The file stores a checksum of the grammar definition (for example the source
+//! file the grammar was built from), so a file built from an outdated definition
+//! is rejected with `LoadError::Stale` instead of being used.
+//!
+//! Strings of a loaded grammar are leaked to get the `'static` lifetime,
+//! load a grammar once at startup and keep it for the lifetime of the program.
+//!
+//! Lexer preprocessors, token filters and predicates are code and can not be saved, add them again after loading.
+
+use std::{fmt, fs, io, path::Path};
+
+use crate::{
+    grammar::{
+        CharClass, Commands, Comparison, Cond, Enumerator, ErrorDefinition, Grammar, GrammarTest,
+        InitialValue, LanguageVersion, MatchToken, Node, OneOf, Origin, Parameters, Rule, Symbol,
+        SymbolKind, TriviaPolicy, VarKind, VariableKind,
+    },
+    intern,
+    lexer::{ControlTokenKind, DocComment, Lexer, TabPolicy, TokenKinds, UnknownChars},
+    parser, Map, Parser,
+};
+
+/// First bytes of every compiled grammar file
+pub const MAGIC: [u8; 4] = *b"RUPG";
+/// Version of the binary format, files of other versions are rejected
+pub const FORMAT_VERSION: u16 = 24;
+
+/// Parser with its grammar ready to be saved to a file
+#[derive(Debug, Clone)]
+pub struct CompiledGrammar<'a> {
+    pub parser: Parser<'a>,
+    /// Checksum of the definition the grammar was built from, see `CompiledGrammar::checksum`
+    pub checksum: u64,
+}
+
+/// Error returned when a compiled grammar can not be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The data does not start with `MAGIC`
+    NotAGrammar,
+    /// The data was written by a different version of the format
+    Version(u16),
+    /// The grammar was built from a different definition
+    Stale {
+        expected: u64,
+        found: u64,
+    },
+    /// The data is truncated or damaged
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "could not read compiled grammar: {}", err),
+            LoadError::NotAGrammar => write!(f, "data is not a compiled grammar"),
+            LoadError::Version(version) => write!(
+                f,
+                "compiled grammar has format version {}, expected {}",
+                version, FORMAT_VERSION
+            ),
+            LoadError::Stale { expected, found } => write!(
+                f,
+                "compiled grammar is outdated (checksum {:016x}, expected {:016x})",
+                found, expected
+            ),
+            LoadError::Corrupt(what) => write!(f, "compiled grammar is corrupt: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+impl<'a> CompiledGrammar<'a> {
+    /// Wraps a parser built from `source`, the definition of the grammar
+    pub fn new(parser: Parser<'a>, source: &[u8]) -> Self {
+        CompiledGrammar {
+            parser,
+            checksum: Self::checksum(source),
+        }
+    }
+
+    /// FNV-1a hash of the grammar definition
+    pub fn checksum(source: &[u8]) -> u64 {
+        source.iter().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    /// FNV-1a hash of the parts with 128 bits, see `Grammar::fingerprint`
+    ///
+    /// The length of every part is hashed too, so moving bytes between parts changes the hash
+    pub fn fingerprint(parts: &[&[u8]]) -> u128 {
+        const PRIME: u128 = 0x0000000001000000000000000000013b;
+        let hash = |hash: u128, byte: &u8| (hash ^ *byte as u128).wrapping_mul(PRIME);
+        parts
+            .iter()
+            .fold(0x6c62272e07bb014262b821756295c58d, |state, part| {
+                let state = (part.len() as u64).to_le_bytes().iter().fold(state, hash);
+                part.iter().fold(state, hash)
+            })
+    }
+
+    /// Options of the lexer in the binary format, see `crate::Parser::fingerprint`
+    ///
+    /// Preprocessors are code and are not included
+    pub fn lexer_options(lexer: &Lexer) -> Vec<u8> {
+        let mut w = Writer::default();
+        lexer.encode(&mut w);
+        w.bytes
+    }
+
+    /// Encodes the grammar into the binary format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = Writer::default();
+        self.parser.lexer.encode(&mut body);
+        self.parser.grammar.encode(&mut body);
+        self.parser.parser.encode(&mut body);
+
+        let mut out = Writer::default();
+        out.bytes.extend_from_slice(&MAGIC);
+        out.bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.u64(self.checksum);
+        out.u64(Self::checksum(&body.bytes));
+        out.bytes.extend_from_slice(&body.bytes);
+        out.bytes
+    }
+
+    /// Writes the grammar into a file
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+}
+
+impl CompiledGrammar<'static> {
+    /// Decodes a grammar from the binary format
+    ///
+    /// The checksum of the definition is not checked, use `CompiledGrammar::load` for that
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LoadError> {
+        if bytes.len() < 4 || bytes[..4] != MAGIC {
+            return Err(LoadError::NotAGrammar);
+        }
+        let mut reader = Reader::new(&bytes[4..]);
+        let version = u16::from_le_bytes([reader.u8()?, reader.u8()?]);
+        if version != FORMAT_VERSION {
+            return Err(LoadError::Version(version));
+        }
+        let checksum = reader.u64()?;
+        let body_checksum = reader.u64()?;
+        if Self::checksum(&reader.bytes[reader.pos..]) != body_checksum {
+            return Err(LoadError::Corrupt("checksum of the data does not match"));
+        }
+
+        let lexer = Lexer::decode(&mut reader)?;
+        let grammar = Grammar::decode(&mut reader)?;
+        let parser = parser::Parser::decode(&mut reader)?;
+        if reader.pos != reader.bytes.len() {
+            return Err(LoadError::Corrupt("trailing data"));
+        }
+        Ok(CompiledGrammar {
+            parser: Parser {
+                lexer,
+                grammar,
+                parser,
+            },
+            checksum,
+        })
+    }
+
+    /// Reads a grammar from a file and checks that it was built from `source`
+    ///
+    /// Returns `LoadError::Stale` if the definition changed since the file was saved
+    pub fn load(path: impl AsRef<Path>, source: &[u8]) -> Result<Self, LoadError> {
+        let compiled = Self::from_bytes(&fs::read(path)?)?;
+        let expected = Self::checksum(source);
+        if compiled.checksum != expected {
+            return Err(LoadError::Stale {
+                expected,
+                found: compiled.checksum,
+            });
+        }
+        Ok(compiled)
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    fn str(&mut self, value: &str) {
+        self.usize(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+    /// Strings that were already leaked, names repeat a lot in grammars
+    strings: Map<String, &'static str>,
+}
+
+impl<'b> Reader<'b> {
+    fn new(bytes: &'b [u8]) -> Self {
+        Reader {
+            bytes,
+            pos: 0,
+            strings: Map::new(),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'b [u8], LoadError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(LoadError::Corrupt("unexpected end of data"))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, LoadError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(LoadError::Corrupt("invalid boolean")),
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, LoadError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn usize(&mut self) -> Result<usize, LoadError> {
+        usize::try_from(self.u64()?).map_err(|_| LoadError::Corrupt("length out of range"))
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.usize()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| LoadError::Corrupt("invalid UTF-8"))
+    }
+
+    fn str(&mut self) -> Result<&'static str, LoadError> {
+        let string = self.string()?;
+        if let Some(leaked) = self.strings.get(&string) {
+            return Ok(leaked);
+        }
+        let leaked: &'static str = Box::leak(string.clone().into_boxed_str());
+        self.strings.insert(string, leaked);
+        Ok(leaked)
+    }
+
+    /// Names are kept in the crate-wide interner instead of being leaked again
+    fn symbol(&mut self) -> Result<intern::Symbol, LoadError> {
+        Ok(intern::Symbol::intern(&self.string()?))
+    }
+}
+
+trait Encode {
+    fn encode(&self, w: &mut Writer);
+}
+
+trait Decode: Sized {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError>;
+}
+
+impl Encode for bool {
+    fn encode(&self, w: &mut Writer) {
+        w.bool(*self);
+    }
+}
+
+impl Decode for bool {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        r.bool()
+    }
+}
+
+impl Encode for usize {
+    fn encode(&self, w: &mut Writer) {
+        w.usize(*self);
+    }
+}
+
+impl Decode for usize {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        r.usize()
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, w: &mut Writer) {
+        w.u32(*self as u32);
+    }
+}
+
+impl Decode for char {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        char::from_u32(r.u32()?).ok_or(LoadError::Corrupt("invalid character"))
+    }
+}
+
+impl Encode for &str {
+    fn encode(&self, w: &mut Writer) {
+        w.str(self);
+    }
+}
+
+impl Decode for &'static str {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        r.str()
+    }
+}
+
+impl Encode for intern::Symbol {
+    fn encode(&self, w: &mut Writer) {
+        w.str(self.as_str());
+    }
+}
+
+impl Decode for intern::Symbol {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        r.symbol()
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Some(value) => {
+                w.u8(1);
+                value.encode(w);
+            }
+            None => w.u8(0),
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.bool()? {
+            true => Ok(Some(T::decode(r)?)),
+            false => Ok(None),
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, w: &mut Writer) {
+        w.usize(self.len());
+        for value in self {
+            value.encode(w);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        let len = r.usize()?;
+        // every value takes at least one byte
+        if len > r.bytes.len() - r.pos {
+            return Err(LoadError::Corrupt("list is longer than the data"));
+        }
+        (0..len).map(|_| T::decode(r)).collect()
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, w: &mut Writer) {
+        self.0.encode(w);
+        self.1.encode(w);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        Ok((A::decode(r)?, B::decode(r)?))
+    }
+}
+
+/// Entries are sorted by key so the same grammar always produces the same bytes
+fn encode_map<V: Encode>(map: &Map<String, V>, w: &mut Writer) {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    w.usize(entries.len());
+    for (key, value) in entries {
+        w.str(key);
+        value.encode(w);
+    }
+}
+
+fn decode_map<V: Decode>(r: &mut Reader) -> Result<Map<String, V>, LoadError> {
+    let len = r.usize()?;
+    let mut map = Map::new();
+    for _ in 0..len {
+        let key = r.string()?;
+        map.insert(key, V::decode(r)?);
+    }
+    Ok(map)
+}
+
+impl Encode for Lexer {
+    fn encode(&self, w: &mut Writer) {
+        // tokens of the same length are in the order they were added,
+        // sorting them keeps the bytes the same for lexers that only differ in that
+        let mut tokens = self
+            .get_tokens()
+            .iter()
+            .map(|token| (*token, self.tag_of(token)))
+            .collect::<Vec<_>>();
+        tokens.sort();
+        tokens.encode(w);
+        self.unknown_chars.encode(w);
+        w.bool(self.strip_bom);
+        w.bool(self.crlf);
+        self.doc_comments.encode(w);
+        w.bool(self.scannerless);
+        #[cfg(feature = "graphemes")]
+        w.bool(self.graphemes);
+        #[cfg(not(feature = "graphemes"))]
+        w.bool(false);
+        self.tabs.encode(w);
+    }
+}
+
+impl Decode for Lexer {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        let mut lexer = Lexer::new();
+        for (token, tag) in Vec::<(&str, Option<&str>)>::decode(r)? {
+            match tag {
+                Some(tag) => lexer.add_token_tagged(token, tag),
+                None => lexer.add_token(token),
+            }
+        }
+        lexer.unknown_chars = UnknownChars::decode(r)?;
+        lexer.strip_bom = r.bool()?;
+        lexer.crlf = r.bool()?;
+        lexer.doc_comments = Vec::decode(r)?;
+        lexer.scannerless = r.bool()?;
+        #[cfg(feature = "graphemes")]
+        {
+            lexer.graphemes = r.bool()?;
+        }
+        #[cfg(not(feature = "graphemes"))]
+        if r.bool()? {
+            return Err(LoadError::Corrupt(
+                "grapheme clusters need the `graphemes` feature",
+            ));
+        }
+        lexer.tabs = TabPolicy::decode(r)?;
+        Ok(lexer)
+    }
+}
+
+impl Encode for UnknownChars {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            UnknownChars::Text => 0,
+            UnknownChars::Error => 1,
+            UnknownChars::Fail => 2,
+        });
+    }
+}
+
+impl Decode for UnknownChars {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(UnknownChars::Text),
+            1 => Ok(UnknownChars::Error),
+            2 => Ok(UnknownChars::Fail),
+            _ => Err(LoadError::Corrupt("invalid unknown characters policy")),
+        }
+    }
+}
+
+impl Encode for TabPolicy {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            TabPolicy::Char => w.u8(0),
+            TabPolicy::Width(width) => {
+                w.u8(1);
+                w.usize(*width);
+            }
+            TabPolicy::Stop(width) => {
+                w.u8(2);
+                w.usize(*width);
+            }
+        }
+    }
+}
+
+impl Decode for TabPolicy {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(TabPolicy::Char),
+            1 => Ok(TabPolicy::Width(r.usize()?)),
+            2 => Ok(TabPolicy::Stop(r.usize()?)),
+            _ => Err(LoadError::Corrupt("invalid tab policy")),
+        }
+    }
+}
+
+impl Encode for DocComment {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            DocComment::Line(marker) => {
+                w.u8(0);
+                w.str(marker);
+            }
+            DocComment::Block(open, close) => {
+                w.u8(1);
+                w.str(open);
+                w.str(close);
+            }
+        }
+    }
+}
+
+impl Decode for DocComment {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(DocComment::Line(r.str()?)),
+            1 => Ok(DocComment::Block(r.str()?, r.str()?)),
+            _ => Err(LoadError::Corrupt("invalid doc comment")),
+        }
+    }
+}
+
+impl Encode for TokenKinds<'_> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            TokenKinds::Token(token) => {
+                w.u8(0);
+                w.str(token);
+            }
+            TokenKinds::Complex(name) => {
+                w.u8(1);
+                w.str(name);
+            }
+            TokenKinds::Text => w.u8(2),
+            TokenKinds::Whitespace => w.u8(3),
+            TokenKinds::Control(ControlTokenKind::Eof) => w.u8(4),
+            TokenKinds::Control(ControlTokenKind::Eol) => w.u8(5),
+            TokenKinds::Error => w.u8(6),
+            TokenKinds::DocComment => w.u8(7),
+        }
+    }
+}
+
+impl Decode for TokenKinds<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(TokenKinds::Token(r.str()?)),
+            1 => Ok(TokenKinds::Complex(r.str()?)),
+            2 => Ok(TokenKinds::Text),
+            3 => Ok(TokenKinds::Whitespace),
+            4 => Ok(TokenKinds::Control(ControlTokenKind::Eof)),
+            5 => Ok(TokenKinds::Control(ControlTokenKind::Eol)),
+            6 => Ok(TokenKinds::Error),
+            7 => Ok(TokenKinds::DocComment),
+            _ => Err(LoadError::Corrupt("invalid token kind")),
+        }
+    }
+}
+
+impl Encode for Grammar<'_> {
+    fn encode(&self, w: &mut Writer) {
+        // an overlay is stored with the nodes of its base
+        let nodes = self
+            .all_nodes()
+            .into_iter()
+            .map(|node| (node.name.to_string(), node.clone()))
+            .collect::<Map<_, _>>();
+        encode_map(&nodes, w);
+        encode_map(&self.enumerators, w);
+        encode_map(&self.aliases, w);
+        encode_map(&self.features, w);
+        self.globals.encode(w);
+        self.ignored.encode(w);
+        w.bool(self.eof);
+        self.trivia.encode(w);
+        self.tests.encode(w);
+        self.brackets.encode(w);
+    }
+}
+
+impl Decode for Grammar<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        Ok(Grammar {
+            nodes: decode_map::<Node>(r)?
+                .into_values()
+                .map(|node| (node.name, node))
+                .collect(),
+            base: None,
+            enumerators: decode_map(r)?,
+            aliases: decode_map(r)?,
+            features: decode_map(r)?,
+            globals: Vec::decode(r)?,
+            ignored: Vec::decode(r)?,
+            eof: r.bool()?,
+            trivia: Option::decode(r)?,
+            tests: Vec::decode(r)?,
+            brackets: Vec::decode(r)?,
+        })
+    }
+}
+
+impl Encode for GrammarTest<'_> {
+    fn encode(&self, w: &mut Writer) {
+        w.str(self.node);
+        w.str(self.input);
+        self.expect.encode(w);
+    }
+}
+
+impl Decode for GrammarTest<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        Ok(GrammarTest {
+            node: r.str()?,
+            input: r.str()?,
+            expect: Vec::decode(r)?,
+        })
+    }
+}
+
+impl Encode for TriviaPolicy {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            TriviaPolicy::AttachToNext => 0,
+            TriviaPolicy::AttachToPrevious => 1,
+            TriviaPolicy::SplitAtLine => 2,
+        });
+    }
+}
+
+impl Decode for TriviaPolicy {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(TriviaPolicy::AttachToNext),
+            1 => Ok(TriviaPolicy::AttachToPrevious),
+            2 => Ok(TriviaPolicy::SplitAtLine),
+            _ => Err(LoadError::Corrupt("invalid trivia policy")),
+        }
+    }
+}
+
+impl Encode for Node<'_> {
+    fn encode(&self, w: &mut Writer) {
+        self.name.encode(w);
+        self.rules.encode(w);
+        self.variables.encode(w);
+        self.docs.encode(w);
+        self.snippet.encode(w);
+        w.bool(self.foldable);
+        w.bool(self.indents_children);
+        self.symbol.encode(w);
+        self.since.encode(w);
+        self.until.encode(w);
+        w.bool(self.record_children);
+        w.bool(self.record_tokens);
+        w.usize(self.keywords.len());
+        for keyword in &self.keywords {
+            w.str(keyword);
+        }
+        self.initial.encode(w);
+        w.bool(self.allow_empty);
+        self.sync.encode(w);
+    }
+}
+
+impl Decode for Node<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        let node = Node {
+            name: r.symbol()?,
+            rules: Vec::decode(r)?,
+            variables: Vec::decode(r)?,
+            docs: Option::decode(r)?,
+            snippet: Option::decode(r)?,
+            foldable: r.bool()?,
+            indents_children: r.bool()?,
+            symbol: Option::decode(r)?,
+            since: Option::decode(r)?,
+            until: Option::decode(r)?,
+            record_children: r.bool()?,
+            record_tokens: r.bool()?,
+            keywords: {
+                let len = r.usize()?;
+                (0..len).map(|_| r.str()).collect::<Result<_, _>>()?
+            },
+            initial: Vec::decode(r)?,
+            allow_empty: r.bool()?,
+            sync: Vec::decode(r)?,
+        };
+        for (name, value) in &node.initial {
+            if !node
+                .variables
+                .iter()
+                .any(|(variable, kind)| *variable == *name && *kind == value.kind())
+            {
+                return Err(LoadError::Corrupt(
+                    "initial value does not match the variable",
+                ));
+            }
+        }
+        Ok(node)
+    }
+}
+
+impl Encode for LanguageVersion {
+    fn encode(&self, w: &mut Writer) {
+        w.u32(self.major);
+        w.u32(self.minor);
+    }
+}
+
+impl Decode for LanguageVersion {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        Ok(LanguageVersion::new(r.u32()?, r.u32()?))
+    }
+}
+
+const SYMBOL_KINDS: [SymbolKind; 16] = [
+    SymbolKind::Module,
+    SymbolKind::Namespace,
+    SymbolKind::Class,
+    SymbolKind::Struct,
+    SymbolKind::Enum,
+    SymbolKind::EnumMember,
+    SymbolKind::Interface,
+    SymbolKind::Function,
+    SymbolKind::Method,
+    SymbolKind::Constructor,
+    SymbolKind::Field,
+    SymbolKind::Property,
+    SymbolKind::Variable,
+    SymbolKind::Constant,
+    SymbolKind::Type,
+    SymbolKind::Other,
+];
+
+impl Encode for Symbol<'_> {
+    fn encode(&self, w: &mut Writer) {
+        w.str(self.name_from);
+        let kind = SYMBOL_KINDS.iter().position(|kind| *kind == self.kind);
+        w.u8(kind.unwrap_or(SYMBOL_KINDS.len() - 1) as u8);
+    }
+}
+
+impl Decode for Symbol<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        let name_from = r.str()?;
+        let kind = *SYMBOL_KINDS
+            .get(r.u8()? as usize)
+            .ok_or(LoadError::Corrupt("invalid symbol kind"))?;
+        Ok(Symbol { name_from, kind })
+    }
+}
+
+impl Encode for InitialValue {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            InitialValue::Number(n) => {
+                w.u8(0);
+                w.u32(*n as u32);
+            }
+            InitialValue::Boolean(b) => {
+                w.u8(1);
+                w.bool(*b);
+            }
+            InitialValue::Number64(n) => {
+                w.u8(2);
+                w.u64(*n as u64);
+            }
+            InitialValue::Float(f) => {
+                w.u8(3);
+                w.u64(f.to_bits());
+            }
+        }
+    }
+}
+
+impl Decode for InitialValue {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(InitialValue::Number(r.u32()? as i32)),
+            1 => Ok(InitialValue::Boolean(r.bool()?)),
+            2 => Ok(InitialValue::Number64(r.u64()? as i64)),
+            3 => Ok(InitialValue::Float(f64::from_bits(r.u64()?))),
+            _ => Err(LoadError::Corrupt("invalid initial value")),
+        }
+    }
+}
+
+impl Encode for VariableKind {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            VariableKind::Node => 0,
+            VariableKind::NodeList => 1,
+            VariableKind::Boolean => 2,
+            VariableKind::Number => 3,
+            VariableKind::Number64 => 4,
+            VariableKind::Float => 5,
+        });
+    }
+}
+
+impl Decode for VariableKind {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(VariableKind::Node),
+            1 => Ok(VariableKind::NodeList),
+            2 => Ok(VariableKind::Boolean),
+            3 => Ok(VariableKind::Number),
+            4 => Ok(VariableKind::Number64),
+            5 => Ok(VariableKind::Float),
+            _ => Err(LoadError::Corrupt("invalid variable kind")),
+        }
+    }
+}
+
+impl Encode for Enumerator<'_> {
+    fn encode(&self, w: &mut Writer) {
+        w.str(self.name);
+        self.values.encode(w);
+    }
+}
+
+impl Decode for Enumerator<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        Ok(Enumerator {
+            name: r.str()?,
+            values: Vec::decode(r)?,
+        })
+    }
+}
+
+impl Encode for MatchToken<'_> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            MatchToken::Token(kind) => {
+                w.u8(0);
+                kind.encode(w);
+            }
+            MatchToken::Node(name) => {
+                w.u8(1);
+                name.encode(w);
+            }
+            MatchToken::Word(word) => {
+                w.u8(2);
+                w.str(word);
+            }
+            MatchToken::Enumerator(name) => {
+                w.u8(3);
+                w.str(name);
+            }
+            MatchToken::Alias(name) => {
+                w.u8(4);
+                w.str(name);
+            }
+            MatchToken::Any => w.u8(5),
+            MatchToken::Char(c) => {
+                w.u8(6);
+                c.encode(w);
+            }
+            MatchToken::CharClass(class) => {
+                w.u8(7);
+                class.encode(w);
+            }
+        }
+    }
+}
+
+impl Decode for MatchToken<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(MatchToken::Token(TokenKinds::decode(r)?)),
+            1 => Ok(MatchToken::Node(r.symbol()?)),
+            2 => Ok(MatchToken::Word(r.str()?)),
+            3 => Ok(MatchToken::Enumerator(r.str()?)),
+            4 => Ok(MatchToken::Alias(r.str()?)),
+            5 => Ok(MatchToken::Any),
+            6 => Ok(MatchToken::Char(char::decode(r)?)),
+            7 => Ok(MatchToken::CharClass(CharClass::decode(r)?)),
+            _ => Err(LoadError::Corrupt("invalid token matcher")),
+        }
+    }
+}
+
+impl Encode for CharClass {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            CharClass::Range(from, to) => {
+                w.u8(0);
+                from.encode(w);
+                to.encode(w);
+            }
+            CharClass::Alphabetic => w.u8(1),
+            CharClass::Numeric => w.u8(2),
+            CharClass::Alphanumeric => w.u8(3),
+            CharClass::Whitespace => w.u8(4),
+            CharClass::Punctuation => w.u8(5),
+            CharClass::Not(c) => {
+                w.u8(6);
+                c.encode(w);
+            }
+        }
+    }
+}
+
+impl Decode for CharClass {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(CharClass::Range(char::decode(r)?, char::decode(r)?)),
+            1 => Ok(CharClass::Alphabetic),
+            2 => Ok(CharClass::Numeric),
+            3 => Ok(CharClass::Alphanumeric),
+            4 => Ok(CharClass::Whitespace),
+            5 => Ok(CharClass::Punctuation),
+            6 => Ok(CharClass::Not(char::decode(r)?)),
+            _ => Err(LoadError::Corrupt("invalid character class")),
+        }
+    }
+}
+
+impl Encode for VarKind<'_> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            VarKind::Local(name) => {
+                w.u8(0);
+                w.str(name);
+            }
+            VarKind::Global(name) => {
+                w.u8(1);
+                w.str(name);
+            }
+        }
+    }
+}
+
+impl Decode for VarKind<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(VarKind::Local(r.str()?)),
+            1 => Ok(VarKind::Global(r.str()?)),
+            _ => Err(LoadError::Corrupt("invalid variable")),
+        }
+    }
+}
+
+impl Encode for ErrorDefinition {
+    fn encode(&self, w: &mut Writer) {
+        w.str(self.header);
+        w.str(self.code);
+        w.str(self.msg);
+    }
+}
+
+/// Error definitions are referenced by rules, so they are leaked like the strings
+fn decode_error(r: &mut Reader) -> Result<&'static ErrorDefinition, LoadError> {
+    Ok(Box::leak(Box::new(ErrorDefinition {
+        header: r.str()?,
+        code: r.str()?,
+        msg: r.str()?,
+    })))
+}
+
+impl Encode for Parameters<'_> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Parameters::Set(var) => {
+                w.u8(0);
+                var.encode(w);
+            }
+            Parameters::SetField(var, field) => {
+                w.u8(1);
+                w.str(var);
+                w.str(field);
+            }
+            Parameters::Increment(var) => {
+                w.u8(2);
+                var.encode(w);
+            }
+            Parameters::Decrement(var) => {
+                w.u8(3);
+                var.encode(w);
+            }
+            Parameters::True(var) => {
+                w.u8(4);
+                var.encode(w);
+            }
+            Parameters::False(var) => {
+                w.u8(5);
+                var.encode(w);
+            }
+            Parameters::CloneValue(from, to) => {
+                w.u8(6);
+                from.encode(w);
+                to.encode(w);
+            }
+            Parameters::Print(message) => {
+                w.u8(7);
+                w.str(message);
+            }
+            Parameters::Debug(var) => {
+                w.u8(8);
+                var.encode(w);
+            }
+            Parameters::Back(steps) => {
+                w.u8(9);
+                w.u8(*steps);
+            }
+            Parameters::Return => w.u8(10),
+            Parameters::Break(depth) => {
+                w.u8(11);
+                w.usize(*depth);
+            }
+            Parameters::Commit(set) => {
+                w.u8(12);
+                w.bool(*set);
+            }
+            Parameters::Cut => w.u8(13),
+            Parameters::Goto(label) => {
+                w.u8(14);
+                w.str(label);
+            }
+            Parameters::NodeStart => w.u8(15),
+            Parameters::NodeEnd => w.u8(16),
+            Parameters::Hint(hint) => {
+                w.u8(17);
+                w.str(hint);
+            }
+            Parameters::Important => w.u8(18),
+            Parameters::AttachDocs => w.u8(19),
+            Parameters::Fail(err) => {
+                w.u8(20);
+                err.encode(w);
+            }
+            Parameters::Declare(var) => {
+                w.u8(21);
+                var.encode(w);
+            }
+            Parameters::EnterScope => w.u8(22),
+            Parameters::ExitScope => w.u8(23),
+            Parameters::Use(var) => {
+                w.u8(24);
+                var.encode(w);
+            }
+            Parameters::Split => w.u8(25),
+        }
+    }
+}
+
+impl Decode for Parameters<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(Parameters::Set(VarKind::decode(r)?)),
+            1 => Ok(Parameters::SetField(r.str()?, r.str()?)),
+            2 => Ok(Parameters::Increment(VarKind::decode(r)?)),
+            3 => Ok(Parameters::Decrement(VarKind::decode(r)?)),
+            4 => Ok(Parameters::True(VarKind::decode(r)?)),
+            5 => Ok(Parameters::False(VarKind::decode(r)?)),
+            6 => Ok(Parameters::CloneValue(
+                VarKind::decode(r)?,
+                VarKind::decode(r)?,
+            )),
+            7 => Ok(Parameters::Print(r.str()?)),
+            8 => Ok(Parameters::Debug(Option::decode(r)?)),
+            9 => Ok(Parameters::Back(r.u8()?)),
+            10 => Ok(Parameters::Return),
+            11 => Ok(Parameters::Break(r.usize()?)),
+            12 => Ok(Parameters::Commit(r.bool()?)),
+            13 => Ok(Parameters::Cut),
+            14 => Ok(Parameters::Goto(r.str()?)),
+            15 => Ok(Parameters::NodeStart),
+            16 => Ok(Parameters::NodeEnd),
+            17 => Ok(Parameters::Hint(r.str()?)),
+            18 => Ok(Parameters::Important),
+            19 => Ok(Parameters::AttachDocs),
+            20 => Ok(Parameters::Fail(decode_error(r)?)),
+            21 => Ok(Parameters::Declare(VarKind::decode(r)?)),
+            22 => Ok(Parameters::EnterScope),
+            23 => Ok(Parameters::ExitScope),
+            24 => Ok(Parameters::Use(VarKind::decode(r)?)),
+            25 => Ok(Parameters::Split),
+            _ => Err(LoadError::Corrupt("invalid parameter")),
+        }
+    }
+}
+
+const COMPARISONS: [Comparison; 6] = [
+    Comparison::Equal,
+    Comparison::NotEqual,
+    Comparison::GreaterThan,
+    Comparison::LessThan,
+    Comparison::GreaterThanOrEqual,
+    Comparison::LessThanOrEqual,
+];
+
+impl Encode for Commands<'_> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Commands::Compare {
+                left,
+                right,
+                comparison,
+                rules,
+            } => {
+                w.u8(0);
+                left.encode(w);
+                right.encode(w);
+                let comparison = COMPARISONS.iter().position(|c| c == comparison);
+                w.u8(comparison.unwrap_or_default() as u8);
+                rules.encode(w);
+            }
+            Commands::Error { err } => {
+                w.u8(1);
+                err.encode(w);
+            }
+            Commands::Commit { set } => {
+                w.u8(2);
+                w.bool(*set);
+            }
+            Commands::Goto { label } => {
+                w.u8(3);
+                w.str(label);
+            }
+            Commands::Label { name } => {
+                w.u8(4);
+                w.str(name);
+            }
+            Commands::Print { message } => {
+                w.u8(5);
+                w.str(message);
+            }
+            Commands::Return => w.u8(6),
+            Commands::Start => w.u8(7),
+            Commands::End => w.u8(8),
+            Commands::SetNumber { var, value } => {
+                w.u8(9);
+                var.encode(w);
+                w.u32(*value as u32);
+            }
+            Commands::SetBool { var, value } => {
+                w.u8(10);
+                var.encode(w);
+                w.bool(*value);
+            }
+            Commands::SetNumber64 { var, value } => {
+                w.u8(11);
+                var.encode(w);
+                w.u64(*value as u64);
+            }
+            Commands::SetFloat { var, value } => {
+                w.u8(12);
+                var.encode(w);
+                w.u64(value.to_bits());
+            }
+            Commands::External { name, args, rules } => {
+                w.u8(13);
+                w.str(name);
+                args.encode(w);
+                rules.encode(w);
+            }
+            Commands::If { cond, rules } => {
+                w.u8(14);
+                cond.encode(w);
+                rules.encode(w);
+            }
+        }
+    }
+}
+
+impl Decode for Commands<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(Commands::Compare {
+                left: VarKind::decode(r)?,
+                right: VarKind::decode(r)?,
+                comparison: *COMPARISONS
+                    .get(r.u8()? as usize)
+                    .ok_or(LoadError::Corrupt("invalid comparison"))?,
+                rules: Vec::decode(r)?,
+            }),
+            1 => Ok(Commands::Error {
+                err: decode_error(r)?,
+            }),
+            2 => Ok(Commands::Commit { set: r.bool()? }),
+            3 => Ok(Commands::Goto { label: r.str()? }),
+            4 => Ok(Commands::Label { name: r.str()? }),
+            5 => Ok(Commands::Print { message: r.str()? }),
+            6 => Ok(Commands::Return),
+            7 => Ok(Commands::Start),
+            8 => Ok(Commands::End),
+            9 => Ok(Commands::SetNumber {
+                var: VarKind::decode(r)?,
+                value: r.u32()? as i32,
+            }),
+            10 => Ok(Commands::SetBool {
+                var: VarKind::decode(r)?,
+                value: r.bool()?,
+            }),
+            11 => Ok(Commands::SetNumber64 {
+                var: VarKind::decode(r)?,
+                value: r.u64()? as i64,
+            }),
+            12 => Ok(Commands::SetFloat {
+                var: VarKind::decode(r)?,
+                value: f64::from_bits(r.u64()?),
+            }),
+            13 => Ok(Commands::External {
+                name: r.str()?,
+                args: Vec::decode(r)?,
+                rules: Vec::decode(r)?,
+            }),
+            14 => Ok(Commands::If {
+                cond: Cond::decode(r)?,
+                rules: Vec::decode(r)?,
+            }),
+            _ => Err(LoadError::Corrupt("invalid command")),
+        }
+    }
+}
+
+impl Encode for Cond<'_> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Cond::Compare {
+                left,
+                right,
+                comparison,
+            } => {
+                w.u8(0);
+                left.encode(w);
+                right.encode(w);
+                let comparison = COMPARISONS.iter().position(|c| c == comparison);
+                w.u8(comparison.unwrap_or_default() as u8);
+            }
+            Cond::External { name, args } => {
+                w.u8(1);
+                w.str(name);
+                args.encode(w);
+            }
+            Cond::And(conds) => {
+                w.u8(2);
+                conds.encode(w);
+            }
+            Cond::Or(conds) => {
+                w.u8(3);
+                conds.encode(w);
+            }
+            Cond::Not(cond) => {
+                w.u8(4);
+                cond.encode(w);
+            }
+        }
+    }
+}
+
+impl Decode for Cond<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(Cond::Compare {
+                left: VarKind::decode(r)?,
+                right: VarKind::decode(r)?,
+                comparison: *COMPARISONS
+                    .get(r.u8()? as usize)
+                    .ok_or(LoadError::Corrupt("invalid comparison"))?,
+            }),
+            1 => Ok(Cond::External {
+                name: r.str()?,
+                args: Vec::decode(r)?,
+            }),
+            2 => Ok(Cond::And(Vec::decode(r)?)),
+            3 => Ok(Cond::Or(Vec::decode(r)?)),
+            4 => Ok(Cond::Not(Box::new(Cond::decode(r)?))),
+            _ => Err(LoadError::Corrupt("invalid condition")),
+        }
+    }
+}
+
+impl Encode for OneOf<'_> {
+    fn encode(&self, w: &mut Writer) {
+        self.token.encode(w);
+        self.rules.encode(w);
+        self.parameters.encode(w);
+    }
+}
+
+impl Decode for OneOf<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        Ok(OneOf {
+            token: MatchToken::decode(r)?,
+            rules: Vec::decode(r)?,
+            parameters: Vec::decode(r)?,
+        })
+    }
+}
+
+impl Encode for Origin<'_> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Origin::Unknown => w.u8(0),
+            Origin::Code { file, line, column } => {
+                w.u8(1);
+                w.str(file);
+                w.u32(*line);
+                w.u32(*column);
+            }
+            Origin::Definition(path) => {
+                w.u8(2);
+                w.str(path);
+            }
+        }
+    }
+}
+
+impl Decode for Origin<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(Origin::Unknown),
+            1 => Ok(Origin::Code {
+                file: r.str()?,
+                line: r.u32()?,
+                column: r.u32()?,
+            }),
+            2 => Ok(Origin::Definition(r.str()?)),
+            _ => Err(LoadError::Corrupt("invalid rule origin")),
+        }
+    }
+}
+
+impl Encode for Rule<'_> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Rule::Is {
+                token,
+                rules,
+                parameters,
+                ..
+            } => {
+                w.u8(0);
+                self.origin().encode(w);
+                token.encode(w);
+                rules.encode(w);
+                parameters.encode(w);
+            }
+            Rule::Isnt {
+                token,
+                rules,
+                parameters,
+                ..
+            } => {
+                w.u8(1);
+                self.origin().encode(w);
+                token.encode(w);
+                rules.encode(w);
+                parameters.encode(w);
+            }
+            Rule::IsOneOf {
+                tokens, parameters, ..
+            } => {
+                w.u8(2);
+                self.origin().encode(w);
+                tokens.encode(w);
+                parameters.encode(w);
+            }
+            Rule::Maybe {
+                token,
+                is,
+                isnt,
+                parameters,
+                ..
+            } => {
+                w.u8(3);
+                self.origin().encode(w);
+                token.encode(w);
+                is.encode(w);
+                isnt.encode(w);
+                parameters.encode(w);
+            }
+            Rule::MaybeOneOf {
+                is_one_of, isnt, ..
+            } => {
+                w.u8(4);
+                self.origin().encode(w);
+                is_one_of.encode(w);
+                isnt.encode(w);
+            }
+            Rule::While {
+                token,
+                rules,
+                parameters,
+                ..
+            } => {
+                w.u8(5);
+                self.origin().encode(w);
+                token.encode(w);
+                rules.encode(w);
+                parameters.encode(w);
+            }
+            Rule::Loop { rules, .. } => {
+                w.u8(6);
+                self.origin().encode(w);
+                rules.encode(w);
+            }
+            Rule::IfFeature {
+                name,
+                rules,
+                else_rules,
+                ..
+            } => {
+                w.u8(7);
+                self.origin().encode(w);
+                w.str(name);
+                rules.encode(w);
+                else_rules.encode(w);
+            }
+            Rule::Until {
+                token,
+                rules,
+                parameters,
+                ..
+            } => {
+                w.u8(8);
+                self.origin().encode(w);
+                token.encode(w);
+                rules.encode(w);
+                parameters.encode(w);
+            }
+            Rule::UntilOneOf { tokens, .. } => {
+                w.u8(9);
+                self.origin().encode(w);
+                tokens.encode(w);
+            }
+            Rule::Peek {
+                token,
+                is,
+                isnt,
+                parameters,
+                ..
+            } => {
+                w.u8(10);
+                self.origin().encode(w);
+                token.encode(w);
+                is.encode(w);
+                isnt.encode(w);
+                parameters.encode(w);
+            }
+            Rule::Command { command, .. } => {
+                w.u8(11);
+                self.origin().encode(w);
+                command.encode(w);
+            }
+            Rule::Debug { target, .. } => {
+                w.u8(12);
+                self.origin().encode(w);
+                target.encode(w);
+            }
+        }
+    }
+}
+
+impl Decode for Rule<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        match r.u8()? {
+            0 => Ok(Rule::Is {
+                origin: Origin::decode(r)?,
+                token: MatchToken::decode(r)?,
+                rules: Vec::decode(r)?,
+                parameters: Vec::decode(r)?,
+            }),
+            1 => Ok(Rule::Isnt {
+                origin: Origin::decode(r)?,
+                token: MatchToken::decode(r)?,
+                rules: Vec::decode(r)?,
+                parameters: Vec::decode(r)?,
+            }),
+            2 => Ok(Rule::IsOneOf {
+                origin: Origin::decode(r)?,
+                tokens: Vec::decode(r)?,
+                parameters: Vec::decode(r)?,
+            }),
+            3 => Ok(Rule::Maybe {
+                origin: Origin::decode(r)?,
+                token: MatchToken::decode(r)?,
+                is: Vec::decode(r)?,
+                isnt: Vec::decode(r)?,
+                parameters: Vec::decode(r)?,
+            }),
+            4 => Ok(Rule::MaybeOneOf {
+                origin: Origin::decode(r)?,
+                is_one_of: Vec::decode(r)?,
+                isnt: Vec::decode(r)?,
+            }),
+            5 => Ok(Rule::While {
+                origin: Origin::decode(r)?,
+                token: MatchToken::decode(r)?,
+                rules: Vec::decode(r)?,
+                parameters: Vec::decode(r)?,
+            }),
+            6 => Ok(Rule::Loop {
+                origin: Origin::decode(r)?,
+                rules: Vec::decode(r)?,
+            }),
+            7 => Ok(Rule::IfFeature {
+                origin: Origin::decode(r)?,
+                name: r.str()?,
+                rules: Vec::decode(r)?,
+                else_rules: Vec::decode(r)?,
+            }),
+            8 => Ok(Rule::Until {
+                origin: Origin::decode(r)?,
+                token: MatchToken::decode(r)?,
+                rules: Vec::decode(r)?,
+                parameters: Vec::decode(r)?,
+            }),
+            9 => Ok(Rule::UntilOneOf {
+                origin: Origin::decode(r)?,
+                tokens: Vec::decode(r)?,
+            }),
+            10 => Ok(Rule::Peek {
+                origin: Origin::decode(r)?,
+                token: MatchToken::decode(r)?,
+                is: Vec::decode(r)?,
+                isnt: Vec::decode(r)?,
+                parameters: Vec::decode(r)?,
+            }),
+            11 => Ok(Rule::Command {
+                origin: Origin::decode(r)?,
+                command: Commands::decode(r)?,
+            }),
+            12 => Ok(Rule::Debug {
+                origin: Origin::decode(r)?,
+                target: Option::decode(r)?,
+            }),
+            _ => Err(LoadError::Corrupt("invalid rule")),
+        }
+    }
+}
+
+impl Encode for parser::Parser<'_> {
+    fn encode(&self, w: &mut Writer) {
+        self.entry.encode(w);
+        w.bool(self.eof_error);
+        w.bool(self.profile);
+        self.backtrack_limit.encode(w);
+        self.max_nodes.encode(w);
+        self.max_nesting.encode(w);
+        w.usize(self.max_candidates);
+        encode_map(&self.features, w);
+        self.version.encode(w);
+        w.usize(self.exploration.max_parses);
+        w.usize(self.exploration.max_attempts);
+        match &self.recovery {
+            Some(recovery) => {
+                w.bool(true);
+                recovery.sync.encode(w);
+            }
+            None => w.bool(false),
+        }
+        self.materialize.encode(w);
+    }
+}
+
+impl Decode for parser::Parser<'static> {
+    fn decode(r: &mut Reader) -> Result<Self, LoadError> {
+        Ok(parser::Parser {
+            entry: Option::decode(r)?,
+            eof_error: r.bool()?,
+            profile: r.bool()?,
+            backtrack_limit: Option::decode(r)?,
+            max_nodes: Option::decode(r)?,
+            max_nesting: Option::decode(r)?,
+            max_candidates: r.usize()?,
+            features: decode_map(r)?,
+            version: Option::decode(r)?,
+            token_filters: Vec::new(),
+            predicates: Default::default(),
+            cancellation: None,
+            progress: None,
+            exploration: parser::Exploration {
+                max_parses: r.usize()?,
+                max_attempts: r.usize()?,
+            },
+            recovery: match r.bool()? {
+                true => Some(parser::Recovery {
+                    sync: Vec::decode(r)?,
+                }),
+                false => None,
+            },
+            materialize: Option::decode(r)?,
+        })
+    }
+}
@@ -0,0 +1,2005 @@
+//! Grammar definition files
+//!
+//! Grammars can be written by hand in a data format instead of being built in code.
+//! The definition is read into a `Value` tree and loaded into a `Parser`, errors point
+//! to the place in the file and suggest the closest known name:
+//!
+//! ```text
+//! unknown rule kind 'Whlie' at nodes[3].rules[0], did you mean 'While'?
+//! ```
+//!
+//! The format is described by `JSON_SCHEMA`. In short, a definition is an object with
+//! `tokens`, `nodes` and optionally `entry`, `ignored`, `eof`, `trivia`, `brackets`, `features`,
+//! `globals`, `enumerators`, `aliases`, `tests`, `eof_error`, `max_candidates`,
+//! `backtrack_limit`, `max_nodes`, `max_nesting`, `recovery` and `materialize`.
+//! Rules are objects with a `kind` (`Is`, `While`, `Compare`, ...) and the fields of the rule.
+//! Tokens, parameters and variable kinds are written as their variant name, for example
+//! `"Text"`, or as an object with the name as the only key, for example `{ "Word": "let" }`.
+//!
+//! The same structure can be written in JSON, TOML or YAML, each behind a feature
+//! of the same name. TOML and YAML allow comments and multi-line docs, which makes
+//! them easier to edit by hand.
+//!
+//! Strings of a loaded grammar are leaked to get the `'static` lifetime,
+//! load a grammar once at startup and keep it for the lifetime of the program.
+//! The loaded grammar is not validated, run `Validator::validate` on it.
+//!
+//! `Grammar::to_definition` goes the other way and `Grammar::to_canonical_json` writes
+//! the result in a canonical form that is the same for grammars of the same structure.
+
+use core::fmt;
+
+use crate::{
+    compiled::CompiledGrammar,
+    grammar::{
+        CharClass, Commands, Comparison, Cond, Enumerator, ErrorDefinition, Grammar, InitialValue,
+        LanguageVersion, MatchToken, Node, OneOf, Origin, Parameters, Rule, Rules, Symbol,
+        SymbolKind, TriviaPolicy, VarKind, VariableKind,
+    },
+    intern,
+    lexer::{ControlTokenKind, TokenKinds},
+    parser::Recovery,
+    Map, Parser,
+};
+
+/// JSON Schema of the definition format
+pub const JSON_SCHEMA: &str = include_str!("../schema/grammar.schema.json");
+
+/// Data of a definition file, independent of the format it was written in
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Entries in the order of the file
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        }
+    }
+}
+
+/// Error in a definition file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefinitionError {
+    /// Location in the definition, for example `nodes[3].rules[0]`
+    ///
+    /// Empty for errors of the whole file
+    pub path: String,
+    pub message: String,
+    /// Closest known name if the error is caused by an unknown one
+    pub suggestion: Option<&'static str>,
+}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if !self.path.is_empty() {
+            write!(f, " at {}", self.path)?;
+        }
+        if let Some(suggestion) = self.suggestion {
+            write!(f, ", did you mean '{}'?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DefinitionError {}
+
+/// Loads a parser from a definition
+pub fn load(value: &Value) -> Result<Parser<'static>, DefinitionError> {
+    Loader::default().parser(value)
+}
+
+/// Loads a parser from a JSON definition
+#[cfg(feature = "json")]
+pub fn from_json(text: &str) -> Result<Parser<'static>, DefinitionError> {
+    let json = serde_json::from_str::<serde_json::Value>(text).map_err(|err| DefinitionError {
+        path: String::new(),
+        message: format!("invalid JSON: {}", err),
+        suggestion: None,
+    })?;
+    load(&json.into())
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(b),
+            serde_json::Value::Number(n) => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+            serde_json::Value::String(s) => Value::String(s),
+            serde_json::Value::Array(values) => {
+                Value::Array(values.into_iter().map(Value::from).collect())
+            }
+            serde_json::Value::Object(entries) => Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Loads a parser from a TOML definition
+#[cfg(feature = "toml")]
+pub fn from_toml(text: &str) -> Result<Parser<'static>, DefinitionError> {
+    let table = text.parse::<toml::Table>().map_err(|err| DefinitionError {
+        path: String::new(),
+        message: format!("invalid TOML: {}", err),
+        suggestion: None,
+    })?;
+    load(&toml::Value::Table(table).into())
+}
+
+#[cfg(feature = "toml")]
+impl From<toml::Value> for Value {
+    fn from(value: toml::Value) -> Self {
+        match value {
+            toml::Value::String(s) => Value::String(s),
+            toml::Value::Integer(i) => Value::Number(i as f64),
+            toml::Value::Float(f) => Value::Number(f),
+            toml::Value::Boolean(b) => Value::Bool(b),
+            toml::Value::Datetime(d) => Value::String(d.to_string()),
+            toml::Value::Array(values) => {
+                Value::Array(values.into_iter().map(Value::from).collect())
+            }
+            toml::Value::Table(entries) => Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Loads a parser from a YAML definition, only the first document is used
+#[cfg(feature = "yaml")]
+pub fn from_yaml(text: &str) -> Result<Parser<'static>, DefinitionError> {
+    let documents = yaml_rust2::YamlLoader::load_from_str(text).map_err(|err| DefinitionError {
+        path: String::new(),
+        message: format!("invalid YAML: {}", err),
+        suggestion: None,
+    })?;
+    let document = documents
+        .into_iter()
+        .next()
+        .ok_or_else(|| error("", "empty YAML document"))?;
+    load(&yaml_value(document, "")?)
+}
+
+#[cfg(feature = "yaml")]
+fn yaml_value(yaml: yaml_rust2::Yaml, path: &str) -> Result<Value, DefinitionError> {
+    use yaml_rust2::Yaml;
+
+    Ok(match yaml {
+        Yaml::Null => Value::Null,
+        Yaml::Boolean(b) => Value::Bool(b),
+        Yaml::Integer(i) => Value::Number(i as f64),
+        Yaml::Real(real) => Value::Number(
+            real.parse()
+                .map_err(|_| error(path, format!("invalid number '{}'", real)))?,
+        ),
+        Yaml::String(s) => Value::String(s),
+        Yaml::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| yaml_value(value, &format!("{}[{}]", path, i)))
+                .collect::<Result<_, _>>()?,
+        ),
+        Yaml::Hash(entries) => {
+            let mut object = Vec::new();
+            for (key, value) in entries {
+                let key = match key {
+                    Yaml::String(key) => key,
+                    Yaml::Integer(i) => i.to_string(),
+                    Yaml::Boolean(b) => b.to_string(),
+                    _ => return Err(error(path, "keys have to be strings")),
+                };
+                let value = yaml_value(value, &join(path, &key))?;
+                object.push((key, value));
+            }
+            Value::Object(object)
+        }
+        Yaml::Alias(_) | Yaml::BadValue => return Err(error(path, "unsupported YAML value")),
+    })
+}
+
+const ROOT_FIELDS: &[&str] = &[
+    "$schema",
+    "tokens",
+    "nodes",
+    "entry",
+    "ignored",
+    "eof",
+    "trivia",
+    "brackets",
+    "features",
+    "globals",
+    "enumerators",
+    "aliases",
+    "tests",
+    "eof_error",
+    "max_candidates",
+    "backtrack_limit",
+    "max_nodes",
+    "max_nesting",
+    "recovery",
+    "materialize",
+];
+const TOKEN_FIELDS: &[&str] = &["token", "tag"];
+const TEST_FIELDS: &[&str] = &["node", "input", "expect"];
+const RECOVERY_FIELDS: &[&str] = &["sync"];
+const NODE_FIELDS: &[&str] = &[
+    "name",
+    "docs",
+    "snippet",
+    "variables",
+    "rules",
+    "foldable",
+    "indents_children",
+    "symbol",
+    "since",
+    "until",
+    "record_children",
+    "record_tokens",
+    "allow_empty",
+    "sync",
+    "keywords",
+];
+const SYMBOL_FIELDS: &[&str] = &["name_from", "kind"];
+const ONE_OF_FIELDS: &[&str] = &["token", "rules", "parameters"];
+const ERROR_FIELDS: &[&str] = &["header", "code", "msg"];
+const RULE_KINDS: &[&str] = &[
+    "Is",
+    "Isnt",
+    "IsOneOf",
+    "Maybe",
+    "MaybeOneOf",
+    "While",
+    "Loop",
+    "IfFeature",
+    "Until",
+    "UntilOneOf",
+    "Peek",
+    "Debug",
+    "Compare",
+    "External",
+    "If",
+    "Error",
+    "Commit",
+    "Goto",
+    "Label",
+    "Print",
+    "Return",
+    "Start",
+    "End",
+    "SetNumber",
+    "SetNumber64",
+    "SetFloat",
+    "SetBool",
+];
+const TOKENS: &[&str] = &[
+    "Token",
+    "Complex",
+    "Word",
+    "Node",
+    "Enumerator",
+    "Alias",
+    "Any",
+    "Text",
+    "Whitespace",
+    "Eol",
+    "Eof",
+    "Error",
+    "DocComment",
+    "Char",
+    "CharClass",
+];
+const CHAR_CLASSES: &[&str] = &[
+    "Range",
+    "Alphabetic",
+    "Numeric",
+    "Alphanumeric",
+    "Whitespace",
+    "Punctuation",
+    "Not",
+];
+const PARAMETERS: &[&str] = &[
+    "Set",
+    "SetField",
+    "Increment",
+    "Decrement",
+    "True",
+    "False",
+    "CloneValue",
+    "Print",
+    "Debug",
+    "Back",
+    "Return",
+    "Break",
+    "Commit",
+    "Cut",
+    "Goto",
+    "NodeStart",
+    "NodeEnd",
+    "Split",
+    "Hint",
+    "Important",
+    "AttachDocs",
+    "Declare",
+    "Use",
+    "EnterScope",
+    "ExitScope",
+    "Fail",
+];
+const VARIABLES: &[&str] = &["Local", "Global"];
+const VARIABLE_KINDS: &[&str] = &["Node", "NodeList", "Boolean", "Number", "Number64", "Float"];
+const COMPARISONS: &[&str] = &[
+    "Equal",
+    "NotEqual",
+    "GreaterThan",
+    "LessThan",
+    "GreaterThanOrEqual",
+    "LessThanOrEqual",
+];
+const CONDITIONS: &[&str] = &["Compare", "External", "And", "Or", "Not"];
+const TRIVIA_POLICIES: &[&str] = &["AttachToNext", "AttachToPrevious", "SplitAtLine"];
+const SYMBOL_KINDS: &[&str] = &[
+    "Module",
+    "Namespace",
+    "Class",
+    "Struct",
+    "Enum",
+    "EnumMember",
+    "Interface",
+    "Function",
+    "Method",
+    "Constructor",
+    "Field",
+    "Property",
+    "Variable",
+    "Constant",
+    "Type",
+    "Other",
+];
+
+/// Fields of a rule kind besides `kind`
+fn rule_fields(kind: &str) -> &'static [&'static str] {
+    match kind {
+        "Is" | "Isnt" | "While" | "Until" => &["token", "rules", "parameters"],
+        "IsOneOf" => &["tokens", "parameters"],
+        "Maybe" | "Peek" => &["token", "is", "isnt", "parameters"],
+        "MaybeOneOf" => &["tokens", "isnt"],
+        "UntilOneOf" => &["tokens"],
+        "Loop" => &["rules"],
+        "IfFeature" => &["name", "rules", "else"],
+        "Debug" => &["target"],
+        "Compare" => &["left", "right", "comparison", "rules"],
+        "External" => &["name", "args", "rules"],
+        "If" => &["cond", "rules"],
+        "Error" => &["error"],
+        "Commit" => &["set"],
+        "Goto" => &["label"],
+        "Label" => &["name"],
+        "Print" => &["message"],
+        "SetNumber" | "SetNumber64" | "SetFloat" | "SetBool" => &["var", "value"],
+        _ => &[],
+    }
+}
+
+/// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(current)
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+/// Known name closest to `name`, if it is close enough to be a typo
+fn suggest(name: &str, known: &[&'static str]) -> Option<&'static str> {
+    let lower = name.to_lowercase();
+    known
+        .iter()
+        .map(|k| (distance(&lower, &k.to_lowercase()), *k))
+        .filter(|(d, _)| *d <= (name.len() / 3).max(2))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k)
+}
+
+fn join(path: &str, field: &str) -> String {
+    match path.is_empty() {
+        true => field.to_string(),
+        false => format!("{}.{}", path, field),
+    }
+}
+
+fn error(path: &str, message: impl Into<String>) -> DefinitionError {
+    DefinitionError {
+        path: path.to_string(),
+        message: message.into(),
+        suggestion: None,
+    }
+}
+
+fn expected(path: &str, what: &str, value: &Value) -> DefinitionError {
+    error(path, format!("expected {}, found {}", what, value.kind()))
+}
+
+/// Object with checked field names
+struct Object<'v> {
+    fields: &'v [(String, Value)],
+    path: String,
+}
+
+impl<'v> Object<'v> {
+    fn new(value: &'v Value, path: &str, known: &[&'static str]) -> Result<Self, DefinitionError> {
+        let Value::Object(fields) = value else {
+            return Err(expected(path, "object", value));
+        };
+        for (name, _) in fields {
+            if !known.contains(&name.as_str()) {
+                return Err(DefinitionError {
+                    suggestion: suggest(name, known),
+                    ..error(path, format!("unknown field '{}'", name))
+                });
+            }
+        }
+        Ok(Object {
+            fields,
+            path: path.to_string(),
+        })
+    }
+
+    fn get(&self, name: &str) -> Option<(&'v Value, String)> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| (value, join(&self.path, name)))
+    }
+
+    fn require(&self, name: &str) -> Result<(&'v Value, String), DefinitionError> {
+        self.get(name)
+            .ok_or_else(|| error(&self.path, format!("missing field '{}'", name)))
+    }
+}
+
+/// Leaks strings of the definition, the same string is leaked only once
+#[derive(Default)]
+struct Loader {
+    strings: Map<String, &'static str>,
+}
+
+impl Loader {
+    fn leak(&mut self, string: &str) -> &'static str {
+        if let Some(leaked) = self.strings.get(string) {
+            return leaked;
+        }
+        let leaked: &'static str = Box::leak(string.to_string().into_boxed_str());
+        self.strings.insert(string.to_string(), leaked);
+        leaked
+    }
+
+    fn string(&mut self, value: &Value, path: &str) -> Result<&'static str, DefinitionError> {
+        match value {
+            Value::String(string) => Ok(self.leak(string)),
+            _ => Err(expected(path, "string", value)),
+        }
+    }
+
+    /// Names are kept in the crate-wide interner instead of being leaked
+    fn symbol(&self, value: &Value, path: &str) -> Result<intern::Symbol, DefinitionError> {
+        match value {
+            Value::String(string) => Ok(intern::Symbol::intern(string)),
+            _ => Err(expected(path, "string", value)),
+        }
+    }
+
+    fn array<'v>(&self, value: &'v Value, path: &str) -> Result<&'v [Value], DefinitionError> {
+        match value {
+            Value::Array(values) => Ok(values),
+            _ => Err(expected(path, "array", value)),
+        }
+    }
+
+    fn entries<'v>(
+        &self,
+        value: &'v Value,
+        path: &str,
+    ) -> Result<&'v [(String, Value)], DefinitionError> {
+        match value {
+            Value::Object(entries) => Ok(entries),
+            _ => Err(expected(path, "object", value)),
+        }
+    }
+
+    fn bool(&self, value: &Value, path: &str) -> Result<bool, DefinitionError> {
+        match value {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(expected(path, "boolean", value)),
+        }
+    }
+
+    fn number(&self, value: &Value, path: &str) -> Result<usize, DefinitionError> {
+        match value {
+            Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 && *n <= u32::MAX as f64 => {
+                Ok(*n as usize)
+            }
+            _ => Err(expected(path, "non-negative integer", value)),
+        }
+    }
+
+    fn integer(&self, value: &Value, path: &str) -> Result<i32, DefinitionError> {
+        match value {
+            Value::Number(n)
+                if n.fract() == 0.0 && *n >= i32::MIN as f64 && *n <= i32::MAX as f64 =>
+            {
+                Ok(*n as i32)
+            }
+            _ => Err(expected(path, "integer", value)),
+        }
+    }
+
+    /// Integers past 2^53 lose precision in the parsed document
+    fn integer64(&self, value: &Value, path: &str) -> Result<i64, DefinitionError> {
+        match value {
+            Value::Number(n)
+                if n.fract() == 0.0 && *n >= i64::MIN as f64 && *n < i64::MAX as f64 =>
+            {
+                Ok(*n as i64)
+            }
+            _ => Err(expected(path, "integer", value)),
+        }
+    }
+
+    fn float(&self, value: &Value, path: &str) -> Result<f64, DefinitionError> {
+        match value {
+            Value::Number(n) => Ok(*n),
+            _ => Err(expected(path, "number", value)),
+        }
+    }
+
+    /// Variant written as `"Name"` or `{ "Name": argument }`
+    fn variant<'v>(
+        &self,
+        value: &'v Value,
+        path: &str,
+        what: &str,
+        known: &[&'static str],
+    ) -> Result<(&'v str, Option<&'v Value>), DefinitionError> {
+        let (name, argument) = match value {
+            Value::String(name) => (name.as_str(), None),
+            Value::Object(entries) if entries.len() == 1 => {
+                (entries[0].0.as_str(), Some(&entries[0].1))
+            }
+            _ => return Err(expected(path, what, value)),
+        };
+        if !known.contains(&name) {
+            return Err(DefinitionError {
+                suggestion: suggest(name, known),
+                ..error(path, format!("unknown {} '{}'", what, name))
+            });
+        }
+        Ok((name, argument))
+    }
+
+    /// Argument of the variant `name`
+    fn argument<'v>(
+        &self,
+        argument: Option<&'v Value>,
+        path: &str,
+        name: &str,
+    ) -> Result<(&'v Value, String), DefinitionError> {
+        argument
+            .map(|value| (value, join(path, name)))
+            .ok_or_else(|| error(path, format!("'{}' needs a value", name)))
+    }
+
+    fn parser(&mut self, value: &Value) -> Result<Parser<'static>, DefinitionError> {
+        let root = Object::new(value, "", ROOT_FIELDS)?;
+        let mut parser = Parser::new();
+
+        let (tokens, path) = root.require("tokens")?;
+        for (i, token) in self.array(tokens, &path)?.iter().enumerate() {
+            let path = format!("{}[{}]", path, i);
+            match token {
+                Value::String(token) => parser.lexer.add_token(token.as_str()),
+                _ => {
+                    let token = Object::new(token, &path, TOKEN_FIELDS)?;
+                    let (text, text_path) = token.require("token")?;
+                    let text = self.string(text, &text_path)?;
+                    match token.get("tag") {
+                        Some((tag, tag_path)) => {
+                            let tag = self.string(tag, &tag_path)?;
+                            parser.lexer.add_token_tagged(text, tag)
+                        }
+                        None => parser.lexer.add_token(text),
+                    }
+                }
+            }
+        }
+
+        let (nodes, path) = root.require("nodes")?;
+        // index of the node that defined each name, a second node would replace it
+        let mut defined = Map::new();
+        for (i, node) in self.array(nodes, &path)?.iter().enumerate() {
+            let node_path = format!("{}[{}]", path, i);
+            let node = self.node(node, &node_path)?;
+            if let Some(first) = defined.insert(node.name, i) {
+                return Err(error(
+                    &format!("{}.name", node_path),
+                    format!(
+                        "node '{}' is already defined at {}[{}]",
+                        node.name, path, first
+                    ),
+                ));
+            }
+            parser.grammar.nodes.insert(node.name, node);
+        }
+
+        if let Some((entry, path)) = root.get("entry") {
+            parser.parser.entry = Some(self.string(entry, &path)?);
+        }
+        if let Some((ignored, path)) = root.get("ignored") {
+            for (i, kind) in self.array(ignored, &path)?.iter().enumerate() {
+                let path = format!("{}[{}]", path, i);
+                match self.token(kind, &path)? {
+                    MatchToken::Token(kind) => parser.grammar.ignored.push(kind),
+                    _ => return Err(error(&path, "only token kinds can be ignored")),
+                }
+            }
+        }
+        if let Some((eof, path)) = root.get("eof") {
+            parser.grammar.eof = self.bool(eof, &path)?;
+        }
+        if let Some((trivia, path)) = root.get("trivia") {
+            let (name, _) = self.variant(trivia, &path, "trivia policy", TRIVIA_POLICIES)?;
+            parser.grammar.trivia = Some(match name {
+                "AttachToNext" => TriviaPolicy::AttachToNext,
+                "AttachToPrevious" => TriviaPolicy::AttachToPrevious,
+                _ => TriviaPolicy::SplitAtLine,
+            });
+        }
+        if let Some((brackets, path)) = root.get("brackets") {
+            for (i, pair) in self.array(brackets, &path)?.iter().enumerate() {
+                let path = format!("{}[{}]", path, i);
+                let [open, close] = self.array(pair, &path)? else {
+                    return Err(error(
+                        &path,
+                        "a bracket pair has an opening and a closing token",
+                    ));
+                };
+                let open_path = format!("{}[0]", path);
+                let close_path = format!("{}[1]", path);
+                match (
+                    self.token(open, &open_path)?,
+                    self.token(close, &close_path)?,
+                ) {
+                    (MatchToken::Token(open), MatchToken::Token(close)) => {
+                        parser.grammar.brackets.push((open, close))
+                    }
+                    _ => return Err(error(&path, "only token kinds can be brackets")),
+                }
+            }
+        }
+        if let Some((features, path)) = root.get("features") {
+            for (name, enabled) in self.entries(features, &path)? {
+                let enabled = self.bool(enabled, &join(&path, name))?;
+                parser.grammar.features.insert(name.clone(), enabled);
+            }
+        }
+        if let Some((globals, path)) = root.get("globals") {
+            parser.grammar.globals = self.variables(globals, &path, None)?;
+        }
+        if let Some((enumerators, path)) = root.get("enumerators") {
+            for (name, values) in self.entries(enumerators, &path)? {
+                let path = join(&path, name);
+                let values = self
+                    .array(values, &path)?
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| self.token(value, &format!("{}[{}]", path, i)))
+                    .collect::<Result<_, _>>()?;
+                let name = self.leak(name);
+                parser
+                    .grammar
+                    .enumerators
+                    .insert(name.to_string(), Enumerator { name, values });
+            }
+        }
+        if let Some((aliases, path)) = root.get("aliases") {
+            for (name, token) in self.entries(aliases, &path)? {
+                let token = self.token(token, &join(&path, name))?;
+                parser.grammar.aliases.insert(name.clone(), token);
+            }
+        }
+        if let Some((tests, path)) = root.get("tests") {
+            for (i, test) in self.array(tests, &path)?.iter().enumerate() {
+                let test = Object::new(test, &format!("{}[{}]", path, i), TEST_FIELDS)?;
+                let (node, node_path) = test.require("node")?;
+                let (input, input_path) = test.require("input")?;
+                let node = self.string(node, &node_path)?;
+                let input = self.string(input, &input_path)?;
+                let mut expect = Vec::new();
+                if let Some((values, path)) = test.get("expect") {
+                    for (name, value) in self.entries(values, &path)? {
+                        let value = self.string(value, &join(&path, name))?;
+                        expect.push((self.leak(name), value));
+                    }
+                }
+                parser.grammar.add_test(node, input, expect);
+            }
+        }
+        if let Some((eof_error, path)) = root.get("eof_error") {
+            parser.parser.eof_error = self.bool(eof_error, &path)?;
+        }
+        if let Some((max, path)) = root.get("max_candidates") {
+            parser.parser.max_candidates = self.number(max, &path)?;
+        }
+        if let Some((limit, path)) = root.get("backtrack_limit") {
+            parser.parser.backtrack_limit = Some(self.number(limit, &path)?);
+        }
+        if let Some((limit, path)) = root.get("max_nodes") {
+            parser.parser.max_nodes = Some(self.number(limit, &path)?);
+        }
+        if let Some((limit, path)) = root.get("max_nesting") {
+            parser.parser.max_nesting = Some(self.number(limit, &path)?);
+        }
+        if let Some((recovery, path)) = root.get("recovery") {
+            let recovery = Object::new(recovery, &path, RECOVERY_FIELDS)?;
+            let sync = match recovery.get("sync") {
+                Some((kinds, path)) => self.sync(kinds, &path)?,
+                None => Vec::new(),
+            };
+            parser.parser.recovery = Some(Recovery { sync });
+        }
+        if let Some((names, path)) = root.get("materialize") {
+            let mut materialize = Vec::new();
+            for (i, name) in self.array(names, &path)?.iter().enumerate() {
+                materialize.push(self.string(name, &format!("{}[{}]", path, i))?);
+            }
+            parser.parser.materialize = Some(materialize);
+        }
+        Ok(parser)
+    }
+
+    /// Token kinds that end regions skipped by error recovery
+    fn sync(
+        &mut self,
+        value: &Value,
+        path: &str,
+    ) -> Result<Vec<TokenKinds<'static>>, DefinitionError> {
+        let mut sync = Vec::new();
+        for (i, kind) in self.array(value, path)?.iter().enumerate() {
+            let path = format!("{}[{}]", path, i);
+            match self.token(kind, &path)? {
+                MatchToken::Token(kind) => sync.push(kind),
+                _ => return Err(error(&path, "only token kinds can be synchronized")),
+            }
+        }
+        Ok(sync)
+    }
+
+    /// Variables written as `{ "name": "Kind" }`
+    ///
+    /// Numbers and booleans of nodes can start with a value, `{ "count": { "Number": 10 } }`
+    fn variables(
+        &mut self,
+        value: &Value,
+        path: &str,
+        mut initial: Option<&mut Vec<(&'static str, InitialValue)>>,
+    ) -> Result<Vec<(&'static str, VariableKind)>, DefinitionError> {
+        let mut variables = Vec::new();
+        for (name, kind) in self.entries(value, path)? {
+            let path = join(path, name);
+            let (kind, argument) = self.variant(kind, &path, "variable kind", VARIABLE_KINDS)?;
+            let name = self.leak(name);
+            if let Some(argument) = argument {
+                let path = join(&path, kind);
+                let value = match kind {
+                    "Number" => InitialValue::Number(self.integer(argument, &path)?),
+                    "Number64" => InitialValue::Number64(self.integer64(argument, &path)?),
+                    "Float" => InitialValue::Float(self.float(argument, &path)?),
+                    "Boolean" => InitialValue::Boolean(self.bool(argument, &path)?),
+                    _ => {
+                        return Err(error(
+                            &path,
+                            "only numbers and booleans have initial values",
+                        ))
+                    }
+                };
+                match initial.as_deref_mut() {
+                    Some(initial) => initial.push((name, value)),
+                    None => return Err(error(&path, "global variables have no initial values")),
+                }
+            }
+            let kind = match kind {
+                "Node" => VariableKind::Node,
+                "NodeList" => VariableKind::NodeList,
+                "Boolean" => VariableKind::Boolean,
+                "Number64" => VariableKind::Number64,
+                "Float" => VariableKind::Float,
+                _ => VariableKind::Number,
+            };
+            variables.push((name, kind));
+        }
+        Ok(variables)
+    }
+
+    fn version(&mut self, value: &Value, path: &str) -> Result<LanguageVersion, DefinitionError> {
+        let version = self.string(value, path)?;
+        let (major, minor) = version.split_once('.').unwrap_or((version, "0"));
+        match (major.parse(), minor.parse()) {
+            (Ok(major), Ok(minor)) => Ok(LanguageVersion::new(major, minor)),
+            _ => Err(error(
+                path,
+                format!("invalid version '{}', expected 'major.minor'", version),
+            )),
+        }
+    }
+
+    fn node(&mut self, value: &Value, path: &str) -> Result<Node<'static>, DefinitionError> {
+        let object = Object::new(value, path, NODE_FIELDS)?;
+        let (name, name_path) = object.require("name")?;
+        let mut node = Node {
+            name: self.symbol(name, &name_path)?,
+            ..Default::default()
+        };
+        if let Some((docs, path)) = object.get("docs") {
+            node.docs = Some(self.string(docs, &path)?);
+        }
+        if let Some((snippet, path)) = object.get("snippet") {
+            node.snippet = Some(self.string(snippet, &path)?);
+        }
+        if let Some((variables, path)) = object.get("variables") {
+            node.variables = self
+                .variables(variables, &path, Some(&mut node.initial))?
+                .into_iter()
+                .map(|(name, kind)| (intern::Symbol::intern(name), kind))
+                .collect();
+        }
+        if let Some((rules, path)) = object.get("rules") {
+            node.rules = self.rules(rules, &path)?;
+        }
+        if let Some((foldable, path)) = object.get("foldable") {
+            node.foldable = self.bool(foldable, &path)?;
+        }
+        if let Some((indents, path)) = object.get("indents_children") {
+            node.indents_children = self.bool(indents, &path)?;
+        }
+        if let Some((symbol, path)) = object.get("symbol") {
+            let symbol = Object::new(symbol, &path, SYMBOL_FIELDS)?;
+            let (name_from, name_path) = symbol.require("name_from")?;
+            let (kind, kind_path) = symbol.require("kind")?;
+            let (kind, _) = self.variant(kind, &kind_path, "symbol kind", SYMBOL_KINDS)?;
+            let kind = match kind {
+                "Module" => SymbolKind::Module,
+                "Namespace" => SymbolKind::Namespace,
+                "Class" => SymbolKind::Class,
+                "Struct" => SymbolKind::Struct,
+                "Enum" => SymbolKind::Enum,
+                "EnumMember" => SymbolKind::EnumMember,
+                "Interface" => SymbolKind::Interface,
+                "Function" => SymbolKind::Function,
+                "Method" => SymbolKind::Method,
+                "Constructor" => SymbolKind::Constructor,
+                "Field" => SymbolKind::Field,
+                "Property" => SymbolKind::Property,
+                "Variable" => SymbolKind::Variable,
+                "Constant" => SymbolKind::Constant,
+                "Type" => SymbolKind::Type,
+                _ => SymbolKind::Other,
+            };
+            node.symbol = Some(Symbol {
+                name_from: self.string(name_from, &name_path)?,
+                kind,
+            });
+        }
+        if let Some((since, path)) = object.get("since") {
+            node.since = Some(self.version(since, &path)?);
+        }
+        if let Some((until, path)) = object.get("until") {
+            node.until = Some(self.version(until, &path)?);
+        }
+        if let Some((record, path)) = object.get("record_children") {
+            node.record_children = self.bool(record, &path)?;
+        }
+        if let Some((record, path)) = object.get("record_tokens") {
+            node.record_tokens = self.bool(record, &path)?;
+        }
+        if let Some((allow, path)) = object.get("allow_empty") {
+            node.allow_empty = self.bool(allow, &path)?;
+        }
+        if let Some((sync, path)) = object.get("sync") {
+            node.sync = self.sync(sync, &path)?;
+        }
+        if let Some((keywords, path)) = object.get("keywords") {
+            for (i, keyword) in self.array(keywords, &path)?.iter().enumerate() {
+                let keyword = self.string(keyword, &format!("{}[{}]", path, i))?;
+                node.keywords.push(keyword);
+            }
+        }
+        Ok(node)
+    }
+
+    fn rules(&mut self, value: &Value, path: &str) -> Result<Rules<'static>, DefinitionError> {
+        self.array(value, path)?
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| self.rule(rule, &format!("{}[{}]", path, i)))
+            .collect()
+    }
+
+    /// Rules of an optional field, empty if the field is missing
+    fn rules_of(
+        &mut self,
+        object: &Object,
+        field: &str,
+    ) -> Result<Rules<'static>, DefinitionError> {
+        match object.get(field) {
+            Some((rules, path)) => self.rules(rules, &path),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn parameters_of(
+        &mut self,
+        object: &Object,
+    ) -> Result<Vec<Parameters<'static>>, DefinitionError> {
+        let Some((parameters, path)) = object.get("parameters") else {
+            return Ok(Vec::new());
+        };
+        self.array(parameters, &path)?
+            .iter()
+            .enumerate()
+            .map(|(i, parameter)| self.parameter(parameter, &format!("{}[{}]", path, i)))
+            .collect()
+    }
+
+    fn token_of(
+        &mut self,
+        object: &Object,
+        field: &str,
+    ) -> Result<MatchToken<'static>, DefinitionError> {
+        let (token, path) = object.require(field)?;
+        self.token(token, &path)
+    }
+
+    fn one_of(&mut self, object: &Object) -> Result<Vec<OneOf<'static>>, DefinitionError> {
+        let (tokens, path) = object.require("tokens")?;
+        let mut result = Vec::new();
+        for (i, one_of) in self.array(tokens, &path)?.iter().enumerate() {
+            let one_of = Object::new(one_of, &format!("{}[{}]", path, i), ONE_OF_FIELDS)?;
+            result.push(OneOf {
+                token: self.token_of(&one_of, "token")?,
+                rules: self.rules_of(&one_of, "rules")?,
+                parameters: self.parameters_of(&one_of)?,
+            });
+        }
+        Ok(result)
+    }
+
+    fn rule(&mut self, value: &Value, path: &str) -> Result<Rule<'static>, DefinitionError> {
+        let Value::Object(fields) = value else {
+            return Err(expected(path, "rule", value));
+        };
+        let kind = match fields.iter().find(|(name, _)| name == "kind") {
+            Some((_, Value::String(kind))) => kind.as_str(),
+            Some((_, kind)) => return Err(expected(&join(path, "kind"), "rule kind", kind)),
+            None => return Err(error(path, "missing field 'kind'")),
+        };
+        if !RULE_KINDS.contains(&kind) {
+            return Err(DefinitionError {
+                suggestion: suggest(kind, RULE_KINDS),
+                ..error(path, format!("unknown rule kind '{}'", kind))
+            });
+        }
+        let mut known = vec!["kind"];
+        known.extend_from_slice(rule_fields(kind));
+        let object = Object::new(value, path, &known)?;
+
+        let rule = match kind {
+            "Is" => Rule::Is {
+                origin: Origin::Definition(self.leak(path)),
+                token: self.token_of(&object, "token")?,
+                rules: self.rules_of(&object, "rules")?,
+                parameters: self.parameters_of(&object)?,
+            },
+            "Isnt" => Rule::Isnt {
+                origin: Origin::Definition(self.leak(path)),
+                token: self.token_of(&object, "token")?,
+                rules: self.rules_of(&object, "rules")?,
+                parameters: self.parameters_of(&object)?,
+            },
+            "While" => Rule::While {
+                origin: Origin::Definition(self.leak(path)),
+                token: self.token_of(&object, "token")?,
+                rules: self.rules_of(&object, "rules")?,
+                parameters: self.parameters_of(&object)?,
+            },
+            "Until" => Rule::Until {
+                origin: Origin::Definition(self.leak(path)),
+                token: self.token_of(&object, "token")?,
+                rules: self.rules_of(&object, "rules")?,
+                parameters: self.parameters_of(&object)?,
+            },
+            "IsOneOf" => Rule::IsOneOf {
+                origin: Origin::Definition(self.leak(path)),
+                tokens: self.one_of(&object)?,
+                parameters: self.parameters_of(&object)?,
+            },
+            "Maybe" => Rule::Maybe {
+                origin: Origin::Definition(self.leak(path)),
+                token: self.token_of(&object, "token")?,
+                is: self.rules_of(&object, "is")?,
+                isnt: self.rules_of(&object, "isnt")?,
+                parameters: self.parameters_of(&object)?,
+            },
+            "Peek" => Rule::Peek {
+                origin: Origin::Definition(self.leak(path)),
+                token: self.token_of(&object, "token")?,
+                is: self.rules_of(&object, "is")?,
+                isnt: self.rules_of(&object, "isnt")?,
+                parameters: self.parameters_of(&object)?,
+            },
+            "MaybeOneOf" => Rule::MaybeOneOf {
+                origin: Origin::Definition(self.leak(path)),
+                is_one_of: self.one_of(&object)?,
+                isnt: self.rules_of(&object, "isnt")?,
+            },
+            "UntilOneOf" => Rule::UntilOneOf {
+                origin: Origin::Definition(self.leak(path)),
+                tokens: self.one_of(&object)?,
+            },
+            "Loop" => Rule::Loop {
+                origin: Origin::Definition(self.leak(path)),
+                rules: self.rules_of(&object, "rules")?,
+            },
+            "IfFeature" => {
+                let (name, name_path) = object.require("name")?;
+                Rule::IfFeature {
+                    origin: Origin::Definition(self.leak(path)),
+                    name: self.string(name, &name_path)?,
+                    rules: self.rules_of(&object, "rules")?,
+                    else_rules: self.rules_of(&object, "else")?,
+                }
+            }
+            "Debug" => Rule::Debug {
+                origin: Origin::Definition(self.leak(path)),
+                target: match object.get("target") {
+                    Some((target, path)) => Some(self.var(target, &path)?),
+                    None => None,
+                },
+            },
+            _ => Rule::Command {
+                origin: Origin::Definition(self.leak(path)),
+                command: self.command(kind, &object)?,
+            },
+        };
+        Ok(rule)
+    }
+
+    fn command(
+        &mut self,
+        kind: &str,
+        object: &Object,
+    ) -> Result<Commands<'static>, DefinitionError> {
+        Ok(match kind {
+            "Compare" => {
+                let (left, right, comparison) = self.comparison(object)?;
+                Commands::Compare {
+                    left,
+                    right,
+                    comparison,
+                    rules: self.rules_of(object, "rules")?,
+                }
+            }
+            "External" => {
+                let (name, args) = self.predicate(object)?;
+                Commands::External {
+                    name,
+                    args,
+                    rules: self.rules_of(object, "rules")?,
+                }
+            }
+            "If" => {
+                let (cond, path) = object.require("cond")?;
+                Commands::If {
+                    cond: self.cond(cond, &path)?,
+                    rules: self.rules_of(object, "rules")?,
+                }
+            }
+            "Error" => {
+                let (err, path) = object.require("error")?;
+                Commands::Error {
+                    err: self.error_definition(err, &path)?,
+                }
+            }
+            "Commit" => {
+                let (set, path) = object.require("set")?;
+                Commands::Commit {
+                    set: self.bool(set, &path)?,
+                }
+            }
+            "Goto" => {
+                let (label, path) = object.require("label")?;
+                Commands::Goto {
+                    label: self.string(label, &path)?,
+                }
+            }
+            "Label" => {
+                let (name, path) = object.require("name")?;
+                Commands::Label {
+                    name: self.string(name, &path)?,
+                }
+            }
+            "Print" => {
+                let (message, path) = object.require("message")?;
+                Commands::Print {
+                    message: self.string(message, &path)?,
+                }
+            }
+            "SetNumber" => {
+                let (var, var_path) = object.require("var")?;
+                let (value, value_path) = object.require("value")?;
+                Commands::SetNumber {
+                    var: self.var(var, &var_path)?,
+                    value: self.integer(value, &value_path)?,
+                }
+            }
+            "SetNumber64" => {
+                let (var, var_path) = object.require("var")?;
+                let (value, value_path) = object.require("value")?;
+                Commands::SetNumber64 {
+                    var: self.var(var, &var_path)?,
+                    value: self.integer64(value, &value_path)?,
+                }
+            }
+            "SetFloat" => {
+                let (var, var_path) = object.require("var")?;
+                let (value, value_path) = object.require("value")?;
+                Commands::SetFloat {
+                    var: self.var(var, &var_path)?,
+                    value: self.float(value, &value_path)?,
+                }
+            }
+            "SetBool" => {
+                let (var, var_path) = object.require("var")?;
+                let (value, value_path) = object.require("value")?;
+                Commands::SetBool {
+                    var: self.var(var, &var_path)?,
+                    value: self.bool(value, &value_path)?,
+                }
+            }
+            "Return" => Commands::Return,
+            "Start" => Commands::Start,
+            _ => Commands::End,
+        })
+    }
+
+    /// `left`, `right` and `comparison` of `Compare`
+    fn comparison(
+        &mut self,
+        object: &Object,
+    ) -> Result<(VarKind<'static>, VarKind<'static>, Comparison), DefinitionError> {
+        let (left, left_path) = object.require("left")?;
+        let (right, right_path) = object.require("right")?;
+        let (comparison, comparison_path) = object.require("comparison")?;
+        let (comparison, _) =
+            self.variant(comparison, &comparison_path, "comparison", COMPARISONS)?;
+        Ok((
+            self.var(left, &left_path)?,
+            self.var(right, &right_path)?,
+            match comparison {
+                "Equal" => Comparison::Equal,
+                "NotEqual" => Comparison::NotEqual,
+                "GreaterThan" => Comparison::GreaterThan,
+                "LessThan" => Comparison::LessThan,
+                "GreaterThanOrEqual" => Comparison::GreaterThanOrEqual,
+                _ => Comparison::LessThanOrEqual,
+            },
+        ))
+    }
+
+    /// `name` and `args` of `External`
+    fn predicate(
+        &mut self,
+        object: &Object,
+    ) -> Result<(&'static str, Vec<&'static str>), DefinitionError> {
+        let (name, path) = object.require("name")?;
+        let mut args = Vec::new();
+        if let Some((list, path)) = object.get("args") {
+            for (i, arg) in self.array(list, &path)?.iter().enumerate() {
+                args.push(self.string(arg, &format!("{}[{}]", path, i))?);
+            }
+        }
+        Ok((self.string(name, &path)?, args))
+    }
+
+    fn cond(&mut self, value: &Value, path: &str) -> Result<Cond<'static>, DefinitionError> {
+        let (name, argument) = self.variant(value, path, "condition", CONDITIONS)?;
+        let (argument, path) = self.argument(argument, path, name)?;
+        Ok(match name {
+            "Compare" => {
+                let object = Object::new(argument, &path, &["left", "right", "comparison"])?;
+                let (left, right, comparison) = self.comparison(&object)?;
+                Cond::Compare {
+                    left,
+                    right,
+                    comparison,
+                }
+            }
+            "External" => {
+                let object = Object::new(argument, &path, &["name", "args"])?;
+                let (name, args) = self.predicate(&object)?;
+                Cond::External { name, args }
+            }
+            "Not" => Cond::Not(Box::new(self.cond(argument, &path)?)),
+            _ => {
+                let mut conds = Vec::new();
+                for (i, cond) in self.array(argument, &path)?.iter().enumerate() {
+                    conds.push(self.cond(cond, &format!("{}[{}]", path, i))?);
+                }
+                match name {
+                    "And" => Cond::And(conds),
+                    _ => Cond::Or(conds),
+                }
+            }
+        })
+    }
+
+    /// Error definitions are referenced by rules, so they are leaked like the strings
+    fn error_definition(
+        &mut self,
+        value: &Value,
+        path: &str,
+    ) -> Result<&'static ErrorDefinition, DefinitionError> {
+        let object = Object::new(value, path, ERROR_FIELDS)?;
+        let (header, header_path) = object.require("header")?;
+        let (code, code_path) = object.require("code")?;
+        let (msg, msg_path) = object.require("msg")?;
+        Ok(Box::leak(Box::new(ErrorDefinition {
+            header: self.string(header, &header_path)?,
+            code: self.string(code, &code_path)?,
+            msg: self.string(msg, &msg_path)?,
+        })))
+    }
+
+    fn token(&mut self, value: &Value, path: &str) -> Result<MatchToken<'static>, DefinitionError> {
+        let (name, argument) = self.variant(value, path, "token", TOKENS)?;
+        let kind = match name {
+            "Any" => return Ok(MatchToken::Any),
+            "Text" => TokenKinds::Text,
+            "Whitespace" => TokenKinds::Whitespace,
+            "Eol" => TokenKinds::Control(ControlTokenKind::Eol),
+            "Eof" => TokenKinds::Control(ControlTokenKind::Eof),
+            "Error" => TokenKinds::Error,
+            "DocComment" => TokenKinds::DocComment,
+            "Char" => {
+                let (argument, path) = self.argument(argument, path, name)?;
+                return Ok(MatchToken::Char(self.char(argument, &path)?));
+            }
+            "CharClass" => {
+                let (argument, path) = self.argument(argument, path, name)?;
+                return Ok(MatchToken::CharClass(self.char_class(argument, &path)?));
+            }
+            _ => {
+                let (argument, path) = self.argument(argument, path, name)?;
+                let argument = self.string(argument, &path)?;
+                return Ok(match name {
+                    "Token" => MatchToken::Token(TokenKinds::Token(argument)),
+                    "Complex" => MatchToken::Token(TokenKinds::Complex(argument)),
+                    "Word" => MatchToken::Word(argument),
+                    "Node" => MatchToken::Node(intern::Symbol::intern(argument)),
+                    "Enumerator" => MatchToken::Enumerator(argument),
+                    _ => MatchToken::Alias(argument),
+                });
+            }
+        };
+        Ok(MatchToken::Token(kind))
+    }
+
+    fn char(&self, value: &Value, path: &str) -> Result<char, DefinitionError> {
+        if let Value::String(string) = value {
+            let mut chars = string.chars();
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                return Ok(c);
+            }
+        }
+        Err(expected(path, "string of one character", value))
+    }
+
+    /// Class written as `"Alphabetic"`, `{ "Not": "x" }` or `{ "Range": ["a", "z"] }`
+    fn char_class(&self, value: &Value, path: &str) -> Result<CharClass, DefinitionError> {
+        let (name, argument) = self.variant(value, path, "character class", CHAR_CLASSES)?;
+        Ok(match name {
+            "Alphabetic" => CharClass::Alphabetic,
+            "Numeric" => CharClass::Numeric,
+            "Alphanumeric" => CharClass::Alphanumeric,
+            "Whitespace" => CharClass::Whitespace,
+            "Punctuation" => CharClass::Punctuation,
+            "Not" => {
+                let (argument, path) = self.argument(argument, path, name)?;
+                CharClass::Not(self.char(argument, &path)?)
+            }
+            _ => {
+                let (argument, path) = self.argument(argument, path, name)?;
+                match self.array(argument, &path)? {
+                    [from, to] => CharClass::Range(
+                        self.char(from, &format!("{}[0]", path))?,
+                        self.char(to, &format!("{}[1]", path))?,
+                    ),
+                    _ => return Err(expected(&path, "array of two characters", argument)),
+                }
+            }
+        })
+    }
+
+    /// Variable written as `"name"` for a local one or `{ "Global": "name" }`
+    fn var(&mut self, value: &Value, path: &str) -> Result<VarKind<'static>, DefinitionError> {
+        if let Value::String(name) = value {
+            return Ok(VarKind::Local(self.leak(name)));
+        }
+        let (kind, argument) = self.variant(value, path, "variable", VARIABLES)?;
+        let (name, path) = self.argument(argument, path, kind)?;
+        let name = self.string(name, &path)?;
+        Ok(match kind {
+            "Local" => VarKind::Local(name),
+            _ => VarKind::Global(name),
+        })
+    }
+
+    /// Two values of a variant written as an array
+    fn pair<'v>(
+        &self,
+        argument: Option<&'v Value>,
+        path: &str,
+        name: &str,
+    ) -> Result<(&'v Value, &'v Value, String), DefinitionError> {
+        let (value, path) = self.argument(argument, path, name)?;
+        match self.array(value, &path)? {
+            [first, second] => Ok((first, second, path)),
+            _ => Err(error(&path, format!("'{}' needs two values", name))),
+        }
+    }
+
+    fn parameter(
+        &mut self,
+        value: &Value,
+        path: &str,
+    ) -> Result<Parameters<'static>, DefinitionError> {
+        let (name, argument) = self.variant(value, path, "parameter", PARAMETERS)?;
+        let parameter =
+            match name {
+                "Return" => Parameters::Return,
+                "Cut" => Parameters::Cut,
+                "NodeStart" => Parameters::NodeStart,
+                "NodeEnd" => Parameters::NodeEnd,
+                "Split" => Parameters::Split,
+                "Important" => Parameters::Important,
+                "AttachDocs" => Parameters::AttachDocs,
+                "EnterScope" => Parameters::EnterScope,
+                "ExitScope" => Parameters::ExitScope,
+                "Debug" => match argument {
+                    None | Some(Value::Null) => Parameters::Debug(None),
+                    Some(var) => Parameters::Debug(Some(self.var(var, &join(path, name))?)),
+                },
+                "SetField" => {
+                    let (var, field, path) = self.pair(argument, path, name)?;
+                    Parameters::SetField(self.string(var, &path)?, self.string(field, &path)?)
+                }
+                "CloneValue" => {
+                    let (from, to, path) = self.pair(argument, path, name)?;
+                    Parameters::CloneValue(self.var(from, &path)?, self.var(to, &path)?)
+                }
+                _ => {
+                    let (argument, path) = self.argument(argument, path, name)?;
+                    match name {
+                        "Set" => Parameters::Set(self.var(argument, &path)?),
+                        "Increment" => Parameters::Increment(self.var(argument, &path)?),
+                        "Decrement" => Parameters::Decrement(self.var(argument, &path)?),
+                        "True" => Parameters::True(self.var(argument, &path)?),
+                        "False" => Parameters::False(self.var(argument, &path)?),
+                        "Declare" => Parameters::Declare(self.var(argument, &path)?),
+                        "Use" => Parameters::Use(self.var(argument, &path)?),
+                        "Print" => Parameters::Print(self.string(argument, &path)?),
+                        "Goto" => Parameters::Goto(self.string(argument, &path)?),
+                        "Hint" => Parameters::Hint(self.string(argument, &path)?),
+                        "Commit" => Parameters::Commit(self.bool(argument, &path)?),
+                        "Break" => Parameters::Break(self.number(argument, &path)?),
+                        "Back" => {
+                            let steps = self.number(argument, &path)?;
+                            Parameters::Back(u8::try_from(steps).map_err(|_| {
+                                error(&path, "can not go back more than 255 tokens")
+                            })?)
+                        }
+                        _ => Parameters::Fail(self.error_definition(argument, &path)?),
+                    }
+                }
+            };
+        Ok(parameter)
+    }
+}
+
+/// Fields of an object being written, empty values are left out
+#[derive(Default)]
+struct Fields(Vec<(String, Value)>);
+
+impl Fields {
+    fn with(mut self, name: &str, value: Value) -> Self {
+        self.0.push((name.to_string(), value));
+        self
+    }
+
+    fn string(self, name: &str, value: Option<&str>) -> Self {
+        match value {
+            Some(value) => self.with(name, Value::String(value.to_string())),
+            None => self,
+        }
+    }
+
+    fn flag(self, name: &str, value: bool) -> Self {
+        match value {
+            true => self.with(name, Value::Bool(true)),
+            false => self,
+        }
+    }
+
+    fn array(self, name: &str, values: Vec<Value>) -> Self {
+        match values.is_empty() {
+            true => self,
+            false => self.with(name, Value::Array(values)),
+        }
+    }
+
+    fn object(self, name: &str, fields: Fields) -> Self {
+        match fields.0.is_empty() {
+            true => self,
+            false => self.with(name, fields.build()),
+        }
+    }
+
+    fn rules(self, name: &str, rules: &[Rule]) -> Self {
+        self.array(name, rules.iter().map(write_rule).collect())
+    }
+
+    fn parameters(self, parameters: &[Parameters]) -> Self {
+        self.array(
+            "parameters",
+            parameters.iter().map(write_parameter).collect(),
+        )
+    }
+
+    fn build(self) -> Value {
+        Value::Object(self.0)
+    }
+}
+
+impl Value {
+    /// Compact JSON with the keys of every object sorted
+    ///
+    /// Numbers without a fraction are written as integers, numbers that are not finite as `null`
+    pub fn to_canonical_json(&self) -> String {
+        let mut out = String::new();
+        write_json(self, &mut out);
+        out
+    }
+}
+
+impl Grammar<'_> {
+    /// Grammar in the definition format, see the module documentation
+    ///
+    /// Nodes are sorted by name, origins of the rules are left out and so are fields
+    /// with their default value. The definition has no `tokens` and no options
+    /// of the parser, these are not part of the grammar.
+    /// Nodes of `Grammar::base` are included.
+    pub fn to_definition(&self) -> Value {
+        let mut nodes = self.all_nodes();
+        nodes.sort_by_key(|node| node.name);
+        let mut fields = Fields::default()
+            .with(
+                "nodes",
+                Value::Array(nodes.into_iter().map(write_node).collect()),
+            )
+            .array("ignored", self.ignored.iter().map(write_kind).collect());
+        if !self.eof {
+            fields = fields.with("eof", Value::Bool(false));
+        }
+        fields
+            .string(
+                "trivia",
+                self.trivia.map(|trivia| format!("{:?}", trivia)).as_deref(),
+            )
+            .array(
+                "brackets",
+                self.brackets
+                    .iter()
+                    .map(|(open, close)| Value::Array(vec![write_kind(open), write_kind(close)]))
+                    .collect(),
+            )
+            .object(
+                "features",
+                self.features
+                    .iter()
+                    .fold(Fields::default(), |fields, (name, enabled)| {
+                        fields.with(name, Value::Bool(*enabled))
+                    }),
+            )
+            .object("globals", write_variables(&self.globals, &[]))
+            .object(
+                "enumerators",
+                self.enumerators
+                    .iter()
+                    .fold(Fields::default(), |fields, (name, enumerator)| {
+                        fields.with(
+                            name,
+                            Value::Array(enumerator.values.iter().map(write_token).collect()),
+                        )
+                    }),
+            )
+            .object(
+                "aliases",
+                self.aliases
+                    .iter()
+                    .fold(Fields::default(), |fields, (name, token)| {
+                        fields.with(name, write_token(token))
+                    }),
+            )
+            .array(
+                "tests",
+                self.tests
+                    .iter()
+                    .map(|test| {
+                        Fields::default()
+                            .string("node", Some(test.node))
+                            .string("input", Some(test.input))
+                            .object(
+                                "expect",
+                                test.expect
+                                    .iter()
+                                    .fold(Fields::default(), |fields, (name, text)| {
+                                        fields.string(name, Some(text))
+                                    }),
+                            )
+                            .build()
+                    })
+                    .collect(),
+            )
+            .build()
+    }
+
+    /// `Grammar::to_definition` written by `Value::to_canonical_json`
+    ///
+    /// Grammars with the same structure give the same text on every machine,
+    /// so the text can be diffed, hashed or used as a cache key
+    pub fn to_canonical_json(&self) -> String {
+        self.to_definition().to_canonical_json()
+    }
+
+    /// Hash of `Grammar::to_canonical_json` with 128 bits
+    ///
+    /// Stays the same across machines and runs as long as the structure of the grammar
+    /// does, use `crate::Parser::fingerprint` to include the options of the lexer
+    pub fn fingerprint(&self) -> u128 {
+        CompiledGrammar::fingerprint(&[self.to_canonical_json().as_bytes()])
+    }
+}
+
+fn write_json(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) if n.is_finite() => out.push_str(&format!("{}", n)),
+        Value::Number(_) => out.push_str("null"),
+        Value::String(s) => write_json_string(s, out),
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json(value, out);
+            }
+            out.push(']');
+        }
+        Value::Object(entries) => {
+            let mut entries = entries.iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_string(key, out);
+                out.push(':');
+                write_json(value, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Variant written as `"Name"` or `{ "Name": argument }`
+fn write_variant(name: &str, argument: Option<Value>) -> Value {
+    match argument {
+        Some(argument) => Value::Object(vec![(name.to_string(), argument)]),
+        None => Value::String(name.to_string()),
+    }
+}
+
+fn write_string(value: &str) -> Value {
+    Value::String(value.to_string())
+}
+
+fn write_kind(kind: &TokenKinds) -> Value {
+    write_token(&MatchToken::Token(*kind))
+}
+
+fn write_token(token: &MatchToken) -> Value {
+    match token {
+        MatchToken::Token(kind) => match kind {
+            TokenKinds::Token(token) => write_variant("Token", Some(write_string(token))),
+            TokenKinds::Complex(token) => write_variant("Complex", Some(write_string(token))),
+            TokenKinds::Text => write_variant("Text", None),
+            TokenKinds::Whitespace => write_variant("Whitespace", None),
+            TokenKinds::Control(ControlTokenKind::Eol) => write_variant("Eol", None),
+            TokenKinds::Control(ControlTokenKind::Eof) => write_variant("Eof", None),
+            TokenKinds::Error => write_variant("Error", None),
+            TokenKinds::DocComment => write_variant("DocComment", None),
+        },
+        MatchToken::Node(name) => write_variant("Node", Some(write_string(name.as_str()))),
+        MatchToken::Word(word) => write_variant("Word", Some(write_string(word))),
+        MatchToken::Enumerator(name) => write_variant("Enumerator", Some(write_string(name))),
+        MatchToken::Alias(name) => write_variant("Alias", Some(write_string(name))),
+        MatchToken::Any => write_variant("Any", None),
+        MatchToken::Char(c) => write_variant("Char", Some(Value::String(c.to_string()))),
+        MatchToken::CharClass(class) => {
+            let class = match class {
+                CharClass::Range(from, to) => write_variant(
+                    "Range",
+                    Some(Value::Array(vec![
+                        Value::String(from.to_string()),
+                        Value::String(to.to_string()),
+                    ])),
+                ),
+                CharClass::Not(c) => write_variant("Not", Some(Value::String(c.to_string()))),
+                class => write_variant(&format!("{:?}", class), None),
+            };
+            write_variant("CharClass", Some(class))
+        }
+    }
+}
+
+/// Variable written as `"name"` for a local one or `{ "Global": "name" }`
+fn write_var(var: &VarKind) -> Value {
+    match var {
+        VarKind::Local(name) => write_string(name),
+        VarKind::Global(name) => write_variant("Global", Some(write_string(name))),
+    }
+}
+
+fn write_variables<N: AsRef<str>>(
+    variables: &[(N, VariableKind)],
+    initial: &[(&str, InitialValue)],
+) -> Fields {
+    variables
+        .iter()
+        .fold(Fields::default(), |fields, (name, kind)| {
+            let value = initial
+                .iter()
+                .find(|(initial, _)| *initial == name.as_ref())
+                .map(|(_, value)| match value {
+                    InitialValue::Number(n) => Value::Number(*n as f64),
+                    InitialValue::Number64(n) => Value::Number(*n as f64),
+                    InitialValue::Float(n) => Value::Number(*n),
+                    InitialValue::Boolean(b) => Value::Bool(*b),
+                });
+            fields.with(name.as_ref(), write_variant(&format!("{:?}", kind), value))
+        })
+}
+
+fn write_version(version: &LanguageVersion) -> Value {
+    Value::String(format!("{}.{}", version.major, version.minor))
+}
+
+fn write_node(node: &Node) -> Value {
+    let mut fields = Fields::default()
+        .string("name", Some(node.name.as_str()))
+        .string("docs", node.docs)
+        .string("snippet", node.snippet)
+        .object("variables", write_variables(&node.variables, &node.initial))
+        .rules("rules", &node.rules)
+        .flag("foldable", node.foldable)
+        .flag("indents_children", node.indents_children);
+    if let Some(symbol) = &node.symbol {
+        fields = fields.with(
+            "symbol",
+            Fields::default()
+                .string("name_from", Some(symbol.name_from))
+                .string("kind", Some(&format!("{:?}", symbol.kind)))
+                .build(),
+        );
+    }
+    if let Some(since) = &node.since {
+        fields = fields.with("since", write_version(since));
+    }
+    if let Some(until) = &node.until {
+        fields = fields.with("until", write_version(until));
+    }
+    fields
+        .flag("record_children", node.record_children)
+        .flag("record_tokens", node.record_tokens)
+        .flag("allow_empty", node.allow_empty)
+        .array("sync", node.sync.iter().map(write_kind).collect())
+        .array(
+            "keywords",
+            node.keywords
+                .iter()
+                .map(|keyword| write_string(keyword))
+                .collect(),
+        )
+        .build()
+}
+
+fn write_one_of(options: &[OneOf]) -> Value {
+    Value::Array(
+        options
+            .iter()
+            .map(|option| {
+                Fields::default()
+                    .with("token", write_token(&option.token))
+                    .rules("rules", &option.rules)
+                    .parameters(&option.parameters)
+                    .build()
+            })
+            .collect(),
+    )
+}
+
+fn write_error(err: &ErrorDefinition) -> Value {
+    Fields::default()
+        .string("header", Some(err.header))
+        .string("code", Some(err.code))
+        .string("msg", Some(err.msg))
+        .build()
+}
+
+fn write_rule(rule: &Rule) -> Value {
+    let fields = |kind: &str| Fields::default().string("kind", Some(kind));
+    match rule {
+        Rule::Is {
+            token,
+            rules,
+            parameters,
+            ..
+        } => fields("Is")
+            .with("token", write_token(token))
+            .rules("rules", rules)
+            .parameters(parameters),
+        Rule::Isnt {
+            token,
+            rules,
+            parameters,
+            ..
+        } => fields("Isnt")
+            .with("token", write_token(token))
+            .rules("rules", rules)
+            .parameters(parameters),
+        Rule::While {
+            token,
+            rules,
+            parameters,
+            ..
+        } => fields("While")
+            .with("token", write_token(token))
+            .rules("rules", rules)
+            .parameters(parameters),
+        Rule::Until {
+            token,
+            rules,
+            parameters,
+            ..
+        } => fields("Until")
+            .with("token", write_token(token))
+            .rules("rules", rules)
+            .parameters(parameters),
+        Rule::IsOneOf {
+            tokens, parameters, ..
+        } => fields("IsOneOf")
+            .with("tokens", write_one_of(tokens))
+            .parameters(parameters),
+        Rule::Maybe {
+            token,
+            is,
+            isnt,
+            parameters,
+            ..
+        } => fields("Maybe")
+            .with("token", write_token(token))
+            .rules("is", is)
+            .rules("isnt", isnt)
+            .parameters(parameters),
+        Rule::Peek {
+            token,
+            is,
+            isnt,
+            parameters,
+            ..
+        } => fields("Peek")
+            .with("token", write_token(token))
+            .rules("is", is)
+            .rules("isnt", isnt)
+            .parameters(parameters),
+        Rule::MaybeOneOf {
+            is_one_of, isnt, ..
+        } => fields("MaybeOneOf")
+            .with("tokens", write_one_of(is_one_of))
+            .rules("isnt", isnt),
+        Rule::UntilOneOf { tokens, .. } => {
+            fields("UntilOneOf").with("tokens", write_one_of(tokens))
+        }
+        Rule::Loop { rules, .. } => fields("Loop").rules("rules", rules),
+        Rule::IfFeature {
+            name,
+            rules,
+            else_rules,
+            ..
+        } => fields("IfFeature")
+            .string("name", Some(name))
+            .rules("rules", rules)
+            .rules("else", else_rules),
+        Rule::Debug { target, .. } => match target {
+            Some(target) => fields("Debug").with("target", write_var(target)),
+            None => fields("Debug"),
+        },
+        Rule::Command { command, .. } => match command {
+            Commands::Compare {
+                left,
+                right,
+                comparison,
+                rules,
+            } => fields("Compare")
+                .with("left", write_var(left))
+                .with("right", write_var(right))
+                .string("comparison", Some(&format!("{:?}", comparison)))
+                .rules("rules", rules),
+            Commands::External { name, args, rules } => fields("External")
+                .string("name", Some(name))
+                .array("args", args.iter().map(|arg| write_string(arg)).collect())
+                .rules("rules", rules),
+            Commands::If { cond, rules } => fields("If")
+                .with("cond", write_cond(cond))
+                .rules("rules", rules),
+            Commands::Error { err } => fields("Error").with("error", write_error(err)),
+            Commands::Commit { set } => fields("Commit").with("set", Value::Bool(*set)),
+            Commands::Goto { label } => fields("Goto").string("label", Some(label)),
+            Commands::Label { name } => fields("Label").string("name", Some(name)),
+            Commands::Print { message } => fields("Print").string("message", Some(message)),
+            Commands::Return => fields("Return"),
+            Commands::Start => fields("Start"),
+            Commands::End => fields("End"),
+            Commands::SetNumber { var, value } => fields("SetNumber")
+                .with("var", write_var(var))
+                .with("value", Value::Number(*value as f64)),
+            Commands::SetNumber64 { var, value } => fields("SetNumber64")
+                .with("var", write_var(var))
+                .with("value", Value::Number(*value as f64)),
+            Commands::SetFloat { var, value } => fields("SetFloat")
+                .with("var", write_var(var))
+                .with("value", Value::Number(*value)),
+            Commands::SetBool { var, value } => fields("SetBool")
+                .with("var", write_var(var))
+                .with("value", Value::Bool(*value)),
+        },
+    }
+    .build()
+}
+
+fn write_cond(cond: &Cond) -> Value {
+    let conds = |conds: &[Cond]| Value::Array(conds.iter().map(write_cond).collect());
+    let (name, argument) = match cond {
+        Cond::Compare {
+            left,
+            right,
+            comparison,
+        } => (
+            "Compare",
+            Fields::default()
+                .with("left", write_var(left))
+                .with("right", write_var(right))
+                .string("comparison", Some(&format!("{:?}", comparison)))
+                .build(),
+        ),
+        Cond::External { name, args } => (
+            "External",
+            Fields::default()
+                .string("name", Some(name))
+                .array("args", args.iter().map(|arg| write_string(arg)).collect())
+                .build(),
+        ),
+        Cond::And(list) => ("And", conds(list)),
+        Cond::Or(list) => ("Or", conds(list)),
+        Cond::Not(cond) => ("Not", write_cond(cond)),
+    };
+    write_variant(name, Some(argument))
+}
+
+fn write_parameter(parameter: &Parameters) -> Value {
+    match parameter {
+        Parameters::Set(var) => write_variant("Set", Some(write_var(var))),
+        Parameters::SetField(var, field) => write_variant(
+            "SetField",
+            Some(Value::Array(vec![write_string(var), write_string(field)])),
+        ),
+        Parameters::Increment(var) => write_variant("Increment", Some(write_var(var))),
+        Parameters::Decrement(var) => write_variant("Decrement", Some(write_var(var))),
+        Parameters::True(var) => write_variant("True", Some(write_var(var))),
+        Parameters::False(var) => write_variant("False", Some(write_var(var))),
+        Parameters::CloneValue(from, to) => write_variant(
+            "CloneValue",
+            Some(Value::Array(vec![write_var(from), write_var(to)])),
+        ),
+        Parameters::Print(message) => write_variant("Print", Some(write_string(message))),
+        Parameters::Debug(var) => write_variant("Debug", var.as_ref().map(write_var)),
+        Parameters::Back(steps) => write_variant("Back", Some(Value::Number(*steps as f64))),
+        Parameters::Return => write_variant("Return", None),
+        Parameters::Break(depth) => write_variant("Break", Some(Value::Number(*depth as f64))),
+        Parameters::Commit(set) => write_variant("Commit", Some(Value::Bool(*set))),
+        Parameters::Cut => write_variant("Cut", None),
+        Parameters::Goto(label) => write_variant("Goto", Some(write_string(label))),
+        Parameters::NodeStart => write_variant("NodeStart", None),
+        Parameters::NodeEnd => write_variant("NodeEnd", None),
+        Parameters::Split => write_variant("Split", None),
+        Parameters::Hint(hint) => write_variant("Hint", Some(write_string(hint))),
+        Parameters::Important => write_variant("Important", None),
+        Parameters::AttachDocs => write_variant("AttachDocs", None),
+        Parameters::Declare(var) => write_variant("Declare", Some(write_var(var))),
+        Parameters::Use(var) => write_variant("Use", Some(write_var(var))),
+        Parameters::EnterScope => write_variant("EnterScope", None),
+        Parameters::ExitScope => write_variant("ExitScope", None),
+        Parameters::Fail(err) => write_variant("Fail", Some(write_error(err))),
+    }
+}
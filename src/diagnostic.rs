@@ -0,0 +1,763 @@
+//! Structured diagnostics
+//!
+//! Every error of ruparse can be converted into a `Diagnostic` which owns all of its data.
+//! This allows applications to merge ruparse diagnostics with their own
+//! and render them in one report (see `format`).
+
+use core::ops::Range;
+
+use crate::{
+    grammar::validator::{
+        ValidationError, ValidationResult, ValidationWarning, ValidationWarnings,
+    },
+    lexer::PreprocessorError,
+    parser::{ParseError, ParseErrors, ParseResult},
+};
+
+// Choose between std and alloc
+cfg_if::cfg_if! {
+    if #[cfg(feature = "std")] {
+        extern crate std;
+        use std::prelude::v1::*;
+    } else {
+        extern crate alloc;
+        use alloc::string::*;
+        use alloc::vec::*;
+        use alloc::vec;
+        use alloc::format;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Kind of the diagnostic displayed in front of the code, for example "syntax error"
+    pub name: String,
+    pub code: String,
+    pub title: String,
+    /// Detailed message
+    ///
+    /// Displayed as the label of the primary span if there is one
+    pub message: String,
+    /// Byte ranges of the source text
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub kind: LabelKind,
+    pub span: Range<usize>,
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelKind {
+    /// Location of the diagnostic
+    Primary,
+    /// Related location, kept in view together with the primary label
+    Secondary,
+    /// Large related region that is cut off if it does not fit in view
+    Region,
+    /// Makes the text visible without highlighting it
+    Visible,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: impl Into<String>, title: impl Into<String>) -> Self {
+        Self {
+            severity,
+            name: match severity {
+                Severity::Error => "error".to_string(),
+                Severity::Warning => "warning".to_string(),
+            },
+            code: code.into(),
+            title: title.into(),
+            message: String::new(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn label(mut self, kind: LabelKind, span: Range<usize>, text: Option<String>) -> Self {
+        self.labels.push(Label { kind, span, text });
+        self
+    }
+
+    /// Label that the rendered text is centered around
+    pub fn primary(&self) -> Option<&Label> {
+        self.labels.iter().find(|l| l.kind == LabelKind::Primary)
+    }
+}
+
+impl<'a> ParseError<'a> {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let (_, header) = self.kind.id_and_header();
+        let title = match &self.node {
+            Some(n) => format!("{header} while parsing {}", n.name),
+            None => header.to_string(),
+        };
+        let mut diagnostic = Diagnostic {
+            name: "syntax error".to_string(),
+            message: format!("{:?}", self.kind),
+            ..Diagnostic::new(Severity::Error, self.kind.code(), title)
+        };
+        let span = self.span.clone();
+        diagnostic = diagnostic.label(LabelKind::Primary, span, None);
+        match (&self.kind, &self.node) {
+            (ParseErrors::MissingEof { leftover, .. }, Some(n)) => {
+                let parsed = n.first_string_idx..n.last_string_idx.max(n.first_string_idx);
+                diagnostic = diagnostic
+                    .label(
+                        LabelKind::Region,
+                        parsed,
+                        Some(format!("parsed as {}", n.name)),
+                    )
+                    .label(
+                        LabelKind::Region,
+                        leftover.clone(),
+                        Some("not parsed".to_string()),
+                    );
+            }
+            (ParseErrors::ExpectedOneOf { candidates, .. }, _) if !candidates.is_empty() => {
+                for c in candidates {
+                    let span = c.span.clone();
+                    let name = c.node.as_ref().map(|n| n.name.as_str()).unwrap_or("token");
+                    diagnostic = diagnostic.label(
+                        LabelKind::Secondary,
+                        span,
+                        Some(format!("as {name}: {:?}", c.kind)),
+                    );
+                }
+                diagnostic.notes.push(format!(
+                    "{} candidate interpretations failed later in the text",
+                    candidates.len()
+                ));
+            }
+            (_, Some(n)) => {
+                diagnostic = diagnostic.label(
+                    LabelKind::Visible,
+                    n.first_string_idx..n.first_string_idx + 1,
+                    None,
+                );
+            }
+            _ => (),
+        }
+        if let Some(origin) = self.origin {
+            diagnostic.notes.push(format!("rule defined at {origin}"));
+        }
+        match (self.hint, self.node.as_ref().and_then(|n| n.docs)) {
+            (Some(hint), _) => diagnostic.help = Some(hint.to_string()),
+            (None, Some(docs)) => diagnostic.notes.push(docs.to_string()),
+            _ => (),
+        }
+        diagnostic
+    }
+}
+
+impl<'a> ParseResult<'a> {
+    /// Errors the parser recovered from followed by a warning for text left unparsed
+    ///
+    /// The warning is only reported if `Grammar::eof` is disabled, see `ParseResult::remaining`
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<_> = self.errors.iter().map(|e| e.diagnostic()).collect();
+        if let Some(remaining) = &self.remaining {
+            let parsed = self.entry.first_string_idx..remaining.span.start;
+            let mut diagnostic = Diagnostic {
+                name: "syntax warning".to_string(),
+                message: format!("{} tokens were not parsed", remaining.tokens),
+                help: Some("Enable `eof` in the grammar to reject unparsed text".to_string()),
+                ..Diagnostic::new(Severity::Warning, "W0200", "Text not parsed")
+            }
+            .label(LabelKind::Primary, remaining.span.clone(), None);
+            if parsed.start < parsed.end {
+                diagnostic = diagnostic.label(
+                    LabelKind::Region,
+                    parsed,
+                    Some(format!("parsed as {}", self.entry.name)),
+                );
+            }
+            diagnostics.push(diagnostic);
+        }
+        diagnostics
+    }
+}
+
+impl PreprocessorError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let span = self.span.clone();
+        Diagnostic {
+            name: "lexing error".to_string(),
+            message: format!("{:?}", self.err.msg),
+            ..Diagnostic::new(Severity::Error, self.err.code, self.err.header)
+        }
+        .label(LabelKind::Primary, span, None)
+    }
+}
+
+impl<'a> ValidationError<'a> {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let (_, header) = self.kind.id_and_header();
+        Diagnostic {
+            name: "parser erorr".to_string(),
+            message: self.to_string(),
+            ..Diagnostic::new(Severity::Error, self.kind.code(), header)
+        }
+    }
+}
+
+impl<'a> ValidationWarning<'a> {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let (_, header) = self.kind.id_and_header();
+        Diagnostic {
+            name: "parser warning".to_string(),
+            message: self.to_string(),
+            help: match &self.kind {
+                ValidationWarnings::Slow(lint) => Some(lint.suggestion().to_string()),
+                _ => None,
+            },
+            ..Diagnostic::new(Severity::Warning, self.kind.code(), header)
+        }
+    }
+}
+
+impl<'a> ValidationResult<'a> {
+    /// Warnings followed by errors
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.warnings
+            .iter()
+            .map(|w| w.diagnostic())
+            .chain(self.errors.iter().map(|e| e.diagnostic()))
+            .collect()
+    }
+}
+
+/// Bounds the number of diagnostics in a report
+///
+/// The kept diagnostics only depend on the diagnostics themselves and the strategy,
+/// not on the order they were collected in, so reports are reproducible across runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiagnosticLimit {
+    pub max_errors: usize,
+    pub strategy: LimitStrategy,
+}
+
+/// Decides which diagnostics are kept by `DiagnosticLimit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitStrategy {
+    /// Keep the diagnostics that come first in the text
+    KeepFirst,
+    /// Keep errors before warnings, each in the order of the text
+    KeepWorst,
+    /// Keep a pseudo random sample, the same seed always keeps the same diagnostics
+    Sample(u64),
+}
+
+impl Default for DiagnosticLimit {
+    fn default() -> Self {
+        Self {
+            max_errors: 100,
+            strategy: LimitStrategy::KeepFirst,
+        }
+    }
+}
+
+impl DiagnosticLimit {
+    pub fn new(max_errors: usize, strategy: LimitStrategy) -> Self {
+        Self {
+            max_errors,
+            strategy,
+        }
+    }
+
+    /// Drops diagnostics over the limit and sorts the rest in the order of the text
+    ///
+    /// Returns the number of dropped diagnostics
+    pub fn apply(&self, diagnostics: &mut Vec<Diagnostic>) -> usize {
+        diagnostics.sort_by(|a, b| Self::position(a).cmp(&Self::position(b)));
+        let dropped = diagnostics.len().saturating_sub(self.max_errors);
+        if dropped == 0 {
+            return 0;
+        }
+        match self.strategy {
+            LimitStrategy::KeepFirst => diagnostics.truncate(self.max_errors),
+            LimitStrategy::KeepWorst => {
+                // stable, so every severity stays in the order of the text
+                diagnostics.sort_by_key(|d| d.severity);
+                diagnostics.truncate(self.max_errors);
+                diagnostics.sort_by(|a, b| Self::position(a).cmp(&Self::position(b)));
+            }
+            LimitStrategy::Sample(seed) => {
+                // partial Fisher-Yates shuffle driven by xorshift
+                let mut state = seed | 1;
+                for i in 0..self.max_errors {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    let j = i + (state % (diagnostics.len() - i) as u64) as usize;
+                    diagnostics.swap(i, j);
+                }
+                diagnostics.truncate(self.max_errors);
+                diagnostics.sort_by(|a, b| Self::position(a).cmp(&Self::position(b)));
+            }
+        }
+        dropped
+    }
+
+    /// Total order of diagnostics, diagnostics without a primary label come last
+    fn position(diagnostic: &Diagnostic) -> (usize, usize, &str, &str, &str) {
+        let span = diagnostic
+            .primary()
+            .map(|l| l.span.clone())
+            .unwrap_or(usize::MAX..usize::MAX);
+        (
+            span.start,
+            span.end,
+            &diagnostic.code,
+            &diagnostic.title,
+            &diagnostic.message,
+        )
+    }
+}
+
+impl<'a> From<&ParseError<'a>> for Diagnostic {
+    fn from(value: &ParseError<'a>) -> Self {
+        value.diagnostic()
+    }
+}
+
+impl From<&PreprocessorError> for Diagnostic {
+    fn from(value: &PreprocessorError) -> Self {
+        value.diagnostic()
+    }
+}
+
+/// Entry of the catalog of error codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CatalogEntry {
+    /// Stable code returned by `code()` of the error kinds
+    pub code: &'static str,
+    pub severity: Severity,
+    pub title: &'static str,
+    pub description: &'static str,
+}
+
+const fn entry(
+    code: &'static str,
+    severity: Severity,
+    title: &'static str,
+    description: &'static str,
+) -> CatalogEntry {
+    CatalogEntry {
+        code,
+        severity,
+        title,
+        description,
+    }
+}
+
+const CATALOG: &[CatalogEntry] = &[
+    // Validation warnings
+    entry(
+        "W0000",
+        Severity::Warning,
+        "Variable unused",
+        "A variable of the node is never set or read.",
+    ),
+    entry(
+        "W0001",
+        Severity::Warning,
+        "Used debug",
+        "Debug rules print to stdout and should only be used in development.",
+    ),
+    entry(
+        "W0002",
+        Severity::Warning,
+        "Used print",
+        "Print rules print to stdout and should only be used in development.",
+    ),
+    entry(
+        "W0003",
+        Severity::Warning,
+        "Feature depricated",
+        "The grammar uses a feature that might be removed, such as Back or Any.",
+    ),
+    entry(
+        "W0004",
+        Severity::Warning,
+        "Unusual token",
+        "A lexer token is long, starts with a number, contains whitespace or is not ASCII.",
+    ),
+    entry(
+        "W0005",
+        Severity::Warning,
+        "Label unused",
+        "A label is declared but no Goto refers to it.",
+    ),
+    entry(
+        "W0006",
+        Severity::Warning,
+        "Fail without explanation",
+        "An explicit failure does not explain what went wrong.",
+    ),
+    entry(
+        "W0007",
+        Severity::Warning,
+        "Slow construct",
+        "The grammar uses a construct that is known to be slow, the warning suggests a rewrite.",
+    ),
+    entry(
+        "W0008",
+        Severity::Warning,
+        "Node overwritten",
+        "A variable holding a single node can be set more than once, use a NodeList to keep every node.",
+    ),
+    entry(
+        "W0009",
+        Severity::Warning,
+        "Token unused",
+        "No rule, enumerator, alias, bracket or sync list uses the lexer token, it only slows lexing down.",
+    ),
+    entry(
+        "W0010",
+        Severity::Warning,
+        "Word is a token",
+        "A word matched by the grammar is also a lexer token, so it is split off the front of longer texts.",
+    ),
+    // Parse warnings
+    entry(
+        "W0200",
+        Severity::Warning,
+        "Text not parsed",
+        "The entry node ended before the end of the text and the grammar does not require the end of file.",
+    ),
+    // Validation errors
+    entry(
+        "E0100",
+        Severity::Error,
+        "Variable usage invalid",
+        "The variable has a kind that can not be used by the rule.",
+    ),
+    entry(
+        "E0101",
+        Severity::Error,
+        "Comparison invalid",
+        "The compared variables have kinds that can not be compared this way.",
+    ),
+    entry(
+        "E0102",
+        Severity::Error,
+        "Variable not found",
+        "The variable is not declared by the node or the grammar.",
+    ),
+    entry(
+        "E0103",
+        Severity::Error,
+        "Empty token invalid",
+        "The lexer contains an empty token.",
+    ),
+    entry(
+        "E0104",
+        Severity::Error,
+        "Token not found",
+        "A rule matches a token that was not added to the lexer.",
+    ),
+    entry(
+        "E0105",
+        Severity::Error,
+        "Duplicate label",
+        "A label is declared more than once in a node.",
+    ),
+    entry(
+        "E0106",
+        Severity::Error,
+        "Label not found",
+        "Goto refers to a label that is not declared in the node.",
+    ),
+    entry(
+        "E0107",
+        Severity::Error,
+        "Duplicate token",
+        "A token was added to the lexer more than once.",
+    ),
+    entry(
+        "E0108",
+        Severity::Error,
+        "Out of scope",
+        "Back goes further than the start of the node.",
+    ),
+    entry(
+        "E0109",
+        Severity::Error,
+        "Alias not found",
+        "A rule refers to an alias that is not declared in the grammar.",
+    ),
+    entry(
+        "E0110",
+        Severity::Error,
+        "Alias cycle",
+        "An alias refers to itself through other aliases.",
+    ),
+    entry(
+        "E0111",
+        Severity::Error,
+        "Feature not found",
+        "IfFeature refers to a feature that is not declared in the grammar.",
+    ),
+    entry(
+        "E0112",
+        Severity::Error,
+        "Node not found",
+        "A rule refers to a node that is not declared in the grammar.",
+    ),
+    entry(
+        "E0113",
+        Severity::Error,
+        "Enumerator not found",
+        "A rule refers to an enumerator that is not declared in the grammar.",
+    ),
+    entry(
+        "E0114",
+        Severity::Error,
+        "Variable type mismatch",
+        "Two variables that are used together have different kinds.",
+    ),
+    entry(
+        "E0115",
+        Severity::Error,
+        "Example failed",
+        "A fenced example in the docs of a node does not parse with the node as entry.",
+    ),
+    entry(
+        "E0116",
+        Severity::Error,
+        "Predicate not found",
+        "An External command calls a predicate that is not registered in the parser.",
+    ),
+    entry(
+        "E0117",
+        Severity::Error,
+        "Global set elsewhere",
+        "The nodes an entry can reach read a global that only other nodes set, so the node can not be parsed on its own.",
+    ),
+    entry(
+        "E0118",
+        Severity::Error,
+        "Label out of scope",
+        "A Goto jumps to a label that is not in its block or a block around it, the jump fails during the parse.",
+    ),
+    entry(
+        "E0119",
+        Severity::Error,
+        "Initial value mismatch",
+        "A node variable starts with a value of another kind than the variable is declared with.",
+    ),
+    // Parse errors caused by the grammar
+    entry(
+        "E0150",
+        Severity::Error,
+        "Node not found",
+        "The parser reached a node that is not declared, run the validator.",
+    ),
+    entry(
+        "E0151",
+        Severity::Error,
+        "Enumerator not found",
+        "The parser reached an enumerator that is not declared, run the validator.",
+    ),
+    entry(
+        "E0152",
+        Severity::Error,
+        "Variable not found",
+        "The parser used a variable that is not declared, run the validator.",
+    ),
+    entry(
+        "E0153",
+        Severity::Error,
+        "Variable is uncountable",
+        "The parser counted a variable that is not a number.",
+    ),
+    entry(
+        "E0154",
+        Severity::Error,
+        "Variable can not be set",
+        "The parser set a variable that can not hold the value.",
+    ),
+    entry(
+        "E0155",
+        Severity::Error,
+        "Label not found",
+        "The parser went to a label that does not exist.",
+    ),
+    entry(
+        "E0156",
+        Severity::Error,
+        "Can not go back",
+        "The parser went back further than the start of the node.",
+    ),
+    entry(
+        "E0157",
+        Severity::Error,
+        "Can not break",
+        "The parser broke out of more blocks than it is in.",
+    ),
+    entry(
+        "E0158",
+        Severity::Error,
+        "Can not find token",
+        "Until could not find the token before the end of the file.",
+    ),
+    entry(
+        "E0159",
+        Severity::Error,
+        "Missing entry point",
+        "The parser has no entry node set.",
+    ),
+    entry(
+        "E0160",
+        Severity::Error,
+        "Alias not found",
+        "The parser reached an alias that is not declared, run the validator.",
+    ),
+    entry(
+        "E0161",
+        Severity::Error,
+        "Predicate not found",
+        "The parser called a predicate that is not registered, run the validator.",
+    ),
+    entry(
+        "E0162",
+        Severity::Error,
+        "Token range out of bounds",
+        "A region given to parse_node_at does not lie within the tokens.",
+    ),
+    entry(
+        "E0163",
+        Severity::Error,
+        "Alias cycle",
+        "The parser reached an alias that refers to itself through other aliases, run the validator.",
+    ),
+    // Parse errors caused by the text
+    entry(
+        "E0200",
+        Severity::Error,
+        "Parser not fully implemented",
+        "The parser reached a feature that is not implemented yet.",
+    ),
+    entry(
+        "E0201",
+        Severity::Error,
+        "Unexpected token",
+        "The text contains a different token than the grammar expects.",
+    ),
+    entry(
+        "E0202",
+        Severity::Error,
+        "Unexpected end of file",
+        "The text ends before the grammar is satisfied.",
+    ),
+    entry(
+        "E0203",
+        Severity::Error,
+        "Could not parse until the end",
+        "The entry node matched, but some text is left after it.",
+    ),
+    entry(
+        "E0204",
+        Severity::Error,
+        "Backtracking limit exceeded",
+        "The parser gave up after failing more times than allowed by the backtrack limit.",
+    ),
+    entry(
+        "E0205",
+        Severity::Error,
+        "Unsupported language version",
+        "The text uses a node that is not available in the selected language version.",
+    ),
+    entry(
+        "E0206",
+        Severity::Error,
+        "Unexpected token",
+        "The text contains a different word than the grammar expects.",
+    ),
+    entry(
+        "E0207",
+        Severity::Error,
+        "Unexpected token",
+        "The text contains a token that the grammar forbids at this place.",
+    ),
+    entry(
+        "E0208",
+        Severity::Error,
+        "Unexpected token",
+        "None of the alternatives of the grammar matched the text.",
+    ),
+    entry(
+        "E0209",
+        Severity::Error,
+        "Custom error",
+        "The grammar failed with its own error definition.",
+    ),
+    entry(
+        "E0210",
+        Severity::Error,
+        "Unexpected token",
+        "The text contains a different character than the grammar expects.",
+    ),
+    entry(
+        "E0211",
+        Severity::Error,
+        "Parsing cancelled",
+        "The parse was stopped through its cancellation token before it finished.",
+    ),
+    entry(
+        "E0212",
+        Severity::Error,
+        "Node limit exceeded",
+        "The parser gave up after creating more nodes than allowed by the node limit.",
+    ),
+    entry(
+        "E0213",
+        Severity::Error,
+        "Unexpected keyword",
+        "A word reserved as a keyword of the node was used where text was expected.",
+    ),
+    entry(
+        "E0214",
+        Severity::Error,
+        "Nesting too deep",
+        "The parser gave up on a node nested deeper than allowed by the nesting limit.",
+    ),
+    entry(
+        "E0215",
+        Severity::Error,
+        "Empty input",
+        "The text contains nothing to parse and the entry node does not allow empty input.",
+    ),
+    entry(
+        "E0299",
+        Severity::Error,
+        "Ok",
+        "Control value of the parser, it is never reported.",
+    ),
+];
+
+/// Returns all error codes with their descriptions
+///
+/// Tooling can use the codes to link diagnostics to documentation
+pub fn catalog() -> &'static [CatalogEntry] {
+    CATALOG
+}
+
+/// Looks up a code returned by `code()` of the error kinds
+pub fn explain(code: &str) -> Option<&'static CatalogEntry> {
+    CATALOG.iter().find(|e| e.code == code)
+}
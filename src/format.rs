@@ -1,47 +1,348 @@
-use std::{borrow::Cow, fmt::Write, path::Path};
+use std::{borrow::Cow, fmt::Write, io::IsTerminal, ops::Range, path::Path};
 
-use annotate_snippets::{renderer::DecorStyle, AnnotationKind, Group, Level, Renderer, Snippet};
+use annotate_snippets::{
+    renderer::DecorStyle, Annotation, AnnotationKind, Group, Level, Renderer, Snippet,
+};
 
 use crate::{
+    diagnostic::{Diagnostic, LabelKind, Severity},
     grammar::validator::ValidationResult,
-    lexer::PreprocessorError,
-    parser::{Node, ParseError},
+    lexer::{PreprocessorError, TabPolicy},
+    parser::ParseError,
 };
 
 const TERM_WIDTH: usize = 60;
 
+/// Default number of lines shown before and after an error
+pub const CONTEXT_LINES: usize = 2;
+
+/// Controls how diagnostics are rendered
+#[derive(Debug, Clone, Copy)]
+pub struct DiagnosticStyle {
+    pub decor: Decor,
+    pub color: ColorChoice,
+    /// Number of lines shown before and after an error
+    pub context_lines: usize,
+    pub term_width: usize,
+    /// Tabs are expanded to spaces with the policy, so the rendered columns match the editor
+    ///
+    /// `TabPolicy::Char` leaves them to the renderer, which shows every tab as four spaces
+    pub tabs: TabPolicy,
+}
+
+impl Default for DiagnosticStyle {
+    fn default() -> Self {
+        Self {
+            decor: Decor::Unicode,
+            color: ColorChoice::Auto,
+            context_lines: CONTEXT_LINES,
+            term_width: TERM_WIDTH,
+            tabs: TabPolicy::Char,
+        }
+    }
+}
+
+impl DiagnosticStyle {
+    /// Plain ascii without colors, useful for logs and snapshot tests
+    pub fn plain() -> Self {
+        Self {
+            decor: Decor::Ascii,
+            color: ColorChoice::Never,
+            ..Default::default()
+        }
+    }
+
+    /// Resolves `ColorChoice::Auto` for a sink that is or is not a terminal
+    ///
+    /// The `write` methods never color with `Auto` since they cannot tell where the text ends up,
+    /// `print` resolves the style for stdout
+    pub fn for_sink(self, terminal: bool) -> Self {
+        Self {
+            color: self.color.resolve(terminal),
+            ..self
+        }
+    }
+
+    fn renderer(&self) -> Renderer {
+        let renderer = match self.color.enabled(false) {
+            true => Renderer::styled(),
+            false => Renderer::plain(),
+        };
+        let decor = match self.decor {
+            Decor::Unicode => DecorStyle::Unicode,
+            Decor::Ascii => DecorStyle::Ascii,
+        };
+        renderer.term_width(self.term_width).decor_style(decor)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Decor {
+    #[default]
+    Unicode,
+    Ascii,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Colors are used if the output goes to a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether output to a sink is colored, `terminal` tells if the sink is a terminal
+    pub fn enabled(&self, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => terminal && std::env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+
+    /// Replaces `Auto` with the choice it makes for the sink, see `ColorChoice::enabled`
+    pub fn resolve(self, terminal: bool) -> Self {
+        match self.enabled(terminal) {
+            true => ColorChoice::Always,
+            false => ColorChoice::Never,
+        }
+    }
+}
+
+/// Part of the source text that is passed to the renderer
+///
+/// Rendering only the lines around an error keeps diagnostics fast on large inputs
+struct Window<'t> {
+    text: Cow<'t, str>,
+    /// Byte offset of the window in the source text
+    offset: usize,
+    /// Length of the window in the source text
+    len: usize,
+    /// Line number of the first line in the window
+    line: usize,
+    /// End of every expanded tab in the source text and in `text`, see `Window::expand_tabs`
+    tabs: Vec<(usize, usize)>,
+}
+
+impl<'t> Window<'t> {
+    /// Lines containing the span extended by `context` lines on both sides
+    fn around(txt: &'t str, span: Range<usize>, context: usize) -> Self {
+        let span_start = floor_char_boundary(txt, span.start);
+        let span_end = floor_char_boundary(txt, span.end.max(span.start));
+        let mut start = txt[..span_start].rfind('\n').map_or(0, |i| i + 1);
+        for _ in 0..context {
+            if start == 0 {
+                break;
+            }
+            start = txt[..start - 1].rfind('\n').map_or(0, |i| i + 1);
+        }
+        let mut end = txt[span_end..]
+            .find('\n')
+            .map_or(txt.len(), |i| span_end + i);
+        for _ in 0..context {
+            if end >= txt.len() {
+                break;
+            }
+            end = txt[end + 1..].find('\n').map_or(txt.len(), |i| end + 1 + i);
+        }
+        Window {
+            text: Cow::Borrowed(&txt[start..end]),
+            tabs: Vec::new(),
+            offset: start,
+            len: end - start,
+            line: txt.as_bytes()[..start]
+                .iter()
+                .filter(|b| **b == b'\n')
+                .count()
+                + 1,
+        }
+    }
+
+    /// Moves the span into the window, `None` if it is not visible
+    fn clip(&self, span: Range<usize>) -> Option<Range<usize>> {
+        let end = self.offset + self.len;
+        if span.start > end || span.end < self.offset {
+            return None;
+        }
+        let start = span.start.max(self.offset) - self.offset;
+        let end = span.end.clamp(span.start, end).max(self.offset) - self.offset;
+        Some(self.expanded(start)..self.expanded(end))
+    }
+
+    /// Replaces tabs with spaces, the window always starts at the start of a line
+    fn expand_tabs(&mut self, tabs: TabPolicy) {
+        if tabs == TabPolicy::Char || !self.text.contains('\t') {
+            return;
+        }
+        let mut text = String::with_capacity(self.text.len());
+        let mut column = 0;
+        for (i, c) in self.text.char_indices() {
+            match c {
+                '\t' => {
+                    let next = tabs.advance(column);
+                    text.extend(core::iter::repeat_n(' ', next - column));
+                    self.tabs.push((i + 1, text.len()));
+                    column = next;
+                }
+                '\n' => {
+                    text.push(c);
+                    column = 0;
+                }
+                _ => {
+                    text.push(c);
+                    column += 1;
+                }
+            }
+        }
+        self.text = Cow::Owned(text);
+    }
+
+    /// Moves a byte offset of the window past the expanded tabs
+    fn expanded(&self, idx: usize) -> usize {
+        match self.tabs.iter().rev().find(|(end, _)| *end <= idx) {
+            Some((end, expanded)) => expanded + (idx - end),
+            None => idx,
+        }
+    }
+}
+
+fn floor_char_boundary(txt: &str, mut idx: usize) -> usize {
+    idx = idx.min(txt.len());
+    while !txt.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
+
+impl Diagnostic {
+    fn group<'d>(
+        &'d self,
+        txt: &'d str,
+        filepath: Option<&'d Path>,
+        style: &DiagnosticStyle,
+    ) -> Group<'d> {
+        let level = match self.severity {
+            Severity::Error => Level::ERROR,
+            Severity::Warning => Level::WARNING,
+        };
+        let title: Cow<'d, str> = match self.primary() {
+            Some(_) => self.title.as_str().into(),
+            None => format!("{}\n{}", self.title, self.message).into(),
+        };
+        let mut group = Group::with_title(
+            level
+                .with_name(self.name.as_str())
+                .primary_title(title)
+                .id(self.code.as_str()),
+        );
+        if let Some(snippet) = self.snippet(txt, filepath, style) {
+            group = group.element(snippet);
+        }
+        for note in &self.notes {
+            group = group.element(Level::NOTE.message(note.as_str()));
+        }
+        if let Some(help) = &self.help {
+            group = group.element(Level::HELP.message(help.as_str()));
+        }
+        group
+    }
+
+    /// Source snippet showing only `style.context_lines` lines around the diagnostic
+    ///
+    /// Labels outside of the window are cut off
+    fn snippet<'d>(
+        &'d self,
+        txt: &'d str,
+        filepath: Option<&'d Path>,
+        style: &DiagnosticStyle,
+    ) -> Option<Snippet<'d, Annotation<'d>>> {
+        let mut focus = self.primary()?.span.clone();
+        for label in &self.labels {
+            if label.kind == LabelKind::Secondary {
+                focus.start = focus.start.min(label.span.start);
+                focus.end = focus.end.max(label.span.end);
+            }
+        }
+        let mut window = Window::around(txt, focus, style.context_lines);
+        window.expand_tabs(style.tabs);
+        let text = core::mem::take(&mut window.text);
+        let len = text.len();
+        let mut snippet = Snippet::source(text)
+            .line_start(window.line)
+            .annotation(AnnotationKind::Visible.span(0..len))
+            .fold(true);
+        if let Some(file) = filepath {
+            snippet = snippet.path(file.to_str());
+        }
+        for label in &self.labels {
+            let span = match window.clip(label.span.clone()) {
+                Some(span) => span,
+                None => continue,
+            };
+            let kind = match label.kind {
+                LabelKind::Primary => AnnotationKind::Primary,
+                LabelKind::Secondary | LabelKind::Region => AnnotationKind::Context,
+                LabelKind::Visible => AnnotationKind::Visible,
+            };
+            let text = match (label.kind, &label.text) {
+                (_, Some(text)) => Some(text.as_str()),
+                (LabelKind::Primary, None) => Some(self.message.as_str()),
+                _ => None,
+            };
+            snippet = snippet.annotation(kind.span(span).label(text));
+        }
+        Some(snippet)
+    }
+
+    pub fn write(
+        &self,
+        w: &mut impl Write,
+        txt: &str,
+        filepath: Option<&Path>,
+        style: &DiagnosticStyle,
+    ) -> std::fmt::Result {
+        write_diagnostics(w, core::slice::from_ref(self), txt, filepath, style)
+    }
+}
+
+/// Renders all diagnostics of one source text in a single report
+pub fn write_diagnostics(
+    w: &mut impl Write,
+    diagnostics: &[Diagnostic],
+    txt: &str,
+    filepath: Option<&Path>,
+    style: &DiagnosticStyle,
+) -> std::fmt::Result {
+    let groups = diagnostics
+        .iter()
+        .map(|d| d.group(txt, filepath, style))
+        .collect::<Vec<_>>();
+    write!(w, "{}", style.renderer().render(&groups))
+}
+
 impl<'a> ValidationResult<'a> {
     pub fn write_all(&self, w: &mut impl Write) -> std::fmt::Result {
-        let mut reports = Vec::new();
-        for warn in &self.warnings {
-            let (id, header) = warn.kind.id_and_header();
-            let report = Group::with_title(
-                Level::WARNING
-                    .with_name("parser warning")
-                    .primary_title(format!("{}\n{}", header, warn))
-                    .id(id),
-            );
-            reports.push(report);
-        }
-        for err in &self.errors {
-            let (id, header) = err.kind.id_and_header();
-            let report = Group::with_title(
-                Level::ERROR
-                    .with_name("parser erorr")
-                    .primary_title(format!("{}\n{}", header, err))
-                    .id(id),
-            );
-            reports.push(report);
-        }
-        let renderer = Renderer::styled()
-            .term_width(TERM_WIDTH)
-            .decor_style(DecorStyle::Unicode);
-        writeln!(w, "{}", renderer.render(&reports[..]))
+        self.write_all_styled(w, &DiagnosticStyle::default())
+    }
+
+    /// Writes all warnings and errors rendered with the style
+    pub fn write_all_styled(
+        &self,
+        w: &mut impl Write,
+        style: &DiagnosticStyle,
+    ) -> std::fmt::Result {
+        write_diagnostics(w, &self.diagnostics(), "", None, style)?;
+        writeln!(w)
     }
 
     pub fn print_all(&self) -> std::fmt::Result {
         let mut buf = String::new();
-        self.write_all(&mut buf)?;
+        self.write_all_styled(
+            &mut buf,
+            &DiagnosticStyle::default().for_sink(stdout_is_terminal()),
+        )?;
         print!("{buf}");
         Ok(())
     }
@@ -54,67 +355,41 @@ impl<'a> ParseError<'a> {
         txt: &'a str,
         filepath: Option<&Path>,
     ) -> std::fmt::Result {
-        let (id, header) = self.kind.id_and_header();
-        let span = self.location.index..self.location.index + self.location.len;
-        let mut snippet = Snippet::source(txt)
-            .annotation(
-                AnnotationKind::Primary
-                    .span(span)
-                    .label(format!("{:?}", self.kind)),
-            )
-            // .annotation(
-            //     AnnotationKind::Visible
-            //         .span(self.location.index - 5..self.location.index + self.location.len),
-            // )
-            .fold(true);
-        if let Some(file) = filepath {
-            snippet = snippet.path(file.to_str());
-        }
-        let header: Cow<'a, str> = match &self.node {
-            Some(n) => format!("{header} while parsing {}", n.name).into(),
-            None => header.into(),
-        };
-        match &self.node {
-            Some(n) => {
-                snippet = snippet.annotation(
-                    AnnotationKind::Visible.span(n.first_string_idx..n.first_string_idx + 1),
-                )
-            }
-            _ => (),
-        }
-        let mut report = Group::with_title(
-            Level::ERROR
-                .with_name("syntax error")
-                .primary_title(header)
-                .id(id),
-        )
-        .element(snippet);
-        report = match &self {
-            Self {
-                hint: Some(hint), ..
-            } => report.element(Level::HELP.message(*hint)),
-            Self {
-                node: Some(Node { docs: Some(d), .. }),
-                ..
-            } => report.element(Level::INFO.message(*d)),
-            _ => report,
+        self.write_window(w, txt, filepath, CONTEXT_LINES)
+    }
+
+    /// Writes the error showing only `context_lines` lines around it
+    ///
+    /// Annotations outside of the window are cut off
+    pub fn write_window(
+        &self,
+        w: &mut impl Write,
+        txt: &'a str,
+        filepath: Option<&Path>,
+        context_lines: usize,
+    ) -> std::fmt::Result {
+        let style = DiagnosticStyle {
+            context_lines,
+            ..Default::default()
         };
-        // // if let Some(Node { docs: Some(d), .. }) = &self.node {
-        // //     report = report.element(Level::INFO.message(*d));
-        // // }
-        // if let Some(hint) = self.hint {
-        //     report = report.element(Level::HELP.message(hint));
-        // }
-        let render = Renderer::styled()
-            .decor_style(DecorStyle::Unicode)
-            .term_width(TERM_WIDTH)
-            .render(&[report]);
-        write!(w, "{render}")
+        self.write_styled(w, txt, filepath, &style)
+    }
+
+    /// Writes the error rendered with the style
+    pub fn write_styled(
+        &self,
+        w: &mut impl Write,
+        txt: &'a str,
+        filepath: Option<&Path>,
+        style: &DiagnosticStyle,
+    ) -> std::fmt::Result {
+        self.diagnostic().write(w, txt, filepath, style)
     }
 
     pub fn print(&self, txt: &'a str, filename: Option<&Path>) -> std::fmt::Result {
         let mut buf = String::new();
-        self.write(&mut buf, txt, filename)?;
+        let style = DiagnosticStyle::default().for_sink(stdout_is_terminal());
+        self.write_styled(&mut buf, txt, filename, &style)?;
         println!("{buf}");
         Ok(())
     }
@@ -127,36 +402,44 @@ impl PreprocessorError {
         txt: &str,
         filename: Option<&Path>,
     ) -> std::fmt::Result {
-        let span = self.location.index..self.location.index + self.len;
-        let mut snippet = Snippet::source(txt)
-            .annotation(
-                AnnotationKind::Primary
-                    .span(span)
-                    .label(format!("{:?}", self.err.msg)),
-            )
-            .fold(true);
-        if let Some(file) = filename {
-            snippet = snippet.path(file.to_str());
-        }
-        let report = Group::with_title(
-            Level::ERROR
-                .with_name("lexing error")
-                .primary_title(self.err.header)
-                .id(self.err.code),
-        )
-        .element(snippet);
+        self.write_window(w, txt, filename, CONTEXT_LINES)
+    }
 
-        let render = Renderer::styled()
-            .decor_style(DecorStyle::Unicode)
-            .term_width(TERM_WIDTH)
-            .render(&[report]);
-        write!(w, "{render}")
+    /// Writes the error showing only `context_lines` lines around it
+    pub fn write_window(
+        &self,
+        w: &mut impl Write,
+        txt: &str,
+        filename: Option<&Path>,
+        context_lines: usize,
+    ) -> std::fmt::Result {
+        let style = DiagnosticStyle {
+            context_lines,
+            ..Default::default()
+        };
+        self.write_styled(w, txt, filename, &style)
+    }
+
+    /// Writes the error rendered with the style
+    pub fn write_styled(
+        &self,
+        w: &mut impl Write,
+        txt: &str,
+        filename: Option<&Path>,
+        style: &DiagnosticStyle,
+    ) -> std::fmt::Result {
+        self.diagnostic().write(w, txt, filename, style)
     }
 
     pub fn print(&self, txt: &str, filename: Option<&Path>) -> std::fmt::Result {
         let mut buf = String::new();
-        self.write(&mut buf, txt, filename)?;
+        let style = DiagnosticStyle::default().for_sink(stdout_is_terminal());
+        self.write_styled(&mut buf, txt, filename, &style)?;
         println!("{buf}");
         Ok(())
     }
 }
+
+fn stdout_is_terminal() -> bool {
+    std::io::stdout().is_terminal()
+}
@@ -1,25 +1,70 @@
-use crate::{lexer::TokenKinds, parser, Map};
+use crate::{intern, lexer::TokenKinds, parser, Map};
 
 // Choose between std and alloc
 cfg_if::cfg_if! {
     if #[cfg(feature = "std")] {
         extern crate std;
         use std::prelude::v1::*;
+        use std::sync::Arc;
     } else {
         extern crate alloc;
         use alloc::string::*;
         use alloc::vec::*;
+        use alloc::vec;
+        use alloc::sync::Arc;
+        use alloc::boxed::Box;
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Grammar<'a> {
-    pub nodes: Map<String, Node<'a>>,
+    /// Nodes of the grammar, without the ones of `Grammar::base`
+    ///
+    /// Look nodes up with `Grammar::node` and `Grammar::all_nodes` to see those too
+    pub nodes: Map<intern::Symbol, Node<'a>>,
+    /// Grammar this one is an overlay of, see `Grammar::overlay`
+    pub base: Option<Arc<Grammar<'a>>>,
     pub enumerators: Map<String, Enumerator<'a>>,
+    /// Named tokens that can be used in rules with `MatchToken::Alias`
+    pub aliases: Map<String, MatchToken<'a>>,
+    /// Declared features and whether they are enabled by default
+    pub features: Map<String, bool>,
     pub globals: Vec<(&'a str, VariableKind)>,
     pub ignored: Vec<TokenKinds<'a>>,
     /// If true, the parser will throw an error if the last token is not EOF
     pub eof: bool,
+    /// Keeps whitespace and ignored tokens around parsed nodes, see `parser::Node::leading_trivia`
+    ///
+    /// Trivia is not kept if None
+    pub trivia: Option<TriviaPolicy>,
+    /// Tests shipped with the grammar, run with `Parser::run_grammar_tests`
+    pub tests: Vec<GrammarTest<'a>>,
+    /// Opening and closing token kinds of brackets, paired up in `ParseResult::brackets`
+    pub brackets: Vec<(TokenKinds<'a>, TokenKinds<'a>)>,
+}
+
+/// Input of a node and the expected text of its variables, see `Grammar::add_test`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrammarTest<'a> {
+    pub node: &'a str,
+    pub input: &'a str,
+    /// Variable names and the text they should contain
+    pub expect: Vec<(&'a str, &'a str)>,
+}
+
+/// Decides which node owns the trivia between two nodes
+///
+/// Trivia are whitespace, new lines and tokens listed in `Grammar::ignored`, such as comments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TriviaPolicy {
+    /// Trivia belongs to the following node, like doc comments do
+    AttachToNext,
+    /// Trivia belongs to the preceding node
+    AttachToPrevious,
+    /// Trivia up to the end of the line of the preceding node belongs to it,
+    /// the rest belongs to the following node
+    #[default]
+    SplitAtLine,
 }
 
 impl<'a> Default for Grammar<'a> {
@@ -32,15 +77,89 @@ impl<'a> Grammar<'a> {
     pub fn new() -> Grammar<'a> {
         Grammar {
             nodes: Map::new(),
+            base: None,
             enumerators: Map::new(),
+            aliases: Map::new(),
+            features: Map::new(),
             globals: Vec::new(),
             ignored: Vec::new(),
             eof: true,
+            trivia: None,
+            tests: Vec::new(),
+            brackets: Vec::new(),
         }
     }
 
+    /// Returns false if the grammar already has a node of the name
+    ///
+    /// A node of the base grammar with the same name is replaced, see `Grammar::overlay`
     pub fn add_node(&mut self, node: Node<'a>) -> bool {
-        self.nodes.insert(node.name.to_string(), node).is_none()
+        self.nodes.insert(node.name, node).is_none()
+    }
+
+    /// Derived grammar that shares the nodes of this one
+    ///
+    /// Nodes added to the overlay replace the nodes of the base with the same name,
+    /// `Grammar::node_mut` copies a node of the base into the overlay to extend it.
+    /// The base is never modified, so several dialects can share it.
+    /// Everything but the nodes is copied into the overlay.
+    ///
+    /// ```text
+    /// let base = Arc::new(grammar);
+    /// let mut dialect = base.overlay();
+    /// dialect.new_node("stmt").rules([...]).build();
+    /// ```
+    pub fn overlay(self: &Arc<Self>) -> Grammar<'a> {
+        Grammar {
+            nodes: Map::new(),
+            base: Some(self.clone()),
+            enumerators: self.enumerators.clone(),
+            aliases: self.aliases.clone(),
+            features: self.features.clone(),
+            globals: self.globals.clone(),
+            ignored: self.ignored.clone(),
+            eof: self.eof,
+            trivia: self.trivia,
+            tests: self.tests.clone(),
+            brackets: self.brackets.clone(),
+        }
+    }
+
+    /// Node of the grammar, or of its base if the grammar does not replace it
+    pub fn node(&self, name: &str) -> Option<&Node<'a>> {
+        self.node_of(intern::Symbol::get(name)?)
+    }
+
+    /// `Grammar::node` by the interned name
+    pub fn node_of(&self, name: intern::Symbol) -> Option<&Node<'a>> {
+        match self.nodes.get(&name) {
+            Some(node) => Some(node),
+            None => self.base.as_ref()?.node_of(name),
+        }
+    }
+
+    /// Node to modify, a node of the base is copied into the grammar first
+    pub fn node_mut(&mut self, name: &str) -> Option<&mut Node<'a>> {
+        let name = intern::Symbol::get(name)?;
+        if !self.nodes.contains_key(&name) {
+            let node = self.base.as_ref()?.node_of(name)?.clone();
+            self.nodes.insert(name, node);
+        }
+        self.nodes.get_mut(&name)
+    }
+
+    /// Nodes of the grammar and the nodes of its base that are not replaced,
+    /// in no particular order
+    pub fn all_nodes(&self) -> Vec<&Node<'a>> {
+        let mut nodes = self.nodes.values().collect::<Vec<_>>();
+        if let Some(base) = &self.base {
+            nodes.extend(
+                base.all_nodes()
+                    .into_iter()
+                    .filter(|node| !self.nodes.contains_key(&node.name)),
+            );
+        }
+        nodes
     }
 
     pub fn add_enum(&mut self, enumerator: Enumerator<'a>) -> bool {
@@ -48,6 +167,56 @@ impl<'a> Grammar<'a> {
             .insert(enumerator.name.to_string(), enumerator)
             .is_none()
     }
+
+    /// Gives a name to a token so that changing it later is a one place edit
+    pub fn alias(&mut self, name: &'a str, token: MatchToken<'a>) -> bool {
+        self.aliases.insert(name.to_string(), token).is_none()
+    }
+
+    /// Token the alias stands for, following aliases of aliases
+    ///
+    /// Gives up after visiting every alias once so that a cycle can not recurse forever
+    pub fn resolve_alias(&self, name: &'a str) -> Result<&MatchToken<'a>, AliasError<'a>> {
+        let mut current = name;
+        for _ in 0..=self.aliases.len() {
+            match self.aliases.get(current) {
+                Some(MatchToken::Alias(next)) => current = next,
+                Some(token) => return Ok(token),
+                None => return Err(AliasError::NotFound(current)),
+            }
+        }
+        Err(AliasError::Cycle(name))
+    }
+
+    /// Declares a feature that can be tested with `Rule::IfFeature`
+    pub fn feature(&mut self, name: &'a str, default: bool) -> bool {
+        self.features.insert(name.to_string(), default).is_none()
+    }
+
+    /// Adds a test that parses `input` as `node` and compares its variables
+    ///
+    /// grammar.add_test("KWLet", "let a: int = 5;", expect! { ident: "a", type: "int" });
+    pub fn add_test(
+        &mut self,
+        node: &'a str,
+        input: &'a str,
+        expect: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) {
+        self.tests.push(GrammarTest {
+            node,
+            input,
+            expect: expect.into_iter().collect(),
+        });
+    }
+}
+
+/// Reason an alias does not stand for a token, see `Grammar::resolve_alias`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AliasError<'a> {
+    /// The alias or an alias it refers to is not declared
+    NotFound(&'a str),
+    /// The alias refers to itself through other aliases
+    Cycle(&'a str),
 }
 
 /// A collection of rules
@@ -58,7 +227,7 @@ pub type Rules<'a> = Vec<Rule<'a>>;
 /// It also contains parameters that can be used if the rule is matched
 ///
 /// Special kind of rules are commands that can be executed without matching a token
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Rule<'a> {
     /// Matches a token
     ///
@@ -69,6 +238,7 @@ pub enum Rule<'a> {
         token: MatchToken<'a>,
         rules: Rules<'a>,
         parameters: Vec<Parameters<'a>>,
+        origin: Origin<'a>,
     },
     /// Matches a token
     ///
@@ -79,6 +249,7 @@ pub enum Rule<'a> {
         token: MatchToken<'a>,
         rules: Rules<'a>,
         parameters: Vec<Parameters<'a>>,
+        origin: Origin<'a>,
     },
     /// Matches one of the tokens
     ///
@@ -88,6 +259,7 @@ pub enum Rule<'a> {
     IsOneOf {
         tokens: Vec<OneOf<'a>>,
         parameters: Vec<Parameters<'a>>,
+        origin: Origin<'a>,
     },
     /// Matches a token
     ///
@@ -103,6 +275,7 @@ pub enum Rule<'a> {
         isnt: Rules<'a>,
         /// Parameters that can be used if the token is matched
         parameters: Vec<Parameters<'a>>,
+        origin: Origin<'a>,
     },
     /// Matches one of the tokens
     ///
@@ -114,6 +287,7 @@ pub enum Rule<'a> {
         is_one_of: Vec<OneOf<'a>>,
         /// Rules that will be executed if none of the tokens is matched
         isnt: Rules<'a>,
+        origin: Origin<'a>,
     },
     /// Matches a token
     ///
@@ -128,56 +302,160 @@ pub enum Rule<'a> {
         ///
         /// The parameters will be used once every time the token is matched
         parameters: Vec<Parameters<'a>>,
+        origin: Origin<'a>,
     },
     /// Loop that will be executed until a break command is executed
     Loop {
         rules: Rules<'a>,
+        origin: Origin<'a>,
+    },
+    /// Executes the rules if the feature is enabled, otherwise executes the else rules
+    ///
+    /// Features are declared with `Grammar::feature` and can be changed per parse
+    /// with `parser::Parser::set_feature`
+    IfFeature {
+        name: &'a str,
+        rules: Rules<'a>,
+        else_rules: Rules<'a>,
+        origin: Origin<'a>,
     },
     /// Searches in the tokens until a token is matched
     Until {
         token: MatchToken<'a>,
         rules: Rules<'a>,
         parameters: Vec<Parameters<'a>>,
+        origin: Origin<'a>,
     },
     /// Searches in the tokens until one of the tokens is matched
     UntilOneOf {
         tokens: Vec<OneOf<'a>>,
+        origin: Origin<'a>,
     },
     Peek {
         token: MatchToken<'a>,
         is: Vec<Rule<'a>>,
         isnt: Vec<Rule<'a>>,
         parameters: Vec<Parameters<'a>>,
+        origin: Origin<'a>,
     },
     /// Performs a command
     ///
     /// The command will be executed without matching a token
     Command {
         command: Commands<'a>,
+        origin: Origin<'a>,
     },
     Debug {
         target: Option<VarKind<'a>>,
+        origin: Origin<'a>,
+    },
+}
+
+/// Place a rule was defined at, reported with validation errors and developer errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Origin<'a> {
+    #[default]
+    Unknown,
+    /// Rust source of the rule, recorded by the constructors of `api::ext`
+    Code {
+        file: &'a str,
+        line: u32,
+        column: u32,
     },
+    /// Path of the rule in a definition file, for example `nodes[3].rules[0]`,
+    /// or the name of the rule an imported rule was converted from
+    Definition(&'a str),
+}
+
+impl Origin<'static> {
+    /// Location of the code that called the function
+    #[track_caller]
+    pub fn caller() -> Self {
+        let location = core::panic::Location::caller();
+        Origin::Code {
+            file: location.file(),
+            line: location.line(),
+            column: location.column(),
+        }
+    }
+}
+
+impl core::fmt::Display for Origin<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Origin::Unknown => write!(f, "unknown origin"),
+            Origin::Code { file, line, column } => write!(f, "{file}:{line}:{column}"),
+            Origin::Definition(path) => write!(f, "{path}"),
+        }
+    }
+}
+
+impl<'a> Rule<'a> {
+    /// Place the rule was defined at
+    pub fn origin(&self) -> Origin<'a> {
+        match self {
+            Rule::Is { origin, .. }
+            | Rule::Isnt { origin, .. }
+            | Rule::IsOneOf { origin, .. }
+            | Rule::Maybe { origin, .. }
+            | Rule::MaybeOneOf { origin, .. }
+            | Rule::While { origin, .. }
+            | Rule::Loop { origin, .. }
+            | Rule::IfFeature { origin, .. }
+            | Rule::Until { origin, .. }
+            | Rule::UntilOneOf { origin, .. }
+            | Rule::Peek { origin, .. }
+            | Rule::Command { origin, .. }
+            | Rule::Debug { origin, .. } => *origin,
+        }
+    }
+
+    pub fn set_origin(&mut self, new: Origin<'a>) {
+        match self {
+            Rule::Is { origin, .. }
+            | Rule::Isnt { origin, .. }
+            | Rule::IsOneOf { origin, .. }
+            | Rule::Maybe { origin, .. }
+            | Rule::MaybeOneOf { origin, .. }
+            | Rule::While { origin, .. }
+            | Rule::Loop { origin, .. }
+            | Rule::IfFeature { origin, .. }
+            | Rule::Until { origin, .. }
+            | Rule::UntilOneOf { origin, .. }
+            | Rule::Peek { origin, .. }
+            | Rule::Command { origin, .. }
+            | Rule::Debug { origin, .. } => *origin = new,
+        }
+    }
 }
 
 /// One of the tokens that will be matched
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct OneOf<'a> {
     pub token: MatchToken<'a>,
     pub rules: Rules<'a>,
     pub parameters: Vec<Parameters<'a>>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum VarKind<'a> {
     Local(&'a str),
     Global(&'a str),
 }
 
+impl core::fmt::Display for VarKind<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            VarKind::Local(name) => write!(f, "local {name}"),
+            VarKind::Global(name) => write!(f, "global {name}"),
+        }
+    }
+}
+
 impl<'a> VarKind<'a> {
     pub fn kind(
         &self,
-        locals: &[(&'a str, VariableKind)],
+        locals: &[(intern::Symbol, VariableKind)],
         globals: &[(&'a str, VariableKind)],
     ) -> Option<VariableKind> {
         match self {
@@ -188,22 +466,22 @@ impl<'a> VarKind<'a> {
 
     pub fn get(
         &self,
-        locals: &'a Map<String, parser::VariableKind<'a>>,
+        locals: &'a parser::Variables<'a>,
         globals: &'a Map<String, parser::VariableKind<'a>>,
     ) -> Option<&parser::VariableKind<'a>> {
         match self {
-            VarKind::Local(v) => locals.get(*v),
+            VarKind::Local(v) => locals.get(v),
             VarKind::Global(v) => globals.get(*v),
         }
     }
 
     pub fn get_mut<'b>(
         &'b self,
-        locals: &'b mut Map<String, parser::VariableKind<'a>>,
+        locals: &'b mut parser::Variables<'a>,
         globals: &'b mut Map<String, parser::VariableKind<'a>>,
     ) -> Option<&'b mut parser::VariableKind<'a>> {
         match self {
-            VarKind::Local(v) => locals.get_mut(*v),
+            VarKind::Local(v) => locals.get_mut(v),
             VarKind::Global(v) => globals.get_mut(*v),
         }
     }
@@ -211,11 +489,11 @@ impl<'a> VarKind<'a> {
     pub fn set(
         &self,
         other: &Self,
-        locals: &mut Map<String, parser::VariableKind<'a>>,
+        locals: &mut parser::Variables<'a>,
         globals: &mut Map<String, parser::VariableKind<'a>>,
     ) {
         let value = match other {
-            VarKind::Local(v) => locals.get(*v),
+            VarKind::Local(v) => locals.get(v),
             VarKind::Global(v) => globals.get(*v),
         }
         .cloned()
@@ -228,7 +506,7 @@ impl<'a> VarKind<'a> {
 
     pub fn validate<'b>(
         &self,
-        locals: &[(&'a str, VariableKind)],
+        locals: &[(intern::Symbol, VariableKind)],
         globals: &[(&'a str, VariableKind)],
     ) -> bool {
         match self {
@@ -239,7 +517,7 @@ impl<'a> VarKind<'a> {
 }
 
 /// Commands that can be executed
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Commands<'a> {
     /// Compares two variables/numbers and executes rules if the comparison is true
     Compare {
@@ -252,6 +530,24 @@ pub enum Commands<'a> {
         /// Rules that will be executed if the comparison is true
         rules: Rules<'a>,
     },
+    /// Executes rules if the predicate registered by the host returns true,
+    /// see `parser::Parser::register_predicate`
+    External {
+        /// Name the predicate is registered under
+        name: &'a str,
+        /// Arguments passed to the predicate
+        args: Vec<&'a str>,
+        /// Rules that will be executed if the predicate returns true
+        rules: Rules<'a>,
+    },
+    /// Executes rules if the condition is true
+    ///
+    /// Compound conditions that would need nested `Compare` and `External` commands
+    If {
+        cond: Cond<'a>,
+        /// Rules that will be executed if the condition is true
+        rules: Rules<'a>,
+    },
     /// Returns an error from node
     Error {
         err: &'a ErrorDefinition,
@@ -271,6 +567,58 @@ pub enum Commands<'a> {
     Return,
     Start,
     End,
+    /// Sets a number variable to the value
+    SetNumber {
+        var: VarKind<'a>,
+        value: i32,
+    },
+    /// Sets a 64 bit number variable to the value
+    SetNumber64 {
+        var: VarKind<'a>,
+        value: i64,
+    },
+    /// Sets a float variable to the value
+    SetFloat {
+        var: VarKind<'a>,
+        value: f64,
+    },
+    /// Sets a boolean variable to the value
+    SetBool {
+        var: VarKind<'a>,
+        value: bool,
+    },
+}
+
+/// Condition of `Commands::If`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cond<'a> {
+    /// True if the comparison is, like `Commands::Compare`
+    Compare {
+        left: VarKind<'a>,
+        right: VarKind<'a>,
+        comparison: Comparison,
+    },
+    /// True if the predicate returns true, like `Commands::External`
+    External {
+        name: &'a str,
+        args: Vec<&'a str>,
+    },
+    /// True if all of the conditions are, checked in order until one is false
+    And(Vec<Cond<'a>>),
+    /// True if any of the conditions is, checked in order until one is true
+    Or(Vec<Cond<'a>>),
+    Not(Box<Cond<'a>>),
+}
+
+impl<'a> Cond<'a> {
+    /// Comparisons and predicates of the condition in order
+    pub fn leaves(&self) -> Vec<&Cond<'a>> {
+        match self {
+            Cond::Compare { .. } | Cond::External { .. } => vec![self],
+            Cond::And(conds) | Cond::Or(conds) => conds.iter().flat_map(Cond::leaves).collect(),
+            Cond::Not(cond) => cond.leaves(),
+        }
+    }
 }
 
 /// Comparison operators
@@ -293,31 +641,222 @@ pub enum Comparison {
 /// A token that will be matched
 ///
 /// Can be a token kind or a node name
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MatchToken<'a> {
     /// A token kind
     Token(TokenKinds<'a>),
     /// A node name
-    Node(&'a str),
+    Node(intern::Symbol),
     /// A constant word
     Word(&'a str),
     /// An enumerator
     Enumerator(&'a str),
+    /// A token declared with `Grammar::alias`
+    Alias(&'a str),
     /// Any token
     Any,
+    /// A token of exactly one character, see `Lexer::scannerless`
+    Char(char),
+    /// A token of one character from the class, see `Lexer::scannerless`
+    CharClass(CharClass),
+}
+
+/// Set of characters matched by `MatchToken::CharClass`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CharClass {
+    /// Characters from the first to the second, both included
+    Range(char, char),
+    Alphabetic,
+    Numeric,
+    Alphanumeric,
+    /// Whitespace including new lines
+    Whitespace,
+    /// ASCII punctuation
+    Punctuation,
+    /// Every character except the one, useful for the body of strings and heredocs
+    Not(char),
+}
+
+impl CharClass {
+    pub fn contains(&self, c: char) -> bool {
+        match self {
+            CharClass::Range(from, to) => (*from..=*to).contains(&c),
+            CharClass::Alphabetic => c.is_alphabetic(),
+            CharClass::Numeric => c.is_numeric(),
+            CharClass::Alphanumeric => c.is_alphanumeric(),
+            CharClass::Whitespace => c.is_whitespace(),
+            CharClass::Punctuation => c.is_ascii_punctuation(),
+            CharClass::Not(not) => c != *not,
+        }
+    }
 }
 
 /// A node is a collection of rules that will be executed when the node is matched
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Node<'a> {
     /// Name of the node
-    pub name: &'a str,
+    pub name: intern::Symbol,
     /// Rules that will be executed when the node is matched
     pub rules: Rules<'a>,
     /// Variables that can be used in the node and will be accessible from the outside
-    pub variables: Vec<(&'a str, VariableKind)>,
+    pub variables: Vec<(intern::Symbol, VariableKind)>,
     /// Documentation for the node
     pub docs: Option<&'a str>,
+    /// Text an editor inserts when the node is expected next
+    ///
+    /// Written in the snippet syntax of the language server protocol,
+    /// `let ${1:name} = ${2:value};`, see `crate::Parser::completions`
+    pub snippet: Option<&'a str>,
+    /// If true, the node will be reported as a folding range
+    ///
+    /// Useful for functions, blocks, lists and other regions an editor might collapse
+    pub foldable: bool,
+    /// If true, lines inside of the node are indented one level deeper than its first line
+    ///
+    /// The last line stays at the level of the node unless a child node covers it,
+    /// so closing brackets line up with the line that opened them.
+    /// See `ParseResult::indent_level_at`
+    pub indents_children: bool,
+    /// If set, the node will be reported in the document outline
+    pub symbol: Option<Symbol<'a>>,
+    /// First version of the language in which the node can be used
+    pub since: Option<LanguageVersion>,
+    /// Version of the language in which the node was removed
+    pub until: Option<LanguageVersion>,
+    /// If true, the parsed node keeps everything it matched in order in `children`
+    ///
+    /// Off by default since it clones every matched node once more
+    pub record_children: bool,
+    /// If true, the parsed node keeps the tokens it matched in order in `tokens`
+    ///
+    /// Lets formatters find punctuation like `:` or `=` without a variable for each
+    pub record_tokens: bool,
+    /// Words that `text()` does not match in the rules of this node
+    ///
+    /// Makes context keywords like `from` or `async` reserved only where the node
+    /// expects them, they stay plain identifiers in other nodes and in child nodes.
+    /// `word()` still matches them.
+    pub keywords: Vec<&'a str>,
+    /// Values the variables start with instead of zero or false
+    pub initial: Vec<(&'a str, InitialValue)>,
+    /// If true and the node is the entry, empty input parses to the node
+    /// with its initial variables without running its rules
+    ///
+    /// Otherwise input that contains only whitespace and ignored tokens
+    /// fails with `ParseErrors::EmptyInput`
+    pub allow_empty: bool,
+    /// Tokens that end the region skipped by error recovery when this node fails
+    /// in a `While` loop, for example `;` and `}` for a statement
+    ///
+    /// Used instead of `parser::Recovery::sync` if not empty
+    pub sync: Vec<TokenKinds<'a>>,
+}
+
+/// Initial value of a variable, see `Node::initial`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InitialValue {
+    Number(i32),
+    Number64(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+impl InitialValue {
+    /// Kind of variable the value can be assigned to
+    pub fn kind(&self) -> VariableKind {
+        match self {
+            InitialValue::Number(_) => VariableKind::Number,
+            InitialValue::Number64(_) => VariableKind::Number64,
+            InitialValue::Float(_) => VariableKind::Float,
+            InitialValue::Boolean(_) => VariableKind::Boolean,
+        }
+    }
+}
+
+impl<'a> Node<'a> {
+    /// Fenced code blocks of the docs, checked by `Validator::check_examples`
+    ///
+    /// Blocks marked with ```` ```ignore ```` are skipped
+    pub fn examples(&self) -> Vec<&'a str> {
+        let Some(docs) = self.docs else {
+            return Vec::new();
+        };
+        let mut examples = Vec::new();
+        let mut open: Option<(usize, bool)> = None;
+        let mut offset = 0;
+        for line in docs.split_inclusive('\n') {
+            let trimmed = line.trim();
+            if let Some(info) = trimmed.strip_prefix("```") {
+                match open.take() {
+                    Some((start, ignored)) => {
+                        if !ignored {
+                            examples.push(docs[start..offset].trim_end_matches(['\n', '\r']));
+                        }
+                    }
+                    None => open = Some((offset + line.len(), info.trim() == "ignore")),
+                }
+            }
+            offset += line.len();
+        }
+        examples
+    }
+}
+
+/// Version of the parsed language, set with `parser::Parser::version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LanguageVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl LanguageVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    /// Returns true if the version is in the range of the node
+    pub fn supports(&self, node: &Node) -> bool {
+        node.since.is_none_or(|since| *self >= since)
+            && node.until.is_none_or(|until| *self < until)
+    }
+}
+
+impl core::fmt::Display for LanguageVersion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Describes how a node appears in the document outline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    /// Node variable that holds the name of the symbol
+    pub name_from: &'a str,
+    /// Kind of the symbol
+    pub kind: SymbolKind,
+}
+
+/// Kinds of symbols in the document outline
+///
+/// Mirrors the most common symbol kinds used by language servers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Module,
+    Namespace,
+    Class,
+    Struct,
+    Enum,
+    EnumMember,
+    Interface,
+    Function,
+    Method,
+    Constructor,
+    Field,
+    Property,
+    Variable,
+    Constant,
+    Type,
+    Other,
 }
 
 /// A variable that can be used in a node
@@ -331,13 +870,33 @@ pub enum VariableKind {
     Boolean,
     /// Holds a number
     Number,
+    /// Holds a 64 bit number
+    ///
+    /// Useful for counters that can outgrow `Number`, like byte counts in large files
+    Number64,
+    /// Holds a floating point number
+    Float,
+}
+
+impl VariableKind {
+    /// Returns true for the kinds that can be counted and ordered
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            VariableKind::Number | VariableKind::Number64 | VariableKind::Float
+        )
+    }
 }
 
 /// Parameters that can be used on a rule if it is matched
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Parameters<'a> {
     /// Sets a variable to a value
     Set(VarKind<'a>),
+    /// Pushes the value to a local list variable and labels the entry with a field name
+    ///
+    /// Labeled entries can be read with `pairs_unchecked`
+    SetField(&'a str, &'a str),
     /// Adds 1 to a variable of type Count
     Increment(VarKind<'a>),
     /// Subtracts 1 from a variable of type Count
@@ -365,6 +924,11 @@ pub enum Parameters<'a> {
     ///
     /// This is useful for using nodes in optional rules
     Commit(bool),
+    /// Commits the node until the end of the rule block this rule is in
+    ///
+    /// Unlike `Commit`, the node can fail softly again once the block
+    /// matches, so only the rest of the block has to match
+    Cut,
     /// Sets the current node to the label with the given name
     Goto(&'a str),
     /// Hints to the parser that the node starts here
@@ -375,10 +939,33 @@ pub enum Parameters<'a> {
     NodeStart,
     /// Hints to the parser that the node ends here
     NodeEnd,
+    /// Lets a rule matching a token match the start of a longer token
+    ///
+    /// The rest of the token is left to the next rules, so `>` closes a generic
+    /// at `>>` and the next `>` matches the rest. Rules without the parameter
+    /// only match a rest that is exactly their token. See `lexer::Token::split`
+    Split,
     /// Display a hint inside an error message
     Hint(&'a str),
     /// Hints to the parser that this error should get priority
     Important,
+    /// Collects the doc comments in front of the node into its `docs` list variable
+    ///
+    /// Only whitespace may separate the comments from the node,
+    /// see `lexer::Lexer::doc_comments`
+    AttachDocs,
+    /// Declares the text of a node variable in the current scope, see `crate::scopes`
+    ///
+    /// For a list the last value is declared
+    Declare(VarKind<'a>),
+    /// Records the text of a node variable as a use of a declared name, see `crate::scopes`
+    ///
+    /// For a list the last value is used
+    Use(VarKind<'a>),
+    /// Opens a scope at the matched value
+    EnterScope,
+    /// Closes the current scope after the matched value
+    ExitScope,
     /// Rule results in a failure and displays message
     Fail(&'a ErrorDefinition),
 }
@@ -389,7 +976,7 @@ pub struct Enumerator<'a> {
     pub values: Vec<MatchToken<'a>>,
 }
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
 pub struct ErrorDefinition {
     pub header: &'static str,
     pub code: &'static str,
@@ -407,8 +994,6 @@ pub mod validator {
 
     use core::fmt::Display;
 
-    use smol_str::SmolStr;
-
     use super::*;
     use crate::{lexer::*, Parser};
 
@@ -419,6 +1004,11 @@ pub mod validator {
         pub allow_debug: bool,
         pub allow_any: bool,
         pub allow_back: bool,
+        /// Warn about constructs that are known to be slow, see `PerformanceLint`
+        pub performance_lints: bool,
+        /// Warn about lexer tokens the grammar never uses and words that are
+        /// also lexer tokens, see `Validator::lint_tokens`
+        pub token_lints: bool,
     }
 
     #[derive(Copy, Clone, Debug)]
@@ -437,44 +1027,732 @@ pub mod validator {
                 allow_debug: Default::default(),
                 allow_any: true,
                 allow_back: Default::default(),
+                performance_lints: false,
+                token_lints: false,
+            }
+        }
+    }
+
+    impl Default for TokenValidator {
+        fn default() -> Self {
+            Self {
+                max_chars: 3,
+                alow_numeric: false,
+                allow_non_ascii: false,
+                allow_whitespace: false,
+            }
+        }
+    }
+
+    impl Validator {
+        pub fn validate<'a, 'src>(&self, parser: &'a Parser<'a>) -> ValidationResult<'a> {
+            let mut result = ValidationResult::new();
+
+            self.validate_tokens(&parser.lexer, &mut result);
+            self.validate_grammar(parser, &mut result);
+            if self.performance_lints {
+                self.lint_performance(parser, &mut result);
+            }
+            if self.token_lints {
+                self.lint_tokens(parser, &mut result);
+            }
+
+            result
+        }
+    }
+
+    /// Shortest chain of nested `Maybe` rules reported by `PerformanceLint::MaybeChain`
+    pub const MAYBE_CHAIN: usize = 3;
+
+    /// Tokens, parameters and nested rule blocks of a rule
+    pub(crate) struct RuleParts<'r, 'a>(
+        pub(crate) Vec<&'r MatchToken<'a>>,
+        pub(crate) Vec<&'r Vec<Parameters<'a>>>,
+        pub(crate) Vec<&'r Rules<'a>>,
+    );
+
+    impl Validator {
+        /// Parses every example in the docs of the nodes with the node as entry
+        ///
+        /// Each example has to match the whole node, failures are reported as `ExampleFailed`
+        pub fn check_examples<'a>(&self, parser: &'a Parser<'a>) -> ValidationResult<'a> {
+            let mut result = ValidationResult::new();
+            let mut nodes = parser.grammar.all_nodes();
+            nodes.sort_by_key(|node| node.name);
+            for node in nodes {
+                for example in node.examples() {
+                    let mut example_parser = parser.parser.clone();
+                    example_parser.entry = Some(node.name.as_str());
+                    example_parser.eof_error = true;
+                    let error = match parser.lexer.lex_utf8(example) {
+                        Ok(tokens) => {
+                            let tokens = example_parser.filter_tokens(example, &tokens);
+                            match example_parser.parse(
+                                &parser.grammar,
+                                &parser.lexer,
+                                example,
+                                &tokens,
+                            ) {
+                                Ok(_) => continue,
+                                Err(err) => {
+                                    let lines = LineIndex::new(example);
+                                    let location =
+                                        parser.lexer.locate(&lines, err.span.start, err.span.len());
+                                    format!(
+                                        "{} at {}:{}",
+                                        err.kind.id_and_header().1,
+                                        location.line,
+                                        location.column
+                                    )
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            let lines = LineIndex::new(example);
+                            let location =
+                                parser.lexer.locate(&lines, err.span.start, err.span.len());
+                            format!(
+                                "{} at {}:{}",
+                                err.err.header, location.line, location.column
+                            )
+                        }
+                    };
+                    result.errors.push(ValidationError {
+                        origin: None,
+                        kind: ValidationErrors::ExampleFailed { example, error },
+                        node: Some(node),
+                    });
+                }
+            }
+            result
+        }
+
+        /// Checks that the node can be the entry of a parse, see `crate::Parser::parse_node_at`
+        ///
+        /// The node and every node it can reach are validated like `Validator::validate` does.
+        /// Globals they read but only other nodes set are reported as `GlobalSetElsewhere`
+        /// and `Goto`s to labels that are not in a block around them as `GotoOutOfScope`,
+        /// both would only show up in the middle of a parse.
+        /// Globals the host sets in the `ParseState` before the parse are not known here.
+        pub fn validate_entry<'a>(
+            &self,
+            parser: &'a Parser<'a>,
+            entry: &'a str,
+        ) -> ValidationResult<'a> {
+            let mut result = ValidationResult::new();
+            let Some(entry) = parser.grammar.node(entry) else {
+                result.errors.push(ValidationError {
+                    origin: None,
+                    kind: ValidationErrors::NodeNotFound(entry),
+                    node: None,
+                });
+                return result;
+            };
+            let reachable = Self::reachable(parser, entry);
+            let mut inside = GlobalUse::default();
+            for node in &reachable {
+                self.validate_node(node, parser, &mut result);
+                let mut declared = Vec::new();
+                Self::labels(&node.rules, &mut declared);
+                Self::goto_scope(&node.rules, &declared, &mut Vec::new(), node, &mut result);
+                inside.block(&node.rules, node);
+            }
+            let mut outside = GlobalUse::default();
+            for node in parser.grammar.all_nodes() {
+                if !reachable.iter().any(|found| found.name == node.name) {
+                    outside.block(&node.rules, node);
+                }
+            }
+            let mut reported = Vec::new();
+            for (name, node) in inside.read {
+                if !inside.set.contains(&name)
+                    && outside.set.contains(&name)
+                    && !reported.contains(&name)
+                {
+                    reported.push(name);
+                    result.errors.push(ValidationError {
+                        origin: None,
+                        kind: ValidationErrors::GlobalSetElsewhere(name),
+                        node: Some(node),
+                    });
+                }
+            }
+            result
+        }
+
+        /// The node and the nodes it can reach through rules, enumerators and aliases
+        fn reachable<'a>(parser: &'a Parser<'a>, entry: &'a Node<'a>) -> Vec<&'a Node<'a>> {
+            fn tokens<'a>(rules: &'a [Rule<'a>], found: &mut Vec<&'a MatchToken<'a>>) {
+                for rule in rules {
+                    let RuleParts(matches, _, blocks) = Validator::parts(rule);
+                    found.extend(matches);
+                    for rules in blocks {
+                        tokens(rules, found);
+                    }
+                }
+            }
+            let grammar = &parser.grammar;
+            let mut nodes = vec![entry];
+            let mut named: Vec<&str> = Vec::new();
+            let mut i = 0;
+            while i < nodes.len() {
+                let mut found = Vec::new();
+                tokens(&nodes[i].rules, &mut found);
+                while let Some(token) = found.pop() {
+                    match token {
+                        MatchToken::Node(name) => {
+                            if let Some(node) = grammar.node_of(*name) {
+                                if !nodes.iter().any(|known| known.name == node.name) {
+                                    nodes.push(node);
+                                }
+                            }
+                        }
+                        MatchToken::Enumerator(name) if !named.contains(name) => {
+                            named.push(name);
+                            if let Some(enumerator) = grammar.enumerators.get(*name) {
+                                found.extend(&enumerator.values);
+                            }
+                        }
+                        MatchToken::Alias(name) if !named.contains(name) => {
+                            named.push(name);
+                            found.extend(grammar.aliases.get(*name));
+                        }
+                        _ => (),
+                    }
+                }
+                i += 1;
+            }
+            nodes
+        }
+
+        /// Labels declared anywhere in the rules
+        fn labels<'a>(rules: &'a [Rule<'a>], found: &mut Vec<&'a str>) {
+            for rule in rules {
+                if let Rule::Command {
+                    command: Commands::Label { name },
+                    ..
+                } = rule
+                {
+                    found.push(name);
+                }
+                for rules in Self::parts(rule).2 {
+                    Self::labels(rules, found);
+                }
+            }
+        }
+
+        /// Reports `Goto`s to declared labels that are not in the block or a block around it
+        ///
+        /// `Goto` only searches the blocks it is in, from the innermost one out
+        fn goto_scope<'a>(
+            rules: &'a [Rule<'a>],
+            declared: &[&'a str],
+            labels: &mut Vec<&'a str>,
+            node: &'a Node<'a>,
+            result: &mut ValidationResult<'a>,
+        ) {
+            let outer = labels.len();
+            labels.extend(rules.iter().filter_map(|rule| match rule {
+                Rule::Command {
+                    command: Commands::Label { name },
+                    ..
+                } => Some(*name),
+                _ => None,
+            }));
+            for rule in rules {
+                let RuleParts(_, parameters, blocks) = Self::parts(rule);
+                let command = match rule {
+                    Rule::Command {
+                        command: Commands::Goto { label },
+                        ..
+                    } => Some(*label),
+                    _ => None,
+                };
+                let gotos = parameters
+                    .iter()
+                    .flat_map(|parameters| parameters.iter())
+                    .filter_map(|parameter| match parameter {
+                        Parameters::Goto(label) => Some(*label),
+                        _ => None,
+                    })
+                    .chain(command);
+                for label in gotos {
+                    if declared.contains(&label) && !labels.contains(&label) {
+                        result.errors.push(ValidationError {
+                            origin: Some(rule.origin()).filter(|origin| *origin != Origin::Unknown),
+                            kind: ValidationErrors::GotoOutOfScope(label),
+                            node: Some(node),
+                        });
+                    }
+                }
+                for rules in blocks {
+                    Self::goto_scope(rules, declared, labels, node, result);
+                }
+            }
+            labels.truncate(outer);
+        }
+
+        /// Flags constructs that are known to be slow
+        ///
+        /// Only runs if `Validator::performance_lints` is set
+        pub fn lint_performance<'a>(
+            &self,
+            parser: &'a Parser<'a>,
+            result: &mut ValidationResult<'a>,
+        ) {
+            for node in parser.grammar.all_nodes() {
+                let mut has_start = false;
+                Self::lint_block(&node.rules, node, false, false, &mut has_start, result);
+                if !has_start {
+                    result.warnings.push(ValidationWarning {
+                        kind: ValidationWarnings::Slow(PerformanceLint::MissingNodeStart),
+                        node: Some(node),
+                    });
+                }
+            }
+            for enumerator in parser.grammar.enumerators.values() {
+                let first_node = enumerator
+                    .values
+                    .iter()
+                    .position(|token| matches!(token, MatchToken::Node(_)));
+                if let Some(first_node) = first_node {
+                    if enumerator.values[first_node..]
+                        .iter()
+                        .any(|token| matches!(token, MatchToken::Token(_) | MatchToken::Word(_)))
+                    {
+                        result.warnings.push(ValidationWarning {
+                            kind: ValidationWarnings::Slow(PerformanceLint::NodeBeforeToken(
+                                enumerator.name,
+                            )),
+                            node: None,
+                        });
+                    }
+                }
+            }
+        }
+
+        /// Checks the lexer tokens against the grammar
+        ///
+        /// Tokens that no rule, enumerator, alias, bracket or sync list uses only slow
+        /// the lexer down and are reported as `UnusedToken`. Words that are also lexer
+        /// tokens are reported as `WordIsToken`, the lexer splits them off longer texts.
+        /// Token filters can use tokens the grammar does not, so the lint is opt-in.
+        ///
+        /// Only runs if `Validator::token_lints` is set
+        pub fn lint_tokens<'a>(&self, parser: &'a Parser<'a>, result: &mut ValidationResult<'a>) {
+            fn block<'a>(
+                rules: &'a [Rule<'a>],
+                node: Option<&'a Node<'a>>,
+                used: &mut Vec<(&'a MatchToken<'a>, Option<&'a Node<'a>>)>,
+            ) {
+                for rule in rules {
+                    let RuleParts(tokens, _, blocks) = Validator::parts(rule);
+                    used.extend(tokens.into_iter().map(|token| (token, node)));
+                    for rules in blocks {
+                        block(rules, node, used);
+                    }
+                }
+            }
+            let grammar = &parser.grammar;
+            let mut used = Vec::new();
+            let mut nodes = grammar.all_nodes();
+            nodes.sort_by_key(|node| node.name);
+            for node in nodes {
+                block(&node.rules, Some(node), &mut used);
+            }
+            let mut enumerators = grammar.enumerators.values().collect::<Vec<_>>();
+            enumerators.sort_by_key(|enumerator| enumerator.name);
+            for enumerator in enumerators {
+                used.extend(enumerator.values.iter().map(|token| (token, None)));
+            }
+            used.extend(grammar.aliases.values().map(|token| (token, None)));
+
+            let mut kinds = grammar
+                .all_nodes()
+                .into_iter()
+                .flat_map(|node| &node.sync)
+                .chain(&grammar.ignored)
+                .chain(
+                    grammar
+                        .brackets
+                        .iter()
+                        .flat_map(|(open, close)| [open, close]),
+                )
+                .collect::<Vec<_>>();
+            if let Some(recovery) = &parser.parser.recovery {
+                kinds.extend(&recovery.sync);
+            }
+            let mut words = Vec::new();
+            for (token, node) in used {
+                match token {
+                    MatchToken::Token(kind) => kinds.push(kind),
+                    MatchToken::Word(word)
+                        if !words.contains(word)
+                            && parser.lexer.token_kinds.iter().any(|token| token == word) =>
+                    {
+                        words.push(*word);
+                        result.warnings.push(ValidationWarning {
+                            kind: ValidationWarnings::WordIsToken(word),
+                            node,
+                        });
+                    }
+                    _ => (),
+                }
+            }
+
+            let mut reported: Vec<&str> = Vec::new();
+            for &token in parser.lexer.token_kinds.iter() {
+                if reported.contains(&token)
+                    || words.contains(&token)
+                    || kinds.contains(&&TokenKinds::Token(token))
+                {
+                    continue;
+                }
+                reported.push(token);
+                result.warnings.push(ValidationWarning {
+                    kind: ValidationWarnings::UnusedToken(token),
+                    node: None,
+                });
+            }
+        }
+
+        /// `chained` is true for the `isnt` block of a `Maybe`
+        fn lint_block<'a>(
+            rules: &'a [Rule<'a>],
+            node: &'a Node<'a>,
+            in_while: bool,
+            chained: bool,
+            has_start: &mut bool,
+            result: &mut ValidationResult<'a>,
+        ) {
+            for rule in rules {
+                let RuleParts(tokens, parameters, blocks) = Self::parts(rule);
+                let in_while = in_while || matches!(rule, Rule::While { .. });
+                if in_while && tokens.iter().any(|t| matches!(t, MatchToken::Any)) {
+                    result.warnings.push(ValidationWarning {
+                        kind: ValidationWarnings::Slow(PerformanceLint::AnyInWhile),
+                        node: Some(node),
+                    });
+                }
+                *has_start |= parameters
+                    .iter()
+                    .any(|params| params.iter().any(|p| matches!(p, Parameters::NodeStart)))
+                    || matches!(
+                        rule,
+                        Rule::Command {
+                            command: Commands::Start,
+                            ..
+                        }
+                    );
+                if let Rule::Maybe { is, isnt, .. } = rule {
+                    let mut depth = 1;
+                    let mut next = isnt;
+                    while let [Rule::Maybe { isnt, .. }] = next.as_slice() {
+                        depth += 1;
+                        next = isnt;
+                    }
+                    if depth >= MAYBE_CHAIN && !(chained && rules.len() == 1) {
+                        result.warnings.push(ValidationWarning {
+                            kind: ValidationWarnings::Slow(PerformanceLint::MaybeChain(depth)),
+                            node: Some(node),
+                        });
+                    }
+                    Self::lint_block(is, node, in_while, false, has_start, result);
+                    Self::lint_block(isnt, node, in_while, true, has_start, result);
+                    continue;
+                }
+                for block in blocks {
+                    Self::lint_block(block, node, in_while, false, has_start, result);
+                }
+            }
+        }
+
+        /// Tokens, parameters and nested rule blocks of a rule
+        pub(crate) fn parts<'r, 'a>(rule: &'r Rule<'a>) -> RuleParts<'r, 'a> {
+            match rule {
+                Rule::Is {
+                    token,
+                    rules,
+                    parameters,
+                    ..
+                }
+                | Rule::Isnt {
+                    token,
+                    rules,
+                    parameters,
+                    ..
+                }
+                | Rule::While {
+                    token,
+                    rules,
+                    parameters,
+                    ..
+                }
+                | Rule::Until {
+                    token,
+                    rules,
+                    parameters,
+                    ..
+                } => RuleParts(vec![token], vec![parameters], vec![rules]),
+                Rule::Maybe {
+                    token,
+                    is,
+                    isnt,
+                    parameters,
+                    ..
+                }
+                | Rule::Peek {
+                    token,
+                    is,
+                    isnt,
+                    parameters,
+                    ..
+                } => RuleParts(vec![token], vec![parameters], vec![is, isnt]),
+                Rule::IsOneOf {
+                    tokens, parameters, ..
+                } => RuleParts(
+                    tokens.iter().map(|o| &o.token).collect(),
+                    tokens
+                        .iter()
+                        .map(|o| &o.parameters)
+                        .chain([parameters])
+                        .collect(),
+                    tokens.iter().map(|o| &o.rules).collect(),
+                ),
+                Rule::MaybeOneOf {
+                    is_one_of, isnt, ..
+                } => RuleParts(
+                    is_one_of.iter().map(|o| &o.token).collect(),
+                    is_one_of.iter().map(|o| &o.parameters).collect(),
+                    is_one_of.iter().map(|o| &o.rules).chain([isnt]).collect(),
+                ),
+                Rule::UntilOneOf { tokens, .. } => RuleParts(
+                    tokens.iter().map(|o| &o.token).collect(),
+                    tokens.iter().map(|o| &o.parameters).collect(),
+                    tokens.iter().map(|o| &o.rules).collect(),
+                ),
+                Rule::Loop { rules, .. } => RuleParts(Vec::new(), Vec::new(), vec![rules]),
+                Rule::IfFeature {
+                    rules, else_rules, ..
+                } => RuleParts(Vec::new(), Vec::new(), vec![rules, else_rules]),
+                Rule::Command {
+                    command:
+                        Commands::Compare { rules, .. }
+                        | Commands::External { rules, .. }
+                        | Commands::If { rules, .. },
+                    ..
+                } => RuleParts(Vec::new(), Vec::new(), vec![rules]),
+                Rule::Command { .. } | Rule::Debug { .. } => {
+                    RuleParts(Vec::new(), Vec::new(), Vec::new())
+                }
+            }
+        }
+    }
+
+    /// Globals read and set by rules, see `Validator::validate_entry`
+    #[derive(Default)]
+    struct GlobalUse<'a> {
+        /// Globals with the node that reads them
+        read: Vec<(&'a str, &'a Node<'a>)>,
+        set: Vec<&'a str>,
+    }
+
+    impl<'a> GlobalUse<'a> {
+        fn block(&mut self, rules: &'a [Rule<'a>], node: &'a Node<'a>) {
+            for rule in rules {
+                let RuleParts(_, parameters, blocks) = Validator::parts(rule);
+                for parameter in parameters.into_iter().flatten() {
+                    match parameter {
+                        Parameters::Set(var)
+                        | Parameters::True(var)
+                        | Parameters::False(var)
+                        | Parameters::Increment(var)
+                        | Parameters::Decrement(var) => self.set(var),
+                        Parameters::CloneValue(from, to) => {
+                            self.read(from, node);
+                            self.set(to);
+                        }
+                        Parameters::Declare(var) | Parameters::Use(var) => self.read(var, node),
+                        _ => (),
+                    }
+                }
+                if let Rule::Command { command, .. } = rule {
+                    match command {
+                        Commands::Compare { left, right, .. } => {
+                            self.read(left, node);
+                            self.read(right, node);
+                        }
+                        Commands::If { cond, .. } => {
+                            for leaf in cond.leaves() {
+                                if let Cond::Compare { left, right, .. } = leaf {
+                                    self.read(left, node);
+                                    self.read(right, node);
+                                }
+                            }
+                        }
+                        Commands::SetNumber { var, .. }
+                        | Commands::SetNumber64 { var, .. }
+                        | Commands::SetFloat { var, .. }
+                        | Commands::SetBool { var, .. } => self.set(var),
+                        _ => (),
+                    }
+                }
+                for rules in blocks {
+                    self.block(rules, node);
+                }
+            }
+        }
+
+        fn read(&mut self, var: &VarKind<'a>, node: &'a Node<'a>) {
+            if let VarKind::Global(name) = var {
+                self.read.push((name, node));
+            }
+        }
+
+        fn set(&mut self, var: &VarKind<'a>) {
+            if let VarKind::Global(name) = var {
+                self.set.push(name);
+            }
+        }
+    }
+
+    /// Finds `Node` variables that can be set more than once along a single path
+    ///
+    /// Each set replaces the previous node, which is rarely intended
+    struct Overwrites<'a, 'v> {
+        locals: &'v [(intern::Symbol, VariableKind)],
+        globals: &'v [(&'a str, VariableKind)],
+        /// Variables set on the current path
+        set: Vec<VarKind<'a>>,
+        found: Vec<VarKind<'a>>,
+    }
+
+    impl<'a> Overwrites<'a, '_> {
+        fn parameters(&mut self, parameters: &[Parameters<'a>], in_loop: bool) {
+            for parameter in parameters {
+                let Parameters::Set(var) = parameter else {
+                    continue;
+                };
+                if var.kind(self.locals, self.globals) != Some(VariableKind::Node) {
+                    continue;
+                }
+                if (in_loop || self.set.contains(var)) && !self.found.contains(var) {
+                    self.found.push(*var);
+                }
+                if !self.set.contains(var) {
+                    self.set.push(*var);
+                }
             }
         }
-    }
 
-    impl Default for TokenValidator {
-        fn default() -> Self {
-            Self {
-                max_chars: 3,
-                alow_numeric: false,
-                allow_non_ascii: false,
-                allow_whitespace: false,
+        /// Walks the alternatives from the same starting point and keeps
+        /// every variable set by any of them
+        fn branches(&mut self, branches: &[(&[Parameters<'a>], &[Rule<'a>])], in_loop: bool) {
+            let start = self.set.clone();
+            let mut set = start.clone();
+            for (parameters, rules) in branches {
+                self.set = start.clone();
+                self.parameters(parameters, in_loop);
+                self.block(rules, in_loop);
+                for var in self.set.drain(..) {
+                    if !set.contains(&var) {
+                        set.push(var);
+                    }
+                }
             }
+            self.set = set;
         }
-    }
-
-    impl Validator {
-        pub fn validate<'a, 'src>(&self, parser: &'a Parser<'a>) -> ValidationResult<'a> {
-            let mut result = ValidationResult::new();
 
-            self.validate_tokens(&parser.lexer, &mut result);
-            self.validate_grammar(parser, &mut result);
-
-            result
+        fn block(&mut self, rules: &[Rule<'a>], in_loop: bool) {
+            for rule in rules {
+                match rule {
+                    Rule::Is {
+                        rules, parameters, ..
+                    }
+                    | Rule::Isnt {
+                        rules, parameters, ..
+                    }
+                    | Rule::Until {
+                        rules, parameters, ..
+                    } => {
+                        self.parameters(parameters, in_loop);
+                        self.block(rules, in_loop);
+                    }
+                    Rule::While {
+                        rules, parameters, ..
+                    } => {
+                        self.parameters(parameters, true);
+                        self.block(rules, true);
+                    }
+                    Rule::Loop { rules, .. } => self.block(rules, true),
+                    Rule::Maybe {
+                        is,
+                        isnt,
+                        parameters,
+                        ..
+                    }
+                    | Rule::Peek {
+                        is,
+                        isnt,
+                        parameters,
+                        ..
+                    } => self.branches(&[(parameters, is), (&[], isnt)], in_loop),
+                    Rule::IsOneOf {
+                        tokens, parameters, ..
+                    } => {
+                        let branches = tokens
+                            .iter()
+                            .map(|option| (option.parameters.as_slice(), option.rules.as_slice()))
+                            .collect::<Vec<_>>();
+                        self.branches(&branches, in_loop);
+                        self.parameters(parameters, in_loop);
+                    }
+                    Rule::MaybeOneOf {
+                        is_one_of, isnt, ..
+                    } => {
+                        let mut branches = is_one_of
+                            .iter()
+                            .map(|option| (option.parameters.as_slice(), option.rules.as_slice()))
+                            .collect::<Vec<_>>();
+                        branches.push((&[], isnt));
+                        self.branches(&branches, in_loop);
+                    }
+                    Rule::UntilOneOf { tokens, .. } => {
+                        let branches = tokens
+                            .iter()
+                            .map(|option| (option.parameters.as_slice(), option.rules.as_slice()))
+                            .collect::<Vec<_>>();
+                        self.branches(&branches, in_loop);
+                    }
+                    Rule::IfFeature {
+                        rules, else_rules, ..
+                    } => self.branches(&[(&[], rules), (&[], else_rules)], in_loop),
+                    Rule::Command {
+                        command:
+                            Commands::Compare { rules, .. }
+                            | Commands::External { rules, .. }
+                            | Commands::If { rules, .. },
+                        ..
+                    } => self.branches(&[(&[], rules), (&[], &[])], in_loop),
+                    Rule::Command { .. } | Rule::Debug { .. } => (),
+                }
+            }
         }
     }
 
     impl Validator {
         fn validate_tokens<'a>(&self, lexer: &'a Lexer, result: &mut ValidationResult<'a>) {
-            let mut tokens: Vec<SmolStr> = Vec::new();
-            for token in &lexer.token_kinds {
+            let mut tokens: Vec<&str> = Vec::new();
+            for token in lexer.token_kinds.iter() {
                 // tokens that have already been validated can be ignored
                 if tokens.contains(token) {
                     continue;
                 }
-                tokens.push(token.clone());
+                tokens.push(token);
                 // check for collisions
                 if lexer.token_kinds.iter().filter(|t| *t == token).count() > 1 {
                     result.errors.push(ValidationError {
+                        origin: None,
                         kind: ValidationErrors::TokenCollision(token),
                         node: None,
                     });
@@ -482,6 +1760,7 @@ pub mod validator {
                 // check if token is empty
                 if token.is_empty() {
                     result.errors.push(ValidationError {
+                        origin: None,
                         kind: ValidationErrors::EmptyToken,
                         node: None,
                     });
@@ -532,7 +1811,7 @@ pub mod validator {
             parser: &'a Parser<'a>,
             result: &mut ValidationResult<'a>,
         ) {
-            for (_, node) in parser.grammar.nodes.iter() {
+            for node in parser.grammar.all_nodes() {
                 self.validate_node(node, parser, result);
             }
         }
@@ -548,11 +1827,221 @@ pub mod validator {
                 self.validate_rule(rule, node, parser, &mut laf, result);
             }
             laf.pass(result, node);
+            if let Some(symbol) = &node.symbol {
+                let name = VarKind::Local(symbol.name_from);
+                match name.kind(&node.variables, &parser.grammar.globals) {
+                    Some(VariableKind::Node) => (),
+                    Some(_) => result.errors.push(ValidationError {
+                        origin: None,
+                        kind: ValidationErrors::CantUseVariable(name),
+                        node: Some(node),
+                    }),
+                    None => result.errors.push(ValidationError {
+                        origin: None,
+                        kind: ValidationErrors::VariableNotFound(name),
+                        node: Some(node),
+                    }),
+                }
+            }
+            for (name, value) in &node.initial {
+                let kind = match node.variables.iter().find(|(var, _)| var == name) {
+                    Some((_, kind)) if *kind == value.kind() => continue,
+                    Some(_) => ValidationErrors::InitialValueMismatch(name, value.kind()),
+                    None => ValidationErrors::VariableNotFound(VarKind::Local(name)),
+                };
+                result.errors.push(ValidationError {
+                    origin: None,
+                    kind,
+                    node: Some(node),
+                });
+            }
+            let mut overwrites = Overwrites {
+                locals: &node.variables,
+                globals: &parser.grammar.globals,
+                set: Vec::new(),
+                found: Vec::new(),
+            };
+            overwrites.block(&node.rules, false);
+            for var in overwrites.found {
+                result.warnings.push(ValidationWarning {
+                    kind: ValidationWarnings::NodeOverwritten(var),
+                    node: Some(node),
+                });
+            }
+        }
+
+        /// Checks that both sides exist and can be compared with the operator
+        fn validate_comparison<'a>(
+            &self,
+            left: &VarKind<'a>,
+            right: &VarKind<'a>,
+            op: &Comparison,
+            node: &'a Node<'a>,
+            parser: &'a Parser<'a>,
+            result: &mut ValidationResult<'a>,
+        ) {
+            use VarKind::*;
+            let l = match left {
+                Local(ll) => {
+                    match node
+                        .variables
+                        .iter()
+                        .find(|(id, _)| id == ll)
+                        .map(|(_, kind)| kind)
+                    {
+                        None => {
+                            result.errors.push(ValidationError {
+                                origin: None,
+                                kind: ValidationErrors::VariableNotFound(*left),
+                                node: Some(node),
+                            });
+                            None
+                        }
+                        some => some,
+                    }
+                }
+                Global(gl) => {
+                    match parser
+                        .grammar
+                        .globals
+                        .iter()
+                        .find(|(id, _)| id == gl)
+                        .map(|(_, kind)| kind)
+                    {
+                        None => {
+                            result.errors.push(ValidationError {
+                                origin: None,
+                                kind: ValidationErrors::VariableNotFound(*left),
+                                node: Some(node),
+                            });
+                            None
+                        }
+                        some => some,
+                    }
+                }
+            };
+            let r = match right {
+                Local(lr) => {
+                    match node
+                        .variables
+                        .iter()
+                        .find(|(id, _)| id == lr)
+                        .map(|(_, kind)| kind)
+                    {
+                        None => {
+                            result.errors.push(ValidationError {
+                                origin: None,
+                                kind: ValidationErrors::VariableNotFound(*right),
+                                node: Some(node),
+                            });
+                            None
+                        }
+                        some => some,
+                    }
+                }
+                Global(gr) => {
+                    match parser
+                        .grammar
+                        .globals
+                        .iter()
+                        .find(|(id, _)| id == gr)
+                        .map(|(_, kind)| kind)
+                    {
+                        None => {
+                            result.errors.push(ValidationError {
+                                origin: None,
+                                kind: ValidationErrors::VariableNotFound(*right),
+                                node: Some(node),
+                            });
+                            None
+                        }
+                        some => some,
+                    }
+                }
+            };
+            match (l, r, op) {
+                (
+                    Some(VariableKind::Boolean),
+                    Some(VariableKind::Boolean),
+                    Comparison::Equal | Comparison::NotEqual,
+                ) => (),
+                (
+                    Some(VariableKind::Node),
+                    Some(VariableKind::Node),
+                    Comparison::Equal | Comparison::NotEqual,
+                ) => (),
+                (Some(l), Some(r), _) if l.is_numeric() && r.is_numeric() => (),
+                (None, None, _) | (None, Some(_), _) | (Some(_), None, _) => (),
+                _ => result.errors.push(ValidationError {
+                    origin: None,
+                    kind: ValidationErrors::ComparisonInvalid(*left, *right, *op),
+                    node: Some(node),
+                }),
+            }
+        }
+
+        fn validate_predicate<'a>(
+            name: &'a str,
+            node: &'a Node<'a>,
+            parser: &'a Parser<'a>,
+            result: &mut ValidationResult<'a>,
+        ) {
+            if !parser.parser.predicates.contains_key(name) {
+                result.errors.push(ValidationError {
+                    origin: None,
+                    kind: ValidationErrors::PredicateNotFound(name),
+                    node: Some(node),
+                });
+            }
+        }
+
+        /// Checks that the variable exists and is of the kind
+        fn validate_var_kind<'a>(
+            &self,
+            var: VarKind<'a>,
+            kind: VariableKind,
+            node: &'a Node<'a>,
+            parser: &'a Parser<'a>,
+            result: &mut ValidationResult<'a>,
+        ) {
+            match var.kind(&node.variables, &parser.grammar.globals) {
+                Some(found) if found == kind => (),
+                Some(_) => result.errors.push(ValidationError {
+                    origin: None,
+                    kind: ValidationErrors::CantUseVariable(var),
+                    node: Some(node),
+                }),
+                None => result.errors.push(ValidationError {
+                    origin: None,
+                    kind: ValidationErrors::VariableNotFound(var),
+                    node: Some(node),
+                }),
+            }
         }
 
         pub fn validate_rule<'a>(
             &self,
-            rule: &'a Rule,
+            rule: &'a Rule<'a>,
+            node: &'a Node<'a>,
+            parser: &'a Parser<'a>,
+            laf: &mut LostAndFound<'a>,
+            result: &mut ValidationResult<'a>,
+        ) {
+            let errors = result.errors.len();
+            self.validate_rule_kind(rule, node, parser, laf, result);
+            let origin = rule.origin();
+            if origin == Origin::Unknown {
+                return;
+            }
+            // errors of nested rules already point at the nested rule
+            for error in &mut result.errors[errors..] {
+                error.origin.get_or_insert(origin);
+            }
+        }
+
+        fn validate_rule_kind<'a>(
+            &self,
+            rule: &'a Rule<'a>,
             node: &'a Node<'a>,
             parser: &'a Parser<'a>,
             laf: &mut LostAndFound<'a>,
@@ -563,6 +2052,7 @@ pub mod validator {
                     token,
                     rules,
                     parameters,
+                    ..
                 } => {
                     self.validate_token(token, node, parser, result);
                     self.validate_parameters(parameters, parser, node, laf, result);
@@ -572,12 +2062,15 @@ pub mod validator {
                     token,
                     rules,
                     parameters,
+                    ..
                 } => {
                     self.validate_token(token, node, parser, result);
                     self.validate_parameters(parameters, parser, node, laf, result);
                     self.validate_ruleblock(rules, node, parser, laf, result)
                 }
-                Rule::IsOneOf { tokens, parameters } => {
+                Rule::IsOneOf {
+                    tokens, parameters, ..
+                } => {
                     for one_of in tokens {
                         self.validate_token(&one_of.token, node, parser, result);
                         self.validate_parameters(&one_of.parameters, parser, node, laf, result);
@@ -590,13 +2083,16 @@ pub mod validator {
                     is,
                     isnt,
                     parameters,
+                    ..
                 } => {
                     self.validate_token(token, node, parser, result);
                     self.validate_parameters(parameters, parser, node, laf, result);
                     self.validate_ruleblock(is, node, parser, laf, result);
                     self.validate_ruleblock(isnt, node, parser, laf, result);
                 }
-                Rule::MaybeOneOf { is_one_of, isnt } => {
+                Rule::MaybeOneOf {
+                    is_one_of, isnt, ..
+                } => {
                     for OneOf {
                         token,
                         rules,
@@ -613,22 +2109,42 @@ pub mod validator {
                     token,
                     rules,
                     parameters,
+                    ..
                 } => {
                     self.validate_token(token, node, parser, result);
                     self.validate_parameters(parameters, parser, node, laf, result);
                     self.validate_ruleblock(rules, node, parser, laf, result)
                 }
-                Rule::Loop { rules } => self.validate_ruleblock(rules, node, parser, laf, result),
+                Rule::Loop { rules, .. } => {
+                    self.validate_ruleblock(rules, node, parser, laf, result)
+                }
+                Rule::IfFeature {
+                    name,
+                    rules,
+                    else_rules,
+                    ..
+                } => {
+                    if !parser.grammar.features.contains_key(*name) {
+                        result.errors.push(ValidationError {
+                            origin: None,
+                            kind: ValidationErrors::FeatureNotFound(name),
+                            node: Some(node),
+                        });
+                    }
+                    self.validate_ruleblock(rules, node, parser, laf, result);
+                    self.validate_ruleblock(else_rules, node, parser, laf, result);
+                }
                 Rule::Until {
                     token,
                     rules,
                     parameters,
+                    ..
                 } => {
                     self.validate_token(token, node, parser, result);
                     self.validate_parameters(parameters, parser, node, laf, result);
                     self.validate_ruleblock(rules, node, parser, laf, result)
                 }
-                Rule::UntilOneOf { tokens } => {
+                Rule::UntilOneOf { tokens, .. } => {
                     for one_of in tokens {
                         self.validate_token(&one_of.token, node, parser, result);
                         self.validate_parameters(&one_of.parameters, parser, node, laf, result);
@@ -640,111 +2156,46 @@ pub mod validator {
                     is,
                     isnt,
                     parameters,
+                    ..
                 } => {
                     self.validate_token(token, node, parser, result);
                     self.validate_ruleblock(is, node, parser, laf, result);
                     self.validate_ruleblock(isnt, node, parser, laf, result);
                     self.validate_parameters(parameters, parser, node, laf, result);
                 }
-                Rule::Command { command } => match command {
+                Rule::Command { command, .. } => match command {
                     Commands::Compare {
                         left,
                         right,
                         comparison: op,
                         rules,
                     } => {
-                        use VarKind::*;
-                        let l = match left {
-                            Local(ll) => {
-                                match node
-                                    .variables
-                                    .iter()
-                                    .find(|(id, _)| id == ll)
-                                    .map(|(_, kind)| kind)
-                                {
-                                    None => {
-                                        result.errors.push(ValidationError {
-                                            kind: ValidationErrors::VariableNotFound(*left),
-                                            node: Some(node),
-                                        });
-                                        None
-                                    }
-                                    some => some,
-                                }
-                            }
-                            Global(gl) => {
-                                match parser
-                                    .grammar
-                                    .globals
-                                    .iter()
-                                    .find(|(id, _)| id == gl)
-                                    .map(|(_, kind)| kind)
-                                {
-                                    None => {
-                                        result.errors.push(ValidationError {
-                                            kind: ValidationErrors::VariableNotFound(*left),
-                                            node: Some(node),
-                                        });
-                                        None
-                                    }
-                                    some => some,
-                                }
-                            }
-                        };
-                        let r = match right {
-                            Local(lr) => {
-                                match node
-                                    .variables
-                                    .iter()
-                                    .find(|(id, _)| id == lr)
-                                    .map(|(_, kind)| kind)
-                                {
-                                    None => {
-                                        result.errors.push(ValidationError {
-                                            kind: ValidationErrors::VariableNotFound(*right),
-                                            node: Some(node),
-                                        });
-                                        None
-                                    }
-                                    some => some,
-                                }
-                            }
-                            Global(gr) => {
-                                match parser
-                                    .grammar
-                                    .globals
-                                    .iter()
-                                    .find(|(id, _)| id == gr)
-                                    .map(|(_, kind)| kind)
-                                {
-                                    None => {
-                                        result.errors.push(ValidationError {
-                                            kind: ValidationErrors::VariableNotFound(*right),
-                                            node: Some(node),
-                                        });
-                                        None
-                                    }
-                                    some => some,
+                        self.validate_comparison(left, right, op, node, parser, result);
+                        for rule in rules {
+                            self.validate_rule(rule, node, parser, laf, result);
+                        }
+                    }
+                    Commands::External { name, rules, .. } => {
+                        Self::validate_predicate(name, node, parser, result);
+                        for rule in rules {
+                            self.validate_rule(rule, node, parser, laf, result);
+                        }
+                    }
+                    Commands::If { cond, rules } => {
+                        for leaf in cond.leaves() {
+                            match leaf {
+                                Cond::Compare {
+                                    left,
+                                    right,
+                                    comparison,
+                                } => self.validate_comparison(
+                                    left, right, comparison, node, parser, result,
+                                ),
+                                Cond::External { name, .. } => {
+                                    Self::validate_predicate(name, node, parser, result)
                                 }
+                                Cond::And(_) | Cond::Or(_) | Cond::Not(_) => (),
                             }
-                        };
-                        match (l, r, op) {
-                            (
-                                Some(VariableKind::Boolean),
-                                Some(VariableKind::Boolean),
-                                Comparison::Equal | Comparison::NotEqual,
-                            ) => (),
-                            (
-                                Some(VariableKind::Node),
-                                Some(VariableKind::Node),
-                                Comparison::Equal | Comparison::NotEqual,
-                            ) => (),
-                            (Some(VariableKind::Number), Some(VariableKind::Number), _) => (),
-                            (None, None, _) | (None, Some(_), _) | (Some(_), None, _) => (),
-                            _ => result.errors.push(ValidationError {
-                                kind: ValidationErrors::ComparisonInvalid(*left, *right, *op),
-                                node: Some(node),
-                            }),
                         }
                         for rule in rules {
                             self.validate_rule(rule, node, parser, laf, result);
@@ -758,6 +2209,7 @@ pub mod validator {
                     Commands::Label { name } => {
                         if laf.found_labels.contains(name) {
                             result.errors.push(ValidationError {
+                                origin: None,
                                 kind: ValidationErrors::DuplicateLabel(name),
                                 node: Some(node),
                             });
@@ -768,11 +2220,24 @@ pub mod validator {
                     Commands::Return => (),
                     Commands::Start => (),
                     Commands::End => (),
+                    Commands::SetNumber { var, .. } => {
+                        self.validate_var_kind(*var, VariableKind::Number, node, parser, result);
+                    }
+                    Commands::SetNumber64 { var, .. } => {
+                        self.validate_var_kind(*var, VariableKind::Number64, node, parser, result);
+                    }
+                    Commands::SetFloat { var, .. } => {
+                        self.validate_var_kind(*var, VariableKind::Float, node, parser, result);
+                    }
+                    Commands::SetBool { var, .. } => {
+                        self.validate_var_kind(*var, VariableKind::Boolean, node, parser, result);
+                    }
                 },
-                Rule::Debug { target } => {
+                Rule::Debug { target, .. } => {
                     if let Some(name) = target {
                         if !name.validate(&node.variables, &parser.grammar.globals) {
                             result.errors.push(ValidationError {
+                                origin: None,
                                 kind: ValidationErrors::VariableNotFound(*name),
                                 node: Some(node),
                             });
@@ -807,9 +2272,10 @@ pub mod validator {
         ) {
             match token {
                 MatchToken::Node(name) => {
-                    if parser.grammar.nodes.get(*name).is_none() {
+                    if parser.grammar.node_of(*name).is_none() {
                         result.errors.push(ValidationError {
-                            kind: ValidationErrors::NodeNotFound(name),
+                            origin: None,
+                            kind: ValidationErrors::NodeNotFound(name.as_str()),
                             node: Some(node),
                         });
                     }
@@ -817,11 +2283,24 @@ pub mod validator {
                 MatchToken::Enumerator(enumerator) => {
                     if !parser.grammar.enumerators.contains_key(*enumerator) {
                         result.errors.push(ValidationError {
+                            origin: None,
                             kind: ValidationErrors::EnumeratorNotFound(enumerator),
                             node: Some(node),
                         });
                     }
                 }
+                MatchToken::Alias(name) => {
+                    let kind = match parser.grammar.resolve_alias(name) {
+                        Ok(token) => return self.validate_token(token, node, parser, result),
+                        Err(AliasError::NotFound(alias)) => ValidationErrors::AliasNotFound(alias),
+                        Err(AliasError::Cycle(alias)) => ValidationErrors::AliasCycle(alias),
+                    };
+                    result.errors.push(ValidationError {
+                        origin: None,
+                        kind,
+                        node: Some(node),
+                    });
+                }
                 MatchToken::Any => {
                     if !self.allow_any {
                         result.warnings.push(ValidationWarning {
@@ -834,6 +2313,7 @@ pub mod validator {
                     if let TokenKinds::Token(txt) = kind {
                         if txt.is_empty() {
                             result.errors.push(ValidationError {
+                                origin: None,
                                 kind: ValidationErrors::EmptyToken,
                                 node: Some(node),
                             });
@@ -842,6 +2322,7 @@ pub mod validator {
                         // check if token is in the lexer
                         if !parser.lexer.token_kinds.iter().any(|k| k == txt) {
                             result.errors.push(ValidationError {
+                                origin: None,
                                 kind: ValidationErrors::TokenNotFound(txt),
                                 node: Some(node),
                             });
@@ -867,25 +2348,42 @@ pub mod validator {
                             Some(_) => (),
                             None => {
                                 result.errors.push(ValidationError {
+                                    origin: None,
                                     kind: ValidationErrors::VariableNotFound(*name),
                                     node: Some(node),
                                 });
                             }
                         }
                     }
-                    Parameters::Increment(name) => {
+                    Parameters::SetField(name, _) => {
+                        let name = VarKind::Local(name);
                         match name.kind(&node.variables, &parser.grammar.globals) {
-                            Some(var) => match var {
-                                VariableKind::Number => (),
-                                VariableKind::Node
-                                | VariableKind::NodeList
-                                | VariableKind::Boolean => result.errors.push(ValidationError {
-                                    kind: ValidationErrors::CantUseVariable(*name),
+                            Some(VariableKind::NodeList) => (),
+                            Some(_) => result.errors.push(ValidationError {
+                                origin: None,
+                                kind: ValidationErrors::CantUseVariable(name),
+                                node: Some(node),
+                            }),
+                            None => {
+                                result.errors.push(ValidationError {
+                                    origin: None,
+                                    kind: ValidationErrors::VariableNotFound(name),
                                     node: Some(node),
-                                }),
-                            },
+                                });
+                            }
+                        }
+                    }
+                    Parameters::Increment(name) => {
+                        match name.kind(&node.variables, &parser.grammar.globals) {
+                            Some(var) if var.is_numeric() => (),
+                            Some(_) => result.errors.push(ValidationError {
+                                origin: None,
+                                kind: ValidationErrors::CantUseVariable(*name),
+                                node: Some(node),
+                            }),
                             None => {
                                 result.errors.push(ValidationError {
+                                    origin: None,
                                     kind: ValidationErrors::VariableNotFound(*name),
                                     node: Some(node),
                                 });
@@ -894,17 +2392,15 @@ pub mod validator {
                     }
                     Parameters::Decrement(name) => {
                         match name.kind(&node.variables, &parser.grammar.globals) {
-                            Some(var) => match var {
-                                VariableKind::Number => (),
-                                VariableKind::Node
-                                | VariableKind::NodeList
-                                | VariableKind::Boolean => result.errors.push(ValidationError {
-                                    kind: ValidationErrors::CantUseVariable(*name),
-                                    node: Some(node),
-                                }),
-                            },
+                            Some(var) if var.is_numeric() => (),
+                            Some(_) => result.errors.push(ValidationError {
+                                origin: None,
+                                kind: ValidationErrors::CantUseVariable(*name),
+                                node: Some(node),
+                            }),
                             None => {
                                 result.errors.push(ValidationError {
+                                    origin: None,
                                     kind: ValidationErrors::VariableNotFound(*name),
                                     node: Some(node),
                                 });
@@ -917,13 +2413,17 @@ pub mod validator {
                                 VariableKind::Boolean => (),
                                 VariableKind::Node
                                 | VariableKind::NodeList
-                                | VariableKind::Number => result.errors.push(ValidationError {
+                                | VariableKind::Number
+                                | VariableKind::Number64
+                                | VariableKind::Float => result.errors.push(ValidationError {
+                                    origin: None,
                                     kind: ValidationErrors::CantUseVariable(*name),
                                     node: Some(node),
                                 }),
                             },
                             None => {
                                 result.errors.push(ValidationError {
+                                    origin: None,
                                     kind: ValidationErrors::VariableNotFound(*name),
                                     node: Some(node),
                                 });
@@ -936,13 +2436,17 @@ pub mod validator {
                                 VariableKind::Boolean => (),
                                 VariableKind::Node
                                 | VariableKind::NodeList
-                                | VariableKind::Number => result.errors.push(ValidationError {
+                                | VariableKind::Number
+                                | VariableKind::Number64
+                                | VariableKind::Float => result.errors.push(ValidationError {
+                                    origin: None,
                                     kind: ValidationErrors::CantUseVariable(*name),
                                     node: Some(node),
                                 }),
                             },
                             None => {
                                 result.errors.push(ValidationError {
+                                    origin: None,
                                     kind: ValidationErrors::VariableNotFound(*name),
                                     node: Some(node),
                                 });
@@ -963,6 +2467,7 @@ pub mod validator {
                                 Some(_) => (),
                                 None => {
                                     result.errors.push(ValidationError {
+                                        origin: None,
                                         kind: ValidationErrors::VariableNotFound(*name),
                                         node: Some(node),
                                     });
@@ -985,6 +2490,7 @@ pub mod validator {
                         }
                         if *n as usize > laf.steps {
                             result.errors.push(ValidationError {
+                                origin: None,
                                 kind: ValidationErrors::CannotGoBackMoreThan {
                                     steps: *n as usize,
                                     max: laf.steps,
@@ -996,13 +2502,52 @@ pub mod validator {
                     Parameters::Return => (),
                     Parameters::Break(_) => (),
                     Parameters::Commit(_) => (),
+                    Parameters::Cut => (),
                     Parameters::Goto(label) => {
                         laf.lost_labels.push(label);
                     }
                     Parameters::NodeStart => (),
                     Parameters::NodeEnd => (),
+                    Parameters::Split => (),
                     Parameters::Hint(_) => (),
                     Parameters::Important => (),
+                    Parameters::Declare(name) | Parameters::Use(name) => {
+                        match name.kind(&node.variables, &parser.grammar.globals) {
+                            Some(VariableKind::Node | VariableKind::NodeList) => (),
+                            Some(_) => result.errors.push(ValidationError {
+                                origin: None,
+                                kind: ValidationErrors::CantUseVariable(*name),
+                                node: Some(node),
+                            }),
+                            None => {
+                                result.errors.push(ValidationError {
+                                    origin: None,
+                                    kind: ValidationErrors::VariableNotFound(*name),
+                                    node: Some(node),
+                                });
+                            }
+                        }
+                    }
+                    Parameters::EnterScope => (),
+                    Parameters::ExitScope => (),
+                    Parameters::AttachDocs => {
+                        let name = VarKind::Local("docs");
+                        match name.kind(&node.variables, &parser.grammar.globals) {
+                            Some(VariableKind::NodeList) => (),
+                            Some(_) => result.errors.push(ValidationError {
+                                origin: None,
+                                kind: ValidationErrors::CantUseVariable(name),
+                                node: Some(node),
+                            }),
+                            None => {
+                                result.errors.push(ValidationError {
+                                    origin: None,
+                                    kind: ValidationErrors::VariableNotFound(name),
+                                    node: Some(node),
+                                });
+                            }
+                        }
+                    }
                     Parameters::CloneValue(var1, var2) => {
                         match (
                             var1.kind(&node.variables, &parser.grammar.globals),
@@ -1010,6 +2555,7 @@ pub mod validator {
                         ) {
                             (Some(t1), Some(t2)) if t1 == t2 => (),
                             (Some(t1), Some(t2)) => result.errors.push(ValidationError {
+                                origin: None,
                                 kind: ValidationErrors::VariableTypeMismatch(
                                     (*var1, t1),
                                     (*var2, t2),
@@ -1017,19 +2563,23 @@ pub mod validator {
                                 node: Some(node),
                             }),
                             (Some(_), None) => result.errors.push(ValidationError {
+                                origin: None,
                                 kind: ValidationErrors::VariableNotFound(*var2),
                                 node: Some(node),
                             }),
                             (None, Some(_)) => result.errors.push(ValidationError {
+                                origin: None,
                                 kind: ValidationErrors::VariableNotFound(*var1),
                                 node: Some(node),
                             }),
                             (None, None) => {
                                 result.errors.push(ValidationError {
+                                    origin: None,
                                     kind: ValidationErrors::VariableNotFound(*var1),
                                     node: Some(node),
                                 });
                                 result.errors.push(ValidationError {
+                                    origin: None,
                                     kind: ValidationErrors::VariableNotFound(*var2),
                                     node: Some(node),
                                 })
@@ -1100,6 +2650,8 @@ pub mod validator {
     pub struct ValidationError<'a> {
         pub kind: ValidationErrors<'a>,
         pub node: Option<&'a Node<'a>>,
+        /// Place the rule with the error was defined at, None for errors outside of rules
+        pub origin: Option<Origin<'a>>,
     }
 
     #[derive(Debug, Clone)]
@@ -1113,9 +2665,31 @@ pub mod validator {
         LabelNotFound(&'a str),
         NodeNotFound(&'a str),
         EnumeratorNotFound(&'a str),
+        AliasNotFound(&'a str),
+        /// Alias refers to itself through other aliases
+        AliasCycle(&'a str),
+        FeatureNotFound(&'a str),
+        /// No predicate is registered under the name, see `parser::Parser::register_predicate`
+        PredicateNotFound(&'a str),
         TokenCollision(&'a str),
-        CannotGoBackMoreThan { steps: usize, max: usize },
+        CannotGoBackMoreThan {
+            steps: usize,
+            max: usize,
+        },
         VariableTypeMismatch((VarKind<'a>, VariableKind), (VarKind<'a>, VariableKind)),
+        /// Example in the docs of the node does not parse, see `Validator::check_examples`
+        ExampleFailed {
+            example: &'a str,
+            error: String,
+        },
+        /// The nodes an entry can reach read the global, but only other nodes set it,
+        /// see `Validator::validate_entry`
+        GlobalSetElsewhere(&'a str),
+        /// The label is not in the block of the `Goto` or a block around it,
+        /// see `Validator::validate_entry`
+        GotoOutOfScope(&'a str),
+        /// Initial value is of another kind than the variable, see `Node::initial`
+        InitialValueMismatch(&'a str, VariableKind),
     }
 
     #[derive(Debug, Clone)]
@@ -1133,6 +2707,61 @@ pub mod validator {
         UnusualToken(&'a str, TokenErrors),
         UnusedLabel(&'a str),
         FailWithoutExplanation,
+        Slow(PerformanceLint<'a>),
+        /// A `Node` variable can be set more than once along a single path,
+        /// the later set silently replaces the earlier node
+        NodeOverwritten(VarKind<'a>),
+        /// No rule of the grammar uses the lexer token, see `Validator::lint_tokens`
+        UnusedToken(&'a str),
+        /// The word is also a lexer token, see `Validator::lint_tokens`
+        WordIsToken(&'a str),
+    }
+
+    /// Constructs that are known to be slow
+    #[derive(Debug, Clone)]
+    pub enum PerformanceLint<'a> {
+        /// `Any` is matched by or inside of a `While`
+        AnyInWhile,
+        /// Number of `Maybe` rules nested in each others `isnt` block
+        MaybeChain(usize),
+        /// The node never uses `NodeStart`
+        MissingNodeStart,
+        /// Enumerator tries a node before a token
+        NodeBeforeToken(&'a str),
+    }
+
+    impl PerformanceLint<'_> {
+        pub fn explanation(&self) -> &'static str {
+            match self {
+                PerformanceLint::AnyInWhile => {
+                    "Any matches every token, so the loop only ends once its body fails"
+                }
+                PerformanceLint::MaybeChain(_) => {
+                    "Nested Maybe rules try the alternatives one by one and keep a block for each of them"
+                }
+                PerformanceLint::MissingNodeStart => {
+                    "Without NodeStart the parser has to find the start of the node after every attempt"
+                }
+                PerformanceLint::NodeBeforeToken(_) => {
+                    "Nodes take much longer to fail than tokens, yet they are tried first"
+                }
+            }
+        }
+
+        pub fn suggestion(&self) -> &'static str {
+            match self {
+                PerformanceLint::AnyInWhile => {
+                    "Use Until with the token that ends the loop, or IsOneOf with explicit tokens"
+                }
+                PerformanceLint::MaybeChain(_) => {
+                    "Merge the chain into a single MaybeOneOf or IsOneOf"
+                }
+                PerformanceLint::MissingNodeStart => "Add NodeStart to the first rule of the node",
+                PerformanceLint::NodeBeforeToken(_) => {
+                    "Move the token alternatives in front of the node alternatives"
+                }
+            }
+        }
     }
 
     #[derive(Debug, Clone)]
@@ -1181,6 +2810,7 @@ pub mod validator {
             for looking_for in &self.lost_labels {
                 if !self.found_labels.contains(looking_for) {
                     result.errors.push(ValidationError {
+                        origin: None,
                         kind: ValidationErrors::LabelNotFound(looking_for),
                         node: Some(node),
                     });
@@ -1230,6 +2860,30 @@ pub mod validator {
                     f,
                     "An explanation msut be provided for an explicit rule fail"
                 ),
+                ValidationWarnings::NodeOverwritten(var) => write!(
+                    f,
+                    "Variable {var:?} holds a single node but can be set more than once, the earlier node is lost. Use a NodeList to keep all of them"
+                ),
+                ValidationWarnings::UnusedToken(token) => write!(
+                    f,
+                    "Token ({token}) is never used by the grammar, remove it from the lexer"
+                ),
+                ValidationWarnings::WordIsToken(word) => write!(
+                    f,
+                    "Word {word:?} is also a lexer token, so the lexer splits it off the front of longer texts. Match it as a Token or remove it from the lexer"
+                ),
+                ValidationWarnings::Slow(lint) => {
+                    match lint {
+                        PerformanceLint::MaybeChain(depth) => {
+                            write!(f, "{depth} nested Maybe rules. ")?
+                        }
+                        PerformanceLint::NodeBeforeToken(name) => {
+                            write!(f, "Enumerator {name:?}. ")?
+                        }
+                        _ => (),
+                    }
+                    write!(f, "{}. {}", lint.explanation(), lint.suggestion())
+                }
             }
         }
     }
@@ -1240,6 +2894,9 @@ pub mod validator {
             if let Some(node) = self.node {
                 write!(f, " in node {}", node.name)?;
             }
+            if let Some(origin) = self.origin {
+                write!(f, " (rule at {origin})")?;
+            }
             write!(f, ": {}", self.kind)
         }
     }
@@ -1248,7 +2905,7 @@ pub mod validator {
         fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
             match self {
                 ValidationErrors::CantUseVariable(key) => {
-                    write!(f, "Can not use variable {key:?} in that way")
+                    write!(f, "Can not use variable {key} in that way")
                 }
                 ValidationErrors::EmptyToken => write!(f, "Empty tokens are not allowed"),
                 ValidationErrors::TokenNotFound(smol_str) => {
@@ -1267,24 +2924,134 @@ pub mod validator {
                     write!(f, "Can not go back {steps} times, maximum: {max}")
                 }
                 ValidationErrors::ComparisonInvalid(l, r, comparison) => {
-                    write!(f, "Invalid comparison {comparison:?} on {l:?}, {r:?}")
+                    write!(f, "Invalid comparison {comparison:?} on {l}, {r}")
                 }
                 ValidationErrors::VariableNotFound(var_kind) => {
-                    write!(f, "Variable {var_kind:?} not found")
+                    write!(f, "Variable {var_kind} not found")
                 }
                 ValidationErrors::NodeNotFound(name) => write!(f, "Node {name:?} not found"),
                 ValidationErrors::EnumeratorNotFound(name) => {
                     write!(f, "Enumerator {name:?} not found")
                 }
-                ValidationErrors::VariableTypeMismatch((var1, t1), (var2, t2)) => write!(
+                ValidationErrors::AliasNotFound(name) => write!(f, "Alias {name:?} not found"),
+                ValidationErrors::AliasCycle(name) => {
+                    write!(f, "Alias {name:?} refers to itself")
+                }
+                ValidationErrors::FeatureNotFound(name) => {
+                    write!(f, "Feature {name:?} is not declared")
+                }
+                ValidationErrors::PredicateNotFound(name) => {
+                    write!(f, "Predicate {name:?} is not registered")
+                }
+                ValidationErrors::VariableTypeMismatch((var1, t1), (var2, t2)) => {
+                    write!(f, "Variable type mismatch for {var1}:{t1:?}, {var2}:{t2:?}")
+                }
+                ValidationErrors::ExampleFailed { example, error } => {
+                    write!(f, "Example {example:?} does not parse: {error}")
+                }
+                ValidationErrors::GlobalSetElsewhere(name) => write!(
                     f,
-                    "Variable type mismatch for {var1:?}:{t1:?}, {var2:?}:{t2:?}"
+                    "Global {name:?} is only set by nodes the entry can not reach"
                 ),
+                ValidationErrors::GotoOutOfScope(label) => {
+                    write!(f, "Goto {label:?} can not reach the label from its block")
+                }
+                ValidationErrors::InitialValueMismatch(name, kind) => {
+                    write!(f, "Variable {name:?} can not start with a {kind:?} value")
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::error::Error for ValidationError<'_> {}
+
+    /// Validation error that does not borrow from the grammar
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ValidationErrorOwned {
+        pub code: &'static str,
+        pub header: &'static str,
+        pub message: String,
+        /// Name of the node in which the error occured
+        pub node: Option<String>,
+        /// Place the rule with the error was defined at
+        pub origin: Option<String>,
+    }
+
+    impl<'a> From<&ValidationError<'a>> for ValidationErrorOwned {
+        fn from(value: &ValidationError<'a>) -> Self {
+            let (code, header) = value.kind.id_and_header();
+            ValidationErrorOwned {
+                code,
+                header,
+                message: value.kind.to_string(),
+                node: value.node.map(|n| n.name.to_string()),
+                origin: value.origin.map(|origin| origin.to_string()),
+            }
+        }
+    }
+
+    impl<'a> From<ValidationError<'a>> for ValidationErrorOwned {
+        fn from(value: ValidationError<'a>) -> Self {
+            (&value).into()
+        }
+    }
+
+    impl Display for ValidationErrorOwned {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "Error in validation")?;
+            if let Some(node) = &self.node {
+                write!(f, " in node {}", node)?;
+            }
+            if let Some(origin) = &self.origin {
+                write!(f, " (rule at {origin})")?;
+            }
+            write!(f, ": {}", self.message)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::error::Error for ValidationErrorOwned {}
+
+    impl<'a> ValidationResult<'a> {
+        /// Returns the first error, if there is any
+        ///
+        /// Useful for propagating validation failures with `?`
+        pub fn into_result(self) -> Result<(), ValidationErrorOwned> {
+            match self.errors.first() {
+                Some(err) => Err(err.into()),
+                None => Ok(()),
             }
         }
     }
 
     impl<'a> ValidationErrors<'a> {
+        /// Stable public code of the error, see `diagnostic::catalog`
+        pub fn code(&self) -> &'static str {
+            match self {
+                ValidationErrors::CantUseVariable(_) => "E0100",
+                ValidationErrors::ComparisonInvalid(_, _, _) => "E0101",
+                ValidationErrors::VariableNotFound(_) => "E0102",
+                ValidationErrors::EmptyToken => "E0103",
+                ValidationErrors::TokenNotFound(_) => "E0104",
+                ValidationErrors::DuplicateLabel(_) => "E0105",
+                ValidationErrors::LabelNotFound(_) => "E0106",
+                ValidationErrors::TokenCollision(_) => "E0107",
+                ValidationErrors::CannotGoBackMoreThan { .. } => "E0108",
+                ValidationErrors::AliasNotFound(_) => "E0109",
+                ValidationErrors::AliasCycle(_) => "E0110",
+                ValidationErrors::FeatureNotFound(_) => "E0111",
+                ValidationErrors::NodeNotFound(_) => "E0112",
+                ValidationErrors::EnumeratorNotFound(_) => "E0113",
+                ValidationErrors::VariableTypeMismatch(_, _) => "E0114",
+                ValidationErrors::ExampleFailed { .. } => "E0115",
+                ValidationErrors::PredicateNotFound(_) => "E0116",
+                ValidationErrors::GlobalSetElsewhere(_) => "E0117",
+                ValidationErrors::GotoOutOfScope(_) => "E0118",
+                ValidationErrors::InitialValueMismatch(_, _) => "E0119",
+            }
+        }
+
         pub fn id_and_header(&self) -> (&'static str, &'static str) {
             match self {
                 ValidationErrors::CantUseVariable(_) => ("100", "Variable usage invalid"),
@@ -1298,12 +3065,37 @@ pub mod validator {
                 ValidationErrors::CannotGoBackMoreThan { .. } => ("108", "Out of scope"),
                 ValidationErrors::NodeNotFound(_) => ("106", "Node not found"),
                 ValidationErrors::EnumeratorNotFound(_) => ("106", "Enumerator not found"),
+                ValidationErrors::AliasNotFound(_) => ("109", "Alias not found"),
+                ValidationErrors::AliasCycle(_) => ("110", "Alias cycle"),
+                ValidationErrors::FeatureNotFound(_) => ("111", "Feature not found"),
                 ValidationErrors::VariableTypeMismatch(_, _) => ("108", "Variable type mismatch"),
+                ValidationErrors::ExampleFailed { .. } => ("112", "Example failed"),
+                ValidationErrors::PredicateNotFound(_) => ("113", "Predicate not found"),
+                ValidationErrors::GlobalSetElsewhere(_) => ("114", "Global set elsewhere"),
+                ValidationErrors::GotoOutOfScope(_) => ("115", "Label out of scope"),
+                ValidationErrors::InitialValueMismatch(_, _) => ("116", "Initial value mismatch"),
             }
         }
     }
 
     impl<'a> ValidationWarnings<'a> {
+        /// Stable public code of the warning, see `diagnostic::catalog`
+        pub fn code(&self) -> &'static str {
+            match self {
+                ValidationWarnings::UnusedVariable(_) => "W0000",
+                ValidationWarnings::UsedDebug => "W0001",
+                ValidationWarnings::UsedPrint => "W0002",
+                ValidationWarnings::UsedDepricated(_) => "W0003",
+                ValidationWarnings::UnusualToken(_, _) => "W0004",
+                ValidationWarnings::UnusedLabel(_) => "W0005",
+                ValidationWarnings::FailWithoutExplanation => "W0006",
+                ValidationWarnings::Slow(_) => "W0007",
+                ValidationWarnings::NodeOverwritten(_) => "W0008",
+                ValidationWarnings::UnusedToken(_) => "W0009",
+                ValidationWarnings::WordIsToken(_) => "W0010",
+            }
+        }
+
         pub fn id_and_header(&self) -> (&'static str, &'static str) {
             match self {
                 ValidationWarnings::UnusedVariable(_) => ("000", "Variable unused"),
@@ -1313,6 +3105,10 @@ pub mod validator {
                 ValidationWarnings::UnusualToken(_, _) => ("004", "Unusual token"),
                 ValidationWarnings::UnusedLabel(_) => ("005", "Label unused"),
                 ValidationWarnings::FailWithoutExplanation => ("006", "Fail withoud explanation"),
+                ValidationWarnings::Slow(_) => ("007", "Slow construct"),
+                ValidationWarnings::NodeOverwritten(_) => ("008", "Node overwritten"),
+                ValidationWarnings::UnusedToken(_) => ("009", "Token unused"),
+                ValidationWarnings::WordIsToken(_) => ("010", "Word is a token"),
             }
         }
     }
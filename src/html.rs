@@ -0,0 +1,171 @@
+//! Standalone HTML page for browsing a parse tree
+//!
+//! `ParseResult::to_html` writes the tree next to the parsed text. Nodes fold like
+//! in a file browser and hovering over a node, token or error highlights its span in
+//! the text. The page has no dependencies, so it can be attached to a bug report.
+
+use std::fmt::Write;
+
+use crate::parser::{Node, Nodes, ParseResult, VariableKind};
+
+const STYLE: &str = "body{display:flex;gap:1em;margin:0;height:100vh;font:13px monospace}\
+#tree,#text{overflow:auto;padding:1em;flex:1}\
+#text{white-space:pre-wrap;border-left:1px solid #ccc;margin:0}\
+details{margin-left:1em}\
+.leaf{margin-left:2em}\
+[data-s]{cursor:pointer}\
+[data-s]:hover{background:#eef}\
+.error{color:#b00}\
+mark{background:#fd6}";
+
+const SCRIPT: &str = "const text=document.getElementById('text');\
+const source=text.textContent;\
+function show(s,e){\
+const mark=document.createElement('mark');\
+mark.textContent=source.slice(s,e);\
+text.replaceChildren(source.slice(0,s),mark,source.slice(e));\
+mark.scrollIntoView({block:'nearest'});}\
+for(const el of document.querySelectorAll('[data-s]')){\
+el.addEventListener('mouseenter',()=>show(+el.dataset.s,+el.dataset.e));}";
+
+impl ParseResult<'_> {
+    /// Returns a standalone HTML page with the parse tree and the text it was parsed from
+    ///
+    /// The text has to be the one that was parsed
+    pub fn to_html(&self, text: &str) -> String {
+        let page = Page {
+            utf16: utf16_offsets(text),
+        };
+        let mut tree = String::new();
+        page.node(&mut tree, &self.entry, None);
+        for error in &self.errors {
+            let span = error.span.clone();
+            page.leaf(&mut tree, "error", &error.compact().to_string(), span, true);
+        }
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        let _ = writeln!(
+            html,
+            "<title>{}</title>\n<style>{STYLE}</style>",
+            escape(self.entry.name.as_str())
+        );
+        html.push_str("</head>\n<body>\n<div id=\"tree\">\n");
+        html.push_str(&tree);
+        let _ = writeln!(
+            html,
+            "</div>\n<pre id=\"text\">{}</pre>\n<script>{SCRIPT}</script>\n</body>\n</html>",
+            escape(text)
+        );
+        html
+    }
+}
+
+/// Offsets of the text in UTF-16 units for every byte, the way JavaScript indexes strings
+fn utf16_offsets(text: &str) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+    let mut offset = 0;
+    for c in text.chars() {
+        offsets.extend(core::iter::repeat_n(offset, c.len_utf8()));
+        offset += c.len_utf16();
+    }
+    offsets.push(offset);
+    offsets
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+struct Page {
+    utf16: Vec<usize>,
+}
+
+impl Page {
+    /// `data-s` and `data-e` attributes of the span
+    fn span(&self, span: core::ops::Range<usize>) -> String {
+        let offset = |i: usize| self.utf16[i.min(self.utf16.len() - 1)];
+        format!(
+            "data-s=\"{}\" data-e=\"{}\"",
+            offset(span.start),
+            offset(span.end.max(span.start))
+        )
+    }
+
+    fn node(&self, out: &mut String, node: &Node, label: Option<&str>) {
+        let span = node.first_string_idx..node.last_string_idx;
+        let label = label
+            .map(|l| format!("{}: ", escape(l)))
+            .unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "<details open><summary {}>{label}{}</summary>",
+            self.span(span.clone()),
+            escape(&node.summary(0).to_string())
+        );
+        let mut keys = node.variables.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            match &node.variables[key] {
+                VariableKind::Node(Some(child)) => self.nodes(out, child, key),
+                VariableKind::NodeList(list) => {
+                    for (i, child) in list.iter().enumerate() {
+                        self.nodes(out, child, &format!("{key}[{i}]"));
+                    }
+                }
+                value => {
+                    let _ = writeln!(
+                        out,
+                        "<div class=\"leaf\">{}: {}</div>",
+                        escape(key),
+                        escape(&value.to_string())
+                    );
+                }
+            }
+        }
+        out.push_str("</details>\n");
+    }
+
+    fn nodes(&self, out: &mut String, nodes: &Nodes, label: &str) {
+        match nodes {
+            Nodes::Node(node) => self.node(out, node, Some(label)),
+            Nodes::Token(token) => {
+                let span = token.index..token.index + token.len;
+                self.leaf(out, label, &nodes.to_string(), span, false)
+            }
+            Nodes::Error { span, .. } => {
+                self.leaf(out, label, &nodes.to_string(), span.clone(), true)
+            }
+        }
+    }
+
+    fn leaf(
+        &self,
+        out: &mut String,
+        label: &str,
+        value: &str,
+        span: core::ops::Range<usize>,
+        error: bool,
+    ) {
+        let class = match error {
+            true => "leaf error",
+            false => "leaf",
+        };
+        let _ = writeln!(
+            out,
+            "<div class=\"{class}\" {}>{}: {}</div>",
+            self.span(span),
+            escape(label),
+            escape(value)
+        );
+    }
+}
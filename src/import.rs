@@ -0,0 +1,1344 @@
+//! Importers of grammars written for other parser generators
+//!
+//! Importers convert a useful subset of a foreign grammar into a `Parser`.
+//! Everything that can not be expressed is reported as an `ImportWarning`,
+//! so the result is a starting point for a migration rather than a drop-in replacement.
+//!
+//! Rules become nodes, the first parser rule is the entry. Rules of the lexer level
+//! become aliases: literals map to tokens or words, alternatives of literals to enumerators
+//! and everything else (character sets, ranges, ...) is approximated as `Text`.
+//! Labels become node variables and lookaheads `Isnt` or `Peek` rules.
+//!
+//! Strings of an imported grammar are leaked to get the `'static` lifetime.
+
+use core::fmt;
+
+use crate::{
+    grammar::{
+        Enumerator, MatchToken, Node, OneOf, Origin, Parameters, Rule, Rules, VarKind, VariableKind,
+    },
+    intern,
+    lexer::{ControlTokenKind, TokenKinds},
+    Map, Parser,
+};
+
+/// Imported parser together with the constructs that could not be converted
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub parser: Parser<'static>,
+    pub warnings: Vec<ImportWarning>,
+}
+
+/// Construct of the imported grammar that was dropped or approximated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportWarning {
+    /// Rule in which the construct appears
+    pub rule: String,
+    pub message: String,
+}
+
+impl fmt::Display for ImportWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in rule {}", self.message, self.rule)
+    }
+}
+
+/// Syntax error in the imported grammar
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.message, self.line, self.column)
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Grammar expression shared by the importers
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Literal(String),
+    /// Reference to a rule
+    Ref(String),
+    Any,
+    Eof,
+    Seq(Vec<Expr>),
+    Choice(Vec<Expr>),
+    Repeat(Box<Expr>, Repeat),
+    /// Stores the matched value in a variable, a list variable if `list` is set
+    Label {
+        name: String,
+        list: bool,
+        expr: Box<Expr>,
+    },
+    /// Negative lookahead
+    Not(Box<Expr>),
+    /// Positive lookahead
+    And(Box<Expr>),
+    /// Characters of a class or a range, approximated on the lexer level
+    Chars(String),
+    /// Construct that can not be converted, the description is reported as a warning
+    Unsupported(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    Optional,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+/// Rule of the imported grammar
+#[derive(Debug, Clone)]
+struct RuleDef {
+    name: String,
+    expr: Expr,
+    /// Rule of the lexer level, converted into an alias
+    lexer: bool,
+    /// Rule that is only used inside of other lexer rules
+    fragment: bool,
+    /// Lexer rule whose tokens are skipped (whitespace, comments)
+    skip: bool,
+    docs: Option<String>,
+}
+
+impl Expr {
+    /// Whether the expression matches a literal that the lexer does not skip as whitespace
+    fn contains_literal(&self) -> bool {
+        match self {
+            Expr::Literal(literal) => !literal.trim().is_empty(),
+            Expr::Seq(items) | Expr::Choice(items) => items.iter().any(Expr::contains_literal),
+            Expr::Repeat(expr, _)
+            | Expr::Label { expr, .. }
+            | Expr::Not(expr)
+            | Expr::And(expr) => expr.contains_literal(),
+            _ => false,
+        }
+    }
+
+    /// Unwraps sequences of one element
+    fn single(&self) -> &Expr {
+        match self {
+            Expr::Seq(items) if items.len() == 1 => items[0].single(),
+            _ => self,
+        }
+    }
+}
+
+/// Converts rules into a parser
+struct Lowering {
+    parser: Parser<'static>,
+    warnings: Vec<ImportWarning>,
+    strings: Map<String, &'static str>,
+    /// Rule that is being converted
+    rule: String,
+    lexer_rules: Vec<String>,
+    parser_rules: Vec<String>,
+    variables: Vec<(intern::Symbol, VariableKind)>,
+    enumerators: usize,
+}
+
+fn lower(mut rules: Vec<RuleDef>) -> Import {
+    // `Grammar::eof` already requires the entry to end at the end of the file
+    if let Some(RuleDef {
+        expr: Expr::Seq(items),
+        ..
+    }) = rules.iter_mut().find(|r| !r.lexer)
+    {
+        if items.last() == Some(&Expr::Eof) {
+            items.pop();
+        }
+    }
+
+    let mut lowering = Lowering {
+        parser: Parser::new(),
+        warnings: Vec::new(),
+        strings: Map::new(),
+        rule: String::new(),
+        lexer_rules: rules
+            .iter()
+            .filter(|r| r.lexer && !r.fragment && !r.skip)
+            .map(|r| r.name.clone())
+            .collect(),
+        parser_rules: rules
+            .iter()
+            .filter(|r| !r.lexer)
+            .map(|r| r.name.clone())
+            .collect(),
+        variables: Vec::new(),
+        enumerators: 0,
+    };
+
+    for rule in rules.iter().filter(|r| r.lexer) {
+        lowering.rule = rule.name.clone();
+        lowering.lexer_rule(rule);
+    }
+    for rule in rules.iter().filter(|r| !r.lexer) {
+        lowering.rule = rule.name.clone();
+        let mut node_rules = Vec::new();
+        lowering.element(&rule.expr, Vec::new(), &mut node_rules);
+        let name = intern::Symbol::intern(&rule.name);
+        let docs = rule.docs.as_deref().map(|docs| lowering.leak(docs));
+        lowering.parser.grammar.nodes.insert(
+            name,
+            Node {
+                name,
+                rules: node_rules,
+                variables: core::mem::take(&mut lowering.variables),
+                docs,
+                ..Default::default()
+            },
+        );
+    }
+    if let Some(entry) = lowering.parser_rules.first() {
+        lowering.parser.parser.entry = Some(lowering.leak(&entry.clone()));
+    }
+
+    Import {
+        parser: lowering.parser,
+        warnings: lowering.warnings,
+    }
+}
+
+impl Lowering {
+    fn leak(&mut self, string: &str) -> &'static str {
+        if let Some(leaked) = self.strings.get(string) {
+            return leaked;
+        }
+        let leaked: &'static str = Box::leak(string.to_string().into_boxed_str());
+        self.strings.insert(string.to_string(), leaked);
+        leaked
+    }
+
+    /// Origin of the rules generated for the rule that is being converted
+    fn origin(&mut self) -> Origin<'static> {
+        let rule = self.rule.clone();
+        Origin::Definition(self.leak(&rule))
+    }
+
+    fn warn(&mut self, message: impl Into<String>) {
+        self.warnings.push(ImportWarning {
+            rule: self.rule.clone(),
+            message: message.into(),
+        });
+    }
+
+    fn lexer_rule(&mut self, rule: &RuleDef) {
+        if rule.fragment {
+            return;
+        }
+        if rule.skip {
+            if rule.expr.contains_literal() {
+                self.warn(
+                    "skipped rule is dropped, add its tokens to `Grammar::ignored` if they are lexed",
+                );
+            }
+            return;
+        }
+        let token = match rule.expr.single() {
+            Expr::Literal(literal) => self.literal(literal),
+            Expr::Choice(alts)
+                if alts
+                    .iter()
+                    .all(|alt| matches!(alt.single(), Expr::Literal(_))) =>
+            {
+                let name = self.leak(&rule.name);
+                let values = alts
+                    .iter()
+                    .map(|alt| match alt.single() {
+                        Expr::Literal(literal) => self.literal(literal),
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                self.parser
+                    .grammar
+                    .enumerators
+                    .insert(rule.name.clone(), Enumerator { name, values });
+                MatchToken::Enumerator(name)
+            }
+            _ => {
+                self.warn("lexer rule is approximated as `Text`");
+                MatchToken::Token(TokenKinds::Text)
+            }
+        };
+        self.parser.grammar.aliases.insert(rule.name.clone(), token);
+    }
+
+    /// Words are matched as `Text`, other literals are added to the lexer
+    fn literal(&mut self, literal: &str) -> MatchToken<'static> {
+        let literal = self.leak(literal);
+        if !literal.is_empty() && literal.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return MatchToken::Word(literal);
+        }
+        if !self.parser.lexer.get_tokens().contains(&literal) {
+            self.parser.lexer.add_token(literal);
+        }
+        MatchToken::Token(TokenKinds::Token(literal))
+    }
+
+    /// Token matching the expression, if it matches exactly one token
+    fn token(&mut self, expr: &Expr) -> Option<MatchToken<'static>> {
+        match expr.single() {
+            Expr::Literal(literal) => Some(self.literal(literal)),
+            Expr::Ref(name) => {
+                if self.lexer_rules.contains(name) {
+                    Some(MatchToken::Alias(self.leak(name)))
+                } else {
+                    if !self.parser_rules.contains(name) {
+                        self.warn(format!("rule {} is not defined", name));
+                    }
+                    Some(MatchToken::Node(intern::Symbol::intern(name)))
+                }
+            }
+            Expr::Any => Some(MatchToken::Any),
+            Expr::Eof => Some(MatchToken::Token(TokenKinds::Control(
+                ControlTokenKind::Eof,
+            ))),
+            Expr::Choice(alts)
+                if alts.iter().all(|alt| {
+                    matches!(
+                        alt.single(),
+                        Expr::Literal(_) | Expr::Ref(_) | Expr::Any | Expr::Eof
+                    )
+                }) =>
+            {
+                let mut values = alts
+                    .iter()
+                    .map(|alt| self.token(alt))
+                    .collect::<Option<Vec<_>>>()?;
+                values.sort_by_key(|token| !matches!(token, MatchToken::Word(_)));
+                self.enumerators += 1;
+                let name = format!("{}_{}", self.rule, self.enumerators);
+                let name = self.leak(&name);
+                self.parser
+                    .grammar
+                    .enumerators
+                    .insert(name.to_string(), Enumerator { name, values });
+                Some(MatchToken::Enumerator(name))
+            }
+            _ => None,
+        }
+    }
+
+    /// First token of the expression with its parameters and the rest of the sequence
+    fn split<'e>(
+        &mut self,
+        expr: &'e Expr,
+    ) -> Option<(MatchToken<'static>, Vec<Parameters<'static>>, &'e [Expr])> {
+        let (first, rest) = match expr {
+            Expr::Seq(items) if !items.is_empty() => (&items[0], &items[1..]),
+            _ => (expr, &[][..]),
+        };
+        let (first, parameters) = match first {
+            Expr::Label { name, list, expr } => (&**expr, vec![self.label(name, *list)]),
+            _ => (first, Vec::new()),
+        };
+        let token = self.token(first)?;
+        Some((token, parameters, rest))
+    }
+
+    fn label(&mut self, name: &str, list: bool) -> Parameters<'static> {
+        let name = self.leak(name);
+        let kind = match list {
+            true => VariableKind::NodeList,
+            false => VariableKind::Node,
+        };
+        match self.variables.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, existing)) if list => *existing = kind,
+            Some(_) => (),
+            None => self.variables.push((intern::Symbol::intern(name), kind)),
+        }
+        Parameters::Set(VarKind::Local(name))
+    }
+
+    fn rules(&mut self, exprs: &[Expr]) -> Rules<'static> {
+        let mut rules = Vec::new();
+        for expr in exprs {
+            self.element(expr, Vec::new(), &mut rules);
+        }
+        rules
+    }
+
+    fn one_of(&mut self, alts: &[Expr]) -> Option<Vec<OneOf<'static>>> {
+        let mut result = Vec::new();
+        for alt in alts {
+            let Some((token, parameters, rest)) = self.split(alt) else {
+                self.warn("alternative that does not start with a token is dropped");
+                return None;
+            };
+            let rules = self.rules(rest);
+            result.push(OneOf {
+                token,
+                rules,
+                parameters,
+            });
+        }
+        // keywords are lexed as `Text`, try them before the rules approximated as `Text`
+        result.sort_by_key(|one_of| !matches!(one_of.token, MatchToken::Word(_)));
+        Some(result)
+    }
+
+    fn element(
+        &mut self,
+        expr: &Expr,
+        parameters: Vec<Parameters<'static>>,
+        out: &mut Rules<'static>,
+    ) {
+        match expr {
+            Expr::Label { name, list, expr } => {
+                let mut parameters = parameters;
+                parameters.push(self.label(name, *list));
+                self.element(expr, parameters, out);
+            }
+            Expr::Seq(items) if items.len() != 1 => {
+                for item in items {
+                    self.element(item, Vec::new(), out);
+                }
+            }
+            Expr::Repeat(inner, repeat) => self.repeat(inner, *repeat, parameters, out),
+            Expr::Not(inner) => match self.token(inner) {
+                Some(token) => out.push(Rule::Isnt {
+                    origin: self.origin(),
+                    token,
+                    rules: Vec::new(),
+                    parameters: Vec::new(),
+                }),
+                None => self.warn("negative lookahead of more than one token is dropped"),
+            },
+            Expr::And(inner) => match self.token(inner) {
+                Some(token) => out.push(Rule::Peek {
+                    origin: self.origin(),
+                    token,
+                    is: Vec::new(),
+                    isnt: Vec::new(),
+                    parameters: Vec::new(),
+                }),
+                None => self.warn("positive lookahead of more than one token is dropped"),
+            },
+            Expr::Chars(what) | Expr::Unsupported(what) => {
+                self.warn(format!("{} is not supported", what))
+            }
+            _ => {
+                if let Some(token) = self.token(expr) {
+                    out.push(Rule::Is {
+                        origin: self.origin(),
+                        token,
+                        rules: Vec::new(),
+                        parameters,
+                    });
+                    return;
+                }
+                match expr.single() {
+                    Expr::Choice(alts) => {
+                        if let Some(tokens) = self.one_of(alts) {
+                            out.push(Rule::IsOneOf {
+                                origin: self.origin(),
+                                tokens,
+                                parameters,
+                            });
+                        }
+                    }
+                    single => self.element(single, parameters, out),
+                }
+            }
+        }
+    }
+
+    fn repeat(
+        &mut self,
+        inner: &Expr,
+        repeat: Repeat,
+        parameters: Vec<Parameters<'static>>,
+        out: &mut Rules<'static>,
+    ) {
+        let (token, parameters, rules) = match self.token(inner) {
+            Some(token) => (token, parameters, Vec::new()),
+            None => match self.split(inner.single()) {
+                Some((token, first, rest)) => {
+                    let rules = self.rules(rest);
+                    (token, parameters.into_iter().chain(first).collect(), rules)
+                }
+                None => {
+                    match (inner.single(), repeat) {
+                        (Expr::Choice(alts), Repeat::Optional) => {
+                            if let Some(is_one_of) = self.one_of(alts) {
+                                out.push(Rule::MaybeOneOf {
+                                    origin: self.origin(),
+                                    is_one_of,
+                                    isnt: Vec::new(),
+                                });
+                            }
+                        }
+                        _ => self
+                            .warn("repetition of alternatives with more than one token is dropped"),
+                    }
+                    return;
+                }
+            },
+        };
+        match repeat {
+            Repeat::Optional => out.push(Rule::Maybe {
+                origin: self.origin(),
+                token,
+                is: rules,
+                isnt: Vec::new(),
+                parameters,
+            }),
+            Repeat::ZeroOrMore => out.push(Rule::While {
+                origin: self.origin(),
+                token,
+                rules,
+                parameters,
+            }),
+            Repeat::OneOrMore => {
+                out.push(Rule::Is {
+                    origin: self.origin(),
+                    token,
+                    rules: rules.clone(),
+                    parameters: parameters.clone(),
+                });
+                out.push(Rule::While {
+                    origin: self.origin(),
+                    token,
+                    rules,
+                    parameters,
+                });
+            }
+        }
+    }
+}
+
+/// Importer of ANTLR 4 grammars (`.g4`)
+///
+/// Supports lexer and parser rules, alternatives, groups, repetition, labels and `EOF`.
+/// Actions, predicates, lexer modes, element options and negated sets are dropped with a warning.
+pub mod antlr {
+    use super::*;
+
+    /// Converts an ANTLR 4 grammar
+    pub fn import(text: &str) -> Result<Import, ImportError> {
+        let tokens = lex(text)?;
+        let mut parser = G4 {
+            tokens,
+            pos: 0,
+            rules: Vec::new(),
+            warnings: Vec::new(),
+        };
+        parser.grammar()?;
+        let mut import = lower(parser.rules);
+        import.warnings.splice(0..0, parser.warnings);
+        Ok(import)
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Tok {
+        Ident(String),
+        Str(String),
+        Punct(&'static str),
+        /// Character set `[...]`
+        Set,
+        /// Action `{...}`, predicate if followed by `?`
+        Action,
+    }
+
+    const PUNCTS: [&str; 21] = [
+        "->", "+=", "..", "::", ":", ";", "|", "(", ")", "*", "+", "?", "~", ".", "=", "#", ",",
+        "@", "<", ">", "$",
+    ];
+
+    fn error(line: usize, column: usize, message: impl Into<String>) -> ImportError {
+        ImportError {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    /// Splits the grammar into tokens with their line and column
+    fn lex(text: &str) -> Result<Vec<(Tok, usize, usize)>, ImportError> {
+        let chars = text.chars().collect::<Vec<_>>();
+        let mut tokens = Vec::new();
+        let (mut i, mut line, mut column) = (0, 1, 1);
+        let advance = |i: &mut usize, line: &mut usize, column: &mut usize, n: usize| {
+            for c in &chars[*i..(*i + n).min(chars.len())] {
+                match c {
+                    '\n' => {
+                        *line += 1;
+                        *column = 1;
+                    }
+                    _ => *column += 1,
+                }
+            }
+            *i += n;
+        };
+        while i < chars.len() {
+            let c = chars[i];
+            let rest = &chars[i..];
+            let (start_line, start_column) = (line, column);
+            if c.is_whitespace() {
+                advance(&mut i, &mut line, &mut column, 1);
+            } else if rest.starts_with(&['/', '/']) {
+                let len = rest.iter().position(|c| *c == '\n').unwrap_or(rest.len());
+                advance(&mut i, &mut line, &mut column, len);
+            } else if rest.starts_with(&['/', '*']) {
+                let len = (2..rest.len())
+                    .find(|j| rest[*j - 1] == '*' && rest[*j] == '/')
+                    .ok_or_else(|| error(line, column, "unterminated comment"))?;
+                advance(&mut i, &mut line, &mut column, len + 1);
+            } else if c.is_alphabetic() || c == '_' {
+                let len = rest
+                    .iter()
+                    .position(|c| !(c.is_alphanumeric() || *c == '_'))
+                    .unwrap_or(rest.len());
+                tokens.push((Tok::Ident(rest[..len].iter().collect()), line, column));
+                advance(&mut i, &mut line, &mut column, len);
+            } else if c == '\'' {
+                let mut value = String::new();
+                let mut j = 1;
+                loop {
+                    match rest.get(j) {
+                        None | Some('\n') => {
+                            return Err(error(line, column, "unterminated string literal"))
+                        }
+                        Some('\'') => break,
+                        Some('\\') => {
+                            let escaped = rest.get(j + 1).copied().unwrap_or('\\');
+                            match escaped {
+                                'n' => value.push('\n'),
+                                't' => value.push('\t'),
+                                'r' => value.push('\r'),
+                                'u' => {
+                                    let hex = rest.get(j + 2..j + 6).unwrap_or_default();
+                                    let code =
+                                        u32::from_str_radix(&hex.iter().collect::<String>(), 16)
+                                            .ok()
+                                            .and_then(char::from_u32)
+                                            .ok_or_else(|| {
+                                                error(line, column, "invalid unicode escape")
+                                            })?;
+                                    value.push(code);
+                                    j += 4;
+                                }
+                                other => value.push(other),
+                            }
+                            j += 2;
+                        }
+                        Some(c) => {
+                            value.push(*c);
+                            j += 1;
+                        }
+                    }
+                }
+                tokens.push((Tok::Str(value), line, column));
+                advance(&mut i, &mut line, &mut column, j + 1);
+            } else if c == '[' {
+                let mut j = 1;
+                while j < rest.len() && rest[j] != ']' {
+                    j += if rest[j] == '\\' { 2 } else { 1 };
+                }
+                if j >= rest.len() {
+                    return Err(error(line, column, "unterminated character set"));
+                }
+                tokens.push((Tok::Set, line, column));
+                advance(&mut i, &mut line, &mut column, j + 1);
+            } else if c == '{' {
+                let mut depth = 0;
+                let mut j = 0;
+                loop {
+                    match rest.get(j) {
+                        None => return Err(error(line, column, "unterminated action")),
+                        Some('{') => depth += 1,
+                        Some('}') => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => (),
+                    }
+                    j += 1;
+                }
+                tokens.push((Tok::Action, line, column));
+                advance(&mut i, &mut line, &mut column, j + 1);
+            } else {
+                let punct = PUNCTS
+                    .iter()
+                    .find(|p| p.chars().enumerate().all(|(j, c)| rest.get(j) == Some(&c)))
+                    .ok_or_else(|| error(line, column, format!("unexpected character '{}'", c)))?;
+                tokens.push((Tok::Punct(punct), start_line, start_column));
+                advance(&mut i, &mut line, &mut column, punct.len());
+            }
+        }
+        Ok(tokens)
+    }
+
+    struct G4 {
+        tokens: Vec<(Tok, usize, usize)>,
+        pos: usize,
+        rules: Vec<RuleDef>,
+        warnings: Vec<ImportWarning>,
+    }
+
+    impl G4 {
+        fn peek(&self) -> Option<&Tok> {
+            self.tokens.get(self.pos).map(|t| &t.0)
+        }
+
+        fn next(&mut self) -> Option<Tok> {
+            let token = self.tokens.get(self.pos).map(|t| t.0.clone());
+            self.pos += 1;
+            token
+        }
+
+        fn eat(&mut self, punct: &str) -> bool {
+            let found = matches!(self.peek(), Some(Tok::Punct(p)) if *p == punct);
+            if found {
+                self.pos += 1;
+            }
+            found
+        }
+
+        fn error(&self, message: impl Into<String>) -> ImportError {
+            let (line, column) = self
+                .tokens
+                .get(self.pos)
+                .or(self.tokens.last())
+                .map(|t| (t.1, t.2))
+                .unwrap_or((1, 1));
+            error(line, column, message)
+        }
+
+        fn warn(&mut self, rule: &str, message: impl Into<String>) {
+            self.warnings.push(ImportWarning {
+                rule: rule.to_string(),
+                message: message.into(),
+            });
+        }
+
+        /// Skips tokens up to and including `;`
+        fn skip_statement(&mut self) {
+            while let Some(token) = self.next() {
+                if token == Tok::Punct(";") {
+                    break;
+                }
+            }
+        }
+
+        fn grammar(&mut self) -> Result<(), ImportError> {
+            let mut fragment = false;
+            while let Some(token) = self.next() {
+                match token {
+                    Tok::Ident(ident) => match ident.as_str() {
+                        "lexer" | "parser" | "grammar" | "import" => self.skip_statement(),
+                        "options" | "tokens" | "channels" => {
+                            if self.peek() == Some(&Tok::Action) {
+                                self.pos += 1;
+                            }
+                        }
+                        "mode" => {
+                            self.skip_statement();
+                            self.warn("mode", "lexer modes are not supported");
+                        }
+                        "fragment" => fragment = true,
+                        _ => {
+                            self.rule(ident, fragment)?;
+                            fragment = false;
+                        }
+                    },
+                    // named actions like @members { ... }
+                    Tok::Punct("@") => while !matches!(self.next(), Some(Tok::Action) | None) {},
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("expected a rule"));
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        fn rule(&mut self, name: String, fragment: bool) -> Result<(), ImportError> {
+            // arguments, returns, locals, options and @init come before the colon
+            while !self.eat(":") {
+                if self.next().is_none() {
+                    return Err(self.error(format!("expected ':' in rule {}", name)));
+                }
+            }
+            let mut skip = false;
+            let expr = self.alternatives(&name, &mut skip)?;
+            if !self.eat(";") {
+                return Err(self.error(format!("expected ';' at the end of rule {}", name)));
+            }
+            // exception handlers
+            while matches!(self.peek(), Some(Tok::Ident(i)) if i == "catch" || i == "finally") {
+                while !matches!(self.next(), Some(Tok::Action) | None) {}
+            }
+            let lexer = name.starts_with(|c: char| c.is_uppercase());
+            self.rules.push(RuleDef {
+                name,
+                expr,
+                lexer,
+                fragment,
+                skip,
+                docs: None,
+            });
+            Ok(())
+        }
+
+        fn alternatives(&mut self, rule: &str, skip: &mut bool) -> Result<Expr, ImportError> {
+            let mut alts = vec![self.alternative(rule, skip)?];
+            while self.eat("|") {
+                alts.push(self.alternative(rule, skip)?);
+            }
+            Ok(match alts.len() {
+                1 => alts.pop().unwrap(),
+                _ => Expr::Choice(alts),
+            })
+        }
+
+        fn alternative(&mut self, rule: &str, skip: &mut bool) -> Result<Expr, ImportError> {
+            let mut items = Vec::new();
+            loop {
+                match self.peek() {
+                    None
+                    | Some(Tok::Punct("|"))
+                    | Some(Tok::Punct(")"))
+                    | Some(Tok::Punct(";")) => break,
+                    // alternative label
+                    Some(Tok::Punct("#")) => {
+                        self.pos += 2;
+                    }
+                    // lexer commands
+                    Some(Tok::Punct("->")) => {
+                        self.pos += 1;
+                        while let Some(token) = self.peek() {
+                            match token {
+                                Tok::Ident(command)
+                                    if command == "skip" || command == "channel" =>
+                                {
+                                    *skip = true
+                                }
+                                Tok::Punct("|") | Tok::Punct(")") | Tok::Punct(";") => break,
+                                _ => (),
+                            }
+                            self.pos += 1;
+                        }
+                    }
+                    _ => {
+                        if let Some(element) = self.element(rule, skip)? {
+                            items.push(element);
+                        }
+                    }
+                }
+            }
+            Ok(Expr::Seq(items))
+        }
+
+        fn element(&mut self, rule: &str, skip: &mut bool) -> Result<Option<Expr>, ImportError> {
+            let atom = match self.next() {
+                Some(Tok::Ident(name)) => {
+                    if self.eat("=") || self.eat("+=") {
+                        let list = self.tokens[self.pos - 1].0 == Tok::Punct("+=");
+                        let Some(expr) = self.element(rule, skip)? else {
+                            return Err(self.error("expected an element after the label"));
+                        };
+                        return Ok(Some(Expr::Label {
+                            name,
+                            list,
+                            expr: Box::new(expr),
+                        }));
+                    }
+                    match name.as_str() {
+                        "EOF" => Expr::Eof,
+                        _ => Expr::Ref(name),
+                    }
+                }
+                Some(Tok::Str(literal)) => {
+                    if self.eat("..") {
+                        self.next();
+                        Expr::Chars("character range".to_string())
+                    } else {
+                        Expr::Literal(literal)
+                    }
+                }
+                Some(Tok::Punct(".")) => Expr::Any,
+                Some(Tok::Punct("(")) => {
+                    let expr = self.alternatives(rule, skip)?;
+                    if !self.eat(")") {
+                        return Err(self.error("expected ')'"));
+                    }
+                    expr
+                }
+                Some(Tok::Punct("~")) => {
+                    self.element(rule, skip)?;
+                    Expr::Chars("negated set".to_string())
+                }
+                Some(Tok::Set) => Expr::Chars("character set".to_string()),
+                Some(Tok::Action) => {
+                    let predicate = self.eat("?");
+                    self.warn(
+                        rule,
+                        match predicate {
+                            true => "semantic predicate is dropped",
+                            false => "action is dropped",
+                        },
+                    );
+                    return Ok(None);
+                }
+                // element options like <assoc=right>
+                Some(Tok::Punct("<")) => {
+                    while !matches!(self.next(), Some(Tok::Punct(">")) | None) {}
+                    return Ok(None);
+                }
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("unexpected token"));
+                }
+            };
+            let repeat = if self.eat("*") {
+                Some(Repeat::ZeroOrMore)
+            } else if self.eat("+") {
+                Some(Repeat::OneOrMore)
+            } else if self.eat("?") {
+                Some(Repeat::Optional)
+            } else {
+                None
+            };
+            Ok(Some(match repeat {
+                Some(repeat) => {
+                    // non-greedy marker
+                    self.eat("?");
+                    Expr::Repeat(Box::new(atom), repeat)
+                }
+                None => atom,
+            }))
+        }
+    }
+}
+
+/// Importer of pest grammars (`.pest`)
+///
+/// Supports sequences `~`, choices `|`, repetition `*`, `+`, `?`, lookaheads `!` and `&`,
+/// tags `#name = ...`, `SOI`, `EOI` and `ANY`. Doc comments `///` become docs of the node.
+///
+/// Atomic rules and rules built only from characters (ranges, `ASCII_DIGIT`, ...)
+/// are on the lexer level. `WHITESPACE` and `COMMENT` are skipped like the lexer skips whitespace.
+/// Bounded repetition, the stack and other builtin rules are dropped with a warning.
+pub mod pest {
+    use super::*;
+
+    /// Converts a pest grammar
+    pub fn import(text: &str) -> Result<Import, ImportError> {
+        let tokens = lex(text)?;
+        let mut parser = Pest {
+            tokens,
+            pos: 0,
+            warnings: Vec::new(),
+        };
+        let mut rules = parser.grammar()?;
+        let names = rules.iter().map(|r| r.name.clone()).collect::<Vec<_>>();
+        for rule in rules.iter_mut() {
+            resolve(&mut rule.expr, &names);
+            rule.lexer |= rule.skip || lexical(&rule.expr);
+        }
+        let mut import = lower(rules);
+        import.warnings.splice(0..0, parser.warnings);
+        Ok(import)
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Tok {
+        Ident(String),
+        Str(String),
+        Char(char),
+        Number(usize),
+        Doc(String),
+        Punct(&'static str),
+    }
+
+    const PUNCTS: [&str; 18] = [
+        "..", ",", "=", "{", "}", "(", ")", "~", "|", "*", "+", "?", "!", "&", "^", "@", "$", "#",
+    ];
+
+    /// Splits the grammar into tokens with their line and column
+    fn lex(text: &str) -> Result<Vec<(Tok, usize, usize)>, ImportError> {
+        let mut tokens = Vec::new();
+        let mut chars = text.char_indices().peekable();
+        let (mut line, mut line_start) = (1, 0);
+        while let Some((i, c)) = chars.next() {
+            let column = text[line_start..i].chars().count() + 1;
+            let rest = &text[i..];
+            let error = |message: &str| ImportError {
+                line,
+                column,
+                message: message.to_string(),
+            };
+            let len = if c == '\n' {
+                line += 1;
+                line_start = i + 1;
+                1
+            } else if c.is_whitespace() {
+                c.len_utf8()
+            } else if let Some(doc) = rest.strip_prefix("///") {
+                let doc = &doc[..doc.find('\n').unwrap_or(doc.len())];
+                tokens.push((Tok::Doc(doc.trim().to_string()), line, column));
+                doc.len() + 3
+            } else if rest.starts_with("//") {
+                rest.find('\n').unwrap_or(rest.len())
+            } else if rest.starts_with("/*") {
+                let end = rest
+                    .find("*/")
+                    .ok_or_else(|| error("unterminated comment"))?;
+                line += rest[..end].matches('\n').count();
+                if let Some(last) = rest[..end].rfind('\n') {
+                    line_start = i + last + 1;
+                }
+                end + 2
+            } else if c.is_alphabetic() || c == '_' {
+                let len = rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                tokens.push((Tok::Ident(rest[..len].to_string()), line, column));
+                len
+            } else if c.is_ascii_digit() {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                let number = rest[..len]
+                    .parse()
+                    .map_err(|_| error("number is too large"))?;
+                tokens.push((Tok::Number(number), line, column));
+                len
+            } else if c == '"' || c == '\'' {
+                let (value, len) = string(rest).map_err(error)?;
+                match c {
+                    '"' => tokens.push((Tok::Str(value), line, column)),
+                    _ => {
+                        let mut value = value.chars();
+                        match (value.next(), value.next()) {
+                            (Some(c), None) => tokens.push((Tok::Char(c), line, column)),
+                            _ => return Err(error("character literal must be one character")),
+                        }
+                    }
+                }
+                len
+            } else {
+                let punct = PUNCTS
+                    .iter()
+                    .find(|p| rest.starts_with(**p))
+                    .ok_or_else(|| error(&format!("unexpected character '{}'", c)))?;
+                tokens.push((Tok::Punct(punct), line, column));
+                punct.len()
+            };
+            // the first character was already consumed
+            for _ in 1..rest[..len].chars().count() {
+                chars.next();
+            }
+        }
+        Ok(tokens)
+    }
+
+    /// Reads a quoted literal, returns its value and length including the quotes
+    fn string(text: &str) -> Result<(String, usize), &'static str> {
+        let quote = text.chars().next().ok_or("unterminated literal")?;
+        let mut value = String::new();
+        let mut chars = text.char_indices().skip(1);
+        while let Some((i, c)) = chars.next() {
+            match c {
+                _ if c == quote => return Ok((value, i + 1)),
+                '\\' => match chars.next().ok_or("unterminated literal")?.1 {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'r' => value.push('\r'),
+                    '0' => value.push('\0'),
+                    escape @ ('u' | 'x') => {
+                        // `\x41` has two digits, `\u{41}` any number of them in braces
+                        let rest = text.get(i + 2..).unwrap_or_default();
+                        let digits = match escape {
+                            'x' => rest.get(..2),
+                            _ => rest
+                                .strip_prefix('{')
+                                .and_then(|rest| rest.get(..rest.find('}')?)),
+                        };
+                        let code = digits
+                            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                            .and_then(char::from_u32)
+                            .ok_or("invalid escape")?;
+                        value.push(code);
+                        let len = match escape {
+                            'x' => 2,
+                            _ => digits.map_or(0, str::len) + 2,
+                        };
+                        for _ in 0..len {
+                            chars.next();
+                        }
+                    }
+                    other => value.push(other),
+                },
+                _ => value.push(c),
+            }
+        }
+        Err("unterminated literal")
+    }
+
+    /// Replaces references to builtin rules
+    fn resolve(expr: &mut Expr, names: &[String]) {
+        match expr {
+            Expr::Ref(name) if !names.contains(name) => {
+                *expr = match name.as_str() {
+                    "SOI" => Expr::Seq(Vec::new()),
+                    "EOI" => Expr::Eof,
+                    "ANY" => Expr::Any,
+                    _ => Expr::Chars(format!("builtin rule {}", name)),
+                }
+            }
+            Expr::Seq(items) | Expr::Choice(items) => {
+                items.iter_mut().for_each(|item| resolve(item, names))
+            }
+            Expr::Repeat(expr, _)
+            | Expr::Label { expr, .. }
+            | Expr::Not(expr)
+            | Expr::And(expr) => resolve(expr, names),
+            _ => (),
+        }
+    }
+
+    /// Whether the rule matches characters rather than tokens
+    fn lexical(expr: &Expr) -> bool {
+        fn walk(expr: &Expr, chars: &mut bool) -> bool {
+            match expr {
+                Expr::Ref(_) => false,
+                Expr::Chars(_) => {
+                    *chars = true;
+                    true
+                }
+                Expr::Seq(items) | Expr::Choice(items) => {
+                    items.iter().all(|item| walk(item, chars))
+                }
+                Expr::Repeat(expr, _)
+                | Expr::Label { expr, .. }
+                | Expr::Not(expr)
+                | Expr::And(expr) => walk(expr, chars),
+                _ => true,
+            }
+        }
+        let mut chars = false;
+        walk(expr, &mut chars) && chars
+    }
+
+    struct Pest {
+        tokens: Vec<(Tok, usize, usize)>,
+        pos: usize,
+        warnings: Vec<ImportWarning>,
+    }
+
+    impl Pest {
+        fn peek(&self) -> Option<&Tok> {
+            self.tokens.get(self.pos).map(|t| &t.0)
+        }
+
+        fn next(&mut self) -> Option<Tok> {
+            let token = self.tokens.get(self.pos).map(|t| t.0.clone());
+            self.pos += 1;
+            token
+        }
+
+        fn eat(&mut self, punct: &str) -> bool {
+            let found = matches!(self.peek(), Some(Tok::Punct(p)) if *p == punct);
+            if found {
+                self.pos += 1;
+            }
+            found
+        }
+
+        fn expect(&mut self, punct: &str) -> Result<(), ImportError> {
+            match self.eat(punct) {
+                true => Ok(()),
+                false => Err(self.error(format!("expected '{}'", punct))),
+            }
+        }
+
+        fn error(&self, message: impl Into<String>) -> ImportError {
+            let (line, column) = self
+                .tokens
+                .get(self.pos)
+                .or(self.tokens.last())
+                .map(|t| (t.1, t.2))
+                .unwrap_or((1, 1));
+            ImportError {
+                line,
+                column,
+                message: message.into(),
+            }
+        }
+
+        fn warn(&mut self, rule: &str, message: impl Into<String>) {
+            self.warnings.push(ImportWarning {
+                rule: rule.to_string(),
+                message: message.into(),
+            });
+        }
+
+        fn grammar(&mut self) -> Result<Vec<RuleDef>, ImportError> {
+            let mut rules = Vec::new();
+            let mut docs = Vec::new();
+            while let Some(token) = self.next() {
+                let name = match token {
+                    Tok::Doc(doc) => {
+                        docs.push(doc);
+                        continue;
+                    }
+                    Tok::Ident(name) => name,
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("expected a rule"));
+                    }
+                };
+                self.expect("=")?;
+                let atomic = match self.peek() {
+                    Some(Tok::Punct("@" | "$")) => {
+                        self.pos += 1;
+                        true
+                    }
+                    Some(Tok::Punct("!")) => {
+                        self.pos += 1;
+                        false
+                    }
+                    Some(Tok::Ident(silent)) if silent == "_" => {
+                        self.pos += 1;
+                        false
+                    }
+                    _ => false,
+                };
+                self.expect("{")?;
+                let expr = self.choice(&name)?;
+                self.expect("}")?;
+                let skip = name == "WHITESPACE" || name == "COMMENT";
+                rules.push(RuleDef {
+                    name,
+                    expr,
+                    lexer: atomic,
+                    fragment: false,
+                    skip,
+                    docs: match docs.is_empty() {
+                        true => None,
+                        false => Some(docs.join("\n")),
+                    },
+                });
+                docs.clear();
+            }
+            Ok(rules)
+        }
+
+        fn choice(&mut self, rule: &str) -> Result<Expr, ImportError> {
+            self.eat("|");
+            let mut alts = vec![self.sequence(rule)?];
+            while self.eat("|") {
+                alts.push(self.sequence(rule)?);
+            }
+            Ok(match alts.len() {
+                1 => alts.pop().unwrap(),
+                _ => Expr::Choice(alts),
+            })
+        }
+
+        fn sequence(&mut self, rule: &str) -> Result<Expr, ImportError> {
+            let mut items = vec![self.term(rule)?];
+            while self.eat("~") {
+                items.push(self.term(rule)?);
+            }
+            Ok(match items.len() {
+                1 => items.pop().unwrap(),
+                _ => Expr::Seq(items),
+            })
+        }
+
+        fn term(&mut self, rule: &str) -> Result<Expr, ImportError> {
+            if self.eat("#") {
+                let Some(Tok::Ident(name)) = self.next() else {
+                    self.pos -= 1;
+                    return Err(self.error("expected a tag name"));
+                };
+                self.expect("=")?;
+                let expr = self.term(rule)?;
+                return Ok(Expr::Label {
+                    name,
+                    list: false,
+                    expr: Box::new(expr),
+                });
+            }
+            if self.eat("!") {
+                return Ok(Expr::Not(Box::new(self.term(rule)?)));
+            }
+            if self.eat("&") {
+                return Ok(Expr::And(Box::new(self.term(rule)?)));
+            }
+            let mut expr = match self.next() {
+                Some(Tok::Str(literal)) => Expr::Literal(literal),
+                Some(Tok::Punct("^")) => match self.next() {
+                    Some(Tok::Str(literal)) => {
+                        self.warn(rule, "case insensitive literal is matched exactly");
+                        Expr::Literal(literal)
+                    }
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("expected a string after '^'"));
+                    }
+                },
+                Some(Tok::Char(_)) => {
+                    self.expect("..")?;
+                    match self.next() {
+                        Some(Tok::Char(_)) => Expr::Chars("character range".to_string()),
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("expected a character"));
+                        }
+                    }
+                }
+                Some(Tok::Ident(name)) => match name.as_str() {
+                    "PUSH" | "PEEK" | "POP" | "POP_ALL" | "DROP" | "PEEK_ALL" => {
+                        if self.eat("(") {
+                            self.choice(rule)?;
+                            self.expect(")")?;
+                        }
+                        Expr::Unsupported("stack operation".to_string())
+                    }
+                    _ => Expr::Ref(name),
+                },
+                Some(Tok::Punct("(")) => {
+                    let expr = self.choice(rule)?;
+                    self.expect(")")?;
+                    expr
+                }
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected an expression"));
+                }
+            };
+            loop {
+                expr = if self.eat("*") {
+                    Expr::Repeat(Box::new(expr), Repeat::ZeroOrMore)
+                } else if self.eat("+") {
+                    Expr::Repeat(Box::new(expr), Repeat::OneOrMore)
+                } else if self.eat("?") {
+                    Expr::Repeat(Box::new(expr), Repeat::Optional)
+                } else if matches!(
+                    self.tokens.get(self.pos..self.pos + 2),
+                    Some([
+                        (Tok::Punct("{"), ..),
+                        (Tok::Number(_) | Tok::Punct(","), ..)
+                    ])
+                ) {
+                    while !matches!(self.next(), Some(Tok::Punct("}")) | None) {}
+                    Expr::Unsupported("bounded repetition".to_string())
+                } else {
+                    return Ok(expr);
+                };
+            }
+        }
+    }
+}
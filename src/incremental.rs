@@ -0,0 +1,403 @@
+//! Parsing an edited text again while sharing the unchanged parts of the previous result
+//!
+//! `crate::Parser::reparse` parses only the smallest node around an edit. Nodes are
+//! shared like with an `Arc` (`memory::NodeRef` of `parser::Nodes::Node`), so every node
+//! that ends before the edit is shared with the previous result instead of being cloned.
+//! Nodes after the edit are copied with their byte offsets moved by the length difference
+//! of the edit, and the nodes around the edit are rebuilt on the way to the parsed node.
+//!
+//! Each node carries the `ParseResult::version` it was built in (`Node::version`),
+//! so caches of per node data can tell the stale nodes from the shared ones.
+//!
+//! Recovered errors outside of the parsed node are kept and moved like the nodes.
+//! Scopes are not, a result with scopes is always parsed in full.
+
+use core::ops::Range;
+
+use crate::{
+    lexer::Token,
+    memory::{NodeRef, NodeVec, ParseAlloc},
+    parser::{
+        BracketPair, Node, Nodes, ParseError, ParseResult, ParseState, TokenSource, VariableKind,
+    },
+    Map,
+};
+
+// Choose between std and alloc
+cfg_if::cfg_if! {
+    if #[cfg(feature = "std")] {
+        extern crate std;
+        use std::prelude::v1::*;
+    } else {
+        extern crate alloc;
+        use alloc::boxed::Box;
+        use alloc::string::*;
+        use alloc::vec::*;
+    }
+}
+
+/// Replacement of a byte range of the original text, see `crate::Parser::reparse`
+/// and `crate::scopes::Scopes::rename`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub span: Range<usize>,
+    pub text: String,
+}
+
+impl TextEdit {
+    /// Bytes of the inserted text once the edit is applied
+    pub fn new_span(&self) -> Range<usize> {
+        self.span.start..self.span.start + self.text.len()
+    }
+
+    /// Applies the edits to the text they were made for
+    ///
+    /// Edits are applied in the order of their start. An edit that overlaps one applied
+    /// before it or that does not fit the text is skipped, the others are still applied.
+    pub fn apply(text: &str, edits: &[TextEdit]) -> String {
+        let mut edits = edits.iter().collect::<Vec<_>>();
+        edits.sort_by_key(|edit| edit.span.start);
+        let mut out = String::with_capacity(text.len());
+        let mut last = 0;
+        for edit in edits {
+            let (Some(before), Some(_)) =
+                (text.get(last..edit.span.start), text.get(edit.span.clone()))
+            else {
+                continue;
+            };
+            out.push_str(before);
+            out.push_str(&edit.text);
+            last = edit.span.end;
+        }
+        out.push_str(&text[last..]);
+        out
+    }
+}
+
+impl<'a> crate::Parser<'a> {
+    /// Parses the text after an edit again, reusing the nodes of `previous` the edit
+    /// did not touch
+    ///
+    /// `previous` is the result for the text before the edit, `tokens` and `text` are
+    /// those after it. The smallest node that contains the edited bytes is parsed again
+    /// from its start, falling back to its parents and to a full parse when the node
+    /// no longer ends where the edit moved its end. The node is parsed with fresh globals,
+    /// like with `crate::Parser::parse_node_at`.
+    ///
+    /// Errors recovered outside of the parsed node are kept, those inside of it are
+    /// replaced by the errors of the new parse. A node that an error reaches into from
+    /// outside is not parsed on its own, its parent is.
+    ///
+    /// Results with scopes and results that did not parse the whole text are always
+    /// parsed in full, the positions in their scopes can not be moved node by node.
+    pub fn reparse<T: TokenSource<'a> + ?Sized>(
+        &'a self,
+        previous: &ParseResult<'a>,
+        edit: &TextEdit,
+        tokens: &T,
+        text: &'a str,
+    ) -> Result<ParseResult<'a>, Box<ParseError<'a>>> {
+        let version = previous.version + 1;
+        let tokens = self.parser.filter_tokens(text, tokens);
+        let reusable = previous.remaining.is_none() && !scoped(&previous.globals);
+        let path = match reusable {
+            true => path(&previous.entry, edit),
+            false => Vec::new(),
+        };
+        for target in path.iter().rev() {
+            let Some((node, errors, brackets)) = self.reparse_node(target, edit, &tokens, text)
+            else {
+                continue;
+            };
+            let mut moved = Moved {
+                edit,
+                delta: edit.text.len() as isize - edit.span.len() as isize,
+                version,
+                target,
+                replacement: NodeRef::new(stamp(node, version)),
+                done: Map::new(),
+            };
+            let Some(errors) = moved.errors(&previous.errors, errors) else {
+                continue;
+            };
+            let brackets = moved.brackets(&previous.brackets, brackets);
+            let entry = moved.rebuild(&previous.entry);
+            return Ok(ParseResult {
+                entry,
+                globals: previous.globals.clone(),
+                stats: None,
+                errors,
+                remaining: None,
+                brackets,
+                version,
+            });
+        }
+        let mut result = self
+            .parser
+            .parse_in(
+                &self.grammar,
+                &self.lexer,
+                text,
+                &tokens,
+                previous.entry.alloc,
+            )
+            .map_err(Box::new)?;
+        result.entry = stamp(result.entry, version);
+        result.version = version;
+        Ok(result)
+    }
+
+    /// The node parsed again in its place after the edit with the errors recovered in it
+    /// and its bracket pairs, None if it does not fit there
+    fn reparse_node(
+        &'a self,
+        target: &Node<'a>,
+        edit: &TextEdit,
+        tokens: &dyn TokenSource<'a>,
+        text: &'a str,
+    ) -> Option<(Node<'a>, NodeVec<ParseError<'a>>, NodeVec<BracketPair>)> {
+        let start = target.first_string_idx;
+        let end = target
+            .last_string_idx
+            .checked_add_signed(edit.text.len() as isize - edit.span.len() as isize)?;
+        let first = tokens.partition_point(|token| token.index < start);
+        let last = tokens.partition_point(|token| token.index < end);
+        let result = self
+            .parser
+            .parse_node_at_in(
+                &self.grammar,
+                &self.lexer,
+                target.name.as_str(),
+                text,
+                tokens,
+                first..last,
+                target.alloc,
+            )
+            .ok()?;
+        let fits = result.remaining.is_none()
+            && result.entry.first_string_idx == start
+            && result.entry.last_string_idx == end;
+        fits.then_some((result.entry, result.errors, result.brackets))
+    }
+}
+
+/// The globals hold positions of declarations that would have to be moved as well
+fn scoped(globals: &ParseState) -> bool {
+    let scopes = &globals.scopes;
+    scopes.scopes().len() > 1 || !scopes.declarations().is_empty() || !scopes.uses().is_empty()
+}
+
+/// Nodes below the entry that contain the edited bytes, outermost first
+///
+/// A node qualifies only if the edit is strictly inside of it, text inserted at
+/// the edge of a node could as well belong to its neighbour
+fn path<'n, 'a>(entry: &'n Node<'a>, edit: &TextEdit) -> Vec<&'n Node<'a>> {
+    let contains = |node: &Node| {
+        node.first_string_idx < edit.span.start && edit.span.end < node.last_string_idx
+    };
+    let mut path = Vec::new();
+    let mut current = entry;
+    'walk: loop {
+        for child in children(current) {
+            if let Nodes::Node(child) = child {
+                if contains(child) {
+                    path.push(&**child);
+                    current = child;
+                    continue 'walk;
+                }
+            }
+        }
+        return path;
+    }
+}
+
+/// Nodes, tokens and skipped regions kept by the node
+fn children<'n, 'a>(node: &'n Node<'a>) -> impl Iterator<Item = &'n Nodes<'a>> {
+    let variables = node.variables.values().flat_map(|value| match value {
+        VariableKind::Node(Some(child)) => core::slice::from_ref(child),
+        VariableKind::NodeList(list) => list.as_slice(),
+        _ => &[],
+    });
+    variables.chain(node.children.iter().flatten())
+}
+
+/// Sets the version of the node and of all nodes in it, the nodes are not shared yet
+fn stamp(mut node: Node<'_>, version: u64) -> Node<'_> {
+    fn stamp_all(node: &mut Node, version: u64) {
+        node.version = version;
+        for_each_child(node, &mut |child| {
+            if let Nodes::Node(child) = child {
+                stamp_all(NodeRef::make_mut(child), version);
+            }
+        });
+    }
+    stamp_all(&mut node, version);
+    node
+}
+
+fn for_each_child<'a>(node: &mut Node<'a>, f: &mut impl FnMut(&mut Nodes<'a>)) {
+    for value in node.variables.values_mut() {
+        match value {
+            VariableKind::Node(Some(child)) => f(child),
+            VariableKind::NodeList(list) => list.iter_mut().for_each(&mut *f),
+            _ => (),
+        }
+    }
+    if let Some(children) = &mut node.children {
+        children.iter_mut().for_each(f);
+    }
+}
+
+/// Builds the tree after the edit out of the tree before it
+struct Moved<'e, 'n, 'a> {
+    edit: &'e TextEdit,
+    /// Length difference of the edit
+    delta: isize,
+    version: u64,
+    /// Node that was parsed again
+    target: &'n Node<'a>,
+    replacement: NodeRef<'a>,
+    /// Nodes already handled, keyed by their address in the previous tree
+    ///
+    /// A child kept in a variable and in `Node::children` stays a single node
+    done: Map<usize, NodeRef<'a>>,
+}
+
+impl<'a> Moved<'_, '_, 'a> {
+    /// Copy of a node on the path to the edited node
+    fn rebuild(&mut self, node: &Node<'a>) -> Node<'a> {
+        let mut node = node.clone();
+        for_each_child(&mut node, &mut |child| self.nodes(child));
+        node.last_string_idx = self.moved(node.last_string_idx);
+        node.version = self.version;
+        self.tokens(&mut node);
+        node
+    }
+
+    /// Copy of a node after the edit
+    fn shift(&mut self, node: &Node<'a>) -> Node<'a> {
+        let mut node = node.clone();
+        for_each_child(&mut node, &mut |child| self.nodes(child));
+        node.first_string_idx = self.moved(node.first_string_idx);
+        node.last_string_idx = self.moved(node.last_string_idx);
+        node.version = self.version;
+        self.tokens(&mut node);
+        node
+    }
+
+    /// Errors of the previous result outside of the parsed node, moved like the nodes,
+    /// with the errors of the new parse in place of the ones inside of it
+    ///
+    /// None if an error of the previous result reaches into the parsed node from outside
+    fn errors(
+        &mut self,
+        previous: &[ParseError<'a>],
+        parsed: NodeVec<ParseError<'a>>,
+    ) -> Option<NodeVec<ParseError<'a>>> {
+        let (start, end) = (self.target.first_string_idx, self.target.last_string_idx);
+        let mut errors = self.alloc().vec();
+        errors.reserve(previous.len() + parsed.len());
+        let mut parsed = Some(parsed);
+        for err in previous {
+            if err.span.end <= start {
+                errors.push(err.clone());
+            } else if err.span.start >= end {
+                errors.extend(parsed.take().into_iter().flatten());
+                let mut err = err.clone();
+                err.span = self.moved(err.span.start)..self.moved(err.span.end);
+                err.node = err.node.map(|node| self.shift(&node));
+                errors.push(err);
+            } else if err.span.start < start || err.span.end > end {
+                return None;
+            }
+        }
+        errors.extend(parsed.into_iter().flatten());
+        Some(errors)
+    }
+
+    /// Bracket pairs of the previous result outside of the parsed node, moved like the
+    /// nodes, with the pairs of the new parse in place of the ones inside of it
+    ///
+    /// A pair is matched by a single node, so a pair is either inside of the parsed node
+    /// or has both brackets outside of it
+    fn brackets(
+        &self,
+        previous: &[BracketPair],
+        parsed: NodeVec<BracketPair>,
+    ) -> NodeVec<BracketPair> {
+        let (start, end) = (self.target.first_string_idx, self.target.last_string_idx);
+        let moved = |span: &Range<usize>| match span.start >= end {
+            true => self.moved(span.start)..self.moved(span.end),
+            false => span.clone(),
+        };
+        let kept = previous
+            .iter()
+            .filter(|pair| pair.open.start < start || pair.close.end > end)
+            .map(|pair| BracketPair {
+                open: moved(&pair.open),
+                close: moved(&pair.close),
+            });
+        let mut brackets = self.alloc().collect(kept.chain(parsed));
+        brackets.sort_by_key(|pair| pair.open.start);
+        brackets
+    }
+
+    fn nodes(&mut self, nodes: &mut Nodes<'a>) {
+        match nodes {
+            Nodes::Node(node) => {
+                let key = NodeRef::as_ptr(node) as usize;
+                if let Some(done) = self.done.get(&key) {
+                    *node = done.clone();
+                    return;
+                }
+                let new = if core::ptr::eq(&**node, self.target) {
+                    self.replacement.clone()
+                } else if node.last_string_idx <= self.edit.span.start {
+                    // before the edit, shared as it is
+                    return;
+                } else if node.first_string_idx >= self.edit.span.end {
+                    NodeRef::new(self.shift(node))
+                } else {
+                    NodeRef::new(self.rebuild(node))
+                };
+                self.done.insert(key, new.clone());
+                *node = new;
+            }
+            Nodes::Token(token) => self.token(token),
+            Nodes::Error { span, children, .. } => {
+                if span.start >= self.edit.span.end {
+                    *span = self.moved(span.start)..self.moved(span.end);
+                    children.iter_mut().for_each(|child| self.nodes(child));
+                }
+            }
+        }
+    }
+
+    /// Moves the tokens the node keeps outside of its variables
+    fn tokens(&self, node: &mut Node<'a>) {
+        if let Some(tokens) = &mut node.tokens {
+            tokens.iter_mut().for_each(|token| self.token(token));
+        }
+        if let Some(trivia) = node.trivia.as_deref_mut() {
+            for token in trivia.leading.iter_mut().chain(&mut trivia.trailing) {
+                self.token(token);
+            }
+        }
+    }
+
+    fn token(&self, token: &mut Token<'a>) {
+        if token.index >= self.edit.span.end {
+            token.index = self.moved(token.index);
+        }
+    }
+
+    /// Allocator of the previous result, the new one is kept there as well
+    fn alloc(&self) -> ParseAlloc {
+        self.target.alloc
+    }
+
+    /// Byte index after the edit of an index after the edited bytes
+    fn moved(&self, index: usize) -> usize {
+        index.saturating_add_signed(self.delta)
+    }
+}
@@ -0,0 +1,275 @@
+//! Interned names shared by the lexer, grammars and parse results
+//!
+//! Names of nodes and variables repeat in every grammar node, rule and parsed node.
+//! They are kept once in the crate-wide `Interner` and passed around as `Symbol`s,
+//! which compare and hash as integers. `Symbol::as_str` reads the text back without
+//! locking, so the `&str` accessors of the crate stay cheap.
+//!
+//! Interned strings are never freed: intern names, not the text of documents.
+
+use core::{
+    fmt,
+    num::NonZeroU32,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU32, Ordering},
+};
+
+// Choose between std and alloc
+cfg_if::cfg_if! {
+    if #[cfg(feature = "std")] {
+        extern crate std;
+        use std::prelude::v1::*;
+        use std::collections::BTreeMap;
+        use std::sync::Mutex;
+    } else {
+        extern crate alloc;
+        use alloc::boxed::Box;
+        use alloc::collections::BTreeMap;
+        use alloc::vec;
+        use core::cell::UnsafeCell;
+        use core::sync::atomic::AtomicBool;
+    }
+}
+
+/// Id of an interned string, see the module documentation
+///
+/// Equal and hashed as an integer, ordered by the text so sorted names stay in the
+/// order they had as strings
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(NonZeroU32);
+
+impl Symbol {
+    /// Symbol of the text, added to the crate-wide `Interner` if it is new
+    pub fn intern(text: &str) -> Symbol {
+        Interner::global().intern(text)
+    }
+
+    /// Symbol of the text if it was interned before
+    pub fn get(text: &str) -> Option<Symbol> {
+        Interner::global().get(text)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        Interner::global().resolve(self)
+    }
+
+    /// Position of the symbol in the `Interner`, symbols are numbered from 0
+    pub fn index(self) -> usize {
+        self.0.get() as usize - 1
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        match self == other {
+            true => core::cmp::Ordering::Equal,
+            false => self.as_str().cmp(other.as_str()),
+        }
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<Symbol> for str {
+    fn eq(&self, other: &Symbol) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl PartialEq<Symbol> for &str {
+    fn eq(&self, other: &Symbol) -> bool {
+        *self == other.as_str()
+    }
+}
+
+/// The empty name
+impl Default for Symbol {
+    fn default() -> Self {
+        Symbol::intern("")
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(text: &str) -> Self {
+        Symbol::intern(text)
+    }
+}
+
+/// Number of texts in the first chunk of the table, every next chunk is twice as large
+const FIRST_CHUNK: usize = 64;
+/// Enough chunks for every `u32` index
+const CHUNKS: usize = 26;
+
+/// Crate-wide table of interned strings, see the module documentation
+///
+/// Interning takes a lock, reading a text back does not: texts are kept in chunks
+/// that never move and a text is written before its symbol is handed out.
+pub struct Interner {
+    /// Symbols by their text
+    ids: Lock<BTreeMap<&'static str, Symbol>>,
+    /// Texts by the index of their symbol
+    chunks: [AtomicPtr<&'static str>; CHUNKS],
+    /// Number of texts written to the chunks
+    len: AtomicU32,
+}
+
+static GLOBAL: Interner = Interner::new();
+
+impl Interner {
+    const fn new() -> Self {
+        Interner {
+            ids: Lock::new(BTreeMap::new()),
+            chunks: [const { AtomicPtr::new(ptr::null_mut()) }; CHUNKS],
+            len: AtomicU32::new(0),
+        }
+    }
+
+    /// The interner behind every `Symbol`
+    pub fn global() -> &'static Interner {
+        &GLOBAL
+    }
+
+    /// Symbol of the text, the text is copied the first time it is interned
+    pub fn intern(&self, text: &str) -> Symbol {
+        self.ids.with(|ids| {
+            if let Some(symbol) = ids.get(text) {
+                return *symbol;
+            }
+            let text: &'static str = Box::leak(Box::from(text));
+            let index = self.len.load(Ordering::Relaxed) as usize;
+            let (chunk, offset) = Self::slot(index);
+            let mut texts = self.chunks[chunk].load(Ordering::Acquire);
+            if texts.is_null() {
+                texts = Box::leak(vec![""; FIRST_CHUNK << chunk].into_boxed_slice()).as_mut_ptr();
+                self.chunks[chunk].store(texts, Ordering::Release);
+            }
+            // Safety: the slot is in the chunk and no symbol of it was handed out yet
+            unsafe { texts.add(offset).write(text) };
+            let symbol = Symbol(NonZeroU32::new(index as u32 + 1).expect("too many symbols"));
+            self.len.store(index as u32 + 1, Ordering::Release);
+            ids.insert(text, symbol);
+            symbol
+        })
+    }
+
+    /// Symbol of the text if it was interned before
+    pub fn get(&self, text: &str) -> Option<Symbol> {
+        self.ids.with(|ids| ids.get(text).copied())
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &'static str {
+        let index = symbol.index();
+        assert!(index < self.len.load(Ordering::Acquire) as usize);
+        let (chunk, offset) = Self::slot(index);
+        let texts = self.chunks[chunk].load(Ordering::Acquire);
+        // Safety: the text was written before the length was raised past it
+        unsafe { *texts.add(offset) }
+    }
+
+    /// Number of interned texts
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Chunk of the index and the offset in it
+    fn slot(index: usize) -> (usize, usize) {
+        let chunk = (usize::BITS - 1 - (index / FIRST_CHUNK + 1).leading_zeros()) as usize;
+        (chunk, index - FIRST_CHUNK * ((1 << chunk) - 1))
+    }
+}
+
+impl fmt::Debug for Interner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interner")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Mutex of the std library, or a spin lock without it
+#[cfg(feature = "std")]
+struct Lock<T>(Mutex<T>);
+
+#[cfg(feature = "std")]
+impl<T> Lock<T> {
+    const fn new(value: T) -> Self {
+        Lock(Mutex::new(value))
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.0.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+}
+
+/// Mutex of the std library, or a spin lock without it
+#[cfg(not(feature = "std"))]
+struct Lock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// Safety: the value is only reached while the lock is held
+#[cfg(not(feature = "std"))]
+unsafe impl<T: Send> Sync for Lock<T> {}
+
+#[cfg(not(feature = "std"))]
+impl<T> Lock<T> {
+    const fn new(value: T) -> Self {
+        Lock {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        // Safety: the lock is held
+        let result = f(unsafe { &mut *self.value.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
@@ -1,6 +1,10 @@
 use smol_str::SmolStr;
 
-use crate::grammar::ErrorDefinition;
+use crate::{grammar::ErrorDefinition, intern};
+#[cfg(feature = "allocator")]
+use allocator_api2::alloc::Allocator;
+#[cfg(feature = "graphemes")]
+use unicode_segmentation::UnicodeSegmentation;
 
 // Choose between std and alloc
 cfg_if::cfg_if! {
@@ -8,6 +12,8 @@ cfg_if::cfg_if! {
         extern crate std;
         use std::prelude::v1::*;
         use std::fmt;
+        use std::borrow::Cow;
+        use std::sync::Arc;
     } else {
         extern crate alloc;
         use alloc::string::*;
@@ -15,6 +21,8 @@ cfg_if::cfg_if! {
         use alloc::vec;
         use alloc::format;
         use core::fmt;
+        use alloc::borrow::Cow;
+        use alloc::sync::Arc;
     }
 }
 
@@ -27,6 +35,14 @@ pub enum TokenKinds<'a> {
     Text,
     Whitespace,
     Control(ControlTokenKind),
+    /// Characters the lexer could not classify
+    ///
+    /// Only produced when `Lexer::unknown_chars` is set to `UnknownChars::Error`
+    Error,
+    /// Documentation comment including its markers, see `Lexer::doc_comments`
+    ///
+    /// Add `TokenKinds::DocComment` to `Grammar::ignored` so rules can skip over them
+    DocComment,
 }
 
 impl<'a> TokenKinds<'a> {
@@ -37,6 +53,19 @@ impl<'a> TokenKinds<'a> {
             _ => false,
         }
     }
+
+    /// Name of the kind, also used by `Display`
+    pub fn name(&self) -> &'a str {
+        match self {
+            TokenKinds::Token(token) | TokenKinds::Complex(token) => token,
+            TokenKinds::Text => "<text>",
+            TokenKinds::Whitespace => "<whitespace>",
+            TokenKinds::Control(ControlTokenKind::Eof) => "End of file",
+            TokenKinds::Control(ControlTokenKind::Eol) => "New line",
+            TokenKinds::Error => "<error>",
+            TokenKinds::DocComment => "<doc comment>",
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
@@ -50,16 +79,16 @@ pub type Preprocessor =
 
 pub struct PreprocessorError {
     pub err: ErrorDefinition,
-    pub location: TextLocation,
-    pub len: usize,
+    /// Bytes of the error, see `Lexer::locate` for its line and column
+    pub span: core::ops::Range<usize>,
 }
 
 impl<'a> fmt::Debug for PreprocessorError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{}:{} at {:?}\n{}",
-            self.err.code, self.err.header, self.location, self.err.header
+            "{}:{} at bytes {:?}\n{}",
+            self.err.code, self.err.header, self.span, self.err.header
         )
     }
 }
@@ -68,18 +97,319 @@ impl<'a> fmt::Display for PreprocessorError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{}:{} at {:?}\n{}",
-            self.err.code, self.err.header, self.location, self.err.header
+            "{}:{} at bytes {:?}\n{}",
+            self.err.code, self.err.header, self.span, self.err.header
         )
     }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for PreprocessorError {}
+
 #[derive(Debug, Clone)]
 pub struct Lexer {
-    /// Possible token kinds
-    pub(crate) token_kinds: Vec<SmolStr>,
+    /// Possible token kinds, sorted from the shortest
+    ///
+    /// Borrowed from a `TokenTable` until a token is added, added tokens are kept in
+    /// the `crate::intern` table shared with the grammars
+    pub(crate) token_kinds: Cow<'static, [&'static str]>,
+    /// Tags of `token_kinds` at the same index
+    token_tags: Cow<'static, [Option<&'static str>]>,
     longest_token_size: usize,
     pub preprocessors: Vec<Preprocessor>,
+    /// What to do with characters that can not be classified
+    pub unknown_chars: UnknownChars,
+    /// Skip the UTF-8 byte order mark at the start of the text
+    ///
+    /// Byte offsets of tokens still point into the original text
+    pub strip_bom: bool,
+    /// Treat `\r\n` as a single `Eol` token
+    pub crlf: bool,
+    /// Markers of documentation comments, lexed as `TokenKinds::DocComment`
+    ///
+    /// The opening marker is stored in the tag of the token
+    pub doc_comments: Vec<DocComment>,
+    /// Emit every character as its own `Text` token instead of lexing the text
+    ///
+    /// Meant for formats where token boundaries depend on the context (Markdown, heredocs),
+    /// the grammar matches the characters with `MatchToken::Char` and `MatchToken::CharClass`.
+    /// New lines and whitespace are ordinary characters and are never skipped,
+    /// token kinds of the lexer are ignored
+    pub scannerless: bool,
+    /// Treat extended grapheme clusters as single characters
+    ///
+    /// Tokens never split a cluster, so emoji and combining characters stay in one `Text` token,
+    /// columns of `Lexer::locate` count clusters and scannerless tokens are whole clusters
+    #[cfg(feature = "graphemes")]
+    pub graphemes: bool,
+    /// How tabs count in the columns of `Lexer::locate`
+    pub tabs: TabPolicy,
+    /// Reports how much of the text is lexed, see `Progress`
+    ///
+    /// Scannerless lexing only reports when done, `Lexer::lex_region` does not report
+    pub progress: Option<Progress>,
+}
+
+/// Callback for progress bars on big inputs, set with `Lexer::on_progress`
+/// and `parser::Parser::on_progress`
+///
+/// Gets the number of bytes consumed and the length of the whole text
+/// every `interval` tokens and once more when done.
+#[derive(Clone)]
+pub struct Progress {
+    /// Number of tokens between two reports
+    pub interval: usize,
+    callback: Arc<dyn Fn(usize, usize) + Send + Sync>,
+}
+
+impl Progress {
+    pub fn new(
+        interval: usize,
+        on_progress: impl Fn(usize, usize) + Send + Sync + 'static,
+    ) -> Self {
+        Progress {
+            interval: interval.max(1),
+            callback: Arc::new(on_progress),
+        }
+    }
+
+    pub fn report(&self, consumed: usize, total: usize) {
+        (self.callback)(consumed, total)
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+const NO_TOKENS: TokenTable<0> = TokenTable::new([]);
+
+/// Token kinds sorted at compile time, see `Lexer::with_table`
+#[derive(Debug, Clone)]
+pub struct TokenTable<const N: usize> {
+    kinds: [&'static str; N],
+    tags: [Option<&'static str>; N],
+    longest: usize,
+}
+
+impl<const N: usize> TokenTable<N> {
+    pub const fn new(tokens: [&'static str; N]) -> Self {
+        Self::tagged(tokens, [None; N])
+    }
+
+    /// Tokens that carry a tag, see `Lexer::add_token_tagged`
+    pub const fn tagged(
+        mut tokens: [&'static str; N],
+        mut tags: [Option<&'static str>; N],
+    ) -> Self {
+        // stable insertion sort by length, the order `Lexer::add_token` keeps
+        let mut i = 1;
+        while i < N {
+            let mut j = i;
+            while j > 0 && tokens[j - 1].len() > tokens[j].len() {
+                let token = tokens[j];
+                tokens[j] = tokens[j - 1];
+                tokens[j - 1] = token;
+                let tag = tags[j];
+                tags[j] = tags[j - 1];
+                tags[j - 1] = tag;
+                j -= 1;
+            }
+            i += 1;
+        }
+        let mut longest = 0;
+        let mut i = 0;
+        while i < N {
+            if tokens[i].len() > longest {
+                longest = tokens[i].len();
+            }
+            i += 1;
+        }
+        Self {
+            kinds: tokens,
+            tags,
+            longest,
+        }
+    }
+}
+
+/// Marker of a documentation comment
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum DocComment {
+    /// Comment from the marker to the end of the line, for example `///`
+    Line(&'static str),
+    /// Comment between an opening and a closing marker, for example `/**` and `*/`
+    Block(&'static str, &'static str),
+}
+
+/// Doc comments of Rust, C# and Java
+pub const C_DOC_COMMENTS: [DocComment; 2] =
+    [DocComment::Line("///"), DocComment::Block("/**", "*/")];
+
+/// Policy for characters the lexer can not classify
+///
+/// Those are control characters that are not whitespace (stray NUL, ESC, ...)
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Default)]
+pub enum UnknownChars {
+    /// Treat them as a part of text
+    #[default]
+    Text,
+    /// Emit `TokenKinds::Error` tokens so the parser and diagnostics can continue
+    ///
+    /// Add `TokenKinds::Error` to `Grammar::ignored` to skip them entirely
+    Error,
+    /// Stop lexing with an error
+    Fail,
+}
+
+impl UnknownChars {
+    fn is_unknown(&self, c: char) -> bool {
+        *self != UnknownChars::Text && c.is_control() && !c.is_whitespace()
+    }
+}
+
+pub const UNKNOWN_CHAR_ERROR: ErrorDefinition = ErrorDefinition {
+    header: "Unknown character",
+    code: "300",
+    msg: "Character could not be classified by the lexer",
+};
+
+pub const MIXED_LINE_ENDINGS: ErrorDefinition = ErrorDefinition {
+    header: "Mixed line endings",
+    code: "301",
+    msg: "Line ending differs from the first line ending in the file",
+};
+
+pub const OUT_OF_MEMORY: ErrorDefinition = ErrorDefinition {
+    header: "Out of memory",
+    code: "302",
+    msg: "The allocator could not make room for another token",
+};
+
+pub const BUFFER_FULL: ErrorDefinition = ErrorDefinition {
+    header: "Token buffer full",
+    code: "303",
+    msg: "The text has more tokens than the buffer can hold",
+};
+
+pub const HEAP_REQUIRED: ErrorDefinition = ErrorDefinition {
+    header: "Heap required",
+    code: "304",
+    msg: "Preprocessors and scannerless lexing can not write into a fixed buffer",
+};
+
+pub const INVALID_REGION: ErrorDefinition = ErrorDefinition {
+    header: "Invalid region",
+    code: "305",
+    msg: "The region is reversed, ends after the text or splits a character",
+};
+
+pub const GLOBAL_ALLOCATOR_REQUIRED: ErrorDefinition = ErrorDefinition {
+    header: "Global allocator required",
+    code: "306",
+    msg: "Preprocessors return their tokens in the global allocator",
+};
+
+/// Characters of the token text shown by `Lexer::dump`
+pub const DUMP_PREVIEW: usize = 24;
+
+/// Storage the lexer pushes tokens into
+trait TokenSink<'tok> {
+    fn push(&mut self, token: Token<'tok>) -> Result<(), PreprocessorError>;
+}
+
+impl<'tok> TokenSink<'tok> for Vec<Token<'tok>> {
+    fn push(&mut self, token: Token<'tok>) -> Result<(), PreprocessorError> {
+        Vec::push(self, token);
+        Ok(())
+    }
+}
+
+/// Fixed buffer filled by `Lexer::lex_into`
+struct SliceSink<'s, 'tok> {
+    buf: &'s mut [Token<'tok>],
+    len: usize,
+}
+
+impl<'tok> TokenSink<'tok> for SliceSink<'_, 'tok> {
+    fn push(&mut self, token: Token<'tok>) -> Result<(), PreprocessorError> {
+        let slot = self.buf.get_mut(self.len).ok_or(PreprocessorError {
+            err: BUFFER_FULL,
+            span: token.span(),
+        })?;
+        *slot = token;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// Passes the tokens on and reports `Lexer::progress`
+struct ProgressSink<'s, S> {
+    tokens: &'s mut S,
+    progress: Option<&'s Progress>,
+    total: usize,
+    count: usize,
+}
+
+impl<'s, S> ProgressSink<'s, S> {
+    fn new(tokens: &'s mut S, progress: Option<&'s Progress>, total: usize) -> Self {
+        ProgressSink {
+            tokens,
+            progress,
+            total,
+            count: 0,
+        }
+    }
+}
+
+impl<'tok, S: TokenSink<'tok>> TokenSink<'tok> for ProgressSink<'_, S> {
+    fn push(&mut self, token: Token<'tok>) -> Result<(), PreprocessorError> {
+        let consumed = token.index + token.len;
+        let done = token.kind == TokenKinds::Control(ControlTokenKind::Eof);
+        self.tokens.push(token)?;
+        if let Some(progress) = self.progress {
+            self.count += 1;
+            if done || self.count.is_multiple_of(progress.interval) {
+                progress.report(consumed, self.total);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "allocator")]
+impl<'tok, A: Allocator> TokenSink<'tok> for allocator_api2::vec::Vec<Token<'tok>, A> {
+    fn push(&mut self, token: Token<'tok>) -> Result<(), PreprocessorError> {
+        self.try_reserve(1).map_err(|_| PreprocessorError {
+            err: OUT_OF_MEMORY,
+            span: token.span(),
+        })?;
+        allocator_api2::vec::Vec::push(self, token);
+        Ok(())
+    }
+}
+
+const BOM: char = '\u{FEFF}';
+
+/// Character used in place of non-ASCII bytes by `ByteEncoding::Raw`
+pub const RAW_BYTE_PLACEHOLDER: char = '\u{1A}';
+
+/// How bytes are turned into text by `Lexer::lex_bytes`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum ByteEncoding {
+    /// Every byte is decoded as the character with the same value
+    ///
+    /// Nothing is lost, but offsets of tokens point into the decoded text
+    /// since bytes above 0x7F take two bytes in UTF-8
+    Latin1,
+    /// Bytes above 0x7F are replaced by `RAW_BYTE_PLACEHOLDER`
+    ///
+    /// Offsets of tokens are the same as offsets into the bytes
+    Raw,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
@@ -88,10 +418,142 @@ pub struct Token<'a> {
     pub index: usize,
     /// Length of the token
     pub len: usize,
-    /// Location for debugging
-    pub location: TextLocation,
     /// Kind of token
     pub kind: TokenKinds<'a>,
+    /// Tag of the matched token, see `Lexer::add_token_tagged`
+    pub tag: Option<&'static str>,
+    /// Value attached by a preprocessor, see `TokenData`
+    pub data: Option<TokenData>,
+}
+
+/// Statistics of token streams, see `Lexer::stats`
+///
+/// Streams of many files can be added to the same statistics with `TokenStats::add`
+#[derive(Debug, Clone, Default)]
+pub struct TokenStats<'a> {
+    /// Number of tokens of each kind in the order the kinds first appeared
+    pub kinds: Vec<(TokenKinds<'a>, usize)>,
+    /// First of the longest tokens
+    pub longest: Option<Token<'a>>,
+    /// Lines of all streams, the new lines of each stream and one
+    pub lines: usize,
+    /// Number of tokens of each length, `lengths[n]` counts the tokens that are `n` bytes long
+    pub lengths: Vec<usize>,
+    /// Number of tokens, end of file markers included
+    pub tokens: usize,
+}
+
+impl<'a> TokenStats<'a> {
+    /// Adds the tokens of one stream
+    pub fn add(&mut self, tokens: &[Token<'a>]) {
+        self.lines += 1;
+        for token in tokens {
+            if token.kind == TokenKinds::Control(ControlTokenKind::Eol) {
+                self.lines += 1;
+            }
+            match self.kinds.iter_mut().find(|(kind, _)| *kind == token.kind) {
+                Some((_, count)) => *count += 1,
+                None => self.kinds.push((token.kind, 1)),
+            }
+            if self
+                .longest
+                .as_ref()
+                .is_none_or(|longest| token.len > longest.len)
+            {
+                self.longest = Some(*token);
+            }
+            if self.lengths.len() <= token.len {
+                self.lengths.resize(token.len + 1, 0);
+            }
+            self.lengths[token.len] += 1;
+        }
+        self.tokens += tokens.len();
+    }
+
+    /// Number of tokens of the kind
+    pub fn count(&self, kind: TokenKinds) -> usize {
+        self.kinds
+            .iter()
+            .find(|(known, _)| *known == kind)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    /// Kinds sorted by the number of tokens, most common first
+    pub fn by_count(&self) -> Vec<(TokenKinds<'a>, usize)> {
+        let mut kinds = self.kinds.clone();
+        kinds.sort_by_key(|(_, count)| core::cmp::Reverse(*count));
+        kinds
+    }
+}
+
+/// Value of a token worked out while lexing, so later passes don't scan the text again
+///
+/// Attached by preprocessors, for example the decoded value of a number literal.
+/// Texts like the dedented content of a string are kept in `TokenTexts` and the token
+/// only holds their handle, so tokens stay `Copy`. Kept in `parser::Nodes::Token`
+/// of the parse tree.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum TokenData {
+    Number(u32),
+    /// Handle of a text in the `TokenTexts` that made it
+    Text(u32),
+}
+
+/// Side table of the texts attached to tokens, see `TokenData::Text`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenTexts(Vec<SmolStr>);
+
+impl TokenTexts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the text and returns the value to attach with `Token::with_data`
+    pub fn add(&mut self, text: impl Into<SmolStr>) -> TokenData {
+        self.0.push(text.into());
+        TokenData::Text(self.0.len() as u32 - 1)
+    }
+
+    /// Text of the value, None for numbers and handles of another table
+    pub fn get(&self, data: TokenData) -> Option<&str> {
+        match data {
+            TokenData::Text(handle) => self.0.get(handle as usize).map(SmolStr::as_str),
+            TokenData::Number(_) => None,
+        }
+    }
+}
+
+/// How a tab counts in the columns of `TextLocation`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Default)]
+pub enum TabPolicy {
+    /// A tab is one column like any other character
+    #[default]
+    Char,
+    /// A tab is always the given number of columns
+    Width(usize),
+    /// A tab moves to the next multiple of the given width, the way editors display it
+    Stop(usize),
+}
+
+impl TabPolicy {
+    /// Zero based column after a tab at the zero based `column`
+    pub fn advance(&self, column: usize) -> usize {
+        match self {
+            TabPolicy::Char => column + 1,
+            TabPolicy::Width(width) => column + width,
+            TabPolicy::Stop(width) => {
+                let width = (*width).max(1);
+                (column / width + 1) * width
+            }
+        }
+    }
+
+    fn column_after(&self, column: usize, c: char) -> usize {
+        match c {
+            '\t' => self.advance(column),
+            _ => column + 1,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
@@ -113,9 +575,116 @@ impl TextLocation {
             len,
         }
     }
+
+    /// Location of the byte range `index..index + len` of the text
+    ///
+    /// Useful to recompute the columns of diagnostics, which only store byte ranges,
+    /// with the tab policy of an editor
+    ///
+    /// Scans the whole text, use `LineIndex` to locate many ranges of the same text
+    pub fn locate(text: &str, index: usize, len: usize, tabs: TabPolicy) -> TextLocation {
+        LineIndex::new(text).locate(index, len, tabs)
+    }
+}
+
+/// Byte indexes at which the lines of a text start
+///
+/// Built once per text, finds the line of a byte index with a binary search
+/// and the column by scanning only that line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex<'t> {
+    text: &'t str,
+    starts: Vec<usize>,
+}
+
+impl<'t> LineIndex<'t> {
+    pub fn new(text: &'t str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(
+            text.bytes()
+                .enumerate()
+                .filter(|(_, b)| *b == b'\n')
+                .map(|(i, _)| i + 1),
+        );
+        LineIndex { text, starts }
+    }
+
+    /// Number of lines, a text always has at least one
+    pub fn line_count(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Line of the byte index, counted from 1 like in `TextLocation`
+    ///
+    /// Indexes past the end of the text are on the last line
+    pub fn line(&self, index: usize) -> usize {
+        self.starts.partition_point(|start| *start <= index)
+    }
+
+    /// Byte index of the first character of the line counted from 1
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.starts.get(line.checked_sub(1)?).copied()
+    }
+
+    /// Location of the byte range `index..index + len`, see `TextLocation::locate`
+    pub fn locate(&self, index: usize, len: usize, tabs: TabPolicy) -> TextLocation {
+        self.locate_with(index, len, |line| {
+            line.chars()
+                .fold(0, |column, c| tabs.column_after(column, c))
+        })
+    }
+
+    /// Location with the column counted by `columns` from the text in front of `index`
+    fn locate_with(
+        &self,
+        index: usize,
+        len: usize,
+        columns: impl FnOnce(&str) -> usize,
+    ) -> TextLocation {
+        let line = self.line(index);
+        let start = self.starts[line - 1];
+        let mut end = index.clamp(start, self.text.len());
+        while !self.text.is_char_boundary(end) {
+            end -= 1;
+        }
+        TextLocation::new(line - 1, columns(&self.text[start..end]), index, len)
+    }
+}
+
+/// Position of the lexer in a text, see `Lexer::lex_region`
+///
+/// The state is a plain snapshot, keep a copy to lex the same region again
+/// and roll back a speculative lexing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct LexerState {
+    /// Byte the lexer continues at
+    pub index: usize,
+}
+
+impl LexerState {
+    /// State at the start of a text
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// State in front of a token, so the text of the token is lexed again
+    pub fn before(token: &Token) -> Self {
+        LexerState { index: token.index }
+    }
 }
 
 impl<'a> Token<'a> {
+    /// Zero length end of file at the start of the text
+    ///
+    /// Meant as a placeholder to fill buffers for `Lexer::lex_into` with
+    pub const EMPTY: Self = Token {
+        index: 0,
+        len: 0,
+        kind: TokenKinds::Control(ControlTokenKind::Eof),
+        tag: None,
+        data: None,
+    };
+
     pub fn stringify<'b>(&self, txt: &'b str) -> &'b str {
         &txt[self.index..self.index + self.len]
     }
@@ -123,17 +692,74 @@ impl<'a> Token<'a> {
     pub fn stringify_until<'b>(&self, other: &Self, txt: &'b str) -> &'b str {
         &txt[self.index..other.index + other.len]
     }
+
+    /// Bytes of the token in the text
+    pub fn span(&self) -> core::ops::Range<usize> {
+        self.index..self.index + self.len
+    }
+
+    /// Splits the token into tokens of the given kinds and byte lengths
+    ///
+    /// The pieces keep their own spans in the text, so diagnostics point at the right
+    /// character.
+    ///
+    /// Returns None if the lengths do not add up to the length of the token
+    /// or a piece ends inside a character
+    pub fn split(
+        &self,
+        text: &str,
+        pieces: impl IntoIterator<Item = (TokenKinds<'a>, usize)>,
+    ) -> Option<Vec<Token<'a>>> {
+        let end = self.index + self.len;
+        let mut index = self.index;
+        let mut result = Vec::new();
+        for (kind, len) in pieces {
+            let piece_end = index
+                .checked_add(len)
+                .filter(|piece_end| *piece_end <= end)?;
+            text.get(index..piece_end)?;
+            result.push(Token {
+                index,
+                len,
+                kind,
+                tag: None,
+                data: None,
+            });
+            index = piece_end;
+        }
+        (index == end).then_some(result)
+    }
+
+    /// Attaches a value to the token, see `TokenData`
+    pub fn with_data(mut self, data: TokenData) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Whether `next` starts right where this token ends
+    ///
+    /// Lets a rule tell `>>` split into two tokens from `> >`
+    pub fn touches(&self, next: &Token) -> bool {
+        self.index + self.len == next.index
+    }
+
+    /// Token that is not in the text, placed at the start of `at` with zero length
+    ///
+    /// Used by token filters to inject tokens while diagnostics still point into the text
+    pub fn virtual_at(kind: TokenKinds<'a>, at: &Token) -> Token<'a> {
+        Token {
+            index: at.index,
+            len: 0,
+            kind,
+            tag: None,
+            data: None,
+        }
+    }
 }
 
 impl fmt::Display for TokenKinds<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            TokenKinds::Token(smol_str) => write!(f, "{smol_str}"),
-            TokenKinds::Complex(smol_str) => write!(f, "{smol_str}"),
-            TokenKinds::Text => write!(f, "<text>"),
-            TokenKinds::Whitespace => write!(f, "<whitespace>"),
-            TokenKinds::Control(ctk) => write!(f, "{ctk}"),
-        }
+        f.write_str(self.name())
     }
 }
 
@@ -150,29 +776,102 @@ impl<'a, 'tok> Lexer
 where
     'a: 'tok,
 {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
+        Self::with_table(&NO_TOKENS)
+    }
+
+    /// Location of the byte range `index..index + len` in the columns of the lexer
+    ///
+    /// Tabs count by `Lexer::tabs` and columns count grapheme clusters with `Lexer::graphemes`.
+    /// Tokens and errors only keep byte ranges, build the `LineIndex` once per text.
+    pub fn locate(&self, lines: &LineIndex, index: usize, len: usize) -> TextLocation {
+        #[cfg(feature = "graphemes")]
+        if self.graphemes {
+            return lines.locate_with(index, len, |line| {
+                line.graphemes(true)
+                    .fold(0, |column, cluster| match cluster {
+                        "\t" => self.tabs.advance(column),
+                        _ => column + 1,
+                    })
+            });
+        }
+        lines.locate(index, len, self.tabs)
+    }
+
+    /// Counts the tokens by kind and length, see `TokenStats`
+    ///
+    /// Useful to analyze a corpus before tuning the lexer,
+    /// for example to see which texts are common enough to become tokens
+    pub fn stats(tokens: &[Token<'tok>]) -> TokenStats<'tok> {
+        let mut stats = TokenStats::default();
+        stats.add(tokens);
+        stats
+    }
+
+    /// Lexer with the tokens of a table built at compile time
+    ///
+    /// Can be used to initialize a `static`, the table is only copied once more
+    /// tokens are added
+    ///
+    /// ```text
+    /// static TOKENS: TokenTable<3> = TokenTable::new(["=", "==", ";"]);
+    /// static LEXER: Lexer = Lexer::with_table(&TOKENS);
+    /// ```
+    pub const fn with_table<const N: usize>(table: &'static TokenTable<N>) -> Self {
         Lexer {
-            token_kinds: Vec::new(),
-            longest_token_size: 0,
+            token_kinds: Cow::Borrowed(&table.kinds),
+            token_tags: Cow::Borrowed(&table.tags),
+            longest_token_size: table.longest,
             preprocessors: Vec::new(),
+            unknown_chars: UnknownChars::Text,
+            strip_bom: false,
+            crlf: false,
+            doc_comments: Vec::new(),
+            scannerless: false,
+            #[cfg(feature = "graphemes")]
+            graphemes: false,
+            tabs: TabPolicy::Char,
+            progress: None,
         }
     }
 
+    /// Calls `on_progress(bytes_consumed, total)` every `interval` tokens, see `Progress`
+    pub fn on_progress(
+        &mut self,
+        interval: usize,
+        on_progress: impl Fn(usize, usize) + Send + Sync + 'static,
+    ) {
+        self.progress = Some(Progress::new(interval, on_progress));
+    }
+
     pub fn add_tokens<T>(&mut self, tokens: impl Iterator<Item = T>)
     where
-        T: Into<SmolStr>,
+        T: AsRef<str>,
     {
-        for into_token in tokens {
-            let token = into_token.into();
-            if token.len() > self.longest_token_size {
-                self.longest_token_size = token.len();
-            }
-            self.add_token(token.clone());
+        for token in tokens {
+            self.add_token(token);
         }
     }
 
-    pub fn add_token(&mut self, token: impl Into<SmolStr>) {
-        let token = token.into();
+    pub fn add_token(&mut self, token: impl AsRef<str>) {
+        self.insert_token(token.as_ref(), None);
+    }
+
+    /// Adds a token that carries a tag into every `Token` it produces
+    ///
+    /// Downstream passes can match on the tag instead of the raw text
+    pub fn add_token_tagged(&mut self, token: impl AsRef<str>, tag: &'static str) {
+        self.insert_token(token.as_ref(), Some(tag));
+    }
+
+    /// Returns the tag of a token kind
+    pub fn tag_of(&self, token: &str) -> Option<&'static str> {
+        let index = self.token_kinds.iter().position(|t| *t == token)?;
+        self.token_tags[index]
+    }
+
+    fn insert_token(&mut self, token: &str, tag: Option<&'static str>) {
+        let token = intern::Symbol::intern(token).as_str();
         if token.len() > self.longest_token_size {
             self.longest_token_size = token.len();
         }
@@ -188,38 +887,316 @@ where
             .iter()
             .position(|x| x.len() > token.len())
             .unwrap_or(self.token_kinds.len());
-        self.token_kinds.insert(index, token);
+        self.token_kinds.to_mut().insert(index, token);
+        self.token_tags.to_mut().insert(index, tag);
     }
 
-    pub fn get_tokens(&self) -> &[SmolStr] {
+    /// Returns the byte length and the opening marker of a doc comment at the start of the text
+    fn doc_comment_len(&self, text: &str) -> Option<(usize, &'static str)> {
+        self.doc_comments.iter().find_map(|doc| match *doc {
+            DocComment::Line(marker) if text.starts_with(marker) => {
+                let mut len = text.find('\n').unwrap_or(text.len());
+                if self.crlf && text[..len].ends_with('\r') {
+                    len -= 1;
+                }
+                Some((len, marker))
+            }
+            DocComment::Block(open, close) if text.starts_with(open) => {
+                let len = text[open.len()..]
+                    .find(close)
+                    .map_or(text.len(), |end| open.len() + end + close.len());
+                Some((len, open))
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns the text of a doc comment token without its markers
+    pub fn doc_content<'t>(&self, token: &Token, text: &'t str) -> Option<&'t str> {
+        if token.kind != TokenKinds::DocComment {
+            return None;
+        }
+        let comment = &text[token.index..token.index + token.len];
+        self.doc_comments.iter().find_map(|doc| match *doc {
+            DocComment::Line(marker) if token.tag == Some(marker) => comment.strip_prefix(marker),
+            DocComment::Block(open, close) if token.tag == Some(open) => comment
+                .strip_prefix(open)
+                .map(|c| c.strip_suffix(close).unwrap_or(c)),
+            _ => None,
+        })
+    }
+
+    /// Pushes a doc comment token
+    fn push_doc_comment(
+        tokens: &mut impl TokenSink<'tok>,
+        comment: &str,
+        index: usize,
+        marker: &'static str,
+    ) -> Result<(), PreprocessorError> {
+        tokens.push(Token {
+            index,
+            len: comment.len(),
+            kind: TokenKinds::DocComment,
+            tag: Some(marker),
+            data: None,
+        })
+    }
+
+    pub fn get_tokens(&self) -> &[&'static str] {
         &self.token_kinds
     }
 
+    fn push_unknown(
+        &self,
+        tokens: &mut impl TokenSink<'tok>,
+        token: Token<'tok>,
+    ) -> Result<(), PreprocessorError> {
+        match self.unknown_chars {
+            UnknownChars::Fail => Err(PreprocessorError {
+                err: UNKNOWN_CHAR_ERROR,
+                span: token.span(),
+            }),
+            _ => tokens.push(token),
+        }
+    }
+
+    /// Looks for the first line ending that is different from the first line ending in the text
+    ///
+    /// The result is meant to be reported as a warning, lexing is not affected by it
+    pub fn check_line_endings(&self, text: &str) -> Option<PreprocessorError> {
+        let bytes = text.as_bytes();
+        let mut first = None;
+        for (i, b) in bytes.iter().enumerate() {
+            if *b != b'\n' {
+                continue;
+            }
+            let crlf = i > 0 && bytes[i - 1] == b'\r';
+            match first {
+                None => first = Some(crlf),
+                Some(first) if first != crlf => {
+                    let start = if crlf { i - 1 } else { i };
+                    return Some(PreprocessorError {
+                        err: MIXED_LINE_ENDINGS,
+                        span: start..i + 1,
+                    });
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// Lexer for text that is not valid UTF-8
+    ///
+    /// Returns the decoded text along with the tokens,
+    /// the text should be used for parsing and diagnostics
+    pub fn lex_bytes(
+        &'a self,
+        bytes: &[u8],
+        encoding: ByteEncoding,
+    ) -> Result<(String, Vec<Token<'tok>>), PreprocessorError> {
+        let text: String = match encoding {
+            ByteEncoding::Latin1 => bytes.iter().map(|b| *b as char).collect(),
+            ByteEncoding::Raw => bytes
+                .iter()
+                .map(|b| match b.is_ascii() {
+                    true => *b as char,
+                    false => RAW_BYTE_PLACEHOLDER,
+                })
+                .collect(),
+        };
+        let tokens = self.lex_utf8(&text)?;
+        Ok((text, tokens))
+    }
+
     /// Lexer for UTF-8 text
-    pub fn lex_utf8(&'a self, text: &'tok str) -> Result<Vec<Token<'tok>>, PreprocessorError> {
-        let chars = text.char_indices().collect::<Vec<(usize, char)>>();
+    pub fn lex_utf8(&'a self, text: &str) -> Result<Vec<Token<'tok>>, PreprocessorError> {
+        if self.scannerless {
+            let mut tokens = Vec::with_capacity(text.len() + 1);
+            self.lex_chars_into(text, &mut tokens)?;
+            return self.preprocess(text, tokens);
+        }
+        // the allocation is a guess, but it should be close enough
+        let mut tokens = Vec::with_capacity(text.len() / 4);
+        self.lex_utf8_into(text, &mut tokens)?;
+        self.preprocess(text, tokens)
+    }
+
+    /// Lexes the bytes `state.index..end` of the text as if the lexer got there on its own
+    ///
+    /// Returns the tokens of the region without the `Eof` token and the state at `end`,
+    /// so lexing can continue after the region. Tokens can not reach past `end`.
+    /// Together with `LexerState::before` a token filter can lex a part of the text again,
+    /// for example with a lexer that does not know `>>`, and keep or drop the result:
+    ///
+    /// ```text
+    /// let state = LexerState::before(&token);
+    /// let (pieces, _) = generics.lex_region(text, state, token.index + token.len)?;
+    /// ```
+    ///
+    /// Preprocessors only see the tokens of the region.
+    /// Fails with `INVALID_REGION` if `end` is in front of `state.index`, past the end
+    /// of the text or either of them is not on a character boundary.
+    pub fn lex_region(
+        &'a self,
+        text: &str,
+        state: LexerState,
+        end: usize,
+    ) -> Result<(Vec<Token<'tok>>, LexerState), PreprocessorError> {
+        if text.get(state.index..end).is_none() {
+            return Err(PreprocessorError {
+                err: INVALID_REGION,
+                span: state.index..end.max(state.index),
+            });
+        }
+        let mut tokens = Vec::with_capacity((end - state.index) / 4);
+        let state = self.lex_region_into(text, state, end, &mut tokens)?;
+        Ok((self.preprocess(text, tokens)?, state))
+    }
+
+    /// `Lexer::lex_region` without the preprocessors, the region must be valid
+    pub(crate) fn lex_region_into(
+        &'a self,
+        text: &str,
+        state: LexerState,
+        end: usize,
+        tokens: &mut Vec<Token<'tok>>,
+    ) -> Result<LexerState, PreprocessorError> {
+        if self.scannerless {
+            return self.lex_chars_region(text, state, end, tokens);
+        }
+        self.lex_utf8_region(text, state, end, tokens)
+    }
+
+    /// Lexer for UTF-8 text that keeps the tokens in the given allocator
+    ///
+    /// A bump allocator can then free the tokens of a whole message at once.
+    /// The lexer still uses the global allocator for its temporary buffers,
+    /// see `crate::Parser::parse_in` to keep the parse result in an allocator too.
+    ///
+    /// Fails with `GLOBAL_ALLOCATOR_REQUIRED` if the lexer has preprocessors,
+    /// use `Lexer::lex_utf8` to run them.
+    #[cfg(feature = "allocator")]
+    pub fn lex_utf8_in<A: Allocator>(
+        &'a self,
+        text: &str,
+        alloc: A,
+    ) -> Result<allocator_api2::vec::Vec<Token<'tok>, A>, PreprocessorError> {
+        self.check_allocator()?;
+        let mut tokens = allocator_api2::vec::Vec::new_in(alloc);
+        match self.scannerless {
+            true => self.lex_chars_into(text, &mut tokens)?,
+            false => self.lex_utf8_into(text, &mut tokens)?,
+        }
+        Ok(tokens)
+    }
+
+    /// Lexer for ascii-only text that keeps the tokens in the given allocator,
+    /// see `Lexer::lex_utf8_in`
+    #[cfg(feature = "allocator")]
+    pub fn lex_ascii_in<A: Allocator>(
+        &'a self,
+        text: &'tok str,
+        alloc: A,
+    ) -> Result<allocator_api2::vec::Vec<Token<'tok>, A>, PreprocessorError> {
+        self.check_allocator()?;
+        let mut tokens = allocator_api2::vec::Vec::new_in(alloc);
+        match self.scannerless {
+            true => self.lex_chars_into(text, &mut tokens)?,
+            false => self.lex_ascii_into(text, &mut tokens)?,
+        }
+        Ok(tokens)
+    }
+
+    /// Preprocessors can only hand their tokens back in a global `Vec`
+    #[cfg(feature = "allocator")]
+    fn check_allocator(&self) -> Result<(), PreprocessorError> {
+        match self.preprocessors.is_empty() {
+            true => Ok(()),
+            false => Err(PreprocessorError {
+                err: GLOBAL_ALLOCATOR_REQUIRED,
+                span: 0..0,
+            }),
+        }
+    }
+
+    fn lex_utf8_into(
+        &'a self,
+        text: &str,
+        tokens: &mut impl TokenSink<'tok>,
+    ) -> Result<(), PreprocessorError> {
+        let tokens = &mut ProgressSink::new(tokens, self.progress.as_ref(), text.len());
+        self.lex_utf8_region(text, LexerState::new(), text.len(), tokens)?;
+        tokens.push(Token {
+            index: text.len(),
+            len: 0,
+            kind: TokenKinds::Control(ControlTokenKind::Eof),
+            tag: None,
+            data: None,
+        })
+    }
+
+    /// Lexes `text[state.index..end]` without the `Eof` token and returns the state at `end`
+    fn lex_utf8_region(
+        &'a self,
+        text: &str,
+        state: LexerState,
+        end: usize,
+        tokens: &mut impl TokenSink<'tok>,
+    ) -> Result<LexerState, PreprocessorError> {
+        let region = &text[state.index..end];
+        let mut chars = region.char_indices().collect::<Vec<(usize, char)>>();
+        // tokens can only start and end where a grapheme cluster starts
+        let starts = self.cluster_starts(region, &chars);
+        for (index, _) in &mut chars {
+            *index += state.index;
+        }
+        let text = &text[..end];
         let len = chars.len();
-        let mut tokens = Vec::with_capacity(chars.len() / 4);
         let mut i = 0;
-        let mut line = 0;
-        let mut column = 0;
+        let at_cluster = |k: usize| starts.as_ref().is_none_or(|starts| starts[k]);
+
+        if self.strip_bom && state.index == 0 && text.starts_with(BOM) {
+            i += 1;
+        }
 
         'chars: while i < len {
+            // Windows new line
+            if self.crlf && chars[i].1 == '\r' && i + 1 < len && chars[i + 1].1 == '\n' {
+                tokens.push(Token {
+                    index: chars[i].0,
+                    len: 2,
+                    kind: TokenKinds::Control(ControlTokenKind::Eol),
+                    tag: None,
+                    data: None,
+                })?;
+                i += 2;
+                continue;
+            }
             // New line
             if chars[i].1 == '\n' {
-                line += 1;
-                column = 0;
                 tokens.push(Token {
                     index: chars[i].0,
                     len: 1,
-                    location: TextLocation::new(line, column, chars[i].0, 1),
                     kind: TokenKinds::Control(ControlTokenKind::Eol),
-                });
+                    tag: None,
+                    data: None,
+                })?;
                 i += 1;
                 continue;
             }
 
-            'tokens: for token_kind in self.token_kinds.iter().rev() {
+            // Doc comment
+            if let Some((byte_len, marker)) = self.doc_comment_len(&text[chars[i].0..]) {
+                let comment = &text[chars[i].0..chars[i].0 + byte_len];
+                Self::push_doc_comment(tokens, comment, chars[i].0, marker)?;
+                i += comment.chars().count();
+                continue;
+            }
+
+            'tokens: for (token_kind, tag) in
+                self.token_kinds.iter().zip(self.token_tags.iter()).rev()
+            {
                 let tok_char_len = token_kind.chars().count();
                 if i + tok_char_len > len {
                     continue;
@@ -231,7 +1208,7 @@ where
                         break;
                     }
                 }
-                if !matches {
+                if !matches || !at_cluster(i + tok_char_len) {
                     continue 'tokens;
                 }
 
@@ -247,38 +1224,65 @@ where
                 tokens.push(Token {
                     index: start_byte,
                     len: byte_len,
-                    location: TextLocation::new(line, column, start_byte, byte_len),
                     kind: TokenKinds::Token(token_kind), // no extra &
-                });
+                    tag: *tag,
+                    data: None,
+                })?;
                 i += tok_char_len;
-                column += tok_char_len;
                 continue 'chars;
             }
 
             // Whitespace
-            if chars[i].1.is_whitespace() {
+            if chars[i].1.is_whitespace() && at_cluster(i + 1) {
                 tokens.push(Token {
                     index: chars[i].0,
                     len: 1,
-                    location: TextLocation::new(line, column, chars[i].0, 1),
                     kind: TokenKinds::Whitespace,
-                });
+                    tag: None,
+                    data: None,
+                })?;
                 i += 1;
-                column += 1;
+                continue;
+            }
+
+            // Unknown characters are grouped into a single token
+            if self.unknown_chars.is_unknown(chars[i].1) {
+                let mut j = 0;
+                let mut byte_len = 0;
+                while i + j < len && self.unknown_chars.is_unknown(chars[i + j].1) {
+                    byte_len += chars[i + j].1.len_utf8();
+                    j += 1;
+                }
+                let token = Token {
+                    index: chars[i].0,
+                    len: byte_len,
+                    kind: TokenKinds::Error,
+                    tag: None,
+                    data: None,
+                };
+                self.push_unknown(tokens, token)?;
+                i += j;
                 continue;
             }
 
             let mut j = 0;
             let mut token_byte_len = 0;
             'word: while i + j < len {
-                if chars[i + j].1.is_whitespace() {
+                let c = chars[i + j].1;
+                if at_cluster(i + j)
+                    && ((c.is_whitespace() && at_cluster(i + j + 1))
+                        || self.unknown_chars.is_unknown(c))
+                {
                     break;
                 }
-                token_byte_len += chars[i + j].1.len_utf8();
+                token_byte_len += c.len_utf8();
                 j += 1;
+                if !at_cluster(i + j) {
+                    continue;
+                }
 
                 // Lookahead: does any token kind start exactly here?
-                for token_kind in &self.token_kinds {
+                for token_kind in self.token_kinds.iter() {
                     let start_char = i + j;
                     let tok_char_len = token_kind.chars().count();
 
@@ -294,7 +1298,7 @@ where
                     };
                     let candidate = &text[token_start_byte..token_end_byte];
 
-                    if candidate == *token_kind {
+                    if candidate == *token_kind && at_cluster(start_char + tok_char_len) {
                         break 'word; // real token starts here → stop text
                     }
                 }
@@ -304,52 +1308,156 @@ where
                 tokens.push(Token {
                     index: chars[i].0,
                     len: token_byte_len,
-                    location: TextLocation::new(line, column, chars[i].0, token_byte_len),
                     kind: TokenKinds::Text,
-                });
-                column += j;
+                    tag: None,
+                    data: None,
+                })?;
                 i += j;
             }
         }
+        Ok(LexerState { index: end })
+    }
 
-        tokens.push(Token {
-            index: text.len(),
-            len: 0,
-            location: TextLocation::new(line, column, text.len(), 0),
-            kind: TokenKinds::Control(ControlTokenKind::Eof),
-        });
+    /// Lexer for ascii-only text
+    pub fn lex_ascii(&'a self, text: &'tok str) -> Result<Vec<Token<'tok>>, PreprocessorError> {
+        if self.scannerless {
+            let mut tokens = Vec::with_capacity(text.len() + 1);
+            self.lex_chars_into(text, &mut tokens)?;
+            return self.preprocess(text, tokens);
+        }
+        // the allocation is a guess, but it should be close enough
+        let mut tokens = Vec::with_capacity(text.len() / 4);
+        self.lex_ascii_into(text, &mut tokens)?;
+        self.preprocess(text, tokens)
+    }
 
-        for preprocessor in &self.preprocessors {
-            tokens = preprocessor(text, &tokens)?;
+    /// Writes the tokens as a table with the index, kind, byte span, location and
+    /// the start of the text of every token, columns of the locations count characters
+    ///
+    /// ```text
+    /// #  kind        span  at   text
+    /// 0  Text        0..3  1:1  "let"
+    /// 1  Whitespace  3..4  1:4  " "
+    /// ```
+    pub fn dump(tokens: &[Token], text: &str, w: &mut impl fmt::Write) -> fmt::Result {
+        const HEADER: [&str; 5] = ["#", "kind", "span", "at", "text"];
+        let lines = LineIndex::new(text);
+        let rows = tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| {
+                let kind = match token.tag {
+                    Some(tag) => format!("{:?} [{}]", token.kind, tag),
+                    None => format!("{:?}", token.kind),
+                };
+                let content = text.get(token.index..token.index + token.len).unwrap_or("");
+                let mut preview = format!(
+                    "{:?}",
+                    content.chars().take(DUMP_PREVIEW).collect::<String>()
+                );
+                if content.chars().nth(DUMP_PREVIEW).is_some() {
+                    preview.push_str("...");
+                }
+                [
+                    i.to_string(),
+                    kind,
+                    format!("{}..{}", token.index, token.index + token.len),
+                    {
+                        let location = lines.locate(token.index, token.len, TabPolicy::Char);
+                        format!("{}:{}", location.line, location.column)
+                    },
+                    preview,
+                ]
+            })
+            .collect::<Vec<_>>();
+        let mut widths = HEADER.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
         }
-        Ok(tokens)
+        let header = HEADER.map(String::from);
+        for row in core::iter::once(&header).chain(&rows) {
+            for (i, cell) in row.iter().enumerate() {
+                match i {
+                    0 => write!(w, "{:<1$}", cell, widths[i])?,
+                    4 => write!(w, "  {}", cell)?,
+                    _ => write!(w, "  {:<1$}", cell, widths[i])?,
+                }
+            }
+            writeln!(w)?;
+        }
+        Ok(())
     }
 
-    /// Lexer for ascii-only text
-    pub fn lex_ascii(&'a self, text: &'tok str) -> Result<Vec<Token<'tok>>, PreprocessorError> {
+    /// Lexer for ascii-only text that writes the tokens into the buffer without
+    /// allocating, returns the number of tokens written
+    ///
+    /// Fails with `BUFFER_FULL` if the tokens do not fit and with `HEAP_REQUIRED`
+    /// if the lexer has preprocessors or is scannerless
+    pub fn lex_into(
+        &'a self,
+        text: &'tok str,
+        buf: &mut [Token<'tok>],
+    ) -> Result<usize, PreprocessorError> {
+        if self.scannerless || !self.preprocessors.is_empty() {
+            return Err(PreprocessorError {
+                err: HEAP_REQUIRED,
+                span: 0..0,
+            });
+        }
+        let mut sink = SliceSink { buf, len: 0 };
+        self.lex_ascii_into(text, &mut sink)?;
+        Ok(sink.len)
+    }
+
+    fn lex_ascii_into(
+        &'a self,
+        text: &'tok str,
+        tokens: &mut impl TokenSink<'tok>,
+    ) -> Result<(), PreprocessorError> {
+        let tokens = &mut ProgressSink::new(tokens, self.progress.as_ref(), text.len());
         let chars = text.as_bytes();
-        // the allocation is a guess, but it should be close enough
-        let mut tokens = Vec::with_capacity(chars.len() / 4);
         let mut i = 0;
-        let mut line = 0;
-        let mut column = 0;
         let len = chars.len();
+        if self.strip_bom && text.starts_with(BOM) {
+            i += BOM.len_utf8();
+        }
         'chars: while i < len {
+            // Windows new line
+            if self.crlf && chars[i] == b'\r' && i + 1 < len && chars[i + 1] == b'\n' {
+                tokens.push(Token {
+                    index: i,
+                    len: 2,
+                    kind: TokenKinds::Control(ControlTokenKind::Eol),
+                    tag: None,
+                    data: None,
+                })?;
+                i += 2;
+                continue;
+            }
             // Take new line into account
             if chars[i] == b'\n' {
-                line += 1;
-                column = 0;
                 i += 1;
                 tokens.push(Token {
                     index: i,
                     len: 1,
-                    location: TextLocation::new(line, column, i, 1),
                     kind: TokenKinds::Control(ControlTokenKind::Eol),
-                });
+                    tag: None,
+                    data: None,
+                })?;
+                continue;
+            }
+
+            // Doc comment
+            if let Some((byte_len, marker)) = self.doc_comment_len(&text[i..]) {
+                let comment = &text[i..i + byte_len];
+                Self::push_doc_comment(tokens, comment, i, marker)?;
+                i += byte_len;
                 continue;
             }
 
-            for token_kind in self.token_kinds.iter().rev() {
+            for (token_kind, tag) in self.token_kinds.iter().zip(self.token_tags.iter()).rev() {
                 let tok_len = token_kind.len();
                 if i + tok_len > len {
                     // All the remaining tokens are longer than the remaining text
@@ -362,11 +1470,11 @@ where
                     tokens.push(Token {
                         index: i,
                         len: tok_len,
-                        location: TextLocation::new(line, column, i, tok_len),
                         kind: TokenKinds::Token(token_kind),
-                    });
+                        tag: *tag,
+                        data: None,
+                    })?;
                     i += tok_len;
-                    column += tok_len;
                     continue 'chars;
                 }
             }
@@ -376,22 +1484,41 @@ where
                 tokens.push(Token {
                     index: i,
                     len: 1,
-                    location: TextLocation::new(line, column, i, 1),
                     kind: TokenKinds::Whitespace,
-                });
+                    tag: None,
+                    data: None,
+                })?;
                 i += 1;
-                column += 1;
+                continue;
+            }
+
+            // Match unknown characters
+            if self.unknown_chars.is_unknown(chars[i] as char) {
+                let mut j = 0;
+                while i + j < len && self.unknown_chars.is_unknown(chars[i + j] as char) {
+                    j += 1;
+                }
+                let token = Token {
+                    index: i,
+                    len: j,
+                    kind: TokenKinds::Error,
+                    tag: None,
+                    data: None,
+                };
+                self.push_unknown(tokens, token)?;
+                i += j;
                 continue;
             }
 
             // Match text until next whitespace/token/eof
             let mut j = 0;
             'word: while i + j < len {
-                if (chars[i + j] as char).is_whitespace() {
+                let c = chars[i + j] as char;
+                if c.is_whitespace() || self.unknown_chars.is_unknown(c) {
                     break;
                 }
                 j += 1;
-                for token_kind in &self.token_kinds {
+                for token_kind in self.token_kinds.iter() {
                     let start = i + j;
                     let tok_len = token_kind.len();
                     let end = if i + j + tok_len <= len {
@@ -408,24 +1535,109 @@ where
             tokens.push(Token {
                 index: i,
                 len: j,
-                location: TextLocation::new(line, column, i, j),
                 kind: TokenKinds::Text,
-            });
-            column += j;
+                tag: None,
+                data: None,
+            })?;
             i += j;
         }
 
         tokens.push(Token {
             index: i,
             len: 0,
-            location: TextLocation::new(line, column, i, 0),
             kind: TokenKinds::Control(ControlTokenKind::Eof),
-        });
+            tag: None,
+            data: None,
+        })
+    }
 
+    pub(crate) fn preprocess(
+        &self,
+        text: &str,
+        mut tokens: Vec<Token<'tok>>,
+    ) -> Result<Vec<Token<'tok>>, PreprocessorError> {
         for preprocessor in &self.preprocessors {
             tokens = preprocessor(text, &tokens)?;
         }
-
         Ok(tokens)
     }
+
+    /// One `Text` token for every character, see `Lexer::scannerless`
+    fn lex_chars_into(
+        &self,
+        text: &str,
+        tokens: &mut impl TokenSink<'tok>,
+    ) -> Result<(), PreprocessorError> {
+        self.lex_chars_region(text, LexerState::new(), text.len(), tokens)?;
+        tokens.push(Token {
+            index: text.len(),
+            len: 0,
+            kind: TokenKinds::Control(ControlTokenKind::Eof),
+            tag: None,
+            data: None,
+        })?;
+        if let Some(progress) = &self.progress {
+            progress.report(text.len(), text.len());
+        }
+        Ok(())
+    }
+
+    /// Scannerless lexing of `text[state.index..end]` without the `Eof` token
+    fn lex_chars_region(
+        &self,
+        text: &str,
+        state: LexerState,
+        end: usize,
+        tokens: &mut impl TokenSink<'tok>,
+    ) -> Result<LexerState, PreprocessorError> {
+        let start = match self.strip_bom && state.index == 0 && text.starts_with(BOM) {
+            true => BOM.len_utf8(),
+            false => state.index,
+        };
+        for (index, unit) in self.units(&text[start..end]) {
+            tokens.push(Token {
+                index: start + index,
+                len: unit.len(),
+                kind: TokenKinds::Text,
+                tag: None,
+                data: None,
+            })?;
+        }
+        Ok(LexerState { index: end })
+    }
+
+    /// Characters of the text, or grapheme clusters if `Lexer::graphemes` is set
+    fn units<'t>(&self, text: &'t str) -> Vec<(usize, &'t str)> {
+        #[cfg(feature = "graphemes")]
+        if self.graphemes {
+            return text.grapheme_indices(true).collect();
+        }
+        text.char_indices()
+            .map(|(i, c)| (i, &text[i..i + c.len_utf8()]))
+            .collect()
+    }
+
+    /// Whether a grapheme cluster starts at each character and after the last one,
+    /// None if `Lexer::graphemes` is not set
+    #[cfg(feature = "graphemes")]
+    fn cluster_starts(&self, text: &str, chars: &[(usize, char)]) -> Option<Vec<bool>> {
+        if !self.graphemes {
+            return None;
+        }
+        let mut starts = vec![false; chars.len() + 1];
+        let mut j = 0;
+        for (index, _) in text.grapheme_indices(true) {
+            while chars[j].0 < index {
+                j += 1;
+            }
+            starts[j] = true;
+        }
+        starts[chars.len()] = true;
+        Some(starts)
+    }
+
+    #[cfg(not(feature = "graphemes"))]
+    fn cluster_starts(&self, _: &str, _: &[(usize, char)]) -> Option<Vec<bool>> {
+        None
+    }
 }
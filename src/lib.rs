@@ -1,12 +1,35 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
 pub mod api;
+pub mod diagnostic;
 pub mod grammar;
+pub mod incremental;
+pub mod intern;
 pub mod lexer;
+pub mod memory;
+pub mod optimize;
 pub mod parser;
+pub mod scopes;
+pub mod testing;
+
+#[cfg(feature = "async")]
+pub mod asynchronous;
+
+#[cfg(feature = "std")]
+pub mod compiled;
+#[cfg(feature = "std")]
+pub mod definition;
+#[cfg(feature = "html")]
+pub mod html;
+#[cfg(feature = "std")]
+pub mod import;
+#[cfg(feature = "std")]
+pub mod session;
 
 pub mod format;
 
+pub use testing::reduce;
+
 // Choose between std and alloc
 cfg_if::cfg_if! {
     if #[cfg(feature = "std")] {
@@ -55,12 +78,142 @@ where
         }
     }
 
-    pub fn parse(
+    pub fn parse<T: parser::TokenSource<'src> + ?Sized>(
+        &'a self,
+        tokens: &T,
+        text: &'a str,
+    ) -> Result<parser::ParseResult<'src>, parser::ParseError<'a>> {
+        let tokens = self.parser.filter_tokens(text, tokens);
+        self.parser.parse(&self.grammar, &self.lexer, text, &tokens)
+    }
+
+    /// Parses with the result kept in the allocator, see `memory`
+    ///
+    /// With a bump allocator all memory of the parse can be reclaimed at once:
+    /// forget the result and reset the allocator
+    #[cfg(feature = "allocator")]
+    pub fn parse_in<T: parser::TokenSource<'src> + ?Sized>(
         &'a self,
-        tokens: &Vec<lexer::Token<'src>>,
+        tokens: &T,
         text: &'a str,
+        alloc: &'static (dyn allocator_api2::alloc::Allocator + Sync),
     ) -> Result<parser::ParseResult<'src>, parser::ParseError<'a>> {
-        self.parser.parse(&self.grammar, &self.lexer, text, tokens)
+        let tokens = self.parser.filter_tokens(text, tokens);
+        self.parser.parse_in(
+            &self.grammar,
+            &self.lexer,
+            text,
+            &tokens,
+            memory::ParseAlloc::new(alloc),
+        )
+    }
+
+    /// Parses with globals seeded by the host, see `parser::ParseState`
+    ///
+    /// The globals are returned in `ParseResult::globals`
+    pub fn parse_with_state<T: parser::TokenSource<'src> + ?Sized>(
+        &'a self,
+        tokens: &T,
+        text: &'a str,
+        state: parser::ParseState<'src>,
+    ) -> Result<parser::ParseResult<'src>, Box<parser::ParseError<'a>>> {
+        let tokens = self.parser.filter_tokens(text, tokens);
+        self.parser
+            .parse_with_state(&self.grammar, &self.lexer, text, &tokens, state)
+    }
+
+    /// Parses the text without building a tree and reports what was found to `sink`
+    ///
+    /// Meant for tools that only extract a few facts from large inputs. Nodes keep no
+    /// children and are dropped as soon as they end, lists only keep their last value.
+    /// Events are delivered as soon as the nodes around them commit or end, events of
+    /// failed nodes are never delivered. A parse that fails still delivers the events
+    /// no node could take back before the error.
+    pub fn parse_events<T: parser::TokenSource<'src> + ?Sized>(
+        &'a self,
+        tokens: &T,
+        text: &'a str,
+        sink: &mut impl parser::EventSink<'src>,
+    ) -> Result<(), Box<parser::ParseError<'a>>> {
+        let tokens = self.parser.filter_tokens(text, tokens);
+        self.parser
+            .parse_events(&self.grammar, &self.lexer, text, &tokens, sink)
+    }
+
+    /// Hash of the grammar and the options of the lexer with 128 bits, see `Grammar::fingerprint`
+    ///
+    /// Meant as the key of caches of parse results and compiled grammars.
+    /// Lexer preprocessors and the options of the parser are not included
+    #[cfg(feature = "std")]
+    pub fn fingerprint(&self) -> u128 {
+        let grammar = self.grammar.to_canonical_json();
+        let lexer = compiled::CompiledGrammar::lexer_options(&self.lexer);
+        compiled::CompiledGrammar::fingerprint(&[grammar.as_bytes(), &lexer])
+    }
+
+    /// Parses the tokens in `range` with `node` as the entry
+    ///
+    /// Regions that the grammar only captures as raw tokens, for example with `Until`,
+    /// can be parsed again once it is known what they contain. The region is parsed
+    /// like a whole file that ends after its last token, byte offsets stay those of `text`.
+    ///
+    /// The tokens are expected to be filtered already, like the ones of a previous parse.
+    pub fn parse_node_at(
+        &'a self,
+        node: &'a str,
+        tokens: &[lexer::Token<'src>],
+        range: core::ops::Range<usize>,
+        text: &'a str,
+    ) -> Result<parser::ParseResult<'src>, Box<parser::ParseError<'a>>> {
+        self.parser
+            .parse_node_at(&self.grammar, &self.lexer, node, text, &tokens, range)
+    }
+
+    /// Suggestions for the text at the byte `offset`
+    ///
+    /// Lists the words and tokens the grammar expects at the offset and the nodes that
+    /// have a `grammar::Node::snippet`. The token the offset is in the middle or at
+    /// the end of counts as being typed, so suggestions cover it up to the offset.
+    pub fn completions<T: parser::TokenSource<'src> + ?Sized>(
+        &'a self,
+        tokens: &T,
+        text: &'a str,
+        offset: usize,
+    ) -> Vec<parser::Completion<'src>> {
+        let tokens = self.parser.filter_tokens(text, tokens);
+        self.parser
+            .completions(&self.grammar, &self.lexer, text, &tokens, offset)
+    }
+
+    /// Parses the text of an edit again with `node` as the entry
+    ///
+    /// Meant for edits made by `parser::ParseResult::edit_node`, `tokens` and `text`
+    /// are those after the edit. Only the tokens of the inserted text are parsed,
+    /// so a codemod can check that the replacement is still a valid node.
+    pub fn reparse_edit(
+        &'a self,
+        node: &'a str,
+        tokens: &[lexer::Token<'src>],
+        edit: &incremental::TextEdit,
+        text: &'a str,
+    ) -> Result<parser::ParseResult<'src>, Box<parser::ParseError<'a>>> {
+        let span = edit.new_span();
+        let start = tokens.partition_point(|token| token.index < span.start);
+        let end = tokens.partition_point(|token| token.index < span.end);
+        self.parse_node_at(node, tokens, start..end, text)
+    }
+
+    /// Returns every parse of an ambiguous text, see `parser::Parser::exploration`
+    ///
+    /// Meant for designing grammars, each result is a separate parse of the whole text
+    pub fn parse_all<T: parser::TokenSource<'src> + ?Sized>(
+        &'a self,
+        tokens: &T,
+        text: &'a str,
+    ) -> parser::Parses<'src> {
+        let tokens = self.parser.filter_tokens(text, tokens);
+        self.parser
+            .parse_all(&self.grammar, &self.lexer, text, &tokens)
     }
 }
 
@@ -73,6 +226,7 @@ mod tests {
 
     use crate::{
         api::ext::{enumerator, local, node, text, token, word},
+        format::DiagnosticStyle,
         grammar::validator::Validator,
         lexer::TokenKinds,
     };
@@ -90,6 +244,74 @@ mod tests {
         assert_eq!(tokens[0].kind, TokenKinds::Text);
     }
 
+    #[test]
+    fn unknown_chars() {
+        let mut parser = Parser::new();
+        let txt = "a\u{0}\u{1b}b";
+        parser.lexer.unknown_chars = lexer::UnknownChars::Error;
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let kinds = tokens.iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                TokenKinds::Text,
+                TokenKinds::Error,
+                TokenKinds::Text,
+                TokenKinds::Control(lexer::ControlTokenKind::Eof)
+            ]
+        );
+        assert_eq!((tokens[1].index, tokens[1].len), (1, 2));
+
+        parser.lexer.unknown_chars = lexer::UnknownChars::Fail;
+        let err = parser.lexer.lex_ascii(txt).unwrap_err();
+        assert_eq!(err.span, 1..3);
+    }
+
+    #[test]
+    fn line_endings() {
+        let mut parser = Parser::new();
+        let txt = "\u{FEFF}a\r\nb\nc";
+        parser.lexer.strip_bom = true;
+        parser.lexer.crlf = true;
+        for tokens in [
+            parser.lexer.lex_utf8(txt).unwrap(),
+            parser.lexer.lex_ascii(txt).unwrap(),
+        ] {
+            assert_eq!(tokens[0].kind, TokenKinds::Text);
+            assert_eq!(tokens[0].index, 3);
+            assert_eq!(
+                tokens[1].kind,
+                TokenKinds::Control(lexer::ControlTokenKind::Eol)
+            );
+            assert_eq!((tokens[1].index, tokens[1].len), (4, 2));
+            assert_eq!(tokens[2].stringify(txt), "b");
+        }
+        let mixed = parser.lexer.check_line_endings(txt).unwrap();
+        assert_eq!(mixed.span, 7..8);
+        assert!(parser.lexer.check_line_endings("a\nb\n").is_none());
+    }
+
+    #[test]
+    fn lex_bytes() {
+        let parser = Parser::new();
+        let bytes = b"obj \xff\xfe endobj";
+
+        let (txt, tokens) = parser
+            .lexer
+            .lex_bytes(bytes, lexer::ByteEncoding::Raw)
+            .unwrap();
+        assert_eq!(txt.len(), bytes.len());
+        assert_eq!((tokens[2].index, tokens[2].len), (4, 2));
+        assert_eq!(tokens[4].stringify(&txt), "endobj");
+
+        let (txt, tokens) = parser
+            .lexer
+            .lex_bytes(bytes, lexer::ByteEncoding::Latin1)
+            .unwrap();
+        assert_eq!(tokens[2].stringify(&txt), "\u{ff}\u{fe}");
+        assert_eq!(tokens[4].stringify(&txt), "endobj");
+    }
+
     #[test]
     fn rules() {
         use crate::api::ext;
@@ -106,19 +328,20 @@ mod tests {
             values: [token("+"), token("-"), token("*"), token("/")].to_vec(),
         });
         parser.grammar.add_node(grammar::Node {
-            name: "value",
+            name: "value".into(),
             rules: ext::rules([
                 ext::is(text()).set(local("nodes")).commit(),
                 ext::while_(enumerator("operators"))
                     .set(local("nodes"))
                     .then([ext::is(text()).set(local("nodes"))]),
             ]),
-            variables: [("nodes", VariableKind::NodeList)].to_vec(),
+            variables: [("nodes".into(), VariableKind::NodeList)].to_vec(),
             docs: Some("example: 1 + 6 - value1"),
+            ..Default::default()
         });
 
         parser.grammar.add_node(grammar::Node {
-            name: "KWLet",
+            name: "KWLet".into(),
             rules: ext::rules([
                 ext::is(word("let")).commit().start(),
                 ext::is(text()).set(local("ident")),
@@ -127,18 +350,20 @@ mod tests {
                 ext::is(token(";")).hint("Close let statement with a semicolon"),
             ]),
             variables: [
-                ("ident", VariableKind::Node),
-                ("type", VariableKind::Node),
-                ("value", VariableKind::Node),
+                ("ident".into(), VariableKind::Node),
+                ("type".into(), VariableKind::Node),
+                ("value".into(), VariableKind::Node),
             ]
             .to_vec(),
             docs: Some("example: let identifier: Type = value;"),
+            ..Default::default()
         });
         parser.grammar.add_node(grammar::Node {
-            name: "entry",
+            name: "entry".into(),
             rules: ext::rules([ext::while_(node("KWLet")).set(local("lets"))]),
-            variables: [("lets", VariableKind::NodeList)].to_vec(),
+            variables: [("lets".into(), VariableKind::NodeList)].to_vec(),
             docs: Some("A list of let statements"),
+            ..Default::default()
         });
         parser.parser.entry = Some("entry");
 
@@ -153,21 +378,25 @@ mod tests {
             Ok(res) => {
                 println!("Parsing done, duration: {:?}", start_time.elapsed());
                 let entry = res.entry;
-                for entry in entry.get_list("lets").iter().map(|e| e.unwrap_node()) {
+                for entry in entry
+                    .list_unchecked("lets")
+                    .iter()
+                    .map(|e| e.as_node_unchecked())
+                {
                     let ident = entry
                         .variables
                         .get("ident")
                         .unwrap()
-                        .unwrap_node()
+                        .node_unchecked()
                         .stringify(txt);
                     print!("result: let {ident}");
                     if let Some(t) = entry.variables.get("type").unwrap().try_unwrap_node() {
                         let t = t.stringify(txt);
                         print!(": {t}")
                     }
-                    if let Some(v) = entry.try_get_node("value") {
+                    if let Some(v) = entry.node_unchecked("value") {
                         print!(" =");
-                        for node in v.unwrap_node().get_list("nodes") {
+                        for node in v.as_node_unchecked().list_unchecked("nodes") {
                             let v = node.stringify(txt);
                             print!(" {v}");
                         }
@@ -187,4 +416,5457 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn folding_ranges() {
+        use crate::api::ext;
+
+        let txt = "{ a;\n  { b; }\n  {\n    c;\n  }\n}";
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens("{};".split("").filter(|s| !s.is_empty()));
+
+        parser
+            .grammar
+            .new_node("block")
+            .rules([
+                ext::is(token("{")),
+                ext::while_(text())
+                    .set(local("stmts"))
+                    .then([ext::is(token(";"))]),
+                ext::while_(node("block")).set(local("stmts")),
+                ext::is(token("}")),
+            ])
+            .variables([ext::list_var("stmts")])
+            .foldable()
+            .build();
+        parser.parser.entry = Some("block");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let ranges = res.folding_ranges(txt);
+        assert_eq!(ranges.len(), 2);
+        assert_eq!((ranges[0].start_line, ranges[0].end_line), (1, 6));
+        assert_eq!((ranges[1].start_line, ranges[1].end_line), (3, 5));
+    }
+
+    #[test]
+    fn document_symbols() {
+        use crate::{api::ext, grammar::SymbolKind};
+
+        let txt = "fn outer { fn inner { } }\nfn other { }";
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens("{}".split("").filter(|s| !s.is_empty()));
+
+        parser
+            .grammar
+            .new_node("function")
+            .rules([
+                ext::is(word("fn")),
+                ext::is(text()).set(local("ident")),
+                ext::is(token("{")),
+                ext::while_(node("function")).set(local("body")),
+                ext::is(token("}")),
+            ])
+            .variables([ext::node_var("ident"), ext::list_var("body")])
+            .symbol("ident", SymbolKind::Function)
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("function")).set(local("functions"))])
+            .variables([ext::list_var("functions")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let valid = Validator::default().validate(&parser);
+        assert!(valid.pass());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let symbols = res.document_symbols(txt);
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0].name, "outer");
+        assert_eq!(symbols[0].kind, SymbolKind::Function);
+        assert_eq!(symbols[0].children.len(), 1);
+        assert_eq!(symbols[0].children[0].name, "inner");
+        assert_eq!(symbols[1].name, "other");
+        assert!(symbols[1].children.is_empty());
+    }
+
+    #[test]
+    fn round_trip() {
+        use crate::{
+            api::ext,
+            testing::{self, Generator, SeedRng},
+        };
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens("{}(),".split("").filter(|s| !s.is_empty()));
+
+        parser
+            .grammar
+            .new_node("function")
+            .rules([
+                ext::is(word("fn")),
+                ext::is(text()).set(local("ident")),
+                ext::is(token("(")),
+                ext::maybe(text())
+                    .set(local("params"))
+                    .then([ext::while_(token(",")).then([ext::is(text()).set(local("params"))])]),
+                ext::is(token(")")),
+                ext::is(token("{")),
+                ext::while_(node("function")).set(local("body")),
+                ext::is(token("}")),
+            ])
+            .variables([
+                ext::node_var("ident"),
+                ext::list_var("params"),
+                ext::list_var("body"),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("function")).set(local("functions"))])
+            .variables([ext::list_var("functions")])
+            .allow_empty()
+            .build();
+        parser.parser.entry = Some("file");
+
+        let generator = Generator::default();
+        let a = generator.generate(&parser, &mut SeedRng::new(7)).unwrap();
+        let b = generator.generate(&parser, &mut SeedRng::new(7)).unwrap();
+        assert_eq!(a, b);
+
+        testing::assert_round_trip(&parser, 200);
+    }
+
+    #[test]
+    fn profile() {
+        use crate::api::ext;
+
+        let txt = "fn outer { fn inner { } }\nfn other { }";
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens("{}".split("").filter(|s| !s.is_empty()));
+
+        parser
+            .grammar
+            .new_node("function")
+            .rules([
+                ext::is(word("fn")),
+                ext::is(text()).set(local("ident")),
+                ext::is(token("{")),
+                ext::while_(node("function")).set(local("body")),
+                ext::is(token("}")),
+            ])
+            .variables([ext::node_var("ident"), ext::list_var("body")])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("function")).set(local("functions"))])
+            .variables([ext::list_var("functions")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert!(parser.parse(&tokens, txt).unwrap().stats.is_none());
+
+        parser.parser.profile = true;
+        let stats = parser.parse(&tokens, txt).unwrap().stats.unwrap();
+        let function = stats.nodes["function"];
+        assert_eq!(function.successes, 3);
+        assert_eq!(function.failures, 0);
+        assert!(function.backtracks > 0);
+        assert_eq!(
+            function.invocations,
+            function.successes + function.backtracks
+        );
+        assert_eq!(stats.nodes["file"].invocations, 1);
+        assert_eq!(stats.by_backtracks()[0].0, "function");
+
+        // rules are numbered within their node
+        assert!(stats.rules.keys().all(|(node, rule)| match *node {
+            "function" => *rule < 5,
+            _ => *rule == 0,
+        }));
+        assert!(stats.top_rules(2).len() <= 2);
+        let mut folded = String::new();
+        stats.write_folded(&mut folded).unwrap();
+        assert!(folded
+            .lines()
+            .any(|line| line.starts_with("file:0;function:3;function:")));
+        assert!(folded.lines().all(|line| line
+            .rsplit(' ')
+            .next()
+            .unwrap()
+            .parse::<u128>()
+            .is_ok()));
+    }
+
+    #[test]
+    fn cut_and_backtrack_limit() {
+        use crate::{api::ext, parser::ParseErrors};
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens("()!;".split("").filter(|s| !s.is_empty()));
+
+        parser
+            .grammar
+            .new_node("call")
+            .rules([
+                ext::is(text()).set(local("ident")),
+                ext::maybe(token("("))
+                    .then([ext::is(text()).set(local("arg")).cut(), ext::is(token(")"))]),
+                ext::is(token("!")),
+            ])
+            .variables([ext::node_var("ident"), ext::node_var("arg")])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::while_(node("call")).set(local("calls")),
+                ext::is(token(";")),
+            ])
+            .variables([ext::list_var("calls")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let parse = |parser: &Parser, txt: &str| {
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            parser
+                .parse(&tokens, txt)
+                .map(|_| ())
+                .map_err(|e| e.kind.id_and_header().0)
+        };
+        let expected = |parser: &Parser, txt: &str, tok: &str| {
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            let err = parser.parse(&tokens, txt).unwrap_err();
+            match err.kind {
+                ParseErrors::ExpectedToken { expected, .. } => {
+                    assert_eq!(expected, TokenKinds::Token(tok))
+                }
+                kind => panic!("unexpected error: {:?}", kind),
+            }
+        };
+
+        assert_eq!(parse(&parser, "a(x)! b! ;"), Ok(()));
+        // The cut prevents `call` from backtracking inside of the parentheses
+        expected(&parser, "a(x ;", ")");
+        // but not after the block it was used in
+        expected(&parser, "a(x) ;", ";");
+
+        parser.parser.backtrack_limit = Some(0);
+        assert_eq!(parse(&parser, "a! b! ;"), Err("204"));
+        parser.parser.backtrack_limit = Some(1);
+        assert_eq!(parse(&parser, "a! b! ;"), Ok(()));
+    }
+
+    #[test]
+    fn missing_eof() {
+        use crate::{api::ext, parser::ParseErrors};
+
+        let txt = "a; b; c; ; d";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";"].into_iter());
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(text())
+                .set(local("items"))
+                .then([ext::is(token(";"))])])
+            .variables([ext::list_var("items")])
+            .build();
+        parser.grammar.eof = true;
+        parser.parser.entry = Some("file");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        match &err.kind {
+            ParseErrors::MissingEof { leftover, .. } => assert_eq!(&txt[leftover.clone()], "; d"),
+            kind => panic!("unexpected error: {:?}", kind),
+        }
+        assert_eq!(err.node.as_ref().unwrap().list_unchecked("items").len(), 3);
+
+        let mut buf = String::new();
+        err.write_styled(&mut buf, txt, None, &DiagnosticStyle::plain())
+            .unwrap();
+        assert!(buf.contains("parsed as file"));
+        assert!(buf.contains("not parsed"));
+    }
+
+    #[test]
+    fn one_of_candidates() {
+        use crate::{api::ext, parser::ParseErrors};
+
+        let txt = "a = b c";
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens("=();".split("").filter(|s| !s.is_empty()));
+        parser
+            .grammar
+            .new_node("assign")
+            .rules([
+                ext::is(text()),
+                ext::is(token("=")),
+                ext::is(text()),
+                ext::is(token(";")),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("call")
+            .rules([
+                ext::is(text()),
+                ext::is(token("(")),
+                ext::is(token(")")),
+                ext::is(token(";")),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("statement")
+            .rules([ext::is_one_of([
+                ext::option(node("assign")),
+                ext::option(node("call")),
+                ext::option(token(";")),
+            ])])
+            .build();
+        parser.parser.entry = Some("statement");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        let ParseErrors::ExpectedOneOf { candidates, .. } = &err.kind else {
+            panic!("unexpected error: {:?}", err.kind);
+        };
+        let names = candidates
+            .iter()
+            .map(|c| c.node.as_ref().unwrap().name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["assign", "call"]);
+
+        let mut buf = String::new();
+        err.write_styled(&mut buf, txt, None, &DiagnosticStyle::plain())
+            .unwrap();
+        assert!(buf.contains("2 candidate interpretations"));
+
+        parser.parser.max_candidates = 1;
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        let ParseErrors::ExpectedOneOf { candidates, .. } = &err.kind else {
+            panic!("unexpected error: {:?}", err.kind);
+        };
+        assert_eq!(candidates.len(), 1);
+    }
+
+    #[test]
+    fn diagnostic_window() {
+        let mut parser = Parser::new();
+        parser.lexer.unknown_chars = lexer::UnknownChars::Fail;
+        let txt = (1..=100)
+            .map(|i| match i {
+                50 => "line50 \u{0}\n".to_string(),
+                _ => format!("line{i}\n"),
+            })
+            .collect::<String>();
+        let err = parser.lexer.lex_utf8(&txt).unwrap_err();
+
+        let mut buf = String::new();
+        err.write_window(&mut buf, &txt, None, 2).unwrap();
+        assert!(buf.contains("50"));
+        assert!(buf.contains("line48") && buf.contains("line52"));
+        assert!(!buf.contains("line47") && !buf.contains("line53"));
+    }
+
+    #[test]
+    fn diagnostic_style() {
+        use crate::format::{ColorChoice, Decor};
+
+        let mut parser = Parser::new();
+        parser.lexer.unknown_chars = lexer::UnknownChars::Fail;
+        let txt = "a \u{0}";
+        let err = parser.lexer.lex_utf8(txt).unwrap_err();
+
+        // a string is not a terminal, so `Auto` does not color it
+        let mut buf = String::new();
+        err.write(&mut buf, txt, None).unwrap();
+        assert!(!buf.contains('\x1b') && buf.contains('│'));
+
+        let mut buf = String::new();
+        err.write_styled(&mut buf, txt, None, &DiagnosticStyle::plain())
+            .unwrap();
+        assert!(!buf.contains('\x1b') && !buf.contains('│'));
+
+        let style = DiagnosticStyle {
+            decor: Decor::Ascii,
+            color: ColorChoice::Always,
+            ..Default::default()
+        };
+        let mut buf = String::new();
+        err.write_styled(&mut buf, txt, None, &style).unwrap();
+        assert!(buf.contains('\x1b') && !buf.contains('│'));
+
+        assert_eq!(ColorChoice::Auto.resolve(false), ColorChoice::Never);
+        assert_eq!(ColorChoice::Never.resolve(true), ColorChoice::Never);
+        let style = DiagnosticStyle::default().for_sink(false);
+        assert_eq!(style.color, ColorChoice::Never);
+    }
+
+    #[test]
+    fn merged_diagnostics() {
+        use crate::{
+            api::ext,
+            diagnostic::{explain, Diagnostic, LabelKind, Severity},
+            format::write_diagnostics,
+        };
+
+        let txt = "a b";
+
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::is(text()), ext::is(token(";"))])
+            .build();
+        parser.parser.entry = Some("file");
+        parser.lexer.add_tokens([";"].into_iter());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        let diagnostic = err.diagnostic();
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.primary().unwrap().span, 2..3);
+        assert_eq!(diagnostic.code, err.kind.code());
+        assert!(explain(&diagnostic.code).is_some());
+
+        let custom = Diagnostic::new(Severity::Warning, "W01", "Unused identifier").label(
+            LabelKind::Primary,
+            0..1,
+            Some("never used".to_string()),
+        );
+        let mut buf = String::new();
+        write_diagnostics(
+            &mut buf,
+            &[diagnostic, custom],
+            txt,
+            None,
+            &DiagnosticStyle::plain(),
+        )
+        .unwrap();
+        assert!(buf.contains("syntax error[E0201]"));
+        assert!(buf.contains("warning[W01]: Unused identifier"));
+        assert!(buf.contains("never used"));
+    }
+
+    #[test]
+    fn token_tags() {
+        let mut lexer = lexer::Lexer::new();
+        lexer.add_token_tagged("->", "arrow");
+        lexer.add_tokens(["-", ">"].into_iter());
+        assert_eq!(lexer.tag_of("->"), Some("arrow"));
+
+        let txt = "a -> b - c";
+        let utf8 = lexer.lex_utf8(txt).unwrap();
+        let ascii = lexer.lex_ascii(txt).unwrap();
+        for tokens in [utf8, ascii] {
+            let tags = tokens
+                .iter()
+                .filter(|t| matches!(t.kind, TokenKinds::Token(_)))
+                .map(|t| t.tag)
+                .collect::<Vec<_>>();
+            assert_eq!(tags, [Some("arrow"), None]);
+        }
+    }
+
+    #[test]
+    fn aliases() {
+        use crate::api::ext;
+
+        let txt = "a := b";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([":="].into_iter());
+        parser.grammar.alias("ASSIGN", token(":="));
+        parser.grammar.alias("OPERATOR", ext::alias("ASSIGN"));
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::is(text()).set(local("ident")),
+                ext::is(ext::alias("OPERATOR")),
+                ext::is(text()).set(local("value")),
+            ])
+            .variables([ext::node_var("ident"), ext::node_var("value")])
+            .build();
+        parser.parser.entry = Some("file");
+        assert!(Validator::default().validate(&parser).success());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let value = res.entry.node_unchecked("value").as_ref().unwrap();
+        assert_eq!(value.stringify(txt), "b");
+
+        parser.grammar.alias("A", ext::alias("B"));
+        parser.grammar.alias("B", ext::alias("A"));
+        parser
+            .grammar
+            .new_node("broken")
+            .rules([ext::is(ext::alias("A")), ext::is(ext::alias("MISSING"))])
+            .build();
+        let codes = Validator::default()
+            .validate(&parser)
+            .errors
+            .iter()
+            .map(|e| e.kind.id_and_header().0)
+            .collect::<Vec<_>>();
+        assert_eq!(codes, ["110", "109"]);
+
+        // an unvalidated cycle is reported instead of recursing forever
+        parser.parser.entry = Some("broken");
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert!(matches!(err.kind, parser::ParseErrors::AliasCycle("A")));
+        assert_eq!(
+            crate::testing::Generator::default()
+                .generate(&parser, &mut crate::testing::SeedRng::new(0)),
+            Err(crate::testing::GenerateError::AliasCycle("A"))
+        );
+    }
+
+    #[test]
+    fn feature_flags() {
+        use crate::api::ext;
+
+        let txt = "let a";
+
+        let mut parser = Parser::new();
+        parser.grammar.feature("let", false);
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::if_feature("let")
+                    .then([ext::is(word("let"))])
+                    .otherwise([ext::is(word("var"))]),
+                ext::is(text()).set(local("ident")),
+            ])
+            .variables([ext::node_var("ident")])
+            .build();
+        parser.parser.entry = Some("file");
+        assert!(Validator::default().validate(&parser).success());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert!(parser.parse(&tokens, txt).is_err());
+
+        parser.parser.set_feature("let", true);
+        let res = parser.parse(&tokens, txt).unwrap();
+        let ident = res.entry.node_unchecked("ident").as_ref().unwrap();
+        assert_eq!(ident.stringify(txt), "a");
+
+        parser
+            .grammar
+            .new_node("broken")
+            .rules([ext::if_feature("jsx")])
+            .build();
+        let codes = Validator::default()
+            .validate(&parser)
+            .errors
+            .iter()
+            .map(|e| e.kind.id_and_header().0)
+            .collect::<Vec<_>>();
+        assert_eq!(codes, ["111"]);
+    }
+
+    #[test]
+    fn language_version() {
+        use crate::{api::ext, grammar::LanguageVersion};
+
+        let txt = "let a";
+
+        let mut parser = Parser::new();
+        let let_stmt = parser
+            .grammar
+            .new_node("let_stmt")
+            .rules([ext::is(word("let")), ext::is(text()).set(local("ident"))])
+            .variables([ext::node_var("ident")])
+            .since(2, 0)
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::is(let_stmt).set(local("stmt"))])
+            .variables([ext::node_var("stmt")])
+            .build();
+        parser.parser.entry = Some("file");
+        assert!(Validator::default().validate(&parser).success());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert!(parser.parse(&tokens, txt).is_ok());
+
+        parser.parser.version = Some(LanguageVersion::new(1, 4));
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert_eq!(err.kind.id_and_header().0, "205");
+        assert_eq!(
+            format!("{:?}", err.kind),
+            "let_stmt requires version 2.0 - found 1.4"
+        );
+
+        parser.parser.version = Some(LanguageVersion::new(2, 0));
+        assert!(parser.parse(&tokens, txt).is_ok());
+    }
+
+    #[test]
+    fn try_accessors() {
+        use crate::api::{ext, AccessError};
+
+        let txt = "let a";
+
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::is(word("let")).set(local("kw")),
+                ext::is(text()).set(local("ident")),
+                ext::maybe(text()).set(local("value")),
+            ])
+            .variables([
+                ext::node_var("kw"),
+                ext::node_var("ident"),
+                ext::node_var("value"),
+            ])
+            .build();
+        parser.parser.entry = Some("file");
+        assert!(Validator::default().validate(&parser).success());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let ident = res.entry.try_required_node("ident").unwrap();
+        assert!(ident.try_name().is_err());
+        assert!(ident.try_token().is_ok());
+        assert!(matches!(
+            res.entry.try_required_node("value"),
+            Err(AccessError::MissingNode { .. })
+        ));
+        assert!(matches!(
+            res.entry.try_number("ident"),
+            Err(AccessError::WrongKind { .. })
+        ));
+        let err = res.entry.try_list("missing").unwrap_err();
+        assert!(matches!(err, AccessError::VariableNotFound { .. }));
+        assert!(err.to_string().starts_with("No variable \"missing\" found"));
+
+        assert!(matches!(ident.try_as_node(), Err(AccessError::IsToken(_))));
+        assert_eq!(ident.try_token().map(|tok| tok.index), Ok(4));
+        assert_eq!(ident.try_len(), Ok(1));
+        assert_eq!(ident.len_unchecked(), 1);
+        #[allow(deprecated)]
+        {
+            // the names before the `*_unchecked` convention still work
+            assert_eq!(ident.len(), 1);
+            assert!(ident.try_unwrap_node().is_err());
+            assert!(res.entry.try_get_node("value").is_none());
+        }
+        let lines = lexer::LineIndex::new(txt);
+        assert_eq!(ident.location(&lines, lexer::TabPolicy::Char).column, 5);
+        assert_eq!(ident.try_stringify(txt), Some("a"));
+        assert_eq!(ident.try_stringify("let"), None);
+        assert!(res.entry.variables["value"].try_unwrap_node().is_none());
+        assert!(res.entry.variables["kw"].try_unwrap_node().is_some());
+    }
+
+    #[test]
+    fn ordered_children() {
+        use crate::api::ext;
+
+        let txt = "a = 1, b, c = 2";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["=", ","].into_iter());
+        let entry = parser
+            .grammar
+            .new_node("entry")
+            .rules([
+                ext::is(text()).set(local("key")),
+                ext::maybe(token("=")).then([ext::is(text()).set(local("value"))]),
+            ])
+            .variables([ext::node_var("key"), ext::node_var("value")])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::is(entry).set(local("entries")),
+                ext::while_(token(",")).then([ext::is(entry).set(local("entries"))]),
+            ])
+            .variables([ext::list_var("entries")])
+            .record_children()
+            .build();
+        parser.parser.entry = Some("file");
+        assert!(Validator::default().validate(&parser).success());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let children = res
+            .entry
+            .children_unchecked()
+            .iter()
+            .map(|c| c.stringify(txt))
+            .collect::<Vec<_>>();
+        assert_eq!(children, ["a = 1", ",", "b", ",", "c = 2"]);
+
+        let first = &res.entry.list_unchecked("entries")[0];
+        assert!(first.try_children().is_err());
+    }
+
+    #[test]
+    fn field_pairs() {
+        use crate::api::ext;
+
+        let txt = "{ a: 1, b: 2 }";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["{", "}", ":", ","].into_iter());
+        parser
+            .grammar
+            .new_node("map")
+            .rules([
+                ext::is(token("{")),
+                ext::is(text()).set_field("entries", "key"),
+                ext::is(token(":")),
+                ext::is(text()).set_field("entries", "value"),
+                ext::while_(token(",")).then([
+                    ext::is(text()).set_field("entries", "key"),
+                    ext::is(token(":")),
+                    ext::is(text()).set_field("entries", "value"),
+                ]),
+                ext::is(token("}")),
+            ])
+            .variables([ext::list_var("entries")])
+            .build();
+        parser.parser.entry = Some("map");
+        assert!(Validator::default().validate(&parser).success());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let pairs = res
+            .entry
+            .pairs_unchecked("entries")
+            .into_iter()
+            .map(|(field, n)| (field, n.stringify(txt)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            [("key", "a"), ("value", "1"), ("key", "b"), ("value", "2")]
+        );
+        assert_eq!(res.entry.list_unchecked("entries").len(), 4);
+
+        parser
+            .grammar
+            .new_node("broken")
+            .rules([ext::is(text()).set_field("name", "key")])
+            .variables([ext::node_var("name")])
+            .build();
+        let codes = Validator::default()
+            .validate(&parser)
+            .errors
+            .iter()
+            .map(|e| e.kind.id_and_header().0)
+            .collect::<Vec<_>>();
+        assert_eq!(codes.len(), 1);
+    }
+
+    #[test]
+    fn trivia_policy() {
+        use crate::{api::ext, grammar::TriviaPolicy, lexer::Token};
+
+        let txt = "a;  #\n  #\nb;";
+        let join = |trivia: &[Token]| {
+            trivia
+                .iter()
+                .map(|t| &txt[t.index..t.index + t.len])
+                .collect::<String>()
+        };
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";", "#"].into_iter());
+        parser.grammar.ignored.push(TokenKinds::Token("#"));
+        let stmt = parser
+            .grammar
+            .new_node("stmt")
+            .rules([ext::is(text()), ext::is(token(";"))])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(stmt).set(local("stmts"))])
+            .variables([ext::list_var("stmts")])
+            .build();
+        parser.parser.entry = Some("file");
+        assert!(Validator::default().validate(&parser).success());
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+
+        let res = parser.parse(&tokens, txt).unwrap();
+        let a = res.entry.list_unchecked("stmts")[0].try_as_node().unwrap();
+        assert!(a.trailing_trivia().is_empty());
+
+        parser.grammar.trivia = Some(TriviaPolicy::SplitAtLine);
+        let res = parser.parse(&tokens, txt).unwrap();
+        let stmts = res.entry.list_unchecked("stmts");
+        let (a, b) = (
+            stmts[0].try_as_node().unwrap(),
+            stmts[1].try_as_node().unwrap(),
+        );
+        assert_eq!(join(a.leading_trivia()), "");
+        assert_eq!(join(a.trailing_trivia()), "  #\n");
+        assert_eq!(join(b.leading_trivia()), "  #\n");
+
+        parser.grammar.trivia = Some(TriviaPolicy::AttachToNext);
+        let res = parser.parse(&tokens, txt).unwrap();
+        let stmts = res.entry.list_unchecked("stmts");
+        let (a, b) = (
+            stmts[0].try_as_node().unwrap(),
+            stmts[1].try_as_node().unwrap(),
+        );
+        assert_eq!(join(a.trailing_trivia()), "");
+        assert_eq!(join(b.leading_trivia()), "  #\n  #\n");
+    }
+
+    #[test]
+    fn doc_comments() {
+        use crate::{api::ext, lexer::C_DOC_COMMENTS};
+
+        let txt = "/// Adds\n/// numbers\nfn add;\n/** Subtracts */ fn sub;\nfn nop;";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";"].into_iter());
+        parser.lexer.doc_comments.extend(C_DOC_COMMENTS);
+        parser.grammar.ignored.push(TokenKinds::DocComment);
+        let function = parser
+            .grammar
+            .new_node("function")
+            .rules([
+                ext::is(word("fn")).attach_docs(),
+                ext::is(text()).set(local("name")),
+                ext::is(token(";")),
+            ])
+            .variables([ext::list_var("docs"), ext::node_var("name")])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(function).set(local("functions"))])
+            .variables([ext::list_var("functions")])
+            .build();
+        parser.parser.entry = Some("file");
+        assert!(Validator::default().validate(&parser).success());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let lines = lexer::LineIndex::new(txt);
+        let docs = |tokens: &[lexer::Token]| {
+            tokens
+                .iter()
+                .filter(|t| t.kind == TokenKinds::DocComment)
+                .map(|t| (t.index, t.len, lines.line(t.index)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(docs(&tokens), docs(&parser.lexer.lex_ascii(txt).unwrap()));
+
+        let res = parser.parse(&tokens, txt).unwrap();
+        let docs = res
+            .entry
+            .list_unchecked("functions")
+            .iter()
+            .map(|f| {
+                f.list_unchecked("docs")
+                    .iter()
+                    .map(|d| parser.lexer.doc_content(d.token_unchecked(), txt).unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            docs,
+            [vec![" Adds", " numbers"], vec![" Subtracts "], vec![]]
+        );
+    }
+
+    #[test]
+    fn performance_lints() {
+        use crate::{
+            api::ext,
+            grammar::validator::{PerformanceLint, ValidationWarnings},
+        };
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["+", "-", "*", ";"].into_iter());
+        let value = parser
+            .grammar
+            .new_node("value")
+            .rules([ext::is(text()).start()])
+            .build();
+        parser
+            .grammar
+            .new_node("op")
+            .rules([ext::maybe(token("+"))
+                .otherwise([ext::maybe(token("-")).otherwise([ext::maybe(token("*"))])])])
+            .build();
+        parser
+            .grammar
+            .new_node("skip")
+            .rules([
+                ext::is(token(";")).start(),
+                ext::while_(grammar::MatchToken::Any),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_enum("operand")
+            .options([value, token("+")])
+            .build();
+
+        let lints = |validator: Validator| {
+            let mut lints = validator
+                .validate(&parser)
+                .warnings
+                .iter()
+                .filter_map(|w| match &w.kind {
+                    ValidationWarnings::Slow(lint) => Some(format!("{lint:?}")),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            lints.sort();
+            lints
+        };
+        assert!(lints(Validator::default()).is_empty());
+        let validator = Validator {
+            performance_lints: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            lints(validator),
+            [
+                format!("{:?}", PerformanceLint::AnyInWhile),
+                format!("{:?}", PerformanceLint::MaybeChain(3)),
+                format!("{:?}", PerformanceLint::MissingNodeStart),
+                format!("{:?}", PerformanceLint::NodeBeforeToken("operand")),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_catalog() {
+        use crate::{
+            diagnostic::{catalog, explain},
+            grammar::validator::{ValidationErrors, ValidationWarnings},
+            parser::ParseErrors,
+        };
+
+        let codes = catalog().iter().map(|e| e.code).collect::<Vec<_>>();
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(codes.len(), unique.len());
+
+        for code in [
+            ParseErrors::Eof.code(),
+            ParseErrors::MissingEntry.code(),
+            ParseErrors::BacktrackLimit(1).code(),
+            ValidationErrors::AliasCycle("a").code(),
+            ValidationErrors::EmptyToken.code(),
+            ValidationWarnings::UsedPrint.code(),
+        ] {
+            assert!(explain(code).is_some(), "{code} is missing in the catalog");
+        }
+        assert_eq!(explain("E0202").unwrap().title, "Unexpected end of file");
+        assert!(explain("E9999").is_none());
+    }
+
+    #[test]
+    fn compiled_grammar() {
+        use crate::{
+            api::ext,
+            compiled::{CompiledGrammar, LoadError},
+        };
+
+        let source = b"file = 'let' ident '=' ident";
+        let txt = "let a = b";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_token_tagged("=", "assign");
+        parser.grammar.feature("let", true);
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::if_feature("let").then([ext::is(word("let"))]),
+                ext::is(text()).set(local("name")),
+                ext::is(token("=")),
+                ext::is(text()).set(local("value")),
+            ])
+            .variables([ext::node_var("name"), ext::node_var("value")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let compiled = CompiledGrammar::new(parser, source);
+        let path = std::env::temp_dir().join(format!("ruparse-{}.rupg", std::process::id()));
+        compiled.save(&path).unwrap();
+
+        let loaded = CompiledGrammar::load(&path, source).unwrap();
+        assert_eq!(loaded.to_bytes(), compiled.to_bytes());
+        assert_eq!(loaded.parser.lexer.tag_of("="), Some("assign"));
+
+        let tokens = loaded.parser.lexer.lex_utf8(txt).unwrap();
+        let res = loaded.parser.parse(&tokens, txt).unwrap();
+        let value = res.entry.node_unchecked("value").as_ref().unwrap();
+        assert_eq!(value.stringify(txt), "b");
+
+        let stale = CompiledGrammar::load(&path, b"file = ident");
+        assert!(matches!(stale, Err(LoadError::Stale { .. })));
+
+        let mut bytes = compiled.to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(
+            CompiledGrammar::from_bytes(&bytes),
+            Err(LoadError::Corrupt(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn token_source() {
+        use std::collections::VecDeque;
+
+        use crate::{api::ext, lexer::Token};
+
+        /// Leaves out the whitespace of the tokens it reads from on every access
+        struct NoWhitespace<'t, 'a>(&'t [Token<'a>]);
+
+        impl<'a> parser::TokenSource<'a> for NoWhitespace<'_, 'a> {
+            fn len(&self) -> usize {
+                self.0.iter().filter(|t| !t.kind.is_whitespace()).count()
+            }
+
+            fn get(&self, idx: usize) -> Option<Token<'a>> {
+                self.0
+                    .iter()
+                    .filter(|t| !t.kind.is_whitespace())
+                    .nth(idx)
+                    .copied()
+            }
+        }
+
+        let txt = "let a";
+
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::is(word("let")), ext::is(text()).set(local("ident"))])
+            .variables([ext::node_var("ident")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let from_slice = parser.parse(&tokens[..], txt).unwrap();
+
+        // pushing to the front of an empty deque wraps around, the tokens end up in two slices
+        let mut deque = VecDeque::with_capacity(tokens.len());
+        deque.extend(tokens[1..].iter().copied());
+        deque.push_front(tokens[0]);
+        assert!(!deque.as_slices().1.is_empty());
+        let from_deque = parser.parse(&deque, txt).unwrap();
+        let filtered = NoWhitespace(&tokens);
+        assert!(parser::TokenSource::len(&filtered) < tokens.len());
+        let from_filtered = parser.parse(&filtered, txt).unwrap();
+
+        for res in [from_slice, from_deque, from_filtered] {
+            let ident = res.entry.node_unchecked("ident").as_ref().unwrap();
+            assert_eq!(ident.stringify(txt), "a");
+        }
+    }
+
+    #[test]
+    fn token_filters() {
+        use crate::{api::ext, lexer::Token};
+
+        fn drop_please<'a>(text: &str, tokens: Vec<Token<'a>>) -> Vec<Token<'a>> {
+            tokens
+                .into_iter()
+                .filter(|t| t.stringify(text) != "please")
+                .collect()
+        }
+
+        fn insert_semicolon<'a>(_: &str, mut tokens: Vec<Token<'a>>) -> Vec<Token<'a>> {
+            let last = tokens.len() - 1;
+            let semicolon = Token::virtual_at(TokenKinds::Token(";"), &tokens[last]);
+            tokens.insert(last, semicolon);
+            tokens
+        }
+
+        let txt = "let please a";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_token(";");
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set(local("ident")),
+                ext::is(token(";")).set(local("end")),
+            ])
+            .variables([ext::node_var("ident"), ext::node_var("end")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert!(parser.parse(&tokens, txt).is_err());
+
+        parser.parser.add_token_filter(drop_please);
+        parser.parser.add_token_filter(insert_semicolon);
+        let res = parser.parse(&tokens, txt).unwrap();
+        let ident = res.entry.node_unchecked("ident").as_ref().unwrap();
+        assert_eq!(ident.stringify(txt), "a");
+        let end = res.entry.node_unchecked("end").as_ref().unwrap();
+        assert_eq!(end.stringify(txt), "");
+        assert_eq!(end.token_unchecked().index, txt.len());
+    }
+
+    #[test]
+    fn auto_semicolon() {
+        use crate::{api::ext, parser::AutoSemicolon};
+
+        let txt = "let a\nlet b;\n\nlet c";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_token(";");
+        parser
+            .grammar
+            .new_node("stmt")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set(local("ident")),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("ident")])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("stmt")).set(local("stmts"))])
+            .variables([ext::list_var("stmts")])
+            .build();
+        parser.parser.entry = Some("file");
+        parser.parser.eof_error = true;
+        parser
+            .parser
+            .add_token_filter(AutoSemicolon::new(";", [TokenKinds::Text]));
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let idents = res
+            .entry
+            .list_unchecked("stmts")
+            .iter()
+            .map(|stmt| {
+                stmt.node_unchecked("ident")
+                    .as_ref()
+                    .unwrap()
+                    .stringify(txt)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(idents, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn diagnostic_limit() {
+        use crate::diagnostic::{Diagnostic, DiagnosticLimit, LabelKind, LimitStrategy, Severity};
+
+        let diagnostics = (0..50)
+            .map(|i| {
+                let severity = match i % 5 {
+                    0 => Severity::Error,
+                    _ => Severity::Warning,
+                };
+                Diagnostic::new(severity, format!("E{}", i), "test").label(
+                    LabelKind::Primary,
+                    i..i + 1,
+                    None,
+                )
+            })
+            .collect::<Vec<_>>();
+        let starts = |diagnostics: &[Diagnostic]| {
+            diagnostics
+                .iter()
+                .map(|d| d.primary().unwrap().span.start)
+                .collect::<Vec<_>>()
+        };
+
+        let mut first = diagnostics.clone();
+        first.reverse();
+        let limit = DiagnosticLimit::new(3, LimitStrategy::KeepFirst);
+        assert_eq!(limit.apply(&mut first), 47);
+        assert_eq!(starts(&first), [0, 1, 2]);
+
+        let mut worst = diagnostics.clone();
+        let limit = DiagnosticLimit::new(11, LimitStrategy::KeepWorst);
+        limit.apply(&mut worst);
+        assert_eq!(starts(&worst), [0, 1, 5, 10, 15, 20, 25, 30, 35, 40, 45]);
+
+        let limit = DiagnosticLimit::new(5, LimitStrategy::Sample(42));
+        let mut sample = diagnostics.clone();
+        limit.apply(&mut sample);
+        let mut shuffled = diagnostics.clone();
+        shuffled.rotate_left(17);
+        limit.apply(&mut shuffled);
+        assert_eq!(sample.len(), 5);
+        assert_eq!(starts(&sample), starts(&shuffled));
+        assert!(starts(&sample).windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn doc_examples() {
+        use crate::{api::ext, grammar::validator::ValidationErrors};
+
+        let mut parser = Parser::new();
+        parser.lexer.add_token("=");
+        parser
+            .grammar
+            .new_node("assign")
+            .rules([
+                ext::is(text()).set(local("name")),
+                ext::is(token("=")),
+                ext::is(text()).set(local("value")),
+            ])
+            .variables([ext::node_var("name"), ext::node_var("value")])
+            .docs(
+                "Assigns a value\n\n```\na = b\n```\n\n```ignore\n= broken\n```\n\n```text\na =\n```\n",
+            )
+            .build();
+
+        let node = &parser.grammar.node("assign").unwrap();
+        assert_eq!(node.examples(), ["a = b", "a ="]);
+
+        let result = Validator::default().check_examples(&parser);
+        assert_eq!(result.errors.len(), 1);
+        match &result.errors[0].kind {
+            ValidationErrors::ExampleFailed { example, .. } => assert_eq!(*example, "a ="),
+            kind => panic!("unexpected error {kind:?}"),
+        }
+        assert_eq!(result.errors[0].kind.code(), "E0115");
+    }
+
+    #[test]
+    fn definition_errors() {
+        use crate::definition::{load, Value};
+
+        let string = |s: &str| Value::String(s.to_string());
+        let object = |entries: Vec<(&str, Value)>| {
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v))
+                    .collect(),
+            )
+        };
+        let definition = |rule: Value| {
+            object(vec![
+                ("tokens", Value::Array(vec![string("=")])),
+                (
+                    "nodes",
+                    Value::Array(vec![object(vec![
+                        ("name", string("file")),
+                        ("rules", Value::Array(vec![rule])),
+                    ])]),
+                ),
+            ])
+        };
+
+        let rule = object(vec![("kind", string("Whlie")), ("token", string("Text"))]);
+        let err = load(&definition(rule)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown rule kind 'Whlie' at nodes[0].rules[0], did you mean 'While'?"
+        );
+
+        let rule = object(vec![("kind", string("Is")), ("tokn", string("Text"))]);
+        let err = load(&definition(rule)).unwrap_err();
+        assert_eq!(err.path, "nodes[0].rules[0]");
+        assert_eq!(err.suggestion, Some("token"));
+
+        let rule = object(vec![("kind", string("Is")), ("token", string("Txt"))]);
+        let err = load(&definition(rule)).unwrap_err();
+        assert_eq!(err.path, "nodes[0].rules[0].token");
+        assert_eq!(err.suggestion, Some("Text"));
+
+        let rule = object(vec![("kind", string("Is")), ("token", string("Text"))]);
+        let parser = load(&definition(rule)).unwrap();
+        assert!(matches!(
+            parser.grammar.node("file").unwrap().rules[0],
+            grammar::Rule::Is {
+                token: grammar::MatchToken::Token(TokenKinds::Text),
+                ..
+            }
+        ));
+
+        let class = object(vec![(
+            "Rnage",
+            Value::Array(vec![string("a"), string("z")]),
+        )]);
+        let token = object(vec![("CharClass", class)]);
+        let rule = object(vec![("kind", string("Is")), ("token", token)]);
+        let err = load(&definition(rule)).unwrap_err();
+        assert_eq!(err.path, "nodes[0].rules[0].token.CharClass");
+        assert_eq!(err.suggestion, Some("Range"));
+
+        let node = |name: &str| {
+            object(vec![
+                ("name", string(name)),
+                ("rules", Value::Array(Vec::new())),
+            ])
+        };
+        let twice = object(vec![
+            ("tokens", Value::Array(Vec::new())),
+            ("nodes", Value::Array(vec![node("a"), node("b"), node("a")])),
+        ]);
+        let err = load(&twice).unwrap_err();
+        assert_eq!(err.path, "nodes[2].name");
+        assert_eq!(
+            err.to_string(),
+            "node 'a' is already defined at nodes[0] at nodes[2].name"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn json_definition() {
+        use crate::definition::{from_json, JSON_SCHEMA};
+
+        assert!(JSON_SCHEMA.contains("\"required\""));
+        let json = r#"{
+            "tokens": ["=", { "token": ";", "tag": "end" }],
+            "entry": "file",
+            "nodes": [
+                {
+                    "name": "assign",
+                    "docs": "Assigns a value",
+                    "variables": { "name": "Node", "value": "Node" },
+                    "rules": [
+                        { "kind": "Is", "token": "Text", "parameters": [{ "Set": "name" }] },
+                        { "kind": "Is", "token": { "Token": "=" } },
+                        { "kind": "Is", "token": "Text", "parameters": [{ "Set": "value" }] },
+                        { "kind": "Is", "token": { "Token": ";" } }
+                    ]
+                },
+                {
+                    "name": "file",
+                    "variables": { "assignments": "NodeList" },
+                    "rules": [
+                        {
+                            "kind": "While",
+                            "token": { "Node": "assign" },
+                            "parameters": [{ "Set": "assignments" }]
+                        }
+                    ]
+                }
+            ]
+        }"#;
+        let parser = from_json(json).unwrap();
+        assert!(Validator::default().validate(&parser).success());
+        assert_eq!(parser.lexer.tag_of(";"), Some("end"));
+        assert_eq!(
+            parser.grammar.node("file").unwrap().rules[0].origin(),
+            grammar::Origin::Definition("nodes[1].rules[0]")
+        );
+
+        let txt = "a = b; c = d;";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.entry.list_unchecked("assignments").len(), 2);
+
+        let err = from_json(r#"{ "tokens": [], "nodes": [], "entyr": "file" }"#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown field 'entyr', did you mean 'entry'?"
+        );
+        assert!(from_json("{")
+            .unwrap_err()
+            .message
+            .starts_with("invalid JSON"));
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "yaml"))]
+    fn toml_yaml_definition() {
+        use crate::definition::{from_toml, from_yaml};
+
+        let toml = r#"
+            tokens = ["="]
+            entry = "assign"
+
+            # multi-line docs with an example
+            [[nodes]]
+            name = "assign"
+            docs = """
+            Assigns a value
+
+            ```
+            a = b
+            ```
+            """
+            variables = { name = "Node", value = "Node" }
+            rules = [
+                { kind = "Is", token = "Text", parameters = [{ Set = "name" }] },
+                { kind = "Is", token = { Token = "=" } },
+                { kind = "Is", token = "Text", parameters = [{ Set = "value" }] },
+            ]
+        "#;
+        let yaml = r#"
+            tokens: ["="]
+            entry: assign
+            # multi-line docs with an example
+            nodes:
+              - name: assign
+                docs: |
+                  Assigns a value
+
+                  ```
+                  a = b
+                  ```
+                variables: { name: Node, value: Node }
+                rules:
+                  - { kind: Is, token: Text, parameters: [{ Set: name }] }
+                  - { kind: Is, token: { Token: "=" } }
+                  - { kind: Is, token: Text, parameters: [{ Set: value }] }
+        "#;
+
+        for parser in [from_toml(toml).unwrap(), from_yaml(yaml).unwrap()] {
+            assert!(Validator::default().validate(&parser).success());
+            assert!(Validator::default().check_examples(&parser).success());
+            let txt = "a = b";
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            let res = parser.parse(&tokens, txt).unwrap();
+            let value = res.entry.node_unchecked("value").as_ref().unwrap();
+            assert_eq!(value.stringify(txt), "b");
+        }
+
+        let err =
+            from_yaml("tokens: []\nnodes:\n  - name: a\n    rules: [{ kind: Whlie }]").unwrap_err();
+        assert_eq!(err.path, "nodes[0].rules[0]");
+        assert_eq!(err.suggestion, Some("While"));
+    }
+
+    #[test]
+    fn antlr_import() {
+        use crate::{
+            grammar::{validator::ValidationWarnings, VarKind},
+            import::antlr,
+        };
+
+        let g4 = r#"
+            grammar Expr;
+
+            prog: stats+=stat+ EOF;
+            stat: name=ID '=' value=expr ';'   # assign
+                | 'print' expr ';'             # print
+                ;
+            expr: term (op=('+' | '-') term)*;
+            term: INT | ID | '(' expr ')';
+
+            ID: [a-zA-Z_] [a-zA-Z_0-9]*;
+            INT: [0-9]+;
+            WS: [ \t\r\n]+ -> skip;
+        "#;
+        let import = antlr::import(g4).unwrap();
+        let parser = import.parser;
+        let result = Validator::default().validate(&parser);
+        assert!(result.errors.is_empty());
+        // `op` keeps only the last operator of the repetition
+        let warnings = result.warnings.iter().map(|w| &w.kind).collect::<Vec<_>>();
+        assert!(matches!(
+            warnings[..],
+            [ValidationWarnings::NodeOverwritten(VarKind::Local("op"))]
+        ));
+        assert_eq!(parser.parser.entry, Some("prog"));
+        let approximated = import
+            .warnings
+            .iter()
+            .map(|w| w.rule.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(approximated, ["ID", "INT"]);
+
+        let txt = "a = 1 + b - 2;\nprint (a);";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let stats = res.entry.list_unchecked("stats");
+        assert_eq!(stats.len(), 2);
+        let name = stats[0].node_unchecked("name").as_ref().unwrap();
+        assert_eq!(&txt[name.token_unchecked().index..][..1], "a");
+
+        let err = antlr::import("grammar X;\na: 'b").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 4));
+    }
+
+    #[test]
+    fn pest_import() {
+        use crate::import::pest;
+
+        let grammar = r#"
+            WHITESPACE = _{ " " | "\t" | NEWLINE }
+            COMMENT = _{ "/*" ~ (!"*/" ~ ANY)* ~ "*/" }
+
+            /// A list of assignments
+            file = { SOI ~ (assignment ~ ";")* ~ EOI }
+            assignment = { "let" ~ #name = ident ~ ("=" ~ #value = value)? }
+            value = { number | ident | "[" ~ (value ~ ("," ~ value)*)? ~ "]" }
+
+            ident = @{ !keyword ~ (ASCII_ALPHA | "_") ~ (ASCII_ALPHANUMERIC | "_")* }
+            number = @{ ASCII_DIGIT+ }
+            keyword = { "let" }
+            digits = { '0'..'9'{1, 3} }
+        "#;
+        let import = pest::import(grammar).unwrap();
+        let mut parser = import.parser;
+        assert!(Validator::default().validate(&parser).success());
+        assert_eq!(parser.parser.entry, Some("file"));
+        assert_eq!(
+            parser.grammar.node("file").unwrap().docs,
+            Some("A list of assignments")
+        );
+        let rules = import
+            .warnings
+            .iter()
+            .map(|w| w.rule.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(rules, ["COMMENT", "ident", "number", "digits"]);
+
+        let txt = "let a = [1, b];\nlet c;";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert!(parser.parse(&tokens, txt).is_ok());
+
+        parser.parser.entry = Some("assignment");
+        let txt = "let a = [1, b]";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let name = res.entry.node_unchecked("name").as_ref().unwrap();
+        assert_eq!(&txt[name.token_unchecked().index..][..1], "a");
+        assert!(res.entry.node_unchecked("value").is_some());
+
+        let err = pest::import("a = { \"b\" ~ }").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 13));
+        // escapes cut off at the end of the input are errors
+        for grammar in ["a = { \"\\u{4", "a = { \"\\x", "a = { \"\\u"] {
+            assert!(pest::import(grammar).is_err(), "{grammar}");
+        }
+        assert!(pest::import("a = { \"\\x41\\u{42}\" }").is_ok());
+    }
+
+    #[test]
+    fn scannerless() {
+        use crate::{api::ext, grammar::CharClass};
+
+        let mut parser = Parser::new();
+        parser.lexer.scannerless = true;
+        parser
+            .grammar
+            .new_node("heading")
+            .rules([
+                ext::is(ext::char('#')),
+                ext::while_(ext::char('#')),
+                ext::is(ext::char(' ')),
+                ext::while_(ext::char_class(CharClass::Not('\n'))).set(local("title")),
+            ])
+            .variables([ext::list_var("title")])
+            .build();
+        parser.parser.entry = Some("heading");
+        assert!(Validator::default().validate(&parser).success());
+
+        let txt = "## Hi there";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert_eq!(tokens.len(), txt.len() + 1);
+        let res = parser.parse(&tokens, txt).unwrap();
+        let title = res.entry.list_unchecked("title");
+        assert_eq!(title.len(), 8);
+        assert_eq!(title[0].stringify_until(&title[7], txt), "Hi there");
+
+        let txt = "#Hi";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert_eq!(err.kind.code(), "E0210");
+        assert!(CharClass::Range('a', 'f').contains('c'));
+    }
+
+    #[test]
+    #[cfg(feature = "graphemes")]
+    fn grapheme_clusters() {
+        use crate::lexer::Lexer;
+
+        let txt = "#\u{FE0F}\u{20E3} e\u{301}x \u{1F468}\u{200D}\u{1F467} #a";
+
+        let mut lexer = Lexer::new();
+        lexer.add_token("#");
+        let split = lexer.lex_utf8(txt).unwrap();
+        assert_eq!(split[0].stringify(txt), "#");
+
+        lexer.graphemes = true;
+        let tokens = lexer.lex_utf8(txt).unwrap();
+        let words = tokens
+            .iter()
+            .filter(|t| !t.kind.is_whitespace() && t.len > 0)
+            .map(|t| t.stringify(txt))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            words,
+            [
+                "#\u{FE0F}\u{20E3}",
+                "e\u{301}x",
+                "\u{1F468}\u{200D}\u{1F467}",
+                "#",
+                "a"
+            ]
+        );
+        let hash = tokens
+            .iter()
+            .find(|t| t.kind == TokenKinds::Token("#"))
+            .unwrap();
+        let lines = lexer::LineIndex::new(txt);
+        assert_eq!(lexer.locate(&lines, hash.index, hash.len).column, 8);
+
+        lexer.scannerless = true;
+        let tokens = lexer.lex_utf8(txt).unwrap();
+        assert_eq!(tokens.len(), 10);
+    }
+
+    #[test]
+    fn tab_columns() {
+        use crate::{
+            diagnostic::{Diagnostic, LabelKind, Severity},
+            lexer::{LineIndex, TabPolicy, TextLocation},
+        };
+        use std::path::Path;
+
+        let txt = "a\tb\n  \tc";
+        let mut parser = Parser::new();
+        let lines = LineIndex::new(txt);
+        let columns = |parser: &Parser| {
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            tokens
+                .iter()
+                .filter(|t| t.kind == TokenKinds::Text)
+                .map(|t| parser.lexer.locate(&lines, t.index, t.len).column)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(columns(&parser), [1, 3, 4]);
+        parser.lexer.tabs = TabPolicy::Width(4);
+        assert_eq!(columns(&parser), [1, 6, 7]);
+        parser.lexer.tabs = TabPolicy::Stop(4);
+        assert_eq!(columns(&parser), [1, 5, 5]);
+
+        let c = txt.len() - 1;
+        let location = TextLocation::locate(txt, c, 1, TabPolicy::Stop(8));
+        assert_eq!((location.line, location.column), (2, 9));
+
+        let diagnostic = Diagnostic::new(Severity::Warning, "W01", "Unused").label(
+            LabelKind::Primary,
+            c..c + 1,
+            None,
+        );
+        let style = DiagnosticStyle {
+            tabs: TabPolicy::Stop(8),
+            ..DiagnosticStyle::plain()
+        };
+        let mut buf = String::new();
+        diagnostic
+            .write(&mut buf, txt, Some(Path::new("file")), &style)
+            .unwrap();
+        assert!(buf.contains("file:2:9"));
+        assert!(buf.contains("        c"));
+    }
+
+    #[test]
+    fn cancellation() {
+        use crate::{api::ext, parser::CancellationToken};
+
+        let txt = "a b c";
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("word")
+            .rules([ext::is(text())])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("word"))])
+            .build();
+        parser.parser.entry = Some("file");
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+
+        let token = CancellationToken::new();
+        parser.parser.cancellation = Some(token.clone());
+        assert!(parser.parse(&tokens, txt).is_ok());
+
+        let other = token.clone();
+        std::thread::spawn(move || other.cancel()).join().unwrap();
+        assert!(token.is_cancelled());
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert!(matches!(err.kind, parser::ParseErrors::Cancelled));
+        assert_eq!(err.kind.code(), "E0211");
+    }
+
+    #[test]
+    fn ambiguity_exploration() {
+        use crate::api::ext;
+
+        let txt = "a b c";
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("pair")
+            .rules([ext::is(text()), ext::is(text())])
+            .build();
+        parser
+            .grammar
+            .new_node("single")
+            .rules([ext::is(text())])
+            .build();
+        parser
+            .grammar
+            .new_node("item")
+            .rules([ext::is_one_of([
+                ext::option(node("pair")),
+                ext::option(node("single")),
+            ])])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("item")).set(local("items"))])
+            .variables([ext::list_var("items")])
+            .build();
+        parser.parser.entry = Some("file");
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+
+        let parses = parser.parse_all(&tokens, txt);
+        let counts = parses
+            .results
+            .iter()
+            .map(|res| res.entry.list_unchecked("items").len())
+            .collect::<Vec<_>>();
+        // (a b) c, a (b c), a b c
+        assert_eq!(counts, [2, 2, 3]);
+        assert!(parses.complete && parses.error.is_none());
+        assert_eq!(parses.ambiguities.len(), 2);
+        assert_eq!(parses.ambiguities[0].node, "item");
+        assert_eq!(parses.ambiguities[1].span, 2..3);
+        assert_eq!(parses.ambiguities[0].alternatives.len(), 2);
+
+        parser.parser.exploration.max_parses = 1;
+        let parses = parser.parse_all(&tokens, txt);
+        assert_eq!(parses.results.len(), 1);
+        assert!(!parses.complete);
+        let preferred = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(
+            preferred.entry.list_unchecked("items").len(),
+            parses.results[0].entry.list_unchecked("items").len()
+        );
+
+        // the alternatives tried at an ambiguity are not counted
+        parser.parser.profile = true;
+        let parses = parser.parse_all(&tokens, txt);
+        let preferred = parser.parse(&tokens, txt).unwrap();
+        let invocations =
+            |stats: &Option<parser::ParseStats>| stats.as_ref().unwrap().nodes["pair"].invocations;
+        assert_eq!(
+            invocations(&parses.results[0].stats),
+            invocations(&preferred.stats)
+        );
+    }
+
+    #[test]
+    fn reparse_shares_nodes() {
+        use crate::{
+            api::ext::{self, node, text, token, word},
+            incremental::TextEdit,
+            memory::NodeRef,
+            parser::Nodes,
+        };
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";", "=", "+"].into_iter());
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("let")).set("lets")])
+            .variables([ext::list_var("lets")])
+            .build();
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set("name"),
+                ext::is(token("=")),
+                ext::is(node("sum")).set("value"),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("name"), ext::node_var("value")])
+            .build();
+        parser
+            .grammar
+            .new_node("sum")
+            .rules([
+                ext::is(text()).set("terms"),
+                ext::while_(token("+")).then([ext::is(text()).set("terms")]),
+            ])
+            .variables([ext::list_var("terms")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let txt = "let x = a + b;\nlet y = c;\nlet z = d;";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let old = parser.parse(&tokens, txt).unwrap();
+        let edit = TextEdit {
+            span: 23..24,
+            text: "c + e".to_string(),
+        };
+        let new_txt = TextEdit::apply(txt, core::slice::from_ref(&edit));
+        let tokens = parser.lexer.lex_utf8(&new_txt).unwrap();
+        let new = parser.reparse(&old, &edit, &tokens, &new_txt).unwrap();
+        assert_eq!(new.version, 1);
+
+        fn nodes<'a>(res: &'a parser::ParseResult<'a>) -> Vec<NodeRef<'a>> {
+            res.entry
+                .list_unchecked("lets")
+                .iter()
+                .map(|n| match n {
+                    Nodes::Node(node) => node.clone(),
+                    _ => panic!("expected a node"),
+                })
+                .collect()
+        }
+        let (before, after) = (nodes(&old), nodes(&new));
+        assert!(NodeRef::ptr_eq(&before[0], &after[0]));
+        assert_eq!(after[0].version(), 0);
+        assert_eq!(after[1].version(), 1);
+        assert_eq!(
+            after[1]
+                .required_node_unchecked("value")
+                .list_unchecked("terms")
+                .len(),
+            2
+        );
+        assert!(!NodeRef::ptr_eq(&before[2], &after[2]));
+        assert_eq!(after[2].version(), 1);
+
+        // the same positions as a full parse of the new text
+        let full = parser.parse(&tokens, &new_txt).unwrap();
+        let text = |node: &parser::Node| &new_txt[node.first_string_idx..node.last_string_idx];
+        for (node, expected) in after.iter().zip(nodes(&full)) {
+            assert_eq!(text(node), text(&expected));
+            assert_eq!(node.first_string_idx, expected.first_string_idx);
+            let (name, expected_name) = (
+                node.required_node_unchecked("name"),
+                expected.required_node_unchecked("name"),
+            );
+            assert_eq!(name.str_idx(), expected_name.str_idx());
+        }
+        assert_eq!(text(&after[2]), "let z = d;");
+
+        // an edit the node can not take is parsed in full and fails there
+        let edit = TextEdit {
+            span: 23..24,
+            text: "c +".to_string(),
+        };
+        let bad = TextEdit::apply(txt, core::slice::from_ref(&edit));
+        let tokens = parser.lexer.lex_utf8(&bad).unwrap();
+        assert!(parser.reparse(&old, &edit, &tokens, &bad).is_err());
+    }
+
+    #[test]
+    fn reparse_errors_and_scopes() {
+        use crate::{
+            api::ext::{self, node, text, token, word},
+            incremental::TextEdit,
+            memory::NodeRef,
+            parser::{Nodes, Recovery},
+        };
+
+        fn parser(declare: bool) -> Parser<'static> {
+            let mut parser = Parser::new();
+            parser.lexer.add_tokens(["=", ";"].into_iter());
+            let ident = match declare {
+                true => ext::is(text()).set("ident").declare("ident"),
+                false => ext::is(text()).set("ident"),
+            };
+            parser
+                .grammar
+                .new_node("let")
+                .rules([
+                    ext::is(word("let")).commit(),
+                    ident,
+                    ext::is(token("=")),
+                    ext::is(text()).set("value"),
+                    ext::is(token(";")),
+                ])
+                .variables([ext::node_var("ident"), ext::node_var("value")])
+                .build();
+            parser
+                .grammar
+                .new_node("file")
+                .rules([ext::while_(node("let")).set("stmts")])
+                .variables([ext::list_var("stmts")])
+                .build();
+            parser.parser.entry = Some("file");
+            parser.parser.recovery = Some(Recovery::new([TokenKinds::Token(";")]));
+            parser
+        }
+
+        /// Which statements the reparse shares with the previous result
+        fn reparse(parser: &Parser, txt: &str, span: core::ops::Range<usize>) -> Vec<bool> {
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            let old = parser.parse(&tokens, txt).unwrap();
+            let edit = TextEdit {
+                span,
+                text: "xy".to_string(),
+            };
+            let new_txt = TextEdit::apply(txt, core::slice::from_ref(&edit));
+            let tokens = parser.lexer.lex_utf8(&new_txt).unwrap();
+            let new = parser.reparse(&old, &edit, &tokens, &new_txt).unwrap();
+
+            // the same spans and errors as a full parse of the new text
+            let full = parser.parse(&tokens, &new_txt).unwrap();
+            let spans = |res: &parser::ParseResult| {
+                let stmts = res.entry.list_unchecked("stmts").iter();
+                let stmts = stmts.map(|stmt| stmt.str_idx()..stmt.str_last_idx());
+                let errors = res.errors.iter().map(|err| err.span.clone());
+                stmts.chain(errors).collect::<Vec<_>>()
+            };
+            assert_eq!(spans(&new), spans(&full));
+            assert_eq!(new.errors.len(), 1);
+
+            let old = old.entry.list_unchecked("stmts");
+            let new = new.entry.list_unchecked("stmts");
+            old.iter()
+                .zip(new)
+                .map(|stmts| match stmts {
+                    (Nodes::Node(old), Nodes::Node(new)) => NodeRef::ptr_eq(old, new),
+                    _ => false,
+                })
+                .collect()
+        }
+
+        // errors before and after the parsed node are kept and moved
+        let txt = "let a = b;\nlet = c d;\nlet e = f;";
+        assert_eq!(reparse(&parser(false), txt, 8..9), [false, false, false]);
+        assert_eq!(reparse(&parser(false), txt, 30..31), [true, false, false]);
+
+        // a result with scopes is parsed in full
+        assert_eq!(reparse(&parser(true), txt, 30..31), [false, false, false]);
+    }
+
+    #[test]
+    fn rule_coverage() {
+        use crate::{api::ext, testing::Coverage};
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens("();!".split("").filter(|s| !s.is_empty()));
+        parser
+            .grammar
+            .new_node("group")
+            .rules([
+                ext::is(token("(")),
+                ext::is(node("value")),
+                ext::is(token(")")),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("value")
+            .rules([ext::is_one_of([
+                ext::option(text()),
+                ext::option(node("group")),
+            ])])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::while_(node("value")),
+                ext::maybe_one_of([ext::option(token(";")), ext::option(token("!"))]),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("unused")
+            .rules([ext::is(text())])
+            .build();
+        parser.grammar.eof = true;
+        parser.parser.entry = Some("file");
+        parser.parser.profile = true;
+
+        let mut coverage = Coverage::new(&parser.grammar);
+        coverage.record_corpus(&parser, ["a b", "(a);", ")"]);
+        assert_eq!(coverage.failures, 1);
+        let uncovered = coverage
+            .uncovered_nodes()
+            .map(|node| node.name)
+            .collect::<Vec<_>>();
+        assert_eq!(uncovered, ["unused"]);
+        let uncovered = coverage.uncovered_alternatives().collect::<Vec<_>>();
+        assert_eq!(uncovered.len(), 1);
+        assert_eq!(uncovered[0].0, "file");
+        assert_eq!(uncovered[0].1.rule, 0);
+        assert!(matches!(
+            uncovered[0].1.token,
+            grammar::MatchToken::Token(TokenKinds::Token("!"))
+        ));
+
+        let value = &coverage.nodes[3];
+        assert_eq!(value.name, "value");
+        assert_eq!(value.hits, 4);
+        assert_eq!(value.alternatives[0].hits, 3);
+        assert!(coverage
+            .to_string()
+            .ends_with("covered 3/4 nodes, 3/4 alternatives, 1 failed texts"));
+    }
+
+    #[test]
+    fn reduce_input() {
+        use crate::{api::ext, testing::reduce_parse_error};
+
+        assert_eq!(
+            reduce("let a = b;\nlet c = d;", |txt| txt.contains('c')),
+            "c"
+        );
+        assert_eq!(reduce("a b", |_| false), "a b");
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens("()".split("").filter(|s| !s.is_empty()));
+        parser
+            .grammar
+            .new_node("group")
+            .rules([
+                ext::is(token("(")),
+                ext::while_(node("value")),
+                ext::is(token(")")),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("value")
+            .rules([ext::is_one_of([
+                ext::option(text()),
+                ext::option(node("group")),
+            ])])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("value"))])
+            .build();
+        parser.grammar.eof = true;
+        parser.parser.entry = Some("file");
+
+        let txt = "a (b c)\nd (e\nf g\n(h)";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let code = parser.parse(&tokens, txt).unwrap_err().kind.code();
+        let reduced = reduce_parse_error(&parser, txt);
+        assert_eq!(reduced, "(");
+        let tokens = parser.lexer.lex_utf8(&reduced).unwrap();
+        assert_eq!(
+            parser.parse(&tokens, &reduced).unwrap_err().kind.code(),
+            code
+        );
+    }
+
+    #[test]
+    fn batch_session() {
+        use crate::{api::ext, session::Session};
+        use std::path::PathBuf;
+
+        let mut parser = Parser::new();
+        parser.lexer.add_token(";");
+        parser
+            .grammar
+            .new_node("statement")
+            .rules([ext::is(text()), ext::is(token(";"))])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("statement")).set(local("statements"))])
+            .variables([ext::list_var("statements")])
+            .allow_empty()
+            .build();
+        parser.grammar.eof = true;
+        parser.parser.entry = Some("file");
+        let session = Session::new(parser).unwrap();
+
+        let files = (0..8)
+            .map(|i| {
+                let text = match i {
+                    5 => "a; b".to_string(),
+                    _ => "a; ".repeat(i),
+                };
+                (PathBuf::from(format!("{}.txt", i)), text)
+            })
+            .collect::<Vec<_>>();
+        let batch = session.parse_all(&files, 3);
+        assert_eq!(batch.files.len(), 8);
+        for (i, file) in batch.files.iter().enumerate() {
+            assert_eq!(file.path, files[i].0);
+            if i != 5 {
+                let result = file.result.as_ref().unwrap();
+                assert_eq!(result.entry.list_unchecked("statements").len(), i);
+            }
+        }
+        assert_eq!(batch.error_count(), 1);
+        let (path, diagnostic) = batch.diagnostics().next().unwrap();
+        assert_eq!(path, Path::new("5.txt"));
+        assert_eq!(diagnostic.severity, diagnostic::Severity::Error);
+
+        let mut buf = String::new();
+        batch
+            .write_diagnostics(&mut buf, &DiagnosticStyle::plain())
+            .unwrap();
+        assert!(buf.contains("5.txt:1:4"));
+        assert_eq!(session.parse_all(&files[..0], 4).files.len(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn async_parsing() {
+        use crate::{
+            api::ext,
+            asynchronous::{AsyncLexError, YIELD_INTERVAL},
+            lexer::C_DOC_COMMENTS,
+        };
+        use std::{
+            future::Future,
+            pin::pin,
+            task::{Context, Poll, Waker},
+        };
+        use tokio::runtime::Builder;
+
+        let mut parser = Parser::new();
+        parser.lexer.add_token(";");
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(text()).set(local("words"))])
+            .variables([ext::list_var("words")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let input = "a b c".as_bytes();
+        for runtime in [
+            Builder::new_multi_thread().build().unwrap(),
+            Builder::new_current_thread().build().unwrap(),
+        ] {
+            let mut txt = String::new();
+            let words = runtime.block_on(async {
+                let tokens = parser.lexer.lex_reader(input, &mut txt).await.unwrap();
+                let result = parser.parse_async(&tokens, &txt).await.unwrap();
+                result.entry.list_unchecked("words").len()
+            });
+            assert_eq!(words, 3);
+            assert_eq!(txt, "a b c");
+
+            // characters and doc comments cut by a chunk are lexed like in the whole text
+            let mut lexer = parser.lexer.clone();
+            lexer.doc_comments.extend(C_DOC_COMMENTS);
+            let long = format!("abc\n{}/**{}*/ b", "a é\n".repeat(3000), "x\n".repeat(1000));
+            let mut txt = String::new();
+            let tokens = runtime
+                .block_on(lexer.lex_reader(long.as_bytes(), &mut txt))
+                .unwrap();
+            assert_eq!(tokens, lexer.lex_utf8(&long).unwrap());
+            assert_eq!(txt, long);
+            let invalid = runtime.block_on(lexer.lex_reader(&b"a \xff"[..], &mut String::new()));
+            assert!(matches!(invalid, Err(AsyncLexError::Io(_))));
+            runtime.shutdown_background();
+        }
+
+        // the parse yields every `YIELD_INTERVAL` rules
+        parser
+            .grammar
+            .new_node("words")
+            .rules([ext::while_(ext::node("word")).set(local("words"))])
+            .variables([ext::list_var("words")])
+            .build();
+        parser
+            .grammar
+            .new_node("word")
+            .rules([ext::is(text())])
+            .build();
+        parser.parser.entry = Some("words");
+        let txt = "a ".repeat(YIELD_INTERVAL);
+        let tokens = parser.lexer.lex_utf8(&txt).unwrap();
+        let mut parse = pin!(parser.parse_async(&tokens, &txt));
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut yields = 0;
+        let result = loop {
+            match parse.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => break result.unwrap(),
+                Poll::Pending => yields += 1,
+            }
+        };
+        assert_eq!(result.entry.list_unchecked("words").len(), YIELD_INTERVAL);
+        assert!(yields >= 2);
+    }
+
+    #[test]
+    fn memory_limits() {
+        use crate::api::ext;
+
+        let txt = "a b c d";
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("word")
+            .rules([ext::is(text()).set(local("value"))])
+            .variables([ext::node_var("value")])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("word")).set(local("words"))])
+            .variables([ext::list_var("words")])
+            .build();
+        parser.parser.entry = Some("file");
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+
+        let report = parser.parse(&tokens, txt).unwrap().memory_report();
+        assert_eq!(report.nodes, 5);
+        assert_eq!(report.tokens, 4);
+        assert!(report.variable_bytes > 0);
+        assert!(report.total_bytes() > report.variable_bytes);
+
+        // the last `word` fails at the end of the file
+        parser.parser.max_nodes = Some(6);
+        assert!(parser.parse(&tokens, txt).is_ok());
+        parser.parser.max_nodes = Some(4);
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert!(matches!(err.kind, parser::ParseErrors::NodeLimit(4)));
+        assert_eq!(err.kind.code(), "E0212");
+        assert_eq!(err.span, 5..6);
+    }
+
+    #[test]
+    fn grammar_tests() {
+        use crate::{api::ext, compiled::CompiledGrammar};
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens(":=;".split("").filter(|s| !s.is_empty()));
+        parser
+            .grammar
+            .new_node("KWLet")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set(local("ident")),
+                ext::is(token(":")),
+                ext::is(text()).set(local("type")),
+                ext::is(token("=")),
+                ext::while_(text()).set(local("value")),
+                ext::is(token(";")),
+            ])
+            .variables([
+                ext::node_var("ident"),
+                ext::node_var("type"),
+                ext::list_var("value"),
+            ])
+            .build();
+        parser.grammar.add_test(
+            "KWLet",
+            "let a: int = 5 6;",
+            expect! { ident: "a", type: "int", value: "5 6" },
+        );
+        assert!(parser.run_grammar_tests().is_empty());
+
+        parser
+            .grammar
+            .add_test("KWLet", "let a: int = 5;", expect! { type: "float" });
+        parser.grammar.add_test("KWLet", "let a = 5;", []);
+        let failures = parser.run_grammar_tests();
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].test.input, "let a: int = 5;");
+        assert!(failures[0]
+            .to_string()
+            .ends_with("expected type to be \"float\", found \"int\""));
+        assert_eq!(failures[1].test.input, "let a = 5;");
+
+        let compiled = CompiledGrammar::new(parser, b"");
+        let loaded = CompiledGrammar::from_bytes(&compiled.to_bytes()).unwrap();
+        assert_eq!(loaded.parser.grammar.tests, compiled.parser.grammar.tests);
+        assert_eq!(loaded.parser.run_grammar_tests().len(), 2);
+    }
+
+    #[test]
+    fn split_tokens() {
+        use crate::{api::ext, compiled::CompiledGrammar, lexer::Token, parser::SplitTokens};
+
+        let txt = "Vec<Vec<i32>>";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["<", ">", ">>"].into_iter());
+        parser
+            .grammar
+            .new_node("type")
+            .rules([
+                ext::is(text()).set(local("name")),
+                ext::maybe(token("<"))
+                    .then([ext::is(node("type")).set(local("arg")), ext::is(token(">"))]),
+            ])
+            .variables([ext::node_var("name"), ext::node_var("arg")])
+            .build();
+        parser.parser.entry = Some("type");
+        parser.parser.eof_error = true;
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert!(parser.parse(&tokens, txt).is_err());
+
+        parser
+            .parser
+            .add_token_filter(SplitTokens::new().split(">>", [">", ">"]));
+        let filtered = parser.parser.filter_tokens(txt, &tokens);
+        let filtered: &dyn parser::TokenSource = &filtered;
+        let closing = filtered
+            .iter()
+            .filter(|t| t.kind == TokenKinds::Token(">"))
+            .collect::<Vec<_>>();
+        assert_eq!(closing.len(), 2);
+        assert_eq!((closing[0].index, closing[0].len), (11, 1));
+        assert_eq!((closing[1].index, closing[1].len), (12, 1));
+        assert!(closing[0].touches(&closing[1]));
+        assert!(parser.parse(&tokens, txt).is_ok());
+
+        // pieces have to cover the token and end on characters
+        let shift = tokens.iter().find(|t| t.kind == TokenKinds::Token(">>"));
+        let shift = shift.unwrap();
+        let gt = TokenKinds::Token(">");
+        assert!(shift.split(txt, [(gt, 1)]).is_none());
+        assert!(shift.split(txt, [(gt, 1), (gt, 2)]).is_none());
+        let txt = "é\n\tab";
+        let word = Token {
+            index: 0,
+            len: txt.len(),
+            kind: TokenKinds::Text,
+            tag: None,
+            data: None,
+        };
+        let kind = TokenKinds::Text;
+        assert!(word.split(txt, [(kind, 1)]).is_none());
+        let pieces = word.split(txt, [(kind, 3), (kind, 2), (kind, 1)]).unwrap();
+        let spans = pieces.iter().map(Token::span).collect::<Vec<_>>();
+        assert_eq!(spans, [0..3, 3..5, 5..6]);
+
+        // a rule can split the token only where it expects a piece
+        let txt = "Vec<Vec<i32>> >>";
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["<", ">", ">>"].into_iter());
+        parser
+            .grammar
+            .new_node("type")
+            .rules([
+                ext::is(text()).set(local("name")),
+                ext::maybe(token("<")).then([
+                    ext::is(node("type")).set(local("arg")),
+                    ext::is(token(">")).split(),
+                ]),
+            ])
+            .variables([ext::node_var("name"), ext::node_var("arg")])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::is(node("type")).set(local("type")),
+                ext::is(token(">>")),
+            ])
+            .variables([ext::node_var("type")])
+            .build();
+        parser.parser.entry = Some("file");
+        parser.parser.eof_error = true;
+        assert!(Validator::default().validate(&parser).success());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let outer = res.entry.required_node_unchecked("type");
+        let inner = outer.required_node_unchecked("arg");
+        assert_eq!(inner.stringify(txt), "Vec<i32>");
+        assert_eq!(outer.stringify(txt), "Vec<Vec<i32>>");
+
+        let txt = "Vec<i32>> >>";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert!(parser.parse(&tokens, txt).is_err());
+
+        let compiled = CompiledGrammar::new(parser, b"");
+        let loaded = CompiledGrammar::from_bytes(&compiled.to_bytes()).unwrap();
+        let txt = "Vec<Vec<i32>> >>";
+        let tokens = loaded.parser.lexer.lex_utf8(txt).unwrap();
+        assert!(loaded.parser.parse(&tokens, txt).is_ok());
+    }
+
+    #[test]
+    fn context_keywords() {
+        use crate::{api::ext, compiled::CompiledGrammar, parser::ParseErrors};
+
+        let txt = "import a b from c\nlet from";
+
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("import")
+            .rules([
+                ext::is(word("import")),
+                ext::while_(text()).set(local("names")),
+                ext::is(word("from")),
+                ext::is(text()).set(local("module")),
+            ])
+            .variables([ext::list_var("names"), ext::node_var("module")])
+            .keywords(["from"])
+            .build();
+        parser
+            .grammar
+            .new_node("let")
+            .rules([ext::is(word("let")), ext::is(text()).set(local("ident"))])
+            .variables([ext::node_var("ident")])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::is(node("import")).set(local("import")),
+                ext::is(node("let")).set(local("let")),
+            ])
+            .variables([ext::node_var("import"), ext::node_var("let")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let import = res.entry.required_node_unchecked("import");
+        assert_eq!(import.list_unchecked("names").len(), 2);
+        // `from` is only reserved in imports
+        let ident = res
+            .entry
+            .required_node_unchecked("let")
+            .required_node_unchecked("ident");
+        assert_eq!(ident.stringify(txt), "from");
+
+        let txt = "import a from from\nlet b";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert!(matches!(err.kind, ParseErrors::Keyword("from")));
+        assert_eq!(err.kind.code(), "E0213");
+        assert_eq!(err.span, 14..18);
+
+        let compiled = CompiledGrammar::new(parser, b"");
+        let loaded = CompiledGrammar::from_bytes(&compiled.to_bytes()).unwrap();
+        assert_eq!(
+            loaded.parser.grammar.node("import").unwrap().keywords,
+            ["from"]
+        );
+    }
+
+    #[test]
+    fn error_recovery() {
+        use crate::{
+            api::ext,
+            compiled::CompiledGrammar,
+            parser::{Nodes, ParseErrors, Recovery},
+        };
+
+        let txt = "let a = b;\nlet = c d;\nlet e = f;";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["=", ";"].into_iter());
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(word("let")).commit(),
+                ext::is(text()).set(local("ident")),
+                ext::is(token("=")),
+                ext::is(text()).set(local("value")),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("ident"), ext::node_var("value")])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("let")).set(local("stmts"))])
+            .variables([ext::list_var("stmts")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert!(parser.parse(&tokens, txt).is_err());
+
+        parser.parser.recovery = Some(Recovery::new([TokenKinds::Token(";")]));
+        let res = parser.parse(&tokens, txt).unwrap();
+        let stmts = res.entry.list_unchecked("stmts");
+        assert_eq!(stmts.len(), 3);
+        assert!(stmts[0].is_node() && stmts[2].is_node());
+        match &stmts[1] {
+            Nodes::Error {
+                span,
+                expected,
+                children,
+            } => {
+                assert_eq!(&txt[span.clone()], "let = c d;");
+                assert!(matches!(
+                    expected[..],
+                    [grammar::MatchToken::Token(TokenKinds::Text)]
+                ));
+                assert_eq!(children.len(), 5);
+            }
+            _ => panic!("expected an error node"),
+        }
+        assert!(stmts[1].try_name().is_err());
+        assert_eq!(&txt[stmts[1].str_last_idx()..], "\nlet e = f;");
+        assert_eq!(stmts[2].str_last_idx(), txt.len());
+        if let Nodes::Error { children, .. } = &stmts[1] {
+            assert_eq!(children[4].str_last_idx(), stmts[1].str_last_idx());
+        }
+        assert_eq!(res.errors.len(), 1);
+        assert!(matches!(
+            res.errors[0].kind,
+            ParseErrors::ExpectedToken { .. }
+        ));
+
+        let compiled = CompiledGrammar::new(parser, b"");
+        let loaded = CompiledGrammar::from_bytes(&compiled.to_bytes()).unwrap();
+        let tokens = loaded.parser.lexer.lex_utf8(txt).unwrap();
+        let res = loaded.parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.errors.len(), 1);
+
+        // errors recovered inside a node that fails are dropped when the parser backtracks
+        let txt = "let a = b;\nlet = c d;";
+        let mut parser = loaded.parser;
+        parser.grammar.node_mut("file").unwrap().rules = vec![ext::is_one_of([
+            ext::option(node("strict")),
+            ext::option(node("loose")),
+        ])];
+        parser
+            .grammar
+            .new_node("strict")
+            .rules([
+                ext::while_(node("let")).set(local("stmts")),
+                ext::is(token("=")),
+            ])
+            .variables([ext::list_var("stmts")])
+            .build();
+        parser
+            .grammar
+            .new_node("loose")
+            .rules([ext::until(ext::eof())])
+            .build();
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert!(res.errors.is_empty());
+    }
+
+    #[test]
+    fn exclusive_ends() {
+        use crate::api::ext::{self, text, token};
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["+"].into_iter());
+        parser
+            .grammar
+            .new_node("sum")
+            .rules([
+                ext::is(text()).set(local("left")),
+                ext::is(token("+")),
+                ext::is(text()).set(local("right")),
+            ])
+            .variables([ext::node_var("left"), ext::node_var("right")])
+            .build();
+        parser.parser.entry = Some("sum");
+
+        // `str_last_idx` is the index right after the text, for tokens and nodes alike
+        let txt = "ab + cde";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let (left, right) = (
+            res.entry.required_node_unchecked("left"),
+            res.entry.required_node_unchecked("right"),
+        );
+        assert_eq!((left.str_idx(), left.str_last_idx()), (0, 2));
+        assert_eq!(&txt[right.str_idx()..right.str_last_idx()], "cde");
+        assert_eq!(right.str_last_idx(), txt.len());
+        let entry = parser::Nodes::from(res.entry.clone());
+        assert_eq!((entry.str_idx(), entry.str_last_idx()), (0, txt.len()));
+    }
+
+    #[test]
+    fn record_tokens() {
+        use crate::{api::ext, api::AccessError};
+
+        let txt = "let a: int = 5";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([":", "="].into_iter());
+        parser
+            .grammar
+            .new_node("value")
+            .rules([ext::is(text()).set(local("value"))])
+            .variables([ext::node_var("value")])
+            .build();
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set(local("ident")),
+                ext::maybe(token(":")).then([ext::is(text()).set(local("type"))]),
+                ext::maybe(token("=")).then([ext::is(node("value")).set(local("value"))]),
+            ])
+            .variables([
+                ext::node_var("ident"),
+                ext::node_var("type"),
+                ext::node_var("value"),
+            ])
+            .record_tokens()
+            .build();
+        parser.parser.entry = Some("let");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let recorded = res
+            .entry
+            .tokens_unchecked()
+            .iter()
+            .map(|t| t.stringify(txt))
+            .collect::<Vec<_>>();
+        // `5` belongs to the value node
+        assert_eq!(recorded, ["let", "a", ":", "int", "="]);
+        let colon = res
+            .entry
+            .find_token(TokenKinds::Token(":"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(colon.index, 5);
+        let value = res
+            .entry
+            .required_node_unchecked("value")
+            .try_as_node()
+            .unwrap();
+        assert_eq!(
+            value.try_tokens(),
+            Err(AccessError::TokensNotRecorded("value".to_string()))
+        );
+    }
+
+    #[test]
+    fn initial_values() {
+        use crate::{
+            api::ext,
+            compiled::CompiledGrammar,
+            grammar::{validator::Validator, InitialValue},
+        };
+
+        let txt = "a b";
+
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::while_(text()).inc(local("count")),
+                ext::set_bool(local("done"), true),
+                ext::set_number(local("limit"), -1),
+            ])
+            .variables([
+                ext::number_var("count"),
+                ext::number_var("limit"),
+                ext::bool_var("done"),
+                ext::bool_var("strict"),
+            ])
+            .initial([
+                ("count", InitialValue::Number(10)),
+                ("strict", InitialValue::Boolean(true)),
+            ])
+            .build();
+        parser.parser.entry = Some("file");
+        assert!(Validator::default().validate(&parser).pass());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.entry.number_unchecked("count"), 12);
+        assert_eq!(res.entry.number_unchecked("limit"), -1);
+        assert!(res.entry.bool_unchecked("done"));
+        assert!(res.entry.bool_unchecked("strict"));
+
+        let compiled = CompiledGrammar::new(parser, b"");
+        let mut parser = CompiledGrammar::from_bytes(&compiled.to_bytes())
+            .unwrap()
+            .parser;
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert_eq!(
+            parser
+                .parse(&tokens, txt)
+                .unwrap()
+                .entry
+                .number_unchecked("count"),
+            12
+        );
+
+        let file = parser.grammar.node_mut("file").unwrap();
+        file.initial.push(("done", InitialValue::Number(1)));
+        file.rules.push(ext::set_bool(local("count"), false));
+        let errors = Validator::default().validate(&parser).errors;
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().any(|err| matches!(
+            err.kind,
+            grammar::validator::ValidationErrors::InitialValueMismatch(
+                "done",
+                grammar::VariableKind::Number
+            )
+        )));
+
+        // a value of another kind never changes the declared kind of the variable
+        let file = parser.grammar.node_mut("file").unwrap();
+        file.rules.pop();
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert!(res.entry.bool_unchecked("done"));
+        assert!(matches!(
+            CompiledGrammar::from_bytes(&CompiledGrammar::new(parser, b"").to_bytes()),
+            Err(compiled::LoadError::Corrupt(
+                "initial value does not match the variable"
+            ))
+        ));
+    }
+
+    #[test]
+    fn wide_numbers() {
+        use crate::{
+            api::ext,
+            compiled::CompiledGrammar,
+            grammar::{validator::Validator, Comparison, InitialValue},
+        };
+
+        let txt = "a b c";
+
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([
+                ext::while_(text()).inc(local("bytes")).inc(local("count")),
+                ext::set_float(local("half"), 2.5),
+                ext::compare(local("half"), local("count"), Comparison::LessThan)
+                    .then([ext::set_bool(local("below"), true)]),
+                ext::compare(local("bytes"), local("count"), Comparison::GreaterThan)
+                    .then([ext::set_number64(local("big"), i64::MAX)]),
+            ])
+            .variables([
+                ext::number64_var("bytes"),
+                ext::number64_var("big"),
+                ext::number_var("count"),
+                ext::float_var("half"),
+                ext::bool_var("below"),
+            ])
+            .initial([("bytes", InitialValue::Number64(5_000_000_000))])
+            .build();
+        parser.parser.entry = Some("file");
+        assert!(Validator::default().validate(&parser).pass());
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.entry.number64_unchecked("bytes"), 5_000_000_003);
+        assert_eq!(res.entry.number64_unchecked("big"), i64::MAX);
+        assert_eq!(res.entry.float_unchecked("half"), 2.5);
+        assert!(res.entry.bool_unchecked("below"));
+        assert!(res.entry.try_float("count").is_err());
+
+        let compiled = CompiledGrammar::new(parser, b"");
+        let mut parser = CompiledGrammar::from_bytes(&compiled.to_bytes())
+            .unwrap()
+            .parser;
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.entry.number64_unchecked("bytes"), 5_000_000_003);
+        assert!(res.entry.bool_unchecked("below"));
+
+        let file = parser.grammar.node_mut("file").unwrap();
+        file.rules.push(ext::set_float(local("below"), 1.0));
+        file.rules.push(ext::compare(
+            local("half"),
+            local("below"),
+            Comparison::Equal,
+        ));
+        assert_eq!(Validator::default().validate(&parser).errors.len(), 2);
+    }
+
+    #[test]
+    fn node_overwritten_lint() {
+        use crate::{api::ext, grammar::validator::ValidationWarnings};
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([",", ";"].into_iter());
+        parser
+            .grammar
+            .new_node("pair")
+            .rules([
+                ext::is(text()).start().set(local("first")),
+                ext::maybe(token(",")).then([ext::is(text()).set(local("first"))]),
+                ext::is_one_of([
+                    ext::option(token(",")).set(local("single")),
+                    ext::option(token(";")).set(local("single")),
+                ]),
+                ext::while_(token(";")).set(local("last")),
+                ext::is(text()).set(local("list")),
+                ext::while_(text()).set(local("list")),
+            ])
+            .variables([
+                ext::node_var("first"),
+                ext::node_var("single"),
+                ext::node_var("last"),
+                ext::list_var("list"),
+            ])
+            .build();
+
+        let result = Validator::default().validate(&parser);
+        assert!(result.pass());
+        let overwritten = result
+            .warnings
+            .iter()
+            .filter_map(|w| match w.kind {
+                ValidationWarnings::NodeOverwritten(var) => Some(var),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(overwritten, [local("first"), local("last")]);
+        assert_eq!(result.warnings[0].kind.code(), "W0008");
+    }
+
+    #[test]
+    fn max_nesting() {
+        use crate::api::ext;
+
+        let txt = "((( x )))";
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["(", ")"].into_iter());
+        parser
+            .grammar
+            .new_node("value")
+            .rules([ext::is_one_of([
+                ext::option(token("(")).then([ext::is(node("value")), ext::is(token(")"))]),
+                ext::option(text()),
+            ])])
+            .build();
+        parser.parser.entry = Some("value");
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+
+        parser.parser.max_nesting = Some(4);
+        assert!(parser.parse(&tokens, txt).is_ok());
+        parser.parser.max_nesting = Some(3);
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        let parser::ParseErrors::TooDeep { limit, span } = &err.kind else {
+            panic!("expected TooDeep, found {:?}", err.kind);
+        };
+        assert_eq!(*limit, 3);
+        assert_eq!(&txt[span.clone()], "x");
+        assert_eq!(err.kind.code(), "E0214");
+    }
+
+    #[test]
+    fn parse_node_at() {
+        use crate::api::ext;
+
+        let txt = "{ a b c } d";
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["{", "}"].into_iter());
+        parser
+            .grammar
+            .new_node("block")
+            .rules([
+                ext::is(token("{")).start(),
+                ext::until(token("}")),
+                ext::while_(text()),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("words")
+            .rules([ext::while_(text()).set(local("words"))])
+            .variables([ext::list_var("words")])
+            .build();
+        parser.parser.entry = Some("block");
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert!(parser.parse(&tokens, txt).is_ok());
+
+        let open = tokens.iter().position(|t| t.kind == TokenKinds::Token("{"));
+        let close = tokens.iter().position(|t| t.kind == TokenKinds::Token("}"));
+        let region = open.unwrap() + 1..close.unwrap();
+        let res = parser
+            .parse_node_at("words", &tokens, region.clone(), txt)
+            .unwrap();
+        let words = res.entry.list_unchecked("words");
+        assert_eq!(words.len(), 3);
+        let lines = lexer::LineIndex::new(txt);
+        assert_eq!(words[2].location(&lines, lexer::TabPolicy::Char).column, 7);
+
+        // the region has to be parsed entirely
+        let err = parser
+            .parse_node_at("words", &tokens, region.start..tokens.len(), txt)
+            .unwrap_err();
+        let parser::ParseErrors::MissingEof { leftover, .. } = err.kind else {
+            panic!("expected MissingEof, found {:?}", err.kind);
+        };
+        assert_eq!(&txt[leftover], "} d");
+
+        // regions outside of the tokens are errors
+        let reversed = core::ops::Range { start: 3, end: 2 };
+        for range in [0..tokens.len() + 1, reversed] {
+            let err = parser
+                .parse_node_at("words", &tokens, range, txt)
+                .unwrap_err();
+            assert!(matches!(
+                err.kind,
+                parser::ParseErrors::RangeOutOfBounds { .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        use crate::{api::ext, grammar::InitialValue};
+
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(text()).inc(local("words"))])
+            .variables([ext::number_var("words")])
+            .initial([("words", InitialValue::Number(1))])
+            .build();
+        parser.parser.entry = Some("file");
+
+        for txt in ["", "  \n\t"] {
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            let err = parser.parse(&tokens, txt).unwrap_err();
+            let parser::ParseErrors::EmptyInput { span } = &err.kind else {
+                panic!("expected EmptyInput, found {:?}", err.kind);
+            };
+            assert_eq!(*span, txt.len()..txt.len());
+            assert_eq!(err.kind.code(), "E0215");
+        }
+
+        parser.grammar.node_mut("file").unwrap().allow_empty = true;
+        let txt = "  ";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.entry.number_unchecked("words"), 1);
+        assert_eq!(res.entry.first_string_idx, 2);
+        let txt = "a b";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.entry.number_unchecked("words"), 3);
+    }
+
+    #[test]
+    fn cursor_past_end() {
+        use crate::api::ext;
+
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(ext::any()), ext::is(ext::word("end"))])
+            .build();
+        parser.parser.entry = Some("file");
+        parser.parser.eof_error = false;
+
+        let txt = "a b";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert!(matches!(err.kind, parser::ParseErrors::ExpectedWord { .. }));
+
+        parser.grammar.node_mut("file").unwrap().rules = vec![
+            ext::is(ext::word("a")),
+            ext::is(ext::word("b")),
+            ext::is(ext::word("c")),
+        ];
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert!(matches!(err.kind, parser::ParseErrors::ExpectedWord { .. }));
+
+        // trailing whitespace before the end of file and an empty stream
+        parser.grammar.eof = true;
+        parser.grammar.node_mut("file").unwrap().rules = vec![ext::while_(ext::word("a"))];
+        let txt = "a a  ";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.entry.last_string_idx, 3);
+        let err = parser.parse(&[], "").unwrap_err();
+        assert!(matches!(err.kind, parser::ParseErrors::EmptyInput { .. }));
+    }
+
+    #[test]
+    fn node_sync() {
+        use crate::{
+            api::ext,
+            compiled::CompiledGrammar,
+            parser::{Nodes, Recovery},
+        };
+
+        let txt = "fn a { x; }\nfn { y; z; }\nfn b { w; }";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["{", "}", ";"].into_iter());
+        parser
+            .grammar
+            .new_node("fn")
+            .rules([
+                ext::is(word("fn")).commit(),
+                ext::is(text()),
+                ext::is(token("{")),
+                ext::until(token("}")),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("fn")).set(local("items"))])
+            .variables([ext::list_var("items")])
+            .build();
+        parser.parser.entry = Some("file");
+        parser.parser.eof_error = true;
+        parser.parser.recovery = Some(Recovery::new([TokenKinds::Token(";")]));
+
+        // the global set stops inside the block
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert!(parser.parse(&tokens, txt).is_err());
+
+        parser.grammar.node_mut("fn").unwrap().sync = vec![TokenKinds::Token("}")];
+        let res = parser.parse(&tokens, txt).unwrap();
+        let items = res.entry.list_unchecked("items");
+        assert_eq!(items.len(), 3);
+        match &items[1] {
+            Nodes::Error { span, .. } => assert_eq!(&txt[span.clone()], "fn { y; z; }"),
+            _ => panic!("expected an error node"),
+        }
+        assert_eq!(res.errors.len(), 1);
+
+        let compiled = CompiledGrammar::new(parser, b"");
+        let loaded = CompiledGrammar::from_bytes(&compiled.to_bytes()).unwrap();
+        assert_eq!(
+            loaded.parser.grammar.node("fn").unwrap().sync,
+            [TokenKinds::Token("}")]
+        );
+    }
+
+    #[test]
+    fn line_index() {
+        use crate::{
+            api::ext,
+            lexer::{LineIndex, TabPolicy, TextLocation},
+        };
+
+        let txt = "a =\tb;\r\n\tc = é;\n\nd";
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["=", ";"].into_iter());
+        parser.lexer.tabs = TabPolicy::Stop(4);
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+
+        let lines = LineIndex::new(txt);
+        assert_eq!(lines.line_count(), 4);
+        assert_eq!(lines.line_start(2), Some(8));
+        assert_eq!(lines.line_start(5), None);
+        let c = tokens.iter().find(|t| t.stringify(txt) == "c").unwrap();
+        assert_eq!(
+            parser.lexer.locate(&lines, c.index, c.len),
+            TextLocation::new(1, 4, 9, 1)
+        );
+        let b = txt.find('b').unwrap();
+        assert_eq!(parser.lexer.locate(&lines, b, 1).column, 5);
+        assert_eq!(lines.locate(b, 1, TabPolicy::Char).column, 5);
+        assert_eq!(lines.locate(b, 1, TabPolicy::Width(8)).column, 12);
+
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::is(token("="))])
+            .build();
+        parser.parser.entry = Some("file");
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert_eq!(
+            err.locate(&lines, TabPolicy::Char),
+            TextLocation::new(0, 0, 0, 1)
+        );
+    }
+
+    #[test]
+    fn remaining_text() {
+        use crate::{api::ext, diagnostic::explain};
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["="].into_iter());
+        parser
+            .grammar
+            .new_node("assign")
+            .rules([ext::is(text()), ext::is(token("=")), ext::is(text())])
+            .build();
+        parser.parser.entry = Some("assign");
+        parser.grammar.eof = false;
+
+        let txt = "a = b c =  ";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.remaining.as_ref().unwrap().tokens, 2);
+        assert_eq!(&txt[res.remaining_range().unwrap()], "c =");
+        let diagnostics = res.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "W0200");
+        assert!(explain("W0200").is_some());
+
+        let txt = "a = b  ";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.remaining_range(), None);
+        assert!(res.diagnostics().is_empty());
+    }
+
+    #[cfg(feature = "allocator")]
+    #[test]
+    fn lex_in_allocator() {
+        use crate::lexer::{GLOBAL_ALLOCATOR_REQUIRED, OUT_OF_MEMORY};
+        use allocator_api2::alloc::{AllocError, Allocator, Global, Layout};
+        use core::ptr::NonNull;
+
+        /// Allocator without any memory
+        struct Empty;
+        unsafe impl Allocator for Empty {
+            fn allocate(&self, _: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                Err(AllocError)
+            }
+            unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {}
+        }
+
+        let txt = "let a = b;\n/// doc\nlet c";
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["=", ";"].into_iter());
+        parser
+            .lexer
+            .doc_comments
+            .push(lexer::DocComment::Line("///"));
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert_eq!(
+            parser.lexer.lex_utf8_in(txt, Global).unwrap()[..],
+            tokens[..]
+        );
+        assert_eq!(
+            parser.lexer.lex_ascii_in(txt, Global).unwrap()[..],
+            parser.lexer.lex_ascii(txt).unwrap()[..]
+        );
+
+        let err = parser.lexer.lex_utf8_in(txt, Empty).unwrap_err();
+        assert_eq!(err.err.code, OUT_OF_MEMORY.code);
+        assert_eq!(err.span, 0..3);
+
+        // scannerless lexing writes into the allocator too
+        parser.lexer.scannerless = true;
+        assert_eq!(
+            parser.lexer.lex_utf8_in(txt, Global).unwrap()[..],
+            parser.lexer.lex_utf8(txt).unwrap()[..]
+        );
+        let err = parser.lexer.lex_ascii_in(txt, Empty).unwrap_err();
+        assert_eq!(err.err.code, OUT_OF_MEMORY.code);
+
+        // preprocessors hand their tokens back in a global `Vec`
+        parser
+            .lexer
+            .preprocessors
+            .push(|_, tokens| Ok(tokens.to_vec()));
+        let err = parser.lexer.lex_utf8_in(txt, Global).unwrap_err();
+        assert_eq!(err.err.code, GLOBAL_ALLOCATOR_REQUIRED.code);
+    }
+
+    #[cfg(feature = "allocator")]
+    #[test]
+    fn parse_in_allocator() {
+        use crate::{
+            api::ext::{self, node, text, token, word},
+            incremental::TextEdit,
+        };
+        use allocator_api2::alloc::{AllocError, Allocator, Global, Layout};
+        use core::{
+            ptr::NonNull,
+            sync::atomic::{AtomicUsize, Ordering},
+        };
+
+        /// Global allocator that counts the allocations alive
+        struct Counting(AtomicUsize);
+        unsafe impl Allocator for Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                Global.allocate(layout)
+            }
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.fetch_sub(1, Ordering::Relaxed);
+                Global.deallocate(ptr, layout)
+            }
+        }
+        static ALLOC: Counting = Counting(AtomicUsize::new(0));
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";", "=", "+"].into_iter());
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("let")).set("lets")])
+            .variables([ext::list_var("lets")])
+            .build();
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set("name"),
+                ext::is(token("=")),
+                ext::is(text()).set("terms"),
+                ext::while_(token("+")).then([ext::is(text()).set("terms")]),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("name"), ext::list_var("terms")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let txt = "let x = a + b;\nlet y = c;";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse_in(&tokens, txt, &ALLOC).unwrap();
+        let global = parser.parse(&tokens, txt).unwrap();
+        assert!(ALLOC.0.load(Ordering::Relaxed) > 0);
+        assert!(!res.entry.alloc.is_global());
+        let lets = res.entry.list_unchecked("lets");
+        assert_eq!(lets.len(), global.entry.list_unchecked("lets").len());
+        assert!(!lets.allocator().is_global());
+        let first = lets[0].as_node_unchecked();
+        assert!(!first.alloc.is_global());
+        assert_eq!(first.list_unchecked("terms").len(), 2);
+
+        // a reparse keeps the new nodes in the allocator of the old result
+        let edit = TextEdit {
+            span: 23..24,
+            text: "c + d".to_string(),
+        };
+        let new_txt = TextEdit::apply(txt, core::slice::from_ref(&edit));
+        let tokens = parser.lexer.lex_utf8(&new_txt).unwrap();
+        let new = parser.reparse(&res, &edit, &tokens, &new_txt).unwrap();
+        let second = new.entry.list_unchecked("lets")[1].as_node_unchecked();
+        assert!(!second.alloc.is_global());
+        assert_eq!(second.list_unchecked("terms").len(), 2);
+
+        // everything was freed in the allocator it came from
+        drop((res, new));
+        assert_eq!(ALLOC.0.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn lex_into_buffer() {
+        use crate::lexer::{Lexer, Token, BUFFER_FULL, HEAP_REQUIRED};
+
+        let txt = "led = on;\nfan = 3;";
+        let mut lexer = Lexer::new();
+        lexer.add_tokens(["=", ";"].into_iter());
+
+        let mut buf = [Token::EMPTY; 16];
+        let len = lexer.lex_into(txt, &mut buf).unwrap();
+        assert_eq!(buf[..len], lexer.lex_ascii(txt).unwrap()[..]);
+
+        let mut small = [Token::EMPTY; 4];
+        let err = lexer.lex_into(txt, &mut small).unwrap_err();
+        assert_eq!(err.err.code, BUFFER_FULL.code);
+        assert_eq!(err.span, 6..8);
+
+        let mut scannerless = Lexer::new();
+        scannerless.scannerless = true;
+        let err = scannerless
+            .lex_into(txt, &mut [Token::EMPTY; 64])
+            .unwrap_err();
+        assert_eq!(err.err.code, HEAP_REQUIRED.code);
+    }
+
+    #[test]
+    fn compact_errors() {
+        use crate::api::ext;
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["=", ";"].into_iter());
+        parser
+            .grammar
+            .new_node("assign")
+            .rules([
+                ext::is(text()),
+                ext::is(token("=")),
+                ext::is_one_of([ext::option(text()), ext::option(word("null"))]),
+                ext::is(token(";")),
+            ])
+            .build();
+        parser.parser.entry = Some("assign");
+
+        let compact = |txt: &str| {
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            parser
+                .parse(&tokens, txt)
+                .unwrap_err()
+                .compact()
+                .to_string()
+        };
+        assert_eq!(compact("a = b"), "E0201@5 expected ;");
+        assert_eq!(compact("a b"), "E0201@2 expected =");
+        assert_eq!(compact("a = ;"), "E0208@4 expected one of 2");
+    }
+
+    #[test]
+    fn optimize_grammar() {
+        use crate::{api::ext, optimize::Optimized};
+
+        let txt = "let a = b;";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["=", ":", ";"].into_iter());
+        parser.grammar.add_enum(grammar::Enumerator {
+            name: "assign",
+            values: [token("="), token(":"), token("=")].to_vec(),
+        });
+        parser
+            .grammar
+            .new_node("semi")
+            .rules([ext::is(token(";"))])
+            .build();
+        parser
+            .grammar
+            .new_node("name")
+            .rules([ext::is(text())])
+            .build();
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(word("let")),
+                ext::is(node("name")).set(local("name")),
+                ext::is_one_of([
+                    ext::option(token("=")),
+                    ext::option(token("=")).then([ext::is(token(":"))]),
+                    ext::option(enumerator("assign")),
+                ]),
+                ext::is(node("name")),
+                ext::is(node("semi")),
+            ])
+            .variables([ext::node_var("name")])
+            .build();
+        parser.parser.entry = Some("let");
+
+        let optimized = parser.grammar.optimize();
+        assert_eq!(
+            optimized,
+            Optimized {
+                inlined: 2,
+                enumerator_values: 1,
+                alternatives: 1,
+                subtrees: 0,
+            }
+        );
+        assert_eq!(parser.grammar.enumerators["assign"].values.len(), 2);
+        assert_eq!(parser.grammar.nodes.len(), 3);
+        let rules = &parser.grammar.node("let").unwrap().rules;
+        assert!(matches!(
+            rules[1],
+            grammar::Rule::Is {
+                token: grammar::MatchToken::Node(name),
+                ..
+            } if name == "name"
+        ));
+        assert!(matches!(
+            rules[4],
+            grammar::Rule::Is {
+                token: grammar::MatchToken::Token(TokenKinds::Token(";")),
+                ..
+            }
+        ));
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert!(res.entry.node_unchecked("name").is_some());
+        assert_eq!(parser.grammar.optimize().total(), 0);
+
+        // both operators continue with the same operand, kept once after the choice
+        parser
+            .grammar
+            .new_node("sum")
+            .rules([
+                ext::is(text()),
+                ext::is_one_of([
+                    ext::option(token("+")).then([ext::is(text()).set(local("rhs"))]),
+                    ext::option(token("-")).then([ext::is(text()).set(local("rhs"))]),
+                ]),
+            ])
+            .variables([ext::node_var("rhs")])
+            .build();
+        parser.lexer.add_tokens(["+", "-"].into_iter());
+        parser.parser.entry = Some("sum");
+        let optimized = parser.grammar.optimize();
+        assert_eq!(optimized.subtrees, 1);
+        let rules = &parser.grammar.node("sum").unwrap().rules;
+        assert_eq!(rules.len(), 3);
+        let grammar::Rule::IsOneOf { tokens, .. } = &rules[1] else {
+            panic!("expected IsOneOf, got {:?}", rules[1]);
+        };
+        assert!(tokens.iter().all(|one_of| one_of.rules.is_empty()));
+        for txt in ["a + b", "a - b"] {
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            let res = parser.parse(&tokens, txt).unwrap();
+            assert_eq!(
+                res.entry
+                    .node_unchecked("rhs")
+                    .as_ref()
+                    .unwrap()
+                    .stringify(txt),
+                "b"
+            );
+        }
+        assert_eq!(parser.grammar.optimize().total(), 0);
+    }
+
+    #[test]
+    fn left_factoring() {
+        use crate::{api::ext, optimize::CommonPrefix};
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["=", "(", ")", ";"].into_iter());
+        parser
+            .grammar
+            .new_node("assign")
+            .rules([ext::is(text()), ext::is(token("=")), ext::is(text())])
+            .build();
+        parser
+            .grammar
+            .new_node("call")
+            .rules([ext::is(text()), ext::is(token("(")), ext::is(token(")"))])
+            .build();
+        parser
+            .grammar
+            .new_node("stmt")
+            .rules([
+                ext::is_one_of([
+                    ext::option(text()).then([ext::is(token("=")), ext::is(text())]),
+                    ext::option(text()).then([ext::is(token("(")), ext::is(token(")"))]),
+                    ext::option(token(";")),
+                ]),
+                ext::maybe_one_of([ext::option(node("assign")), ext::option(node("call"))]),
+            ])
+            .build();
+        parser.parser.entry = Some("stmt");
+
+        assert_eq!(
+            parser.grammar.common_prefixes(),
+            [
+                CommonPrefix {
+                    node: "stmt",
+                    rule: 0,
+                    alternatives: vec![0, 1],
+                    prefix: vec![text()],
+                    factorable: true,
+                },
+                CommonPrefix {
+                    node: "stmt",
+                    rule: 1,
+                    alternatives: vec![0, 1],
+                    prefix: vec![text()],
+                    factorable: false,
+                },
+            ]
+        );
+
+        let txt = "f()";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        assert!(parser.parse(&tokens, txt).is_err());
+
+        assert_eq!(parser.grammar.left_factor(), 1);
+        assert_eq!(parser.grammar.common_prefixes().len(), 1);
+        for txt in ["f()", "a = b", "; x = y"] {
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            assert!(parser.parse(&tokens, txt).is_ok(), "{txt}");
+        }
+        assert_eq!(parser.grammar.left_factor(), 0);
+    }
+
+    #[test]
+    fn token_data() {
+        use crate::{
+            api::ext,
+            lexer::{PreprocessorError, Token, TokenData, TokenTexts},
+        };
+
+        fn decode<'tok>(
+            text: &str,
+            tokens: &[Token<'tok>],
+        ) -> Result<Vec<Token<'tok>>, PreprocessorError> {
+            Ok(tokens
+                .iter()
+                .map(|token| {
+                    let txt = token.stringify(text);
+                    match txt
+                        .strip_prefix("0x")
+                        .map(|hex| u32::from_str_radix(hex, 16))
+                    {
+                        Some(Ok(value)) => token.with_data(TokenData::Number(value)),
+                        _ => *token,
+                    }
+                })
+                .collect())
+        }
+
+        let txt = "mask = 0x1F;";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["=", ";"].into_iter());
+        parser.lexer.preprocessors.push(decode);
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(text()).set(local("name")),
+                ext::is(token("=")),
+                ext::is(text()).set(local("value")),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("name"), ext::node_var("value")])
+            .build();
+        parser.parser.entry = Some("let");
+
+        let mut tokens = parser.lexer.lex_utf8(txt).unwrap();
+        // texts live in a side table, the token only keeps the handle
+        let mut texts = TokenTexts::new();
+        tokens[0] = tokens[0].with_data(texts.add("MASK"));
+        let res = parser.parse(&tokens, txt).unwrap();
+        let value = res.entry.node_unchecked("value").as_ref().unwrap();
+        assert_eq!(value.token_data(), Some(TokenData::Number(31)));
+        assert_eq!(texts.get(value.token_data().unwrap()), None);
+        let name = res.entry.node_unchecked("name").as_ref().unwrap();
+        assert_eq!(texts.get(name.token_data().unwrap()), Some("MASK"));
+    }
+
+    #[test]
+    fn bracket_pairs() {
+        use crate::{api::ext, compiled::CompiledGrammar};
+
+        let txt = "f({ ] })";
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens(["(", ")", "{", "}", "]"].into_iter());
+        parser
+            .grammar
+            .new_node("call")
+            .rules([
+                ext::is(text()),
+                ext::is(token("(")),
+                ext::is(token("{")),
+                ext::is(token("]")),
+                ext::is(token("}")),
+                ext::is(token(")")),
+            ])
+            .build();
+        parser.parser.entry = Some("call");
+        parser.grammar.brackets = vec![
+            (TokenKinds::Token("("), TokenKinds::Token(")")),
+            (TokenKinds::Token("{"), TokenKinds::Token("}")),
+            (TokenKinds::Token("["), TokenKinds::Token("]")),
+        ];
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.brackets.len(), 2);
+        assert_eq!(res.matching_bracket(1), Some(7..8));
+        assert_eq!(res.matching_bracket(7), Some(1..2));
+        assert_eq!(res.matching_bracket(6), Some(2..3));
+        assert_eq!(res.matching_bracket(4), None);
+        assert_eq!(res.matching_bracket(0), None);
+
+        let compiled = CompiledGrammar::new(parser, b"");
+        let loaded = CompiledGrammar::from_bytes(&compiled.to_bytes()).unwrap();
+        assert_eq!(loaded.parser.grammar.brackets.len(), 3);
+    }
+
+    #[test]
+    fn mismatched_brackets() {
+        use crate::api::ext;
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens(["(", ")", "[", "]", ";"].into_iter());
+        // `[` is not closed before `)`
+        parser
+            .grammar
+            .new_node("group")
+            .rules([
+                ext::is(token("(")),
+                ext::is(token("[")),
+                ext::maybe(node("group")),
+                ext::is(token(")")),
+            ])
+            .build();
+        // tries a closed index first and backtracks to an open one
+        parser
+            .grammar
+            .new_node("index")
+            .rules([ext::is_one_of([
+                ext::option(node("closed")),
+                ext::option(node("open")),
+            ])])
+            .build();
+        parser
+            .grammar
+            .new_node("closed")
+            .rules([
+                ext::is(token("[")),
+                ext::is(token("]")),
+                ext::is(token(";")),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("open")
+            .rules([ext::is(token("[")), ext::is(token("]"))])
+            .build();
+        // the closing bracket belongs to a different node than the opening one
+        parser
+            .grammar
+            .new_node("split")
+            .rules([ext::is(token("(")), ext::is(node("close"))])
+            .build();
+        parser
+            .grammar
+            .new_node("close")
+            .rules([ext::is(token(")"))])
+            .build();
+        parser.grammar.brackets = vec![
+            (TokenKinds::Token("("), TokenKinds::Token(")")),
+            (TokenKinds::Token("["), TokenKinds::Token("]")),
+        ];
+
+        let pairs = |parser: &mut Parser<'static>, entry: &'static str, txt: &'static str| {
+            parser.parser.entry = Some(entry);
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            let res = parser.parse(&tokens, txt).unwrap();
+            res.brackets
+                .iter()
+                .map(|pair| (pair.open.start, pair.close.start))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pairs(&mut parser, "group", "([)"), vec![(0, 2)]);
+        assert_eq!(pairs(&mut parser, "group", "([([))"), vec![(0, 5), (2, 4)]);
+        assert_eq!(pairs(&mut parser, "index", "[]"), vec![(0, 1)]);
+        assert_eq!(pairs(&mut parser, "split", "()"), vec![]);
+    }
+
+    #[test]
+    fn indent_levels() {
+        use crate::api::ext;
+
+        let txt = "a {\n  b;\n  c {\n    d;\n  }\n}\ne;";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["{", "}", ";"].into_iter());
+        parser
+            .grammar
+            .new_node("block")
+            .rules([
+                ext::is(token("{")),
+                ext::while_(node("stmt")).set(local("stmts")),
+                ext::is(token("}")),
+            ])
+            .variables([ext::list_var("stmts")])
+            .indents_children()
+            .build();
+        parser
+            .grammar
+            .new_node("stmt")
+            .rules([
+                ext::is(text()),
+                ext::is_one_of([
+                    ext::option(token(";")),
+                    ext::option(node("block")).set(local("body")),
+                ]),
+            ])
+            .variables([ext::node_var("body")])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("stmt")).set(local("stmts"))])
+            .variables([ext::list_var("stmts")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let levels = (1..=7)
+            .map(|line| res.indent_level_at(txt, line))
+            .collect::<Vec<_>>();
+        assert_eq!(levels, [0, 1, 1, 2, 1, 0, 0]);
+    }
+
+    #[test]
+    fn token_dump() {
+        use crate::lexer::Lexer;
+
+        let txt = "x = abcdefghijklmnopqrstuvwxyz;\n";
+        let mut lexer = Lexer::new();
+        lexer.add_token_tagged("=", "assign");
+        lexer.add_token(";");
+        let tokens = lexer.lex_utf8(txt).unwrap();
+
+        let mut out = String::new();
+        Lexer::dump(&tokens, txt, &mut out).unwrap();
+        let expected = [
+            "#  kind                 span    at    text",
+            "0  Text                 0..1    1:1   \"x\"",
+            "1  Whitespace           1..2    1:2   \" \"",
+            "2  Token(\"=\") [assign]  2..3    1:3   \"=\"",
+            "3  Whitespace           3..4    1:4   \" \"",
+            "4  Text                 4..30   1:5   \"abcdefghijklmnopqrstuvwx\"...",
+            "5  Token(\";\")           30..31  1:31  \";\"",
+            "6  Control(Eol)         31..32  1:32  \"\\n\"",
+            "7  Control(Eof)         32..32  2:1   \"\"",
+        ];
+        assert_eq!(out.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn tree_dump() {
+        use crate::{api::ext, parser::DumpOptions};
+
+        let txt = "let a = 1;\nlet b = 2;";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["=", ";"].into_iter());
+        parser
+            .grammar
+            .new_node("value")
+            .rules([ext::is(text()).set(local("text"))])
+            .variables([ext::node_var("text")])
+            .build();
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set(local("name")),
+                ext::is(token("=")),
+                ext::is(node("value")).set(local("value")),
+                ext::is(token(";")),
+            ])
+            .variables([
+                ext::node_var("name"),
+                ext::node_var("value"),
+                ext::bool_var("mutable"),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("let")).set(local("stmts"))])
+            .variables([ext::list_var("stmts")])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let dump = |options: &DumpOptions| {
+            let mut out = String::new();
+            res.dump_tree(&mut out, options).unwrap();
+            out
+        };
+
+        let full = DumpOptions {
+            include_tokens: true,
+            ..Default::default()
+        };
+        let expected = [
+            "file[0..21]",
+            "  stmts[0]: let[0..10]",
+            "    mutable: false",
+            "    name: <text>[4..5]",
+            "    value: value[8..9]",
+            "      text: <text>[8..9]",
+            "  stmts[1]: let[11..21]",
+        ];
+        assert_eq!(dump(&full).lines().take(7).collect::<Vec<_>>(), expected);
+
+        let shallow = DumpOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            dump(&shallow),
+            "file[0..21]\n  stmts[0]: let[0..10] { ... }\n  stmts[1]: let[11..21] { ... }\n"
+        );
+
+        let filtered = DumpOptions {
+            filter_nodes: &["file", "value"],
+            ..Default::default()
+        };
+        assert_eq!(
+            dump(&filtered),
+            "file[0..21]\n  value: value[8..9]\n  value: value[19..20]\n"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "html")]
+    fn html_tree() {
+        use crate::api::ext;
+
+        let txt = "é = a<b;";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["=", ";"].into_iter());
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(text()).set(local("name")),
+                ext::is(token("=")),
+                ext::is(text()).set(local("value")),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("name"), ext::node_var("value")])
+            .build();
+        parser.parser.entry = Some("let");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let html = res.to_html(txt);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html
+            .contains(r#"<div class="leaf" data-s="0" data-e="1">name: &lt;text&gt;[0..2]</div>"#));
+        assert!(html.contains(r#"data-s="4" data-e="7">value: "#));
+        assert!(html.contains("<pre id=\"text\">é = a&lt;b;</pre>"));
+    }
+
+    #[test]
+    fn node_builder_vars() {
+        use crate::api::ext;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let txt = "let x;";
+
+        let mut parser = Parser::new();
+        parser.lexer.add_token(";");
+        let let_node = parser
+            .grammar
+            .new_node("KWLet")
+            .docs("let ident;")
+            .var("ident", VariableKind::Node)
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set(local("ident")),
+                ext::is(token(";")),
+            ])
+            .build();
+        assert!(matches!(let_node, grammar::MatchToken::Node(name) if name == "KWLet"));
+        parser.parser.entry = Some("KWLet");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert!(res.entry.node_unchecked("ident").is_some());
+
+        let duplicate = catch_unwind(AssertUnwindSafe(|| {
+            parser
+                .grammar
+                .new_node("twice")
+                .var("ident", VariableKind::Node)
+                .has(text(), "ident")
+                .build()
+        }));
+        assert!(duplicate.is_err());
+        assert!(parser.grammar.node("twice").is_none());
+    }
+
+    #[test]
+    fn interned_names() {
+        use crate::api::ext;
+        use crate::intern::Symbol;
+
+        let name = Symbol::intern("interned_names");
+        assert_eq!(name, Symbol::intern("interned_names"));
+        assert_eq!(Symbol::get("interned_names"), Some(name));
+        assert_eq!(name.as_str(), "interned_names");
+        assert!(Symbol::get("interned_names but never interned").is_none());
+        assert!(Symbol::intern("a") < Symbol::intern("b"));
+        assert_eq!(
+            format!("{:?} {}", name, name),
+            "\"interned_names\" interned_names"
+        );
+
+        let txt = "let x;";
+        let mut parser = Parser::new();
+        parser.lexer.add_token(String::from(";"));
+        let semicolon = Symbol::get(";").unwrap().as_str();
+        assert!(core::ptr::eq(parser.lexer.get_tokens()[0], semicolon));
+        parser
+            .grammar
+            .new_node("interned_names")
+            .var("ident", VariableKind::Node)
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set(local("ident")),
+                ext::is(token(";")),
+            ])
+            .build();
+        parser.parser.entry = Some("interned_names");
+
+        let grammar_node = parser.grammar.node_of(name).unwrap();
+        assert_eq!(grammar_node.name, name);
+        assert_eq!(grammar_node.variables[0].0, "ident");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.entry.name, name);
+        let ident = Symbol::get("ident").unwrap();
+        assert!(res.entry.variables.get_of(ident).is_some());
+        assert_eq!(res.entry.variables.symbols().collect::<Vec<_>>(), [ident]);
+        assert!(res.entry.variables.get("ident").is_some());
+        assert!(res.entry.variables.get("missing").is_none());
+    }
+
+    #[test]
+    fn enumerator_builder() {
+        use crate::api::ext;
+
+        let txt = "let a = b * c";
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens("=+-*/".split("").filter(|s| !s.is_empty()));
+        let operators = parser
+            .grammar
+            .new_enum("operators")
+            .tokens("+ - * /".split(' '))
+            .build();
+        parser
+            .grammar
+            .add_enum(ext::enum_of("declarations", ext::words(["let", "const"])));
+        assert_eq!(parser.grammar.enumerators["operators"].values.len(), 4);
+        parser
+            .grammar
+            .new_node("decl")
+            .rules([
+                ext::is(enumerator("declarations")),
+                ext::is(text()),
+                ext::is(token("=")),
+                ext::is(text()),
+                ext::is(operators),
+                ext::is(text()),
+            ])
+            .build();
+        parser.parser.entry = Some("decl");
+
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        parser.parse(&tokens, txt).unwrap();
+    }
+
+    #[test]
+    fn grammar_macro() {
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([":", "=", ";", ","].into_iter());
+        grammar! { parser.grammar;
+            KWLet: "let" <ident:text> (":" <type:text>)? ("=" <value:value>)? ";";
+            value: <text:text> ("," text)*;
+        }
+        parser.parser.entry = Some("KWLet");
+
+        for (txt, vars) in [
+            ("let x: int = 5, 6;", ["x", "int", "5, 6"]),
+            ("let y;", ["y", "", ""]),
+            ("let z = 1;", ["z", "", "1"]),
+        ] {
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            let res = parser.parse(&tokens, txt).unwrap();
+            let text = |var: &str| match res.entry.node_unchecked(var) {
+                Some(node) => parser::ParseResult::stringify_node(node, txt),
+                None => "",
+            };
+            assert_eq!([text("ident"), text("type"), text("value")], vars);
+        }
+        let tokens = parser.lexer.lex_utf8("let = 1;").unwrap();
+        assert!(parser.parse(&tokens, "let = 1;").is_err());
+    }
+
+    #[test]
+    fn static_lexer() {
+        use lexer::{Lexer, TokenTable};
+
+        static TOKENS: TokenTable<4> =
+            TokenTable::tagged(["==", ";", "=", "=>"], [None, None, None, Some("arrow")]);
+        static LEXER: Lexer = Lexer::with_table(&TOKENS);
+
+        let txt = "a == b => c = d;";
+        let kinds = |lexer: &Lexer| {
+            lexer
+                .lex_utf8(txt)
+                .unwrap()
+                .iter()
+                .filter_map(|t| match t.kind {
+                    TokenKinds::Token(kind) => Some((kind.to_string(), t.tag)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = [
+            ("==", None),
+            ("=>", Some("arrow")),
+            ("=", None),
+            (";", None),
+        ]
+        .map(|(kind, tag)| (kind.to_string(), tag));
+        assert_eq!(kinds(&LEXER), expected);
+        assert_eq!(LEXER.get_tokens(), [";", "=", "==", "=>"]);
+
+        // the table is copied when tokens are added
+        let mut lexer = LEXER.clone();
+        lexer.add_token("===");
+        assert_eq!(lexer.get_tokens().len(), 5);
+        assert_eq!(LEXER.get_tokens().len(), 4);
+        assert_eq!(kinds(&lexer), expected);
+    }
+
+    #[test]
+    fn parse_state() {
+        use crate::api::ext;
+
+        let mut parser = Parser::new();
+        parser.lexer.add_token(",");
+        parser.grammar.globals.push(("count", VariableKind::Number));
+        parser
+            .grammar
+            .new_node("list")
+            .rules([ext::is(ext::text()).inc(ext::global("count"))])
+            .rules([ext::repeated([
+                ext::is(ext::token(",")),
+                ext::is(ext::text()).inc(ext::global("count")),
+            ])])
+            .build();
+        parser.parser.entry = Some("list");
+
+        let txt = "a, b, c";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let result = parser.parse(&tokens, txt).unwrap();
+        assert_eq!(result.globals.number("count"), Some(3));
+
+        let mut state = parser::ParseState::new();
+        state.set_number("count", 10);
+        state.set_bool("host", true);
+        let result = parser.parse_with_state(&tokens, txt, state).unwrap();
+        assert_eq!(result.globals.number("count"), Some(13));
+        assert_eq!(result.globals.boolean("host"), Some(true));
+
+        let mut state = parser::ParseState::new();
+        state.set_float("count", 1.0);
+        let err = parser.parse_with_state(&tokens, txt, state).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            parser::ParseErrors::CannotSetVariable(grammar::VarKind::Global("count"), _)
+        ));
+    }
+
+    #[test]
+    fn external_predicates() {
+        use crate::api::ext;
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";", "*"].into_iter());
+        parser
+            .grammar
+            .new_node("stmt")
+            .rules([
+                ext::external("is_type", []).then([ext::is(ext::text()).set("type")]),
+                ext::external("flag", ["pointers"]).then([ext::maybe(ext::token("*"))]),
+                ext::is(ext::text()).set("name"),
+                ext::is(ext::token(";")),
+            ])
+            .variables([ext::node_var("type"), ext::node_var("name")])
+            .build();
+        parser.parser.entry = Some("stmt");
+
+        let valid = Validator::default().validate(&parser);
+        let missing = valid
+            .errors
+            .iter()
+            .map(|e| e.kind.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            missing,
+            [
+                "Predicate \"is_type\" is not registered",
+                "Predicate \"flag\" is not registered"
+            ]
+        );
+
+        parser.parser.register_predicate(
+            "is_type",
+            |txt: &str, _: &parser::ParseState, _: &[&str]| ["T", "size_t"].contains(&txt),
+        );
+        parser.parser.register_predicate(
+            "flag",
+            |_: &str, state: &parser::ParseState, args: &[&str]| {
+                state.boolean(args[0]) == Some(true)
+            },
+        );
+        assert!(Validator::default().validate(&parser).errors.is_empty());
+
+        for (txt, pointers, vars) in [
+            ("T x;", false, ["T", "x"]),
+            ("x;", false, ["", "x"]),
+            ("size_t *x;", true, ["size_t", "x"]),
+        ] {
+            let mut state = parser::ParseState::new();
+            state.set_bool("pointers", pointers);
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            let res = parser.parse_with_state(&tokens, txt, state).unwrap();
+            let text = |var: &str| match res.entry.node_unchecked(var) {
+                Some(node) => parser::ParseResult::stringify_node(node, txt),
+                None => "",
+            };
+            assert_eq!([text("type"), text("name")], vars);
+        }
+        // `U` is not a type, so it is taken as the name and `x` is unexpected
+        let tokens = parser.lexer.lex_utf8("U x;").unwrap();
+        assert!(parser.parse(&tokens, "U x;").is_err());
+    }
+
+    #[test]
+    fn scopes() {
+        use crate::api::ext::{self, node, text, token, word};
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";", ":", "{", "}"].into_iter());
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("stmt"))])
+            .build();
+        parser
+            .grammar
+            .new_node("stmt")
+            .rules([ext::is_one_of([
+                ext::option(node("typed")),
+                ext::option(node("decl")),
+                ext::option(node("block")),
+                ext::option(node("use")),
+            ])])
+            .build();
+        // declares the name before it fails on `let x;`, the declaration is dropped with the node
+        parser
+            .grammar
+            .new_node("typed")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set("name").declare("name"),
+                ext::is(token(":")),
+                ext::is(text()),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("name")])
+            .build();
+        parser
+            .grammar
+            .new_node("decl")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set("name").declare("name"),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("name")])
+            .build();
+        parser
+            .grammar
+            .new_node("block")
+            .rules([
+                ext::is(token("{")).enter_scope(),
+                ext::while_(node("stmt")),
+                ext::is(token("}")).exit_scope(),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("use")
+            .rules([ext::is(text()), ext::is(token(";"))])
+            .build();
+        parser.parser.entry = Some("file");
+        assert!(Validator::default().validate(&parser).errors.is_empty());
+
+        let txt = "let a; { let b: int; a; { let a; a; } b; } let a; b;";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let scopes = res.scopes();
+        let names = scopes
+            .declarations()
+            .iter()
+            .map(|d| (d.name.as_str(), d.scope))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [("a", 0), ("b", 1), ("a", 2), ("a", 0)]);
+        assert_eq!(scopes.scopes()[2].parent, Some(1));
+        assert_eq!(scopes.scopes()[1].end, Some(txt.find("} let").unwrap() + 1));
+
+        let at = |pat: &str| txt.find(pat).unwrap();
+        assert_eq!(scopes.lookup("a", at("a; {")).unwrap().span.start, 4);
+        assert_eq!(
+            scopes.lookup("a", at("a; }")).unwrap().span,
+            at("a; a;")..at("a; a;") + 1
+        );
+        assert_eq!(scopes.lookup("b", at("b; }")).unwrap().scope, 1);
+        assert!(scopes.lookup("b", txt.len() - 2).is_none());
+
+        let duplicates = scopes.duplicates();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].0.span.start, 4);
+        assert_eq!(duplicates[0].1.span.start, at("a; b;"));
+
+        // the table survives the compiled format
+        let compiled = compiled::CompiledGrammar::new(parser, b"");
+        let loaded = compiled::CompiledGrammar::from_bytes(&compiled.to_bytes()).unwrap();
+        let tokens = loaded.parser.lexer.lex_utf8(txt).unwrap();
+        let res = loaded.parser.parse(&tokens, txt).unwrap();
+        assert_eq!(res.scopes().declarations().len(), 4);
+    }
+
+    #[test]
+    fn definitions() {
+        use crate::api::ext::{self, node, text, token, word};
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";", "{", "}"].into_iter());
+        parser
+            .grammar
+            .new_node("stmt")
+            .rules([ext::is_one_of([
+                ext::option(node("decl")),
+                ext::option(node("block")),
+                ext::option(node("use")),
+            ])])
+            .build();
+        parser
+            .grammar
+            .new_node("decl")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set("name").declare("name"),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("name")])
+            .build();
+        parser
+            .grammar
+            .new_node("block")
+            .rules([
+                ext::is(token("{")).enter_scope(),
+                ext::while_(node("stmt")),
+                ext::is(token("}")).exit_scope(),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("use")
+            .rules([
+                ext::is(text()).set("name").use_name("name"),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("name")])
+            .build();
+        parser.parser.entry = Some("block");
+        assert!(Validator::default().validate(&parser).errors.is_empty());
+
+        let txt = "{ let x; x; { let x; x; } x; y; }";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let uses = |declaration: &scopes::Declaration| {
+            res.references(declaration)
+                .iter()
+                .map(|reference| reference.span.start)
+                .collect::<Vec<_>>()
+        };
+
+        let outer = res.definition_at(6).unwrap();
+        assert_eq!(outer.span, 6..7);
+        assert_eq!(uses(outer), [9, 26]);
+        // a use resolves to the innermost declaration in front of it
+        let inner = res.definition_at(21).unwrap();
+        assert_eq!(inner.span, 18..19);
+        assert_eq!(uses(inner), [21]);
+        assert_eq!(res.definition_at(26), Some(outer));
+        assert_eq!(res.definition_at(29), None);
+        assert_eq!(res.definition_at(3), None);
+
+        let unresolved = res.scopes().unresolved();
+        assert_eq!(unresolved.len(), 1);
+        assert_eq!(unresolved[0].name, "y");
+
+        let edits = res.rename(inner, "z");
+        assert_eq!(edits.len(), 2);
+        assert_eq!(
+            incremental::TextEdit::apply(txt, &edits),
+            "{ let x; x; { let z; z; } x; y; }"
+        );
+        let edits = res.rename(outer, "value");
+        assert_eq!(
+            incremental::TextEdit::apply(txt, &edits),
+            "{ let value; value; { let x; x; } value; y; }"
+        );
+        assert!(res.rename(outer, "x").is_empty());
+
+        // overlapping edits are skipped
+        let edit = |span: core::ops::Range<usize>, text: &str| incremental::TextEdit {
+            span,
+            text: text.to_string(),
+        };
+        assert_eq!(
+            incremental::TextEdit::apply(
+                "abcdef",
+                &[edit(3..5, "X"), edit(1..4, "Y"), edit(5..6, "Z")]
+            ),
+            "aYeZ"
+        );
+    }
+
+    #[test]
+    fn completions() {
+        use crate::api::ext::{self, node, text, token, word};
+        use parser::CompletionKind;
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";", "="].into_iter());
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("stmt"))])
+            .build();
+        parser
+            .grammar
+            .new_node("stmt")
+            .rules([ext::is_one_of([
+                ext::option(node("let")),
+                ext::option(node("return")),
+            ])])
+            .build();
+        parser
+            .grammar
+            .new_node("let")
+            .snippet("let ${1:name} = ${2:value};")
+            .docs("Declares a variable")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()),
+                ext::is(token("=")),
+                ext::is(text()),
+                ext::is(token(";")),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("return")
+            .rules([
+                ext::is(word("return")),
+                ext::is(text()),
+                ext::is(token(";")),
+            ])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let complete = |txt: &'static str, offset: usize| {
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            parser
+                .completions(&tokens, txt, offset)
+                .into_iter()
+                .map(|c| (c.label, c.kind, c.replace))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            complete("", 0),
+            [
+                ("let", CompletionKind::Snippet, 0..0),
+                ("let", CompletionKind::Keyword, 0..0),
+                ("return", CompletionKind::Keyword, 0..0),
+            ]
+        );
+        let txt = "let x = 1;\nre";
+        assert_eq!(
+            complete(txt, txt.len()),
+            [
+                ("let", CompletionKind::Snippet, 11..13),
+                ("let", CompletionKind::Keyword, 11..13),
+                ("return", CompletionKind::Keyword, 11..13),
+            ]
+        );
+        assert_eq!(complete("let x ", 6), [("=", CompletionKind::Token, 6..6)]);
+        // the offset is right after `;`, which is not being typed
+        assert_eq!(complete("let x = 1;", 10).len(), 3);
+
+        let tokens = parser.lexer.lex_utf8("").unwrap();
+        let snippet = &parser.completions(&tokens, "", 0)[0];
+        assert_eq!(snippet.snippet, Some("let ${1:name} = ${2:value};"));
+        assert_eq!(snippet.docs, Some("Declares a variable"));
+    }
+
+    #[test]
+    fn edit_node() {
+        use crate::api::ext::{self, node, text, token, word};
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";", "=", "+"].into_iter());
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set("name"),
+                ext::is(token("=")),
+                ext::is(node("sum")).set("value"),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("name"), ext::node_var("value")])
+            .build();
+        parser
+            .grammar
+            .new_node("sum")
+            .rules([
+                ext::is(text()).set("terms"),
+                ext::while_(token("+")).then([ext::is(text()).set("terms")]),
+            ])
+            .variables([ext::list_var("terms")])
+            .build();
+        parser.parser.entry = Some("let");
+
+        let txt = "let x = a + b;";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let res = parser.parse(&tokens, txt).unwrap();
+        let value = res.entry.required_node_unchecked("value");
+        let (new, edit) = parser::ParseResult::edit_node(value, txt, "c + d + e");
+        assert_eq!(new, "let x = c + d + e;");
+        assert_eq!(edit.span, 8..13);
+        assert_eq!(
+            incremental::TextEdit::apply(txt, core::slice::from_ref(&edit)),
+            new
+        );
+
+        let tokens = parser.lexer.lex_utf8(&new).unwrap();
+        let sum = parser.reparse_edit("sum", &tokens, &edit, &new).unwrap();
+        assert_eq!(sum.entry.list_unchecked("terms").len(), 3);
+
+        let (new, edit) = parser::ParseResult::edit_node(value, txt, "c +");
+        let tokens = parser.lexer.lex_utf8(&new).unwrap();
+        assert!(parser.reparse_edit("sum", &tokens, &edit, &new).is_err());
+    }
+
+    #[test]
+    fn token_stats() {
+        let mut lexer = lexer::Lexer::new();
+        lexer.add_tokens(["=", ";", "=="].into_iter());
+        let txt = "a = b;\nc == dd;\n";
+        let tokens = lexer.lex_utf8(txt).unwrap();
+        let stats = lexer::Lexer::stats(&tokens);
+
+        assert_eq!(stats.count(TokenKinds::Text), 4);
+        assert_eq!(stats.count(TokenKinds::Token(";")), 2);
+        assert_eq!(stats.count(TokenKinds::Token("==")), 1);
+        assert_eq!(stats.by_count()[0], (TokenKinds::Text, 4));
+        assert_eq!(stats.tokens, tokens.len());
+        assert_eq!(stats.lines, 3);
+        let longest = stats.longest.as_ref().unwrap();
+        assert_eq!(&txt[longest.index..longest.index + longest.len], "==");
+        assert_eq!(stats.lengths, [1, 12, 2]);
+
+        let mut corpus = stats.clone();
+        corpus.add(&tokens);
+        assert_eq!(corpus.count(TokenKinds::Text), 8);
+        assert_eq!(corpus.lines, 6);
+    }
+
+    #[test]
+    fn grammar_overlay() {
+        use crate::api::ext::{self, node, text, token, word};
+        use std::sync::Arc;
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";", "="].into_iter());
+        parser
+            .grammar
+            .new_node("stmt")
+            .rules([ext::is_one_of([
+                ext::option(node("let")),
+                ext::option(node("print")),
+            ])])
+            .build();
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()),
+                ext::is(token("=")),
+                ext::is(text()),
+                ext::is(token(";")),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("print")
+            .rules([ext::is(word("print")), ext::is(text()), ext::is(token(";"))])
+            .build();
+        parser.parser.entry = Some("stmt");
+        let base = Arc::new(core::mem::take(&mut parser.grammar));
+
+        let mut dialect = base.overlay();
+        // replaced node
+        dialect
+            .new_node("print")
+            .rules([ext::is(word("echo")), ext::is(text()), ext::is(token(";"))])
+            .build();
+        // extended node
+        dialect
+            .new_node("const")
+            .rules([
+                ext::is(word("const")),
+                ext::is(text()),
+                ext::is(token("=")),
+                ext::is(text()),
+                ext::is(token(";")),
+            ])
+            .build();
+        match &mut dialect.node_mut("stmt").unwrap().rules[0] {
+            grammar::Rule::IsOneOf { tokens, .. } => tokens.push(ext::option(node("const"))),
+            _ => unreachable!(),
+        }
+        assert_eq!(dialect.nodes.len(), 3);
+        assert_eq!(dialect.all_nodes().len(), 4);
+        assert!(dialect.node("let").is_some());
+
+        let dialect = Parser {
+            lexer: parser.lexer.clone(),
+            grammar: dialect,
+            parser: parser.parser.clone(),
+        };
+        parser.grammar = base.overlay();
+        assert!(Validator::default().validate(&dialect).errors.is_empty());
+        assert!(Validator::default().validate(&parser).errors.is_empty());
+
+        for (txt, in_base, in_dialect) in [
+            ("let x = 1;", true, true),
+            ("print x;", true, false),
+            ("echo x;", false, true),
+            ("const x = 1;", false, true),
+        ] {
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            assert_eq!(parser.parse(&tokens, txt).is_ok(), in_base, "{txt}");
+            assert_eq!(dialect.parse(&tokens, txt).is_ok(), in_dialect, "{txt}");
+        }
+        // the base is shared and unchanged
+        assert_eq!(Arc::strong_count(&base), 3);
+        assert_eq!(base.nodes.len(), 3);
+
+        // compiled overlays carry the nodes of their base
+        let compiled = compiled::CompiledGrammar::new(dialect, b"");
+        let loaded = compiled::CompiledGrammar::from_bytes(&compiled.to_bytes()).unwrap();
+        assert!(loaded.parser.grammar.base.is_none());
+        assert_eq!(loaded.parser.grammar.nodes.len(), 4);
+
+        // nodes of the base are seen through the overlay
+        let mut overlay = base.overlay();
+        overlay
+            .new_node("let_mut")
+            .rules([ext::is(word("let")), ext::is(word("mut")), ext::is(text())])
+            .build();
+        match &mut overlay.node_mut("stmt").unwrap().rules[0] {
+            grammar::Rule::IsOneOf { tokens, .. } => tokens.push(ext::option(node("let_mut"))),
+            _ => unreachable!(),
+        }
+        let prefixes = overlay.common_prefixes();
+        assert_eq!(prefixes.len(), 1);
+        assert_eq!(prefixes[0].prefix, [word("let")]);
+
+        let mut base = grammar::Grammar::default();
+        base.new_node("file")
+            .rules([ext::while_(text())])
+            .allow_empty()
+            .build();
+        let mut empty = Parser {
+            lexer: parser.lexer.clone(),
+            grammar: Arc::new(base).overlay(),
+            parser: parser.parser.clone(),
+        };
+        empty.parser.entry = Some("file");
+        assert!(empty.parse(&[], "").is_ok());
+    }
+
+    #[test]
+    fn rule_origin() {
+        use crate::api::ext::{self, node, token};
+        use crate::compiled::CompiledGrammar;
+        use grammar::Origin;
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";"].into_iter());
+        let (missing_token, token_line) = (ext::is(token("?")), line!());
+        let (missing_node, node_line) = (ext::is(node("missing")), line!());
+        assert!(matches!(
+            missing_token.origin(),
+            Origin::Code { file: "src/lib.rs", line, .. } if line == token_line
+        ));
+        parser
+            .grammar
+            .new_node("file")
+            .rules([missing_token.clone(), ext::is(token(";"))])
+            .build();
+        parser
+            .grammar
+            .new_node("entry")
+            .rules([ext::is(token(";")), missing_node])
+            .build();
+        parser.parser.entry = Some("entry");
+
+        let result = Validator::default().validate(&parser);
+        let error = result
+            .errors
+            .iter()
+            .find(|error| error.node.is_some_and(|node| node.name == "file"))
+            .unwrap();
+        assert_eq!(error.origin, Some(missing_token.origin()));
+        let owned = grammar::validator::ValidationErrorOwned::from(error);
+        assert!(owned
+            .to_string()
+            .contains(&format!("(rule at src/lib.rs:{token_line}:")));
+
+        let txt = "; ;";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert!(err.kind.is_developer_error());
+        assert!(matches!(
+            err.origin,
+            Some(Origin::Code { line, .. }) if line == node_line
+        ));
+
+        let compiled = CompiledGrammar::new(parser, b"");
+        let loaded = CompiledGrammar::from_bytes(&compiled.to_bytes()).unwrap();
+        assert_eq!(
+            loaded.parser.grammar.node("file").unwrap().rules[0].origin(),
+            missing_token.origin()
+        );
+    }
+
+    #[test]
+    fn developer_error_messages() {
+        use crate::api::ext::{self, enumerator, local, node, token};
+        use grammar::{validator::ValidationErrors, VarKind};
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";"].into_iter());
+        parser
+            .grammar
+            .new_node("entry")
+            .rules([
+                ext::is(token(";")).set(local("value")),
+                ext::is(node("missing")),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("other")
+            .rules([ext::is(enumerator("keywords"))])
+            .build();
+        parser.parser.entry = Some("entry");
+
+        let messages = Validator::default()
+            .validate(&parser)
+            .errors
+            .iter()
+            .map(|error| error.kind.to_string())
+            .collect::<Vec<_>>();
+        assert!(messages.contains(&"Variable local value not found".to_string()));
+        assert!(messages.contains(&"Node \"missing\" not found".to_string()));
+
+        let txt = ";";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert_eq!(format!("{:?}", err.kind), "Variable not found: local value");
+
+        assert_eq!(
+            format!("{:?}", parser::ParseErrors::NodeNotFound("missing")),
+            "Node not found: missing"
+        );
+        assert_eq!(
+            format!("{:?}", parser::ParseErrors::EnumeratorNotFound("keywords")),
+            "Enumerator not found: keywords"
+        );
+        assert_eq!(
+            ValidationErrors::CantUseVariable(VarKind::Global("count")).to_string(),
+            "Can not use variable global count in that way"
+        );
+    }
+
+    #[test]
+    fn lexer_region() {
+        use crate::lexer::LexerState;
+
+        let mut lexer = lexer::Lexer::new();
+        lexer.add_tokens([">>", ">", "<", ";"].into_iter());
+        let txt = "a<b<c>>\n\td >> e;";
+        let tokens = lexer.lex_utf8(txt).unwrap();
+        // lexing again from any token gives the same tokens
+        for (i, token) in tokens.iter().enumerate() {
+            let (region, state) = lexer
+                .lex_region(txt, LexerState::before(token), txt.len())
+                .unwrap();
+            assert_eq!(region, tokens[i..tokens.len() - 1]);
+            assert_eq!(state.index, txt.len());
+        }
+
+        // speculative lexing of the first `>>` as two `>`
+        let mut generics = lexer::Lexer::new();
+        generics.add_tokens([">", "<", ";"].into_iter());
+        let shift = tokens
+            .iter()
+            .position(|t| t.kind == TokenKinds::Token(">>"))
+            .unwrap();
+        let saved = LexerState::before(&tokens[shift]);
+        let end = tokens[shift].index + tokens[shift].len;
+        let (pieces, after) = generics.lex_region(txt, saved, end).unwrap();
+        assert_eq!(pieces.len(), 2);
+        assert!(pieces.iter().all(|p| p.kind == TokenKinds::Token(">")));
+        assert_eq!(pieces[1].index, tokens[shift].index + 1);
+        assert!(pieces[0].touches(&pieces[1]));
+        // the rest of the text continues from the state after the pieces
+        let (rest, _) = lexer.lex_region(txt, after, txt.len()).unwrap();
+        assert_eq!(rest, tokens[shift + 1..tokens.len() - 1]);
+        // rolling back lexes the original token again
+        let (again, _) = lexer.lex_region(txt, saved, end).unwrap();
+        assert_eq!(again, tokens[shift..shift + 1]);
+        // regions that are reversed, too long or split a character are errors
+        let state = LexerState::before(&tokens[2]);
+        for end in [state.index - 1, txt.len() + 1] {
+            let err = lexer.lex_region(txt, state, end).unwrap_err();
+            assert_eq!(err.err.code, lexer::INVALID_REGION.code);
+        }
+        assert!(lexer.lex_region("é", LexerState::new(), 1).is_err());
+
+        lexer.scannerless = true;
+        let chars = lexer.lex_utf8(txt).unwrap();
+        let (region, _) = lexer
+            .lex_region(txt, LexerState::before(&chars[8]), txt.len())
+            .unwrap();
+        assert_eq!(region, chars[8..chars.len() - 1]);
+    }
+
+    #[test]
+    fn parse_events() {
+        use crate::api::ext::{self, local, node, text, token};
+        use parser::Event;
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["=", ";"].into_iter());
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("assign")).set(local("assignments"))])
+            .var("assignments", grammar::VariableKind::NodeList)
+            .build();
+        parser
+            .grammar
+            .new_node("assign")
+            .rules([
+                ext::is(text()).set(local("name")),
+                ext::is(token("=")),
+                ext::is_one_of([ext::option(node("call")), ext::option(text())]),
+                ext::is(token(";")),
+            ])
+            .var("name", grammar::VariableKind::Node)
+            .build();
+        parser
+            .grammar
+            .new_node("call")
+            .rules([ext::is(text()), ext::is(token("(")), ext::is(token(")"))])
+            .build();
+        parser.parser.entry = Some("file");
+
+        let txt = "a = b; c = d;";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let mut events = Vec::new();
+        parser
+            .parse_events(&tokens, txt, &mut |event| events.push(event))
+            .unwrap();
+
+        // the failed `call` alternatives leave no events behind
+        assert!(!events.iter().any(|event| matches!(
+            event,
+            Event::NodeStart { name: "call", .. } | Event::NodeEnd { name: "call", .. }
+        )));
+        let nodes = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::NodeEnd { name, span } => Some((*name, &txt[span.clone()])),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            nodes,
+            [
+                ("assign", "a = b;"),
+                ("assign", "c = d;"),
+                ("file", "a = b; c = d;")
+            ]
+        );
+        assert_eq!(
+            events[0],
+            Event::NodeStart {
+                name: "file",
+                start: 0
+            }
+        );
+        assert_eq!(
+            events[1],
+            Event::NodeStart {
+                name: "assign",
+                start: 0
+            }
+        );
+        assert!(matches!(&events[2], Event::TokenMatched(token) if token.stringify(txt) == "a"));
+        assert_eq!(
+            events[3],
+            Event::VariableSet {
+                variable: grammar::VarKind::Local("name"),
+                span: 0..1
+            }
+        );
+        let matched = events
+            .iter()
+            .filter(|event| matches!(event, Event::TokenMatched(_)))
+            .count();
+        assert_eq!(matched, 8);
+        // the whole assignment is stored in the list of the file
+        assert!(events.contains(&Event::VariableSet {
+            variable: grammar::VarKind::Local("assignments"),
+            span: 7..13
+        }));
+
+        // events are delivered once no node can take them back, before the parse fails
+        let txt = "a = b; c = ;";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let mut events = Vec::new();
+        assert!(parser
+            .parse_events(&tokens, txt, &mut |event| events.push(event))
+            .is_err());
+        let words = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::TokenMatched(token) => Some(token.stringify(txt)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(words, ["a", "=", "b", ";"]);
+
+        // a node that committed can not backtrack, its events so far are delivered
+        parser.grammar.node_mut("assign").unwrap().rules[1] = ext::is(token("=")).commit();
+        let mut events = Vec::new();
+        assert!(parser
+            .parse_events(&tokens, txt, &mut |event| events.push(event))
+            .is_err());
+        let words = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::TokenMatched(token) => Some(token.stringify(txt)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(words, ["a", "=", "b", ";", "c", "="]);
+    }
+
+    #[test]
+    fn materialize() {
+        use crate::api::ext::{self, local, node, text, token, word};
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens(["{", "}", ";"].into_iter());
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("stmt")).set(local("stmts"))])
+            .var("stmts", grammar::VariableKind::NodeList)
+            .build();
+        parser
+            .grammar
+            .new_node("stmt")
+            .rules([ext::is_one_of([
+                ext::option(node("let")).set(local("inner")),
+                ext::option(node("block")).set(local("inner")),
+            ])])
+            .var("inner", grammar::VariableKind::Node)
+            .build();
+        parser
+            .grammar
+            .new_node("block")
+            .rules([
+                ext::is(token("{")),
+                ext::while_(node("stmt")).set(local("stmts")),
+                ext::is(token("}")),
+            ])
+            .var("stmts", grammar::VariableKind::NodeList)
+            .build();
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set(local("name")),
+                ext::is(token(";")),
+            ])
+            .var("name", grammar::VariableKind::Node)
+            .build();
+        parser.parser.entry = Some("file");
+        parser.parser.materialize = Some(vec!["let"]);
+
+        let txt = "let a; { let b; { } } { }";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let result = parser.parse(&tokens, txt).unwrap();
+
+        // the last block holds no `let`, so it is dropped with its statement
+        let stmts = result.entry.list_unchecked("stmts");
+        assert_eq!(stmts.len(), 2);
+        let block = stmts[1]
+            .try_as_node()
+            .unwrap()
+            .required_node_unchecked("inner");
+        assert_eq!(block.list_unchecked("stmts").len(), 1);
+        assert_eq!(
+            parser::ParseResult::stringify_node(block, txt),
+            "{ let b; { } }"
+        );
+
+        let lets = result.entry.find_all("let");
+        let names = lets
+            .iter()
+            .map(|node| {
+                parser::ParseResult::stringify_node(node.required_node_unchecked("name"), txt)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(result.entry.find_all("block").len(), 1);
+    }
+
+    #[test]
+    fn progress() {
+        use crate::api::ext::{self, text};
+        use std::sync::{Arc, Mutex};
+
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(text())])
+            .build();
+        parser.parser.entry = Some("file");
+        let lexed = Arc::new(Mutex::new(Vec::new()));
+        let reports = lexed.clone();
+        parser.lexer.on_progress(4, move |consumed, total| {
+            reports.lock().unwrap().push((consumed, total))
+        });
+        let parsed = Arc::new(Mutex::new(Vec::new()));
+        let reports = parsed.clone();
+        parser.parser.on_progress(4, move |consumed, total| {
+            reports.lock().unwrap().push((consumed, total))
+        });
+
+        let txt = "a b c d e f";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        // 11 tokens and `Eof`, reported after the 4th and 8th token and at the end
+        assert_eq!(*lexed.lock().unwrap(), [(4, 11), (8, 11), (11, 11)]);
+
+        parser.parse(&tokens, txt).unwrap();
+        let parsed = parsed.lock().unwrap();
+        assert!(parsed.len() > 1);
+        assert!(parsed.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(parsed.iter().all(|(_, total)| *total == 11));
+        assert_eq!(parsed.last(), Some(&(11, 11)));
+    }
+
+    #[test]
+    fn canonical_json() {
+        use crate::api::ext::{self, local, node, text, token};
+        use crate::definition::{load, Value};
+
+        fn build(grammar: &mut grammar::Grammar<'static>, names: [&'static str; 2]) {
+            for name in names {
+                match name {
+                    "file" => grammar
+                        .new_node("file")
+                        .rules([ext::while_(node("assign")).set(local("assignments"))])
+                        .var("assignments", grammar::VariableKind::NodeList)
+                        .build(),
+                    _ => grammar
+                        .new_node("assign")
+                        .rules([
+                            ext::is(text()).set(local("name")),
+                            ext::is(token("=")),
+                            ext::is(text()),
+                        ])
+                        .var("name", grammar::VariableKind::Node)
+                        .build(),
+                };
+            }
+            grammar.feature("strict", false);
+        }
+
+        // nodes added in another order by rules with other origins
+        let mut first = Parser::new();
+        build(&mut first.grammar, ["file", "assign"]);
+        let mut second = Parser::new();
+        build(&mut second.grammar, ["assign", "file"]);
+        let canonical = first.grammar.to_canonical_json();
+        assert_eq!(canonical, second.grammar.to_canonical_json());
+        assert!(canonical.starts_with(
+            r#"{"features":{"strict":false},"nodes":[{"name":"assign","rules":[{"kind":"Is","#
+        ));
+
+        // the definition loads back into the same grammar
+        let Value::Object(mut fields) = first.grammar.to_definition() else {
+            panic!("a grammar is written as an object");
+        };
+        fields.push((
+            "tokens".into(),
+            Value::Array(vec![Value::String("=".into())]),
+        ));
+        let loaded = load(&Value::Object(fields)).unwrap();
+        assert_eq!(loaded.grammar.to_canonical_json(), canonical);
+
+        let value = Value::Object(vec![
+            ("b".into(), Value::Number(1.0)),
+            ("a".into(), Value::String("\"\n".into())),
+        ]);
+        assert_eq!(value.to_canonical_json(), r#"{"a":"\"\n","b":1}"#);
+    }
+
+    #[test]
+    fn fingerprint() {
+        use crate::api::ext::{self, text, token};
+
+        let build = |tokens: [&str; 2]| {
+            let mut parser = Parser::new();
+            parser.lexer.add_tokens(tokens.into_iter());
+            parser
+                .grammar
+                .new_node("assign")
+                .rules([ext::is(text()), ext::is(token("=")), ext::is(text())])
+                .build();
+            parser
+        };
+        let first = build(["=", ";"]);
+        let mut second = build([";", "="]);
+        assert_eq!(first.fingerprint(), second.fingerprint());
+        assert_eq!(first.grammar.fingerprint(), second.grammar.fingerprint());
+
+        second.lexer.crlf = true;
+        assert_ne!(first.fingerprint(), second.fingerprint());
+        assert_eq!(first.grammar.fingerprint(), second.grammar.fingerprint());
+
+        second.grammar.feature("strict", true);
+        assert_ne!(first.grammar.fingerprint(), second.grammar.fingerprint());
+    }
+
+    #[test]
+    fn token_lints() {
+        use crate::{api::ext, grammar::validator::ValidationWarnings};
+
+        let mut parser = Parser::new();
+        parser
+            .lexer
+            .add_tokens(["=", ";", "fn", "+", "{", "}"].into_iter());
+        parser
+            .grammar
+            .new_node("decl")
+            .rules([
+                ext::is(word("fn")),
+                ext::is(text()),
+                ext::is(token("=")),
+                ext::is(ext::enumerator("end")),
+            ])
+            .build();
+        parser.grammar.new_enum("end").options([token(";")]).build();
+        parser
+            .grammar
+            .brackets
+            .push((TokenKinds::Token("{"), TokenKinds::Token("}")));
+
+        let warnings = |validator: Validator| {
+            validator
+                .validate(&parser)
+                .warnings
+                .iter()
+                .filter_map(|warning| match warning.kind {
+                    ValidationWarnings::UnusedToken(token) => Some(("unused", token, None)),
+                    ValidationWarnings::WordIsToken(word) => {
+                        Some(("word", word, warning.node.map(|node| node.name.as_str())))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(warnings(Validator::default()).is_empty());
+        let validator = Validator {
+            token_lints: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            warnings(validator),
+            [("word", "fn", Some("decl")), ("unused", "+", None)]
+        );
+        let result = validator.validate(&parser);
+        assert!(result.warnings.iter().any(|w| w.kind.code() == "W0009"));
+        assert!(result.warnings.iter().any(|w| w.kind.code() == "W0010"));
+    }
+
+    #[test]
+    fn validate_entry() {
+        use crate::{
+            api::ext,
+            grammar::{validator::ValidationErrors, Comparison},
+        };
+
+        let mut parser = Parser::new();
+        parser.lexer.add_token(";");
+        parser
+            .grammar
+            .globals
+            .push(("strict", VariableKind::Boolean));
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::maybe(ext::node("pragma")), ext::is(ext::node("item"))])
+            .build();
+        parser
+            .grammar
+            .new_node("pragma")
+            .rules([
+                ext::is(word("strict")),
+                ext::set_bool(ext::global("strict"), true),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("item")
+            .rules([
+                ext::compare(
+                    ext::global("strict"),
+                    ext::global("strict"),
+                    Comparison::Equal,
+                ),
+                ext::is(text()),
+            ])
+            .build();
+        parser
+            .grammar
+            .new_node("jump")
+            .rules([
+                ext::optional([ext::is(token(";")), ext::label("inner")]),
+                ext::goto("inner"),
+            ])
+            .build();
+
+        let errors = |entry| {
+            Validator::default()
+                .validate_entry(&parser, entry)
+                .errors
+                .into_iter()
+                .filter_map(|error| match error.kind {
+                    ValidationErrors::GlobalSetElsewhere(name) => Some((
+                        error.kind.code(),
+                        name,
+                        error.node.map(|node| node.name.as_str()),
+                    )),
+                    ValidationErrors::GotoOutOfScope(label) => Some((
+                        error.kind.code(),
+                        label,
+                        error.node.map(|node| node.name.as_str()),
+                    )),
+                    ValidationErrors::NodeNotFound(name) => Some((error.kind.code(), name, None)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(errors("file").is_empty());
+        assert_eq!(errors("item"), [("E0117", "strict", Some("item"))]);
+        assert_eq!(errors("jump"), [("E0118", "inner", Some("jump"))]);
+        assert_eq!(errors("missing").len(), 1);
+    }
+
+    #[test]
+    fn if_conditions() {
+        use crate::{
+            api::ext::{self, global},
+            compiled::CompiledGrammar,
+            definition::{load, Value},
+            grammar::Comparison,
+        };
+
+        let mut parser = Parser::new();
+        parser.lexer.add_token(";");
+        parser
+            .grammar
+            .globals
+            .push(("strict", VariableKind::Boolean));
+        parser.grammar.globals.push(("yes", VariableKind::Boolean));
+        // a type if the word is known as one, any word is a type when not strict
+        parser
+            .grammar
+            .new_node("stmt")
+            .rules([
+                ext::if_(ext::or([
+                    ext::predicate("is_type", []),
+                    ext::not(ext::comparison(
+                        global("strict"),
+                        global("yes"),
+                        Comparison::Equal,
+                    )),
+                ]))
+                .then([ext::is(text()).set("type")]),
+                ext::is(text()).set("name"),
+                ext::is(token(";")),
+            ])
+            .variables([ext::node_var("type"), ext::node_var("name")])
+            .build();
+        parser.parser.entry = Some("stmt");
+
+        let errors = |parser: &Parser| {
+            Validator::default()
+                .validate(parser)
+                .errors
+                .iter()
+                .map(|e| e.kind.code())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(errors(&parser), ["E0116"]);
+        parser.parser.register_predicate(
+            "is_type",
+            |txt: &str, _: &parser::ParseState, _: &[&str]| ["T", "size_t"].contains(&txt),
+        );
+        assert!(errors(&parser).is_empty());
+
+        for (txt, strict, vars) in [
+            ("T x;", true, Some(["T", "x"])),
+            ("x;", true, Some(["", "x"])),
+            ("U x;", true, None),
+            ("U x;", false, Some(["U", "x"])),
+        ] {
+            let mut state = parser::ParseState::new();
+            state.set_bool("strict", strict);
+            state.set_bool("yes", true);
+            let tokens = parser.lexer.lex_utf8(txt).unwrap();
+            let res = parser.parse_with_state(&tokens, txt, state);
+            let vars = vars.map(|vars| vars.map(String::from));
+            let found = res.ok().map(|res| {
+                ["type", "name"].map(|var| match res.entry.node_unchecked(var) {
+                    Some(node) => parser::ParseResult::stringify_node(node, txt).to_string(),
+                    None => String::new(),
+                })
+            });
+            assert_eq!(found, vars, "{txt:?}");
+        }
+
+        // definitions and compiled grammars keep the condition
+        let canonical = parser.grammar.to_canonical_json();
+        assert!(canonical
+            .contains(r#"{"cond":{"Or":[{"External":{"name":"is_type"}},{"Not":{"Compare":"#));
+        let Value::Object(mut fields) = parser.grammar.to_definition() else {
+            panic!("a grammar is written as an object");
+        };
+        fields.push((
+            "tokens".into(),
+            Value::Array(vec![Value::String(";".into())]),
+        ));
+        let loaded = load(&Value::Object(fields)).unwrap();
+        assert_eq!(loaded.grammar.to_canonical_json(), canonical);
+        let compiled = CompiledGrammar::new(parser, b"");
+        let loaded = CompiledGrammar::from_bytes(&compiled.to_bytes()).unwrap();
+        assert_eq!(loaded.parser.grammar.to_canonical_json(), canonical);
+
+        // a variable that does not exist fails the parse instead of panicking
+        let mut parser = Parser::new();
+        parser
+            .grammar
+            .new_node("stmt")
+            .rules([ext::if_(ext::comparison(
+                "missing",
+                "missing",
+                Comparison::Equal,
+            ))])
+            .build();
+        parser.parser.entry = Some("stmt");
+        let tokens = parser.lexer.lex_utf8("x").unwrap();
+        let err = parser.parse(&tokens, "x").unwrap_err();
+        assert!(matches!(
+            err.kind,
+            parser::ParseErrors::VariableNotFound(grammar::VarKind::Local("missing"))
+        ));
+    }
+
+    #[test]
+    fn node_summary() {
+        use crate::api::ext::{self, local, node, text, token, word};
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";"].into_iter());
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::while_(node("let")).set(local("stmts"))])
+            .var("stmts", grammar::VariableKind::NodeList)
+            .build();
+        parser
+            .grammar
+            .new_node("let")
+            .rules([
+                ext::is(word("let")),
+                ext::is(text()).set(local("name")),
+                ext::is(token(";")),
+            ])
+            .var("name", grammar::VariableKind::Node)
+            .build();
+        parser.parser.entry = Some("file");
+
+        let txt = "let a; let b; let c; let d; let e; let f;";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let result = parser.parse(&tokens, txt).unwrap();
+
+        assert_eq!(result.entry.summary(0).to_string(), "file[0..41]");
+        assert_eq!(
+            result.entry.to_string(),
+            "file[0..41] { stmts: [let[0..6], let[7..13], let[14..20], let[21..27], ... 2 more] }"
+        );
+        assert_eq!(
+            result.entry.summary(2).to_string(),
+            "file[0..41] { stmts: [let[0..6] { name: <text>[4..5] }, \
+             let[7..13] { name: <text>[11..12] }, let[14..20] { name: <text>[18..19] }, \
+             let[21..27] { name: <text>[25..26] }, ... 2 more] }"
+        );
+
+        // errors only print the failing node itself, never its whole subtree
+        let txt = "let a; let b let";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        assert!(err.to_string().ends_with("\nError in node: file[0..6]\n"));
+    }
+
+    #[test]
+    fn std_errors() {
+        use crate::api::ext::{self, token};
+        use std::error::Error;
+
+        let mut parser = Parser::new();
+        parser.lexer.add_tokens([";"].into_iter());
+        parser
+            .grammar
+            .new_node("file")
+            .rules([ext::is(token(";"))])
+            .build();
+        parser.parser.entry = Some("file");
+
+        // owned errors outlive the parser and travel through `?` like any other error
+        fn run(parser: &Parser, txt: &str) -> Result<(), Box<dyn Error>> {
+            let tokens = parser.lexer.lex_utf8(txt)?;
+            parser.parse(&tokens, txt).map_err(|err| err.into_owned())?;
+            Ok(())
+        }
+        assert!(run(&parser, ";").is_ok());
+        let err = run(&parser, "x").unwrap_err();
+        let owned = err.downcast_ref::<parser::ParseErrorOwned>().unwrap();
+        assert_eq!(owned.node.as_deref(), Some("file"));
+        assert!(owned.to_string().starts_with(owned.code));
+        assert!(owned.source().is_none());
+
+        // borrowed errors convert the same way
+        let txt = "x";
+        let tokens = parser.lexer.lex_utf8(txt).unwrap();
+        let err = parser.parse(&tokens, txt).unwrap_err();
+        let boxed: Box<dyn Error + '_> = Box::new(err.clone());
+        assert_eq!(boxed.to_string(), err.to_string());
+        assert_eq!(&parser::ParseErrorOwned::from(&err), owned);
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_inputs() {
+        use crate::{
+            api::ext,
+            testing::{GrammarInput, InputGrammar},
+        };
+        use arbitrary::{Arbitrary, Unstructured};
+
+        struct Lists;
+
+        impl InputGrammar for Lists {
+            fn parser() -> &'static Parser<'static> {
+                thread_local! {
+                    static PARSER: &'static Parser<'static> = {
+                        let mut parser = Parser::new();
+                        parser.lexer.add_tokens(["[", "]", ","].into_iter());
+                        parser
+                            .grammar
+                            .new_node("list")
+                            .rules([
+                                ext::is(token("[")),
+                                ext::maybe(text())
+                                    .then([ext::while_(token(",")).then([ext::is(text())])]),
+                                ext::is(token("]")),
+                            ])
+                            .build();
+                        parser.parser.entry = Some("list");
+                        Box::leak(Box::new(parser))
+                    };
+                }
+                PARSER.with(|parser| *parser)
+            }
+        }
+
+        let parser = Lists::parser();
+        for seed in 0..50u8 {
+            let bytes = (0..64)
+                .map(|i| seed.wrapping_mul(31).wrapping_add(i))
+                .collect::<Vec<_>>();
+            let input = GrammarInput::<Lists>::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            assert!(input.text.starts_with('['));
+            let tokens = parser.lexer.lex_utf8(&input.text).unwrap();
+            parser.parse(&tokens, &input.text).unwrap();
+        }
+    }
 }
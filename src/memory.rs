@@ -0,0 +1,706 @@
+//! Memory of a parse result
+//!
+//! Nodes, their variables and lists, recorded children and tokens, recovered errors and
+//! bracket pairs are kept in the allocator of the parse. It is the global allocator
+//! unless the parse was started with `crate::Parser::parse_in` (feature `allocator`).
+//! A bump allocator can then reclaim a whole parse at once: forget the result and reset
+//! the arena. The globals, profiling stats and the payload of boxed errors stay in the
+//! global allocator.
+//!
+//! The collections here drop their items through a function pointer made for the type
+//! of the items instead of a generic `Drop` impl. Like with `Vec` and `Arc`, a result
+//! then only has to outlive the grammar while it is used, not until it is dropped.
+
+use core::{
+    alloc::Layout,
+    fmt,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    slice,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+
+#[cfg(feature = "allocator")]
+use allocator_api2::alloc::{Allocator, Global};
+
+use crate::parser::Node;
+
+// Choose between std and alloc
+cfg_if::cfg_if! {
+    if #[cfg(feature = "std")] {
+        extern crate std;
+        use std::prelude::v1::*;
+        use std::alloc::handle_alloc_error;
+        #[cfg(not(feature = "allocator"))]
+        use std::alloc as global;
+    } else {
+        extern crate alloc;
+        use alloc::vec::Vec;
+        use alloc::alloc::handle_alloc_error;
+        #[cfg(not(feature = "allocator"))]
+        use alloc::alloc as global;
+    }
+}
+
+/// Allocator of a parse, see the module documentation
+///
+/// The default is the global allocator
+#[derive(Clone, Copy, Default)]
+pub struct ParseAlloc {
+    /// The global allocator if not set
+    #[cfg(feature = "allocator")]
+    alloc: Option<&'static (dyn Allocator + Sync)>,
+}
+
+impl ParseAlloc {
+    /// Allocator passed in by the caller
+    ///
+    /// It has to be `Sync` because results can be sent to other threads, and `'static`
+    /// because results do not name its lifetime
+    #[cfg(feature = "allocator")]
+    pub fn new(alloc: &'static (dyn Allocator + Sync)) -> Self {
+        ParseAlloc { alloc: Some(alloc) }
+    }
+
+    pub fn is_global(&self) -> bool {
+        #[cfg(feature = "allocator")]
+        return self.alloc.is_none();
+        #[cfg(not(feature = "allocator"))]
+        true
+    }
+
+    /// Empty vector in the allocator
+    pub fn vec<T>(self) -> NodeVec<T> {
+        NodeVec::new_in(self)
+    }
+
+    /// Vector of the items in the allocator
+    pub fn collect<T>(self, items: impl IntoIterator<Item = T>) -> NodeVec<T> {
+        let mut vec = self.vec();
+        vec.extend(items);
+        vec
+    }
+
+    /// Moves the value into the allocator
+    pub fn boxed<T>(self, value: T) -> NodeBox<T> {
+        NodeBox::new_in(value, self)
+    }
+
+    /// Memory for the layout, its size is never 0
+    fn allocate(self, layout: Layout) -> NonNull<u8> {
+        #[cfg(feature = "allocator")]
+        let ptr = match self.alloc {
+            Some(alloc) => alloc.allocate(layout),
+            None => Global.allocate(layout),
+        }
+        .ok()
+        .map(NonNull::cast);
+        #[cfg(not(feature = "allocator"))]
+        // Safety: the size is not 0
+        let ptr = NonNull::new(unsafe { global::alloc(layout) });
+        ptr.unwrap_or_else(|| handle_alloc_error(layout))
+    }
+
+    /// Moves the memory to a larger layout with the same alignment
+    ///
+    /// Safety: the memory was allocated here with the old layout
+    unsafe fn grow(self, ptr: NonNull<u8>, old: Layout, new: Layout) -> NonNull<u8> {
+        #[cfg(feature = "allocator")]
+        let ptr = match self.alloc {
+            Some(alloc) => alloc.grow(ptr, old, new),
+            None => Global.grow(ptr, old, new),
+        }
+        .ok()
+        .map(NonNull::cast);
+        #[cfg(not(feature = "allocator"))]
+        let ptr = NonNull::new(global::realloc(ptr.as_ptr(), old, new.size()));
+        ptr.unwrap_or_else(|| handle_alloc_error(new))
+    }
+
+    /// Safety: the memory was allocated here with the layout
+    unsafe fn deallocate(self, ptr: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "allocator")]
+        match self.alloc {
+            Some(alloc) => alloc.deallocate(ptr, layout),
+            None => Global.deallocate(ptr, layout),
+        }
+        #[cfg(not(feature = "allocator"))]
+        global::dealloc(ptr.as_ptr(), layout)
+    }
+}
+
+impl fmt::Debug for ParseAlloc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.is_global() {
+            true => f.write_str("ParseAlloc(global)"),
+            false => f.write_str("ParseAlloc(..)"),
+        }
+    }
+}
+
+/// Buffer of a `NodeVec` without the type of its items
+struct RawVec {
+    ptr: NonNull<u8>,
+    len: usize,
+    cap: usize,
+    alloc: ParseAlloc,
+    /// Drops the items and frees the buffer, made for the type of the items
+    free: unsafe fn(&mut RawVec),
+}
+
+impl Drop for RawVec {
+    fn drop(&mut self) {
+        // Safety: `free` was made for the items in the buffer
+        unsafe { (self.free)(self) }
+    }
+}
+
+/// Safety: the buffer holds `len` items of `T` and has room for `cap`
+unsafe fn free_vec<T>(raw: &mut RawVec) {
+    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+        raw.ptr.as_ptr().cast::<T>(),
+        raw.len,
+    ));
+    if raw.cap != 0 && mem::size_of::<T>() != 0 {
+        raw.alloc
+            .deallocate(raw.ptr, Layout::array::<T>(raw.cap).unwrap());
+    }
+}
+
+/// Vector in the allocator of a parse, derefs to a slice like `Vec`
+pub struct NodeVec<T> {
+    raw: RawVec,
+    /// The vector owns its items for the drop check
+    marker: PhantomData<T>,
+}
+
+// Safety: the vector owns its items like `Vec` and the allocator is `Sync`
+unsafe impl<T: Send> Send for NodeVec<T> {}
+unsafe impl<T: Sync> Sync for NodeVec<T> {}
+
+impl<T> NodeVec<T> {
+    /// Empty vector in the global allocator
+    pub fn new() -> Self {
+        Self::new_in(ParseAlloc::default())
+    }
+
+    pub fn new_in(alloc: ParseAlloc) -> Self {
+        NodeVec {
+            raw: RawVec {
+                ptr: NonNull::<T>::dangling().cast(),
+                len: 0,
+                cap: match mem::size_of::<T>() {
+                    0 => usize::MAX,
+                    _ => 0,
+                },
+                alloc,
+                free: free_vec::<T>,
+            },
+            marker: PhantomData,
+        }
+    }
+
+    pub fn allocator(&self) -> ParseAlloc {
+        self.raw.alloc
+    }
+
+    pub fn len(&self) -> usize {
+        self.raw.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.raw.cap
+    }
+
+    fn ptr(&self) -> *mut T {
+        self.raw.ptr.as_ptr().cast()
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        // Safety: the first `len` items are initialized
+        unsafe { slice::from_raw_parts(self.ptr(), self.raw.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        // Safety: the first `len` items are initialized
+        unsafe { slice::from_raw_parts_mut(self.ptr(), self.raw.len) }
+    }
+
+    /// Makes room for at least `additional` more items
+    pub fn reserve(&mut self, additional: usize) {
+        let raw = &mut self.raw;
+        if raw.cap - raw.len >= additional {
+            return;
+        }
+        let cap = raw
+            .len
+            .checked_add(additional)
+            .expect("capacity overflow")
+            .max(raw.cap * 2)
+            .max(4);
+        let layout = Layout::array::<T>(cap).expect("capacity overflow");
+        raw.ptr = match raw.cap {
+            0 => raw.alloc.allocate(layout),
+            // Safety: the buffer was allocated with the layout of the old capacity
+            old => unsafe {
+                raw.alloc
+                    .grow(raw.ptr, Layout::array::<T>(old).unwrap(), layout)
+            },
+        };
+        raw.cap = cap;
+    }
+
+    pub fn push(&mut self, value: T) {
+        if self.raw.len == self.raw.cap {
+            self.reserve(1);
+        }
+        // Safety: there is room for the item
+        unsafe { self.ptr().add(self.raw.len).write(value) };
+        self.raw.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.raw.len == 0 {
+            return None;
+        }
+        self.raw.len -= 1;
+        // Safety: the item is initialized and no longer counted
+        Some(unsafe { self.ptr().add(self.raw.len).read() })
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.raw.len {
+            return;
+        }
+        // Safety: `len` is in bounds
+        let tail =
+            ptr::slice_from_raw_parts_mut(unsafe { self.ptr().add(len) }, self.raw.len - len);
+        // a panicking drop leaks the rest of the tail instead of dropping it twice
+        self.raw.len = len;
+        // Safety: the tail is initialized and no longer counted
+        unsafe { ptr::drop_in_place(tail) };
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Keeps the items `keep` returns true for, in order
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        let len = self.raw.len;
+        // a panic leaks the items instead of dropping them twice
+        self.raw.len = 0;
+        let mut kept = 0;
+        for i in 0..len {
+            // Safety: every item is read once, kept items are moved to the front
+            unsafe {
+                let item = self.ptr().add(i);
+                if keep(&*item) {
+                    ptr::copy(item, self.ptr().add(kept), 1);
+                    kept += 1;
+                } else {
+                    ptr::drop_in_place(item);
+                }
+            }
+        }
+        self.raw.len = kept;
+    }
+}
+
+impl<T> Default for NodeVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deref for NodeVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T> DerefMut for NodeVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T: Clone> Clone for NodeVec<T> {
+    fn clone(&self) -> Self {
+        self.allocator().collect(self.iter().cloned())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for NodeVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+impl<T: PartialEq<U>, U> PartialEq<NodeVec<U>> for NodeVec<T> {
+    fn eq(&self, other: &NodeVec<U>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<T: Eq> Eq for NodeVec<T> {}
+
+impl<T: PartialEq<U>, U> PartialEq<[U]> for NodeVec<T> {
+    fn eq(&self, other: &[U]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl<T: PartialEq<U>, U, const N: usize> PartialEq<[U; N]> for NodeVec<T> {
+    fn eq(&self, other: &[U; N]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl<T: PartialEq<U>, U> PartialEq<Vec<U>> for NodeVec<T> {
+    fn eq(&self, other: &Vec<U>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<T> Extend<T> for NodeVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, items: I) {
+        let items = items.into_iter();
+        self.reserve(items.size_hint().0);
+        items.for_each(|item| self.push(item));
+    }
+}
+
+/// Collects into the global allocator, see `ParseAlloc::collect` for others
+impl<T> FromIterator<T> for NodeVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(items: I) -> Self {
+        ParseAlloc::default().collect(items)
+    }
+}
+
+impl<T> From<Vec<T>> for NodeVec<T> {
+    fn from(items: Vec<T>) -> Self {
+        items.into_iter().collect()
+    }
+}
+
+impl<'v, T> IntoIterator for &'v NodeVec<T> {
+    type Item = &'v T;
+    type IntoIter = slice::Iter<'v, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'v, T> IntoIterator for &'v mut NodeVec<T> {
+    type Item = &'v mut T;
+    type IntoIter = slice::IterMut<'v, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T> IntoIterator for NodeVec<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(mut self) -> IntoIter<T> {
+        let end = self.raw.len;
+        // the iterator drops the items it does not hand out
+        self.raw.len = 0;
+        IntoIter {
+            vec: self,
+            start: 0,
+            end,
+        }
+    }
+}
+
+/// Items of a `NodeVec` by value
+pub struct IntoIter<T> {
+    /// Counts no items, `start..end` are left
+    vec: NodeVec<T>,
+    start: usize,
+    end: usize,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.start == self.end {
+            return None;
+        }
+        self.start += 1;
+        // Safety: the item is initialized and handed out once
+        Some(unsafe { self.vec.ptr().add(self.start - 1).read() })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.end - self.start, Some(self.end - self.start))
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.start == self.end {
+            return None;
+        }
+        self.end -= 1;
+        // Safety: the item is initialized and handed out once
+        Some(unsafe { self.vec.ptr().add(self.end).read() })
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+impl<T> Drop for IntoIter<T> {
+    fn drop(&mut self) {
+        // Safety: `start` is in bounds
+        let left = ptr::slice_from_raw_parts_mut(
+            unsafe { self.vec.ptr().add(self.start) },
+            self.end - self.start,
+        );
+        self.start = self.end;
+        // Safety: the items were not handed out
+        unsafe { ptr::drop_in_place(left) };
+    }
+}
+
+/// Allocation of a `NodeBox` without the type of its value
+struct RawBox {
+    ptr: NonNull<u8>,
+    alloc: ParseAlloc,
+    /// Drops the value and frees it, made for the type of the value
+    free: unsafe fn(&mut RawBox),
+}
+
+impl Drop for RawBox {
+    fn drop(&mut self) {
+        // Safety: `free` was made for the value
+        unsafe { (self.free)(self) }
+    }
+}
+
+/// Safety: the box holds a `T`
+unsafe fn free_box<T>(raw: &mut RawBox) {
+    ptr::drop_in_place(raw.ptr.as_ptr().cast::<T>());
+    if mem::size_of::<T>() != 0 {
+        raw.alloc.deallocate(raw.ptr, Layout::new::<T>());
+    }
+}
+
+/// Box in the allocator of a parse
+pub struct NodeBox<T> {
+    raw: RawBox,
+    /// The box owns its value for the drop check
+    marker: PhantomData<T>,
+}
+
+// Safety: the box owns its value like `Box` and the allocator is `Sync`
+unsafe impl<T: Send> Send for NodeBox<T> {}
+unsafe impl<T: Sync> Sync for NodeBox<T> {}
+
+impl<T> NodeBox<T> {
+    pub fn new_in(value: T, alloc: ParseAlloc) -> Self {
+        let ptr = match mem::size_of::<T>() {
+            0 => NonNull::<T>::dangling(),
+            _ => alloc.allocate(Layout::new::<T>()).cast(),
+        };
+        // Safety: the memory has the layout of `T`
+        unsafe { ptr.as_ptr().write(value) };
+        NodeBox {
+            raw: RawBox {
+                ptr: ptr.cast(),
+                alloc,
+                free: free_box::<T>,
+            },
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for NodeBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: the value lives as long as the box
+        unsafe { &*self.raw.ptr.as_ptr().cast::<T>() }
+    }
+}
+
+impl<T> DerefMut for NodeBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the value lives as long as the box
+        unsafe { &mut *self.raw.ptr.as_ptr().cast::<T>() }
+    }
+}
+
+impl<T: Clone> Clone for NodeBox<T> {
+    fn clone(&self) -> Self {
+        NodeBox::new_in((**self).clone(), self.raw.alloc)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for NodeBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Value and the number of references to it, the count comes first so `RawRef` can
+/// reach it without the type of the value
+#[repr(C)]
+struct Shared<T> {
+    count: AtomicUsize,
+    value: T,
+}
+
+/// Counted allocation of a `NodeRef` without the type of its node
+struct RawRef {
+    ptr: NonNull<AtomicUsize>,
+    alloc: ParseAlloc,
+    /// Drops the node and frees it, made for the type of the node
+    free: unsafe fn(&mut RawRef),
+}
+
+impl RawRef {
+    fn count(&self) -> &AtomicUsize {
+        // Safety: the count keeps the allocation alive
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl Clone for RawRef {
+    fn clone(&self) -> Self {
+        self.count().fetch_add(1, Ordering::Relaxed);
+        RawRef {
+            ptr: self.ptr,
+            alloc: self.alloc,
+            free: self.free,
+        }
+    }
+}
+
+impl Drop for RawRef {
+    fn drop(&mut self) {
+        if self.count().fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        fence(Ordering::Acquire);
+        // Safety: this was the last reference and `free` was made for the node
+        unsafe { (self.free)(self) }
+    }
+}
+
+/// Safety: the allocation holds a `Shared<T>`
+unsafe fn free_shared<T>(raw: &mut RawRef) {
+    let shared = raw.ptr.cast::<Shared<T>>();
+    ptr::drop_in_place(shared.as_ptr());
+    raw.alloc
+        .deallocate(shared.cast(), Layout::new::<Shared<T>>());
+}
+
+/// Shared node of a parse result, see `crate::parser::Nodes::Node`
+///
+/// Like `Arc<Node>`, kept in the allocator of the node. Has the methods of `Arc` the
+/// crate uses.
+pub struct NodeRef<'a> {
+    raw: RawRef,
+    /// The reference owns the node for the drop check
+    marker: PhantomData<Node<'a>>,
+}
+
+// Safety: shared between threads like `Arc<Node>`, the count is atomic and nodes are
+// `Send` and `Sync` as checked below. A bound on `Node` would be cyclic.
+unsafe impl Send for NodeRef<'_> {}
+unsafe impl Sync for NodeRef<'_> {}
+
+const _: () = {
+    const fn shareable<T: Send + Sync>() {}
+    shareable::<Node>();
+};
+
+impl<'a> NodeRef<'a> {
+    /// Moves the node into its own allocator
+    pub fn new(node: Node<'a>) -> Self {
+        let alloc = node.alloc;
+        let ptr = alloc
+            .allocate(Layout::new::<Shared<Node<'a>>>())
+            .cast::<Shared<Node<'a>>>();
+        // Safety: the memory has the layout of `Shared`
+        unsafe {
+            ptr.as_ptr().write(Shared {
+                count: AtomicUsize::new(1),
+                value: node,
+            })
+        };
+        NodeRef {
+            raw: RawRef {
+                ptr: ptr.cast(),
+                alloc,
+                free: free_shared::<Node<'a>>,
+            },
+            marker: PhantomData,
+        }
+    }
+
+    fn shared(&self) -> NonNull<Shared<Node<'a>>> {
+        self.raw.ptr.cast()
+    }
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.raw.ptr == other.raw.ptr
+    }
+
+    pub fn as_ptr(this: &Self) -> *const Node<'a> {
+        &**this
+    }
+
+    pub fn strong_count(this: &Self) -> usize {
+        this.raw.count().load(Ordering::Acquire)
+    }
+
+    /// Clones the node if it is shared, see `Arc::make_mut`
+    pub fn make_mut(this: &mut Self) -> &mut Node<'a> {
+        if Self::strong_count(this) != 1 {
+            *this = NodeRef::new((**this).clone());
+        }
+        // Safety: no other reference exists, new ones can only be made from `this`
+        unsafe { &mut (*this.shared().as_ptr()).value }
+    }
+}
+
+impl<'a> Deref for NodeRef<'a> {
+    type Target = Node<'a>;
+
+    fn deref(&self) -> &Node<'a> {
+        // Safety: the count keeps the node alive
+        unsafe { &self.shared().as_ref().value }
+    }
+}
+
+impl Clone for NodeRef<'_> {
+    fn clone(&self) -> Self {
+        NodeRef {
+            raw: self.raw.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl fmt::Debug for NodeRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,630 @@
+//! Rewrites of a grammar that parse the same texts with less work
+//!
+//! Generated and imported grammars tend to have many nodes that only wrap a single
+//! token and alternatives that repeat each other. `Grammar::optimize` removes the
+//! node calls and the alternatives the parser can never take, and keeps rules that
+//! every branch of a rule ends with only once.
+//!
+//! `Grammar::common_prefixes` finds alternatives that start with the same tokens and
+//! `Grammar::left_factor` rewrites them to match the shared token once.
+
+use crate::{
+    grammar::{
+        validator::{RuleParts, Validator},
+        Commands, Grammar, MatchToken, Node, OneOf, Origin, Parameters, Rule, Rules,
+    },
+    intern,
+    lexer::TokenKinds,
+    Map,
+};
+
+// Choose between std and alloc
+cfg_if::cfg_if! {
+    if #[cfg(feature = "std")] {
+        extern crate std;
+        use std::prelude::v1::*;
+    } else {
+        extern crate alloc;
+        use alloc::string::*;
+        use alloc::vec::*;
+    }
+}
+
+/// What `Grammar::optimize` changed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Optimized {
+    /// References to nodes replaced by the one token the node matches
+    pub inlined: usize,
+    /// Values removed from enumerators because an earlier value is the same
+    pub enumerator_values: usize,
+    /// Alternatives removed because an earlier alternative matches the same token
+    pub alternatives: usize,
+    /// Copies of rules removed from the branches of a rule because every branch ends
+    /// with the same rules, one copy runs after the rule instead
+    pub subtrees: usize,
+}
+
+impl Optimized {
+    /// Number of all changes
+    pub fn total(&self) -> usize {
+        self.inlined + self.enumerator_values + self.alternatives + self.subtrees
+    }
+}
+
+impl<'a> Grammar<'a> {
+    /// Rewrites the rules to parse the same texts with less work
+    ///
+    /// - References to nodes that only match one token are replaced by the token,
+    ///   unless the reference stores the matched value in a variable
+    /// - Repeated values of enumerators are removed
+    /// - Alternatives of `IsOneOf`, `MaybeOneOf` and `UntilOneOf` are removed if an
+    ///   earlier alternative matches the same token, the parser never reaches them
+    /// - Rules that every branch of a `Maybe`, `Peek`, `IfFeature`, `IsOneOf` or
+    ///   `MaybeOneOf` ends with are moved after the rule, so the grammar keeps one copy.
+    ///   Branches using `Goto` or `Label` are left alone since the labels are local to them
+    ///
+    /// Alternatives sharing more than their first token are left to `Grammar::left_factor`.
+    ///
+    /// Nodes are never removed, so the entry and grammar tests keep working.
+    /// Errors inside of an inlined node name the token instead of the node.
+    /// Alternatives and enumerator values matching nodes are kept since a failed
+    /// node can change global variables.
+    pub fn optimize(&mut self) -> Optimized {
+        let mut optimized = Optimized::default();
+        let trivial: Map<intern::Symbol, MatchToken<'a>> = self
+            .all_nodes()
+            .into_iter()
+            .filter_map(|node| Some((node.name, trivial_token(node)?)))
+            .collect();
+        for node in self.nodes.values_mut() {
+            let site = Site {
+                trivial: &trivial,
+                keywords: !node.keywords.is_empty(),
+                records: node.record_children || node.record_tokens,
+            };
+            site.rules(&mut node.rules, &mut optimized);
+        }
+        for enumerator in self.enumerators.values_mut() {
+            optimized.enumerator_values += dedup_by_token(&mut enumerator.values, |v| v);
+        }
+        optimized
+    }
+}
+
+/// Token of a node that only matches that token and keeps nothing
+fn trivial_token<'a>(node: &Node<'a>) -> Option<MatchToken<'a>> {
+    let [Rule::Is {
+        token,
+        rules,
+        parameters,
+        ..
+    }] = &node.rules[..]
+    else {
+        return None;
+    };
+    let plain = rules.is_empty()
+        && parameters.is_empty()
+        && node.variables.is_empty()
+        && node.keywords.is_empty()
+        && node.initial.is_empty()
+        && node.sync.is_empty()
+        && !node.allow_empty
+        && node.symbol.is_none()
+        && node.docs.is_none()
+        && node.snippet.is_none()
+        && !node.foldable
+        && !node.indents_children
+        && node.since.is_none()
+        && node.until.is_none();
+    match token {
+        MatchToken::Node(_) => None,
+        token if plain => Some(*token),
+        _ => None,
+    }
+}
+
+/// Removes items whose token is the same as the token of an earlier item,
+/// returns how many were removed
+///
+/// Node tokens are kept, see `Grammar::optimize`
+fn dedup_by_token<'a, T>(items: &mut Vec<T>, token: impl Fn(&T) -> &MatchToken<'a>) -> usize {
+    let len = items.len();
+    let mut seen: Vec<MatchToken<'a>> = Vec::new();
+    items.retain(|item| match token(item) {
+        MatchToken::Node(_) => true,
+        token if seen.contains(token) => false,
+        token => {
+            seen.push(*token);
+            true
+        }
+    });
+    len - items.len()
+}
+
+/// Node whose rules are being optimized
+struct Site<'t, 'a> {
+    trivial: &'t Map<intern::Symbol, MatchToken<'a>>,
+    /// Text matched in the node excludes its keywords
+    keywords: bool,
+    /// The node keeps its children or tokens, inlining would change them
+    records: bool,
+}
+
+impl<'a> Site<'_, 'a> {
+    fn rules(&self, rules: &mut Rules<'a>, optimized: &mut Optimized) {
+        let mut i = 0;
+        while i < rules.len() {
+            self.rule(&mut rules[i], optimized);
+            let (tail, copies) = shared_tail(&mut rules[i]);
+            optimized.subtrees += tail.len() * copies;
+            rules.splice(i + 1..i + 1, tail);
+            i += 1;
+        }
+    }
+
+    fn rule(&self, rule: &mut Rule<'a>, optimized: &mut Optimized) {
+        match rule {
+            Rule::Is {
+                token,
+                rules,
+                parameters,
+                ..
+            }
+            | Rule::Isnt {
+                token,
+                rules,
+                parameters,
+                ..
+            }
+            | Rule::While {
+                token,
+                rules,
+                parameters,
+                ..
+            }
+            | Rule::Until {
+                token,
+                rules,
+                parameters,
+                ..
+            } => {
+                self.inline(token, parameters, optimized);
+                self.rules(rules, optimized);
+            }
+            Rule::Maybe {
+                token,
+                is,
+                isnt,
+                parameters,
+                ..
+            }
+            | Rule::Peek {
+                token,
+                is,
+                isnt,
+                parameters,
+                ..
+            } => {
+                self.inline(token, parameters, optimized);
+                self.rules(is, optimized);
+                self.rules(isnt, optimized);
+            }
+            Rule::IsOneOf { tokens, .. } | Rule::UntilOneOf { tokens, .. } => {
+                self.alternatives(tokens, optimized);
+            }
+            Rule::MaybeOneOf {
+                is_one_of, isnt, ..
+            } => {
+                self.alternatives(is_one_of, optimized);
+                self.rules(isnt, optimized);
+            }
+            Rule::Loop { rules, .. }
+            | Rule::Command {
+                command:
+                    Commands::Compare { rules, .. }
+                    | Commands::External { rules, .. }
+                    | Commands::If { rules, .. },
+                ..
+            } => self.rules(rules, optimized),
+            Rule::IfFeature {
+                rules, else_rules, ..
+            } => {
+                self.rules(rules, optimized);
+                self.rules(else_rules, optimized);
+            }
+            Rule::Command { .. } | Rule::Debug { .. } => (),
+        }
+    }
+
+    fn alternatives(&self, alternatives: &mut Vec<OneOf<'a>>, optimized: &mut Optimized) {
+        for one_of in alternatives.iter_mut() {
+            self.inline(&mut one_of.token, &one_of.parameters, optimized);
+            self.rules(&mut one_of.rules, optimized);
+        }
+        optimized.alternatives += dedup_by_token(alternatives, |one_of| &one_of.token);
+    }
+
+    /// Replaces a reference to a trivial node by its token
+    fn inline(
+        &self,
+        token: &mut MatchToken<'a>,
+        parameters: &[Parameters<'a>],
+        optimized: &mut Optimized,
+    ) {
+        let MatchToken::Node(name) = token else {
+            return;
+        };
+        let Some(inlined) = self.trivial.get(name) else {
+            return;
+        };
+        let stores = parameters
+            .iter()
+            .any(|p| matches!(p, Parameters::Set(_) | Parameters::SetField(..)));
+        let keyword = self.keywords && *inlined == MatchToken::Token(TokenKinds::Text);
+        if stores || keyword || self.records {
+            return;
+        }
+        *token = *inlined;
+        optimized.inlined += 1;
+    }
+}
+
+/// Takes the rules that every branch of the rule ends with
+///
+/// Returns the rules and the number of copies removed from the other branches
+fn shared_tail<'a>(rule: &mut Rule<'a>) -> (Rules<'a>, usize) {
+    let mut branches = branches_mut(rule);
+    if branches.len() < 2 || branches.iter().any(|branch| jumps(branch)) {
+        return (Vec::new(), 0);
+    }
+    let shortest = branches
+        .iter()
+        .map(|branch| branch.len())
+        .min()
+        .unwrap_or(0);
+    let len = (1..=shortest)
+        .take_while(|n| {
+            let first = without_origins(&branches[0][branches[0].len() - n]);
+            branches[1..]
+                .iter()
+                .all(|branch| without_origins(&branch[branch.len() - n]) == first)
+        })
+        .count();
+    if len == 0 {
+        return (Vec::new(), 0);
+    }
+    let copies = branches.len() - 1;
+    let tails = branches
+        .iter_mut()
+        .map(|branch| branch.split_off(branch.len() - len))
+        .collect::<Vec<_>>();
+    (tails.into_iter().next().unwrap_or_default(), copies)
+}
+
+/// Branches of a rule of which exactly one runs once the rule is entered
+fn branches_mut<'r, 'a>(rule: &'r mut Rule<'a>) -> Vec<&'r mut Rules<'a>> {
+    match rule {
+        Rule::Maybe { is, isnt, .. } | Rule::Peek { is, isnt, .. } => vec![is, isnt],
+        Rule::IfFeature {
+            rules, else_rules, ..
+        } => vec![rules, else_rules],
+        Rule::IsOneOf { tokens, .. } => tokens.iter_mut().map(|one_of| &mut one_of.rules).collect(),
+        Rule::MaybeOneOf {
+            is_one_of, isnt, ..
+        } => {
+            let mut branches = is_one_of
+                .iter_mut()
+                .map(|one_of| &mut one_of.rules)
+                .collect::<Vec<_>>();
+            branches.push(isnt);
+            branches
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// The rules contain a `Goto` or a `Label`
+fn jumps(rules: &[Rule]) -> bool {
+    rules.iter().any(|rule| {
+        matches!(
+            rule,
+            Rule::Command {
+                command: Commands::Goto { .. } | Commands::Label { .. },
+                ..
+            }
+        ) || Validator::parts(rule)
+            .2
+            .into_iter()
+            .any(|block| jumps(block))
+    })
+}
+
+/// Copy of the rule that compares equal to copies of the same rule written elsewhere
+fn without_origins<'a>(rule: &Rule<'a>) -> Rule<'a> {
+    fn clear(rule: &mut Rule) {
+        rule.set_origin(Origin::default());
+        for block in blocks_mut(rule) {
+            block.iter_mut().for_each(clear);
+        }
+    }
+    let mut rule = rule.clone();
+    clear(&mut rule);
+    rule
+}
+
+/// Alternatives of a `IsOneOf` or `MaybeOneOf` that start with the same tokens,
+/// see `Grammar::common_prefixes`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommonPrefix<'a> {
+    /// Node containing the rule
+    pub node: &'a str,
+    /// Index of the rule among the `IsOneOf` and `MaybeOneOf` rules of the node,
+    /// counted in the order they are written
+    pub rule: usize,
+    /// Indexes of the alternatives
+    pub alternatives: Vec<usize>,
+    /// Tokens every one of the alternatives starts with
+    pub prefix: Vec<MatchToken<'a>>,
+    /// `Grammar::left_factor` can rewrite the alternatives
+    ///
+    /// Alternatives matching nodes have to be merged by hand since the prefix
+    /// is part of the nodes
+    pub factorable: bool,
+}
+
+impl<'a> Grammar<'a> {
+    /// Finds alternatives that start with the same tokens
+    ///
+    /// Alternatives matching a node use the leading tokens of the node, the parser
+    /// matches the prefix again for every one of them that fails.
+    /// Alternatives matching the same token are never tried after the first one,
+    /// see `Grammar::left_factor` to merge them.
+    pub fn common_prefixes(&self) -> Vec<CommonPrefix<'a>> {
+        let mut nodes = self.all_nodes();
+        nodes.sort_by_key(|node| node.name);
+        let mut found = Vec::new();
+        for node in nodes {
+            let mut rule = 0;
+            self.block_prefixes(node.name.as_str(), &node.rules, &mut rule, &mut found);
+        }
+        found
+    }
+
+    fn block_prefixes(
+        &self,
+        node: &'a str,
+        rules: &[Rule<'a>],
+        rule: &mut usize,
+        found: &mut Vec<CommonPrefix<'a>>,
+    ) {
+        for current in rules {
+            let RuleParts(_, _, blocks) = Validator::parts(current);
+            if let Rule::IsOneOf { tokens, .. }
+            | Rule::MaybeOneOf {
+                is_one_of: tokens, ..
+            } = current
+            {
+                self.alternative_prefixes(node, *rule, tokens, found);
+                *rule += 1;
+            }
+            for block in blocks {
+                self.block_prefixes(node, block, rule, found);
+            }
+        }
+    }
+
+    fn alternative_prefixes(
+        &self,
+        node: &'a str,
+        rule: usize,
+        alternatives: &[OneOf<'a>],
+        found: &mut Vec<CommonPrefix<'a>>,
+    ) {
+        let prefixes = alternatives
+            .iter()
+            .map(|one_of| match one_of.token {
+                MatchToken::Node(name) => self
+                    .node_of(name)
+                    .map(|node| leading_tokens(&node.rules))
+                    .unwrap_or_default(),
+                token => {
+                    let mut prefix = vec![token];
+                    prefix.extend(leading_tokens(&one_of.rules));
+                    prefix
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut grouped = vec![false; alternatives.len()];
+        for (i, prefix) in prefixes.iter().enumerate() {
+            let Some(first) = prefix.first() else {
+                continue;
+            };
+            if grouped[i] {
+                continue;
+            }
+            let members = (i..prefixes.len())
+                .filter(|j| prefixes[*j].first() == Some(first))
+                .collect::<Vec<_>>();
+            if members.len() < 2 {
+                continue;
+            }
+            let len = members
+                .iter()
+                .map(|j| {
+                    prefix
+                        .iter()
+                        .zip(&prefixes[*j])
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                })
+                .min()
+                .unwrap_or(0);
+            let factorable = members.iter().all(|j| {
+                let one_of = &alternatives[*j];
+                !matches!(one_of.token, MatchToken::Node(_))
+                    && one_of.parameters == alternatives[i].parameters
+            });
+            for j in &members {
+                grouped[*j] = true;
+            }
+            found.push(CommonPrefix {
+                node,
+                rule,
+                alternatives: members,
+                prefix: prefix[..len].to_vec(),
+                factorable,
+            });
+        }
+    }
+
+    /// Merges alternatives starting with the same token into one that matches the
+    /// token once and continues with a `IsOneOf` of what the alternatives match next
+    ///
+    /// This is opt-in since it changes which texts are accepted: without it only the
+    /// first of the alternatives is ever taken.
+    /// Alternatives are merged only if they match the same token with the same
+    /// parameters and each of them continues with an `Is` rule.
+    ///
+    /// Returns the number of alternatives removed by merging.
+    pub fn left_factor(&mut self) -> usize {
+        let mut merged = 0;
+        for node in self.nodes.values_mut() {
+            factor_block(&mut node.rules, &mut merged);
+        }
+        merged
+    }
+}
+
+/// Tokens matched by the leading `Is` rules
+fn leading_tokens<'a>(rules: &[Rule<'a>]) -> Vec<MatchToken<'a>> {
+    let mut tokens = Vec::new();
+    for rule in rules {
+        match rule {
+            Rule::Is { token, rules, .. } if !matches!(token, MatchToken::Node(_)) => {
+                tokens.push(*token);
+                if !rules.is_empty() {
+                    tokens.extend(leading_tokens(rules));
+                    break;
+                }
+            }
+            _ => break,
+        }
+    }
+    tokens
+}
+
+fn factor_block(rules: &mut Rules<'_>, merged: &mut usize) {
+    for rule in rules {
+        match rule {
+            Rule::IsOneOf { tokens, .. }
+            | Rule::MaybeOneOf {
+                is_one_of: tokens, ..
+            } => factor_alternatives(tokens, merged),
+            _ => (),
+        }
+        for block in blocks_mut(rule) {
+            factor_block(block, merged);
+        }
+    }
+}
+
+fn factor_alternatives(alternatives: &mut Vec<OneOf<'_>>, merged: &mut usize) {
+    let mut i = 0;
+    while i < alternatives.len() {
+        let first = &alternatives[i];
+        let members = (i..alternatives.len())
+            .filter(|j| {
+                let other = &alternatives[*j];
+                !matches!(first.token, MatchToken::Node(_))
+                    && first.token == other.token
+                    && first.parameters == other.parameters
+            })
+            .collect::<Vec<_>>();
+        let continues = members
+            .iter()
+            .all(|j| matches!(alternatives[*j].rules.first(), Some(Rule::Is { .. })));
+        if members.len() > 1 && continues {
+            *merged += members.len() - 1;
+            let mut tails = Vec::new();
+            let mut shared = None;
+            for j in members.into_iter().rev() {
+                let one_of = alternatives.remove(j);
+                tails.push(continuation(one_of.rules));
+                shared = Some((one_of.token, one_of.parameters));
+            }
+            tails.reverse();
+            factor_alternatives(&mut tails, merged);
+            let Some((token, parameters)) = shared else {
+                unreachable!("at least two alternatives are merged");
+            };
+            alternatives.insert(
+                i,
+                OneOf {
+                    token,
+                    rules: vec![Rule::IsOneOf {
+                        origin: Origin::default(),
+                        tokens: tails,
+                        parameters: Vec::new(),
+                    }],
+                    parameters,
+                },
+            );
+        }
+        i += 1;
+    }
+}
+
+/// Turns rules starting with an `Is` into an alternative matching the same
+fn continuation<'a>(mut rules: Rules<'a>) -> OneOf<'a> {
+    let Rule::Is {
+        token,
+        rules: mut then,
+        parameters,
+        ..
+    } = rules.remove(0)
+    else {
+        unreachable!("only rules starting with Is are merged");
+    };
+    then.extend(rules);
+    OneOf {
+        token,
+        rules: then,
+        parameters,
+    }
+}
+
+/// Nested rule blocks of a rule
+fn blocks_mut<'r, 'a>(rule: &'r mut Rule<'a>) -> Vec<&'r mut Rules<'a>> {
+    match rule {
+        Rule::Is { rules, .. }
+        | Rule::Isnt { rules, .. }
+        | Rule::While { rules, .. }
+        | Rule::Until { rules, .. }
+        | Rule::Loop { rules, .. }
+        | Rule::Command {
+            command:
+                Commands::Compare { rules, .. }
+                | Commands::External { rules, .. }
+                | Commands::If { rules, .. },
+            ..
+        } => vec![rules],
+        Rule::Maybe { is, isnt, .. } | Rule::Peek { is, isnt, .. } => vec![is, isnt],
+        Rule::IfFeature {
+            rules, else_rules, ..
+        } => vec![rules, else_rules],
+        Rule::IsOneOf { tokens, .. } | Rule::UntilOneOf { tokens, .. } => {
+            tokens.iter_mut().map(|one_of| &mut one_of.rules).collect()
+        }
+        Rule::MaybeOneOf {
+            is_one_of, isnt, ..
+        } => {
+            let mut blocks = is_one_of
+                .iter_mut()
+                .map(|one_of| &mut one_of.rules)
+                .collect::<Vec<_>>();
+            blocks.push(isnt);
+            blocks
+        }
+        Rule::Command { .. } | Rule::Debug { .. } => Vec::new(),
+    }
+}
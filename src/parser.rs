@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use crate::{
     grammar::{ErrorDefinition, Parameters, VarKind},
@@ -6,8 +6,15 @@ use crate::{
 };
 
 use crate::{
-    grammar::{self, Grammar, MatchToken, OneOf},
-    lexer::{Lexer, TextLocation, Token, TokenKinds},
+    api::AccessError,
+    grammar::{self, Grammar, LanguageVersion, MatchToken, OneOf, Origin},
+    incremental::TextEdit,
+    intern,
+    lexer::{
+        ControlTokenKind, Lexer, LineIndex, Progress, TabPolicy, TextLocation, Token, TokenKinds,
+    },
+    memory::{NodeBox, NodeRef, NodeVec, ParseAlloc},
+    scopes::{self, Declaration, Reference, Scopes},
 };
 
 // Choose between std and alloc
@@ -16,13 +23,18 @@ cfg_if::cfg_if! {
         extern crate std;
         use std::prelude::v1::*;
         use std::fmt;
+        use std::sync::Arc;
+        use std::{borrow::Cow, collections::VecDeque};
     } else {
         extern crate alloc;
         use alloc::string::*;
         use alloc::vec::*;
         use alloc::vec;
+        use alloc::boxed::Box;
+        use alloc::sync::Arc;
         use core::fmt;
         use alloc::format;
+        use alloc::{borrow::Cow, collections::VecDeque};
     }
 }
 
@@ -31,6 +43,309 @@ pub struct Parser<'a> {
     pub entry: Option<&'a str>,
     /// Option to enable error on eof
     pub eof_error: bool,
+    /// Option to collect `ParseStats` for every node
+    ///
+    /// Adds a small overhead to every node invocation
+    pub profile: bool,
+    /// Maximum number of failed nodes the parser can recover from
+    ///
+    /// Protects against exponential backtracking on ambiguous grammars
+    pub backtrack_limit: Option<usize>,
+    /// Maximum number of nodes created during a parse, including the ones discarded by backtracking
+    ///
+    /// Stops pathological inputs with `ParseErrors::NodeLimit` before they exhaust memory
+    pub max_nodes: Option<usize>,
+    /// Maximum number of nodes nested in each other, the entry node is at depth 1
+    ///
+    /// Stops deeply nested inputs with `ParseErrors::TooDeep` before they exhaust the stack
+    pub max_nesting: Option<usize>,
+    /// Maximum number of failed alternatives reported when no option of `IsOneOf` matches
+    pub max_candidates: usize,
+    /// Features enabled or disabled for this parser, overriding the grammar defaults
+    pub features: Map<String, bool>,
+    /// Version of the parsed language
+    ///
+    /// Nodes outside of their `since`/`until` range are reported as `UnsupportedVersion`
+    pub version: Option<LanguageVersion>,
+    /// Filters the tokens pass through before parsing, in the order they were added
+    pub token_filters: Vec<Arc<dyn TokenFilter>>,
+    /// Host checks called by `grammar::Commands::External`, by name
+    pub predicates: Map<String, Arc<dyn Predicate>>,
+    /// Aborts the parse with `ParseErrors::Cancelled` once the token is cancelled
+    pub cancellation: Option<CancellationToken>,
+    /// Limits of `Parser::parse_all`
+    pub exploration: Exploration,
+    /// Recovers from committed errors of nodes in `While` loops, see `Recovery`
+    pub recovery: Option<Recovery<'a>>,
+    /// Names of the nodes kept whole, None keeps every node
+    ///
+    /// Every other node is reduced to its span once it is parsed: it only keeps the variables
+    /// that lead to kept nodes and its counters and flags. Useful when only a few constructs
+    /// of a big file are needed, find them with `Node::find_all`.
+    pub materialize: Option<Vec<&'a str>>,
+    /// Reports how far the parse got, see `lexer::Progress`
+    ///
+    /// Counts the tokens the parser moved past, a parse that backtracks
+    /// only reports once it gets further than before
+    pub progress: Option<Progress>,
+}
+
+/// Flag that stops a running parse from another thread
+///
+/// Clones share the same flag. Editors can cancel the parse of an outdated text
+/// as soon as the user types again instead of waiting for the result.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests every parse using the token to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Error recovery, enabled by setting `Parser::recovery`
+///
+/// When a node matched by a `While` rule fails with a committed error, the parser
+/// skips tokens up to and including the next synchronization token, sets the
+/// variables of the rule to a `Nodes::Error` and keeps looping. The errors are
+/// collected in `ParseResult::errors`.
+///
+/// Nodes can override the synchronization tokens with `grammar::Node::sync`.
+#[derive(Debug, Clone, Default)]
+pub struct Recovery<'a> {
+    /// Tokens that end a skipped region, for example `;` or `}`
+    pub sync: Vec<TokenKinds<'a>>,
+}
+
+impl<'a> Recovery<'a> {
+    pub fn new(sync: impl IntoIterator<Item = TokenKinds<'a>>) -> Self {
+        Recovery {
+            sync: sync.into_iter().collect(),
+        }
+    }
+}
+
+/// Limits of the ambiguity exploration done by `Parser::parse_all`
+///
+/// Every explored combination of alternatives is a full parse, so the number of
+/// attempts grows quickly with the number of ambiguous spots in the text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exploration {
+    /// Parses collected before the exploration stops
+    pub max_parses: usize,
+    /// Parses attempted, including the ones that fail, before the exploration stops
+    pub max_attempts: usize,
+}
+
+impl Default for Exploration {
+    fn default() -> Self {
+        Exploration {
+            max_parses: 8,
+            max_attempts: 64,
+        }
+    }
+}
+
+/// Stage between lexing and parsing that can drop, rewrite or inject tokens
+///
+/// Useful for macro expansion, conditional compilation or semicolon insertion.
+/// Injected tokens should be created with `Token::virtual_at` so their positions
+/// stay valid for diagnostics.
+pub trait TokenFilter: Send + Sync {
+    fn filter<'a>(&self, text: &str, tokens: Vec<Token<'a>>) -> Vec<Token<'a>>;
+}
+
+impl<F> TokenFilter for F
+where
+    F: for<'a> Fn(&str, Vec<Token<'a>>) -> Vec<Token<'a>> + Send + Sync,
+{
+    fn filter<'a>(&self, text: &str, tokens: Vec<Token<'a>>) -> Vec<Token<'a>> {
+        self(text, tokens)
+    }
+}
+
+impl fmt::Debug for dyn TokenFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TokenFilter")
+    }
+}
+
+/// Host check that decides whether the rules of `grammar::Commands::External` run
+///
+/// Gets the text of the next token that is not whitespace or ignored, the globals of
+/// the parse and the arguments of the command. Meant for context that the grammar can
+/// not express, like names declared as types in C.
+pub trait Predicate: Send + Sync {
+    fn check(&self, text: &str, state: &ParseState, args: &[&str]) -> bool;
+}
+
+impl<F> Predicate for F
+where
+    F: Fn(&str, &ParseState, &[&str]) -> bool + Send + Sync,
+{
+    fn check(&self, text: &str, state: &ParseState, args: &[&str]) -> bool {
+        self(text, state, args)
+    }
+}
+
+impl fmt::Debug for dyn Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Predicate")
+    }
+}
+
+/// What the parser found, reported by `crate::Parser::parse_events` in the order of the text
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<'a> {
+    /// Node the parser started to match at the byte, `Event::NodeEnd` has the bytes it took
+    NodeStart { name: &'a str, start: usize },
+    /// End of the innermost node that started and did not end yet
+    NodeEnd {
+        name: &'a str,
+        span: core::ops::Range<usize>,
+    },
+    /// Token consumed by the innermost node
+    TokenMatched(Token<'a>),
+    /// Value stored in a variable of the innermost node or in a global
+    VariableSet {
+        variable: VarKind<'a>,
+        /// Bytes of the value
+        span: core::ops::Range<usize>,
+    },
+}
+
+/// Receiver of the events of `crate::Parser::parse_events`
+pub trait EventSink<'a> {
+    fn event(&mut self, event: Event<'a>);
+}
+
+impl<'a, F> EventSink<'a> for F
+where
+    F: FnMut(Event<'a>),
+{
+    fn event(&mut self, event: Event<'a>) {
+        self(event)
+    }
+}
+
+/// Token filter that inserts a virtual semicolon at the end of a line
+///
+/// The semicolon is inserted only if the last token on the line is listed in `after`,
+/// so grammars of JavaScript or Go like languages can require it in every statement
+#[derive(Debug, Clone)]
+pub struct AutoSemicolon {
+    /// Text of the inserted token
+    pub semicolon: &'static str,
+    /// Kinds of tokens that can end a statement, for example `TokenKinds::Text` or `)`
+    pub after: Vec<TokenKinds<'static>>,
+    /// Insert the semicolon before the end of the text as well
+    pub at_eof: bool,
+}
+
+impl AutoSemicolon {
+    pub fn new(
+        semicolon: &'static str,
+        after: impl IntoIterator<Item = TokenKinds<'static>>,
+    ) -> Self {
+        AutoSemicolon {
+            semicolon,
+            after: after.into_iter().collect(),
+            at_eof: true,
+        }
+    }
+}
+
+impl TokenFilter for AutoSemicolon {
+    fn filter<'a>(&self, _: &str, tokens: Vec<Token<'a>>) -> Vec<Token<'a>> {
+        let mut result = Vec::with_capacity(tokens.len());
+        let mut last = None;
+        for token in tokens {
+            let line_end = match token.kind {
+                TokenKinds::Control(ControlTokenKind::Eol) => true,
+                TokenKinds::Control(ControlTokenKind::Eof) => self.at_eof,
+                _ => false,
+            };
+            if line_end && last.is_some_and(|kind| self.after.contains(&kind)) {
+                result.push(Token::virtual_at(TokenKinds::Token(self.semicolon), &token));
+            }
+            if !token.kind.is_whitespace() {
+                last = Some(token.kind);
+            }
+            result.push(token);
+        }
+        result
+    }
+}
+
+/// Token filter that splits tokens into smaller tokens
+///
+/// For example `>>` can be split into two `>` so nested generics close properly.
+/// The filter splits the token everywhere, so the grammar has to match the pieces
+/// everywhere, a shift operator becomes two `>` that `Token::touches`.
+/// To split a token only where a rule expects a piece, see `grammar::Parameters::Split`.
+#[derive(Debug, Clone, Default)]
+pub struct SplitTokens {
+    /// Token to split and the texts of its pieces
+    pub splits: Vec<(&'static str, Vec<&'static str>)>,
+}
+
+impl SplitTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Splits every `token` into `pieces`
+    ///
+    /// Panics if the pieces do not spell the token
+    pub fn split(
+        mut self,
+        token: &'static str,
+        pieces: impl IntoIterator<Item = &'static str>,
+    ) -> Self {
+        let pieces = pieces.into_iter().collect::<Vec<_>>();
+        assert!(pieces.concat() == token, "Pieces do not spell the token");
+        self.splits.push((token, pieces));
+        self
+    }
+}
+
+impl TokenFilter for SplitTokens {
+    fn filter<'a>(&self, text: &str, tokens: Vec<Token<'a>>) -> Vec<Token<'a>> {
+        let mut result = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let pieces = match token.kind {
+                TokenKinds::Token(kind) => self
+                    .splits
+                    .iter()
+                    .find(|(split, _)| *split == kind)
+                    .map(|(_, pieces)| pieces),
+                _ => None,
+            };
+            // a token that does not span its text in `text` is kept whole
+            let split = pieces.and_then(|pieces| {
+                token.split(
+                    text,
+                    pieces
+                        .iter()
+                        .map(|piece| (TokenKinds::Token(piece), piece.len())),
+                )
+            });
+            match split {
+                Some(split) => result.extend(split),
+                None => result.push(token),
+            }
+        }
+        result
+    }
 }
 
 impl<'a> Default for Parser<'a> {
@@ -44,6 +359,76 @@ impl<'a> Parser<'a> {
         Parser {
             entry: None,
             eof_error: false,
+            profile: false,
+            backtrack_limit: None,
+            max_nodes: None,
+            max_nesting: None,
+            max_candidates: 3,
+            features: Map::new(),
+            version: None,
+            token_filters: Vec::new(),
+            predicates: Map::new(),
+            cancellation: None,
+            exploration: Exploration::default(),
+            recovery: None,
+            materialize: None,
+            progress: None,
+        }
+    }
+
+    /// Calls `on_progress(bytes_consumed, total)` every `interval` tokens, see `lexer::Progress`
+    pub fn on_progress(
+        &mut self,
+        interval: usize,
+        on_progress: impl Fn(usize, usize) + Send + Sync + 'static,
+    ) {
+        self.progress = Some(Progress::new(interval, on_progress));
+    }
+
+    /// Adds a filter that runs after the previously added ones
+    pub fn add_token_filter(&mut self, filter: impl TokenFilter + 'static) {
+        self.token_filters.push(Arc::new(filter));
+    }
+
+    /// Registers a predicate for `grammar::Commands::External`, replacing the one with the same name
+    pub fn register_predicate(
+        &mut self,
+        name: impl Into<String>,
+        predicate: impl Predicate + 'static,
+    ) {
+        self.predicates.insert(name.into(), Arc::new(predicate));
+    }
+
+    /// Passes the tokens through all token filters
+    ///
+    /// The tokens are not copied if there are no filters
+    pub fn filter_tokens<'s, 't, T: TokenSource<'t> + ?Sized>(
+        &self,
+        text: &str,
+        tokens: &'s T,
+    ) -> FilteredTokens<'s, 't, T> {
+        if self.token_filters.is_empty() {
+            return FilteredTokens::Source(tokens);
+        }
+        let mut tokens = (0..tokens.len())
+            .map_while(|idx| tokens.get(idx))
+            .collect::<Vec<_>>();
+        for filter in &self.token_filters {
+            tokens = filter.filter(text, tokens);
+        }
+        FilteredTokens::Filtered(tokens)
+    }
+
+    /// Enables or disables a feature declared with `Grammar::feature`
+    pub fn set_feature(&mut self, name: &str, enabled: bool) {
+        self.features.insert(name.to_string(), enabled);
+    }
+
+    /// Returns whether the feature is enabled, undeclared features are disabled
+    pub fn feature_enabled(&self, grammar: &Grammar, name: &str) -> bool {
+        match self.features.get(name) {
+            Some(enabled) => *enabled,
+            None => grammar.features.get(name).copied().unwrap_or(false),
         }
     }
 
@@ -52,148 +437,755 @@ impl<'a> Parser<'a> {
         grammar: &'a Grammar<'a>,
         lexer: &Lexer,
         text: &'a str,
-        tokens: &Vec<Token<'a>>,
+        tokens: &dyn TokenSource<'a>,
     ) -> Result<ParseResult<'a>, ParseError<'a>> {
-        let mut cursor = Cursor {
-            idx: 0,
-            to_advance: false,
+        self.parse_with_state(grammar, lexer, text, tokens, ParseState::new())
+            .map_err(|err| *err)
+    }
+
+    /// Parses the text into the allocator, see `crate::Parser::parse_in`
+    pub(crate) fn parse_in(
+        &'a self,
+        grammar: &'a Grammar<'a>,
+        lexer: &Lexer,
+        text: &'a str,
+        tokens: &dyn TokenSource<'a>,
+        alloc: ParseAlloc,
+    ) -> Result<ParseResult<'a>, ParseError<'a>> {
+        let start = Start {
+            entry: self.entry,
+            choices: None,
+            next: None,
+            events: None,
+            state: ParseState::new(),
+            checkpoint: None,
+            alloc,
         };
-        let entry = match &self.entry {
-            Some(e) => e,
-            None => {
-                return Err(ParseError {
-                    kind: ParseErrors::MissingEntry,
-                    location: TextLocation::new(0, 0, 0, 0),
-                    node: None,
-                    hint: Some("Set an entry point in the parser"),
-                    importance: 0,
-                })
-            }
+        self.parse_with(grammar, lexer, text, tokens, start).0
+    }
+
+    /// Parses the text with the globals seeded from `state`, see `crate::Parser::parse_with_state`
+    pub(crate) fn parse_with_state(
+        &'a self,
+        grammar: &'a Grammar<'a>,
+        lexer: &Lexer,
+        text: &'a str,
+        tokens: &dyn TokenSource<'a>,
+        state: ParseState<'a>,
+    ) -> Result<ParseResult<'a>, Box<ParseError<'a>>> {
+        let start = Start {
+            entry: self.entry,
+            choices: None,
+            next: None,
+            events: None,
+            state,
+            checkpoint: None,
+            alloc: ParseAlloc::default(),
         };
-        let mut globals = Node::variables_from_grammar(&grammar.globals)?;
-        let entry = match self.parse_node(
+        self.parse_with(grammar, lexer, text, tokens, start)
+            .0
+            .map_err(Box::new)
+    }
+
+    /// Parses the text and pauses at every checkpoint, see `crate::Parser::parse_async`
+    #[cfg(feature = "async")]
+    pub(crate) fn parse_paused<'h>(
+        &'a self,
+        grammar: &'a Grammar<'a>,
+        lexer: &Lexer,
+        text: &'a str,
+        tokens: &dyn TokenSource<'a>,
+        checkpoint: Checkpoint<'h>,
+    ) -> Result<ParseResult<'a>, Box<ParseError<'a>>>
+    where
+        'a: 'h,
+    {
+        let start = Start {
+            entry: self.entry,
+            choices: None,
+            next: None,
+            events: None,
+            state: ParseState::new(),
+            checkpoint: Some(checkpoint),
+            alloc: ParseAlloc::default(),
+        };
+        self.parse_with(grammar, lexer, text, tokens, start)
+            .0
+            .map_err(Box::new)
+    }
+
+    /// Parses the text without keeping a tree, see `crate::Parser::parse_events`
+    pub(crate) fn parse_events(
+        &'a self,
+        grammar: &'a Grammar<'a>,
+        lexer: &Lexer,
+        text: &'a str,
+        tokens: &dyn TokenSource<'a>,
+        sink: &mut impl EventSink<'a>,
+    ) -> Result<(), Box<ParseError<'a>>> {
+        let start = Start {
+            entry: self.entry,
+            choices: None,
+            next: None,
+            events: Some(sink),
+            state: ParseState::new(),
+            checkpoint: None,
+            alloc: ParseAlloc::default(),
+        };
+        self.parse_with(grammar, lexer, text, tokens, start)
+            .0
+            .map(|_| ())
+            .map_err(Box::new)
+    }
+
+    /// Parses the tokens in `range` with `node` as the entry, see `crate::Parser::parse_node_at`
+    pub fn parse_node_at(
+        &'a self,
+        grammar: &'a Grammar<'a>,
+        lexer: &Lexer,
+        node: &'a str,
+        text: &'a str,
+        tokens: &dyn TokenSource<'a>,
+        range: core::ops::Range<usize>,
+    ) -> Result<ParseResult<'a>, Box<ParseError<'a>>> {
+        self.parse_node_at_in(
             grammar,
             lexer,
-            entry,
-            &mut cursor,
-            &mut globals,
-            tokens,
+            node,
             text,
-            false,
-        ) {
-            Ok(node) => {
-                if !grammar.eof {
-                    node
-                } else {
-                    if cursor.to_advance && cursor.idx < tokens.len() - 1 {
-                        cursor.to_advance = false;
-                        cursor.idx += 1;
+            tokens,
+            range,
+            ParseAlloc::default(),
+        )
+    }
+
+    /// Parses the tokens in `range` into the allocator, see `Parser::parse_node_at`
+    pub(crate) fn parse_node_at_in(
+        &'a self,
+        grammar: &'a Grammar<'a>,
+        lexer: &Lexer,
+        node: &'a str,
+        text: &'a str,
+        tokens: &dyn TokenSource<'a>,
+        range: core::ops::Range<usize>,
+        alloc: ParseAlloc,
+    ) -> Result<ParseResult<'a>, Box<ParseError<'a>>> {
+        if range.start > range.end || range.end > tokens.len() {
+            let span = tokens.last().map_or(0..0, |token| token.span());
+            return Err(Box::new(ParseError {
+                origin: None,
+                kind: ParseErrors::RangeOutOfBounds {
+                    range,
+                    len: tokens.len(),
+                },
+                span,
+                node: None,
+                hint: Some("Use token indexes of the tokens passed in"),
+                importance: 0,
+            }));
+        }
+        // the region ends where the next token starts
+        let eof = tokens.get(range.end).map(|next| Token {
+            index: next.index,
+            len: 0,
+            kind: TokenKinds::Control(ControlTokenKind::Eof),
+            tag: None,
+            data: None,
+        });
+        let region = Region { tokens, range, eof };
+        let start = Start {
+            entry: Some(node),
+            choices: None,
+            next: None,
+            events: None,
+            state: ParseState::new(),
+            checkpoint: None,
+            alloc,
+        };
+        self.parse_with(grammar, lexer, text, &region, start)
+            .0
+            .map_err(Box::new)
+    }
+
+    /// Parses the text with every combination of `IsOneOf` alternatives that match
+    ///
+    /// The first attempt makes the same choices as `parse`, the next ones differ in
+    /// at least one ambiguous choice. Alternatives are explored breadth first, closest
+    /// to the preferred parse first, until the limits of `Parser::exploration` are reached.
+    pub(crate) fn parse_all(
+        &'a self,
+        grammar: &'a Grammar<'a>,
+        lexer: &Lexer,
+        text: &'a str,
+        tokens: &dyn TokenSource<'a>,
+    ) -> Parses<'a> {
+        let mut parses = Parses {
+            results: Vec::new(),
+            ambiguities: Vec::new(),
+            error: None,
+            complete: true,
+        };
+        let mut queue = VecDeque::from([Vec::new()]);
+        let mut attempts = 0;
+        while let Some(script) = queue.pop_front() {
+            if attempts == self.exploration.max_attempts
+                || parses.results.len() == self.exploration.max_parses
+            {
+                parses.complete = false;
+                break;
+            }
+            attempts += 1;
+            let choices = Choices {
+                script,
+                taken: Vec::new(),
+                points: Vec::new(),
+            };
+            let start = Start {
+                entry: self.entry,
+                choices: Some(choices),
+                next: None,
+                events: None,
+                state: ParseState::new(),
+                checkpoint: None,
+                alloc: ParseAlloc::default(),
+            };
+            let (result, trail) = self.parse_with(grammar, lexer, text, tokens, start);
+            let choices = trail.choices.expect("exploration runs with choices");
+            // Every choice made after the script ran out took the first alternative
+            for (depth, ambiguity) in choices.points.iter().enumerate().skip(choices.script.len()) {
+                for alternative in 1..ambiguity.alternatives.len() {
+                    let mut next = choices.taken[..depth].to_vec();
+                    next.push(alternative);
+                    queue.push_back(next);
+                }
+            }
+            match result {
+                Ok(result) => {
+                    for ambiguity in choices.points {
+                        if !parses.ambiguities.iter().any(|known| {
+                            known.node == ambiguity.node && known.span == ambiguity.span
+                        }) {
+                            parses.ambiguities.push(ambiguity);
+                        }
                     }
-                    // If the grammar has an eof token, we need to check if the cursor is at the end of the tokens
-                    // Consume all the whitespace tokens
-                    while cursor.idx < tokens.len() - 1
-                        && (tokens[cursor.idx].kind.is_whitespace()
-                            || grammar.ignored.contains(&tokens[cursor.idx].kind))
-                    {
-                        cursor.idx += 1;
+                    parses.results.push(result);
+                }
+                Err(err) if attempts == 1 => parses.error = Some(err),
+                Err(_) => (),
+            }
+        }
+        parses
+    }
+
+    /// Suggestions for the text at the byte `offset`, see `crate::Parser::completions`
+    pub(crate) fn completions(
+        &'a self,
+        grammar: &'a Grammar<'a>,
+        lexer: &Lexer,
+        text: &'a str,
+        tokens: &dyn TokenSource<'a>,
+        offset: usize,
+    ) -> Vec<Completion<'a>> {
+        // the token being typed at the offset or the first one after it
+        let Some((target, found)) = tokens.iter().enumerate().find(|(_, token)| {
+            let end = token.index + token.len;
+            (end > offset
+                || token.index >= offset
+                || token.kind == TokenKinds::Text && end == offset)
+                && !token.kind.is_whitespace()
+                && !grammar.ignored.contains(&token.kind)
+        }) else {
+            return Vec::new();
+        };
+        let replace = match found.index < offset {
+            true => found.index..offset,
+            false => offset..offset,
+        };
+        let start = Start {
+            entry: self.entry,
+            choices: None,
+            next: Some(NextTokens {
+                target,
+                expected: Vec::new(),
+            }),
+            events: None,
+            state: ParseState::new(),
+            checkpoint: None,
+            alloc: ParseAlloc::default(),
+        };
+        let (_, trail) = self.parse_with(grammar, lexer, text, tokens, start);
+        let expected = trail.next.map_or_else(Vec::new, |next| next.expected);
+        let mut completions: Vec<Completion<'a>> = Vec::new();
+        let mut add = |label: &'a str, kind: CompletionKind, node: Option<&grammar::Node<'a>>| {
+            if !completions
+                .iter()
+                .any(|known| known.label == label && known.kind == kind)
+            {
+                completions.push(Completion {
+                    label,
+                    kind,
+                    snippet: node.and_then(|node| node.snippet),
+                    docs: node.and_then(|node| node.docs),
+                    replace: replace.clone(),
+                });
+            }
+        };
+        for token in expected {
+            let values = match token {
+                MatchToken::Enumerator(name) => match grammar.enumerators.get(name) {
+                    Some(enumerator) => enumerator.values.as_slice(),
+                    None => &[],
+                },
+                _ => core::slice::from_ref(&token),
+            };
+            for value in values {
+                match value {
+                    MatchToken::Node(name) => {
+                        if let Some(node) = grammar.node_of(*name) {
+                            if node.snippet.is_some() {
+                                add(node.name.as_str(), CompletionKind::Snippet, Some(node));
+                            }
+                        }
                     }
-                    if let TokenKinds::Control(crate::lexer::ControlTokenKind::Eof) =
-                        tokens[cursor.idx].kind
-                    {
+                    MatchToken::Word(word) => add(word, CompletionKind::Keyword, None),
+                    MatchToken::Token(TokenKinds::Token(token)) => {
+                        add(token, CompletionKind::Token, None)
+                    }
+                    _ => (),
+                }
+            }
+        }
+        completions
+    }
+
+    /// Parses the text as described by `start`
+    fn parse_with(
+        &'a self,
+        grammar: &'a Grammar<'a>,
+        lexer: &Lexer,
+        text: &'a str,
+        tokens: &dyn TokenSource<'a>,
+        start: Start<'a, '_>,
+    ) -> (Result<ParseResult<'a>, ParseError<'a>>, Trail<'a>) {
+        let Start {
+            entry,
+            choices,
+            next,
+            events,
+            state,
+            checkpoint,
+            alloc,
+        } = start;
+        let mut ctx = Context {
+            stats: self.profile.then(ParseStats::default),
+            backtracks: 0,
+            nodes: 0,
+            depth: 0,
+            out_of_budget: false,
+            choices,
+            #[cfg(feature = "std")]
+            timer: self.profile.then(|| RuleTimer::new(grammar)),
+            keywords: &[],
+            recovered: alloc.vec(),
+            next,
+            origin: Origin::Unknown,
+            events: events.map(|sink| Events::new(sink, self.recovery.is_some())),
+            progress: self
+                .progress
+                .as_ref()
+                .map_or(0, |progress| progress.interval),
+            checkpoint,
+            bracket_kinds: &grammar.brackets,
+            brackets: alloc.vec(),
+            pairs: alloc.vec(),
+            alloc,
+        };
+        let result = 'run: {
+            let mut cursor = Cursor {
+                idx: 0,
+                to_advance: false,
+                split: 0,
+            };
+            let entry = match entry {
+                Some(e) => e,
+                None => {
+                    break 'run Err(ParseError {
+                        origin: None,
+                        kind: ParseErrors::MissingEntry,
+                        span: 0..0,
+                        node: None,
+                        hint: Some("Set an entry point in the parser"),
+                        importance: 0,
+                    })
+                }
+            };
+            let mut globals = state;
+            if let Err(err) = globals.declare(&grammar.globals, alloc) {
+                break 'run Err(*err);
+            }
+            // a state carried over from an earlier parse starts with a fresh table
+            globals.scopes = Scopes::new();
+            let empty = tokens.iter().all(|token| {
+                token.kind.is_whitespace()
+                    || grammar.ignored.contains(&token.kind)
+                    || token.kind == TokenKinds::Control(ControlTokenKind::Eof)
+            });
+            // completions need the rules to run to see what is expected
+            if empty && ctx.next.is_none() {
+                let end = tokens.last().map_or(0, |eof| eof.index);
+                let mut node = match Node::from_grammar_in(grammar, entry, alloc) {
+                    Ok(node) => node,
+                    Err(err) => break 'run Err(err),
+                };
+                node.first_string_idx = end;
+                node.last_string_idx = end;
+                if !grammar.node(entry).is_some_and(|found| found.allow_empty) {
+                    break 'run Err(ParseError {
+                        origin: None,
+                        kind: ParseErrors::EmptyInput { span: end..end },
+                        span: end..end,
+                        node: Some(node),
+                        hint: Some("Set `allow_empty` on the entry node to accept empty input"),
+                        importance: 0,
+                    });
+                }
+                if let Some(events) = &mut ctx.events {
+                    events.push(Event::NodeStart {
+                        name: node.name.as_str(),
+                        start: end,
+                    });
+                    events.push(Event::NodeEnd {
+                        name: node.name.as_str(),
+                        span: end..end,
+                    });
+                }
+                break 'run Ok(ParseResult {
+                    entry: node,
+                    globals,
+                    stats: ctx.stats,
+                    errors: alloc.vec(),
+                    remaining: None,
+                    brackets: alloc.vec(),
+                    version: 0,
+                });
+            }
+            let mut remaining = None;
+            let Some(entry) = intern::Symbol::get(entry) else {
+                break 'run Err(Node::not_found(entry));
+            };
+            let entry = match self.parse_node(
+                grammar,
+                lexer,
+                entry,
+                &mut cursor,
+                &mut globals,
+                &mut ctx,
+                tokens,
+                text,
+                false,
+            ) {
+                Ok(node) => {
+                    if !grammar.eof {
+                        remaining = Self::remaining(grammar, &cursor, tokens);
                         node
                     } else {
-                        return Err(ParseError {
-                            kind: ParseErrors::MissingEof(tokens[cursor.idx].kind.clone()),
-                            location: tokens[cursor.idx].location,
-                            node: Some(node),
-                            hint: Some("Remove all unneccesary text from the end of file"),
-                            importance: 0,
-                        });
+                        if cursor.to_advance && cursor.step(tokens) {
+                            cursor.to_advance = false;
+                        }
+                        // If the grammar has an eof token, we need to check if the cursor is at the end of the tokens
+                        // Consume all the whitespace tokens
+                        while (cursor.peek(tokens).kind.is_whitespace()
+                            || grammar.ignored.contains(&cursor.peek(tokens).kind))
+                            && cursor.step(tokens)
+                        {}
+                        let found = cursor.peek(tokens);
+                        if let TokenKinds::Control(crate::lexer::ControlTokenKind::Eof) = found.kind
+                        {
+                            node
+                        } else {
+                            break 'run Err(ParseError {
+                                origin: None,
+                                kind: ParseErrors::MissingEof {
+                                    found: found.kind,
+                                    leftover: found.index
+                                        ..tokens.last().map_or(found.index, |t| t.index),
+                                },
+                                span: found.span(),
+                                node: Some(node),
+                                hint: Some("Remove all unneccesary text from the end of file"),
+                                importance: 0,
+                            });
+                        }
                     }
                 }
-            }
-            Err(err) => return Err(err.1),
+                Err(err) => break 'run Err(err.1),
+            };
+
+            let mut brackets = core::mem::replace(&mut ctx.pairs, alloc.vec());
+            brackets.sort_by_key(|pair| pair.open.start);
+            Ok(ParseResult {
+                entry,
+                globals,
+                stats: ctx.stats,
+                errors: core::mem::replace(&mut ctx.recovered, alloc.vec()),
+                remaining,
+                brackets,
+                version: 0,
+            })
+        };
+        if let (Some(progress), Ok(_)) = (&self.progress, &result) {
+            progress.report(text.len(), text.len());
+        }
+        if let (Some(events), Ok(_)) = (&mut ctx.events, &result) {
+            events.flush();
+        }
+        let trail = Trail {
+            choices: ctx.choices,
+            next: ctx.next,
         };
+        (result, trail)
+    }
+
+    /// Text left after the entry node, see `ParseResult::remaining`
+    fn remaining(
+        grammar: &Grammar,
+        cursor: &Cursor,
+        tokens: &dyn TokenSource,
+    ) -> Option<Remaining> {
+        let start = cursor.next_idx();
+        let mut left = tokens.range(start..tokens.len()).filter(|token| {
+            !token.kind.is_whitespace()
+                && !grammar.ignored.contains(&token.kind)
+                && token.kind != TokenKinds::Control(ControlTokenKind::Eof)
+        });
+        let first = left.next()?;
+        let (count, last) = left.fold((1, first), |(count, _), token| (count + 1, token));
+        Some(Remaining {
+            tokens: count,
+            span: first.index..last.index + last.len,
+        })
+    }
 
-        Ok(ParseResult { entry, globals })
+    /// Pairs the brackets the node matched itself, see `ParseResult::brackets`
+    ///
+    /// A closing bracket closes the last open bracket of its kind, the brackets opened
+    /// after that one stay unpaired. Brackets without a partner in the node are dropped.
+    fn pair_brackets(ctx: &mut Context<'a, '_>, mark: usize) {
+        let mut open: Vec<&Bracket> = Vec::new();
+        for bracket in &ctx.brackets[mark..] {
+            if bracket.open {
+                open.push(bracket);
+                continue;
+            }
+            let Some(i) = open.iter().rposition(|o| o.kind == bracket.kind) else {
+                continue;
+            };
+            let start = open.swap_remove(i);
+            open.truncate(i);
+            ctx.pairs.push(BracketPair {
+                open: start.span.clone(),
+                close: bracket.span.clone(),
+            });
+        }
+        ctx.brackets.truncate(mark);
+    }
+
+    fn record_alternative(ctx: &mut Context<'a, '_>, one_of: &OneOf<'a>) {
+        if let Some(stats) = &mut ctx.stats {
+            *stats
+                .alternatives
+                .entry(one_of as *const OneOf as usize)
+                .or_default() += 1;
+        }
     }
 
     fn parse_node(
         &'a self,
         grammar: &'a Grammar<'a>,
         lexer: &Lexer,
-        name: &'a str,
+        name: intern::Symbol,
         cursor: &mut Cursor,
-        globals: &mut Map<String, VariableKind<'a>>,
-        tokens: &Vec<Token<'a>>,
+        globals: &mut ParseState<'a>,
+        ctx: &mut Context<'a, '_>,
+        tokens: &dyn TokenSource<'a>,
         text: &'a str,
         auto_commit: bool,
     ) -> Result<Node<'a>, (bool, ParseError<'a>)> {
         #[cfg(feature = "debug")]
         println!("-- start, cursor: {:?}", cursor);
-        let mut node = match Node::from_grammar(grammar, name) {
-            Ok(node) => node,
-            Err(err) => return Err((false, err)),
+        #[cfg(feature = "std")]
+        let start = ctx.stats.is_some().then(std::time::Instant::now);
+        let Some(found) = grammar.node_of(name) else {
+            return Err((false, Node::not_found(name.as_str())));
         };
+        let mut node = Node::from_grammar_node_in(found, ctx.alloc);
         node.commit = auto_commit;
-        let peek = Self::next_non_whitespace(&tokens[cursor.idx..], &grammar.ignored).unwrap_or(0);
-        let safe_idx = (cursor.idx + peek).min(tokens.len().saturating_sub(1));
-        node.first_string_idx = tokens[safe_idx].index;
-        // In case the node fails to parse, we want to restore the cursor to its original position
-        let cursor_clone = cursor.clone();
-        let rules = match grammar.nodes.get(name) {
-            Some(node) => &node.rules,
-            None => {
+        ctx.nodes += 1;
+        if let Some(limit) = self.max_nodes {
+            if ctx.nodes > limit {
+                // no alternative is tried after this error
+                ctx.out_of_budget = true;
                 return Err((
-                    node.commit,
+                    true,
                     ParseError {
-                        kind: ParseErrors::NodeNotFound(name),
-                        location: tokens[cursor.idx].location,
-                        node: Some(node.clone()),
-                        hint: Some("Please run the parser through validator with .success()"),
+                        origin: None,
+                        kind: ParseErrors::NodeLimit(limit),
+                        span: cursor.peek(tokens).span(),
+                        node: Some(node),
+                        hint: Some("Raise `Parser::max_nodes` if the input is not pathological"),
                         importance: 0,
                     },
-                ))
+                ));
             }
-        };
-        let result = self.parse_rules(
-            grammar,
-            lexer,
-            rules,
-            cursor,
-            globals,
-            &cursor_clone,
-            &mut node,
-            tokens,
-            text,
-        );
+        }
+        let peek = Self::next_non_whitespace(cursor.rest(tokens), &grammar.ignored).unwrap_or(0);
+        let safe_idx = cursor.index(tokens, peek);
+        node.first_string_idx = cursor.peek_nth(tokens, peek).index;
+        if let Some(limit) = self.max_nesting {
+            if ctx.depth >= limit {
+                // the parents would fail the same way in every alternative
+                ctx.out_of_budget = true;
+                let first = cursor.peek_nth(tokens, peek);
+                return Err((
+                    true,
+                    ParseError {
+                        origin: None,
+                        kind: ParseErrors::TooDeep {
+                            limit,
+                            span: first.index..first.index + first.len,
+                        },
+                        span: first.span(),
+                        node: Some(node),
+                        hint: Some("Raise `Parser::max_nesting` if the input is not pathological"),
+                        importance: 0,
+                    },
+                ));
+            }
+        }
+        // In case the node fails to parse, we want to restore the cursor to its original position
+        let cursor_clone = cursor.clone();
+        let scope_mark = globals.scopes.mark();
+        #[cfg(feature = "std")]
+        if let (Some(timer), Some(stats)) = (&mut ctx.timer, &mut ctx.stats) {
+            timer.enter(stats, name.as_str());
+        }
+        if let Some(events) = &mut ctx.events {
+            // the values are reported as events, the node only keeps what its rules read
+            node.children = None;
+            node.tokens = None;
+            events.enter(
+                Event::NodeStart {
+                    name: name.as_str(),
+                    start: node.first_string_idx,
+                },
+                node.commit,
+            );
+        }
+        let outer_keywords = core::mem::replace(&mut ctx.keywords, &found.keywords);
+        let outer_origin = ctx.origin;
+        let recovered_mark = ctx.recovered.len();
+        let brackets_mark = (ctx.brackets.len(), ctx.pairs.len());
+        ctx.depth += 1;
+        let result = self
+            .parse_rules(
+                grammar,
+                lexer,
+                &found.rules,
+                cursor,
+                globals,
+                ctx,
+                &cursor_clone,
+                &mut node,
+                tokens,
+                text,
+            )
+            .map_err(|mut err| {
+                if err.kind.is_developer_error() && ctx.origin != Origin::Unknown {
+                    err.origin.get_or_insert(ctx.origin);
+                }
+                err
+            });
+        ctx.origin = outer_origin;
+        #[cfg(feature = "std")]
+        if let (Some(timer), Some(stats)) = (&mut ctx.timer, &mut ctx.stats) {
+            timer.leave(stats);
+        }
+        ctx.keywords = outer_keywords;
+        ctx.depth -= 1;
 
         #[cfg(feature = "debug")]
         println!("-- end: {}, cursor: {:?}", node.name, cursor);
 
         // If the node has not set the last_string_idx, we set it to the end of the last token
+        let last_idx = if cursor.to_advance || cursor.split > 0 {
+            cursor.index(tokens, 0)
+        } else {
+            cursor.prev_index(tokens)
+        };
         if node.last_string_idx == 0 {
-            if cursor.idx >= tokens.len() {
-                node.last_string_idx = tokens.last().unwrap().index + tokens.last().unwrap().len;
-            } else {
-                let idx = if cursor.to_advance {
-                    cursor.idx
-                } else {
-                    cursor.idx.saturating_sub(1)
-                };
-                node.last_string_idx = tokens[idx].index + tokens[idx].len;
+            let last = Cursor::token(tokens, last_idx);
+            // the node ends with the start of a token split by `Parameters::Split`
+            let len = match cursor.split {
+                0 => last.len,
+                split => split,
+            };
+            node.last_string_idx = last.index + len;
+        }
+
+        if let (Some(policy), Ok(_), None) = (grammar.trivia, &result, &ctx.events) {
+            if last_idx >= safe_idx {
+                node.trivia = Some(ctx.alloc.boxed(Trivia::around(
+                    policy,
+                    tokens,
+                    &grammar.ignored,
+                    safe_idx,
+                    last_idx,
+                    ctx.alloc,
+                )));
             }
         }
 
-        match result {
+        if let Some(stats) = &mut ctx.stats {
+            let entry = stats.nodes.entry(name.as_str()).or_default();
+            entry.invocations += 1;
+            match (&result, node.commit) {
+                (Ok(Msg::Ok | Msg::Return), _) => entry.successes += 1,
+                (_, true) => entry.failures += 1,
+                (_, false) => entry.backtracks += 1,
+            }
+            #[cfg(feature = "std")]
+            if let Some(start) = start {
+                entry.time += start.elapsed();
+            }
+        }
+
+        let mut result = match result {
             Ok(ref msg) => match msg {
-                Msg::Ok => Ok(node),
-                Msg::Return => Ok(node),
+                Msg::Ok | Msg::Return => match self.version {
+                    Some(version) if !version.supports(found) => Err((
+                        true,
+                        ParseError {
+                            origin: None,
+                            kind: ParseErrors::UnsupportedVersion {
+                                node: name.as_str(),
+                                since: found.since,
+                                until: found.until,
+                                version,
+                            },
+                            span: cursor.peek_nth(tokens, peek).span(),
+                            node: Some(node.clone()),
+                            hint: None,
+                            importance: 0,
+                        },
+                    )),
+                    _ => Ok(node),
+                },
                 Msg::Break(n) => Err((
                     node.commit,
                     ParseError {
+                        origin: None,
                         kind: ParseErrors::CannotBreak(*n),
-                        location: tokens[cursor.idx].location,
+                        span: cursor.peek(tokens).span(),
                         node: Some(node.clone()),
                         hint: None,
                         importance: 0,
@@ -202,8 +1194,9 @@ impl<'a> Parser<'a> {
                 Msg::Back(steps) => Err((
                     node.commit,
                     ParseError {
+                        origin: None,
                         kind: ParseErrors::CannotGoBack(*steps),
-                        location: tokens[cursor.idx].location,
+                        span: cursor.peek(tokens).span(),
                         node: Some(node.clone()),
                         hint: None,
                         importance: 0,
@@ -212,8 +1205,9 @@ impl<'a> Parser<'a> {
                 Msg::Goto(label) => Err((
                     node.commit,
                     ParseError {
+                        origin: None,
                         kind: ParseErrors::LabelNotFound(label.to_string()),
-                        location: tokens[cursor.idx].location,
+                        span: cursor.peek(tokens).span(),
                         node: Some(node.clone()),
                         hint: None,
                         importance: 0,
@@ -224,24 +1218,205 @@ impl<'a> Parser<'a> {
                 #[cfg(feature = "debug")]
                 println!("error: {:?}", err);
                 *cursor = cursor_clone;
+                globals.scopes.reset(scope_mark);
                 if err.node.is_none() {
                     err.node = Some(node.clone());
                 }
-                Err((node.commit, err))
+                if !node.commit && !ctx.out_of_budget {
+                    ctx.backtracks += 1;
+                    if let Some(limit) = self.backtrack_limit {
+                        if ctx.backtracks > limit {
+                            ctx.out_of_budget = true;
+                            err = ParseError {
+                                origin: None,
+                                kind: ParseErrors::BacktrackLimit(limit),
+                                span: err.span,
+                                node: err.node,
+                                hint: Some("Commit or cut alternatives once they can not fail"),
+                                importance: 0,
+                            };
+                        }
+                    }
+                }
+                // Once the budget runs out, no other alternative is tried
+                Err((node.commit || ctx.out_of_budget, err))
+            }
+        };
+        if let Some(events) = &mut ctx.events {
+            match &mut result {
+                Ok(node) => {
+                    events.push(Event::NodeEnd {
+                        name: name.as_str(),
+                        span: node.first_string_idx..node.last_string_idx,
+                    });
+                    events.leave(true);
+                    // the parent only needs the name and the span, the events describe the rest
+                    node.variables.clear();
+                }
+                Err(_) => events.leave(false),
+            }
+        }
+        // the parser backtracks over a failed node, errors recovered inside it never happened
+        match result {
+            Ok(_) => Self::pair_brackets(ctx, brackets_mark.0),
+            Err(_) => {
+                ctx.recovered.truncate(recovered_mark);
+                ctx.brackets.truncate(brackets_mark.0);
+                ctx.pairs.truncate(brackets_mark.1);
+            }
+        }
+        if let (Some(materialize), Ok(node)) = (&self.materialize, &mut result) {
+            if !materialize.contains(&name.as_str()) {
+                Self::reduce(node, materialize);
+            }
+        }
+        result
+    }
+
+    /// Drops everything of a node that does not lead to a materialized node,
+    /// see `Parser::materialize`
+    fn reduce(node: &mut Node<'a>, materialize: &[&str]) {
+        // nested nodes are already reduced, so only their own variables need a look
+        let leads = |value: &Nodes| match value {
+            Nodes::Node(child) => {
+                materialize.contains(&child.name.as_str())
+                    || child.variables.values().any(|variable| match variable {
+                        VariableKind::Node(value) => value.is_some(),
+                        VariableKind::NodeList(list) => !list.is_empty(),
+                        _ => false,
+                    })
+            }
+            Nodes::Error { .. } => true,
+            Nodes::Token(_) => false,
+        };
+        for variable in node.variables.values_mut() {
+            match variable {
+                VariableKind::Node(value) if !value.as_ref().is_some_and(leads) => *value = None,
+                VariableKind::NodeList(list) => list.retain(leads),
+                _ => (),
+            }
+        }
+        node.fields.clear();
+        node.children = None;
+        node.tokens = None;
+        node.trivia = None;
+    }
+
+    /// Skips the tokens of a failed node up to a synchronization token, see `Recovery`
+    ///
+    /// The sync tokens of the node matched by `token` are used if it has any,
+    /// see `grammar::Node::sync`
+    ///
+    /// Returns None if recovery is disabled or there is nothing left to skip
+    fn recover(
+        &self,
+        grammar: &Grammar<'a>,
+        token: &MatchToken<'a>,
+        err: &ParseError<'a>,
+        cursor: &mut Cursor,
+        tokens: &dyn TokenSource<'a>,
+        ctx: &mut Context<'a, '_>,
+    ) -> Option<Nodes<'a>> {
+        let recovery = self.recovery.as_ref()?;
+        if ctx.out_of_budget {
+            return None;
+        }
+        let sync = match token {
+            MatchToken::Node(name) => grammar.node_of(*name).map(|node| &node.sync),
+            _ => None,
+        }
+        .filter(|sync| !sync.is_empty())
+        .unwrap_or(&recovery.sync);
+        let mut idx = cursor.next_idx();
+        let mut children = ctx.alloc.vec();
+        let mut synced = false;
+        while let Some(token) = tokens.get(idx) {
+            if token.kind == TokenKinds::Control(ControlTokenKind::Eof) {
+                break;
+            }
+            if !token.kind.is_whitespace() && !grammar.ignored.contains(&token.kind) {
+                children.push(Nodes::Token(token));
+                if sync.contains(&token.kind) {
+                    synced = true;
+                    break;
+                }
+            }
+            idx += 1;
+        }
+        let start = children.first()?.str_idx();
+        let last = children.last()?.token_unchecked();
+        let end = last.index + last.len;
+        // A consumed sync token is treated like a matched token
+        cursor.seek(tokens, idx);
+        cursor.to_advance = synced;
+        cursor.split = 0;
+        ctx.recovered.push(err.clone());
+        let expected = match &err.kind {
+            ParseErrors::ExpectedToken { expected, .. } => &[MatchToken::Token(*expected)][..],
+            ParseErrors::ExpectedWord { expected, .. } => &[MatchToken::Word(expected)],
+            ParseErrors::ExpectedChar { expected, .. } => &[*expected],
+            ParseErrors::ExpectedOneOf { expected, .. } => expected,
+            ParseErrors::CouldNotFindToken(expected) => &[*expected],
+            ParseErrors::Keyword(_) => &[MatchToken::Token(TokenKinds::Text)],
+            _ => &[],
+        };
+        let expected = ctx.alloc.collect(expected.iter().copied());
+        Some(Nodes::Error {
+            span: start..end,
+            expected,
+            children,
+        })
+    }
+
+    /// Replaces the value of a number or boolean variable with a value of the same kind
+    ///
+    /// Returns the error if the variable is missing or holds another kind of value
+    fn set_value(
+        var: &VarKind<'a>,
+        value: VariableKind<'a>,
+        node: &mut Node<'a>,
+        globals: &mut ParseState<'a>,
+    ) -> Option<ParseErrors<'a>> {
+        match var.get_mut(&mut node.variables, &mut globals.values) {
+            Some(current)
+                if core::mem::discriminant(current) == core::mem::discriminant(&value) =>
+            {
+                *current = value;
+                None
+            }
+            Some(current) => Some(ParseErrors::CannotSetVariable(*var, current.clone())),
+            None => Some(ParseErrors::VariableNotFound(*var)),
+        }
+    }
+
+    /// Records the matched value if the node keeps its children or tokens
+    fn record_child(ctx: &mut Context<'a, '_>, node: &mut Node<'a>, value: &Nodes<'a>) {
+        if let (Some(events), Nodes::Token(token)) = (&mut ctx.events, value) {
+            events.push(Event::TokenMatched(*token));
+        }
+        if let Nodes::Token(token) = value {
+            for (kind, (open, close)) in ctx.bracket_kinds.iter().enumerate() {
+                if token.kind == *open || token.kind == *close {
+                    ctx.brackets.push(Bracket {
+                        kind,
+                        open: token.kind == *open,
+                        span: token.span(),
+                    });
+                    break;
+                }
             }
         }
+        if let Some(children) = &mut node.children {
+            children.push(value.clone());
+        }
+        if let (Some(tokens), Nodes::Token(token)) = (&mut node.tokens, value) {
+            tokens.push(*token);
+        }
     }
 
-    fn try_set_text_start_index(
-        node: &mut Node,
-        matched: &Nodes,
-        tokens: &[Token],
-        cursor: &Cursor,
-    ) {
+    fn try_set_text_start_index(node: &mut Node, matched: &Nodes) {
         if !node.encoutered_first_match {
             node.first_string_idx = matched.str_idx();
-            let safe_idx = cursor.idx.min(tokens.len().saturating_sub(1));
-            node.location = tokens[safe_idx].location;
             node.encoutered_first_match = true;
         }
     }
@@ -252,25 +1427,57 @@ impl<'a> Parser<'a> {
         lexer: &Lexer,
         rules: &'a Vec<grammar::Rule<'a>>,
         cursor: &mut Cursor,
-        globals: &mut Map<String, VariableKind<'a>>,
+        globals: &mut ParseState<'a>,
+        ctx: &mut Context<'a, '_>,
         cursor_clone: &Cursor,
         node: &mut Node<'a>,
-        tokens: &Vec<Token<'a>>,
+        tokens: &dyn TokenSource<'a>,
         text: &'a str,
     ) -> Result<Msg, ParseError<'a>> {
+        let outer_cut = core::mem::replace(&mut node.cut, false);
+        let outer_origin = ctx.origin;
         let mut advance = true;
         let mut msg_bus = MsgBus::new();
         let mut i = 0;
         while i < rules.len() {
             let rule = &rules[i];
+            ctx.origin = rule.origin();
+            #[cfg(feature = "std")]
+            if let (Some(timer), Some(stats)) = (&mut ctx.timer, &mut ctx.stats) {
+                timer.rule(stats, rule);
+            }
+            if self.cancellation.as_ref().is_some_and(|c| c.is_cancelled()) {
+                // no alternative is tried after this error
+                ctx.out_of_budget = true;
+                return Err(ParseError {
+                    origin: None,
+                    kind: ParseErrors::Cancelled,
+                    span: cursor.peek(tokens).span(),
+                    node: Some(node.clone()),
+                    hint: None,
+                    importance: 0,
+                });
+            }
+            if let Some(checkpoint) = &mut ctx.checkpoint {
+                checkpoint.rule();
+            }
+            if let Some(events) = &mut ctx.events {
+                events.commit(node.commit);
+            }
+            if let Some(progress) = &self.progress {
+                if cursor.next_idx() >= ctx.progress {
+                    ctx.progress = cursor.next_idx() + progress.interval;
+                    progress.report(cursor.peek(tokens).index, text.len());
+                }
+            }
             if cursor.to_advance {
                 cursor.to_advance = false;
-                cursor.idx += 1;
-                if cursor.idx >= tokens.len() {
+                if !cursor.advance(tokens) {
                     if self.eof_error {
                         return Err(ParseError {
+                            origin: None,
                             kind: ParseErrors::Eof,
-                            location: tokens[cursor.idx - 1].location,
+                            span: cursor.peek(tokens).span(),
                             node: Some(node.clone()),
                             hint: None,
                             importance: 0,
@@ -281,8 +1488,8 @@ impl<'a> Parser<'a> {
             #[cfg(feature = "debug")]
             println!(
                 "tok: <{}> kind: {:?} -- parent: {}",
-                &tokens[cursor.idx].stringify(text),
-                tokens[cursor.idx].kind,
+                cursor.peek(tokens).stringify(text),
+                cursor.peek(tokens).kind,
                 node.name
             );
             #[cfg(feature = "debug")]
@@ -293,6 +1500,7 @@ impl<'a> Parser<'a> {
                     token,
                     rules,
                     parameters,
+                    ..
                 } => {
                     match self.match_token(
                         grammar,
@@ -300,6 +1508,7 @@ impl<'a> Parser<'a> {
                         token,
                         cursor,
                         globals,
+                        ctx,
                         cursor_clone,
                         tokens,
                         Some(parameters),
@@ -308,10 +1517,12 @@ impl<'a> Parser<'a> {
                     )? {
                         TokenCompare::Is(val) => {
                             let is_token = val.is_token();
+                            Self::record_child(ctx, node, &val);
                             self.parse_parameters(
                                 parameters,
                                 cursor,
                                 globals,
+                                ctx,
                                 node,
                                 &val,
                                 &mut msg_bus,
@@ -327,13 +1538,14 @@ impl<'a> Parser<'a> {
                                 rules,
                                 cursor,
                                 globals,
+                                ctx,
                                 cursor_clone,
                                 node,
                                 tokens,
                                 text,
                             )?
                             .push(&mut msg_bus);
-                            Self::try_set_text_start_index(node, &val, tokens, &cursor);
+                            Self::try_set_text_start_index(node, &val);
                         }
                         TokenCompare::IsNot(err) => {
                             return Err(err);
@@ -344,6 +1556,7 @@ impl<'a> Parser<'a> {
                     token,
                     rules,
                     parameters,
+                    ..
                 } => {
                     match self.match_token(
                         grammar,
@@ -351,6 +1564,7 @@ impl<'a> Parser<'a> {
                         token,
                         cursor,
                         globals,
+                        ctx,
                         cursor_clone,
                         tokens,
                         None,
@@ -358,15 +1572,12 @@ impl<'a> Parser<'a> {
                         false,
                     )? {
                         TokenCompare::Is(_) => {
-                            let safe_idx = cursor.idx.min(tokens.len().saturating_sub(1));
-                            let kind = tokens.get(cursor.idx).map(|t| t.kind.clone()).unwrap_or(
-                                TokenKinds::Control(crate::lexer::ControlTokenKind::Eof),
-                            );
+                            let found = cursor.peek(tokens);
                             err(
-                                ParseErrors::ExpectedToNotBe(kind),
+                                ParseErrors::ExpectedToNotBe(found.kind),
                                 cursor,
                                 cursor_clone,
-                                &tokens[safe_idx].location,
+                                found.span(),
                                 Some(node.clone()),
                                 Some(&parameters),
                             )?;
@@ -378,6 +1589,7 @@ impl<'a> Parser<'a> {
                                 rules,
                                 cursor,
                                 globals,
+                                ctx,
                                 cursor_clone,
                                 node,
                                 tokens,
@@ -392,6 +1604,7 @@ impl<'a> Parser<'a> {
                     is,
                     isnt,
                     parameters,
+                    ..
                 } => {
                     match self.match_token(
                         grammar,
@@ -399,6 +1612,7 @@ impl<'a> Parser<'a> {
                         token,
                         cursor,
                         globals,
+                        ctx,
                         cursor_clone,
                         tokens,
                         Some(parameters),
@@ -410,6 +1624,7 @@ impl<'a> Parser<'a> {
                                 parameters,
                                 cursor,
                                 globals,
+                                ctx,
                                 node,
                                 &val,
                                 &mut msg_bus,
@@ -422,6 +1637,7 @@ impl<'a> Parser<'a> {
                                 is,
                                 cursor,
                                 globals,
+                                ctx,
                                 cursor_clone,
                                 node,
                                 tokens,
@@ -436,6 +1652,7 @@ impl<'a> Parser<'a> {
                                 isnt,
                                 cursor,
                                 globals,
+                                ctx,
                                 cursor_clone,
                                 node,
                                 tokens,
@@ -449,14 +1666,71 @@ impl<'a> Parser<'a> {
                 grammar::Rule::IsOneOf {
                     tokens: pos_tokens,
                     parameters,
+                    ..
                 } => {
                     let mut found = false;
                     let mut best_err: Option<ParseError<'_>> = None;
-                    for OneOf {
+                    let mut candidates = Vec::new();
+                    let mut alternatives = 0..pos_tokens.len();
+                    if let Some(mut choices) = ctx.choices.take() {
+                        // Only the first token of every alternative is tried here, the
+                        // choices inside of it are made the usual way
+                        let mut matching = Vec::new();
+                        for (i, one_of) in pos_tokens.iter().enumerate() {
+                            let mut trial = cursor.clone();
+                            let mut trial_globals = globals.clone();
+                            let snapshot = ctx.snapshot();
+                            let matched = self.match_token(
+                                grammar,
+                                lexer,
+                                &one_of.token,
+                                &mut trial,
+                                &mut trial_globals,
+                                ctx,
+                                cursor_clone,
+                                tokens,
+                                Some(&one_of.parameters),
+                                text,
+                                false,
+                            );
+                            ctx.restore(&snapshot);
+                            if let Ok(TokenCompare::Is(_)) = matched {
+                                matching.push(i);
+                            }
+                        }
+                        if matching.len() > 1 {
+                            // A scripted choice takes only its alternative, the attempt fails with it,
+                            // once the script runs out the first one is preferred like in `parse`
+                            let pick = match choices.script.get(choices.taken.len()) {
+                                Some(&pick) => {
+                                    alternatives = matching[pick]..matching[pick] + 1;
+                                    pick
+                                }
+                                None => {
+                                    alternatives = matching[0]..pos_tokens.len();
+                                    0
+                                }
+                            };
+                            let peek =
+                                Self::next_non_whitespace(cursor.rest(tokens), &grammar.ignored)
+                                    .unwrap_or(0);
+                            choices.taken.push(pick);
+                            choices.points.push(Ambiguity {
+                                node: node.name.as_str(),
+                                span: cursor.peek_nth(tokens, peek).span(),
+                                alternatives: matching
+                                    .iter()
+                                    .map(|i| pos_tokens[*i].token)
+                                    .collect(),
+                            });
+                        }
+                        ctx.choices = Some(choices);
+                    }
+                    for one_of @ OneOf {
                         token,
                         rules,
                         parameters,
-                    } in pos_tokens
+                    } in &pos_tokens[alternatives]
                     {
                         use TokenCompare::*;
                         #[cfg(feature = "debug")]
@@ -467,6 +1741,7 @@ impl<'a> Parser<'a> {
                             token,
                             cursor,
                             globals,
+                            ctx,
                             cursor_clone,
                             tokens,
                             Some(parameters),
@@ -474,14 +1749,17 @@ impl<'a> Parser<'a> {
                             false,
                         )? {
                             Is(val) => {
+                                Self::record_alternative(ctx, one_of);
                                 #[cfg(feature = "debug")]
                                 println!("success");
                                 found = true;
                                 let is_token = val.is_token();
+                                Self::record_child(ctx, node, &val);
                                 self.parse_parameters(
                                     parameters,
                                     cursor,
                                     globals,
+                                    ctx,
                                     node,
                                     &val,
                                     &mut msg_bus,
@@ -497,13 +1775,14 @@ impl<'a> Parser<'a> {
                                     rules,
                                     cursor,
                                     globals,
+                                    ctx,
                                     cursor_clone,
                                     node,
                                     tokens,
                                     text,
                                 )?
                                 .push(&mut msg_bus);
-                                Self::try_set_text_start_index(node, &val, tokens, &cursor);
+                                Self::try_set_text_start_index(node, &val);
                                 break;
                             }
                             IsNot(err) => match err.node {
@@ -518,6 +1797,8 @@ impl<'a> Parser<'a> {
                                             >= best_err.as_ref().map(|e| e.importance).unwrap_or(0)
                                     {
                                         best_err = Some(err);
+                                    } else {
+                                        candidates.push(err);
                                     }
                                 }
                                 None => {
@@ -529,26 +1810,30 @@ impl<'a> Parser<'a> {
                                             >= best_err.as_ref().map(|e| e.importance).unwrap_or(0)
                                     {
                                         best_err = Some(err);
+                                    } else {
+                                        candidates.push(err);
                                     }
                                 }
                             },
                         }
                     }
                     if !found {
-                        let safe_cursor = cursor.idx.min(tokens.len().saturating_sub(1));
-                        let peek =
-                            Self::next_non_whitespace(&tokens[safe_cursor..], &grammar.ignored)
-                                .unwrap_or(0);
-                        let err_idx = cursor.idx + peek;
-                        let safe_err_idx = err_idx.min(tokens.len().saturating_sub(1));
-                        let kind = tokens
-                            .get(err_idx)
-                            .map(|t| t.kind.clone())
-                            .unwrap_or(TokenKinds::Control(crate::lexer::ControlTokenKind::Eof));
+                        let peek = Self::next_non_whitespace(cursor.rest(tokens), &grammar.ignored)
+                            .unwrap_or(0);
+                        let found = cursor.peek_nth(tokens, peek);
+                        let kind = match cursor.at_end(tokens) {
+                            true => TokenKinds::Control(crate::lexer::ControlTokenKind::Eof),
+                            false => found.kind,
+                        };
 
                         match best_err {
                             Some(e) => return Err(e),
                             None => {
+                                // Only alternatives that got past the first token are worth showing
+                                let start = found.index;
+                                candidates.retain(|c| c.span.start > start);
+                                candidates.sort_by_key(|c| core::cmp::Reverse(c.span.start));
+                                candidates.truncate(self.max_candidates);
                                 err(
                                     ParseErrors::ExpectedOneOf {
                                         expected: pos_tokens
@@ -556,10 +1841,11 @@ impl<'a> Parser<'a> {
                                             .map(|x| x.token.clone())
                                             .collect(),
                                         found: kind,
+                                        candidates,
                                     },
                                     cursor,
                                     cursor_clone,
-                                    &tokens[safe_err_idx].location,
+                                    found.span(),
                                     Some(node.clone()),
                                     Some(&parameters),
                                 )?;
@@ -572,6 +1858,7 @@ impl<'a> Parser<'a> {
                     is,
                     isnt,
                     parameters,
+                    ..
                 } => {
                     use TokenCompare::*;
                     match self.match_token(
@@ -580,6 +1867,7 @@ impl<'a> Parser<'a> {
                         token,
                         cursor,
                         globals,
+                        ctx,
                         cursor_clone,
                         tokens,
                         Some(parameters),
@@ -588,10 +1876,12 @@ impl<'a> Parser<'a> {
                     )? {
                         Is(val) => {
                             let is_token = val.is_token();
+                            Self::record_child(ctx, node, &val);
                             self.parse_parameters(
                                 parameters,
                                 cursor,
                                 globals,
+                                ctx,
                                 node,
                                 &val,
                                 &mut msg_bus,
@@ -607,13 +1897,14 @@ impl<'a> Parser<'a> {
                                 is,
                                 cursor,
                                 globals,
+                                ctx,
                                 cursor_clone,
                                 node,
                                 tokens,
                                 text,
                             )?
                             .push(&mut msg_bus);
-                            Self::try_set_text_start_index(node, &val, tokens, &cursor);
+                            Self::try_set_text_start_index(node, &val);
                         }
                         IsNot(err) => {
                             if let Some(ref node) = err.node {
@@ -627,6 +1918,7 @@ impl<'a> Parser<'a> {
                                 isnt,
                                 cursor,
                                 globals,
+                                ctx,
                                 cursor_clone,
                                 node,
                                 tokens,
@@ -636,9 +1928,11 @@ impl<'a> Parser<'a> {
                         }
                     }
                 }
-                grammar::Rule::MaybeOneOf { is_one_of, isnt } => {
+                grammar::Rule::MaybeOneOf {
+                    is_one_of, isnt, ..
+                } => {
                     let mut found = false;
-                    for OneOf {
+                    for one_of @ OneOf {
                         token,
                         rules,
                         parameters,
@@ -651,6 +1945,7 @@ impl<'a> Parser<'a> {
                             token,
                             cursor,
                             globals,
+                            ctx,
                             cursor_clone,
                             tokens,
                             Some(parameters),
@@ -658,12 +1953,15 @@ impl<'a> Parser<'a> {
                             false,
                         )? {
                             Is(val) => {
+                                Self::record_alternative(ctx, one_of);
                                 found = true;
                                 let is_token = val.is_token();
+                                Self::record_child(ctx, node, &val);
                                 self.parse_parameters(
                                     parameters,
                                     cursor,
                                     globals,
+                                    ctx,
                                     node,
                                     &val,
                                     &mut msg_bus,
@@ -681,13 +1979,14 @@ impl<'a> Parser<'a> {
                                     rules,
                                     cursor,
                                     globals,
+                                    ctx,
                                     cursor_clone,
                                     node,
                                     tokens,
                                     text,
                                 )?
                                 .push(&mut msg_bus);
-                                Self::try_set_text_start_index(node, &val, tokens, &cursor);
+                                Self::try_set_text_start_index(node, &val);
                                 break;
                             }
                             IsNot(err) => {
@@ -706,6 +2005,7 @@ impl<'a> Parser<'a> {
                             isnt,
                             cursor,
                             globals,
+                            ctx,
                             cursor_clone,
                             node,
                             tokens,
@@ -718,25 +2018,52 @@ impl<'a> Parser<'a> {
                     token,
                     rules,
                     parameters,
+                    ..
                 } => {
-                    match self.match_token(
+                    let matched = match self.match_token(
                         grammar,
                         lexer,
                         token,
                         cursor,
                         globals,
+                        ctx,
                         cursor_clone,
                         tokens,
                         Some(parameters),
                         text,
                         false,
-                    )? {
-                        TokenCompare::Is(val) => {
+                    ) {
+                        Ok(matched) => Some(matched),
+                        Err(err) => match self.recover(grammar, token, &err, cursor, tokens, ctx) {
+                            Some(val) => {
+                                Self::record_child(ctx, node, &val);
+                                self.parse_parameters(
+                                    parameters,
+                                    cursor,
+                                    globals,
+                                    ctx,
+                                    node,
+                                    &val,
+                                    &mut msg_bus,
+                                    tokens,
+                                    text,
+                                )?;
+                                advance = false;
+                                None
+                            }
+                            None => return Err(err),
+                        },
+                    };
+                    match matched {
+                        None => {}
+                        Some(TokenCompare::Is(val)) => {
                             let is_token = val.is_token();
+                            Self::record_child(ctx, node, &val);
                             self.parse_parameters(
                                 parameters,
                                 cursor,
                                 globals,
+                                ctx,
                                 node,
                                 &val,
                                 &mut msg_bus,
@@ -752,16 +2079,17 @@ impl<'a> Parser<'a> {
                                 rules,
                                 cursor,
                                 globals,
+                                ctx,
                                 cursor_clone,
                                 node,
                                 tokens,
                                 text,
                             )?
                             .push(&mut msg_bus);
-                            Self::try_set_text_start_index(node, &val, tokens, &cursor);
+                            Self::try_set_text_start_index(node, &val);
                             advance = false;
                         }
-                        TokenCompare::IsNot(err) => {
+                        Some(TokenCompare::IsNot(err)) => {
                             if let Some(ref node) = err.node {
                                 if node.commit {
                                     return Err(err);
@@ -772,20 +2100,15 @@ impl<'a> Parser<'a> {
                     #[cfg(feature = "debug")]
                     println!("WHILE DONE, CURSOR.TO_ADVANCE = {}", cursor.to_advance);
                     #[cfg(feature = "debug")]
-                    println!("\t - WHILE DONE, CURSOR.IDX = {}", cursor.idx);
+                    println!("\t - WHILE DONE, CURSOR = {:?}", cursor);
                 }
                 grammar::Rule::Until {
                     token,
                     rules,
                     parameters,
+                    ..
                 } => {
-                    let safe_idx = cursor.idx.min(tokens.len().saturating_sub(1));
-                    Self::try_set_text_start_index(
-                        node,
-                        &Nodes::Token(tokens[safe_idx].clone()), // Clamp this token
-                        tokens,
-                        &cursor,
-                    );
+                    Self::try_set_text_start_index(node, &Nodes::Token(cursor.peek(tokens)));
                     // search for the token and execute the rules when the token is found
                     while let TokenCompare::IsNot(_) = self.match_token(
                         grammar,
@@ -793,6 +2116,7 @@ impl<'a> Parser<'a> {
                         token,
                         cursor,
                         globals,
+                        ctx,
                         cursor_clone,
                         tokens,
                         Some(parameters),
@@ -800,11 +2124,11 @@ impl<'a> Parser<'a> {
                         false,
                     )? {
                         // No need to handle the error here
-                        cursor.idx += 1;
-                        if cursor.idx >= tokens.len() {
+                        if !cursor.bump(tokens) {
                             return Err(ParseError {
+                                origin: None,
                                 kind: ParseErrors::CouldNotFindToken(token.clone()),
-                                location: tokens[cursor.idx - 1].location,
+                                span: cursor.peek(tokens).span(),
                                 node: Some(node.clone()),
                                 hint: None,
                                 importance: 0,
@@ -812,12 +2136,13 @@ impl<'a> Parser<'a> {
                         }
                     }
 
-                    let safe_val_idx = cursor.idx.min(tokens.len().saturating_sub(1));
-                    let val = &Nodes::Token(tokens[safe_val_idx].clone());
+                    let val = &Nodes::Token(cursor.peek(tokens));
+                    Self::record_child(ctx, node, val);
                     self.parse_parameters(
                         parameters,
                         cursor,
                         globals,
+                        ctx,
                         node,
                         val,
                         &mut msg_bus,
@@ -831,96 +2156,78 @@ impl<'a> Parser<'a> {
                         rules,
                         cursor,
                         globals,
+                        ctx,
                         cursor_clone,
                         node,
                         tokens,
                         text,
                     )?
                     .push(&mut msg_bus);
-                    Self::try_set_text_start_index(node, &val, tokens, &cursor);
+                    Self::try_set_text_start_index(node, &val);
                 }
-                grammar::Rule::Command { command } => match command {
+                grammar::Rule::Command { command, .. } => match command {
                     grammar::Commands::Compare {
                         left,
                         right,
                         comparison,
                         rules,
                     } => {
-                        let left = left.get(&node.variables, globals).unwrap();
-                        let right = right.get(&node.variables, globals).unwrap();
-
-                        let comparisons = match left {
-                            VariableKind::Node(node_left) => {
-                                if let VariableKind::Node(node_right) = right {
-                                    match (node_left, node_right) {
-                                        (Some(Nodes::Node(left)), Some(Nodes::Node(right))) => {
-                                            if left.name == right.name {
-                                                vec![grammar::Comparison::Equal]
-                                            } else {
-                                                vec![grammar::Comparison::NotEqual]
-                                            }
-                                        }
-                                        (Some(Nodes::Token(left)), Some(Nodes::Token(right))) => {
-                                            if left == right {
-                                                vec![grammar::Comparison::Equal]
-                                            } else {
-                                                vec![grammar::Comparison::NotEqual]
-                                            }
-                                        }
-                                        (None, None) => {
-                                            vec![grammar::Comparison::Equal]
-                                        }
-                                        _ => {
-                                            vec![grammar::Comparison::NotEqual]
-                                        }
-                                    }
-                                } else {
-                                    vec![grammar::Comparison::NotEqual]
-                                }
-                            }
-                            VariableKind::NodeList(_) => vec![grammar::Comparison::NotEqual],
-                            VariableKind::Boolean(left) => {
-                                if let VariableKind::Boolean(right) = right {
-                                    if left == right {
-                                        vec![grammar::Comparison::Equal]
-                                    } else {
-                                        vec![grammar::Comparison::NotEqual]
-                                    }
-                                } else {
-                                    vec![grammar::Comparison::NotEqual]
-                                }
-                            }
-                            VariableKind::Number(left) => {
-                                if let VariableKind::Number(right) = right {
-                                    let mut result = Vec::new();
-                                    if left == right {
-                                        result.push(grammar::Comparison::Equal);
-                                        result.push(grammar::Comparison::GreaterThanOrEqual);
-                                        result.push(grammar::Comparison::LessThanOrEqual);
-                                    } else {
-                                        result.push(grammar::Comparison::NotEqual);
-                                        if left > right {
-                                            result.push(grammar::Comparison::GreaterThan);
-                                            result.push(grammar::Comparison::GreaterThanOrEqual);
-                                        }
-                                        if left < right {
-                                            result.push(grammar::Comparison::LessThan);
-                                            result.push(grammar::Comparison::LessThanOrEqual);
-                                        }
-                                    }
-                                    result
-                                } else {
-                                    vec![grammar::Comparison::NotEqual]
-                                }
-                            }
-                        };
-                        if comparisons.contains(comparison) {
+                        let left = left
+                            .get(&node.variables, &globals.values)
+                            .ok_or_else(|| Self::variable_not_found(*left, cursor, tokens))?;
+                        let right = right
+                            .get(&node.variables, &globals.values)
+                            .ok_or_else(|| Self::variable_not_found(*right, cursor, tokens))?;
+                        if Self::compare(left, right, comparison) {
+                            self.parse_rules(
+                                grammar,
+                                lexer,
+                                rules,
+                                cursor,
+                                globals,
+                                ctx,
+                                cursor_clone,
+                                node,
+                                tokens,
+                                text,
+                            )?
+                            .push(&mut msg_bus);
+                        }
+                    }
+                    grammar::Commands::External { name, args, rules } => {
+                        let txt = Self::next_text(grammar, cursor, tokens, text);
+                        let checked = self.predicate(name, args, globals, txt);
+                        if checked
+                            .map_err(|kind| Self::condition_error(kind, cursor, node, tokens))?
+                        {
                             self.parse_rules(
                                 grammar,
                                 lexer,
                                 rules,
                                 cursor,
                                 globals,
+                                ctx,
+                                cursor_clone,
+                                node,
+                                tokens,
+                                text,
+                            )?
+                            .push(&mut msg_bus);
+                        }
+                    }
+                    grammar::Commands::If { cond, rules } => {
+                        let txt = Self::next_text(grammar, cursor, tokens, text);
+                        let checked = self.condition(cond, &node.variables, globals, txt);
+                        if checked
+                            .map_err(|kind| Self::condition_error(kind, cursor, node, tokens))?
+                        {
+                            self.parse_rules(
+                                grammar,
+                                lexer,
+                                rules,
+                                cursor,
+                                globals,
+                                ctx,
                                 cursor_clone,
                                 node,
                                 tokens,
@@ -930,14 +2237,16 @@ impl<'a> Parser<'a> {
                         }
                     }
                     grammar::Commands::Error { err } => Err(ParseError {
+                        origin: None,
                         kind: ParseErrors::Message(err),
-                        location: tokens[cursor.idx].location,
+                        span: cursor.peek(tokens).span(),
                         node: Some(node.clone()),
                         hint: None,
                         importance: 0,
                     })?,
                     grammar::Commands::Commit { set } => {
                         node.commit = *set;
+                        node.cut = false;
                     }
                     grammar::Commands::Goto { label } => {
                         msg_bus.send(Msg::Goto(label.to_string()));
@@ -950,19 +2259,96 @@ impl<'a> Parser<'a> {
                     grammar::Commands::Return => {
                         msg_bus.send(Msg::Return);
                     }
-                    grammar::Commands::Start => node.first_string_idx = tokens[cursor.idx].index,
+                    grammar::Commands::Start => node.first_string_idx = cursor.peek(tokens).index,
                     grammar::Commands::End => {
-                        let prev = cursor.idx.saturating_sub(1);
-                        node.last_string_idx = tokens[prev].index + tokens[prev].len - 1;
+                        let prev = cursor.prev(tokens);
+                        node.last_string_idx = (prev.index + prev.len).saturating_sub(1);
+                    }
+                    grammar::Commands::SetNumber { var, value } => {
+                        let value = VariableKind::Number(*value);
+                        if let Some(kind) = Self::set_value(var, value, node, globals) {
+                            return Err(ParseError {
+                                origin: None,
+                                kind,
+                                span: cursor.peek(tokens).span(),
+                                node: None,
+                                hint: None,
+                                importance: 0,
+                            });
+                        }
+                    }
+                    grammar::Commands::SetNumber64 { var, value } => {
+                        let value = VariableKind::Number64(*value);
+                        if let Some(kind) = Self::set_value(var, value, node, globals) {
+                            return Err(ParseError {
+                                origin: None,
+                                kind,
+                                span: cursor.peek(tokens).span(),
+                                node: None,
+                                hint: None,
+                                importance: 0,
+                            });
+                        }
+                    }
+                    grammar::Commands::SetFloat { var, value } => {
+                        let value = VariableKind::Float(*value);
+                        if let Some(kind) = Self::set_value(var, value, node, globals) {
+                            return Err(ParseError {
+                                origin: None,
+                                kind,
+                                span: cursor.peek(tokens).span(),
+                                node: None,
+                                hint: None,
+                                importance: 0,
+                            });
+                        }
+                    }
+                    grammar::Commands::SetBool { var, value } => {
+                        let value = VariableKind::Boolean(*value);
+                        if let Some(kind) = Self::set_value(var, value, node, globals) {
+                            return Err(ParseError {
+                                origin: None,
+                                kind,
+                                span: cursor.peek(tokens).span(),
+                                node: None,
+                                hint: None,
+                                importance: 0,
+                            });
+                        }
                     }
                 },
-                grammar::Rule::Loop { rules } => {
+                grammar::Rule::IfFeature {
+                    name,
+                    rules,
+                    else_rules,
+                    ..
+                } => {
+                    let rules = match self.feature_enabled(grammar, name) {
+                        true => rules,
+                        false => else_rules,
+                    };
+                    self.parse_rules(
+                        grammar,
+                        lexer,
+                        rules,
+                        cursor,
+                        globals,
+                        ctx,
+                        cursor_clone,
+                        node,
+                        tokens,
+                        text,
+                    )?
+                    .push(&mut msg_bus);
+                }
+                grammar::Rule::Loop { rules, .. } => {
                     self.parse_rules(
                         grammar,
                         lexer,
                         rules,
                         cursor,
                         globals,
+                        ctx,
                         cursor_clone,
                         node,
                         tokens,
@@ -973,10 +2359,11 @@ impl<'a> Parser<'a> {
                 }
                 grammar::Rule::UntilOneOf {
                     tokens: match_tokens,
+                    ..
                 } => {
                     let mut found = false;
-                    while cursor.idx < tokens.len() {
-                        for OneOf {
+                    while !cursor.at_end(tokens) {
+                        for one_of @ OneOf {
                             token,
                             rules,
                             parameters,
@@ -989,6 +2376,7 @@ impl<'a> Parser<'a> {
                                 token,
                                 cursor,
                                 globals,
+                                ctx,
                                 cursor_clone,
                                 tokens,
                                 Some(parameters),
@@ -996,12 +2384,15 @@ impl<'a> Parser<'a> {
                                 false,
                             )? {
                                 Is(val) => {
+                                    Self::record_alternative(ctx, one_of);
                                     found = true;
                                     let is_token = val.is_token();
+                                    Self::record_child(ctx, node, &val);
                                     self.parse_parameters(
                                         parameters,
                                         cursor,
                                         globals,
+                                        ctx,
                                         node,
                                         &val,
                                         &mut msg_bus,
@@ -1017,13 +2408,14 @@ impl<'a> Parser<'a> {
                                         rules,
                                         cursor,
                                         globals,
+                                        ctx,
                                         cursor_clone,
                                         node,
                                         tokens,
                                         text,
                                     )?
                                     .push(&mut msg_bus);
-                                    Self::try_set_text_start_index(node, &val, tokens, &cursor);
+                                    Self::try_set_text_start_index(node, &val);
                                     break;
                                 }
                                 IsNot(err) => {
@@ -1038,34 +2430,32 @@ impl<'a> Parser<'a> {
                         if found {
                             break;
                         }
-                        cursor.idx += 1;
+                        cursor.bump(tokens);
                     }
                     if !found {
-                        let safe_cursor = cursor.idx.min(tokens.len().saturating_sub(1));
-                        let peek =
-                            Self::next_non_whitespace(&tokens[safe_cursor..], &grammar.ignored)
-                                .unwrap_or(0);
-                        let err_idx = cursor.idx + peek;
-                        let safe_err_idx = err_idx.min(tokens.len().saturating_sub(1));
-                        let kind = tokens
-                            .get(err_idx)
-                            .map(|t| t.kind.clone())
-                            .unwrap_or(TokenKinds::Control(crate::lexer::ControlTokenKind::Eof));
+                        let peek = Self::next_non_whitespace(cursor.rest(tokens), &grammar.ignored)
+                            .unwrap_or(0);
+                        let found = cursor.peek_nth(tokens, peek);
+                        let kind = match cursor.at_end(tokens) {
+                            true => TokenKinds::Control(crate::lexer::ControlTokenKind::Eof),
+                            false => found.kind,
+                        };
 
                         err(
                             ParseErrors::ExpectedOneOf {
                                 expected: match_tokens.iter().map(|x| x.token.clone()).collect(),
                                 found: kind,
+                                candidates: Vec::new(),
                             },
                             cursor,
                             cursor_clone,
-                            &tokens[safe_err_idx].location,
+                            found.span(),
                             Some(node.clone()),
                             None,
                         )?;
                     }
                 }
-                grammar::Rule::Debug { target } => {
+                grammar::Rule::Debug { target, .. } => {
                     #[cfg(feature = "std")]
                     {
                         match target {
@@ -1075,7 +2465,7 @@ impl<'a> Parser<'a> {
                                 //     None => {
                                 //         return Err(ParseError {
                                 //             kind: ParseErrors::VariableNotFound(ident.to_string()),
-                                //             location: tokens[cursor.idx].location.clone(),
+                                //             location: cursor.peek(tokens).location.clone(),
                                 //             node: Some(node.clone()),
                                 //         })
                                 //     }
@@ -1083,10 +2473,10 @@ impl<'a> Parser<'a> {
                                 // println!("{:?}", kind);
                             }
                             None => {
-                                if cursor.idx >= tokens.len() {
+                                if cursor.at_end(tokens) {
                                     println!("Eof");
                                 } else {
-                                    println!("{:?}", tokens[cursor.idx].stringify(text));
+                                    println!("{:?}", cursor.peek(tokens).stringify(text));
                                 }
                             }
                         }
@@ -1100,23 +2490,32 @@ impl<'a> Parser<'a> {
             }
             while let Some(msg) = msg_bus.receive() {
                 match msg {
-                    Msg::Return => return Ok(Msg::Return),
+                    Msg::Return => {
+                        Self::end_cut(node, outer_cut);
+                        ctx.origin = outer_origin;
+                        return Ok(Msg::Return);
+                    }
                     Msg::Break(n) => {
+                        Self::end_cut(node, outer_cut);
+                        ctx.origin = outer_origin;
                         return if n == 1 {
                             Ok(Msg::Ok)
                         } else {
                             Ok(Msg::Break(n - 1))
-                        }
+                        };
                     }
 
                     Msg::Goto(label) => {
                         let mut j = 0;
                         loop {
                             if j >= rules.len() {
+                                Self::end_cut(node, outer_cut);
+                                ctx.origin = outer_origin;
                                 return Ok(Msg::Goto(label));
                             }
                             if let grammar::Rule::Command {
                                 command: grammar::Commands::Label { name },
+                                ..
                             } = &rules[j]
                             {
                                 if *name == label {
@@ -1129,6 +2528,8 @@ impl<'a> Parser<'a> {
                     }
                     Msg::Back(steps) => {
                         if i < steps {
+                            Self::end_cut(node, outer_cut);
+                            ctx.origin = outer_origin;
                             return Ok(Msg::Back(steps - i));
                         }
                         i -= steps;
@@ -1138,12 +2539,12 @@ impl<'a> Parser<'a> {
             }
             if cursor.to_advance {
                 cursor.to_advance = false;
-                cursor.idx += 1;
-                if cursor.idx >= tokens.len() {
+                if !cursor.advance(tokens) {
                     if self.eof_error {
                         return Err(ParseError {
+                            origin: None,
                             kind: ParseErrors::Eof,
-                            location: tokens[cursor.idx - 1].location,
+                            span: cursor.peek(tokens).span(),
                             node: Some(node.clone()),
                             hint: None,
                             importance: 0,
@@ -1152,9 +2553,192 @@ impl<'a> Parser<'a> {
                 }
             }
         }
+        Self::end_cut(node, outer_cut);
+        ctx.origin = outer_origin;
         Ok(Msg::Ok)
     }
 
+    /// Whether the values compare like the comparison says, see `grammar::Commands::Compare`
+    fn compare(
+        left: &VariableKind,
+        right: &VariableKind,
+        comparison: &grammar::Comparison,
+    ) -> bool {
+        let comparisons = match left {
+            VariableKind::Node(node_left) => {
+                if let VariableKind::Node(node_right) = right {
+                    match (node_left, node_right) {
+                        (Some(Nodes::Node(left)), Some(Nodes::Node(right))) => {
+                            if left.name == right.name {
+                                vec![grammar::Comparison::Equal]
+                            } else {
+                                vec![grammar::Comparison::NotEqual]
+                            }
+                        }
+                        (Some(Nodes::Token(left)), Some(Nodes::Token(right))) => {
+                            if left == right {
+                                vec![grammar::Comparison::Equal]
+                            } else {
+                                vec![grammar::Comparison::NotEqual]
+                            }
+                        }
+                        (None, None) => {
+                            vec![grammar::Comparison::Equal]
+                        }
+                        _ => {
+                            vec![grammar::Comparison::NotEqual]
+                        }
+                    }
+                } else {
+                    vec![grammar::Comparison::NotEqual]
+                }
+            }
+            VariableKind::NodeList(_) => vec![grammar::Comparison::NotEqual],
+            VariableKind::Boolean(left) => {
+                if let VariableKind::Boolean(right) = right {
+                    if left == right {
+                        vec![grammar::Comparison::Equal]
+                    } else {
+                        vec![grammar::Comparison::NotEqual]
+                    }
+                } else {
+                    vec![grammar::Comparison::NotEqual]
+                }
+            }
+            VariableKind::Number(_) | VariableKind::Number64(_) | VariableKind::Float(_) => {
+                match left.cmp_numeric(right) {
+                    Some(core::cmp::Ordering::Equal) => vec![
+                        grammar::Comparison::Equal,
+                        grammar::Comparison::GreaterThanOrEqual,
+                        grammar::Comparison::LessThanOrEqual,
+                    ],
+                    Some(core::cmp::Ordering::Greater) => vec![
+                        grammar::Comparison::NotEqual,
+                        grammar::Comparison::GreaterThan,
+                        grammar::Comparison::GreaterThanOrEqual,
+                    ],
+                    Some(core::cmp::Ordering::Less) => vec![
+                        grammar::Comparison::NotEqual,
+                        grammar::Comparison::LessThan,
+                        grammar::Comparison::LessThanOrEqual,
+                    ],
+                    None => vec![grammar::Comparison::NotEqual],
+                }
+            }
+        };
+        comparisons.contains(comparison)
+    }
+
+    /// Checks the condition, failing on a variable that does not exist or a predicate
+    /// that is not registered
+    ///
+    /// `txt` is the text of the next token that predicates get
+    fn condition(
+        &self,
+        cond: &grammar::Cond<'a>,
+        variables: &Variables<'a>,
+        globals: &ParseState<'a>,
+        txt: &str,
+    ) -> Result<bool, Unchecked<'a>> {
+        Ok(match cond {
+            grammar::Cond::Compare {
+                left,
+                right,
+                comparison,
+            } => {
+                let values = &globals.values;
+                let left = left
+                    .get(variables, values)
+                    .ok_or(Unchecked::Variable(*left))?;
+                let right = right
+                    .get(variables, values)
+                    .ok_or(Unchecked::Variable(*right))?;
+                Self::compare(left, right, comparison)
+            }
+            grammar::Cond::External { name, args } => self.predicate(name, args, globals, txt)?,
+            grammar::Cond::And(conds) => {
+                for cond in conds {
+                    if !self.condition(cond, variables, globals, txt)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            grammar::Cond::Or(conds) => {
+                for cond in conds {
+                    if self.condition(cond, variables, globals, txt)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+            grammar::Cond::Not(cond) => !self.condition(cond, variables, globals, txt)?,
+        })
+    }
+
+    /// Calls the predicate registered under the name
+    fn predicate(
+        &self,
+        name: &'a str,
+        args: &[&str],
+        globals: &ParseState<'a>,
+        txt: &str,
+    ) -> Result<bool, Unchecked<'a>> {
+        match self.predicates.get(name) {
+            Some(predicate) => Ok(predicate.check(txt, globals, args)),
+            None => Err(Unchecked::Predicate(name)),
+        }
+    }
+
+    /// Text of the next token that is not whitespace or ignored, as predicates get it
+    fn next_text(
+        grammar: &Grammar<'a>,
+        cursor: &Cursor,
+        tokens: &dyn TokenSource<'a>,
+        text: &'a str,
+    ) -> &'a str {
+        let start = cursor.next_idx();
+        let next = tokens
+            .range(start..tokens.len())
+            .find(|token| !token.kind.is_whitespace() && !grammar.ignored.contains(&token.kind));
+        next.map_or("", |token| &text[token.index..token.index + token.len])
+    }
+
+    /// Error of a condition that could not be checked, see `Parser::condition`
+    fn condition_error(
+        unchecked: Unchecked<'a>,
+        cursor: &Cursor,
+        node: &Node<'a>,
+        tokens: &dyn TokenSource<'a>,
+    ) -> ParseError<'a> {
+        let (kind, hint) = match unchecked {
+            Unchecked::Variable(var) => (
+                ParseErrors::VariableNotFound(var),
+                "Please run the parser through validator with .success()",
+            ),
+            Unchecked::Predicate(name) => (
+                ParseErrors::PredicateNotFound(name),
+                "Register the predicate with `Parser::register_predicate`",
+            ),
+        };
+        ParseError {
+            origin: None,
+            kind,
+            span: cursor.peek(tokens).span(),
+            node: Some(node.clone()),
+            hint: Some(hint),
+            importance: 0,
+        }
+    }
+
+    /// A cut only lasts until the end of the rule block it was used in
+    fn end_cut(node: &mut Node, outer_cut: bool) {
+        if node.cut {
+            node.commit = false;
+        }
+        node.cut = outer_cut;
+    }
+
     fn find_hint<'b>(parameters: Option<&'b [grammar::Parameters<'b>]>) -> Option<&'b str> {
         parameters?.iter().find_map(|p| {
             if let grammar::Parameters::Hint(s) = p {
@@ -1179,130 +2763,223 @@ impl<'a> Parser<'a> {
         lexer: &Lexer,
         token: &'a grammar::MatchToken,
         cursor: &mut Cursor,
-        globals: &mut Map<String, VariableKind<'a>>,
+        globals: &mut ParseState<'a>,
+        ctx: &mut Context<'a, '_>,
         cursor_clone: &Cursor,
-        tokens: &Vec<Token<'a>>,
+        tokens: &dyn TokenSource<'a>,
         parameters: Option<&'a [Parameters<'a>]>,
         text: &'a str,
         auto_commit: bool,
     ) -> Result<TokenCompare<'a>, ParseError<'a>> {
+        if let Some(next) = &mut ctx.next {
+            let peek =
+                Self::next_non_whitespace(cursor.rest(tokens), &grammar.ignored).unwrap_or(0);
+            if cursor.index(tokens, peek) == next.target && !next.expected.contains(token) {
+                next.expected.push(*token);
+            }
+        }
         match token {
+            grammar::MatchToken::Token(tok) if cursor.split > 0 => {
+                let current = cursor.peek(tokens);
+                let rest = current.len - cursor.split;
+                let piece = Self::split_piece(tok, &current, cursor.split, text)
+                    .filter(|piece| piece.len == rest || Self::splits(parameters));
+                match piece {
+                    Some(piece) => {
+                        cursor.split = (cursor.split + piece.len) % current.len;
+                        Ok(TokenCompare::Is(Nodes::Token(piece)))
+                    }
+                    None => Ok(TokenCompare::IsNot(ParseError {
+                        origin: None,
+                        kind: ParseErrors::ExpectedToken {
+                            expected: *tok,
+                            found: current.kind,
+                        },
+                        span: current.span(),
+                        node: None,
+                        hint: Self::find_hint(parameters),
+                        importance: Self::get_importance(parameters),
+                    })),
+                }
+            }
             grammar::MatchToken::Token(tok) => {
                 if *tok == TokenKinds::Control(crate::lexer::ControlTokenKind::Eof)
-                    && cursor.idx >= tokens.len()
+                    && cursor.at_end(tokens)
                 {
                     return Ok(TokenCompare::Is(Nodes::Token(Token {
                         kind: TokenKinds::Control(crate::lexer::ControlTokenKind::Eof),
+                        tag: None,
+                        data: None,
                         index: 0,
                         len: 0,
-                        location: TextLocation::new(0, 0, 0, 0),
                     })));
                 }
-                if cursor.idx >= tokens.len() {
+                if cursor.at_end(tokens) {
                     return Ok(TokenCompare::IsNot(ParseError {
+                        origin: None,
                         kind: ParseErrors::Eof,
-                        location: tokens[cursor.idx - 1].location,
+                        span: cursor.peek(tokens).span(),
                         node: None,
                         hint: Self::find_hint(parameters),
                         importance: Self::get_importance(parameters),
                     }));
                 }
 
-                let mut current_token = &tokens[cursor.idx];
+                let mut current_token = cursor.peek(tokens);
                 let mut peek = 0;
 
                 while current_token.kind.is_whitespace()
                     || grammar.ignored.contains(&current_token.kind)
                 {
                     if *tok == current_token.kind {
-                        cursor.idx += peek;
-                        return Ok(TokenCompare::Is(Nodes::Token(current_token.clone())));
+                        cursor.skip(peek);
+                        return Ok(TokenCompare::Is(Nodes::Token(current_token)));
                     }
                     peek += 1;
 
-                    if cursor.idx + peek >= tokens.len() {
+                    if !cursor.has(tokens, peek) {
                         if *tok == TokenKinds::Control(crate::lexer::ControlTokenKind::Eof) {
-                            cursor.idx += peek; // Advance past the whitespace
+                            cursor.skip(peek); // Advance past the whitespace
                             return Ok(TokenCompare::Is(Nodes::Token(Token {
                                 kind: TokenKinds::Control(crate::lexer::ControlTokenKind::Eof),
+                                tag: None,
+                                data: None,
                                 index: 0,
                                 len: 0,
-                                location: TextLocation::new(0, 0, 0, 0),
                             })));
                         }
                         break;
                     }
-                    current_token = &tokens[cursor.idx + peek];
+                    current_token = cursor.peek_nth(tokens, peek);
                 }
 
+                if *tok != current_token.kind && Self::splits(parameters) {
+                    if let Some(piece) = Self::split_piece(tok, &current_token, 0, text) {
+                        cursor.skip(peek);
+                        cursor.split = piece.len;
+                        return Ok(TokenCompare::Is(Nodes::Token(piece)));
+                    }
+                }
                 if *tok != current_token.kind {
                     return Ok(TokenCompare::IsNot(ParseError {
+                        origin: None,
                         kind: ParseErrors::ExpectedToken {
                             expected: tok.clone(),
                             found: current_token.kind.clone(),
                         },
-                        location: current_token.location,
+                        span: current_token.span(),
                         node: None,
                         hint: Self::find_hint(parameters),
                         importance: Self::get_importance(parameters),
                     }));
                 }
-                cursor.idx += peek;
-                Ok(TokenCompare::Is(Nodes::Token(current_token.clone())))
+                if *tok == TokenKinds::Text {
+                    let word = current_token.stringify(text);
+                    if ctx.keywords.contains(&word) {
+                        return Ok(TokenCompare::IsNot(ParseError {
+                            origin: None,
+                            kind: ParseErrors::Keyword(word),
+                            span: current_token.span(),
+                            node: None,
+                            hint: Self::find_hint(parameters),
+                            importance: Self::get_importance(parameters),
+                        }));
+                    }
+                }
+                cursor.skip(peek);
+                Ok(TokenCompare::Is(Nodes::Token(current_token)))
             }
             grammar::MatchToken::Node(node_name) => {
                 match self.parse_node(
                     grammar,
                     lexer,
-                    node_name,
+                    *node_name,
                     cursor,
                     globals,
+                    ctx,
                     tokens,
                     text,
                     auto_commit,
                 ) {
-                    Ok(node) => Ok(TokenCompare::Is(Nodes::Node(node))),
+                    Ok(node) => Ok(TokenCompare::Is(node.into())),
                     Err((commit, err)) => match commit {
                         true => Err(err),
                         false => Ok(TokenCompare::IsNot(Self::attach_hint(err, parameters))),
                     },
                 }
             }
+            grammar::MatchToken::Word(word) if cursor.split > 0 => {
+                let current = cursor.peek(tokens);
+                Ok(TokenCompare::IsNot(ParseError {
+                    origin: None,
+                    kind: ParseErrors::ExpectedWord {
+                        expected: word,
+                        found: current.kind,
+                    },
+                    span: current.span(),
+                    node: None,
+                    hint: Self::find_hint(parameters),
+                    importance: Self::get_importance(parameters),
+                }))
+            }
             grammar::MatchToken::Word(word) => {
-                let mut current_token = &tokens[cursor.idx];
-                let mut peek = 0;
-                while current_token.kind.is_whitespace()
-                    || grammar.ignored.contains(&current_token.kind)
-                {
-                    peek += 1;
-                    current_token = &tokens[cursor.idx + peek];
-                }
+                let peek =
+                    Self::next_non_whitespace(cursor.rest(tokens), &grammar.ignored).unwrap_or(0);
+                let current_token = cursor.peek_nth(tokens, peek);
                 if !matches!(current_token.kind, TokenKinds::Text)
                     || word != &current_token.stringify(text)
                 {
                     if word != &current_token.stringify(text) {
                         return Ok(TokenCompare::IsNot(ParseError {
+                            origin: None,
                             kind: ParseErrors::ExpectedWord {
                                 expected: word,
                                 found: current_token.kind.clone(),
                             },
-                            location: current_token.location,
+                            span: current_token.span(),
                             node: None,
                             hint: Self::find_hint(parameters),
                             importance: Self::get_importance(parameters),
                         }));
                     }
                 }
-                cursor.idx += peek;
-                Ok(TokenCompare::Is(Nodes::Token(current_token.clone())))
+                cursor.skip(peek);
+                Ok(TokenCompare::Is(Nodes::Token(current_token)))
             }
+            grammar::MatchToken::Alias(name) => match grammar.resolve_alias(name) {
+                Ok(token) => self.match_token(
+                    grammar,
+                    lexer,
+                    token,
+                    cursor,
+                    globals,
+                    ctx,
+                    cursor_clone,
+                    tokens,
+                    parameters,
+                    text,
+                    auto_commit,
+                ),
+                Err(err) => Err(ParseError {
+                    origin: None,
+                    kind: match err {
+                        grammar::AliasError::NotFound(alias) => ParseErrors::AliasNotFound(alias),
+                        grammar::AliasError::Cycle(alias) => ParseErrors::AliasCycle(alias),
+                    },
+                    span: cursor.peek(tokens).span(),
+                    node: None,
+                    hint: Self::find_hint(parameters),
+                    importance: Self::get_importance(parameters),
+                }),
+            },
             grammar::MatchToken::Enumerator(enumerator) => {
                 let enumerator = match grammar.enumerators.get(*enumerator) {
                     Some(enumerator) => enumerator,
                     None => {
                         return Err(ParseError {
+                            origin: None,
                             kind: ParseErrors::EnumeratorNotFound(enumerator),
-                            location: tokens[cursor.idx].location,
+                            span: cursor.peek(tokens).span(),
                             node: None,
                             hint: Self::find_hint(parameters),
                             importance: Self::get_importance(parameters),
@@ -1314,18 +2991,20 @@ impl<'a> Parser<'a> {
                 let cursor_clone_local = cursor.clone();
                 let token = loop {
                     if i >= enumerator.values.len() {
-                        let peek =
-                            Self::next_non_whitespace(&tokens[cursor.idx..], &grammar.ignored)
-                                .unwrap_or(0);
+                        let peek = Self::next_non_whitespace(cursor.rest(tokens), &grammar.ignored)
+                            .unwrap_or(0);
+                        let found = cursor.peek_nth(tokens, peek);
                         match best_err {
                             Some(e) => return Err(e),
                             None => {
                                 return Ok(TokenCompare::IsNot(ParseError {
+                                    origin: None,
                                     kind: ParseErrors::ExpectedOneOf {
                                         expected: enumerator.values.to_vec(),
-                                        found: tokens[cursor.idx + peek].kind.clone(),
+                                        found: found.kind,
+                                        candidates: Vec::new(),
                                     },
-                                    location: tokens[cursor.idx + peek].location,
+                                    span: found.span(),
                                     node: None,
                                     hint: Self::find_hint(parameters),
                                     importance: Self::get_importance(parameters),
@@ -1340,6 +3019,7 @@ impl<'a> Parser<'a> {
                         token,
                         cursor,
                         globals,
+                        ctx,
                         cursor_clone,
                         tokens,
                         parameters,
@@ -1368,13 +3048,95 @@ impl<'a> Parser<'a> {
                 println!("matched: {:?}", token);
                 Ok(TokenCompare::Is(token))
             }
-            grammar::MatchToken::Any => {
-                let token = tokens[cursor.idx].clone();
-                Ok(TokenCompare::Is(Nodes::Token(token)))
+            grammar::MatchToken::Any => {
+                // takes the rest of a split token
+                cursor.split = 0;
+                if cursor.at_end(tokens) {
+                    return Ok(TokenCompare::IsNot(ParseError {
+                        origin: None,
+                        kind: ParseErrors::Eof,
+                        span: cursor.peek(tokens).span(),
+                        node: None,
+                        hint: Self::find_hint(parameters),
+                        importance: Self::get_importance(parameters),
+                    }));
+                }
+                Ok(TokenCompare::Is(Nodes::Token(cursor.peek(tokens))))
+            }
+            // characters are significant, whitespace is not skipped
+            // the rest of a split token is matched by token rules only
+            grammar::MatchToken::Char(_) | grammar::MatchToken::CharClass(_)
+                if cursor.split > 0 =>
+            {
+                let current = cursor.peek(tokens);
+                Ok(TokenCompare::IsNot(ParseError {
+                    origin: None,
+                    kind: ParseErrors::ExpectedChar {
+                        expected: *token,
+                        found: current.stringify(text)[cursor.split..].chars().next(),
+                    },
+                    span: current.span(),
+                    node: None,
+                    hint: Self::find_hint(parameters),
+                    importance: Self::get_importance(parameters),
+                }))
+            }
+            grammar::MatchToken::Char(_) | grammar::MatchToken::CharClass(_) => {
+                let current_token = cursor.peek(tokens);
+                let mut chars = current_token.stringify(text).chars();
+                let found = chars.next();
+                let matches = match (token, found, chars.next()) {
+                    (grammar::MatchToken::Char(expected), Some(c), None) => *expected == c,
+                    (grammar::MatchToken::CharClass(class), Some(c), None) => class.contains(c),
+                    _ => false,
+                };
+                if !matches {
+                    return Ok(TokenCompare::IsNot(ParseError {
+                        origin: None,
+                        kind: ParseErrors::ExpectedChar {
+                            expected: *token,
+                            found,
+                        },
+                        span: current_token.span(),
+                        node: None,
+                        hint: Self::find_hint(parameters),
+                        importance: Self::get_importance(parameters),
+                    }));
+                }
+                Ok(TokenCompare::Is(Nodes::Token(current_token)))
             }
         }
     }
 
+    /// Whether the rule can match the start of a longer token, see `Parameters::Split`
+    fn splits(parameters: Option<&[Parameters]>) -> bool {
+        parameters.is_some_and(|parameters| parameters.contains(&Parameters::Split))
+    }
+
+    /// Piece of `token` that `tok` matches `offset` bytes into the token
+    ///
+    /// None if the rest of the token does not start with the text of `tok`
+    fn split_piece(
+        tok: &TokenKinds<'a>,
+        token: &Token<'a>,
+        offset: usize,
+        text: &str,
+    ) -> Option<Token<'a>> {
+        let TokenKinds::Token(expected) = tok else {
+            return None;
+        };
+        let rest = text.get(token.index + offset..token.index + token.len)?;
+        if expected.is_empty() || !rest.starts_with(expected) {
+            return None;
+        }
+        let pieces = [
+            (token.kind, offset),
+            (*tok, expected.len()),
+            (token.kind, rest.len() - expected.len()),
+        ];
+        token.split(text, pieces)?.into_iter().nth(1)
+    }
+
     fn attach_hint(
         mut error: ParseError<'a>,
         parameters: Option<&'a [grammar::Parameters<'a>]>,
@@ -1388,36 +3150,72 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn next_non_whitespace(tokens: &[Token], ignored: &[TokenKinds<'_>]) -> Option<usize> {
-        for (idx, token) in tokens.iter().enumerate() {
-            if !token.kind.is_whitespace() && !ignored.contains(&token.kind) {
-                return Some(idx);
-            }
+    fn next_non_whitespace<'t>(
+        mut tokens: impl Iterator<Item = Token<'t>>,
+        ignored: &[TokenKinds<'_>],
+    ) -> Option<usize> {
+        tokens.position(|token| !token.kind.is_whitespace() && !ignored.contains(&token.kind))
+    }
+
+    fn variable_not_found(
+        name: grammar::VarKind<'a>,
+        cursor: &Cursor,
+        tokens: &dyn TokenSource<'a>,
+    ) -> ParseError<'a> {
+        ParseError {
+            kind: ParseErrors::VariableNotFound(name),
+            span: cursor.peek(tokens).span(),
+            node: None,
+            hint: Some("Please run the parser through validator with .success()"),
+            importance: 0,
+            origin: None,
+        }
+    }
+
+    /// Bytes of the value of a variable, for a list of its last value
+    fn symbol_span(
+        name: &grammar::VarKind,
+        node: &Node<'a>,
+        globals: &ParseState<'a>,
+    ) -> Option<core::ops::Range<usize>> {
+        let variable = match name {
+            grammar::VarKind::Local(name) => node.variables.get(name),
+            grammar::VarKind::Global(name) => globals.values.get(*name),
+        };
+        match variable? {
+            VariableKind::Node(value) => value.as_ref().map(scopes::span),
+            VariableKind::NodeList(list) => list.last().map(scopes::span),
+            _ => None,
         }
-        None
     }
 
     fn parse_parameters(
         &'a self,
         parameters: &'a Vec<grammar::Parameters>,
         cursor: &mut Cursor,
-        globals: &mut Map<String, VariableKind<'a>>,
+        globals: &mut ParseState<'a>,
+        ctx: &mut Context<'a, '_>,
         node: &mut Node<'a>,
         value: &Nodes<'a>,
         bus: &mut MsgBus,
-        tokens: &Vec<Token>,
+        tokens: &dyn TokenSource<'a>,
         text: &str,
     ) -> Result<(), ParseError<'a>> {
         for parameter in parameters {
             match parameter {
                 grammar::Parameters::Set(name) => {
                     let kind = name
-                        .get_mut(&mut node.variables, globals)
-                        .expect("Variable exists not :(");
+                        .get_mut(&mut node.variables, &mut globals.values)
+                        .ok_or_else(|| Self::variable_not_found(*name, cursor, tokens))?;
                     match kind {
                         VariableKind::Node(ref mut single) => {
                             *single = Some(value.clone());
                         }
+                        // only the last value is kept for the rules, the events have the others
+                        VariableKind::NodeList(list) if ctx.events.is_some() => {
+                            list.clear();
+                            list.push(value.clone());
+                        }
                         VariableKind::NodeList(list) => {
                             list.push(value.clone());
                         }
@@ -1425,7 +3223,48 @@ impl<'a> Parser<'a> {
                             *bol = true;
                         }
                         VariableKind::Number(n) => *n += 1,
+                        VariableKind::Number64(n) => *n += 1,
+                        VariableKind::Float(n) => *n += 1.0,
                     };
+                    if let Some(events) = &mut ctx.events {
+                        events.push(Event::VariableSet {
+                            variable: *name,
+                            span: scopes::span(value),
+                        });
+                    }
+                }
+                grammar::Parameters::SetField(name, field) => {
+                    let kind = node.variables.get_mut(name).unwrap();
+                    match kind {
+                        VariableKind::NodeList(list) => match &mut ctx.events {
+                            Some(events) => {
+                                list.clear();
+                                list.push(value.clone());
+                                events.push(Event::VariableSet {
+                                    variable: VarKind::Local(name),
+                                    span: scopes::span(value),
+                                });
+                            }
+                            None => {
+                                let idx = list.len();
+                                list.push(value.clone());
+                                node.fields.push((name, idx, field));
+                            }
+                        },
+                        _ => {
+                            return Err(ParseError {
+                                origin: None,
+                                hint: None,
+                                kind: ParseErrors::CannotSetVariable(
+                                    grammar::VarKind::Local(name),
+                                    kind.clone(),
+                                ),
+                                span: cursor.peek(tokens).span(),
+                                node: None,
+                                importance: 0,
+                            })
+                        }
+                    }
                 }
                 grammar::Parameters::Print(_str) => {
                     #[cfg(feature = "std")]
@@ -1435,29 +3274,38 @@ impl<'a> Parser<'a> {
                     Some(_ident) => {
                         #[cfg(feature = "std")]
                         {
-                            let kind = _ident.get(&node.variables, globals);
+                            let kind = _ident.get(&node.variables, &globals.values);
                             println!("{:?}", kind.map(|k| k.stringify(text)));
                         }
                     }
                     None =>
                     {
                         #[cfg(feature = "std")]
-                        if cursor.idx >= tokens.len() {
+                        if cursor.at_end(tokens) {
                             println!("Eof");
                         } else {
-                            println!("{:?}", tokens[cursor.idx].stringify(text));
+                            println!("{:?}", cursor.peek(tokens).stringify(text));
                         }
                     }
                 },
                 grammar::Parameters::Increment(ident) => {
-                    let kind = ident.get_mut(&mut node.variables, globals).unwrap();
+                    let kind = ident
+                        .get_mut(&mut node.variables, &mut globals.values)
+                        .ok_or_else(|| Self::variable_not_found(*ident, cursor, tokens))?;
                     match kind {
                         VariableKind::Number(ref mut val) => {
                             *val += 1;
                         }
+                        VariableKind::Number64(ref mut val) => {
+                            *val += 1;
+                        }
+                        VariableKind::Float(ref mut val) => {
+                            *val += 1.0;
+                        }
                         _ => Err(ParseError {
+                            origin: None,
                             kind: ParseErrors::UncountableVariable(*ident, kind.clone()),
-                            location: tokens[cursor.idx].location,
+                            span: cursor.peek(tokens).span(),
                             node: None,
                             hint: None,
                             importance: 0,
@@ -1465,60 +3313,85 @@ impl<'a> Parser<'a> {
                     };
                 }
                 grammar::Parameters::Decrement(ident) => {
-                    let kind = ident.get_mut(&mut node.variables, globals).unwrap();
+                    let kind = ident
+                        .get_mut(&mut node.variables, &mut globals.values)
+                        .ok_or_else(|| Self::variable_not_found(*ident, cursor, tokens))?;
                     match kind {
                         VariableKind::Number(ref mut val) => {
                             *val -= 1;
                         }
+                        VariableKind::Number64(ref mut val) => {
+                            *val -= 1;
+                        }
+                        VariableKind::Float(ref mut val) => {
+                            *val -= 1.0;
+                        }
                         _ => Err(ParseError {
+                            origin: None,
                             hint: None,
                             kind: ParseErrors::UncountableVariable(*ident, kind.clone()),
-                            location: tokens[cursor.idx].location,
+                            span: cursor.peek(tokens).span(),
                             node: None,
                             importance: 0,
                         })?,
                     };
                 }
                 grammar::Parameters::True(variable) => {
-                    let kind = variable.get_mut(&mut node.variables, globals).unwrap();
+                    let kind = variable
+                        .get_mut(&mut node.variables, &mut globals.values)
+                        .ok_or_else(|| Self::variable_not_found(*variable, cursor, tokens))?;
                     if let VariableKind::Boolean(ref mut val) = kind {
                         *val = true;
                     } else {
                         return Err(ParseError {
+                            origin: None,
                             hint: None,
                             kind: ParseErrors::UncountableVariable(*variable, kind.clone()),
-                            location: tokens[cursor.idx].location,
+                            span: cursor.peek(tokens).span(),
                             node: None,
                             importance: 0,
                         });
                     }
                 }
                 grammar::Parameters::False(variable) => {
-                    let kind = variable.get_mut(&mut node.variables, globals).unwrap();
+                    let kind = variable
+                        .get_mut(&mut node.variables, &mut globals.values)
+                        .ok_or_else(|| Self::variable_not_found(*variable, cursor, tokens))?;
                     if let VariableKind::Boolean(ref mut val) = kind {
                         *val = false;
                     } else {
                         return Err(ParseError {
+                            origin: None,
                             hint: None,
                             kind: ParseErrors::UncountableVariable(*variable, kind.clone()),
-                            location: tokens[cursor.idx].location,
+                            span: cursor.peek(tokens).span(),
                             node: None,
                             importance: 0,
                         });
                     }
                 }
                 grammar::Parameters::CloneValue(var1, var2) => {
-                    var2.set(var1, &mut node.variables, globals);
+                    var2.set(var1, &mut node.variables, &mut globals.values);
                 }
                 grammar::Parameters::Commit(value) => {
                     node.commit = *value;
+                    node.cut = false;
+                }
+                grammar::Parameters::Cut => {
+                    if !node.commit {
+                        node.commit = true;
+                        node.cut = true;
+                    }
                 }
                 grammar::Parameters::NodeStart => {
-                    node.first_string_idx = tokens[cursor.idx].index;
+                    node.first_string_idx = cursor.peek(tokens).index;
                 }
                 grammar::Parameters::NodeEnd => {
-                    node.last_string_idx = tokens[cursor.idx].index + tokens[cursor.idx].len - 1;
+                    let token = cursor.peek(tokens);
+                    node.last_string_idx = (token.index + token.len).saturating_sub(1);
                 }
+                // applied while matching the token
+                grammar::Parameters::Split => (),
                 grammar::Parameters::Back(steps) => {
                     bus.send(Msg::Back(*steps as usize));
                 }
@@ -1534,100 +3407,728 @@ impl<'a> Parser<'a> {
                 grammar::Parameters::Hint(_) => (),
                 grammar::Parameters::Fail(msg) => {
                     return Err(ParseError {
+                        origin: None,
                         kind: ParseErrors::Message(&msg),
-                        location: tokens[cursor.idx].location,
+                        span: cursor.peek(tokens).span(),
                         node: None,
                         hint: Self::find_hint(Some(parameters)),
                         importance: Self::get_importance(Some(parameters)),
                     })
                 }
-                &grammar::Parameters::Important => (),
+                &grammar::Parameters::Important => (),
+                grammar::Parameters::Declare(name) => {
+                    if let Some(span) = Self::symbol_span(name, node, globals) {
+                        let name = text.get(span.clone()).unwrap_or_default().to_string();
+                        globals.scopes.declare(name, span);
+                    }
+                }
+                grammar::Parameters::Use(name) => {
+                    if let Some(span) = Self::symbol_span(name, node, globals) {
+                        let name = text.get(span.clone()).unwrap_or_default().to_string();
+                        globals.scopes.use_name(name, span);
+                    }
+                }
+                grammar::Parameters::EnterScope => {
+                    globals.scopes.enter(scopes::span(value).start);
+                }
+                grammar::Parameters::ExitScope => {
+                    globals.scopes.exit(scopes::span(value).end);
+                }
+                grammar::Parameters::AttachDocs => {
+                    let start = tokens.partition_point(|t| t.index < node.first_string_idx);
+                    let docs = tokens
+                        .range(0..start)
+                        .rev()
+                        .take_while(|t| t.kind.is_whitespace() || t.kind == TokenKinds::DocComment)
+                        .filter(|t| t.kind == TokenKinds::DocComment)
+                        .map(Nodes::Token);
+                    let mut docs = node.alloc.collect(docs);
+                    docs.reverse();
+                    let kind = node.variables.get_mut("docs").unwrap();
+                    match kind {
+                        VariableKind::NodeList(list) => *list = docs,
+                        _ => {
+                            return Err(ParseError {
+                                origin: None,
+                                hint: None,
+                                kind: ParseErrors::CannotSetVariable(
+                                    grammar::VarKind::Local("docs"),
+                                    kind.clone(),
+                                ),
+                                span: cursor.peek(tokens).span(),
+                                node: None,
+                                importance: 0,
+                            })
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+enum TokenCompare<'a> {
+    Is(Nodes<'a>),
+    IsNot(ParseError<'a>),
+}
+
+/// Tokens the parser reads from
+///
+/// The parser backtracks, so it reads the tokens by their index and may read a token
+/// many times. Slices, arrays, vectors and `VecDeque`s are read in place. Other sources
+/// (streams, filtered or generated tokens) implement the trait over whatever they keep,
+/// the parser never copies their tokens into a `Vec`.
+pub trait TokenSource<'a> {
+    /// Number of tokens, including the end of file
+    fn len(&self) -> usize;
+
+    /// Token at the index, None from `len` on
+    fn get(&self, idx: usize) -> Option<Token<'a>>;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a> TokenSource<'a> for [Token<'a>] {
+    fn len(&self) -> usize {
+        <[Token]>::len(self)
+    }
+
+    fn get(&self, idx: usize) -> Option<Token<'a>> {
+        <[Token]>::get(self, idx).copied()
+    }
+}
+
+impl<'a> TokenSource<'a> for Vec<Token<'a>> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn get(&self, idx: usize) -> Option<Token<'a>> {
+        self.as_slice().get(idx).copied()
+    }
+}
+
+impl<'a, const N: usize> TokenSource<'a> for [Token<'a>; N] {
+    fn len(&self) -> usize {
+        N
+    }
+
+    fn get(&self, idx: usize) -> Option<Token<'a>> {
+        self.as_slice().get(idx).copied()
+    }
+}
+
+impl<'a> TokenSource<'a> for VecDeque<Token<'a>> {
+    fn len(&self) -> usize {
+        VecDeque::len(self)
+    }
+
+    fn get(&self, idx: usize) -> Option<Token<'a>> {
+        VecDeque::get(self, idx).copied()
+    }
+}
+
+impl<'a, T: TokenSource<'a> + ?Sized> TokenSource<'a> for &T {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn get(&self, idx: usize) -> Option<Token<'a>> {
+        (**self).get(idx)
+    }
+}
+
+impl<'a> dyn TokenSource<'a> + '_ {
+    /// Tokens in the range that are in the source
+    pub fn range(
+        &self,
+        range: core::ops::Range<usize>,
+    ) -> impl DoubleEndedIterator<Item = Token<'a>> + '_ {
+        range.filter_map(|idx| self.get(idx))
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Token<'a>> + '_ {
+        self.range(0..self.len())
+    }
+
+    pub fn last(&self) -> Option<Token<'a>> {
+        self.get(self.len().checked_sub(1)?)
+    }
+
+    /// Index of the first token the predicate is false for, like `slice::partition_point`
+    pub fn partition_point(&self, mut pred: impl FnMut(&Token<'a>) -> bool) -> usize {
+        let (mut start, mut end) = (0, self.len());
+        while start < end {
+            let mid = start + (end - start) / 2;
+            match self.get(mid).is_some_and(|token| pred(&token)) {
+                true => start = mid + 1,
+                false => end = mid,
+            }
+        }
+        start
+    }
+}
+
+/// Tokens passed through `Parser::filter_tokens`
+///
+/// Borrows the source if there are no filters
+pub enum FilteredTokens<'s, 'a, T: ?Sized> {
+    Source(&'s T),
+    Filtered(Vec<Token<'a>>),
+}
+
+impl<'a, T: TokenSource<'a> + ?Sized> TokenSource<'a> for FilteredTokens<'_, 'a, T> {
+    fn len(&self) -> usize {
+        match self {
+            FilteredTokens::Source(tokens) => tokens.len(),
+            FilteredTokens::Filtered(tokens) => tokens.len(),
+        }
+    }
+
+    fn get(&self, idx: usize) -> Option<Token<'a>> {
+        match self {
+            FilteredTokens::Source(tokens) => tokens.get(idx),
+            FilteredTokens::Filtered(tokens) => tokens.as_slice().get(idx).copied(),
+        }
+    }
+}
+
+/// Tokens of `Parser::parse_node_at`, the region ends where the next token starts
+struct Region<'s, 'a> {
+    tokens: &'s dyn TokenSource<'a>,
+    range: core::ops::Range<usize>,
+    eof: Option<Token<'a>>,
+}
+
+impl<'a> TokenSource<'a> for Region<'_, 'a> {
+    fn len(&self) -> usize {
+        self.range.len() + self.eof.is_some() as usize
+    }
+
+    fn get(&self, idx: usize) -> Option<Token<'a>> {
+        match idx.checked_sub(self.range.len()) {
+            None => self.tokens.get(self.range.start + idx),
+            Some(0) => self.eof,
+            Some(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseResult<'a> {
+    pub entry: Node<'a>,
+    /// Globals after the parse, see `crate::Parser::parse_with_state`
+    pub globals: ParseState<'a>,
+    /// Collected only if `Parser::profile` is enabled
+    pub stats: Option<ParseStats<'a>>,
+    /// Errors the parser recovered from, see `Parser::recovery`
+    pub errors: NodeVec<ParseError<'a>>,
+    /// Text after the entry node, only checked if `Grammar::eof` is disabled
+    ///
+    /// Whitespace and ignored tokens do not count
+    pub remaining: Option<Remaining>,
+    /// Pairs of brackets in the parsed text ordered by the opening bracket,
+    /// see `Grammar::brackets`
+    ///
+    /// Only brackets matched by the same node are paired, so brackets in skipped tokens
+    /// or split between a node and its children are not. A closing bracket pairs with
+    /// the last open bracket of its kind, the brackets opened after it stay unpaired.
+    pub brackets: NodeVec<BracketPair>,
+    /// Number of `crate::Parser::reparse` calls that led to the result, 0 for a full parse
+    pub version: u64,
+}
+
+/// Globals of a single parse
+///
+/// The host can seed globals before the parse with `crate::Parser::parse_with_state`
+/// and read them back from `ParseResult::globals`. Globals declared in the grammar that
+/// are not seeded start at their default values, seeded values have to be of the
+/// declared kind. Values the grammar does not declare are kept for the host.
+#[derive(Debug, Clone, Default)]
+pub struct ParseState<'a> {
+    pub(crate) values: Map<String, VariableKind<'a>>,
+    pub(crate) scopes: Scopes,
+}
+
+impl<'a> ParseState<'a> {
+    pub fn new() -> Self {
+        Self {
+            values: Map::new(),
+            scopes: Scopes::new(),
+        }
+    }
+
+    /// Adds the globals declared in the grammar that are not seeded yet
+    fn declare(
+        &mut self,
+        declared: &[(&'a str, grammar::VariableKind)],
+        alloc: ParseAlloc,
+    ) -> Result<(), Box<ParseError<'a>>> {
+        for (name, kind) in declared {
+            let default = Node::variable_default(*kind, alloc);
+            match self.values.get(*name) {
+                Some(value)
+                    if core::mem::discriminant(value) != core::mem::discriminant(&default) =>
+                {
+                    return Err(Box::new(ParseError {
+                        origin: None,
+                        kind: ParseErrors::CannotSetVariable(VarKind::Global(name), value.clone()),
+                        span: 0..0,
+                        node: None,
+                        hint: Some("Seed the global with the kind it is declared with"),
+                        importance: 0,
+                    }));
+                }
+                Some(_) => (),
+                None => {
+                    self.values.insert(name.to_string(), default);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&VariableKind<'a>> {
+        self.values.get(name)
+    }
+
+    /// Sets the global, returns the previous value
+    pub fn set(
+        &mut self,
+        name: impl Into<String>,
+        value: VariableKind<'a>,
+    ) -> Option<VariableKind<'a>> {
+        self.values.insert(name.into(), value)
+    }
+
+    pub fn set_bool(&mut self, name: impl Into<String>, value: bool) {
+        self.set(name, VariableKind::Boolean(value));
+    }
+
+    pub fn set_number(&mut self, name: impl Into<String>, value: i32) {
+        self.set(name, VariableKind::Number(value));
+    }
+
+    pub fn set_number64(&mut self, name: impl Into<String>, value: i64) {
+        self.set(name, VariableKind::Number64(value));
+    }
+
+    pub fn set_float(&mut self, name: impl Into<String>, value: f64) {
+        self.set(name, VariableKind::Float(value));
+    }
+
+    /// Returns None if the global is missing or not a boolean
+    pub fn boolean(&self, name: &str) -> Option<bool> {
+        match self.values.get(name)? {
+            VariableKind::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns None if the global is missing or not a number
+    pub fn number(&self, name: &str) -> Option<i32> {
+        match self.values.get(name)? {
+            VariableKind::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns None if the global is missing or not a 64-bit number
+    pub fn number64(&self, name: &str) -> Option<i64> {
+        match self.values.get(name)? {
+            VariableKind::Number64(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns None if the global is missing or not a float
+    pub fn float(&self, name: &str) -> Option<f64> {
+        match self.values.get(name)? {
+            VariableKind::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns None if the global is missing, not a node or was not set
+    pub fn node(&self, name: &str) -> Option<&Nodes<'a>> {
+        match self.values.get(name)? {
+            VariableKind::Node(node) => node.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns None if the global is missing or not a node list
+    pub fn node_list(&self, name: &str) -> Option<&[Nodes<'a>]> {
+        match self.values.get(name)? {
+            VariableKind::NodeList(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    /// Iterates over the globals in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &VariableKind<'a>)> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+}
+
+/// Suggestion for the text at an offset, see `crate::Parser::completions`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion<'a> {
+    /// Node name, word or token
+    pub label: &'a str,
+    pub kind: CompletionKind,
+    /// Text to insert for a node, see `grammar::Node::snippet`
+    pub snippet: Option<&'a str>,
+    pub docs: Option<&'a str>,
+    /// Bytes the completion replaces, the part of the token typed so far
+    pub replace: core::ops::Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    /// Node with a snippet that can start at the offset
+    Snippet,
+    /// Word the grammar expects
+    Keyword,
+    /// Token the grammar expects
+    Token,
+}
+
+/// Opening and closing bracket of a pair, see `ParseResult::brackets`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BracketPair {
+    /// Bytes of the opening bracket
+    pub open: core::ops::Range<usize>,
+    /// Bytes of the closing bracket
+    pub close: core::ops::Range<usize>,
+}
+
+/// Text the parser did not consume, see `ParseResult::remaining`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remaining {
+    /// Number of tokens left, not counting whitespace and ignored tokens
+    pub tokens: usize,
+    /// Bytes from the first to the last token left
+    pub span: core::ops::Range<usize>,
+}
+
+impl ParseResult<'_> {
+    /// Byte range of the text left unparsed, see `ParseResult::remaining`
+    ///
+    /// Always None if `Grammar::eof` is enabled since the parse fails instead
+    pub fn remaining_range(&self) -> Option<core::ops::Range<usize>> {
+        self.remaining
+            .as_ref()
+            .map(|remaining| remaining.span.clone())
+    }
+
+    /// Span of the bracket paired with the one at the byte `offset`, see `Grammar::brackets`
+    ///
+    /// None if there is no bracket at the offset or it has no partner
+    pub fn matching_bracket(&self, offset: usize) -> Option<core::ops::Range<usize>> {
+        self.brackets.iter().find_map(|pair| {
+            if pair.open.contains(&offset) {
+                Some(pair.close.clone())
+            } else if pair.close.contains(&offset) {
+                Some(pair.open.clone())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Scopes and declarations collected by the grammar, see `crate::scopes`
+    pub fn scopes(&self) -> &Scopes {
+        &self.globals.scopes
+    }
+
+    /// Uses of the declaration, see `Scopes::references`
+    pub fn references(&self, declaration: &Declaration) -> Vec<&Reference> {
+        self.globals.scopes.references(declaration)
+    }
+
+    /// Declaration of the name at the byte `offset`, see `Scopes::definition_at`
+    pub fn definition_at(&self, offset: usize) -> Option<&Declaration> {
+        self.globals.scopes.definition_at(offset)
+    }
+
+    /// Edits renaming the declaration and its uses, see `Scopes::rename`
+    pub fn rename(&self, declaration: &Declaration, new_name: &str) -> Vec<TextEdit> {
+        self.globals.scopes.rename(declaration, new_name)
+    }
+
+    /// Counts the nodes and tokens of the result and the heap memory of its variables
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report = MemoryReport::default();
+        report.add_node(&self.entry);
+        report.add_variables(&self.globals.values);
+        report
+    }
+}
+
+/// Approximate memory used by a parse result, see `ParseResult::memory_report`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    pub nodes: usize,
+    pub tokens: usize,
+    /// Heap bytes of variable names, lists, children and fields, not counting the nodes
+    /// and tokens in them
+    pub variable_bytes: usize,
+}
+
+impl MemoryReport {
+    /// Estimate of all bytes used by the nodes, tokens and variables
+    pub fn total_bytes(&self) -> usize {
+        self.nodes * core::mem::size_of::<Node>()
+            + self.tokens * core::mem::size_of::<Token>()
+            + self.variable_bytes
+    }
+
+    fn add(&mut self, value: &Nodes) {
+        match value {
+            Nodes::Node(node) => self.add_node(node),
+            Nodes::Token(_) => self.tokens += 1,
+            Nodes::Error { children, .. } => {
+                self.variable_bytes += children.capacity() * core::mem::size_of::<Nodes>();
+                children.iter().for_each(|child| self.add(child));
+            }
+        }
+    }
+
+    fn add_node(&mut self, node: &Node) {
+        self.nodes += 1;
+        self.variable_bytes +=
+            node.variables.capacity() * core::mem::size_of::<(&str, VariableKind)>();
+        node.variables
+            .values()
+            .for_each(|value| self.add_variable(value));
+        if let Some(children) = &node.children {
+            self.variable_bytes += children.capacity() * core::mem::size_of::<Nodes>();
+            children.iter().for_each(|child| self.add(child));
+        }
+        if let Some(tokens) = &node.tokens {
+            self.variable_bytes += tokens.capacity() * core::mem::size_of::<Token>();
+        }
+        self.variable_bytes += node.fields.capacity() * core::mem::size_of::<(&str, usize, &str)>();
+        if let Some(trivia) = &node.trivia {
+            self.variable_bytes += core::mem::size_of::<Trivia>();
+            self.tokens += trivia.leading.len() + trivia.trailing.len();
+        }
+    }
+
+    fn add_variables(&mut self, variables: &Map<String, VariableKind>) {
+        for (name, value) in variables {
+            self.variable_bytes += name.capacity() + core::mem::size_of::<(String, VariableKind)>();
+            self.add_variable(value);
+        }
+    }
+
+    fn add_variable(&mut self, value: &VariableKind) {
+        match value {
+            VariableKind::Node(Some(value)) => self.add(value),
+            VariableKind::NodeList(list) => {
+                self.variable_bytes += list.capacity() * core::mem::size_of::<Nodes>();
+                list.iter().for_each(|value| self.add(value));
+            }
+            VariableKind::Node(None)
+            | VariableKind::Boolean(_)
+            | VariableKind::Number(_)
+            | VariableKind::Number64(_)
+            | VariableKind::Float(_) => (),
+        }
+    }
+}
+
+/// Results of `Parser::parse_all`
+#[derive(Debug)]
+pub struct Parses<'a> {
+    /// Successful parses, the preferred one first if it succeeded
+    pub results: Vec<ParseResult<'a>>,
+    /// Spots where more than one alternative matched in any of the results
+    pub ambiguities: Vec<Ambiguity<'a>>,
+    /// Error of the preferred parse, the one `parse` would return
+    ///
+    /// The results can still contain parses that took a different alternative
+    pub error: Option<ParseError<'a>>,
+    /// False if the exploration stopped at one of the `Exploration` limits
+    pub complete: bool,
+}
+
+/// `IsOneOf` rule where more than one alternative matched the same tokens
+#[derive(Debug, Clone)]
+pub struct Ambiguity<'a> {
+    /// Node containing the rule
+    pub node: &'a str,
+    /// Bytes of the token the alternatives start at
+    pub span: core::ops::Range<usize>,
+    /// Matching alternatives in the order they are tried
+    pub alternatives: Vec<MatchToken<'a>>,
+}
+
+/// Per node statistics collected during parsing
+#[derive(Debug, Clone, Default)]
+pub struct ParseStats<'a> {
+    pub nodes: Map<&'a str, NodeStats>,
+    /// Number of times each `OneOf` matched, keyed by its address in the grammar
+    ///
+    /// Read through `testing::Coverage`
+    pub(crate) alternatives: Map<usize, usize>,
+    /// Self time of every rule, keyed by the node and the index of the rule in it
+    ///
+    /// Rules are numbered in the order they are written, nested rules included
+    pub rules: Map<(&'a str, usize), core::time::Duration>,
+    /// Self time of every stack of nodes with their current rules, see `ParseStats::write_folded`
+    pub stacks: Map<Vec<(&'a str, usize)>, core::time::Duration>,
+}
+
+impl<'a> ParseStats<'a> {
+    /// Returns the nodes sorted by the number of backtracks, highest first
+    pub fn by_backtracks(&self) -> Vec<(&'a str, &NodeStats)> {
+        let mut nodes = self
+            .nodes
+            .iter()
+            .map(|(name, stats)| (*name, stats))
+            .collect::<Vec<_>>();
+        nodes.sort_by(|a, b| b.1.backtracks.cmp(&a.1.backtracks).then(a.0.cmp(b.0)));
+        nodes
+    }
+
+    /// Returns the `n` rules with the highest self time, highest first
+    pub fn top_rules(&self, n: usize) -> Vec<((&'a str, usize), core::time::Duration)> {
+        let mut rules = self
+            .rules
+            .iter()
+            .map(|(rule, time)| (*rule, *time))
+            .collect::<Vec<_>>();
+        rules.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        rules.truncate(n);
+        rules
+    }
+
+    /// Writes the stacks in the folded format read by flamegraph tools
+    ///
+    /// Every line is a stack of `node:rule` frames and its self time in nanoseconds
+    pub fn write_folded(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        let mut stacks = self.stacks.iter().collect::<Vec<_>>();
+        stacks.sort();
+        for (stack, time) in stacks {
+            for (i, (node, rule)) in stack.iter().enumerate() {
+                if i > 0 {
+                    w.write_char(';')?;
+                }
+                write!(w, "{}:{}", node, rule)?;
             }
+            writeln!(w, " {}", time.as_nanos())?;
         }
         Ok(())
     }
 }
 
-enum TokenCompare<'a> {
-    Is(Nodes<'a>),
-    IsNot(ParseError<'a>),
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NodeStats {
+    /// Number of times the node was entered
+    pub invocations: usize,
+    pub successes: usize,
+    /// Failures after which the parser tried another alternative
+    pub backtracks: usize,
+    /// Failures of committed nodes that were propagated as errors
+    pub failures: usize,
+    /// Time spent in the node including its children
+    ///
+    /// Only measured with the `std` feature
+    pub time: core::time::Duration,
 }
 
-#[derive(Debug)]
-pub struct ParseResult<'a> {
-    pub entry: Node<'a>,
-    pub globals: Map<String, VariableKind<'a>>,
+impl NodeStats {
+    pub fn success_ratio(&self) -> f64 {
+        match self.invocations {
+            0 => 0.0,
+            n => self.successes as f64 / n as f64,
+        }
+    }
+
+    pub fn backtrack_ratio(&self) -> f64 {
+        match self.invocations {
+            0 => 0.0,
+            n => self.backtracks as f64 / n as f64,
+        }
+    }
 }
 
 pub mod map_tools {
     use super::*;
 
-    pub fn try_get_node<'a>(map: &'a Map<String, VariableKind>, key: &str) -> Option<&'a Node<'a>> {
+    pub fn try_get_node<'a>(map: &'a Variables<'a>, key: &str) -> Option<&'a Node<'a>> {
         match map.get(key) {
             Some(VariableKind::Node(Some(Nodes::Node(node)))) => Some(node),
             _ => None,
         }
     }
 
-    pub fn get_node<'a>(map: &'a Map<String, VariableKind>, key: &str) -> &'a Node<'a> {
+    pub fn get_node<'a>(map: &'a Variables<'a>, key: &str) -> &'a Node<'a> {
         match map.get(key) {
             Some(n) => match n {
                 VariableKind::Node(Some(Nodes::Node(node))) => node,
-                _ => panic!("Node found with a different type {:#?}", n),
+                _ => panic!("Node found with a different type {}", n),
             },
             _ => panic!("Node not found"),
         }
     }
 
     pub fn try_get_node_list<'a>(
-        map: &'a Map<String, VariableKind>,
+        map: &'a Variables<'a>,
         key: &str,
-    ) -> Option<&'a Vec<Nodes<'a>>> {
+    ) -> Option<&'a NodeVec<Nodes<'a>>> {
         match map.get(key) {
             Some(VariableKind::NodeList(list)) => Some(list),
             _ => None,
         }
     }
 
-    pub fn get_node_list<'a>(map: &'a Map<String, VariableKind>, key: &str) -> &'a Vec<Nodes<'a>> {
+    pub fn get_node_list<'a>(map: &'a Variables<'a>, key: &str) -> &'a NodeVec<Nodes<'a>> {
         match map.get(key) {
             Some(list) => match list {
                 VariableKind::NodeList(list) => list,
-                _ => panic!("Node list found with a different type {:#?}", list),
+                _ => panic!("Node list found with a different type {}", list),
             },
             _ => panic!("Node list not found"),
         }
     }
 
-    pub fn try_get_boolean(map: &Map<String, VariableKind>, key: &str) -> Option<bool> {
+    pub fn try_get_boolean(map: &Variables, key: &str) -> Option<bool> {
         match map.get(key) {
             Some(VariableKind::Boolean(val)) => Some(*val),
             _ => None,
         }
     }
 
-    pub fn get_boolean(map: &Map<String, VariableKind>, key: &str) -> bool {
+    pub fn get_boolean(map: &Variables, key: &str) -> bool {
         match map.get(key) {
             Some(val) => match val {
                 VariableKind::Boolean(val) => *val,
-                _ => panic!("Boolean found with a different type {:#?}", val),
+                _ => panic!("Boolean found with a different type {}", val),
             },
             _ => panic!("Boolean not found"),
         }
     }
 
-    pub fn try_get_number(map: &Map<String, VariableKind>, key: &str) -> Option<i32> {
+    pub fn try_get_number(map: &Variables, key: &str) -> Option<i32> {
         match map.get(key) {
             Some(VariableKind::Number(val)) => Some(*val),
             _ => None,
         }
     }
 
-    pub fn get_number(map: &Map<String, VariableKind>, key: &str) -> i32 {
+    pub fn get_number(map: &Variables, key: &str) -> i32 {
         match map.get(key) {
             Some(val) => match val {
                 VariableKind::Number(val) => *val,
-                _ => panic!("Number found with a different type {:#?}", val),
+                _ => panic!("Number found with a different type {}", val),
             },
             _ => panic!("Number not found"),
         }
@@ -1636,13 +4137,27 @@ pub mod map_tools {
 
 #[derive(Debug, Clone)]
 pub enum Nodes<'a> {
-    Node(Node<'a>),
+    /// Shared with the results of `crate::Parser::reparse` that did not change it,
+    /// see `Node::version`
+    Node(NodeRef<'a>),
     Token(Token<'a>),
+    /// Text skipped while recovering from an error, see `Parser::recovery`
+    ///
+    /// Takes the place of the node that failed, so consumers can tell patched over
+    /// regions from healthy nodes
+    Error {
+        /// Byte range of the skipped text
+        span: core::ops::Range<usize>,
+        /// What the parser expected where the node failed
+        expected: NodeVec<MatchToken<'a>>,
+        /// Tokens that were skipped
+        children: NodeVec<Nodes<'a>>,
+    },
 }
 
 impl<'a> From<Node<'a>> for Nodes<'a> {
     fn from(value: Node<'a>) -> Self {
-        Nodes::Node(value)
+        Nodes::Node(NodeRef::new(value))
     }
 }
 
@@ -1667,96 +4182,278 @@ impl<'a> Nodes<'a> {
         }
     }
 
-    pub fn unwrap_node(&self) -> &Node<'_> {
-        match self {
-            Nodes::Node(node) => node,
-            _ => panic!("unwrap_node called on {:#?}", self),
-        }
+    #[deprecated(note = "renamed to `as_node_unchecked`, see `try_as_node`")]
+    #[track_caller]
+    pub fn unwrap_node(&self) -> &Node<'a> {
+        self.as_node_unchecked()
     }
 
-    pub fn unwrap_token(&'_ self) -> &'_ Token<'_> {
-        match self {
-            Nodes::Token(token) => token,
-            _ => panic!("unwrap_token called on {:#?}", self),
-        }
+    #[deprecated(note = "renamed to `try_as_node`")]
+    pub fn try_unwrap_node(&self) -> Result<&Node<'a>, AccessError> {
+        self.try_as_node()
+    }
+
+    #[deprecated(note = "renamed to `token_unchecked`, see `try_token`")]
+    #[track_caller]
+    pub fn unwrap_token(&self) -> &Token<'a> {
+        self.token_unchecked()
+    }
+
+    #[deprecated(note = "renamed to `try_token`")]
+    pub fn try_unwrap_token(&self) -> Result<&Token<'a>, AccessError> {
+        self.try_token()
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Nodes::Error { .. })
     }
 
     pub fn str_idx(&self) -> usize {
         match self {
             Nodes::Node(node) => node.first_string_idx,
             Nodes::Token(token) => token.index,
+            Nodes::Error { span, .. } => span.start,
         }
     }
+    /// Index right after the text, the end of the range `str_idx()..str_last_idx()`
     pub fn str_last_idx(&self) -> usize {
         match self {
             Nodes::Node(node) => node.last_string_idx,
-            Nodes::Token(token) => token.index + token.len - 1,
+            Nodes::Token(token) => token.index + token.len,
+            Nodes::Error { span, .. } => span.end,
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Node<'a> {
-    pub name: &'a str,
-    pub variables: Map<String, VariableKind<'a>>,
+    pub name: intern::Symbol,
+    pub variables: Variables<'a>,
     encoutered_first_match: bool,
     pub(crate) first_string_idx: usize,
     pub(crate) last_string_idx: usize,
     pub(crate) commit: bool,
+    /// Commit was set by a cut and ends with the current rule block
+    pub(crate) cut: bool,
     pub docs: Option<&'a str>,
-    pub location: TextLocation,
+    /// Copied from the grammar node
+    pub foldable: bool,
+    /// Copied from the grammar node
+    pub indents_children: bool,
+    /// Copied from the grammar node
+    pub symbol: Option<grammar::Symbol<'a>>,
+    /// Everything the node matched in the order it was matched
+    ///
+    /// Only recorded for nodes with `record_children` set in the grammar
+    pub children: Option<NodeVec<Nodes<'a>>>,
+    /// Tokens the node matched itself in the order they were matched
+    ///
+    /// Only recorded for nodes with `record_tokens` set in the grammar,
+    /// boxed like the trivia to keep nodes small
+    pub tokens: Option<NodeBox<NodeVec<Token<'a>>>>,
+    /// Field labels of list entries set with `Parameters::SetField`
+    ///
+    /// Stored as the list variable, index of the entry and the label
+    pub fields: NodeVec<(&'a str, usize, &'a str)>,
+    /// Only kept if `Grammar::trivia` is set
+    pub(crate) trivia: Option<NodeBox<Trivia<'a>>>,
+    /// `ParseResult::version` of the result that built or moved the node
+    pub(crate) version: u64,
+    /// Allocator of the node and everything in it, see `crate::memory`
+    pub(crate) alloc: ParseAlloc,
+}
+
+/// Whitespace and ignored tokens around a node
+#[derive(Debug, Clone)]
+pub(crate) struct Trivia<'a> {
+    pub(crate) leading: NodeVec<Token<'a>>,
+    pub(crate) trailing: NodeVec<Token<'a>>,
+}
+
+impl<'a> Trivia<'a> {
+    /// Collects trivia around the tokens `first..=last` and splits it by the policy
+    fn around(
+        policy: grammar::TriviaPolicy,
+        tokens: &dyn TokenSource<'a>,
+        ignored: &[TokenKinds<'a>],
+        first: usize,
+        last: usize,
+        alloc: ParseAlloc,
+    ) -> Self {
+        use grammar::TriviaPolicy::*;
+        let is_trivia = |t: &Token| t.kind.is_whitespace() || ignored.contains(&t.kind);
+        let start = first - tokens.range(0..first).rev().take_while(is_trivia).count();
+        let end = last
+            + 1
+            + tokens
+                .range(last + 1..tokens.len())
+                .take_while(is_trivia)
+                .count();
+        // Index right after the first new line of the range
+        let line_end = |start: usize, end: usize| {
+            tokens
+                .range(start..end)
+                .position(|t| t.kind == TokenKinds::Control(ControlTokenKind::Eol))
+                .map_or(end, |i| start + i + 1)
+        };
+        // Trivia at the edges of the file has only one node to attach to
+        let at_eof = tokens
+            .get(end)
+            .is_none_or(|t| t.kind == TokenKinds::Control(ControlTokenKind::Eof));
+        let leading = match policy {
+            _ if start == 0 => start,
+            AttachToNext => start,
+            AttachToPrevious => first,
+            SplitAtLine => line_end(start, first),
+        };
+        let trailing = match policy {
+            _ if at_eof => end,
+            AttachToNext => last + 1,
+            AttachToPrevious => end,
+            SplitAtLine => line_end(last + 1, end),
+        };
+        Trivia {
+            leading: alloc.collect(tokens.range(leading..first)),
+            trailing: alloc.collect(tokens.range(last + 1..trailing)),
+        }
+    }
 }
 
 impl<'a> Node<'a> {
     pub fn new(name: &'a str) -> Node<'a> {
+        Self::new_in(name, ParseAlloc::default())
+    }
+
+    /// Node kept in the allocator of a parse, see `crate::memory`
+    pub fn new_in(name: &'a str, alloc: ParseAlloc) -> Node<'a> {
+        Self::of_symbol_in(intern::Symbol::intern(name), alloc)
+    }
+
+    fn of_symbol_in(name: intern::Symbol, alloc: ParseAlloc) -> Node<'a> {
         Node {
             name,
-            variables: Map::new(),
+            variables: Variables::new_in(alloc),
             encoutered_first_match: false,
             first_string_idx: 0,
             last_string_idx: 0,
             commit: false,
+            cut: false,
             docs: None,
-            location: TextLocation::new(0, 0, 0, 0),
+            foldable: false,
+            indents_children: false,
+            symbol: None,
+            children: None,
+            tokens: None,
+            fields: alloc.vec(),
+            trivia: None,
+            version: 0,
+            alloc,
         }
     }
 
+    /// `ParseResult::version` of the result that built the node
+    ///
+    /// `crate::Parser::reparse` shares unchanged nodes with the previous result, they keep
+    /// its version. Nodes that were parsed again or moved by the edit get the new one.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     pub fn from_grammar(
         grammar: &'a Grammar<'a>,
         name: &'a str,
     ) -> Result<Node<'a>, ParseError<'a>> {
-        let found = match grammar.nodes.get(name) {
-            Some(node) => node,
-            None => {
-                return Err(ParseError {
-                    hint: None,
-                    kind: ParseErrors::NodeNotFound(name),
-                    location: TextLocation::new(0, 0, 0, 0),
-                    node: None,
-                    importance: 0,
-                })
-            }
-        };
-        let mut node = Node::new(found.name);
-        node.variables = Self::variables_from_grammar(&found.variables)?;
+        Self::from_grammar_in(grammar, name, ParseAlloc::default())
+    }
+
+    /// Node of the grammar kept in the allocator of a parse, see `crate::memory`
+    pub fn from_grammar_in(
+        grammar: &'a Grammar<'a>,
+        name: &'a str,
+        alloc: ParseAlloc,
+    ) -> Result<Node<'a>, ParseError<'a>> {
+        match grammar.node(name) {
+            Some(found) => Ok(Self::from_grammar_node_in(found, alloc)),
+            None => Err(Self::not_found(name)),
+        }
+    }
+
+    fn not_found(name: &'a str) -> ParseError<'a> {
+        ParseError {
+            origin: None,
+            hint: None,
+            kind: ParseErrors::NodeNotFound(name),
+            span: 0..0,
+            node: None,
+            importance: 0,
+        }
+    }
+
+    /// Node of an already found grammar node, the parser looks nodes up by their symbol
+    fn from_grammar_node_in(found: &'a grammar::Node<'a>, alloc: ParseAlloc) -> Node<'a> {
+        let mut node = Node::of_symbol_in(found.name, alloc);
+        node.variables = Self::variables_of(&found.variables, alloc);
         node.docs = found.docs;
-        Ok(node)
+        node.foldable = found.foldable;
+        node.indents_children = found.indents_children;
+        node.symbol = found.symbol;
+        if found.record_children {
+            node.children = Some(alloc.vec());
+        }
+        if found.record_tokens {
+            node.tokens = Some(alloc.boxed(alloc.vec()));
+        }
+        for (name, value) in &found.initial {
+            let Some(variable) = node.variables.get_mut(name) else {
+                continue;
+            };
+            // values of another kind are rejected by the validator, never change the declared kind
+            match (variable, value) {
+                (VariableKind::Number(v), grammar::InitialValue::Number(n)) => *v = *n,
+                (VariableKind::Number64(v), grammar::InitialValue::Number64(n)) => *v = *n,
+                (VariableKind::Float(v), grammar::InitialValue::Float(n)) => *v = *n,
+                (VariableKind::Boolean(v), grammar::InitialValue::Boolean(b)) => *v = *b,
+                _ => (),
+            }
+        }
+        node
     }
 
     pub fn variables_from_grammar(
-        variables: &[(&'a str, grammar::VariableKind)],
-    ) -> Result<Map<String, VariableKind<'a>>, ParseError<'a>> {
-        let mut result = Map::new();
+        variables: &[(intern::Symbol, grammar::VariableKind)],
+    ) -> Result<Variables<'a>, ParseError<'a>> {
+        Self::variables_from_grammar_in(variables, ParseAlloc::default())
+    }
+
+    /// Variables of a grammar node kept in the allocator of a parse, see `crate::memory`
+    pub fn variables_from_grammar_in(
+        variables: &[(intern::Symbol, grammar::VariableKind)],
+        alloc: ParseAlloc,
+    ) -> Result<Variables<'a>, ParseError<'a>> {
+        Ok(Self::variables_of(variables, alloc))
+    }
+
+    fn variables_of(
+        variables: &[(intern::Symbol, grammar::VariableKind)],
+        alloc: ParseAlloc,
+    ) -> Variables<'a> {
+        let mut result = Variables::new_in(alloc);
         for value in variables.iter() {
-            let var = match value.1 {
-                crate::grammar::VariableKind::Node => VariableKind::Node(None),
-                crate::grammar::VariableKind::NodeList => VariableKind::NodeList(Vec::new()),
-                crate::grammar::VariableKind::Boolean => VariableKind::Boolean(false),
-                crate::grammar::VariableKind::Number => VariableKind::Number(0),
-            };
-            result.insert(value.0.to_string(), var);
+            result.insert(value.0, Self::variable_default(value.1, alloc));
+        }
+        result
+    }
+
+    /// Value a variable of the kind starts with
+    fn variable_default(kind: grammar::VariableKind, alloc: ParseAlloc) -> VariableKind<'a> {
+        match kind {
+            crate::grammar::VariableKind::Node => VariableKind::Node(None),
+            crate::grammar::VariableKind::NodeList => VariableKind::NodeList(alloc.vec()),
+            crate::grammar::VariableKind::Boolean => VariableKind::Boolean(false),
+            crate::grammar::VariableKind::Number => VariableKind::Number(0),
+            crate::grammar::VariableKind::Number64 => VariableKind::Number64(0),
+            crate::grammar::VariableKind::Float => VariableKind::Float(0.0),
         }
-        Ok(result)
     }
 }
 
@@ -1764,26 +4461,178 @@ fn err<'a>(
     error: ParseErrors<'a>,
     cursor: &mut Cursor,
     cursor_clone: &Cursor,
-    location: &TextLocation,
+    span: core::ops::Range<usize>,
     node: Option<Node<'a>>,
     parameters: Option<&'a [Parameters<'a>]>,
 ) -> Result<(), ParseError<'a>> {
     *cursor = cursor_clone.clone();
     Err(ParseError {
+        origin: None,
         kind: error,
-        location: *location,
+        span,
         node,
         hint: Parser::find_hint(parameters),
         importance: Parser::get_importance(parameters),
     })
 }
 
+/// Variables of a parsed node by name, in the order the grammar declares them
+///
+/// Nodes have a handful of variables, so they are kept in the allocator of the parse
+/// as a list and looked up by comparing the names. The names are interned, see
+/// `Variables::get_of`.
+#[derive(Debug, Clone)]
+pub struct Variables<'a> {
+    entries: NodeVec<(intern::Symbol, VariableKind<'a>)>,
+}
+
+impl Default for Variables<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Variables<'a> {
+    pub fn new() -> Self {
+        Self::new_in(ParseAlloc::default())
+    }
+
+    pub fn new_in(alloc: ParseAlloc) -> Self {
+        Variables {
+            entries: alloc.vec(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&VariableKind<'a>> {
+        self.entries
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut VariableKind<'a>> {
+        self.entries
+            .iter_mut()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// `Variables::get` by the interned name
+    pub fn get_of(&self, name: intern::Symbol) -> Option<&VariableKind<'a>> {
+        self.entries
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// `Variables::get_mut` by the interned name
+    pub fn get_of_mut(&mut self, name: intern::Symbol) -> Option<&mut VariableKind<'a>> {
+        self.entries
+            .iter_mut()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets the variable, returns the previous value
+    pub fn insert(
+        &mut self,
+        name: intern::Symbol,
+        value: VariableKind<'a>,
+    ) -> Option<VariableKind<'a>> {
+        match self.get_of_mut(name) {
+            Some(current) => Some(core::mem::replace(current, value)),
+            None => {
+                self.entries.push((name, value));
+                None
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of variables the list has room for, see `ParseResult::memory_report`
+    pub(crate) fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &VariableKind<'a>)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&'a str, &mut VariableKind<'a>)> {
+        self.entries
+            .iter_mut()
+            .map(|(key, value)| (key.as_str(), value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.entries.iter().map(|(key, _)| key.as_str())
+    }
+
+    /// Interned names of the variables
+    pub fn symbols(&self) -> impl Iterator<Item = intern::Symbol> + '_ {
+        self.entries.iter().map(|(key, _)| *key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &VariableKind<'a>> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut VariableKind<'a>> {
+        self.entries.iter_mut().map(|(_, value)| value)
+    }
+}
+
+impl<'a> core::ops::Index<&str> for Variables<'a> {
+    type Output = VariableKind<'a>;
+
+    #[track_caller]
+    fn index(&self, name: &str) -> &VariableKind<'a> {
+        match self.get(name) {
+            Some(value) => value,
+            None => panic!("Variable not found: {}", name),
+        }
+    }
+}
+
+impl<'v, 'a> IntoIterator for &'v Variables<'a> {
+    type Item = (&'a str, &'v VariableKind<'a>);
+    type IntoIter = core::iter::Map<
+        core::slice::Iter<'v, (intern::Symbol, VariableKind<'a>)>,
+        fn(&'v (intern::Symbol, VariableKind<'a>)) -> (&'a str, &'v VariableKind<'a>),
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum VariableKind<'a> {
     Node(Option<Nodes<'a>>),
-    NodeList(Vec<Nodes<'a>>),
+    NodeList(NodeVec<Nodes<'a>>),
     Boolean(bool),
     Number(i32),
+    Number64(i64),
+    Float(f64),
 }
 
 impl<'a> VariableKind<'a> {
@@ -1794,88 +4643,384 @@ impl<'a> VariableKind<'a> {
         }
     }
 
-    pub fn is_node_list(&self) -> bool {
+    pub fn is_node_list(&self) -> bool {
+        match self {
+            VariableKind::NodeList(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_boolean(&self) -> bool {
+        match self {
+            VariableKind::Boolean(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        match self {
+            VariableKind::Number(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_number64(&self) -> bool {
+        matches!(self, VariableKind::Number64(_))
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, VariableKind::Float(_))
+    }
+
+    /// Orders two numeric values of any kind
+    ///
+    /// Integers are compared exactly, floats only when either side is a float.
+    /// Returns `None` for non-numeric values and NaN
+    pub fn cmp_numeric(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        match (self, other) {
+            (VariableKind::Float(_), _) | (_, VariableKind::Float(_)) => {
+                self.as_f64()?.partial_cmp(&other.as_f64()?)
+            }
+            _ => Some(self.as_i64()?.cmp(&other.as_i64()?)),
+        }
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        match self {
+            VariableKind::Number(n) => Some(*n as i64),
+            VariableKind::Number64(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            VariableKind::Float(n) => Some(*n),
+            _ => self.as_i64().map(|n| n as f64),
+        }
+    }
+
+    /// Panics if the variable is not a node or is not set
+    #[track_caller]
+    pub fn node_unchecked(&self) -> &Nodes<'a> {
+        match self {
+            VariableKind::Node(Some(node)) => node,
+            _ => panic!("node_unchecked called on {}", self),
+        }
+    }
+
+    /// None if the variable is not a node or is not set
+    pub fn try_unwrap_node(&self) -> Option<&Nodes<'a>> {
+        match self {
+            VariableKind::Node(n) => n.as_ref(),
+            _ => None,
+        }
+    }
+
+    #[track_caller]
+    pub fn node_list_unchecked(&self) -> &NodeVec<Nodes<'a>> {
+        match self {
+            VariableKind::NodeList(list) => list,
+            _ => panic!("node_list_unchecked called on {}", self),
+        }
+    }
+
+    #[track_caller]
+    pub fn boolean_unchecked(&self) -> &bool {
+        match self {
+            VariableKind::Boolean(val) => val,
+            _ => panic!("boolean_unchecked called on {}", self),
+        }
+    }
+
+    #[track_caller]
+    pub fn number_unchecked(&self) -> &i32 {
+        match self {
+            VariableKind::Number(val) => val,
+            _ => panic!("number_unchecked called on {}", self),
+        }
+    }
+
+    #[track_caller]
+    pub fn number64_unchecked(&self) -> &i64 {
+        match self {
+            VariableKind::Number64(val) => val,
+            _ => panic!("number64_unchecked called on {}", self),
+        }
+    }
+
+    #[track_caller]
+    pub fn float_unchecked(&self) -> &f64 {
+        match self {
+            VariableKind::Float(val) => val,
+            _ => panic!("float_unchecked called on {}", self),
+        }
+    }
+
+    #[deprecated(note = "renamed to `node_unchecked`")]
+    #[track_caller]
+    pub fn unwrap_node(&self) -> &Nodes<'a> {
+        self.node_unchecked()
+    }
+
+    #[deprecated(note = "renamed to `node_list_unchecked`")]
+    #[track_caller]
+    pub fn unwrap_node_list(&self) -> &NodeVec<Nodes<'a>> {
+        self.node_list_unchecked()
+    }
+
+    #[deprecated(note = "renamed to `boolean_unchecked`")]
+    #[track_caller]
+    pub fn unwrap_boolean(&self) -> &bool {
+        self.boolean_unchecked()
+    }
+
+    #[deprecated(note = "renamed to `number_unchecked`")]
+    #[track_caller]
+    pub fn unwrap_number(&self) -> &i32 {
+        self.number_unchecked()
+    }
+
+    pub fn stringify(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            VariableKind::Node(Some(nodes)) => nodes.stringify(text).into(),
+            VariableKind::NodeList(items) => format!("Nodes len: {}", items.len()).into(),
+            VariableKind::Boolean(v) => v.to_string().into(),
+            VariableKind::Number(v) => v.to_string().into(),
+            VariableKind::Number64(v) => v.to_string().into(),
+            VariableKind::Float(v) => v.to_string().into(),
+            VariableKind::Node(None) => "None".into(),
+        }
+    }
+}
+
+/// Maximum number of list items printed in a summary
+const SUMMARY_LIST_LEN: usize = 4;
+
+/// Bounded and deterministic printable view of a node
+///
+/// Unlike `Debug`, it never descends deeper than the given depth
+/// and prints variables sorted by name, so printing a node
+/// from a huge parse doesn't dump the whole tree
+pub struct NodeSummary<'n, 'a> {
+    node: &'n Node<'a>,
+    depth: usize,
+}
+
+impl<'a> Node<'a> {
+    /// Returns a printable summary of the node
+    ///
+    /// `depth` is the number of levels of variables that will be printed,
+    /// 0 prints only the name and span of the node
+    pub fn summary(&self, depth: usize) -> NodeSummary<'_, 'a> {
+        NodeSummary { node: self, depth }
+    }
+}
+
+impl fmt::Display for NodeSummary<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let node = self.node;
+        write!(
+            f,
+            "{}[{}..{}]",
+            node.name, node.first_string_idx, node.last_string_idx
+        )?;
+        if self.depth == 0 || node.variables.is_empty() {
+            return Ok(());
+        }
+        let mut keys = node.variables.keys().collect::<Vec<_>>();
+        keys.sort();
+        write!(f, " {{ ")?;
+        for (i, key) in keys.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{key}: ")?;
+            node.variables[key].write_summary(f, self.depth - 1)?;
+        }
+        write!(f, " }}")
+    }
+}
+
+impl<'a> Nodes<'a> {
+    fn write_summary(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
         match self {
-            VariableKind::NodeList(_) => true,
-            _ => false,
+            Nodes::Node(node) => write!(f, "{}", node.summary(depth)),
+            Nodes::Token(token) => write!(
+                f,
+                "{}[{}..{}]",
+                token.kind,
+                token.index,
+                token.index + token.len
+            ),
+            Nodes::Error { span, .. } => write!(f, "<error>[{}..{}]", span.start, span.end),
         }
     }
+}
 
-    pub fn is_boolean(&self) -> bool {
+impl<'a> VariableKind<'a> {
+    fn write_summary(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
         match self {
-            VariableKind::Boolean(_) => true,
-            _ => false,
+            VariableKind::Node(Some(node)) => node.write_summary(f, depth),
+            VariableKind::Node(None) => write!(f, "None"),
+            VariableKind::NodeList(list) => {
+                write!(f, "[")?;
+                for (i, node) in list.iter().take(SUMMARY_LIST_LEN).enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    node.write_summary(f, depth)?;
+                }
+                if list.len() > SUMMARY_LIST_LEN {
+                    write!(f, ", ... {} more", list.len() - SUMMARY_LIST_LEN)?;
+                }
+                write!(f, "]")
+            }
+            VariableKind::Boolean(v) => write!(f, "{v}"),
+            VariableKind::Number(v) => write!(f, "{v}"),
+            VariableKind::Number64(v) => write!(f, "{v}"),
+            VariableKind::Float(v) => write!(f, "{v}"),
         }
     }
+}
 
-    pub fn is_number(&self) -> bool {
-        match self {
-            VariableKind::Number(_) => true,
-            _ => false,
-        }
+impl fmt::Display for Node<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.summary(1))
     }
+}
 
-    pub fn unwrap_node(&self) -> &Nodes<'_> {
-        match self {
-            VariableKind::Node(Some(node)) => node,
-            _ => panic!("unwrap_node called on {:#?}", self),
-        }
+/// What `ParseResult::dump_tree` prints
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DumpOptions<'f> {
+    /// Levels of nodes printed below the entry, every level if None
+    pub max_depth: Option<usize>,
+    /// Print tokens stored in variables, otherwise only nodes and values are printed
+    pub include_tokens: bool,
+    /// Print only nodes with these names, nodes stored in the other nodes take their place
+    ///
+    /// Every node is printed if empty
+    pub filter_nodes: &'f [&'f str],
+}
+
+impl ParseResult<'_> {
+    /// Writes the parse tree with one node, token or value per line,
+    /// children are indented below their parent
+    ///
+    /// ```text
+    /// file[0..10]
+    ///   stmts[0]: let[0..10]
+    ///     mutable: false
+    ///     name: <text>[4..5]
+    /// ```
+    ///
+    /// Variables are sorted by name like in `Node::summary`,
+    /// nodes cut off by `DumpOptions::max_depth` end with `{ ... }`
+    pub fn dump_tree(&self, w: &mut impl fmt::Write, options: &DumpOptions) -> fmt::Result {
+        dump_node(w, options, &self.entry, None, 0, 0)
     }
+}
 
-    pub fn try_unwrap_node(&self) -> &Option<Nodes<'_>> {
-        match self {
-            VariableKind::Node(n) => n,
-            _ => panic!("try_unwrap_node called on {self:#?}"),
+fn dump_node(
+    w: &mut impl fmt::Write,
+    options: &DumpOptions,
+    node: &Node,
+    label: Option<&str>,
+    depth: usize,
+    indent: usize,
+) -> fmt::Result {
+    let shown =
+        options.filter_nodes.is_empty() || options.filter_nodes.contains(&node.name.as_str());
+    let mut indent = indent;
+    if shown {
+        write!(w, "{:1$}", "", indent * 2)?;
+        if let Some(label) = label {
+            write!(w, "{label}: ")?;
+        }
+        write!(w, "{}", node.summary(0))?;
+        if options.max_depth.is_some_and(|max| depth >= max) {
+            if !node.variables.is_empty() {
+                write!(w, " {{ ... }}")?;
+            }
+            return writeln!(w);
         }
+        writeln!(w)?;
+        indent += 1;
     }
-
-    pub fn unwrap_node_list(&self) -> &Vec<Nodes<'_>> {
-        match self {
-            VariableKind::NodeList(list) => list,
-            _ => panic!("unwrap_node_list called on {:#?}", self),
+    let mut keys = node.variables.keys().collect::<Vec<_>>();
+    keys.sort();
+    for key in keys {
+        let value = &node.variables[key];
+        match value {
+            VariableKind::Node(Some(child)) if shown || child.is_node() => {
+                dump_nodes(w, options, child, key, depth + 1, indent)?
+            }
+            VariableKind::NodeList(list) => {
+                for (i, child) in list.iter().enumerate() {
+                    if !shown && !child.is_node() {
+                        continue;
+                    }
+                    let label = format!("{key}[{i}]");
+                    dump_nodes(w, options, child, &label, depth + 1, indent)?;
+                }
+            }
+            _ if !shown => (),
+            VariableKind::Node(None) => writeln!(w, "{:1$}{key}: None", "", indent * 2)?,
+            _ => writeln!(w, "{:1$}{key}: {value}", "", indent * 2)?,
         }
     }
+    Ok(())
+}
 
-    pub fn unwrap_boolean(&self) -> &bool {
-        match self {
-            VariableKind::Boolean(val) => val,
-            _ => panic!("unwrap_boolean called on {:#?}", self),
-        }
+fn dump_nodes(
+    w: &mut impl fmt::Write,
+    options: &DumpOptions,
+    nodes: &Nodes,
+    label: &str,
+    depth: usize,
+    indent: usize,
+) -> fmt::Result {
+    match nodes {
+        Nodes::Node(node) => dump_node(w, options, node, Some(label), depth, indent),
+        Nodes::Token(_) if !options.include_tokens => Ok(()),
+        _ => writeln!(w, "{:1$}{label}: {nodes}", "", indent * 2),
     }
+}
 
-    pub fn unwrap_number(&self) -> &i32 {
-        match self {
-            VariableKind::Number(val) => val,
-            _ => panic!("unwrap_number called on {:#?}", self),
-        }
+impl fmt::Display for Nodes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_summary(f, 1)
     }
+}
 
-    pub fn stringify(&self, text: &'a str) -> Cow<'a, str> {
-        match self {
-            VariableKind::Node(Some(nodes)) => nodes.stringify(text).into(),
-            VariableKind::NodeList(items) => format!("Nodes len: {}", items.len()).into(),
-            VariableKind::Boolean(v) => v.to_string().into(),
-            VariableKind::Number(v) => v.to_string().into(),
-            VariableKind::Node(None) => "None".into(),
-        }
+impl fmt::Display for VariableKind<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_summary(f, 1)
     }
 }
 
 #[derive(Clone)]
 pub struct ParseError<'a> {
     pub kind: ParseErrors<'a>,
-    pub location: TextLocation,
+    /// Bytes of the error, see `ParseError::locate` for its line and column
+    pub span: core::ops::Range<usize>,
     pub node: Option<Node<'a>>,
     pub hint: Option<&'a str>,
     pub importance: usize,
+    /// Place the rule that caused a developer error was defined at,
+    /// see `ParseErrors::is_developer_error`
+    pub origin: Option<Origin<'a>>,
 }
 
 impl<'a> fmt::Debug for ParseError<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?} at {:?}", self.kind, self.location)?;
+        write!(f, "{:?} at bytes {:?}", self.kind, self.span)?;
+        if let Some(origin) = self.origin {
+            write!(f, " (rule at {origin})")?;
+        }
         match &self.node {
             Some(node) => {
-                let mut txt = format!("\nError in node: {:?}", node.name);
+                let mut txt = format!("\nError in node: {}", node.summary(0));
                 if let Some(docs) = &node.docs {
                     txt.push_str(&format!("\n{}", docs));
                 }
@@ -1888,10 +5033,13 @@ impl<'a> fmt::Debug for ParseError<'a> {
 
 impl<'a> fmt::Display for ParseError<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?} at {:?}", self.kind, self.location)?;
+        write!(f, "{:?} at bytes {:?}", self.kind, self.span)?;
+        if let Some(origin) = self.origin {
+            write!(f, " (rule at {origin})")?;
+        }
         match &self.node {
             Some(node) => {
-                let mut txt = format!("\nError in node: {:?}", node.name);
+                let mut txt = format!("\nError in node: {}", node.summary(0));
                 if let Some(docs) = &node.docs {
                     txt.push_str(&format!("\n{}", docs));
                 }
@@ -1902,6 +5050,163 @@ impl<'a> fmt::Display for ParseError<'a> {
     }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError<'_> {}
+
+impl<'a> ParseError<'a> {
+    /// Converts the error into a form that does not borrow the grammar
+    pub fn into_owned(self) -> ParseErrorOwned {
+        self.into()
+    }
+
+    /// Error code, byte offset and the expected token, see `CompactError`
+    pub fn compact(&self) -> CompactError<'_, 'a> {
+        CompactError(self)
+    }
+
+    /// Location of the error derived from its byte range
+    ///
+    /// Use `Lexer::locate` to count the columns the way the lexer does
+    pub fn locate(&self, lines: &LineIndex, tabs: TabPolicy) -> TextLocation {
+        lines.locate(self.span.start, self.span.len(), tabs)
+    }
+}
+
+/// Short form of a parse error for devices without the diagnostic renderer
+///
+/// Displayed as `E0201@12 expected ;`, the node, hint and text are left out.
+/// Implements `defmt::Format` with the `defmt` feature to log errors over RTT.
+#[derive(Debug, Clone, Copy)]
+pub struct CompactError<'e, 'a>(&'e ParseError<'a>);
+
+/// What a compact error expected instead of the found token
+enum Expected<'a> {
+    Nothing,
+    Name(&'a str),
+    OneOf(usize),
+}
+
+impl<'a> CompactError<'_, 'a> {
+    fn expected(&self) -> Expected<'a> {
+        let name = |token: &MatchToken<'a>| match token {
+            MatchToken::Token(kind) => Expected::Name(kind.name()),
+            MatchToken::Node(name) => Expected::Name(name.as_str()),
+            MatchToken::Word(name) | MatchToken::Enumerator(name) | MatchToken::Alias(name) => {
+                Expected::Name(name)
+            }
+            _ => Expected::Nothing,
+        };
+        match &self.0.kind {
+            ParseErrors::ExpectedToken { expected, .. } => Expected::Name(expected.name()),
+            ParseErrors::ExpectedWord { expected, .. } => Expected::Name(expected),
+            ParseErrors::ExpectedChar { expected, .. } => name(expected),
+            ParseErrors::CouldNotFindToken(expected) => name(expected),
+            ParseErrors::ExpectedOneOf { expected, .. } => match &expected[..] {
+                [one] => name(one),
+                many => Expected::OneOf(many.len()),
+            },
+            _ => Expected::Nothing,
+        }
+    }
+}
+
+impl fmt::Display for CompactError<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.0.kind.code(), self.0.span.start)?;
+        match self.expected() {
+            Expected::Nothing => Ok(()),
+            Expected::Name(name) => write!(f, " expected {name}"),
+            Expected::OneOf(count) => write!(f, " expected one of {count}"),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for CompactError<'_, '_> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=str}@{=usize}", self.0.kind.code(), self.0.span.start);
+        match self.expected() {
+            Expected::Nothing => (),
+            Expected::Name(name) => defmt::write!(f, " expected {=str}", name),
+            Expected::OneOf(count) => defmt::write!(f, " expected one of {=usize}", count),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ParseError<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::Format::format(&self.compact(), f)
+    }
+}
+
+/// Parse error that does not borrow from the grammar
+///
+/// Can be returned from functions using `?` together with other `std::error::Error` types
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseErrorOwned {
+    pub code: &'static str,
+    pub header: &'static str,
+    pub message: String,
+    /// Bytes of the error
+    pub span: core::ops::Range<usize>,
+    /// Name of the node in which the error occured
+    pub node: Option<String>,
+    pub docs: Option<String>,
+    pub hint: Option<String>,
+    /// Place the rule that caused a developer error was defined at
+    pub origin: Option<String>,
+}
+
+impl<'a> From<&ParseError<'a>> for ParseErrorOwned {
+    fn from(value: &ParseError<'a>) -> Self {
+        let (code, header) = value.kind.id_and_header();
+        ParseErrorOwned {
+            code,
+            header,
+            message: format!("{:?}", value.kind),
+            span: value.span.clone(),
+            node: value.node.as_ref().map(|n| n.name.to_string()),
+            docs: value
+                .node
+                .as_ref()
+                .and_then(|n| n.docs)
+                .map(|d| d.to_string()),
+            hint: value.hint.map(|h| h.to_string()),
+            origin: value.origin.map(|origin| origin.to_string()),
+        }
+    }
+}
+
+impl<'a> From<ParseError<'a>> for ParseErrorOwned {
+    fn from(value: ParseError<'a>) -> Self {
+        (&value).into()
+    }
+}
+
+impl fmt::Display for ParseErrorOwned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{} at bytes {:?}\n{}",
+            self.code, self.header, self.span, self.message
+        )?;
+        if let Some(node) = &self.node {
+            write!(f, "\nError in node: {}", node)?;
+        }
+        if let Some(origin) = &self.origin {
+            write!(f, "\nRule defined at: {}", origin)?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\nhint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseErrorOwned {}
+
 #[derive(Clone)]
 pub enum ParseErrors<'a> {
     /// Parser not fully implemented - My fault
@@ -1918,10 +5223,24 @@ pub enum ParseErrors<'a> {
         expected: &'a str,
         found: TokenKinds<'a>,
     },
+    /// Expected a character, found a different one or a longer token
+    ExpectedChar {
+        expected: MatchToken<'a>,
+        /// First character of the token, None at the end of the file
+        found: Option<char>,
+    },
     /// Enumerator not found - Developer error
     EnumeratorNotFound(&'a str),
+    /// Alias not found - Developer error
+    AliasNotFound(&'a str),
+    /// Alias refers to itself through other aliases - Developer error
+    AliasCycle(&'a str),
+    /// Predicate not registered - Developer error
+    PredicateNotFound(&'a str),
     /// Expected to not be
     ExpectedToNotBe(TokenKinds<'a>),
+    /// Expected text, found a keyword of the node, see `grammar::Node::keywords`
+    Keyword(&'a str),
     /// Variable not found - Developer error
     VariableNotFound(VarKind<'a>),
     /// Uncountable variable - Developer error
@@ -1942,28 +5261,133 @@ pub enum ParseErrors<'a> {
     ExpectedOneOf {
         expected: Vec<MatchToken<'a>>,
         found: TokenKinds<'a>,
+        /// Errors of the alternatives that matched the most text, deepest first
+        ///
+        /// The number of candidates is limited by `Parser::max_candidates`
+        candidates: Vec<ParseError<'a>>,
     },
     /// Could not find token
     CouldNotFindToken(MatchToken<'a>),
     /// This error occurers when the parser ends on different token than eof
     ///
     /// This behaviour can be changed by setting the `eof` field in the grammar
-    MissingEof(TokenKinds<'a>),
+    ///
+    /// The node of the error contains everything that was parsed successfully
+    MissingEof {
+        found: TokenKinds<'a>,
+        /// Byte range of the text that was not parsed
+        leftover: core::ops::Range<usize>,
+    },
     MissingEntry,
+    /// The parser failed more times than allowed by `Parser::backtrack_limit`
+    BacktrackLimit(usize),
+    /// The parse was stopped by `Parser::cancellation`
+    Cancelled,
+    /// The parser created more nodes than allowed by `Parser::max_nodes`
+    NodeLimit(usize),
+    /// The text contains only whitespace and ignored tokens,
+    /// see `grammar::Node::allow_empty`
+    EmptyInput {
+        /// Zero-length range at the end of the text
+        span: core::ops::Range<usize>,
+    },
+    /// The node would be nested deeper than allowed by `Parser::max_nesting`
+    TooDeep {
+        limit: usize,
+        /// Byte range of the first token of the innermost node
+        span: core::ops::Range<usize>,
+    },
+    /// The region given to `Parser::parse_node_at` is not within the tokens - Developer error
+    RangeOutOfBounds {
+        range: core::ops::Range<usize>,
+        /// Number of the tokens
+        len: usize,
+    },
+    /// The node was parsed, but it is not available in `Parser::version`
+    UnsupportedVersion {
+        node: &'a str,
+        since: Option<LanguageVersion>,
+        until: Option<LanguageVersion>,
+        version: LanguageVersion,
+    },
 
     /// Control key
     Ok,
 }
 
 impl<'a> ParseErrors<'a> {
+    /// The error is caused by the grammar or the code around the parser, not by the text
+    pub fn is_developer_error(&self) -> bool {
+        matches!(
+            self,
+            ParseErrors::NodeNotFound(_)
+                | ParseErrors::EnumeratorNotFound(_)
+                | ParseErrors::AliasNotFound(_)
+                | ParseErrors::AliasCycle(_)
+                | ParseErrors::PredicateNotFound(_)
+                | ParseErrors::VariableNotFound(_)
+                | ParseErrors::UncountableVariable(_, _)
+                | ParseErrors::CannotSetVariable(_, _)
+                | ParseErrors::LabelNotFound(_)
+                | ParseErrors::CannotGoBack(_)
+                | ParseErrors::CannotBreak(_)
+                | ParseErrors::RangeOutOfBounds { .. }
+        )
+    }
+
+    /// Stable public code of the error, see `diagnostic::catalog`
+    ///
+    /// Unlike the id returned by `id_and_header`, the code is unique for every variant
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseErrors::ParserNotFullyImplemented => "E0200",
+            ParseErrors::NodeNotFound(_) => "E0150",
+            ParseErrors::ExpectedToken { .. } => "E0201",
+            ParseErrors::ExpectedWord { .. } => "E0206",
+            ParseErrors::ExpectedChar { .. } => "E0210",
+            ParseErrors::ExpectedToNotBe(_) => "E0207",
+            ParseErrors::Keyword(_) => "E0213",
+            ParseErrors::EnumeratorNotFound(_) => "E0151",
+            ParseErrors::AliasNotFound(_) => "E0160",
+            ParseErrors::PredicateNotFound(_) => "E0161",
+            ParseErrors::RangeOutOfBounds { .. } => "E0162",
+            ParseErrors::AliasCycle(_) => "E0163",
+            ParseErrors::VariableNotFound(_) => "E0152",
+            ParseErrors::UncountableVariable(_, _) => "E0153",
+            ParseErrors::CannotSetVariable(_, _) => "E0154",
+            ParseErrors::Message(_) => "E0209",
+            ParseErrors::Eof => "E0202",
+            ParseErrors::LabelNotFound(_) => "E0155",
+            ParseErrors::CannotGoBack(_) => "E0156",
+            ParseErrors::CannotBreak(_) => "E0157",
+            ParseErrors::ExpectedOneOf { .. } => "E0208",
+            ParseErrors::CouldNotFindToken(_) => "E0158",
+            ParseErrors::MissingEof { .. } => "E0203",
+            ParseErrors::MissingEntry => "E0159",
+            ParseErrors::BacktrackLimit(_) => "E0204",
+            ParseErrors::Cancelled => "E0211",
+            ParseErrors::NodeLimit(_) => "E0212",
+            ParseErrors::TooDeep { .. } => "E0214",
+            ParseErrors::EmptyInput { .. } => "E0215",
+            ParseErrors::UnsupportedVersion { .. } => "E0205",
+            ParseErrors::Ok => "E0299",
+        }
+    }
+
     pub fn id_and_header(&self) -> (&'static str, &'static str) {
         match self {
             ParseErrors::ParserNotFullyImplemented => ("200", "Parser not fully implemented"),
             ParseErrors::NodeNotFound(_) => ("150", "Node not found"),
             ParseErrors::ExpectedToken { .. } => ("201", "Unexpected token"),
             ParseErrors::ExpectedWord { .. } => ("201", "Unexpected token"),
+            ParseErrors::ExpectedChar { .. } => ("201", "Unexpected token"),
             ParseErrors::ExpectedToNotBe(_) => ("201", "Unexpected token"),
+            ParseErrors::Keyword(_) => ("201", "Unexpected token"),
             ParseErrors::EnumeratorNotFound(_) => ("151", "Enumerator not found"),
+            ParseErrors::AliasNotFound(_) => ("160", "Alias not found"),
+            ParseErrors::PredicateNotFound(_) => ("161", "Predicate not found"),
+            ParseErrors::RangeOutOfBounds { .. } => ("162", "Token range out of bounds"),
+            ParseErrors::AliasCycle(_) => ("163", "Alias cycle"),
             ParseErrors::VariableNotFound(_) => ("152", "Variable not found"),
             ParseErrors::UncountableVariable(_, _) => ("153", "Variable is uncountable"),
             ParseErrors::CannotSetVariable(_, _) => ("154", "Variable can not be set"),
@@ -1974,8 +5398,14 @@ impl<'a> ParseErrors<'a> {
             ParseErrors::CannotBreak(_) => ("157", "Can not break"),
             ParseErrors::ExpectedOneOf { .. } => ("201", "Unexpected token"),
             ParseErrors::CouldNotFindToken(_) => ("158", "Can not find token"),
-            ParseErrors::MissingEof(_) => ("203", "Could not parse until the end"),
+            ParseErrors::MissingEof { .. } => ("203", "Could not parse until the end"),
             ParseErrors::MissingEntry => ("159", "Missing entry point"),
+            ParseErrors::BacktrackLimit(_) => ("204", "Backtracking limit exceeded"),
+            ParseErrors::Cancelled => ("206", "Parsing cancelled"),
+            ParseErrors::NodeLimit(_) => ("207", "Node limit exceeded"),
+            ParseErrors::TooDeep { .. } => ("208", "Nesting too deep"),
+            ParseErrors::EmptyInput { .. } => ("209", "Empty input"),
+            ParseErrors::UnsupportedVersion { .. } => ("205", "Unsupported language version"),
             ParseErrors::Ok => ("---", "Ok"),
         }
     }
@@ -1985,46 +5415,423 @@ impl<'a> fmt::Debug for ParseErrors<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ParseErrors::ParserNotFullyImplemented => write!(f, "Parser not fully implemented"),
-            ParseErrors::NodeNotFound(_name) => write!(f, "Node not found: working on it :)"),
+            ParseErrors::NodeNotFound(name) => write!(f, "Node not found: {}", name),
             ParseErrors::ExpectedToken { expected, found } => {
                 write!(f, "Expected token {} - found {}", expected, found)
             }
             ParseErrors::ExpectedWord { expected, found } => {
                 write!(f, "Expected word {} - found {}", expected, found)
             }
-            ParseErrors::EnumeratorNotFound(_name) => {
-                write!(f, "Enumerator not found: working on it :)")
-            }
+            ParseErrors::ExpectedChar { expected, found } => match found {
+                Some(found) => write!(f, "Expected {:?} - found {:?}", expected, found),
+                None => write!(f, "Expected {:?} - found end of file", expected),
+            },
+            ParseErrors::EnumeratorNotFound(name) => write!(f, "Enumerator not found: {}", name),
+            ParseErrors::AliasNotFound(name) => write!(f, "Alias not found: {}", name),
+            ParseErrors::AliasCycle(name) => write!(f, "Alias refers to itself: {}", name),
+            ParseErrors::PredicateNotFound(name) => write!(f, "Predicate not found: {}", name),
+            ParseErrors::RangeOutOfBounds { range, len } => write!(
+                f,
+                "Tokens {}..{} are out of bounds of {} tokens",
+                range.start, range.end, len
+            ),
             ParseErrors::ExpectedToNotBe(kind) => write!(f, "Expected to not be {:?}", kind),
-            ParseErrors::VariableNotFound(_name) => {
-                write!(f, "Variable not found: working on it :)")
+            ParseErrors::Keyword(word) => {
+                write!(f, "Expected text - found keyword {}", word)
             }
-            ParseErrors::UncountableVariable(_name, kind) => {
-                write!(f, "Uncountable variable: dont know<{:?}>", kind)
+            ParseErrors::VariableNotFound(name) => write!(f, "Variable not found: {}", name),
+            ParseErrors::UncountableVariable(name, kind) => {
+                write!(f, "Uncountable variable {}: {}", name, kind)
             }
-            ParseErrors::CannotSetVariable(_name, kind) => {
-                write!(f, "Cannot set variable: dont know<{:?}>", kind)
+            ParseErrors::CannotSetVariable(name, kind) => {
+                write!(f, "Cannot set variable {}: {}", name, kind)
             }
             ParseErrors::Message(err) => write!(f, "{}", err.msg),
             ParseErrors::Eof => write!(f, "Unexpected end of file"),
             ParseErrors::LabelNotFound(name) => write!(f, "Label not found: {}", name),
             ParseErrors::CannotGoBack(steps) => write!(f, "Cannot go back {} steps", steps),
             ParseErrors::CannotBreak(n) => write!(f, "Cannot break {} more steps", n),
-            ParseErrors::ExpectedOneOf { expected, found } => {
+            ParseErrors::ExpectedOneOf {
+                expected, found, ..
+            } => {
                 write!(f, "Expected one of {:?} - found {}", expected, found)
             }
             ParseErrors::CouldNotFindToken(kind) => write!(f, "Could not find token {:?}", kind),
             ParseErrors::Ok => write!(f, "If you see this, it could be a bug in the parser"),
-            ParseErrors::MissingEof(found) => write!(
+            ParseErrors::MissingEof { found, leftover } => write!(
                 f,
-                "Could not parse to the end of the file - found {}",
-                found
+                "Could not parse to the end of the file - found {}, {} bytes left",
+                found,
+                leftover.len()
             ),
             ParseErrors::MissingEntry => write!(f, "Entry node not set"),
+            ParseErrors::BacktrackLimit(limit) => write!(
+                f,
+                "The parser gave up after backtracking {} times, the grammar might be ambiguous",
+                limit
+            ),
+            ParseErrors::Cancelled => write!(f, "The parse was cancelled"),
+            ParseErrors::NodeLimit(limit) => {
+                write!(f, "The parser gave up after creating {} nodes", limit)
+            }
+            ParseErrors::EmptyInput { .. } => write!(f, "The text is empty"),
+            ParseErrors::TooDeep { limit, .. } => {
+                write!(f, "Nodes are nested deeper than {} levels", limit)
+            }
+            ParseErrors::UnsupportedVersion {
+                node,
+                since,
+                until,
+                version,
+            } => match (since, until) {
+                (Some(since), _) if version < since => {
+                    write!(f, "{} requires version {} - found {}", node, since, version)
+                }
+                (_, Some(until)) => {
+                    write!(
+                        f,
+                        "{} was removed in version {} - found {}",
+                        node, until, version
+                    )
+                }
+                _ => write!(f, "{} is not available in version {}", node, version),
+            },
+        }
+    }
+}
+
+/// Reason a condition could not be checked, see `Parser::condition`
+#[derive(Debug, Clone, Copy)]
+enum Unchecked<'a> {
+    Variable(grammar::VarKind<'a>),
+    Predicate(&'a str),
+}
+
+/// Where a parse starts and what it starts with, see `Parser::parse_with`
+struct Start<'a, 'h> {
+    entry: Option<&'a str>,
+    /// Set only while exploring ambiguities
+    choices: Option<Choices<'a>>,
+    /// Set only while collecting completions
+    next: Option<NextTokens<'a>>,
+    /// Set only while collecting events
+    events: Option<&'h mut dyn EventSink<'a>>,
+    state: ParseState<'a>,
+    checkpoint: Option<Checkpoint<'h>>,
+    /// Allocator of the result, see `crate::memory`
+    alloc: ParseAlloc,
+}
+
+/// Pauses the parse every `interval` rules, see `crate::Parser::parse_async`
+pub(crate) struct Checkpoint<'h> {
+    interval: usize,
+    /// Rules left until the next pause
+    left: usize,
+    pause: &'h mut dyn FnMut(),
+}
+
+impl<'h> Checkpoint<'h> {
+    #[cfg(feature = "async")]
+    pub(crate) fn new(interval: usize, pause: &'h mut dyn FnMut()) -> Self {
+        let interval = interval.max(1);
+        Checkpoint {
+            interval,
+            left: interval,
+            pause,
+        }
+    }
+
+    fn rule(&mut self) {
+        self.left -= 1;
+        if self.left == 0 {
+            self.left = self.interval;
+            (self.pause)();
+        }
+    }
+}
+
+/// What a parse leaves besides its result
+struct Trail<'a> {
+    choices: Option<Choices<'a>>,
+    next: Option<NextTokens<'a>>,
+}
+
+/// Tokens the rules tried to match at the token the completions are for
+struct NextTokens<'a> {
+    /// Index of the token
+    target: usize,
+    expected: Vec<MatchToken<'a>>,
+}
+
+/// Per parse state that is not restored when the parser backtracks
+struct Context<'a, 'h> {
+    stats: Option<ParseStats<'a>>,
+    /// Number of failed nodes that were not committed
+    backtracks: usize,
+    /// Number of nodes created, checked against `Parser::max_nodes`
+    nodes: usize,
+    /// Number of nodes being parsed, checked against `Parser::max_nesting`
+    depth: usize,
+    /// Set when the backtrack limit is exceeded or the parse is cancelled
+    out_of_budget: bool,
+    /// Set only while exploring ambiguities
+    choices: Option<Choices<'a>>,
+    /// Set if `Parser::profile` is enabled
+    #[cfg(feature = "std")]
+    timer: Option<RuleTimer<'a>>,
+    /// `grammar::Node::keywords` of the node being parsed
+    keywords: &'a [&'a str],
+    /// Errors skipped by `Parser::recovery`
+    recovered: NodeVec<ParseError<'a>>,
+    /// Set only while collecting completions
+    next: Option<NextTokens<'a>>,
+    /// Origin of the rule being matched, attached to developer errors
+    origin: Origin<'a>,
+    /// Set only while collecting events
+    events: Option<Events<'a, 'h>>,
+    /// Token index at which `Parser::progress` reports next
+    progress: usize,
+    /// Set only while parsing from async code
+    checkpoint: Option<Checkpoint<'h>>,
+    /// `Grammar::brackets` of the parsed grammar
+    bracket_kinds: &'a [(TokenKinds<'a>, TokenKinds<'a>)],
+    /// Brackets matched by the nodes being parsed, paired when the node that matched
+    /// them ends
+    brackets: NodeVec<Bracket>,
+    /// Pairs of brackets of the nodes that ended
+    pairs: NodeVec<BracketPair>,
+    /// Allocator of the result, see `crate::memory`
+    alloc: ParseAlloc,
+}
+
+/// Bracket of `Grammar::brackets` matched by a node, see `Context::brackets`
+struct Bracket {
+    /// Index of the pair in `Grammar::brackets`
+    kind: usize,
+    open: bool,
+    span: core::ops::Range<usize>,
+}
+
+impl<'a> Context<'a, '_> {
+    /// Saves the state a trial match may change, see `Context::restore`
+    fn snapshot(&self) -> Snapshot<'a> {
+        Snapshot {
+            stats: self.stats.clone(),
+            backtracks: self.backtracks,
+            nodes: self.nodes,
+            depth: self.depth,
+            out_of_budget: self.out_of_budget,
+            keywords: self.keywords,
+            recovered: self.recovered.len(),
+            origin: self.origin,
+            events: self.events.as_ref().map_or(0, Events::len),
+            progress: self.progress,
+            brackets: (self.brackets.len(), self.pairs.len()),
+        }
+    }
+
+    /// Undoes everything a trial match did since the snapshot was taken
+    fn restore(&mut self, snapshot: &Snapshot<'a>) {
+        self.stats.clone_from(&snapshot.stats);
+        self.backtracks = snapshot.backtracks;
+        self.nodes = snapshot.nodes;
+        self.depth = snapshot.depth;
+        self.out_of_budget = snapshot.out_of_budget;
+        self.keywords = snapshot.keywords;
+        self.recovered.truncate(snapshot.recovered);
+        self.origin = snapshot.origin;
+        if let Some(events) = &mut self.events {
+            events.truncate(snapshot.events);
+        }
+        self.progress = snapshot.progress;
+        self.brackets.truncate(snapshot.brackets.0);
+        self.pairs.truncate(snapshot.brackets.1);
+    }
+}
+
+/// Events of `Parser::parse_events` on their way to the sink
+///
+/// Events of a node are taken back when it fails. They are delivered once no node
+/// they belong to can fail without failing the whole parse, which is when the nodes
+/// around them commit or end.
+struct Events<'a, 'h> {
+    /// Events not delivered yet, in order
+    pending: Vec<Event<'a>>,
+    /// Number of events delivered
+    flushed: usize,
+    /// Index of the `Event::NodeStart` and whether the node committed for the nodes
+    /// being parsed, outermost first
+    open: Vec<(usize, bool)>,
+    /// With `Parser::recovery` committed nodes can fail as well
+    recovery: bool,
+    sink: &'h mut dyn EventSink<'a>,
+}
+
+impl<'a, 'h> Events<'a, 'h> {
+    fn new(sink: &'h mut dyn EventSink<'a>, recovery: bool) -> Self {
+        Events {
+            pending: Vec::new(),
+            flushed: 0,
+            open: Vec::new(),
+            recovery,
+            sink,
+        }
+    }
+
+    /// Number of events so far, delivered or not
+    fn len(&self) -> usize {
+        self.flushed + self.pending.len()
+    }
+
+    fn push(&mut self, event: Event<'a>) {
+        self.pending.push(event);
+    }
+
+    /// Drops the events after the first `len`, only the pending ones can be dropped
+    fn truncate(&mut self, len: usize) {
+        self.pending.truncate(len.saturating_sub(self.flushed));
+    }
+
+    /// Starts the events of a node
+    fn enter(&mut self, start: Event<'a>, committed: bool) {
+        self.open.push((self.len(), committed));
+        self.push(start);
+    }
+
+    /// Updates whether the innermost node committed
+    fn commit(&mut self, committed: bool) {
+        let Some(innermost) = self.open.last_mut() else {
+            return;
+        };
+        if innermost.1 != committed {
+            innermost.1 = committed;
+            if committed {
+                self.flush();
+            }
+        }
+    }
+
+    /// Ends the events of the innermost node, they are dropped if it failed
+    fn leave(&mut self, matched: bool) {
+        let Some((start, _)) = self.open.pop() else {
+            return;
+        };
+        match matched {
+            true => self.flush(),
+            false => self.truncate(start),
+        }
+    }
+
+    /// Delivers the events no node can take back anymore
+    fn flush(&mut self) {
+        // the entry node can not fail without failing the parse
+        let kept = self
+            .open
+            .iter()
+            .skip(1)
+            .find(|(_, committed)| !committed || self.recovery)
+            .map_or(self.len(), |(start, _)| *start);
+        if kept <= self.flushed {
+            return;
+        }
+        for event in self.pending.drain(..kept - self.flushed) {
+            self.sink.event(event);
+        }
+        self.flushed = kept;
+    }
+}
+
+/// Parts of `Context` restored after a trial match, see `Context::snapshot`
+struct Snapshot<'a> {
+    stats: Option<ParseStats<'a>>,
+    backtracks: usize,
+    nodes: usize,
+    depth: usize,
+    out_of_budget: bool,
+    keywords: &'a [&'a str],
+    /// Length of `Context::recovered`
+    recovered: usize,
+    origin: Origin<'a>,
+    /// Length of `Context::events`
+    events: usize,
+    progress: usize,
+    /// Length of `Context::brackets` and `Context::pairs`
+    brackets: (usize, usize),
+}
+
+/// Measures the self time of rules for `ParseStats::rules`
+///
+/// Time is always counted to the rule that started last in the innermost node
+#[cfg(feature = "std")]
+struct RuleTimer<'a> {
+    /// Index of every rule in its node, keyed by the address of the rule
+    ids: Map<usize, usize>,
+    /// Nodes being parsed with the index of their current rule
+    frames: Vec<(&'a str, usize)>,
+    since: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl<'a> RuleTimer<'a> {
+    fn new(grammar: &'a Grammar<'a>) -> Self {
+        fn number(ids: &mut Map<usize, usize>, rules: &[grammar::Rule], next: &mut usize) {
+            for rule in rules {
+                ids.insert(rule as *const grammar::Rule as usize, *next);
+                *next += 1;
+                for rules in grammar::validator::Validator::parts(rule).2 {
+                    number(ids, rules, next);
+                }
+            }
+        }
+        let mut ids = Map::new();
+        for node in grammar.all_nodes() {
+            number(&mut ids, &node.rules, &mut 0);
+        }
+        RuleTimer {
+            ids,
+            frames: Vec::new(),
+            since: std::time::Instant::now(),
+        }
+    }
+
+    /// Counts the time since the last change to the current rule
+    fn lap(&mut self, stats: &mut ParseStats<'a>) {
+        let now = std::time::Instant::now();
+        let elapsed = now - self.since;
+        self.since = now;
+        if let Some(frame) = self.frames.last() {
+            *stats.rules.entry(*frame).or_default() += elapsed;
+            *stats.stacks.entry(self.frames.clone()).or_default() += elapsed;
+        }
+    }
+
+    fn enter(&mut self, stats: &mut ParseStats<'a>, name: &'a str) {
+        self.lap(stats);
+        self.frames.push((name, 0));
+    }
+
+    fn leave(&mut self, stats: &mut ParseStats<'a>) {
+        self.lap(stats);
+        self.frames.pop();
+    }
+
+    fn rule(&mut self, stats: &mut ParseStats<'a>, rule: &grammar::Rule) {
+        self.lap(stats);
+        let id = self.ids.get(&(rule as *const grammar::Rule as usize));
+        if let (Some(frame), Some(id)) = (self.frames.last_mut(), id) {
+            frame.1 = *id;
         }
     }
 }
 
+/// Alternatives picked at the ambiguous `IsOneOf` rules of one parse
+struct Choices<'a> {
+    /// Index into the matching alternatives for the first ambiguities, the rest take the first one
+    script: Vec<usize>,
+    taken: Vec<usize>,
+    points: Vec<Ambiguity<'a>>,
+}
+
 /// A cursor is used to keep track of the current position in the token stream and other useful information (no useful information yet)
 #[derive(Clone, Debug)]
 struct Cursor {
@@ -2035,6 +5842,100 @@ struct Cursor {
     /// This is used to prevent the cursor from advancing more than once in a single iteration
     /// This could happen if a rule is executed and the cursor is advanced, then the rule returns and the cursor is advanced again
     to_advance: bool,
+    /// Bytes of the current token already matched by `Parameters::Split`
+    split: usize,
+}
+
+impl Cursor {
+    /// Index of the token `ahead` places after the current one, clamped to the last token
+    fn index(&self, tokens: &dyn TokenSource, ahead: usize) -> usize {
+        (self.idx + ahead).min(tokens.len().saturating_sub(1))
+    }
+
+    /// Index of the token before the current one, clamped like `index`
+    fn prev_index(&self, tokens: &dyn TokenSource) -> usize {
+        self.idx
+            .saturating_sub(1)
+            .min(tokens.len().saturating_sub(1))
+    }
+
+    /// Token at `idx`, or an empty end of file if the stream has no such token
+    fn token<'a>(tokens: &dyn TokenSource<'a>, idx: usize) -> Token<'a> {
+        tokens.get(idx).unwrap_or(Token::EMPTY)
+    }
+
+    /// Current token, or the last one (end of file) once the cursor ran past the end
+    fn peek<'a>(&self, tokens: &dyn TokenSource<'a>) -> Token<'a> {
+        Self::token(tokens, self.index(tokens, 0))
+    }
+
+    /// Token `ahead` places after the current one, clamped like `peek`
+    fn peek_nth<'a>(&self, tokens: &dyn TokenSource<'a>, ahead: usize) -> Token<'a> {
+        Self::token(tokens, self.index(tokens, ahead))
+    }
+
+    /// Token before the current one, clamped like `peek`
+    fn prev<'a>(&self, tokens: &dyn TokenSource<'a>) -> Token<'a> {
+        Self::token(tokens, self.prev_index(tokens))
+    }
+
+    /// Whether there is a token `ahead` places after the current one
+    fn has(&self, tokens: &dyn TokenSource, ahead: usize) -> bool {
+        self.idx + ahead < tokens.len()
+    }
+
+    /// Tokens from the current one on, empty once the cursor ran past the end
+    fn rest<'t, 'a>(
+        &self,
+        tokens: &'t dyn TokenSource<'a>,
+    ) -> impl Iterator<Item = Token<'a>> + 't {
+        tokens.range(self.idx..tokens.len())
+    }
+
+    fn at_end(&self, tokens: &dyn TokenSource) -> bool {
+        self.idx >= tokens.len()
+    }
+
+    /// Index of the first token no rule has matched yet
+    fn next_idx(&self) -> usize {
+        self.idx + (self.to_advance && self.split == 0) as usize
+    }
+
+    /// Moves to the next token, returns false once the cursor ran past the end
+    fn bump(&mut self, tokens: &dyn TokenSource) -> bool {
+        self.split = 0;
+        self.idx += 1;
+        !self.at_end(tokens)
+    }
+
+    /// Moves to the next token unless the cursor is on the last one,
+    /// returns whether it moved
+    fn step(&mut self, tokens: &dyn TokenSource) -> bool {
+        if !self.has(tokens, 1) {
+            return false;
+        }
+        self.bump(tokens)
+    }
+
+    /// Skips `ahead` tokens, the whitespace in front of a matched token
+    fn skip(&mut self, ahead: usize) {
+        self.idx += ahead;
+    }
+
+    /// Moves to the token at `idx`, clamped to the last token
+    fn seek(&mut self, tokens: &dyn TokenSource, idx: usize) {
+        self.idx = idx.min(tokens.len().saturating_sub(1));
+        self.split = 0;
+    }
+
+    /// Moves past the matched token like `bump`, but stays on a token
+    /// that `Parameters::Split` matched only the start of
+    fn advance(&mut self, tokens: &dyn TokenSource) -> bool {
+        if self.split > 0 {
+            return true;
+        }
+        self.bump(tokens)
+    }
 }
 
 struct MsgBus {
@@ -0,0 +1,294 @@
+//! Scoped symbol table filled by the grammar during a parse
+//!
+//! `Parameters::EnterScope` opens a scope at the matched value, `Parameters::ExitScope`
+//! closes the innermost one after it and `Parameters::Declare` adds the text of a variable
+//! to the innermost open scope. `Parameters::Use` records a name that refers to a declaration.
+//! The table of a parse is returned by `ParseResult::scopes` and answers simple name-resolution
+//! questions, like which declaration a name at some offset refers to, where a declaration is used
+//! or which names are declared twice in the same scope.
+//! `Scopes::rename` turns the same data into text edits for a simple rename refactoring.
+//!
+//! Declarations and scopes of a node that fails to parse are dropped with it.
+
+use core::ops::Range;
+
+use crate::{incremental::TextEdit, parser::Nodes};
+
+// Choose between std and alloc
+cfg_if::cfg_if! {
+    if #[cfg(feature = "std")] {
+        extern crate std;
+        use std::prelude::v1::*;
+    } else {
+        extern crate alloc;
+        use alloc::{string::*, vec, vec::*};
+    }
+}
+
+/// Region of the text names can be declared in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scope {
+    /// Enclosing scope, None only for the root scope
+    pub parent: Option<usize>,
+    /// Byte the scope starts at
+    pub start: usize,
+    /// Byte after the end of the scope, None if it was never closed
+    pub end: Option<usize>,
+}
+
+impl Scope {
+    pub fn contains(&self, offset: usize) -> bool {
+        offset >= self.start && self.end.is_none_or(|end| offset < end)
+    }
+}
+
+/// Name declared in a scope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    pub name: String,
+    /// Bytes of the declared name
+    pub span: Range<usize>,
+    /// Index of the scope in `Scopes::scopes`
+    pub scope: usize,
+}
+
+/// Name used in the text, see `Scopes::resolve`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub name: String,
+    /// Bytes of the used name
+    pub span: Range<usize>,
+}
+
+/// State of the table that a failed node rolls back to
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Mark {
+    scopes: usize,
+    declarations: usize,
+    uses: usize,
+    closed: usize,
+    current: usize,
+}
+
+/// Scopes and declarations of a parse, see the module documentation
+///
+/// The root scope with index 0 spans the whole text and is always present.
+#[derive(Debug, Clone)]
+pub struct Scopes {
+    scopes: Vec<Scope>,
+    declarations: Vec<Declaration>,
+    uses: Vec<Reference>,
+    /// Scopes in the order they were closed, so a rollback can reopen them
+    closed: Vec<usize>,
+    current: usize,
+}
+
+impl Default for Scopes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scopes {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![Scope {
+                parent: None,
+                start: 0,
+                end: None,
+            }],
+            declarations: Vec::new(),
+            uses: Vec::new(),
+            closed: Vec::new(),
+            current: 0,
+        }
+    }
+
+    /// All scopes in the order they were opened, the root scope first
+    pub fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+
+    /// All declarations in the order they were declared
+    pub fn declarations(&self) -> &[Declaration] {
+        &self.declarations
+    }
+
+    /// All used names in the order they were used
+    pub fn uses(&self) -> &[Reference] {
+        &self.uses
+    }
+
+    /// Declarations made directly in the scope
+    pub fn declared_in(&self, scope: usize) -> impl Iterator<Item = &Declaration> {
+        self.declarations
+            .iter()
+            .filter(move |declaration| declaration.scope == scope)
+    }
+
+    /// Innermost scope containing the byte `offset`
+    pub fn scope_at(&self, offset: usize) -> usize {
+        // scopes open in text order, so the last one containing the offset is the innermost
+        self.scopes
+            .iter()
+            .rposition(|scope| scope.contains(offset))
+            .unwrap_or(0)
+    }
+
+    /// Declaration a name at the byte `offset` refers to
+    ///
+    /// Looks through the scopes enclosing the offset from the innermost one out
+    /// and only sees declarations in front of the offset.
+    /// In each scope the last declaration of the name wins.
+    pub fn lookup(&self, name: &str, offset: usize) -> Option<&Declaration> {
+        let mut scope = Some(self.scope_at(offset));
+        while let Some(current) = scope {
+            let found = self.declarations.iter().rev().find(|declaration| {
+                declaration.scope == current
+                    && declaration.name == name
+                    && declaration.span.start <= offset
+            });
+            if found.is_some() {
+                return found;
+            }
+            scope = self.scopes[current].parent;
+        }
+        None
+    }
+
+    /// Declaration the used name refers to
+    pub fn resolve(&self, reference: &Reference) -> Option<&Declaration> {
+        self.lookup(&reference.name, reference.span.start)
+    }
+
+    /// Uses of the declaration in the order they were used, without the declaration itself
+    pub fn references(&self, declaration: &Declaration) -> Vec<&Reference> {
+        self.uses
+            .iter()
+            .filter(|reference| self.resolve(reference) == Some(declaration))
+            .collect()
+    }
+
+    /// Declaration of the name at the byte `offset`
+    ///
+    /// The offset can be on a used name or on the declared name itself
+    pub fn definition_at(&self, offset: usize) -> Option<&Declaration> {
+        if let Some(reference) = self
+            .uses
+            .iter()
+            .find(|reference| reference.span.contains(&offset))
+        {
+            return self.resolve(reference);
+        }
+        self.declarations
+            .iter()
+            .find(|declaration| declaration.span.contains(&offset))
+    }
+
+    /// Edits renaming the declaration and all of its uses, ordered by their position
+    ///
+    /// Returns no edits if the name does not change
+    pub fn rename(&self, declaration: &Declaration, new_name: &str) -> Vec<TextEdit> {
+        if declaration.name == new_name {
+            return Vec::new();
+        }
+        let mut spans = self
+            .references(declaration)
+            .into_iter()
+            .map(|reference| reference.span.clone())
+            .collect::<Vec<_>>();
+        spans.push(declaration.span.clone());
+        spans.sort_by_key(|span| span.start);
+        spans.dedup();
+        spans
+            .into_iter()
+            .map(|span| TextEdit {
+                span,
+                text: new_name.to_string(),
+            })
+            .collect()
+    }
+
+    /// Used names that do not refer to any declaration
+    pub fn unresolved(&self) -> Vec<&Reference> {
+        self.uses
+            .iter()
+            .filter(|reference| self.resolve(reference).is_none())
+            .collect()
+    }
+
+    /// Names declared more than once in the same scope as pairs of
+    /// the first declaration and the repeated one
+    pub fn duplicates(&self) -> Vec<(&Declaration, &Declaration)> {
+        let mut duplicates = Vec::new();
+        for (i, declaration) in self.declarations.iter().enumerate() {
+            let first = self.declarations[..i].iter().find(|earlier| {
+                earlier.scope == declaration.scope && earlier.name == declaration.name
+            });
+            if let Some(first) = first {
+                duplicates.push((first, declaration));
+            }
+        }
+        duplicates
+    }
+
+    pub(crate) fn enter(&mut self, start: usize) {
+        self.scopes.push(Scope {
+            parent: Some(self.current),
+            start,
+            end: None,
+        });
+        self.current = self.scopes.len() - 1;
+    }
+
+    /// Closing the root scope does nothing
+    pub(crate) fn exit(&mut self, end: usize) {
+        let scope = &mut self.scopes[self.current];
+        if let Some(parent) = scope.parent {
+            scope.end = Some(end);
+            self.closed.push(self.current);
+            self.current = parent;
+        }
+    }
+
+    pub(crate) fn declare(&mut self, name: String, span: Range<usize>) {
+        self.declarations.push(Declaration {
+            name,
+            span,
+            scope: self.current,
+        });
+    }
+
+    pub(crate) fn use_name(&mut self, name: String, span: Range<usize>) {
+        self.uses.push(Reference { name, span });
+    }
+
+    pub(crate) fn mark(&self) -> Mark {
+        Mark {
+            scopes: self.scopes.len(),
+            declarations: self.declarations.len(),
+            uses: self.uses.len(),
+            closed: self.closed.len(),
+            current: self.current,
+        }
+    }
+
+    pub(crate) fn reset(&mut self, mark: Mark) {
+        for scope in self.closed.drain(mark.closed..) {
+            self.scopes[scope].end = None;
+        }
+        self.scopes.truncate(mark.scopes);
+        self.declarations.truncate(mark.declarations);
+        self.uses.truncate(mark.uses);
+        self.current = mark.current;
+    }
+}
+
+/// Bytes of a matched value as used for scope boundaries and declared names
+pub(crate) fn span(value: &Nodes) -> Range<usize> {
+    match value {
+        Nodes::Token(token) => token.index..token.index + token.len,
+        Nodes::Node(node) => node.first_string_idx..node.last_string_idx,
+        Nodes::Error { span, .. } => span.clone(),
+    }
+}
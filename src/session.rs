@@ -0,0 +1,186 @@
+//! Parsing many files with one grammar
+//!
+//! A `Session` validates the grammar once and shares it between worker threads,
+//! which is the usual shape of a compiler front-end driver. Files are handed out
+//! one at a time, so a few large files do not leave the other threads idle.
+//! Results keep the order of the input files.
+
+use std::{
+    fmt::Write,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    compiled::CompiledGrammar,
+    diagnostic::{Diagnostic, Severity},
+    format::{write_diagnostics, DiagnosticStyle},
+    grammar::validator::Validator,
+    parser::ParseResult,
+    Parser,
+};
+
+/// Validated parser shared by all files of a batch
+#[derive(Debug)]
+pub struct Session<'a> {
+    parser: Parser<'a>,
+    /// Validation warnings of the grammar
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Outcome of parsing one file
+#[derive(Debug)]
+pub struct FileResult<'s> {
+    pub path: &'s Path,
+    pub text: &'s str,
+    /// None if the file could not be lexed or parsed
+    pub result: Option<ParseResult<'s>>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Time spent lexing and parsing the file
+    pub time: Duration,
+}
+
+/// Results of `Session::parse_all`, in the order of the input files
+#[derive(Debug)]
+pub struct Batch<'s> {
+    pub files: Vec<FileResult<'s>>,
+    /// Wall clock time of the whole batch
+    pub time: Duration,
+}
+
+impl<'a> Session<'a> {
+    /// Validates the grammar with the default `Validator`
+    ///
+    /// Returns the diagnostics of all validation errors and warnings if there are errors
+    pub fn new(parser: Parser<'a>) -> Result<Self, Vec<Diagnostic>> {
+        Self::with_validator(parser, &Validator::default())
+    }
+
+    pub fn with_validator(
+        parser: Parser<'a>,
+        validator: &Validator,
+    ) -> Result<Self, Vec<Diagnostic>> {
+        let result = validator.validate(&parser);
+        let diagnostics = result.diagnostics();
+        if !result.pass() {
+            return Err(diagnostics);
+        }
+        Ok(Session {
+            parser,
+            warnings: diagnostics,
+        })
+    }
+
+    /// Uses a compiled grammar, which was already validated when it was built
+    pub fn from_compiled(compiled: CompiledGrammar<'a>) -> Self {
+        Session {
+            parser: compiled.parser,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn parser(&self) -> &Parser<'a> {
+        &self.parser
+    }
+
+    /// Lexes and parses one file
+    pub fn parse_file<'s>(&'s self, path: &'s Path, text: &'s str) -> FileResult<'s> {
+        let start = Instant::now();
+        let mut diagnostics = Vec::new();
+        let result = match self.parser.lexer.lex_utf8(text) {
+            Ok(tokens) => match self.parser.parse(&tokens, text) {
+                Ok(result) => {
+                    diagnostics.extend(result.errors.iter().map(|err| err.diagnostic()));
+                    Some(result)
+                }
+                Err(err) => {
+                    diagnostics.push(err.diagnostic());
+                    None
+                }
+            },
+            Err(err) => {
+                diagnostics.push(err.diagnostic());
+                None
+            }
+        };
+        FileResult {
+            path,
+            text,
+            result,
+            diagnostics,
+            time: start.elapsed(),
+        }
+    }
+
+    /// Parses the files on `num_threads` threads, at least one is used
+    pub fn parse_all<'s, P, T>(&'s self, files: &'s [(P, T)], num_threads: usize) -> Batch<'s>
+    where
+        P: AsRef<Path> + Sync,
+        T: AsRef<str> + Sync,
+    {
+        let start = Instant::now();
+        let next = AtomicUsize::new(0);
+        let worker = || {
+            let mut results = Vec::new();
+            loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some((path, text)) = files.get(index) else {
+                    break;
+                };
+                results.push((index, self.parse_file(path.as_ref(), text.as_ref())));
+            }
+            results
+        };
+        let threads = num_threads.clamp(1, files.len().max(1));
+        let mut results = thread::scope(|scope| {
+            let handles = (0..threads)
+                .map(|_| scope.spawn(worker))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("parser thread panicked"))
+                .collect::<Vec<_>>()
+        });
+        results.sort_by_key(|(index, _)| *index);
+        Batch {
+            files: results.into_iter().map(|(_, result)| result).collect(),
+            time: start.elapsed(),
+        }
+    }
+}
+
+impl Batch<'_> {
+    /// Diagnostics of all files with the path of their file
+    pub fn diagnostics(&self) -> impl Iterator<Item = (&Path, &Diagnostic)> {
+        self.files
+            .iter()
+            .flat_map(|file| file.diagnostics.iter().map(move |d| (file.path, d)))
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.diagnostics()
+            .filter(|(_, d)| d.severity == Severity::Error)
+            .count()
+    }
+
+    /// Sum of the time spent on every file, larger than `Batch::time` when threads were used
+    pub fn cpu_time(&self) -> Duration {
+        self.files.iter().map(|file| file.time).sum()
+    }
+
+    /// Renders the diagnostics of every file in the order of the files
+    pub fn write_diagnostics(
+        &self,
+        w: &mut impl Write,
+        style: &DiagnosticStyle,
+    ) -> std::fmt::Result {
+        for file in &self.files {
+            if !file.diagnostics.is_empty() {
+                write_diagnostics(w, &file.diagnostics, file.text, Some(file.path), style)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,855 @@
+//! Helpers for testing grammars
+//!
+//! The generator walks the rules of a grammar and produces text that should be accepted by it.
+//! Feeding the text back into the parser (see `check_round_trip`) quickly finds
+//! ambiguous or greedy rules that reject valid looking input.
+//! With the `arbitrary` feature `GrammarInput` brings the generator to fuzzers.
+//!
+//! `Coverage` goes the other way and shows which nodes and alternatives a corpus of
+//! texts never exercises, and `reduce` shrinks a failing text for bug reports.
+//! Tests added with `Grammar::add_test` are run by `Parser::run_grammar_tests`.
+
+use crate::{
+    grammar::{
+        validator::Validator, AliasError, CharClass, Commands, Grammar, GrammarTest, MatchToken,
+        OneOf, Parameters, Rule,
+    },
+    lexer::{ControlTokenKind, DocComment, Lexer, PreprocessorError, TokenKinds},
+    parser::{self, ParseErrorOwned, ParseStats, VariableKind},
+    Map, Parser,
+};
+use core::fmt;
+
+// Choose between std and alloc
+cfg_if::cfg_if! {
+    if #[cfg(feature = "std")] {
+        extern crate std;
+        use std::prelude::v1::*;
+    } else {
+        extern crate alloc;
+        use alloc::string::*;
+        use alloc::vec::*;
+    }
+}
+
+/// Source of randomness for the generator
+pub trait Entropy {
+    fn next_u32(&mut self) -> u32;
+
+    /// Returns a number in range `0..max`
+    fn below(&mut self, max: usize) -> usize {
+        if max == 0 {
+            return 0;
+        }
+        self.next_u32() as usize % max
+    }
+}
+
+/// Small deterministic random number generator (xorshift)
+///
+/// The same seed always produces the same text
+#[derive(Debug, Clone)]
+pub struct SeedRng {
+    state: u64,
+}
+
+impl SeedRng {
+    pub fn new(seed: u64) -> Self {
+        SeedRng {
+            // xorshift can not leave the zero state
+            state: (seed ^ 0x9E37_79B9_7F4A_7C15) | 1,
+        }
+    }
+}
+
+impl Entropy for SeedRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        (x >> 32) as u32
+    }
+}
+
+/// Lets fuzzers and property testing frameworks drive the generator
+#[cfg(feature = "arbitrary")]
+impl Entropy for arbitrary::Unstructured<'_> {
+    fn next_u32(&mut self) -> u32 {
+        <u32 as arbitrary::Arbitrary>::arbitrary(self).unwrap_or(0)
+    }
+}
+
+/// Grammar that `GrammarInput` generates texts for
+#[cfg(feature = "arbitrary")]
+pub trait InputGrammar {
+    /// Parser of the grammar, built once and shared by all inputs
+    fn parser() -> &'static Parser<'static>;
+
+    fn generator() -> Generator {
+        Generator::default()
+    }
+}
+
+/// Text accepted by the grammar `G`, generated from the bytes of a fuzzer
+///
+/// Implements `arbitrary::Arbitrary`, so fuzz targets and property tests can take
+/// valid inputs directly instead of rejecting most of the random ones
+#[cfg(feature = "arbitrary")]
+pub struct GrammarInput<G> {
+    pub text: String,
+    grammar: core::marker::PhantomData<fn() -> G>,
+}
+
+#[cfg(feature = "arbitrary")]
+impl<G> fmt::Debug for GrammarInput<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("GrammarInput").field(&self.text).finish()
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<G> Clone for GrammarInput<G> {
+    fn clone(&self) -> Self {
+        GrammarInput {
+            text: self.text.clone(),
+            grammar: core::marker::PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'u, G: InputGrammar> arbitrary::Arbitrary<'u> for GrammarInput<G> {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'u>) -> arbitrary::Result<Self> {
+        let text = G::generator()
+            .generate(G::parser(), u)
+            .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+        Ok(GrammarInput {
+            text,
+            grammar: core::marker::PhantomData,
+        })
+    }
+}
+
+/// Generates text from the rules of a grammar
+///
+/// Rules that depend on the state of the parser are approximated:
+/// - `Compare` rules are never executed
+/// - `Peek` and `Isnt` do not produce any text
+/// - `Loop` rules are executed once
+/// - `Until` produces only the token it is searching for
+#[derive(Debug, Clone, Copy)]
+pub struct Generator {
+    /// Maximum depth of nested nodes
+    ///
+    /// Optional rules are skipped once it is reached
+    pub max_depth: usize,
+    /// Maximum number of repetitions of `While` rules
+    pub max_repeat: usize,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self {
+            max_depth: 8,
+            max_repeat: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenerateError<'a> {
+    MissingEntry,
+    NodeNotFound(&'a str),
+    EnumeratorNotFound(&'a str),
+    AliasNotFound(&'a str),
+    AliasCycle(&'a str),
+    /// Complex tokens are produced by preprocessors and can not be generated
+    ComplexToken(&'a str),
+}
+
+impl Generator {
+    /// Generates text for the entry node of the parser
+    pub fn generate<'a>(
+        &self,
+        parser: &Parser<'a>,
+        entropy: &mut dyn Entropy,
+    ) -> Result<String, GenerateError<'a>> {
+        let entry = parser.parser.entry.ok_or(GenerateError::MissingEntry)?;
+        self.generate_node(parser, entry, entropy)
+    }
+
+    /// Generates text for the given node
+    pub fn generate_node<'a>(
+        &self,
+        parser: &Parser<'a>,
+        name: &'a str,
+        entropy: &mut dyn Entropy,
+    ) -> Result<String, GenerateError<'a>> {
+        let mut out = String::new();
+        let mut state = State {
+            parser: &parser.parser,
+            grammar: &parser.grammar,
+            lexer: &parser.lexer,
+            entropy,
+            out: &mut out,
+        };
+        self.node(&mut state, name, 0)?;
+        Ok(out)
+    }
+
+    fn node<'a>(
+        &self,
+        state: &mut State<'_, 'a>,
+        name: &'a str,
+        depth: usize,
+    ) -> Result<(), GenerateError<'a>> {
+        let grammar = state.grammar;
+        let node = match grammar.node(name) {
+            Some(node) => node,
+            None => return Err(GenerateError::NodeNotFound(name)),
+        };
+        self.rules(state, &node.rules, depth + 1)
+    }
+
+    fn rules<'a>(
+        &self,
+        state: &mut State<'_, 'a>,
+        rules: &[Rule<'a>],
+        depth: usize,
+    ) -> Result<(), GenerateError<'a>> {
+        for rule in rules {
+            self.rule(state, rule, depth)?;
+        }
+        Ok(())
+    }
+
+    fn rule<'a>(
+        &self,
+        state: &mut State<'_, 'a>,
+        rule: &Rule<'a>,
+        depth: usize,
+    ) -> Result<(), GenerateError<'a>> {
+        let optional = depth < self.max_depth;
+        match rule {
+            Rule::Is { token, rules, .. } => {
+                self.token(state, token, depth)?;
+                self.rules(state, rules, depth)
+            }
+            Rule::Isnt { rules, .. } => self.rules(state, rules, depth),
+            Rule::IsOneOf { tokens, .. } => match self.pick(state, tokens, depth) {
+                Some(option) => {
+                    self.token(state, &option.token, depth)?;
+                    self.rules(state, &option.rules, depth)
+                }
+                None => Ok(()),
+            },
+            Rule::Maybe {
+                token,
+                is,
+                isnt,
+                parameters,
+                ..
+            } => {
+                if optional && !fails(parameters) && state.entropy.below(2) == 1 {
+                    self.token(state, token, depth)?;
+                    self.rules(state, is, depth)
+                } else {
+                    self.rules(state, isnt, depth)
+                }
+            }
+            Rule::MaybeOneOf {
+                is_one_of, isnt, ..
+            } => {
+                let option = match optional && state.entropy.below(2) == 1 {
+                    true => self.pick(state, is_one_of, depth),
+                    false => None,
+                };
+                match option {
+                    Some(option) => {
+                        self.token(state, &option.token, depth)?;
+                        self.rules(state, &option.rules, depth)
+                    }
+                    None => self.rules(state, isnt, depth),
+                }
+            }
+            Rule::While {
+                token,
+                rules,
+                parameters,
+                ..
+            } => {
+                let count = match optional && !fails(parameters) {
+                    true => state.entropy.below(self.max_repeat + 1),
+                    false => 0,
+                };
+                for _ in 0..count {
+                    self.token(state, token, depth)?;
+                    self.rules(state, rules, depth)?;
+                }
+                Ok(())
+            }
+            Rule::Loop { rules, .. } => self.rules(state, rules, depth),
+            Rule::IfFeature {
+                name,
+                rules,
+                else_rules,
+                ..
+            } => match state.parser.feature_enabled(state.grammar, name) {
+                true => self.rules(state, rules, depth),
+                false => self.rules(state, else_rules, depth),
+            },
+            Rule::Until { token, rules, .. } => {
+                self.token(state, token, depth)?;
+                self.rules(state, rules, depth)
+            }
+            Rule::UntilOneOf { tokens, .. } => match self.pick(state, tokens, depth) {
+                Some(option) => {
+                    self.token(state, &option.token, depth)?;
+                    self.rules(state, &option.rules, depth)
+                }
+                None => Ok(()),
+            },
+            Rule::Peek { .. } => Ok(()),
+            Rule::Command { command, .. } => match command {
+                Commands::Compare { .. }
+                | Commands::External { .. }
+                | Commands::If { .. }
+                | Commands::Error { .. }
+                | Commands::Commit { .. }
+                | Commands::Goto { .. }
+                | Commands::Label { .. }
+                | Commands::Print { .. }
+                | Commands::Return
+                | Commands::Start
+                | Commands::End
+                | Commands::SetNumber { .. }
+                | Commands::SetNumber64 { .. }
+                | Commands::SetFloat { .. }
+                | Commands::SetBool { .. } => Ok(()),
+            },
+            Rule::Debug { .. } => Ok(()),
+        }
+    }
+
+    /// Picks one of the options that does not fail
+    ///
+    /// Once the maximum depth is reached, options that are not nodes are preferred
+    fn pick<'o, 'a>(
+        &self,
+        state: &mut State<'_, 'a>,
+        options: &'o [OneOf<'a>],
+        depth: usize,
+    ) -> Option<&'o OneOf<'a>> {
+        let candidates = options
+            .iter()
+            .filter(|o| !fails(&o.parameters))
+            .collect::<Vec<_>>();
+        let shallow = candidates
+            .iter()
+            .copied()
+            .filter(|o| !matches!(o.token, MatchToken::Node(_)))
+            .collect::<Vec<_>>();
+        let candidates = match depth >= self.max_depth && !shallow.is_empty() {
+            true => shallow,
+            false => candidates,
+        };
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(candidates[state.entropy.below(candidates.len())])
+    }
+
+    fn token<'a>(
+        &self,
+        state: &mut State<'_, 'a>,
+        token: &MatchToken<'a>,
+        depth: usize,
+    ) -> Result<(), GenerateError<'a>> {
+        match token {
+            MatchToken::Token(kind) => match kind {
+                TokenKinds::Token(txt) => state.push(txt),
+                TokenKinds::Complex(name) => return Err(GenerateError::ComplexToken(name)),
+                TokenKinds::Text | TokenKinds::Error => {
+                    let word = state.word();
+                    state.push(&word)
+                }
+                TokenKinds::Whitespace => state.out.push(' '),
+                TokenKinds::Control(ControlTokenKind::Eol) => state.out.push('\n'),
+                TokenKinds::Control(ControlTokenKind::Eof) => (),
+                TokenKinds::DocComment => match state.lexer.doc_comments.first() {
+                    Some(DocComment::Line(marker)) => {
+                        state.push(marker);
+                        state.out.push('\n');
+                    }
+                    Some(DocComment::Block(open, close)) => {
+                        state.push(open);
+                        state.push(close);
+                    }
+                    None => (),
+                },
+            },
+            MatchToken::Node(name) => self.node(state, name.as_str(), depth)?,
+            MatchToken::Word(word) => state.push(word),
+            MatchToken::Enumerator(name) => {
+                let grammar = state.grammar;
+                let enumerator = match grammar.enumerators.get(*name) {
+                    Some(enumerator) => enumerator,
+                    None => return Err(GenerateError::EnumeratorNotFound(name)),
+                };
+                if enumerator.values.is_empty() {
+                    return Ok(());
+                }
+                let value = enumerator.values[state.entropy.below(enumerator.values.len())];
+                self.token(state, &value, depth)?;
+            }
+            MatchToken::Alias(name) => {
+                let grammar = state.grammar;
+                match grammar.resolve_alias(name) {
+                    Ok(token) => self.token(state, token, depth)?,
+                    Err(AliasError::NotFound(alias)) => {
+                        return Err(GenerateError::AliasNotFound(alias))
+                    }
+                    Err(AliasError::Cycle(alias)) => return Err(GenerateError::AliasCycle(alias)),
+                }
+            }
+            MatchToken::Any => {
+                let word = state.word();
+                state.push(&word)
+            }
+            MatchToken::Char(c) => state.out.push(*c),
+            MatchToken::CharClass(class) => {
+                let c = match class {
+                    CharClass::Range(from, _) => *from,
+                    CharClass::Alphabetic | CharClass::Alphanumeric => {
+                        (b'a' + state.entropy.below(26) as u8) as char
+                    }
+                    CharClass::Numeric => (b'0' + state.entropy.below(10) as u8) as char,
+                    CharClass::Whitespace => ' ',
+                    CharClass::Punctuation => '.',
+                    CharClass::Not('a') => 'b',
+                    CharClass::Not(_) => 'a',
+                };
+                state.out.push(c)
+            }
+        }
+        Ok(())
+    }
+}
+
+fn fails(parameters: &[Parameters]) -> bool {
+    parameters.iter().any(|p| matches!(p, Parameters::Fail(_)))
+}
+
+struct State<'s, 'a> {
+    parser: &'s parser::Parser<'a>,
+    grammar: &'s Grammar<'a>,
+    lexer: &'s Lexer,
+    entropy: &'s mut dyn Entropy,
+    out: &'s mut String,
+}
+
+impl State<'_, '_> {
+    /// Appends text separated by a space so that neighbouring tokens don't merge
+    fn push(&mut self, txt: &str) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push(' ');
+        }
+        self.out.push_str(txt);
+    }
+
+    /// Random word that will be lexed as a single text token
+    fn word(&mut self) -> String {
+        for _ in 0..8 {
+            let len = 1 + self.entropy.below(6);
+            let word = (0..len)
+                .map(|_| (b'a' + self.entropy.below(26) as u8) as char)
+                .collect::<String>();
+            let collides = self
+                .lexer
+                .token_kinds
+                .iter()
+                .any(|t| !t.is_empty() && word.contains(*t));
+            if !collides {
+                return word;
+            }
+        }
+        "_".to_string()
+    }
+}
+
+/// Generated text that the parser did not accept
+#[derive(Debug)]
+pub enum RoundTripError<'a> {
+    Generate(GenerateError<'a>),
+    Lex {
+        seed: u64,
+        text: String,
+        error: Box<PreprocessorError>,
+    },
+    Parse {
+        seed: u64,
+        text: String,
+        error: Box<ParseErrorOwned>,
+    },
+}
+
+/// Generates text for every seed and checks that the parser accepts it
+pub fn check_round_trip<'a>(
+    parser: &Parser<'a>,
+    generator: &Generator,
+    seeds: core::ops::Range<u64>,
+) -> Result<(), RoundTripError<'a>> {
+    for seed in seeds {
+        let mut rng = SeedRng::new(seed);
+        let text = generator
+            .generate(parser, &mut rng)
+            .map_err(RoundTripError::Generate)?;
+        let tokens = match parser.lexer.lex_utf8(&text) {
+            Ok(tokens) => tokens,
+            Err(error) => {
+                let error = Box::new(error);
+                return Err(RoundTripError::Lex { seed, text, error });
+            }
+        };
+        let error = match parser.parse(&tokens, &text) {
+            Ok(_) => continue,
+            Err(error) => Box::new(error.into_owned()),
+        };
+        return Err(RoundTripError::Parse { seed, text, error });
+    }
+    Ok(())
+}
+
+/// Panics if the parser rejects any of the generated texts
+#[track_caller]
+pub fn assert_round_trip(parser: &Parser, iterations: u64) {
+    if let Err(err) = check_round_trip(parser, &Generator::default(), 0..iterations) {
+        panic!("round trip failed: {:#?}", err);
+    }
+}
+
+/// Nodes and alternatives exercised by a set of parses
+///
+/// Counts are read from the `ParseStats` of successful parses, so the parser needs
+/// `parser::Parser::profile` enabled. Every option of an `IsOneOf`, `MaybeOneOf` or
+/// `UntilOneOf` rule is a separate alternative.
+#[derive(Debug, Clone)]
+pub struct Coverage<'a> {
+    /// Nodes of the grammar sorted by name
+    pub nodes: Vec<NodeCoverage<'a>>,
+    /// Texts that could not be lexed or parsed and did not add to the counts
+    pub failures: usize,
+    /// Address of every `OneOf` in the grammar and its place in `nodes`
+    alternatives: Map<usize, (usize, usize)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeCoverage<'a> {
+    pub name: &'a str,
+    /// Successful parses of the node
+    pub hits: usize,
+    pub alternatives: Vec<AlternativeCoverage<'a>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AlternativeCoverage<'a> {
+    /// Index of the rule among the rules with alternatives of the node, in the order they are written
+    pub rule: usize,
+    pub token: MatchToken<'a>,
+    /// Number of times the alternative matched
+    pub hits: usize,
+}
+
+impl<'a> Coverage<'a> {
+    /// Empty coverage of every node and alternative of the grammar
+    pub fn new(grammar: &'a Grammar<'a>) -> Self {
+        let mut coverage = Coverage {
+            nodes: Vec::new(),
+            failures: 0,
+            alternatives: Map::new(),
+        };
+        let mut nodes = grammar.all_nodes();
+        nodes.sort_by_key(|node| node.name);
+        for node in nodes {
+            coverage.nodes.push(NodeCoverage {
+                name: node.name.as_str(),
+                hits: 0,
+                alternatives: Vec::new(),
+            });
+            coverage.add_rules(&node.rules);
+        }
+        coverage
+    }
+
+    fn add_rules(&mut self, rules: &'a [Rule<'a>]) {
+        let index = self.nodes.len() - 1;
+        for rule in rules {
+            let options = match rule {
+                Rule::IsOneOf { tokens, .. } | Rule::UntilOneOf { tokens, .. } => Some(tokens),
+                Rule::MaybeOneOf { is_one_of, .. } => Some(is_one_of),
+                _ => None,
+            };
+            if let Some(options) = options {
+                let node = &mut self.nodes[index];
+                let rule = node.alternatives.last().map_or(0, |last| last.rule + 1);
+                for option in options {
+                    let address = option as *const OneOf as usize;
+                    self.alternatives
+                        .insert(address, (index, node.alternatives.len()));
+                    node.alternatives.push(AlternativeCoverage {
+                        rule,
+                        token: option.token,
+                        hits: 0,
+                    });
+                }
+            }
+            for rules in Validator::parts(rule).2 {
+                self.add_rules(rules);
+            }
+        }
+    }
+
+    /// Adds the counts of one parse
+    ///
+    /// The stats have to come from the grammar the coverage was created for
+    pub fn record(&mut self, stats: &ParseStats) {
+        for node in &mut self.nodes {
+            if let Some(node_stats) = stats.nodes.get(node.name) {
+                node.hits += node_stats.successes;
+            }
+        }
+        for (address, hits) in &stats.alternatives {
+            if let Some((node, alternative)) = self.alternatives.get(address) {
+                self.nodes[*node].alternatives[*alternative].hits += hits;
+            }
+        }
+    }
+
+    /// Parses every text of the corpus and adds the counts of the successful parses
+    pub fn record_corpus<'t>(
+        &mut self,
+        parser: &Parser,
+        corpus: impl IntoIterator<Item = &'t str>,
+    ) {
+        for text in corpus {
+            let Ok(tokens) = parser.lexer.lex_utf8(text) else {
+                self.failures += 1;
+                continue;
+            };
+            match parser.parse(&tokens, text) {
+                Ok(result) => match &result.stats {
+                    Some(stats) => self.record(stats),
+                    None => self.failures += 1,
+                },
+                Err(_) => self.failures += 1,
+            }
+        }
+    }
+
+    /// Nodes that were never parsed successfully
+    pub fn uncovered_nodes(&self) -> impl Iterator<Item = &NodeCoverage<'a>> + '_ {
+        self.nodes.iter().filter(|node| node.hits == 0)
+    }
+
+    /// Alternatives that never matched, with the name of their node
+    pub fn uncovered_alternatives(
+        &self,
+    ) -> impl Iterator<Item = (&'a str, &AlternativeCoverage<'a>)> + '_ {
+        self.nodes.iter().flat_map(|node| {
+            node.alternatives
+                .iter()
+                .filter(|alternative| alternative.hits == 0)
+                .map(|alternative| (node.name, alternative))
+        })
+    }
+}
+
+/// Report with the counts of every node and alternative
+impl fmt::Display for Coverage<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in &self.nodes {
+            writeln!(f, "{}: {}", node.name, node.hits)?;
+            for alternative in &node.alternatives {
+                writeln!(
+                    f,
+                    "  rule {} {:?}: {}",
+                    alternative.rule, alternative.token, alternative.hits
+                )?;
+            }
+        }
+        let alternatives = self
+            .nodes
+            .iter()
+            .map(|node| node.alternatives.len())
+            .sum::<usize>();
+        write!(
+            f,
+            "covered {}/{} nodes, {}/{} alternatives",
+            self.nodes.len() - self.uncovered_nodes().count(),
+            self.nodes.len(),
+            alternatives - self.uncovered_alternatives().count(),
+            alternatives
+        )?;
+        if self.failures > 0 {
+            write!(f, ", {} failed texts", self.failures)?;
+        }
+        Ok(())
+    }
+}
+
+/// Shrinks the input while `fails` keeps returning true for it
+///
+/// Delta debugging removes chunks of lines first and then chunks of words, spaces and
+/// single symbols, so the result is usually small but not always the smallest possible.
+/// The input is returned unchanged if `fails` is false for it.
+pub fn reduce(input: &str, mut fails: impl FnMut(&str) -> bool) -> String {
+    if !fails(input) {
+        return input.to_string();
+    }
+    let lines = input.split_inclusive('\n').collect::<Vec<_>>();
+    let text = reduce_units(lines, &mut fails).concat();
+    reduce_units(split_words(&text), &mut fails).concat()
+}
+
+/// Reduces the input while the parser fails on it with the same error code
+pub fn reduce_parse_error(parser: &Parser, input: &str) -> String {
+    let code = |text: &str| {
+        let tokens = parser.lexer.lex_utf8(text).ok()?;
+        parser.parse(&tokens, text).err().map(|err| err.kind.code())
+    };
+    match code(input) {
+        Some(expected) => reduce(input, |text| code(text) == Some(expected)),
+        None => input.to_string(),
+    }
+}
+
+/// Removes chunks of units, halving their size whenever no chunk can be removed
+fn reduce_units<'t>(mut units: Vec<&'t str>, fails: &mut impl FnMut(&str) -> bool) -> Vec<&'t str> {
+    let mut parts = 2;
+    while units.len() > 1 {
+        let chunk = units.len().div_ceil(parts);
+        let mut reduced = false;
+        for start in (0..units.len()).step_by(chunk) {
+            let end = (start + chunk).min(units.len());
+            let candidate = [&units[..start], &units[end..]].concat();
+            if fails(&candidate.concat()) {
+                units = candidate;
+                parts = (parts - 1).max(2);
+                reduced = true;
+                break;
+            }
+        }
+        if !reduced {
+            if parts >= units.len() {
+                break;
+            }
+            parts = (parts * 2).min(units.len());
+        }
+    }
+    units
+}
+
+/// Splits the text into runs of alphanumeric characters, runs of whitespace and other characters
+fn split_words(text: &str) -> Vec<&str> {
+    let class = |c: char| {
+        if c.is_alphanumeric() || c == '_' {
+            0
+        } else if c.is_whitespace() {
+            1
+        } else {
+            2
+        }
+    };
+    let mut units = Vec::new();
+    let mut start = 0;
+    let mut last = None;
+    for (i, c) in text.char_indices() {
+        let current = class(c);
+        if i > start && (last != Some(current) || current == 2) {
+            units.push(&text[start..i]);
+            start = i;
+        }
+        last = Some(current);
+    }
+    if start < text.len() {
+        units.push(&text[start..]);
+    }
+    units
+}
+
+/// Expected variables of a grammar test, see `Grammar::add_test`
+///
+/// `expect! { ident: "a", type: "int" }` is a list of variable names and their text
+#[macro_export]
+macro_rules! expect {
+    ($($name:ident : $value:expr),* $(,)?) => {
+        [$((stringify!($name), $value)),*]
+    };
+}
+
+/// Grammar test that did not pass
+#[derive(Debug, Clone)]
+pub struct GrammarTestFailure<'a> {
+    pub test: &'a GrammarTest<'a>,
+    pub message: String,
+}
+
+impl fmt::Display for GrammarTestFailure<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "test of {} failed for {:?}: {}",
+            self.test.node, self.test.input, self.message
+        )
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// Runs the tests of the grammar and returns the ones that failed
+    ///
+    /// Every test parses its input with the node as the entry. Lists are compared with
+    /// the text from their first to their last item, other variables with their text.
+    pub fn run_grammar_tests(&self) -> Vec<GrammarTestFailure<'_>> {
+        let mut failures = Vec::new();
+        for test in &self.grammar.tests {
+            if let Err(message) = self.run_grammar_test(test) {
+                failures.push(GrammarTestFailure { test, message });
+            }
+        }
+        failures
+    }
+
+    fn run_grammar_test(&self, test: &GrammarTest) -> Result<(), String> {
+        let mut parser = self.parser.clone();
+        parser.entry = Some(test.node);
+        let tokens = self
+            .lexer
+            .lex_utf8(test.input)
+            .map_err(|err| err.to_string())?;
+        let tokens = parser.filter_tokens(test.input, &tokens);
+        let result = parser
+            .parse(&self.grammar, &self.lexer, test.input, &tokens)
+            .map_err(|err| err.to_string())?;
+        for (name, expected) in &test.expect {
+            let found = match result.entry.variables.get(name) {
+                Some(VariableKind::NodeList(items)) => match (items.first(), items.last()) {
+                    (Some(first), Some(last)) => result
+                        .stringify_nodes_range(first, last, test.input)
+                        .to_string(),
+                    _ => String::new(),
+                },
+                Some(value) => value.stringify(test.input).into_owned(),
+                None => return Err(format!("node has no variable {}", name)),
+            };
+            if found != *expected {
+                return Err(format!(
+                    "expected {} to be {:?}, found {:?}",
+                    name, expected, found
+                ));
+            }
+        }
+        Ok(())
+    }
+}